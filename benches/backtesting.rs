@@ -0,0 +1,206 @@
+//! Benchmarks of the backtesting hot paths, run with `cargo bench`.
+//!
+//! Full replays need a strategy dylib, their throughput is reported by
+//! BacktestingEngine::set_perf_counters instead.
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use polars::prelude::*;
+
+use vnrs::vnrs::trader::constant::{Direction, Exchange, Interval, Offset, OrderType};
+use vnrs::vnrs::trader::converter::OffsetConverter;
+use vnrs::vnrs::trader::history::BarHistory;
+use vnrs::vnrs::trader::object::{BarData, ContractData, OrderRequest, TradeData};
+use vnrs::vnrs::trader::utility::{resample_bars, ActiveIds, ArrayManager, IdGenerator};
+use vnrs::vnrs_ctastrategy::backtesting::BacktestingEngine;
+use vnrs::vnrs_ctastrategy::base::BacktestingMode;
+
+const BAR_COUNT: usize = 100_000;
+
+fn start() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2020, 1, 1)
+        .unwrap()
+        .and_hms_opt(9, 0, 0)
+        .unwrap()
+}
+
+fn minute_bars(count: usize) -> Vec<BarData> {
+    (0..count)
+        .map(|i| {
+            let price = 4000.0 + (i as f64 * 0.1).sin() * 50.0;
+            BarData {
+                gateway_name: "DB".into(),
+                symbol: "IF888".into(),
+                exchange: Exchange::CFFEX,
+                datetime: start() + Duration::minutes(i as i64),
+                interval: Interval::MINUTE,
+                volume: 100.0,
+                turnover: price * 100.0,
+                open_interest: 1000.0,
+                open_price: price,
+                high_price: price + 2.0,
+                low_price: price - 2.0,
+                close_price: price + 1.0,
+            }
+        })
+        .collect()
+}
+
+fn bar_replay(c: &mut Criterion) {
+    let history = BarHistory::from_bars(minute_bars(BAR_COUNT));
+    c.bench_function("bar_history_replay", |b| {
+        b.iter(|| {
+            let mut bar = BarData::default();
+            let mut close = 0.0;
+            for index in 0..history.len() {
+                history.read_into(index, &mut bar);
+                close += bar.close_price;
+            }
+            black_box(close)
+        })
+    });
+
+    let bars = minute_bars(BAR_COUNT);
+    c.bench_function("resample_5_minute", |b| {
+        b.iter(|| resample_bars(black_box(&bars), Interval::MINUTE, 5))
+    });
+}
+
+fn order_crossing(c: &mut Criterion) {
+    c.bench_function("active_ids_cross", |b| {
+        b.iter_batched(
+            || {
+                let mut ids = IdGenerator::new("BACKTESTING.0_strategy");
+                let mut active = ActiveIds::default();
+                for _ in 0..100 {
+                    active.insert(ids.next_id());
+                }
+                active
+            },
+            |mut active| {
+                let sequences: Vec<i64> = active.sequences().collect();
+                for sequence in sequences.into_iter().step_by(2) {
+                    let id = active.get(sequence).unwrap().to_string();
+                    active.remove(&id);
+                }
+                active
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let contract = ContractData {
+        symbol: "rb2410".into(),
+        exchange: Exchange::SHFE,
+        size: 10.0,
+        pricetick: 1.0,
+        ..Default::default()
+    };
+    let mut converter = OffsetConverter::new(move |_| Some(contract.clone()));
+    converter.update_trade(&TradeData {
+        symbol: "rb2410".into(),
+        exchange: Exchange::SHFE,
+        orderid: "1".to_string(),
+        tradeid: "1".to_string(),
+        direction: Direction::LONG,
+        offset: Offset::OPEN,
+        price: 3500.0,
+        volume: 10.0,
+        datetime: start(),
+        reference: String::new(),
+        gateway_name: "BACKTESTING".into(),
+    });
+    let req = OrderRequest {
+        symbol: "rb2410".into(),
+        exchange: Exchange::SHFE,
+        direction: Direction::SHORT,
+        type_: OrderType::LIMIT,
+        volume: 5.0,
+        price: 3500.0,
+        offset: Offset::CLOSE,
+        ..Default::default()
+    };
+    c.bench_function("convert_order_request", |b| {
+        b.iter(|| converter.convert_order_request(black_box(&req), false, false))
+    });
+}
+
+fn array_manager(c: &mut Criterion) {
+    let bars = minute_bars(10_000);
+    c.bench_function("array_manager_update", |b| {
+        b.iter(|| {
+            let mut am = ArrayManager::new(100);
+            for bar in &bars {
+                am.update_bar(bar);
+            }
+            black_box(am.count)
+        })
+    });
+}
+
+fn daily_df(days: usize) -> DataFrame {
+    let column =
+        |name: &str, f: fn(usize) -> f64| Series::new(name, (0..days).map(f).collect::<Vec<f64>>());
+    DataFrame::new(vec![
+        Series::new(
+            "date",
+            (0..days)
+                .map(|i| start().date() + Duration::days(i as i64))
+                .collect::<Vec<NaiveDate>>(),
+        ),
+        column("close_price", |i| 4000.0 + i as f64),
+        column("pre_close", |i| 3999.0 + i as f64),
+        Series::new(
+            "trade_count",
+            (0..days).map(|i| (i % 3) as i64).collect::<Vec<i64>>(),
+        ),
+        column("start_pos", |_| 0.0),
+        column("end_pos", |_| 0.0),
+        column("turnover", |_| 1_000_000.0),
+        column("commission", |_| 30.0),
+        column("slippage", |_| 20.0),
+        column("trading_pnl", |i| (i as f64).sin() * 1000.0),
+        column("holding_pnl", |i| (i as f64).cos() * 500.0),
+        column("total_pnl", |i| {
+            (i as f64).sin() * 1000.0 + (i as f64).cos() * 500.0
+        }),
+        column("net_pnl", |i| {
+            (i as f64).sin() * 1000.0 + (i as f64).cos() * 500.0 - 50.0
+        }),
+    ])
+    .unwrap()
+}
+
+fn statistics(c: &mut Criterion) {
+    let mut engine = BacktestingEngine::new();
+    engine
+        .set_parameters(
+            "IF888.CFFEX",
+            Interval::MINUTE,
+            start(),
+            start() + Duration::days(2500),
+            0.3 / 10000.0,
+            0.2,
+            300.0,
+            0.2,
+            10_000_000.0,
+            BacktestingMode::BAR,
+            0.0,
+            240,
+            0,
+        )
+        .unwrap();
+    engine.set_output_callback(|_| {});
+    let df = daily_df(2500);
+    c.bench_function("calculate_statistics", |b| {
+        b.iter(|| engine.calculate_statistics(Some(df.clone()), false))
+    });
+}
+
+criterion_group!(
+    benches,
+    bar_replay,
+    order_crossing,
+    array_manager,
+    statistics
+);
+criterion_main!(benches);
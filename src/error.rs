@@ -0,0 +1,40 @@
+/*!Error type shared by the crate. */
+#[cfg(feature = "dylib-strategies")]
+use crate::vnrs_ctastrategy::base::StrategyLoadError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum VnrsError {
+    ///Query failed in the SQL database.
+    #[cfg(any(feature = "sqlite", feature = "mysql"))]
+    #[error("database error: {0}")]
+    Sql(#[from] sqlx::Error),
+    ///Database not available or unable to handle the request.
+    #[error("database error: {0}")]
+    Database(String),
+    ///Strategy dylib that couldn't be loaded.
+    #[cfg(feature = "dylib-strategies")]
+    #[error(transparent)]
+    StrategyLoad(#[from] StrategyLoadError),
+    ///Call across the strategy ABI or into another dylib failed.
+    #[error("FFI error: {0}")]
+    Ffi(String),
+    ///Invalid setting or parameter, e.g. a vt_symbol without exchange.
+    #[error("setting error: {0}")]
+    Setting(String),
+    ///Market data breaking its invariants, e.g. a close outside high/low or a negative volume.
+    #[error("invalid data: {0}")]
+    Data(String),
+    #[error("datafeed error: {0}")]
+    Datafeed(String),
+    #[error("engine error: {0}")]
+    Engine(String),
+    #[cfg(feature = "backtest-stats")]
+    #[error(transparent)]
+    Polars(#[from] polars::prelude::PolarsError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+pub type VnrsResult<T> = Result<T, VnrsError>;
@@ -1,22 +1,42 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
+/*!Event-driven CTA backtesting in the style of vnpy: the backtesting engine, trader data
+objects and utilities, and the SDK for writing strategies loaded as dylibs.
+
+The common types are in [`prelude`]:
+```no_run
+use vnrs::prelude::*;
+```
+
+Heavy dependencies are behind features, all enabled by default:
+- `backtest-stats`: polars, the backtesting engine with `dylib-strategies` and its statistics.
+- `sqlite`, `mysql`: databases through sqlx and tokio.
+- `dylib-strategies`: libloading, loading strategies built as dylibs.
+
+With `default-features = false` only the data objects, utilities like BarGenerator and the
+strategy SDK are built, as in the example strategies.
+*/
+pub mod error;
 pub mod vnrs;
 pub mod vnrs_ctastrategy;
-// pub use vnrs_ctastrategy::backtesting::BacktestingEngine;
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
+pub use error::{VnrsError, VnrsResult};
 
-    #[test]
-    fn works(){
-        // let bte=BacktestingEngine{};
-    }
+///Types most backtests and strategies need, to import with `use vnrs::prelude::*`.
+pub mod prelude {
+    pub use crate::error::{VnrsError, VnrsResult};
+    pub use crate::export_strategy;
+    pub use crate::vnrs::trader::constant::{
+        Direction, Exchange, Interval, Offset, OrderType, Status,
+    };
+    pub use crate::vnrs::trader::database::{get_database, set_database, BaseDatabase};
+    pub use crate::vnrs::trader::object::{BarData, ContractData, OrderData, TickData, TradeData};
+    pub use crate::vnrs::trader::utility::{ArrayManager, BarGenerator};
+    #[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+    pub use crate::vnrs_ctastrategy::backtesting::{
+        BacktestingEngine, BacktestingStatistics, DailyResult,
+    };
+    pub use crate::vnrs_ctastrategy::base::{BacktestingMode, StopOrder};
+    pub use crate::vnrs_ctastrategy::quick::QuickContext;
+    #[cfg(feature = "dylib-strategies")]
+    pub use crate::vnrs_ctastrategy::base::ExternClass;
+    pub use crate::vnrs_ctastrategy::sdk::{setting_value, CtaStrategy, StrategyContext};
 }
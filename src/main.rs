@@ -1,26 +1,28 @@
 use std::{
-    ffi::{c_char, CStr, CString},
-    ptr,
-    sync::Arc, time::Instant,
+    ffi::{c_char, CStr},
+    sync::Arc,
+    time::Instant,
 };
 
-use ::vnrs::vnrs_ctastrategy::{
-    base::{BacktestingMode, ExternClass},
-    template::{CtaEngineTable, CtaTemplate},
-};
-use ::vnrs::{vnrs::trader::constant::Interval, vnrs_ctastrategy::backtesting::BacktestingEngine};
 use chrono::NaiveDateTime;
+use vnrs::prelude::*;
 
-pub mod vnrs;
-pub mod vnrs_ctastrategy;
-
-pub extern "C" fn api_print_log(msg: *const c_char) {
+///# Safety
+///msg is a NUL terminated string valid for the call.
+pub unsafe extern "C" fn api_print_log(msg: *const c_char) {
     unsafe {
         println!("{}", CStr::from_ptr(msg).to_owned().into_string().unwrap());
     }
 }
 
 fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
     /*
     let cstring: CString;
     unsafe {
@@ -60,7 +62,255 @@ fn main() {
     }
 
     */
+    // e.g. --profile research, a profile of vt_setting.json instead of VNRS_PROFILE
+    if let Some(pos) = std::env::args().position(|arg| arg == "--profile") {
+        let Some(profile) = std::env::args().nth(pos + 1) else {
+            eprintln!("用法：--profile <配置名>");
+            std::process::exit(2);
+        };
+        vnrs::vnrs::trader::setting::set_profile(&profile);
+    }
+
+    // e.g. vnrs secret set email.password, the value read from stdin if not given, or vnrs
+    // secret key to store the passphrase in the OS keychain instead of VNRS_SECRET_KEY
+    #[cfg(feature = "secrets")]
+    if std::env::args().nth(1).as_deref() == Some("secret") {
+        set_secret();
+        return Ok(());
+    }
+
+    #[cfg(feature = "gui")]
+    if std::env::args().any(|arg| arg == "--gui") {
+        vnrs::vnrs_ctastrategy::gui::run_gui()?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "tui")]
+    if std::env::args().any(|arg| arg == "--tui") {
+        // A failed setup is shown by the TUI as the error of the run
+        vnrs::vnrs_ctastrategy::tui::run_with_tui("double_ma_strategy", setup_engine)?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(pos) = std::env::args().position(|arg| arg == "--grpc") {
+        let addr = std::env::args()
+            .nth(pos + 1)
+            .unwrap_or("127.0.0.1:50051".to_string());
+        let strategies = vnrs::vnrs_ctastrategy::base::StrategyDirectory::from_settings();
+        vnrs::vnrs_ctastrategy::grpc::serve(&addr, strategies)?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "rpc")]
+    if std::env::args().any(|arg| arg == "--rpc") {
+        let mut server = vnrs::vnrs::rpc::RpcServer::new();
+        let strategies = vnrs::vnrs_ctastrategy::base::StrategyDirectory::from_settings();
+        vnrs::vnrs_ctastrategy::rpc_service::register_backtesting(&server, strategies);
+        server.start("tcp://127.0.0.1:2014", "tcp://127.0.0.1:4102")?;
+        loop {
+            std::thread::park();
+        }
+    }
+
+    // e.g. optimize-worker --listen 0.0.0.0:50052, backtesting the shards of the
+    // DistributedOptimizers with the optimize.token setting
+    if std::env::args().nth(1).as_deref() == Some("optimize-worker") {
+        let addr = std::env::args()
+            .position(|arg| arg == "--listen")
+            .and_then(|pos| std::env::args().nth(pos + 1))
+            .unwrap_or("127.0.0.1:50052".to_string());
+        vnrs::vnrs_ctastrategy::optimize::serve_worker(&addr)?;
+        return Ok(());
+    }
+
+    // e.g. trace-diff a.trace.gz b.trace.gz, where two --trace runs diverge
+    if std::env::args().nth(1).as_deref() == Some("trace-diff") {
+        diff_traces()?;
+        return Ok(());
+    }
+    // e.g. result-diff before.json after.json, runs saved with --result
+    if std::env::args().nth(1).as_deref() == Some("result-diff") {
+        diff_results()?;
+        return Ok(());
+    }
+
     let mut engine = BacktestingEngine::new();
+    setup_engine(&mut engine)?;
+    if let Some(pos) = std::env::args().position(|arg| arg == "--trace") {
+        let Some(path) = std::env::args().nth(pos + 1) else {
+            eprintln!("用法：--trace <跟踪文件>");
+            std::process::exit(2);
+        };
+        engine.set_debug_trace(path)?;
+    }
+    // e.g. --events events.jsonl or --events http://localhost:8000/events
+    if let Some(pos) = std::env::args().position(|arg| arg == "--events") {
+        let Some(target) = std::env::args().nth(pos + 1) else {
+            eprintln!("用法：--events <事件文件或URL>");
+            std::process::exit(2);
+        };
+        let hook = if target.starts_with("http://") {
+            vnrs::vnrs_ctastrategy::hook::http_hook(&target)
+        } else {
+            vnrs::vnrs_ctastrategy::hook::jsonl_hook(&target)
+        };
+        engine.set_event_hook(hook?);
+    }
+    // e.g. --stats table or --stats json, and --locale en for English labels
+    {
+        use vnrs::vnrs_ctastrategy::report::{Locale, StatisticsFormatter, StatisticsStyle};
+        let arg = |name: &str| {
+            let pos = std::env::args().position(|arg| arg == name)?;
+            std::env::args().nth(pos + 1)
+        };
+        let style = match arg("--stats").as_deref() {
+            None | Some("tabs") => StatisticsStyle::Tabs,
+            Some("table") => StatisticsStyle::Table,
+            Some("json") => StatisticsStyle::Json,
+            Some(other) => {
+                eprintln!("无效的统计格式{}，用法：--stats tabs|table|json", other);
+                std::process::exit(2);
+            }
+        };
+        let locale = match arg("--locale").as_deref() {
+            None | Some("zh") => Locale::Zh,
+            Some("en") => Locale::En,
+            Some(other) => {
+                eprintln!("无效的语言{}，用法：--locale zh|en", other);
+                std::process::exit(2);
+            }
+        };
+        engine.set_statistics_formatter(StatisticsFormatter::new(locale, style));
+    }
+    engine.load_data()?;
+    let beg=Instant::now();
+    engine.run_backtesting()?;
+    let dur=Instant::now()-beg;
+    engine.calculate_result();
+    #[allow(unused_variables)]
+    let statistics = engine.calculate_statistics(None, true);
+    eprintln!("{:?}",dur);
+
+    if let Some(pos) = std::env::args().position(|arg| arg == "--result") {
+        let Some(path) = std::env::args().nth(pos + 1) else {
+            eprintln!("用法：--result <回测结果文件>");
+            std::process::exit(2);
+        };
+        vnrs::vnrs_ctastrategy::diff::RunResult::from_engine(&engine).save(path)?;
+    }
+
+    #[cfg(feature = "ipc")]
+    if let Some(pos) = std::env::args().position(|arg| arg == "--ipc") {
+        let dir = std::env::args()
+            .nth(pos + 1)
+            .unwrap_or("results".to_string());
+        vnrs::vnrs_ctastrategy::ipc::save_results(&engine, &statistics, dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "secrets")]
+fn set_secret() {
+    use vnrs::vnrs::trader::secret::{SecretKey, SECRET_KEY_ENV};
+    use vnrs::vnrs::trader::setting::save_setting;
+
+    let args: Vec<String> = std::env::args().collect();
+    let read_line = || {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).unwrap();
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if args.get(2).map(String::as_str) == Some("key") {
+        if let Err(e) = SecretKey::store_in_keychain(&read_line()) {
+            eprintln!("保存密钥失败：{}", e);
+            std::process::exit(1);
+        }
+        println!("已保存密钥到系统钥匙串");
+        return;
+    }
+    let (Some("set"), Some(key)) = (args.get(2).map(String::as_str), args.get(3)) else {
+        eprintln!("用法：vnrs secret set <配置项> [值] 或 vnrs secret key");
+        std::process::exit(2);
+    };
+    let Some(secret_key) = SecretKey::load() else {
+        eprintln!("未设置{}或系统钥匙串密钥", SECRET_KEY_ENV);
+        std::process::exit(1);
+    };
+    let value = args.get(4).cloned().unwrap_or_else(read_line);
+    let result = secret_key
+        .encrypt(&value)
+        .and_then(|sealed| save_setting(key, &sealed));
+    if let Err(e) = result {
+        eprintln!("保存加密配置失败：{}", e);
+        std::process::exit(1);
+    }
+    println!("已加密保存配置项{}", key);
+}
+
+fn diff_traces() -> VnrsResult<()> {
+    use vnrs::vnrs_ctastrategy::trace::{diff_traces, load_trace};
+
+    let args: Vec<String> = std::env::args().collect();
+    let (Some(left), Some(right)) = (args.get(2), args.get(3)) else {
+        eprintln!("用法：vnrs trace-diff <跟踪文件> <跟踪文件>");
+        std::process::exit(2);
+    };
+    let diffs = diff_traces(&load_trace(left)?, &load_trace(right)?);
+    if diffs.is_empty() {
+        println!("跟踪文件一致");
+    }
+    for diff in diffs {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            diff.index, diff.datetime, diff.field, diff.left, diff.right
+        );
+    }
+    Ok(())
+}
+
+fn diff_results() -> VnrsResult<()> {
+    use vnrs::vnrs_ctastrategy::diff::{diff_results, RunResult};
+
+    let args: Vec<String> = std::env::args().collect();
+    let (Some(left), Some(right)) = (args.get(2), args.get(3)) else {
+        eprintln!("用法：vnrs result-diff <回测结果文件> <回测结果文件>");
+        std::process::exit(2);
+    };
+    let diff = diff_results(&RunResult::load(left)?, &RunResult::load(right)?, 1e-9);
+    let Some(first_divergence) = diff.first_divergence else {
+        println!("回测结果一致");
+        return Ok(());
+    };
+    println!("首次分歧：\t{}", first_divergence);
+    for mismatch in &diff.trade_mismatches {
+        let describe = |trade: &Option<TradeData>| match trade {
+            Some(t) => format!(
+                "{} {:?} {:?} {}@{}",
+                t.datetime, t.direction, t.offset, t.volume, t.price
+            ),
+            None => "-".to_string(),
+        };
+        println!(
+            "成交{}\t{}\t{}",
+            mismatch.index,
+            describe(&mismatch.left),
+            describe(&mismatch.right)
+        );
+    }
+    for day in &diff.day_deltas {
+        println!(
+            "{}\t{:.2}\t{:.2}\t{:.2}",
+            day.date,
+            day.left_net_pnl,
+            day.right_net_pnl,
+            day.delta()
+        );
+    }
+    Ok(())
+}
+
+fn setup_engine(engine: &mut BacktestingEngine) -> VnrsResult<()> {
     // engine.set_parameters(
     //     "000905.LOCAL",
     //     Interval::DAILY,
@@ -76,9 +326,20 @@ fn main() {
     //     240,
     //     120,
     // );
+    // e.g. --interval 1h, bars as stored in the database
+    let interval: Interval = std::env::args()
+        .position(|arg| arg == "--interval")
+        .and_then(|pos| std::env::args().nth(pos + 1))
+        .map(|arg| {
+            arg.parse().unwrap_or_else(|_| {
+                eprintln!("无效的K线周期{}，用法：--interval 1m|1h|d|w|tick|5s", arg);
+                std::process::exit(2);
+            })
+        })
+        .unwrap_or(Interval::MINUTE);
     engine.set_parameters(
         "ETH.LOCAL",
-        Interval::MINUTE,
+        interval,
         NaiveDateTime::parse_from_str("2020-1-22 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap(),
         NaiveDateTime::parse_from_str("2020-12-31 23:59:59", "%Y-%m-%d %H:%M:%S").unwrap(),
         2.5e-5,
@@ -88,18 +349,11 @@ fn main() {
         1000000.0,
         BacktestingMode::BAR,
         0.0,
-        240,
+        0,
         120,
-    );
+    )?;
     engine.add_strategy(
-        Arc::new(ExternClass::new("double_ma_strategy")),
+        Arc::new(ExternClass::new("double_ma_strategy")?),
         "fast_window:10,slow_window:20",
-    );
-    engine.load_data();
-    let beg=Instant::now();
-    engine.run_backtesting();
-    let dur=Instant::now()-beg;
-    engine.calculate_result();
-    engine.calculate_statistics(None, true);
-    eprintln!("{:?}",dur);
+    )
 }
@@ -1 +1,5 @@
+pub mod chart;
+pub mod event;
+#[cfg(feature = "rpc")]
+pub mod rpc;
 pub mod trader;
\ No newline at end of file
@@ -0,0 +1,150 @@
+/*!Candlestick chart of bars with a volume pane and indicator overlays. Rendered to an SVG file
+with the `chart` feature and drawn in an egui window with the `gui` feature. */
+#[cfg(feature = "chart")]
+mod svg;
+#[cfg(feature = "gui")]
+mod widget;
+
+use crate::vnrs::trader::object::BarData;
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+// Colors given to overlays in the order they are added
+const OVERLAY_COLORS: [[u8; 3]; 6] = [
+    [255, 127, 14],
+    [31, 119, 180],
+    [148, 103, 189],
+    [23, 190, 207],
+    [140, 86, 75],
+    [188, 189, 34],
+];
+
+///Rising candles are red and falling ones green, as on Chinese exchanges.
+pub const RISE_COLOR: [u8; 3] = [214, 39, 40];
+pub const FALL_COLOR: [u8; 3] = [44, 160, 44];
+
+///Indicator line drawn over the candles, one value per bar and NaN where it has none.
+#[derive(Debug, Clone)]
+pub struct ChartOverlay {
+    pub name: String,
+    pub values: Vec<f64>,
+    pub color: [u8; 3],
+}
+
+///Bars to chart and the overlays on them. The x axis is the bar index so sessions without
+///trading leave no gaps.
+#[derive(Debug, Clone, Default)]
+pub struct CandleChart {
+    pub title: String,
+    bars: Vec<BarData>,
+    overlays: Vec<ChartOverlay>,
+}
+
+impl CandleChart {
+    pub fn new(title: &str, bars: Vec<BarData>) -> Self {
+        CandleChart {
+            title: title.to_string(),
+            bars,
+            overlays: Vec::new(),
+        }
+    }
+
+    ///Add an indicator line, values must be aligned with the bars.
+    pub fn add_overlay(&mut self, name: &str, values: Vec<f64>) -> &mut Self {
+        assert_eq!(
+            values.len(),
+            self.bars.len(),
+            "overlay {} has {} values for {} bars",
+            name,
+            values.len(),
+            self.bars.len()
+        );
+        let color = OVERLAY_COLORS[self.overlays.len() % OVERLAY_COLORS.len()];
+        self.overlays.push(ChartOverlay {
+            name: name.to_string(),
+            values,
+            color,
+        });
+        self
+    }
+
+    pub fn bars(&self) -> &[BarData] {
+        &self.bars
+    }
+
+    pub fn overlays(&self) -> &[ChartOverlay] {
+        &self.overlays
+    }
+
+    ///Lowest and highest of bar prices and overlay values, (0, 1) without any.
+    pub fn price_range(&self) -> (f64, f64) {
+        let values = self
+            .bars
+            .iter()
+            .flat_map(|bar| [bar.low_price, bar.high_price])
+            .chain(
+                self.overlays
+                    .iter()
+                    .flat_map(|overlay| overlay.values.iter().copied()),
+            )
+            .filter(|value| value.is_finite());
+        let (low, high) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), v| {
+            (low.min(v), high.max(v))
+        });
+        if low > high {
+            (0.0, 1.0)
+        } else if low == high {
+            (low - 1.0, high + 1.0)
+        } else {
+            (low, high)
+        }
+    }
+
+    pub fn max_volume(&self) -> f64 {
+        self.bars
+            .iter()
+            .map(|bar| bar.volume)
+            .fold(0.0, f64::max)
+            .max(1.0)
+    }
+
+    ///Datetime label of the bar nearest to x, empty outside the bars.
+    pub fn label(&self, x: f64) -> String {
+        let index = x.round();
+        if index < 0.0 || index >= self.bars.len() as f64 {
+            return String::new();
+        }
+        self.bars[index as usize]
+            .datetime
+            .format(DATETIME_FORMAT)
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::synthetic::SyntheticBars;
+
+    #[test]
+    fn range_includes_overlays() {
+        let bars = SyntheticBars {
+            count: 20,
+            ..Default::default()
+        }
+        .generate();
+        let mut chart = CandleChart::new("TEST", bars);
+        let (low, high) = chart.price_range();
+        assert!(low < high);
+
+        let mut values = vec![f64::NAN; 20];
+        values[19] = high + 10.0;
+        chart.add_overlay("sma", values);
+        assert_eq!(chart.price_range(), (low, high + 10.0));
+        assert_eq!(chart.overlays()[0].color, OVERLAY_COLORS[0]);
+
+        assert_eq!(chart.label(0.2), "2024-01-01 00:00");
+        assert_eq!(chart.label(20.0), "");
+        assert_eq!(CandleChart::default().price_range(), (0.0, 1.0));
+    }
+}
@@ -0,0 +1,98 @@
+use plotters::prelude::*;
+use std::error::Error;
+use std::path::Path;
+
+use super::{CandleChart, FALL_COLOR, RISE_COLOR};
+
+fn rgb(color: [u8; 3]) -> RGBColor {
+    RGBColor(color[0], color[1], color[2])
+}
+
+impl CandleChart {
+    ///Render to an SVG file of width x height pixels, the price pane takes the upper three
+    ///quarters and the volume pane the rest.
+    pub fn save_svg<P: AsRef<Path>>(
+        &self,
+        path: P,
+        width: u32,
+        height: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        let root = SVGBackend::new(path.as_ref(), (width, height)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let (upper, lower) = root.split_vertically(height * 3 / 4);
+
+        let x_range = -0.5..self.bars.len() as f64 - 0.5;
+        let (low, high) = self.price_range();
+        let mut price_chart = ChartBuilder::on(&upper)
+            .caption(&self.title, ("sans-serif", 20))
+            .margin(10)
+            .y_label_area_size(60)
+            .build_cartesian_2d(x_range.clone(), low..high)?;
+        price_chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(0)
+            .draw()?;
+
+        let candle_width = (width as f64 * 0.8 / self.bars.len().max(1) as f64 * 0.7).max(1.0);
+        price_chart.draw_series(self.bars.iter().enumerate().map(|(i, bar)| {
+            CandleStick::new(
+                i as f64,
+                bar.open_price,
+                bar.high_price,
+                bar.low_price,
+                bar.close_price,
+                rgb(RISE_COLOR).filled(),
+                rgb(FALL_COLOR).filled(),
+                candle_width as u32,
+            )
+        }))?;
+
+        for overlay in &self.overlays {
+            let color = rgb(overlay.color);
+            let points = overlay
+                .values
+                .iter()
+                .enumerate()
+                .filter(|(_, value)| value.is_finite())
+                .map(|(i, value)| (i as f64, *value));
+            price_chart
+                .draw_series(LineSeries::new(points, color.stroke_width(2)))?
+                .label(&overlay.name)
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        }
+        if !self.overlays.is_empty() {
+            price_chart
+                .configure_series_labels()
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .draw()?;
+        }
+
+        let mut volume_chart = ChartBuilder::on(&lower)
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(60)
+            .build_cartesian_2d(x_range, 0.0..self.max_volume())?;
+        volume_chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(5)
+            .x_label_formatter(&|x| self.label(*x))
+            .draw()?;
+        volume_chart.draw_series(self.bars.iter().enumerate().map(|(i, bar)| {
+            let color = if bar.close_price >= bar.open_price {
+                rgb(RISE_COLOR)
+            } else {
+                rgb(FALL_COLOR)
+            };
+            Rectangle::new(
+                [(i as f64 - 0.35, 0.0), (i as f64 + 0.35, bar.volume)],
+                color.filled(),
+            )
+        }))?;
+
+        root.present()?;
+        Ok(())
+    }
+}
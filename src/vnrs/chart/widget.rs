@@ -0,0 +1,88 @@
+use eframe::egui::{self, Color32};
+use egui_plot::{Bar, BarChart, BoxElem, BoxPlot, BoxSpread, Legend, Line, Plot, PlotPoints};
+
+use super::{CandleChart, FALL_COLOR, RISE_COLOR};
+
+fn color32(color: [u8; 3]) -> Color32 {
+    Color32::from_rgb(color[0], color[1], color[2])
+}
+
+impl CandleChart {
+    ///Draw the chart into ui, a price plot of height above a volume plot sharing its x axis,
+    ///so zooming and dragging one moves the other.
+    pub fn show(&self, ui: &mut egui::Ui, height: f32) {
+        let group = ui.id().with(("candle_chart", &self.title));
+        let candle_color = |open: f64, close: f64| {
+            if close >= open {
+                color32(RISE_COLOR)
+            } else {
+                color32(FALL_COLOR)
+            }
+        };
+
+        let candles: Vec<BoxElem> = self
+            .bars
+            .iter()
+            .enumerate()
+            .map(|(i, bar)| {
+                let color = candle_color(bar.open_price, bar.close_price);
+                let spread = BoxSpread::new(
+                    bar.low_price,
+                    bar.open_price.min(bar.close_price),
+                    bar.close_price,
+                    bar.open_price.max(bar.close_price),
+                    bar.high_price,
+                );
+                BoxElem::new(i as f64, spread)
+                    .name(self.label(i as f64))
+                    .box_width(0.7)
+                    .whisker_width(0.0)
+                    .fill(color)
+                    .stroke(egui::Stroke::new(1.0, color))
+            })
+            .collect();
+
+        Plot::new(group.with("price"))
+            .height(height)
+            .legend(Legend::default())
+            .link_axis(group, [true, false])
+            .link_cursor(group, [true, false].into())
+            .x_axis_formatter(|mark, _| self.label(mark.value))
+            .show(ui, |plot_ui| {
+                plot_ui.box_plot(BoxPlot::new(candles).name(&self.title));
+                for overlay in &self.overlays {
+                    let points: PlotPoints = overlay
+                        .values
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, value)| value.is_finite())
+                        .map(|(i, value)| [i as f64, *value])
+                        .collect();
+                    plot_ui.line(
+                        Line::new(points)
+                            .name(&overlay.name)
+                            .color(color32(overlay.color)),
+                    );
+                }
+            });
+
+        let volumes: Vec<Bar> = self
+            .bars
+            .iter()
+            .enumerate()
+            .map(|(i, bar)| {
+                Bar::new(i as f64, bar.volume)
+                    .width(0.7)
+                    .fill(candle_color(bar.open_price, bar.close_price))
+            })
+            .collect();
+        Plot::new(group.with("volume"))
+            .height(height / 3.0)
+            .link_axis(group, [true, false])
+            .link_cursor(group, [true, false].into())
+            .x_axis_formatter(|mark, _| self.label(mark.value))
+            .show(ui, |plot_ui| {
+                plot_ui.bar_chart(BarChart::new(volumes).name("volume"));
+            });
+    }
+}
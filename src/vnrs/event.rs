@@ -0,0 +1,260 @@
+/*!Event-driven framework of the trading platform. */
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub const EVENT_TIMER: &str = "eTimer";
+
+pub type HandlerType = Arc<dyn Fn(&Event) + Send + Sync>;
+
+///Event object consists of a type string which is used by event engine for
+///distributing event, and a data object which contains the real data.
+#[derive(Clone)]
+pub struct Event {
+    pub type_: String,
+    pub data: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl Event {
+    pub fn new<T: Any + Send + Sync>(type_: &str, data: T) -> Self {
+        Event {
+            type_: type_.to_string(),
+            data: Some(Arc::new(data)),
+        }
+    }
+
+    pub fn empty(type_: &str) -> Self {
+        Event {
+            type_: type_.to_string(),
+            data: None,
+        }
+    }
+
+    ///Downcast the data object, None if empty or of another type.
+    pub fn data<T: Any>(&self) -> Option<&T> {
+        self.data.as_ref()?.downcast_ref::<T>()
+    }
+}
+
+///Event engine distributes event object based on its type to those handlers registered.
+///
+///It also generates timer event by every interval seconds, which can be used for timing purpose.
+pub struct EventEngine {
+    interval: u64,
+    sender: Sender<Event>,
+    receiver: Mutex<Option<Receiver<Event>>>,
+    active: Arc<AtomicBool>,
+    handlers: Arc<RwLock<HashMap<String, Vec<HandlerType>>>>,
+    general_handlers: Arc<RwLock<Vec<HandlerType>>>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Default for EventEngine {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl EventEngine {
+    ///Timer event is generated every 1 second by default, if interval not specified.
+    pub fn new(interval: u64) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        EventEngine {
+            interval,
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            active: Arc::new(AtomicBool::new(false)),
+            handlers: Arc::new(RwLock::new(HashMap::new())),
+            general_handlers: Arc::new(RwLock::new(Vec::new())),
+            threads: Mutex::new(Vec::new()),
+        }
+    }
+
+    ///Start event engine to process events and generate timer events.
+    pub fn start(&self) {
+        let Some(receiver) = self.receiver.lock().unwrap().take() else {
+            return;
+        };
+        self.active.store(true, Ordering::SeqCst);
+
+        let active = self.active.clone();
+        let handlers = self.handlers.clone();
+        let general_handlers = self.general_handlers.clone();
+        let run_thread = thread::spawn(move || {
+            while active.load(Ordering::SeqCst) {
+                match receiver.recv_timeout(Duration::from_secs(1)) {
+                    Ok(event) => process(&event, &handlers, &general_handlers),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        let active = self.active.clone();
+        let sender = self.sender.clone();
+        let interval = self.interval;
+        let timer_thread = thread::spawn(move || {
+            while active.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_secs(interval));
+                let _ = sender.send(Event::empty(EVENT_TIMER));
+            }
+        });
+
+        let mut threads = self.threads.lock().unwrap();
+        threads.push(run_thread);
+        threads.push(timer_thread);
+    }
+
+    ///Stop event engine.
+    pub fn stop(&self) {
+        self.active.store(false, Ordering::SeqCst);
+        for thread in self.threads.lock().unwrap().drain(..) {
+            let _ = thread.join();
+        }
+    }
+
+    ///Put an event object into event queue.
+    pub fn put(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+
+    ///Register a new handler function for a specific event type. Every
+    ///function can only be registered once for each event type.
+    pub fn register(&self, type_: &str, handler: HandlerType) {
+        let mut handlers = self.handlers.write().unwrap();
+        let handler_list = handlers.entry(type_.to_string()).or_default();
+        if !handler_list.iter().any(|h| Arc::ptr_eq(h, &handler)) {
+            handler_list.push(handler);
+        }
+    }
+
+    ///Unregister an existing handler function from event engine.
+    pub fn unregister(&self, type_: &str, handler: &HandlerType) {
+        let mut handlers = self.handlers.write().unwrap();
+        if let Some(handler_list) = handlers.get_mut(type_) {
+            handler_list.retain(|h| !Arc::ptr_eq(h, handler));
+            if handler_list.is_empty() {
+                handlers.remove(type_);
+            }
+        }
+    }
+
+    ///Register a new handler function for all event types. Every function
+    ///can only be registered once for each event type.
+    pub fn register_general(&self, handler: HandlerType) {
+        let mut general_handlers = self.general_handlers.write().unwrap();
+        if !general_handlers.iter().any(|h| Arc::ptr_eq(h, &handler)) {
+            general_handlers.push(handler);
+        }
+    }
+
+    ///Unregister an existing general handler function.
+    pub fn unregister_general(&self, handler: &HandlerType) {
+        self.general_handlers
+            .write()
+            .unwrap()
+            .retain(|h| !Arc::ptr_eq(h, handler));
+    }
+}
+
+///First distribute event to those handlers registered listening to this type,
+///then distribute event to those general handlers which listens to all types.
+fn process(
+    event: &Event,
+    handlers: &RwLock<HashMap<String, Vec<HandlerType>>>,
+    general_handlers: &RwLock<Vec<HandlerType>>,
+) {
+    // Handlers are cloned out of the locks so they can register or unregister themselves
+    let type_handlers = handlers.read().unwrap().get(&event.type_).cloned();
+    for handler in type_handlers.unwrap_or_default() {
+        handler(event);
+    }
+
+    let general_handlers = general_handlers.read().unwrap().clone();
+    for handler in general_handlers {
+        handler(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    ///Wait until condition holds, false after timeout.
+    fn wait_for(timeout: Duration, condition: impl Fn() -> bool) -> bool {
+        let start = Instant::now();
+        while !condition() {
+            if start.elapsed() > timeout {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn events_put_to_registered_handlers() {
+        let engine = EventEngine::new(1);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let on_tick: HandlerType = {
+            let received = received.clone();
+            Arc::new(move |event: &Event| {
+                let data = event.data::<i32>().copied();
+                received.lock().unwrap().push(("tick", data));
+            })
+        };
+        let general: HandlerType = {
+            let received = received.clone();
+            Arc::new(move |event: &Event| {
+                if event.type_ != EVENT_TIMER {
+                    received.lock().unwrap().push(("general", None));
+                }
+            })
+        };
+        // Registering twice still calls the handler once
+        engine.register("eTick.", on_tick.clone());
+        engine.register("eTick.", on_tick.clone());
+        engine.register_general(general.clone());
+        engine.start();
+
+        engine.put(Event::new("eTick.", 1));
+        engine.put(Event::empty("eBar."));
+        assert!(wait_for(Duration::from_secs(5), || {
+            received.lock().unwrap().len() == 3
+        }));
+        assert_eq!(
+            *received.lock().unwrap(),
+            [("tick", Some(1)), ("general", None), ("general", None)]
+        );
+
+        engine.unregister("eTick.", &on_tick);
+        engine.unregister_general(&general);
+        engine.put(Event::new("eTick.", 2));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(received.lock().unwrap().len(), 3);
+        engine.stop();
+    }
+
+    #[test]
+    fn timer_events_every_interval() {
+        let engine = EventEngine::new(1);
+        let timers = Arc::new(Mutex::new(0));
+        let counter = timers.clone();
+        engine.register(
+            EVENT_TIMER,
+            Arc::new(move |_: &Event| *counter.lock().unwrap() += 1),
+        );
+        engine.start();
+        assert!(wait_for(Duration::from_secs(5), || *timers.lock().unwrap() >= 2));
+        engine.stop();
+        // No timer after the engine stopped
+        let count = *timers.lock().unwrap();
+        thread::sleep(Duration::from_millis(1100));
+        assert_eq!(*timers.lock().unwrap(), count);
+    }
+}
@@ -0,0 +1,432 @@
+/*!RPC server and client compatible with vnpy.rpc, enabled by the `rpc` feature.
+
+Messages are pickled the same way as send_pyobj/recv_pyobj in vnpy:
+- REQ/REP request `[name, args, kwargs]`, reply `[True, result]` or `[False, error]`
+- PUB/SUB message `[topic, data]`, with a heartbeat topic published every 10 seconds
+*/
+use serde_pickle::{DeOptions, SerOptions};
+pub use serde_pickle::{HashableValue, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use zeromq::{
+    PubSocket, RepSocket, ReqSocket, Socket, SocketRecv, SocketSend, SubSocket, ZmqError,
+    ZmqMessage,
+};
+
+pub const HEARTBEAT_TOPIC: &str = "heartbeat";
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+pub const HEARTBEAT_TOLERANCE: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+///Keyword arguments of a remote call.
+pub type Kwargs = BTreeMap<HashableValue, Value>;
+
+type RpcFunction = Arc<dyn Fn(Vec<Value>, Kwargs) -> Result<Value, String> + Send + Sync>;
+
+#[derive(Debug)]
+pub enum RpcError {
+    Zmq(ZmqError),
+    Pickle(serde_pickle::Error),
+    ///Error message (traceback for vnpy) returned by the server
+    Remote(String),
+    Timeout,
+    NotStarted,
+    InvalidMessage,
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Zmq(e) => write!(f, "zmq error: {}", e),
+            RpcError::Pickle(e) => write!(f, "pickle error: {}", e),
+            RpcError::Remote(msg) => write!(f, "remote error: {}", msg),
+            RpcError::Timeout => write!(f, "request timeout"),
+            RpcError::NotStarted => write!(f, "rpc not started"),
+            RpcError::InvalidMessage => write!(f, "invalid message"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<ZmqError> for RpcError {
+    fn from(e: ZmqError) -> Self {
+        RpcError::Zmq(e)
+    }
+}
+
+impl From<serde_pickle::Error> for RpcError {
+    fn from(e: serde_pickle::Error) -> Self {
+        RpcError::Pickle(e)
+    }
+}
+
+fn pack(value: &Value) -> Result<ZmqMessage, RpcError> {
+    Ok(ZmqMessage::from(serde_pickle::value_to_vec(
+        value,
+        SerOptions::new(),
+    )?))
+}
+
+fn unpack(message: ZmqMessage) -> Result<Value, RpcError> {
+    let frame = message.get(0).ok_or(RpcError::InvalidMessage)?;
+    // Python objects without a Rust counterpart (e.g. datetime) are received as None
+    Ok(serde_pickle::value_from_slice(
+        frame,
+        DeOptions::new().replace_unresolved_globals(),
+    )?)
+}
+
+///Publish handle of an RpcServer, cheap to clone into engine callbacks.
+#[derive(Clone)]
+pub struct RpcPublisher {
+    sender: mpsc::UnboundedSender<Value>,
+}
+
+impl RpcPublisher {
+    pub fn publish(&self, topic: &str, data: Value) {
+        let _ = self
+            .sender
+            .send(Value::List(vec![Value::String(topic.to_string()), data]));
+    }
+}
+
+pub struct RpcServer {
+    runtime: Runtime,
+    functions: Arc<RwLock<HashMap<String, RpcFunction>>>,
+    publisher: RpcPublisher,
+    receiver: Option<mpsc::UnboundedReceiver<Value>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Default for RpcServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RpcServer {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        RpcServer {
+            runtime: tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap(),
+            functions: Arc::new(RwLock::new(HashMap::new())),
+            publisher: RpcPublisher { sender },
+            receiver: Some(receiver),
+            tasks: Vec::new(),
+        }
+    }
+
+    ///Register a function callable by name, it runs on a blocking thread of the server.
+    pub fn register<F>(&self, name: &str, function: F)
+    where
+        F: Fn(Vec<Value>, Kwargs) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.functions
+            .write()
+            .unwrap()
+            .insert(name.to_string(), Arc::new(function));
+    }
+
+    pub fn publisher(&self) -> RpcPublisher {
+        self.publisher.clone()
+    }
+
+    pub fn publish(&self, topic: &str, data: Value) {
+        self.publisher.publish(topic, data);
+    }
+
+    ///Bind the sockets, e.g. "tcp://127.0.0.1:2014" and "tcp://127.0.0.1:4102", or
+    ///"tcp://*:2014" and "tcp://*:4102" as in vnpy examples to accept calls from any host.
+    pub fn start(&mut self, rep_address: &str, pub_address: &str) -> Result<(), RpcError> {
+        let receiver = self
+            .receiver
+            .take()
+            .expect("RpcServer can only be started once");
+        let (rep_socket, pub_socket) = self.runtime.block_on(async {
+            let mut rep_socket = RepSocket::new();
+            rep_socket.bind(&tcp_address(rep_address)).await?;
+            let mut pub_socket = PubSocket::new();
+            pub_socket.bind(&tcp_address(pub_address)).await?;
+            Ok::<_, RpcError>((rep_socket, pub_socket))
+        })?;
+
+        self.tasks.push(
+            self.runtime
+                .spawn(run_rep(rep_socket, self.functions.clone())),
+        );
+        self.tasks
+            .push(self.runtime.spawn(run_pub(pub_socket, receiver)));
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
+}
+
+///zeromq binds "*" as "0.0.0.0".
+fn tcp_address(address: &str) -> String {
+    address.replace("://*:", "://0.0.0.0:")
+}
+
+async fn run_rep(mut socket: RepSocket, functions: Arc<RwLock<HashMap<String, RpcFunction>>>) {
+    while let Ok(message) = socket.recv().await {
+        let result = match unpack(message) {
+            Ok(request) => call_function(request, &functions).await,
+            Err(e) => Err(e.to_string()),
+        };
+        let reply = match result {
+            Ok(value) => Value::List(vec![Value::Bool(true), value]),
+            Err(msg) => Value::List(vec![Value::Bool(false), Value::String(msg)]),
+        };
+        let Ok(reply) = pack(&reply) else { break };
+        if socket.send(reply).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn call_function(
+    request: Value,
+    functions: &RwLock<HashMap<String, RpcFunction>>,
+) -> Result<Value, String> {
+    let (Value::List(items) | Value::Tuple(items)) = request else {
+        return Err("request must be [name, args, kwargs]".to_string());
+    };
+    let mut items = items.into_iter();
+    match (items.next(), items.next(), items.next()) {
+        (
+            Some(Value::String(name)),
+            Some(Value::List(args) | Value::Tuple(args)),
+            Some(Value::Dict(kwargs)),
+        ) => {
+            let function = functions.read().unwrap().get(&name).cloned();
+            match function {
+                Some(function) => tokio::task::spawn_blocking(move || function(args, kwargs))
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string())),
+                None => Err(format!("function {} not registered", name)),
+            }
+        }
+        _ => Err("request must be [name, args, kwargs]".to_string()),
+    }
+}
+
+async fn run_pub(mut socket: PubSocket, mut receiver: mpsc::UnboundedReceiver<Value>) {
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        let message = tokio::select! {
+            Some(message) = receiver.recv() => message,
+            _ = heartbeat.tick() => Value::List(vec![
+                Value::String(HEARTBEAT_TOPIC.to_string()),
+                Value::F64(chrono::Utc::now().timestamp_millis() as f64 / 1000.0),
+            ]),
+        };
+        if let Ok(message) = pack(&message) {
+            let _ = socket.send(message).await;
+        }
+    }
+}
+
+pub struct RpcClient {
+    runtime: Runtime,
+    req_socket: Mutex<Option<ReqSocket>>,
+    last_heartbeat: Arc<Mutex<Option<Instant>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Default for RpcClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RpcClient {
+    pub fn new() -> Self {
+        RpcClient {
+            runtime: tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap(),
+            req_socket: Mutex::new(None),
+            last_heartbeat: Arc::new(Mutex::new(None)),
+            task: None,
+        }
+    }
+
+    ///Connect to a server, callback receives every published topic except heartbeats.
+    pub fn start<F>(
+        &mut self,
+        req_address: &str,
+        sub_address: &str,
+        callback: F,
+    ) -> Result<(), RpcError>
+    where
+        F: Fn(&str, Value) + Send + 'static,
+    {
+        let (req_socket, sub_socket) = self.runtime.block_on(async {
+            let mut req_socket = ReqSocket::new();
+            req_socket.connect(req_address).await?;
+            let mut sub_socket = SubSocket::new();
+            sub_socket.connect(sub_address).await?;
+            sub_socket.subscribe("").await?;
+            Ok::<_, RpcError>((req_socket, sub_socket))
+        })?;
+
+        *self.req_socket.lock().unwrap() = Some(req_socket);
+        self.task = Some(self.runtime.spawn(run_sub(
+            sub_socket,
+            self.last_heartbeat.clone(),
+            callback,
+        )));
+        Ok(())
+    }
+
+    ///Call a function registered on the server and wait for its result.
+    pub fn call(&self, name: &str, args: Vec<Value>, kwargs: Kwargs) -> Result<Value, RpcError> {
+        let mut req_socket = self.req_socket.lock().unwrap();
+        let socket = req_socket.as_mut().ok_or(RpcError::NotStarted)?;
+
+        let request = Value::List(vec![
+            Value::String(name.to_string()),
+            Value::Tuple(args),
+            Value::Dict(kwargs),
+        ]);
+        let reply = self.runtime.block_on(async {
+            socket.send(pack(&request)?).await?;
+            tokio::time::timeout(REQUEST_TIMEOUT, socket.recv())
+                .await
+                .map_err(|_| RpcError::Timeout)?
+                .map_err(RpcError::from)
+        })?;
+
+        let Value::List(items) = unpack(reply)? else {
+            return Err(RpcError::InvalidMessage);
+        };
+        let mut items = items.into_iter();
+        match (items.next(), items.next()) {
+            (Some(Value::Bool(true)), Some(value)) => Ok(value),
+            (Some(Value::Bool(false)), Some(Value::String(msg))) => Err(RpcError::Remote(msg)),
+            _ => Err(RpcError::InvalidMessage),
+        }
+    }
+
+    ///Whether a heartbeat was received within HEARTBEAT_TOLERANCE.
+    pub fn is_alive(&self) -> bool {
+        self.last_heartbeat
+            .lock()
+            .unwrap()
+            .is_some_and(|t| t.elapsed() < HEARTBEAT_TOLERANCE)
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        *self.req_socket.lock().unwrap() = None;
+    }
+}
+
+async fn run_sub<F>(mut socket: SubSocket, last_heartbeat: Arc<Mutex<Option<Instant>>>, callback: F)
+where
+    F: Fn(&str, Value) + Send + 'static,
+{
+    while let Ok(message) = socket.recv().await {
+        let Ok(Value::List(items)) = unpack(message) else {
+            continue;
+        };
+        let mut items = items.into_iter();
+        if let (Some(Value::String(topic)), Some(data)) = (items.next(), items.next()) {
+            if topic == HEARTBEAT_TOPIC {
+                *last_heartbeat.lock().unwrap() = Some(Instant::now());
+            } else {
+                callback(&topic, data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc as std_mpsc;
+
+    ///Addresses of two free local ports.
+    fn addresses() -> (String, String) {
+        let listeners = [(); 2].map(|_| TcpListener::bind("127.0.0.1:0").unwrap());
+        let [rep_address, pub_address] = listeners
+            .each_ref()
+            .map(|listener| format!("tcp://{}", listener.local_addr().unwrap()));
+        (rep_address, pub_address)
+    }
+
+    #[test]
+    fn call_and_publish_round_trip() {
+        let (rep_address, pub_address) = addresses();
+        let mut server = RpcServer::new();
+        server.register("add", |args, kwargs| {
+            let offset = kwargs
+                .get(&HashableValue::String("offset".to_string()))
+                .cloned();
+            args.into_iter()
+                .chain(offset)
+                .map(|value| match value {
+                    Value::I64(value) => Ok(value),
+                    other => Err(format!("not an integer: {:?}", other)),
+                })
+                .sum::<Result<i64, String>>()
+                .map(Value::I64)
+        });
+        server.start(&rep_address, &pub_address).unwrap();
+
+        let (sender, receiver) = std_mpsc::channel();
+        let mut client = RpcClient::new();
+        client
+            .start(&rep_address, &pub_address, move |topic, data| {
+                let _ = sender.send((topic.to_string(), data));
+            })
+            .unwrap();
+
+        let mut kwargs = Kwargs::new();
+        kwargs.insert(HashableValue::String("offset".to_string()), Value::I64(3));
+        let args = vec![Value::I64(1), Value::I64(2)];
+        assert_eq!(client.call("add", args, kwargs).unwrap(), Value::I64(6));
+        // Errors of the function and unknown functions come back as remote errors
+        let args = vec![Value::String("one".to_string())];
+        assert!(matches!(
+            client.call("add", args, Kwargs::new()),
+            Err(RpcError::Remote(msg)) if msg.starts_with("not an integer")
+        ));
+        assert!(matches!(
+            client.call("sub", Vec::new(), Kwargs::new()),
+            Err(RpcError::Remote(msg)) if msg == "function sub not registered"
+        ));
+
+        // The subscriber connects in the background, publish until it receives
+        let tick = Value::String("IF888.CFFEX".to_string());
+        let received = (0..50).find_map(|_| {
+            server.publish("tick", tick.clone());
+            receiver.recv_timeout(Duration::from_millis(100)).ok()
+        });
+        assert_eq!(received, Some(("tick".to_string(), tick)));
+
+        client.stop();
+        assert!(matches!(
+            client.call("add", Vec::new(), Kwargs::new()),
+            Err(RpcError::NotStarted)
+        ));
+        server.stop();
+    }
+}
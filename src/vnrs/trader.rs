@@ -1,5 +1,27 @@
+pub mod audit;
+#[cfg(feature = "backtest-stats")]
+pub mod cache;
+pub mod calendar;
+pub mod clock;
 pub mod constant;
+pub mod converter;
 pub mod database;
+pub mod datafeed;
+pub mod event;
+pub mod features;
+pub mod fx;
+pub mod history;
+pub mod indicators;
+pub mod mapping;
 pub mod object;
+pub mod option;
+pub mod replay;
+#[cfg(feature = "secrets")]
+pub mod secret;
 pub mod setting;
+pub mod spread;
+pub mod stream;
+pub mod synthetic;
+pub mod throttle;
+pub mod timeseries;
 pub mod utility;
\ No newline at end of file
@@ -0,0 +1,294 @@
+/*!Audit log of live trading: inbound ticks, outbound order requests and order/trade updates
+are appended to one JSON lines file per day, which AuditLog loads back to reconstruct the day.
+Parameters of running strategies edited by hand are recorded along with them. */
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use super::clock::{Clock, RealClock};
+use super::event::{EVENT_ORDER, EVENT_TICK, EVENT_TIMER, EVENT_TRADE};
+use super::object::{OrderData, OrderRequest, TickData, TradeData};
+use super::utility::get_file_path;
+use crate::error::VnrsResult;
+use crate::vnrs::event::{Event, EventEngine, HandlerType};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AuditEntry {
+    Tick(TickData),
+    OrderRequest(OrderRequest),
+    Order(OrderData),
+    Trade(TradeData),
+    ///Parameters changed on a running strategy, setting as "name:value" pairs.
+    SettingUpdate {
+        strategy_name: String,
+        setting: String,
+    },
+}
+
+///One line of the audit log, time is the local time it was recorded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub time: NaiveDateTime,
+    pub gateway_name: String,
+    pub entry: AuditEntry,
+}
+
+///Appends what passes through the event engine to the audit log in dir, the file of a day is
+///named like 20240102.jsonl. Writes are buffered and flushed on every timer event.
+pub struct AuditRecorder {
+    dir: PathBuf,
+    clock: Arc<dyn Clock>,
+    writer: Mutex<Option<(NaiveDate, BufWriter<File>)>>,
+}
+
+impl AuditRecorder {
+    pub fn new<P: AsRef<Path>>(dir: P) -> VnrsResult<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(AuditRecorder {
+            dir: dir.as_ref().to_path_buf(),
+            clock: Arc::new(RealClock),
+            writer: Mutex::new(None),
+        })
+    }
+
+    ///Clock records are timed by, the system time by default.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    ///The "audit" folder in the trader dir.
+    pub fn default_dir() -> PathBuf {
+        get_file_path("audit")
+    }
+
+    ///Record EVENT_TICK, EVENT_ORDER and EVENT_TRADE events of the engine, returns the handler
+    ///for unregistering.
+    pub fn register(self: &Arc<Self>, event_engine: &EventEngine) -> HandlerType {
+        let recorder = self.clone();
+        let handler: HandlerType = Arc::new(move |event: &Event| recorder.process_event(event));
+        for type_ in [EVENT_TICK, EVENT_ORDER, EVENT_TRADE, EVENT_TIMER] {
+            event_engine.register(type_, handler.clone());
+        }
+        handler
+    }
+
+    fn process_event(&self, event: &Event) {
+        let result = if let Some(tick) = event.data::<TickData>() {
+            self.record(&tick.gateway_name, AuditEntry::Tick(tick.clone()))
+        } else if let Some(order) = event.data::<OrderData>() {
+            self.record(&order.gateway_name, AuditEntry::Order(order.clone()))
+        } else if let Some(trade) = event.data::<TradeData>() {
+            self.record(&trade.gateway_name, AuditEntry::Trade(trade.clone()))
+        } else if event.type_ == EVENT_TIMER {
+            self.flush()
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            log::error!("审计记录写入失败：{}", e);
+        }
+    }
+
+    ///Record a request before it is sent to the gateway.
+    pub fn record_order_request(&self, gateway_name: &str, req: &OrderRequest) -> VnrsResult<()> {
+        self.record(gateway_name, AuditEntry::OrderRequest(req.clone()))
+    }
+
+    ///Record a parameter change of a running strategy once it is applied.
+    pub fn record_setting_update(
+        &self,
+        gateway_name: &str,
+        strategy_name: &str,
+        setting: &str,
+    ) -> VnrsResult<()> {
+        self.record(
+            gateway_name,
+            AuditEntry::SettingUpdate {
+                strategy_name: strategy_name.to_string(),
+                setting: setting.to_string(),
+            },
+        )
+    }
+
+    pub fn record(&self, gateway_name: &str, entry: AuditEntry) -> VnrsResult<()> {
+        let record = AuditRecord {
+            time: self.clock.now(),
+            gateway_name: gateway_name.to_string(),
+            entry,
+        };
+        let date = record.time.date();
+
+        let mut writer = self.writer.lock().unwrap();
+        // A new file is started when the day changes
+        if writer.as_ref().map(|(d, _)| *d) != Some(date) {
+            if let Some((_, mut file)) = writer.take() {
+                file.flush()?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(audit_path(&self.dir, date))?;
+            *writer = Some((date, BufWriter::new(file)));
+        }
+        let (_, file) = writer.as_mut().unwrap();
+        serde_json::to_writer(&mut *file, &record)?;
+        file.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> VnrsResult<()> {
+        if let Some((_, file)) = self.writer.lock().unwrap().as_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AuditRecorder {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn audit_path(dir: &Path, date: NaiveDate) -> PathBuf {
+    dir.join(format!("{}.jsonl", date.format("%Y%m%d")))
+}
+
+///Records of one day in the order they were made.
+#[derive(Debug, Default, Clone)]
+pub struct AuditLog {
+    pub records: Vec<AuditRecord>,
+}
+
+impl AuditLog {
+    ///Load the log of date in dir. A line cut off by a crash at the end of the file is skipped.
+    pub fn load<P: AsRef<Path>>(dir: P, date: NaiveDate) -> VnrsResult<Self> {
+        let file = File::open(audit_path(dir.as_ref(), date))?;
+        let mut records = Vec::new();
+        let mut lines = BufReader::new(file).lines().peekable();
+        while let Some(line) = lines.next() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(record) => records.push(record),
+                Err(_) if lines.peek().is_none() => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(AuditLog { records })
+    }
+
+    pub fn ticks(&self) -> impl Iterator<Item = &TickData> {
+        self.records
+            .iter()
+            .filter_map(|record| match &record.entry {
+                AuditEntry::Tick(tick) => Some(tick),
+                _ => None,
+            })
+    }
+
+    pub fn order_requests(&self) -> impl Iterator<Item = &OrderRequest> {
+        self.records
+            .iter()
+            .filter_map(|record| match &record.entry {
+                AuditEntry::OrderRequest(req) => Some(req),
+                _ => None,
+            })
+    }
+
+    pub fn trades(&self) -> impl Iterator<Item = &TradeData> {
+        self.records
+            .iter()
+            .filter_map(|record| match &record.entry {
+                AuditEntry::Trade(trade) => Some(trade),
+                _ => None,
+            })
+    }
+
+    ///Last update of every order at the end of the day, keyed by "{gateway_name}.{orderid}".
+    pub fn final_orders(&self) -> HashMap<String, OrderData> {
+        let mut orders = HashMap::new();
+        for record in &self.records {
+            if let AuditEntry::Order(order) = &record.entry {
+                orders.insert(
+                    format!("{}.{}", record.gateway_name, order.orderid),
+                    order.clone(),
+                );
+            }
+        }
+        orders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::clock::SimClock;
+    use crate::vnrs::trader::constant::{Direction, Exchange, Status};
+
+    #[test]
+    fn record_and_reconstruct_day() {
+        let dir = std::env::temp_dir().join(format!("vnrs_audit_{}", std::process::id()));
+        let start = NaiveDateTime::parse_from_str("2024-09-30 09:00", "%Y-%m-%d %H:%M").unwrap();
+        let recorder = Arc::new(
+            AuditRecorder::new(&dir)
+                .unwrap()
+                .with_clock(Arc::new(SimClock::new(start))),
+        );
+        let event_engine = EventEngine::new(1);
+        recorder.register(&event_engine);
+        event_engine.start();
+
+        let tick = TickData {
+            gateway_name: "CTP".into(),
+            symbol: "rb2410".into(),
+            exchange: Exchange::SHFE,
+            last_price: 3500.0,
+            ..Default::default()
+        };
+        event_engine.put(Event::new(EVENT_TICK, tick));
+        let req = OrderRequest {
+            symbol: "rb2410".into(),
+            exchange: Exchange::SHFE,
+            direction: Direction::LONG,
+            price: 3500.0,
+            volume: 1.0,
+            ..Default::default()
+        };
+        recorder.record_order_request("CTP", &req).unwrap();
+        let mut order = req.create_order_data("1", "CTP".into());
+        order.status = Status::NOTTRADED;
+        event_engine.put(Event::new(EVENT_ORDER, order.clone()));
+        order.status = Status::ALLTRADED;
+        order.traded = 1.0;
+        event_engine.put(Event::new(EVENT_ORDER, order));
+        // Unrelated events aren't recorded
+        event_engine.put(Event::new("eTick.rb2410.SHFE", 0));
+
+        // The timer event flushes what was recorded
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        event_engine.stop();
+
+        let date = start.date();
+        let log = AuditLog::load(&dir, date).unwrap();
+        assert_eq!(log.records.len(), 4);
+        assert_eq!(log.ticks().next().unwrap().last_price, 3500.0);
+        assert_eq!(log.order_requests().next().unwrap().symbol, "rb2410");
+        assert_eq!(log.final_orders()["CTP.1"].status, Status::ALLTRADED);
+        assert_eq!(log.trades().count(), 0);
+
+        // A partial line left by a crash is skipped
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(audit_path(&dir, date))
+            .unwrap();
+        file.write_all(b"{\"time\":").unwrap();
+        assert_eq!(AuditLog::load(&dir, date).unwrap().records.len(), 4);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,237 @@
+/*!On-disk cache of bar data as zstd compressed Arrow IPC files, built with `backtest-stats`.
+
+Files are keyed by symbol, exchange, interval in its text format, date range and price
+adjustment, so repeated loads of the same range, e.g. during an optimization sweep, skip the
+database. The cache isn't invalidated when
+the database changes, call clear after importing new data.
+*/
+use chrono::NaiveDateTime;
+use polars::prelude::*;
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+
+use super::constant::{Exchange, Interval};
+use super::database::AdjustMode;
+use super::object::{BarData, Symbol};
+use super::utility::get_trader_dir;
+
+const DATETIME_FORMAT: &str = "%Y%m%d%H%M%S";
+
+pub struct BarCache {
+    dir: PathBuf,
+}
+
+impl Default for BarCache {
+    ///Cache in the "cache" folder of the trader dir.
+    fn default() -> Self {
+        Self::new(get_trader_dir().join("cache"))
+    }
+}
+
+impl BarCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        BarCache { dir: dir.into() }
+    }
+
+    fn file_path(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        adjust: AdjustMode,
+    ) -> PathBuf {
+        // Bars as stored keep the name they had before adjustment was keyed
+        let adjust = match adjust {
+            AdjustMode::None => "",
+            AdjustMode::Forward => "_forward",
+            AdjustMode::Backward => "_backward",
+        };
+        self.dir.join(format!(
+            "{}.{}_{}_{}_{}{}.feather",
+            symbol,
+            exchange,
+            interval,
+            start.format(DATETIME_FORMAT),
+            end.format(DATETIME_FORMAT),
+            adjust
+        ))
+    }
+
+    ///None if the range isn't cached with this adjustment or the file can't be read.
+    pub fn load(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        adjust: AdjustMode,
+    ) -> Option<Vec<BarData>> {
+        let path = self.file_path(symbol, exchange, interval, start, end, adjust);
+        let file = File::open(path).ok()?;
+        let df = IpcReader::new(file).finish().ok()?;
+        df_to_bars(&df, symbol, exchange, interval).ok()
+    }
+
+    ///Store bars loaded with the adjustment adjust, AdjustMode::None for bars as stored.
+    #[allow(clippy::too_many_arguments)]
+    pub fn save(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        adjust: AdjustMode,
+        bars: &[BarData],
+    ) -> PolarsResult<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut df = bars_to_df(bars)?;
+        let path = self.file_path(symbol, exchange, interval, start, end, adjust);
+        let file = File::create(path)?;
+        IpcWriter::new(file)
+            .with_compression(Some(IpcCompression::ZSTD))
+            .finish(&mut df)
+    }
+
+    ///Remove all cached files.
+    pub fn clear(&self) -> io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn bars_to_df(bars: &[BarData]) -> PolarsResult<DataFrame> {
+    let column = |name: &str, f: fn(&BarData) -> f64| {
+        Series::new(name, bars.iter().map(f).collect::<Vec<f64>>())
+    };
+    DataFrame::new(vec![
+        Series::new(
+            "datetime",
+            bars.iter()
+                .map(|bar| bar.datetime)
+                .collect::<Vec<NaiveDateTime>>(),
+        ),
+        column("volume", |bar| bar.volume),
+        column("turnover", |bar| bar.turnover),
+        column("open_interest", |bar| bar.open_interest),
+        column("open_price", |bar| bar.open_price),
+        column("high_price", |bar| bar.high_price),
+        column("low_price", |bar| bar.low_price),
+        column("close_price", |bar| bar.close_price),
+    ])
+}
+
+fn df_to_bars(
+    df: &DataFrame,
+    symbol: &str,
+    exchange: Exchange,
+    interval: Interval,
+) -> PolarsResult<Vec<BarData>> {
+    let datetime = df.column("datetime")?.datetime()?;
+    let column = |name: &str| df.column(name).and_then(|c| c.f64().cloned());
+    let volume = column("volume")?;
+    let turnover = column("turnover")?;
+    let open_interest = column("open_interest")?;
+    let open_price = column("open_price")?;
+    let high_price = column("high_price")?;
+    let low_price = column("low_price")?;
+    let close_price = column("close_price")?;
+    let symbol = Symbol::new(symbol);
+
+    Ok(datetime
+        .as_datetime_iter()
+        .enumerate()
+        .map(|(i, dt)| BarData {
+            gateway_name: "DB".into(),
+            symbol,
+            exchange,
+            datetime: dt.unwrap_or_default(),
+            interval,
+            volume: volume.get(i).unwrap_or_default(),
+            turnover: turnover.get(i).unwrap_or_default(),
+            open_interest: open_interest.get(i).unwrap_or_default(),
+            open_price: open_price.get(i).unwrap_or_default(),
+            high_price: high_price.get(i).unwrap_or_default(),
+            low_price: low_price.get(i).unwrap_or_default(),
+            close_price: close_price.get(i).unwrap_or_default(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn miss_store_hit_invalidate() {
+        let dir = std::env::temp_dir().join(format!("vnrs_cache_{}", std::process::id()));
+        let cache = BarCache::new(&dir);
+        let at = |day: u32| {
+            NaiveDateTime::parse_from_str(&format!("2024-09-{:02} 09:00", day), "%Y-%m-%d %H:%M")
+                .unwrap()
+        };
+        let interval = Interval::SECONDS(5);
+        let load = || {
+            cache.load(
+                "rb2410",
+                Exchange::SHFE,
+                interval,
+                at(1),
+                at(30),
+                AdjustMode::None,
+            )
+        };
+        assert!(load().is_none());
+
+        let bars: Vec<BarData> = (1..=3)
+            .map(|day| BarData {
+                datetime: at(day),
+                interval,
+                volume: day as f64,
+                close_price: 3500.0 + day as f64,
+                ..Default::default()
+            })
+            .collect();
+        cache
+            .save(
+                "rb2410",
+                Exchange::SHFE,
+                interval,
+                at(1),
+                at(30),
+                AdjustMode::None,
+                &bars,
+            )
+            .unwrap();
+        assert!(dir
+            .join("rb2410.SHFE_5s_20240901090000_20240930090000.feather")
+            .exists());
+
+        let cached = load().unwrap();
+        assert_eq!(cached.len(), 3);
+        assert_eq!(cached[2].datetime, at(3));
+        assert_eq!(cached[2].interval, interval);
+        assert_eq!(cached[2].volume, 3.0);
+        assert_eq!(cached[2].close_price, 3503.0);
+        assert!(cache
+            .load(
+                "rb2410",
+                Exchange::SHFE,
+                Interval::MINUTE,
+                at(1),
+                at(30),
+                AdjustMode::None
+            )
+            .is_none());
+
+        cache.clear().unwrap();
+        assert!(load().is_none());
+        cache.clear().unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+/*!Trading days of a market: weekdays that aren't holidays, and how many a year has. */
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Weekday};
+use serde::{Deserialize, Serialize};
+
+use super::constant::{Exchange, Interval, Product};
+use super::object::BarData;
+use crate::error::VnrsResult;
+
+///Time the night session of Chinese futures opens, the trading day starting then is the next
+///trading day.
+pub const CHINA_FUTURES_DAY_START: NaiveTime = NaiveTime::from_hms_opt(20, 0, 0).unwrap();
+
+///Trading days a year of markets not in MARKET_ANNUAL_DAYS.
+pub const DEFAULT_ANNUAL_DAYS: i64 = 240;
+
+///Trading days a year of the markets of each exchange.
+pub const MARKET_ANNUAL_DAYS: &[(&[Exchange], i64)] = &[
+    (
+        &[
+            Exchange::CFFEX,
+            Exchange::SHFE,
+            Exchange::CZCE,
+            Exchange::DCE,
+            Exchange::INE,
+            Exchange::GFEX,
+        ],
+        240,
+    ),
+    (
+        &[
+            Exchange::SSE,
+            Exchange::SZSE,
+            Exchange::BSE,
+            Exchange::SHHK,
+            Exchange::SZHK,
+        ],
+        244,
+    ),
+    (&[Exchange::SEHK, Exchange::HKFE], 247),
+    (
+        &[
+            Exchange::SMART,
+            Exchange::NYSE,
+            Exchange::NASDAQ,
+            Exchange::ARCA,
+            Exchange::EDGEA,
+            Exchange::ISLAND,
+            Exchange::BATS,
+            Exchange::IEX,
+            Exchange::AMEX,
+            Exchange::TSE,
+            Exchange::NYMEX,
+            Exchange::COMEX,
+            Exchange::GLOBEX,
+            Exchange::CME,
+            Exchange::ICE,
+            Exchange::CBOT,
+            Exchange::CBOE,
+            Exchange::CFE,
+            Exchange::EUREX,
+            Exchange::EUNX,
+            Exchange::LME,
+        ],
+        252,
+    ),
+    (&[Exchange::OKX], 365),
+];
+
+///Trading days a year used to annualize statistics of a symbol, forex trades every weekday and
+///swaps, like crypto, every day.
+pub fn get_annual_days(exchange: Exchange, product: Product) -> i64 {
+    match product {
+        Product::FOREX => 260,
+        Product::SWAP => 365,
+        _ => MARKET_ANNUAL_DAYS
+            .iter()
+            .find(|(exchanges, _)| exchanges.contains(&exchange))
+            .map(|(_, days)| *days)
+            .unwrap_or(DEFAULT_ANNUAL_DAYS),
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradingCalendar {
+    pub holidays: BTreeSet<NaiveDate>,
+}
+
+impl TradingCalendar {
+    pub fn new<I: IntoIterator<Item = NaiveDate>>(holidays: I) -> Self {
+        TradingCalendar {
+            holidays: holidays.into_iter().collect(),
+        }
+    }
+
+    ///Load holidays from a JSON array of dates like "2024-10-01".
+    pub fn load_json<P: AsRef<Path>>(path: P) -> VnrsResult<Self> {
+        let holidays: Vec<NaiveDate> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(Self::new(holidays))
+    }
+
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !self.holidays.contains(&date)
+    }
+
+    ///First trading day after date.
+    pub fn next_trading_day(&self, date: NaiveDate) -> NaiveDate {
+        let mut next = date.succ_opt().unwrap();
+        while !self.is_trading_day(next) {
+            next = next.succ_opt().unwrap();
+        }
+        next
+    }
+
+    ///Trading day data at datetime belongs to, for a market whose trading day starts the
+    ///evening before at day_start, or at midnight with NaiveTime::MIN. A night session from
+    ///day_start, including its hours after midnight, counts as the next trading day, e.g. a
+    ///Friday night as the Monday, or the day after a holiday.
+    pub fn trading_day(&self, datetime: NaiveDateTime, day_start: NaiveTime) -> NaiveDate {
+        let shift = match day_start {
+            NaiveTime::MIN => TimeDelta::zero(),
+            _ => TimeDelta::days(1) - (day_start - NaiveTime::MIN),
+        };
+        let date = (datetime + shift).date();
+        if self.is_trading_day(date) {
+            date
+        } else {
+            self.next_trading_day(date)
+        }
+    }
+}
+
+///Daily bars from bars of a smaller interval sorted by datetime, one per trading day of the
+///calendar with the trading day starting at day_start, see TradingCalendar::trading_day.
+///Bars are stamped with midnight of their trading day.
+pub fn daily_bars(
+    bars: &[BarData],
+    calendar: &TradingCalendar,
+    day_start: NaiveTime,
+) -> Vec<BarData> {
+    let mut result: Vec<BarData> = Vec::new();
+    for bar in bars {
+        let datetime = calendar
+            .trading_day(bar.datetime, day_start)
+            .and_time(NaiveTime::MIN);
+        match result.last_mut() {
+            Some(daily) if daily.datetime == datetime => {
+                daily.high_price = daily.high_price.max(bar.high_price);
+                daily.low_price = daily.low_price.min(bar.low_price);
+                daily.close_price = bar.close_price;
+                daily.volume += bar.volume;
+                daily.turnover += bar.turnover;
+                daily.open_interest = bar.open_interest;
+            }
+            _ => result.push(BarData {
+                datetime,
+                interval: Interval::DAILY,
+                ..bar.clone()
+            }),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weekends_and_holidays() {
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        let calendar = TradingCalendar::new([date("2024-10-01"), date("2024-10-02")]);
+        assert!(calendar.is_trading_day(date("2024-09-30")));
+        assert!(!calendar.is_trading_day(date("2024-10-01")));
+        assert!(!calendar.is_trading_day(date("2024-10-05")));
+        assert_eq!(
+            calendar.next_trading_day(date("2024-09-30")),
+            date("2024-10-03")
+        );
+    }
+
+    #[test]
+    fn night_sessions_count_as_next_trading_day() {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        // 2024-09-27 is a Friday, the national day holidays run from 10-01 to 10-07
+        let calendar = TradingCalendar::new((1..=7).map(|day| date(&format!("2024-10-0{}", day))));
+        let day_start = CHINA_FUTURES_DAY_START;
+        assert_eq!(
+            calendar.trading_day(at("2024-09-26 21:00"), day_start),
+            date("2024-09-27")
+        );
+        assert_eq!(
+            calendar.trading_day(at("2024-09-27 14:59"), day_start),
+            date("2024-09-27")
+        );
+        assert_eq!(
+            calendar.trading_day(at("2024-09-27 21:00"), day_start),
+            date("2024-09-30")
+        );
+        assert_eq!(
+            calendar.trading_day(at("2024-09-28 01:30"), day_start),
+            date("2024-09-30")
+        );
+        assert_eq!(
+            calendar.trading_day(at("2024-09-30 21:00"), day_start),
+            date("2024-10-08")
+        );
+        assert_eq!(
+            calendar.trading_day(at("2024-09-27 21:00"), NaiveTime::MIN),
+            date("2024-09-27")
+        );
+
+        let bar = |datetime: &str, close: f64| BarData {
+            datetime: at(datetime),
+            interval: Interval::MINUTE,
+            volume: 1.0,
+            open_price: close,
+            high_price: close,
+            low_price: close,
+            close_price: close,
+            ..Default::default()
+        };
+        let daily = daily_bars(
+            &[
+                bar("2024-09-27 09:00", 10.0),
+                bar("2024-09-27 14:59", 11.0),
+                bar("2024-09-27 21:00", 12.0),
+                bar("2024-09-28 02:29", 9.0),
+                bar("2024-09-30 09:00", 13.0),
+            ],
+            &calendar,
+            day_start,
+        );
+        assert_eq!(daily.len(), 2);
+        assert_eq!(daily[1].datetime, at("2024-09-30 00:00"));
+        assert_eq!(daily[1].interval, Interval::DAILY);
+        assert_eq!(
+            (
+                daily[1].open_price,
+                daily[1].low_price,
+                daily[1].close_price
+            ),
+            (12.0, 9.0, 13.0)
+        );
+        assert_eq!(daily[1].volume, 3.0);
+    }
+
+    #[test]
+    fn annual_days_by_market() {
+        assert_eq!(get_annual_days(Exchange::SHFE, Product::FUTURES), 240);
+        assert_eq!(get_annual_days(Exchange::SSE, Product::EQUITY), 244);
+        assert_eq!(get_annual_days(Exchange::CME, Product::FUTURES), 252);
+        assert_eq!(get_annual_days(Exchange::OKX, Product::SPOT), 365);
+        assert_eq!(get_annual_days(Exchange::IDEALPRO, Product::FOREX), 260);
+        assert_eq!(get_annual_days(Exchange::LOCAL, Product::FUTURES), 240);
+    }
+}
@@ -0,0 +1,90 @@
+/*!Source of the current time. Engines ask a Clock instead of the system time, so that time
+dependent logic runs the same on replayed data as it does live. */
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use chrono::{Local, NaiveDateTime};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> NaiveDateTime;
+
+    ///Block until now() reaches datetime, returns at once if it already has.
+    fn sleep_until(&self, datetime: NaiveDateTime);
+}
+
+///Local system time, for live mode.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+
+    fn sleep_until(&self, datetime: NaiveDateTime) {
+        if let Ok(duration) = (datetime - self.now()).to_std() {
+            thread::sleep(duration);
+        }
+    }
+}
+
+///Time set by whoever drives the simulation, e.g. the datetime of the data being replayed.
+#[derive(Debug, Default)]
+pub struct SimClock {
+    now: Mutex<NaiveDateTime>,
+    changed: Condvar,
+}
+
+impl SimClock {
+    pub fn new(start: NaiveDateTime) -> Self {
+        SimClock {
+            now: Mutex::new(start),
+            changed: Condvar::new(),
+        }
+    }
+
+    ///Move the time forward to datetime, an earlier datetime is ignored.
+    pub fn advance_to(&self, datetime: NaiveDateTime) {
+        let mut now = self.now.lock().unwrap();
+        if datetime > *now {
+            *now = datetime;
+            self.changed.notify_all();
+        }
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> NaiveDateTime {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep_until(&self, datetime: NaiveDateTime) {
+        let now = self.now.lock().unwrap();
+        let _now = self.changed.wait_while(now, |now| *now < datetime).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn sim_clock_wakes_sleepers() {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        let clock = Arc::new(SimClock::new(at("2024-09-30 09:00")));
+        let sleeper = {
+            let clock = clock.clone();
+            thread::spawn(move || {
+                clock.sleep_until(at("2024-09-30 09:30"));
+                clock.now()
+            })
+        };
+
+        clock.advance_to(at("2024-09-30 09:15"));
+        clock.advance_to(at("2024-09-30 09:10"));
+        assert_eq!(clock.now(), at("2024-09-30 09:15"));
+        clock.advance_to(at("2024-09-30 09:30"));
+        assert_eq!(sleeper.join().unwrap(), at("2024-09-30 09:30"));
+    }
+}
@@ -1,7 +1,9 @@
 /*!General constant enums used in the trading platform. */
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
 use strum::{Display, EnumString};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(C)]
 pub enum Direction {
     NONE,
@@ -16,7 +18,7 @@ impl Default for Direction {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(C)]
 pub enum Offset {
     NONE,
@@ -32,7 +34,7 @@ impl Default for Offset {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Status {
     SUBMITTING,
     NOTTRADED,
@@ -48,8 +50,10 @@ impl Default for Status {
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Product {
     EQUITY,
+    #[default]
     FUTURES,
     OPTION,
     INDEX,
@@ -64,7 +68,7 @@ pub enum Product {
     SWAP,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum OrderType {
     LIMIT,
     MARKET,
@@ -80,12 +84,13 @@ impl Default for OrderType {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OptionType {
     CALL,
     PUT,
 }
 
-#[derive(Debug, Clone, Copy, EnumString, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumString, Display, Serialize, Deserialize)]
 pub enum Exchange {
     // Chinese
     CFFEX, // China Financial Futures Exchange
@@ -149,15 +154,26 @@ impl Default for Exchange {
     }
 }
 
+///Currency of the prices of a contract, see FxRates for converting between them.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, EnumString, Display, Serialize, Deserialize,
+)]
 pub enum Currency {
     USD,
     HKD,
+    #[default]
     CNY,
     CAD,
 }
 
+///Written as vnpy's interval values ("1m", "1h", "d", "w", "tick") or e.g. "5s" for seconds,
+///"renko10", "range10" and "vol500" for bars closing on price or volume, by Display and serde,
+///the same in the database, CLI arguments, datafeeds and config files. The variant names, e.g.
+///"MINUTE", are parsed too.
+///
+///The variants holding a size don't cross the strategy ABI, which takes the IntervalKind tag
+///and the size apart.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-#[repr(C)]
 pub enum Interval {
     NONE,
     MINUTE,
@@ -165,9 +181,212 @@ pub enum Interval {
     DAILY,
     WEEKLY,
     TICK,
+    ///Bars of a number of seconds, e.g. "5s".
+    SECONDS(u32),
+    ///Renko bricks of a number of price ticks.
+    RENKO(u32),
+    ///Bars closing once their high and low are a number of price ticks apart.
+    RANGE(u32),
+    ///Bars closing once they hold a volume.
+    VOLUME(u32),
+}
+
+impl Interval {
+    ///Whether bars of the interval close on price or volume instead of time, see
+    ///AltBarBuilder.
+    pub fn is_alt_bar(&self) -> bool {
+        matches!(
+            self,
+            Interval::RENKO(_) | Interval::RANGE(_) | Interval::VOLUME(_)
+        )
+    }
+
+    ///Tag of the interval passed over the strategy ABI, with its size().
+    pub fn kind(&self) -> IntervalKind {
+        match self {
+            Interval::NONE => IntervalKind::NONE,
+            Interval::MINUTE => IntervalKind::MINUTE,
+            Interval::HOUR => IntervalKind::HOUR,
+            Interval::DAILY => IntervalKind::DAILY,
+            Interval::WEEKLY => IntervalKind::WEEKLY,
+            Interval::TICK => IntervalKind::TICK,
+            Interval::SECONDS(_) => IntervalKind::SECONDS,
+            Interval::RENKO(_) => IntervalKind::RENKO,
+            Interval::RANGE(_) => IntervalKind::RANGE,
+            Interval::VOLUME(_) => IntervalKind::VOLUME,
+        }
+    }
+
+    ///Number of seconds, price ticks or volume of the bars, 0 for the fixed intervals.
+    pub fn size(&self) -> u32 {
+        match self {
+            Interval::SECONDS(size)
+            | Interval::RENKO(size)
+            | Interval::RANGE(size)
+            | Interval::VOLUME(size) => *size,
+            _ => 0,
+        }
+    }
+
+    ///Interval of a tag and size from the strategy ABI, None for a sized kind of size 0.
+    pub fn from_kind(kind: IntervalKind, size: u32) -> Option<Interval> {
+        let sized = |variant: fn(u32) -> Interval| (size > 0).then(|| variant(size));
+        match kind {
+            IntervalKind::NONE => Some(Interval::NONE),
+            IntervalKind::MINUTE => Some(Interval::MINUTE),
+            IntervalKind::HOUR => Some(Interval::HOUR),
+            IntervalKind::DAILY => Some(Interval::DAILY),
+            IntervalKind::WEEKLY => Some(Interval::WEEKLY),
+            IntervalKind::TICK => Some(Interval::TICK),
+            IntervalKind::SECONDS => sized(Interval::SECONDS),
+            IntervalKind::RENKO => sized(Interval::RENKO),
+            IntervalKind::RANGE => sized(Interval::RANGE),
+            IntervalKind::VOLUME => sized(Interval::VOLUME),
+        }
+    }
+}
+
+///Fieldless tag of an Interval for the strategy ABI, the size of the sized ones passed next
+///to it. The first variants keep the values of the fieldless Interval strategies were built
+///against before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub enum IntervalKind {
+    NONE,
+    MINUTE,
+    HOUR,
+    DAILY,
+    WEEKLY,
+    TICK,
+    SECONDS,
+    RENKO,
+    RANGE,
+    VOLUME,
 }
 impl Default for Interval {
     fn default() -> Self {
         Interval::NONE
     }
 }
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interval::NONE => write!(f, "NONE"),
+            Interval::MINUTE => write!(f, "1m"),
+            Interval::HOUR => write!(f, "1h"),
+            Interval::DAILY => write!(f, "d"),
+            Interval::WEEKLY => write!(f, "w"),
+            Interval::TICK => write!(f, "tick"),
+            Interval::SECONDS(seconds) => write!(f, "{}s", seconds),
+            Interval::RENKO(ticks) => write!(f, "renko{}", ticks),
+            Interval::RANGE(ticks) => write!(f, "range{}", ticks),
+            Interval::VOLUME(volume) => write!(f, "vol{}", volume),
+        }
+    }
+}
+
+impl FromStr for Interval {
+    type Err = strum::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "NONE" => Ok(Interval::NONE),
+            "1m" | "MINUTE" => Ok(Interval::MINUTE),
+            "1h" | "HOUR" => Ok(Interval::HOUR),
+            "d" | "DAILY" => Ok(Interval::DAILY),
+            "w" | "WEEKLY" => Ok(Interval::WEEKLY),
+            "tick" | "TICK" => Ok(Interval::TICK),
+            _ => {
+                let (variant, size): (fn(u32) -> Interval, &str) =
+                    if let Some(size) = s.strip_prefix("renko") {
+                        (Interval::RENKO, size)
+                    } else if let Some(size) = s.strip_prefix("range") {
+                        (Interval::RANGE, size)
+                    } else if let Some(size) = s.strip_prefix("vol") {
+                        (Interval::VOLUME, size)
+                    } else if let Some(size) = s.strip_suffix('s') {
+                        (Interval::SECONDS, size)
+                    } else {
+                        return Err(strum::ParseError::VariantNotFound);
+                    };
+                size.parse()
+                    .ok()
+                    .filter(|size| *size > 0)
+                    .map(variant)
+                    .ok_or(strum::ParseError::VariantNotFound)
+            }
+        }
+    }
+}
+
+impl Serialize for Interval {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Interval {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Interval::from_str(&text)
+            .map_err(|_| de::Error::custom(format!("invalid interval {}", text)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval_text() {
+        for (interval, text) in [
+            (Interval::MINUTE, "1m"),
+            (Interval::HOUR, "1h"),
+            (Interval::DAILY, "d"),
+            (Interval::WEEKLY, "w"),
+            (Interval::TICK, "tick"),
+            (Interval::SECONDS(5), "5s"),
+            (Interval::RENKO(10), "renko10"),
+            (Interval::RANGE(4), "range4"),
+            (Interval::VOLUME(500), "vol500"),
+        ] {
+            assert_eq!(interval.to_string(), text);
+            assert_eq!(text.parse::<Interval>().unwrap(), interval);
+            assert_eq!(serde_json::to_value(interval).unwrap(), text);
+        }
+        assert_eq!("MINUTE".parse::<Interval>().unwrap(), Interval::MINUTE);
+        assert_eq!(
+            serde_json::from_str::<Interval>("\"DAILY\"").unwrap(),
+            Interval::DAILY
+        );
+        assert!("5m".parse::<Interval>().is_err());
+        assert!("0s".parse::<Interval>().is_err());
+        assert!("renko0".parse::<Interval>().is_err());
+        assert!("rangex".parse::<Interval>().is_err());
+    }
+
+    #[test]
+    fn interval_kind_round_trip() {
+        for interval in [
+            Interval::NONE,
+            Interval::MINUTE,
+            Interval::WEEKLY,
+            Interval::TICK,
+            Interval::SECONDS(5),
+            Interval::RENKO(10),
+            Interval::RANGE(4),
+            Interval::VOLUME(500),
+        ] {
+            assert_eq!(
+                Interval::from_kind(interval.kind(), interval.size()),
+                Some(interval)
+            );
+        }
+        assert_eq!(
+            Interval::from_kind(IntervalKind::DAILY, 7),
+            Some(Interval::DAILY)
+        );
+        assert_eq!(Interval::from_kind(IntervalKind::SECONDS, 0), None);
+    }
+}
@@ -0,0 +1,564 @@
+/*!Offset conversion between order requests and long/short position holdings. */
+use std::collections::HashMap;
+
+use super::constant::{Direction, Exchange, Offset};
+use super::object::{ContractData, GatewayName, OrderData, OrderRequest, PositionData, TradeData};
+
+///Exchanges which distinguish close today from close yesterday.
+const CLOSE_YD_EXCHANGES: [Exchange; 2] = [Exchange::SHFE, Exchange::INE];
+
+type GetContract = Box<dyn Fn(&str) -> Option<ContractData> + Send + Sync>;
+
+pub struct OffsetConverter {
+    holdings: HashMap<String, PositionHolding>,
+    get_contract: GetContract,
+}
+
+impl Default for OffsetConverter {
+    ///Converter without contracts, every request is passed through unchanged.
+    fn default() -> Self {
+        Self::new(|_| None)
+    }
+}
+
+impl OffsetConverter {
+    pub fn new(
+        get_contract: impl Fn(&str) -> Option<ContractData> + Send + Sync + 'static,
+    ) -> Self {
+        OffsetConverter {
+            holdings: HashMap::new(),
+            get_contract: Box::new(get_contract),
+        }
+    }
+
+    pub fn update_position(&mut self, position: &PositionData) {
+        if let Some(holding) = self.get_position_holding(&position.vt_symbol()) {
+            holding.update_position(position);
+        }
+    }
+
+    pub fn update_trade(&mut self, trade: &TradeData) {
+        if let Some(holding) = self.get_position_holding(&trade.vt_symbol()) {
+            holding.update_trade(trade);
+        }
+    }
+
+    pub fn update_order(&mut self, order: &OrderData) {
+        if let Some(holding) = self.get_position_holding(&order.vt_symbol()) {
+            holding.update_order(order);
+        }
+    }
+
+    pub fn update_order_request(&mut self, req: &OrderRequest, vt_orderid: &str) {
+        if let Some(holding) = self.get_position_holding(&req.vt_symbol()) {
+            holding.update_order_request(req, vt_orderid);
+        }
+    }
+
+    ///Holding of a symbol, None before its first order or trade.
+    pub fn get_holding(&self, vt_symbol: &str) -> Option<&PositionHolding> {
+        self.holdings.get(vt_symbol)
+    }
+
+    ///Move today positions into yesterday positions when a new trading day begins.
+    pub fn roll_day(&mut self) {
+        for holding in self.holdings.values_mut() {
+            holding.roll_day();
+        }
+    }
+
+    ///Split or convert the offset of an order request, an empty list means the
+    ///request can't be satisfied by current positions.
+    pub fn convert_order_request(
+        &mut self,
+        req: &OrderRequest,
+        lock: bool,
+        net: bool,
+    ) -> Vec<OrderRequest> {
+        let Some(holding) = self.get_position_holding(&req.vt_symbol()) else {
+            return vec![req.clone()];
+        };
+
+        if lock {
+            holding.convert_order_request_lock(req)
+        } else if net {
+            holding.convert_order_request_net(req)
+        } else if CLOSE_YD_EXCHANGES.contains(&req.exchange) {
+            holding.convert_order_request_shfe(req)
+        } else {
+            vec![req.clone()]
+        }
+    }
+
+    ///None if conversion isn't required, i.e. the contract is unknown or uses net position.
+    fn get_position_holding(&mut self, vt_symbol: &str) -> Option<&mut PositionHolding> {
+        if !self.holdings.contains_key(vt_symbol) {
+            let contract = (self.get_contract)(vt_symbol)?;
+            if contract.net_position {
+                return None;
+            }
+            self.holdings
+                .insert(vt_symbol.to_string(), PositionHolding::new(&contract));
+        }
+        self.holdings.get_mut(vt_symbol)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PositionHolding {
+    pub vt_symbol: String,
+    pub exchange: Exchange,
+
+    active_orders: HashMap<String, OrderData>,
+
+    pub long_pos: f64,
+    pub long_yd: f64,
+    pub long_td: f64,
+
+    pub short_pos: f64,
+    pub short_yd: f64,
+    pub short_td: f64,
+
+    pub long_pos_frozen: f64,
+    pub long_yd_frozen: f64,
+    pub long_td_frozen: f64,
+
+    pub short_pos_frozen: f64,
+    pub short_yd_frozen: f64,
+    pub short_td_frozen: f64,
+}
+
+impl PositionHolding {
+    pub fn new(contract: &ContractData) -> Self {
+        PositionHolding {
+            vt_symbol: contract.vt_symbol(),
+            exchange: contract.exchange,
+            ..Default::default()
+        }
+    }
+
+    pub fn update_position(&mut self, position: &PositionData) {
+        if position.direction == Direction::LONG {
+            self.long_pos = position.volume;
+            self.long_yd = position.yd_volume;
+            self.long_td = self.long_pos - self.long_yd;
+        } else {
+            self.short_pos = position.volume;
+            self.short_yd = position.yd_volume;
+            self.short_td = self.short_pos - self.short_yd;
+        }
+    }
+
+    pub fn update_order(&mut self, order: &OrderData) {
+        self.update_active_order(order.vt_orderid(), order);
+    }
+
+    pub fn update_order_request(&mut self, req: &OrderRequest, vt_orderid: &str) {
+        let orderid = vt_orderid
+            .split_once('.')
+            .map_or(vt_orderid, |(_, orderid)| orderid);
+        let order = req.create_order_data(orderid, GatewayName::default());
+        self.update_active_order(vt_orderid.to_string(), &order);
+    }
+
+    fn update_active_order(&mut self, vt_orderid: String, order: &OrderData) {
+        if order.is_active() {
+            self.active_orders.insert(vt_orderid, order.clone());
+        } else {
+            self.active_orders.remove(&vt_orderid);
+        }
+
+        self.calculate_frozen();
+    }
+
+    pub fn update_trade(&mut self, trade: &TradeData) {
+        let close_yd = CLOSE_YD_EXCHANGES.contains(&trade.exchange);
+        let (td, yd) = if trade.direction == Direction::LONG {
+            if trade.offset == Offset::OPEN {
+                self.long_td += trade.volume;
+                (None, None)
+            } else {
+                (Some(&mut self.short_td), Some(&mut self.short_yd))
+            }
+        } else if trade.offset == Offset::OPEN {
+            self.short_td += trade.volume;
+            (None, None)
+        } else {
+            (Some(&mut self.long_td), Some(&mut self.long_yd))
+        };
+
+        if let (Some(td), Some(yd)) = (td, yd) {
+            match trade.offset {
+                Offset::CLOSETODAY => *td -= trade.volume,
+                Offset::CLOSEYESTERDAY => *yd -= trade.volume,
+                Offset::CLOSE if close_yd => *yd -= trade.volume,
+                Offset::CLOSE => {
+                    *td -= trade.volume;
+                    if *td < 0.0 {
+                        *yd += *td;
+                        *td = 0.0;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.long_pos = self.long_td + self.long_yd;
+        self.short_pos = self.short_td + self.short_yd;
+
+        // Update frozen volume to ensure no more than total volume
+        self.sum_pos_frozen();
+    }
+
+    pub fn roll_day(&mut self) {
+        self.long_yd += self.long_td;
+        self.long_td = 0.0;
+        self.short_yd += self.short_td;
+        self.short_td = 0.0;
+        self.calculate_frozen();
+    }
+
+    fn calculate_frozen(&mut self) {
+        self.long_pos_frozen = 0.0;
+        self.long_yd_frozen = 0.0;
+        self.long_td_frozen = 0.0;
+
+        self.short_pos_frozen = 0.0;
+        self.short_yd_frozen = 0.0;
+        self.short_td_frozen = 0.0;
+
+        for order in self.active_orders.values() {
+            // Ignore position open orders
+            if order.offset == Offset::OPEN {
+                continue;
+            }
+
+            let frozen = order.volume - order.traded;
+            let (td_frozen, yd_frozen, td) = if order.direction == Direction::LONG {
+                (
+                    &mut self.short_td_frozen,
+                    &mut self.short_yd_frozen,
+                    self.short_td,
+                )
+            } else {
+                (
+                    &mut self.long_td_frozen,
+                    &mut self.long_yd_frozen,
+                    self.long_td,
+                )
+            };
+
+            match order.offset {
+                Offset::CLOSETODAY => *td_frozen += frozen,
+                Offset::CLOSEYESTERDAY => *yd_frozen += frozen,
+                Offset::CLOSE => {
+                    *td_frozen += frozen;
+                    if *td_frozen > td {
+                        *yd_frozen += *td_frozen - td;
+                        *td_frozen = td;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.sum_pos_frozen();
+    }
+
+    fn sum_pos_frozen(&mut self) {
+        // Frozen volume should be no more than total volume
+        self.long_td_frozen = self.long_td_frozen.min(self.long_td);
+        self.long_yd_frozen = self.long_yd_frozen.min(self.long_yd);
+
+        self.short_td_frozen = self.short_td_frozen.min(self.short_td);
+        self.short_yd_frozen = self.short_yd_frozen.min(self.short_yd);
+
+        self.long_pos_frozen = self.long_td_frozen + self.long_yd_frozen;
+        self.short_pos_frozen = self.short_td_frozen + self.short_yd_frozen;
+    }
+
+    ///(pos, td, yd) available for closing by an order of direction.
+    fn available(&self, direction: Direction) -> (f64, f64, f64) {
+        if direction == Direction::LONG {
+            (
+                self.short_pos - self.short_pos_frozen,
+                self.short_td - self.short_td_frozen,
+                self.short_yd - self.short_yd_frozen,
+            )
+        } else {
+            (
+                self.long_pos - self.long_pos_frozen,
+                self.long_td - self.long_td_frozen,
+                self.long_yd - self.long_yd_frozen,
+            )
+        }
+    }
+
+    pub fn convert_order_request_shfe(&self, req: &OrderRequest) -> Vec<OrderRequest> {
+        if req.offset == Offset::OPEN {
+            return vec![req.clone()];
+        }
+
+        let (pos_available, td_available, _) = self.available(req.direction);
+
+        if req.volume > pos_available {
+            Vec::new()
+        } else if req.volume <= td_available {
+            vec![split_request(req, Offset::CLOSETODAY, req.volume)]
+        } else {
+            let mut req_list = Vec::new();
+            if td_available > 0.0 {
+                req_list.push(split_request(req, Offset::CLOSETODAY, td_available));
+            }
+            req_list.push(split_request(
+                req,
+                Offset::CLOSEYESTERDAY,
+                req.volume - td_available,
+            ));
+            req_list
+        }
+    }
+
+    pub fn convert_order_request_lock(&self, req: &OrderRequest) -> Vec<OrderRequest> {
+        let (td_volume, yd_available) = if req.direction == Direction::LONG {
+            (self.short_td, self.short_yd - self.short_yd_frozen)
+        } else {
+            (self.long_td, self.long_yd - self.long_yd_frozen)
+        };
+        let close_yd = CLOSE_YD_EXCHANGES.contains(&self.exchange);
+
+        // If there is td_volume, we can only lock position
+        if td_volume > 0.0 && !close_yd {
+            return vec![split_request(req, Offset::OPEN, req.volume)];
+        }
+
+        // If no td_volume, we close opposite yd position first then open new position
+        let close_volume = req.volume.min(yd_available);
+        let open_volume = (req.volume - yd_available).max(0.0);
+
+        let mut req_list = Vec::new();
+        if yd_available > 0.0 {
+            let offset = if close_yd {
+                Offset::CLOSEYESTERDAY
+            } else {
+                Offset::CLOSE
+            };
+            req_list.push(split_request(req, offset, close_volume));
+        }
+        if open_volume > 0.0 {
+            req_list.push(split_request(req, Offset::OPEN, open_volume));
+        }
+        req_list
+    }
+
+    pub fn convert_order_request_net(&self, req: &OrderRequest) -> Vec<OrderRequest> {
+        let (pos_available, td_available, yd_available) = self.available(req.direction);
+        let mut req_list = Vec::new();
+        let mut volume_left = req.volume;
+
+        // Split close order to close today/yesterday for SHFE/INE exchange
+        if CLOSE_YD_EXCHANGES.contains(&req.exchange) {
+            if td_available > 0.0 {
+                let td_volume = td_available.min(volume_left);
+                volume_left -= td_volume;
+                req_list.push(split_request(req, Offset::CLOSETODAY, td_volume));
+            }
+            if volume_left > 0.0 && yd_available > 0.0 {
+                let yd_volume = yd_available.min(volume_left);
+                volume_left -= yd_volume;
+                req_list.push(split_request(req, Offset::CLOSEYESTERDAY, yd_volume));
+            }
+        }
+        // Just use close for other exchanges
+        else if pos_available > 0.0 {
+            let close_volume = pos_available.min(volume_left);
+            volume_left -= close_volume;
+            req_list.push(split_request(req, Offset::CLOSE, close_volume));
+        }
+
+        if volume_left > 0.0 {
+            req_list.push(split_request(req, Offset::OPEN, volume_left));
+        }
+        req_list
+    }
+}
+
+fn split_request(req: &OrderRequest, offset: Offset, volume: f64) -> OrderRequest {
+    OrderRequest {
+        offset,
+        volume,
+        ..req.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::constant::{OrderType, Status};
+
+    fn converter(exchange: Exchange) -> OffsetConverter {
+        OffsetConverter::new(move |vt_symbol| {
+            let (symbol, _) = vt_symbol.split_once('.')?;
+            Some(ContractData {
+                symbol: symbol.into(),
+                exchange,
+                net_position: symbol == "NET",
+                ..Default::default()
+            })
+        })
+    }
+
+    fn request(
+        symbol: &str,
+        exchange: Exchange,
+        direction: Direction,
+        volume: f64,
+    ) -> OrderRequest {
+        OrderRequest {
+            symbol: symbol.into(),
+            exchange,
+            direction,
+            type_: OrderType::LIMIT,
+            volume,
+            price: 100.0,
+            offset: Offset::CLOSE,
+            reference: String::new(),
+        }
+    }
+
+    fn trade(exchange: Exchange, direction: Direction, offset: Offset, volume: f64) -> TradeData {
+        TradeData {
+            gateway_name: GatewayName::default(),
+            symbol: "rb2410".into(),
+            exchange,
+            orderid: String::new(),
+            tradeid: String::new(),
+            direction,
+            offset,
+            price: 100.0,
+            volume,
+            datetime: Default::default(),
+            reference: String::new(),
+        }
+    }
+
+    fn offsets(req_list: &[OrderRequest]) -> Vec<(Offset, f64)> {
+        req_list
+            .iter()
+            .map(|req| (req.offset, req.volume))
+            .collect()
+    }
+
+    #[test]
+    fn close_today_split_on_shfe() {
+        let mut converter = converter(Exchange::SHFE);
+        converter.update_trade(&trade(Exchange::SHFE, Direction::LONG, Offset::OPEN, 3.0));
+        converter.roll_day();
+        converter.update_trade(&trade(Exchange::SHFE, Direction::LONG, Offset::OPEN, 2.0));
+        let holding = converter.get_holding("rb2410.SHFE").unwrap();
+        assert_eq!((holding.long_td, holding.long_yd), (2.0, 3.0));
+
+        let sell = |volume| request("rb2410", Exchange::SHFE, Direction::SHORT, volume);
+        assert_eq!(
+            offsets(&converter.convert_order_request(&sell(1.0), false, false)),
+            [(Offset::CLOSETODAY, 1.0)]
+        );
+        assert_eq!(
+            offsets(&converter.convert_order_request(&sell(4.0), false, false)),
+            [(Offset::CLOSETODAY, 2.0), (Offset::CLOSEYESTERDAY, 2.0)]
+        );
+        assert!(converter
+            .convert_order_request(&sell(6.0), false, false)
+            .is_empty());
+
+        // Volume of an active close order is frozen until it's no longer active
+        let req = sell(4.0);
+        let mut order = req.create_order_data("1", GatewayName::default());
+        converter.update_order_request(&req, &order.vt_orderid());
+        assert!(converter
+            .convert_order_request(&sell(2.0), false, false)
+            .is_empty());
+        order.status = Status::CANCELLED;
+        converter.update_order(&order);
+        assert_eq!(
+            converter
+                .convert_order_request(&sell(2.0), false, false)
+                .len(),
+            1
+        );
+
+        // Close on SHFE closes yesterday's position
+        converter.update_trade(&trade(Exchange::SHFE, Direction::SHORT, Offset::CLOSE, 1.0));
+        let holding = converter.get_holding("rb2410.SHFE").unwrap();
+        assert_eq!(
+            (holding.long_td, holding.long_yd, holding.long_pos),
+            (2.0, 2.0, 4.0)
+        );
+        // Opening passes through
+        let mut open = sell(10.0);
+        open.offset = Offset::OPEN;
+        assert_eq!(
+            offsets(&converter.convert_order_request(&open, false, false)),
+            [(Offset::OPEN, 10.0)]
+        );
+    }
+
+    #[test]
+    fn lock_and_net_conversion() {
+        let mut converter = converter(Exchange::CFFEX);
+        converter.update_trade(&trade(Exchange::CFFEX, Direction::LONG, Offset::OPEN, 2.0));
+        let sell = |volume| request("rb2410", Exchange::CFFEX, Direction::SHORT, volume);
+
+        // A position opened today is locked instead of closed
+        assert_eq!(
+            offsets(&converter.convert_order_request(&sell(3.0), true, false)),
+            [(Offset::OPEN, 3.0)]
+        );
+        // Net closes what is held and opens the rest
+        assert_eq!(
+            offsets(&converter.convert_order_request(&sell(5.0), false, true)),
+            [(Offset::CLOSE, 2.0), (Offset::OPEN, 3.0)]
+        );
+
+        // Closing today's position moves into yesterday's on a new day, then it's closed
+        converter.roll_day();
+        let holding = converter.get_holding("rb2410.CFFEX").unwrap();
+        assert_eq!((holding.long_td, holding.long_yd), (0.0, 2.0));
+        assert_eq!(
+            offsets(&converter.convert_order_request(&sell(3.0), true, false)),
+            [(Offset::CLOSE, 2.0), (Offset::OPEN, 1.0)]
+        );
+
+        // SHFE nets today's position first, then yesterday's
+        let mut converter = self::converter(Exchange::SHFE);
+        converter.update_trade(&trade(Exchange::SHFE, Direction::LONG, Offset::OPEN, 1.0));
+        converter.roll_day();
+        converter.update_trade(&trade(Exchange::SHFE, Direction::LONG, Offset::OPEN, 1.0));
+        let sell = request("rb2410", Exchange::SHFE, Direction::SHORT, 3.0);
+        assert_eq!(
+            offsets(&converter.convert_order_request(&sell, false, true)),
+            [
+                (Offset::CLOSETODAY, 1.0),
+                (Offset::CLOSEYESTERDAY, 1.0),
+                (Offset::OPEN, 1.0)
+            ]
+        );
+        assert_eq!(
+            offsets(&converter.convert_order_request(&sell, true, false)),
+            [(Offset::CLOSEYESTERDAY, 1.0), (Offset::OPEN, 2.0)]
+        );
+    }
+
+    #[test]
+    fn net_position_contracts_pass_through() {
+        let mut converter = converter(Exchange::SHFE);
+        let req = request("NET", Exchange::SHFE, Direction::SHORT, 5.0);
+        assert_eq!(
+            offsets(&converter.convert_order_request(&req, false, false)),
+            [(Offset::CLOSE, 5.0)]
+        );
+        assert!(converter.get_holding("NET.SHFE").is_none());
+        let mut converter = OffsetConverter::default();
+        assert_eq!(converter.convert_order_request(&req, true, false).len(), 1);
+    }
+}
@@ -1,29 +1,71 @@
-use super::setting::{get_settings, SETTINGS};
+use super::setting::get_settings;
 use env_logger::builder;
 use log::{self};
-use sqlx::sqlite::SqlitePool;
-use sqlx::Row;
-use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-use tokio;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 
 use super::constant::{Exchange, Interval};
-use super::object::BarData;
+use super::object::{BarData, TickData};
+use super::timeseries::TimeSeries;
+use crate::error::{VnrsError, VnrsResult};
 
-pub static DBMAP: Mutex<GlobalDBMap> = Mutex::new(GlobalDBMap::new());
+#[cfg(feature = "mysql")]
+mod mysql;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "mysql")]
+pub use mysql::MysqlDatabase;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteDatabase;
+
+///Databases connected so far, read by every get_database call and written only on connect.
+pub static DBMAP: RwLock<GlobalDBMap> = RwLock::new(GlobalDBMap::new());
 
 pub struct GlobalDBMap {
+    #[cfg(feature = "sqlite")]
     sqlite: Option<Arc<SqliteDatabase>>,
+    #[cfg(feature = "mysql")]
+    mysql: Option<Arc<MysqlDatabase>>,
+    memory: Option<Arc<MemoryDatabase>>,
+    ///Set by set_database, used instead of the database in the settings.
+    custom: Option<Arc<dyn BaseDatabase + Send + Sync>>,
 }
 
 impl GlobalDBMap {
     pub const fn new() -> Self {
-        GlobalDBMap { sqlite: None }
+        GlobalDBMap {
+            #[cfg(feature = "sqlite")]
+            sqlite: None,
+            #[cfg(feature = "mysql")]
+            mysql: None,
+            memory: None,
+            custom: None,
+        }
     }
 }
 
+///Use database for all later get_database calls in the process, e.g. a MemoryDatabase in
+///tests.
+pub fn set_database(database: Arc<dyn BaseDatabase + Send + Sync>) {
+    DBMAP.write().unwrap().custom = Some(database);
+}
+
+///set_database for the test holding the guard, tests setting the database run one at a time.
+#[cfg(test)]
+pub(crate) fn set_test_database(
+    database: Arc<dyn BaseDatabase + Send + Sync>,
+) -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    set_database(database);
+    guard
+}
+
+///Databases are shared by the threads of parallel backtests, loads may run concurrently.
 pub trait BaseDatabase {
     fn load_bar_data(
         &self,
@@ -32,45 +74,323 @@ pub trait BaseDatabase {
         interval: Interval,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> Vec<BarData>;
+    ) -> VnrsResult<Vec<BarData>>;
+
+    fn load_tick_data(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<Vec<TickData>>;
+
+    ///Store bars, e.g. history fetched from a datafeed.
+    fn save_bar_data(&self, bars: Vec<BarData>) -> VnrsResult<()> {
+        self.save_bar_data_with_progress(bars, &|_, _| {})
+    }
+
+    ///save_bar_data calling progress with (bars saved, total) as it goes, e.g. when importing
+    ///years of minute bars. All or none of the bars are stored.
+    fn save_bar_data_with_progress(
+        &self,
+        _bars: Vec<BarData>,
+        _progress: &dyn Fn(usize, usize),
+    ) -> VnrsResult<()> {
+        Err(unsupported("saving bars"))
+    }
+
+    ///Store a backtest run, returns the id given to it.
+    fn save_backtest_record(&self, _record: BacktestRecord) -> VnrsResult<i64> {
+        Err(unsupported("saving backtest records"))
+    }
+
+    ///Backtest runs matching query, latest first and without their trades.
+    fn load_backtest_records(&self, _query: &BacktestQuery) -> VnrsResult<Vec<BacktestRecord>> {
+        Err(unsupported("loading backtest records"))
+    }
+
+    ///One backtest run with its trades if they were saved, None if there is no such run.
+    fn load_backtest_record(&self, _id: i64) -> VnrsResult<Option<BacktestRecord>> {
+        Err(unsupported("loading backtest records"))
+    }
+
+    ///Store a setting evaluated by an optimization.
+    fn save_optimization_trial(&self, _trial: OptimizationTrial) -> VnrsResult<()> {
+        Err(unsupported("saving optimization trials"))
+    }
+
+    ///Trials of the optimization study_id in the order they were saved.
+    fn load_optimization_trials(&self, _study_id: &str) -> VnrsResult<Vec<OptimizationTrial>> {
+        Err(unsupported("loading optimization trials"))
+    }
+
+    ///Store the adjustment factors of a stock, replacing those of the same dates.
+    fn save_adjust_factors(
+        &self,
+        _symbol: &str,
+        _exchange: Exchange,
+        _factors: &AdjustFactors,
+    ) -> VnrsResult<()> {
+        Err(unsupported("saving adjustment factors"))
+    }
+
+    ///Adjustment factors of a stock, empty if none were saved.
+    fn load_adjust_factors(&self, _symbol: &str, _exchange: Exchange) -> VnrsResult<AdjustFactors> {
+        Err(unsupported("loading adjustment factors"))
+    }
+
+    ///load_bar_data with the prices and volumes of SSE and SZSE stocks adjusted for splits and
+    ///dividends by their adjustment factors. Bars of other exchanges are returned as stored.
+    fn load_adjusted_bar_data(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        adjust: AdjustMode,
+    ) -> VnrsResult<Vec<BarData>> {
+        let mut bars = self.load_bar_data(symbol, exchange, interval, start, end)?;
+        if adjust.applies_to(exchange) {
+            adjust_bars(
+                &mut bars,
+                &self.load_adjust_factors(symbol, exchange)?,
+                adjust,
+            );
+        }
+        Ok(bars)
+    }
+}
+
+///Cumulative adjustment factor of a stock by ex-date, a price times the factor in effect on
+///its date being the backward adjusted price.
+pub type AdjustFactors = TimeSeries<f64, NaiveDate>;
+
+///How prices are adjusted for splits and dividends.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdjustMode {
+    ///Prices as traded.
+    #[default]
+    None,
+    ///Latest prices as traded, earlier prices scaled to them.
+    Forward,
+    ///Earliest prices as traded, later prices scaled to them.
+    Backward,
+}
+
+impl AdjustMode {
+    ///Whether bars of exchange are adjusted, only stocks of SSE and SZSE are.
+    pub fn applies_to(self, exchange: Exchange) -> bool {
+        self != AdjustMode::None && matches!(exchange, Exchange::SSE | Exchange::SZSE)
+    }
+}
+
+///Scale the prices of bars by the factor in effect on their date, relative to the latest
+///factor for forward adjustment, and the volumes by its inverse so that the turnover stays as
+///traded. Bars before the first factor take a factor of 1.
+pub fn adjust_bars(bars: &mut [BarData], factors: &AdjustFactors, adjust: AdjustMode) {
+    let base = match (adjust, factors.last()) {
+        (AdjustMode::None, _) | (_, None) => return,
+        (AdjustMode::Forward, Some((_, latest))) => *latest,
+        (AdjustMode::Backward, _) => 1.0,
+    };
+    for bar in bars {
+        let ratio = factors.asof(bar.datetime.date()).copied().unwrap_or(1.0) / base;
+        bar.open_price *= ratio;
+        bar.high_price *= ratio;
+        bar.low_price *= ratio;
+        bar.close_price *= ratio;
+        bar.volume /= ratio;
+    }
+}
+
+fn unsupported(what: &str) -> VnrsError {
+    VnrsError::Database(format!("{} is not supported by the database", what))
+}
+
+///Rows written by one INSERT when saving bars, within the bind parameter limits of SQLite
+///and MySQL at 11 columns a row.
+#[cfg(any(feature = "sqlite", feature = "mysql"))]
+const BAR_CHUNK_SIZE: usize = 1000;
+
+///Schema change applied once on connect in order of version, recorded in the dbschemaversion
+///table. Tables are created only if missing, so databases made by vnpy are taken as they are.
+#[cfg(any(feature = "sqlite", feature = "mysql"))]
+struct Migration {
+    version: i64,
+    description: &'static str,
+    sql: &'static str,
+}
+
+///Value of a text column, e.g. an exchange or interval.
+#[cfg(any(feature = "sqlite", feature = "mysql"))]
+fn parse_column<T: std::str::FromStr>(name: &str, text: &str) -> VnrsResult<T> {
+    text.parse()
+        .map_err(|_| VnrsError::Database(format!("invalid {} {}", name, text)))
+}
+
+///One backtest run kept in the database. Parameters, statistics and trades are JSON so the
+///store doesn't depend on the engine that produced them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BacktestRecord {
+    ///Given by the database when saved.
+    pub id: i64,
+    pub created: NaiveDateTime,
+    pub strategy: String,
+    ///Hash of the strategy library, tells rebuilt strategies of the same name apart.
+    pub strategy_hash: String,
+    pub setting: String,
+    pub vt_symbol: String,
+    pub interval: Interval,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub parameters: Value,
+    pub statistics: Value,
+    pub trades: Option<Value>,
+}
+
+///One setting evaluated by an optimization, kept so that an interrupted study resumes where it
+///stopped and its results can be analyzed without running them again.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OptimizationTrial {
+    ///Name of the optimization, shared by its trials.
+    pub study_id: String,
+    pub created: NaiveDateTime,
+    pub setting: String,
+    ///Statistics as JSON, Null if the backtest failed.
+    pub statistics: Value,
+    ///Error message of a failed backtest.
+    pub error: Option<String>,
+    ///Seconds the backtest took.
+    pub duration: f64,
+}
+
+///Filter of load_backtest_records, None matches any. start and end bound the time the runs
+///were saved.
+#[derive(Debug, Clone, Default)]
+pub struct BacktestQuery {
+    pub strategy: Option<String>,
+    pub vt_symbol: Option<String>,
+    pub start: Option<NaiveDateTime>,
+    pub end: Option<NaiveDateTime>,
 }
 
-pub fn get_database() -> Arc<dyn BaseDatabase> {
+impl BacktestQuery {
+    pub fn matches(&self, record: &BacktestRecord) -> bool {
+        self.strategy.as_ref().is_none_or(|s| *s == record.strategy)
+            && self
+                .vt_symbol
+                .as_ref()
+                .is_none_or(|s| *s == record.vt_symbol)
+            && self.start.is_none_or(|start| record.created >= start)
+            && self.end.is_none_or(|end| record.created <= end)
+    }
+}
+
+///Database of the settings, connected on first use. Fails if the connection fails or
+///database.name is not a database built in.
+pub fn get_database() -> VnrsResult<Arc<dyn BaseDatabase + Send + Sync>> {
+    if let Some(database) = DBMAP.read().unwrap().custom.clone() {
+        return Ok(database);
+    }
+
     // Read database related global setting
     let database_name = get_settings()["database.name"].clone();
     match database_name.as_str() {
+        #[cfg(feature = "sqlite")]
         "sqlite" => {
-            if DBMAP.lock().unwrap().sqlite.is_some() {
-                return DBMAP.lock().unwrap().sqlite.as_ref().unwrap().clone();
-            } else {
-                DBMAP.lock().unwrap().sqlite = Some(Arc::new(
-                    SqliteDatabase::connect(&get_settings()["database.database"]).unwrap(),
-                ));
-                return DBMAP.lock().unwrap().sqlite.as_ref().unwrap().clone();
+            if let Some(database) = DBMAP.read().unwrap().sqlite.clone() {
+                return Ok(database);
+            }
+            let mut dbmap = DBMAP.write().unwrap();
+            // Another thread may have connected since the read
+            if dbmap.sqlite.is_none() {
+                dbmap.sqlite = Some(Arc::new(SqliteDatabase::connect(
+                    SqliteDatabase::options_from_settings()?,
+                )?));
             }
+            Ok(dbmap.sqlite.clone().unwrap())
         }
-        _ => {
-            unreachable!("unsupported Database")
+        #[cfg(feature = "mysql")]
+        "mysql" => {
+            if let Some(database) = DBMAP.read().unwrap().mysql.clone() {
+                return Ok(database);
+            }
+            let mut dbmap = DBMAP.write().unwrap();
+            if dbmap.mysql.is_none() {
+                dbmap.mysql = Some(Arc::new(MysqlDatabase::connect(
+                    MysqlDatabase::options_from_settings(),
+                )?));
+            }
+            Ok(dbmap.mysql.clone().unwrap())
         }
+        "memory" => Ok(get_memory_database()),
+        // Including backends whose feature isn't enabled
+        _ => Err(VnrsError::Setting(format!(
+            "unsupported database {}",
+            database_name
+        ))),
     }
 }
 
-pub struct SqliteDatabase {
-    pool: SqlitePool,
-    rt: tokio::runtime::Runtime,
+///The MemoryDatabase used when database.name is "memory", fill it before loading data.
+pub fn get_memory_database() -> Arc<MemoryDatabase> {
+    if let Some(database) = DBMAP.read().unwrap().memory.clone() {
+        return database;
+    }
+    DBMAP
+        .write()
+        .unwrap()
+        .memory
+        .get_or_insert_with(|| Arc::new(MemoryDatabase::new()))
+        .clone()
+}
+
+///Database held in memory, filled with save_bar_data and save_tick_data.
+#[derive(Default)]
+pub struct MemoryDatabase {
+    bars: RwLock<HashMap<(String, Exchange, Interval), Vec<BarData>>>,
+    ticks: RwLock<HashMap<(String, Exchange), Vec<TickData>>>,
+    records: RwLock<Vec<BacktestRecord>>,
+    trials: RwLock<Vec<OptimizationTrial>>,
+    adjust_factors: RwLock<HashMap<(String, Exchange), AdjustFactors>>,
 }
 
-impl SqliteDatabase {
-    pub fn connect(url: &str) -> Result<SqliteDatabase, Box<dyn std::error::Error>> {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()?;
-        let pool = rt.block_on(SqlitePool::connect("database.db"))?;
-        Ok(SqliteDatabase { pool, rt })
+impl MemoryDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Add bars, replacing stored bars of the same symbol, interval and datetime.
+    pub fn save_bar_data(&self, bars: Vec<BarData>) {
+        let mut stored = self.bars.write().unwrap();
+        for bar in bars {
+            let series = stored
+                .entry((bar.symbol.to_string(), bar.exchange, bar.interval))
+                .or_default();
+            match series.binary_search_by_key(&bar.datetime, |b| b.datetime) {
+                Ok(i) => series[i] = bar,
+                Err(i) => series.insert(i, bar),
+            }
+        }
+    }
+
+    ///Add ticks, replacing stored ticks of the same symbol and datetime.
+    pub fn save_tick_data(&self, ticks: Vec<TickData>) {
+        let mut stored = self.ticks.write().unwrap();
+        for tick in ticks {
+            let series = stored
+                .entry((tick.symbol.to_string(), tick.exchange))
+                .or_default();
+            match series.binary_search_by_key(&tick.datetime, |t| t.datetime) {
+                Ok(i) => series[i] = tick,
+                Err(i) => series.insert(i, tick),
+            }
+        }
     }
 }
 
-impl BaseDatabase for SqliteDatabase {
+impl BaseDatabase for MemoryDatabase {
     fn load_bar_data(
         &self,
         symbol: &str,
@@ -78,42 +398,333 @@ impl BaseDatabase for SqliteDatabase {
         interval: Interval,
         start: NaiveDateTime,
         end: NaiveDateTime,
-    ) -> Vec<BarData> {
-        let interval_str = match interval {
-            Interval::DAILY => "d",
-            Interval::MINUTE => "1m",
-            _ => {
-                unreachable!("invaild interval!");
+    ) -> VnrsResult<Vec<BarData>> {
+        let stored = self.bars.read().unwrap();
+        let Some(series) = stored.get(&(symbol.to_string(), exchange, interval)) else {
+            return Ok(Vec::new());
+        };
+        let from = series.partition_point(|bar| bar.datetime < start);
+        let to = series.partition_point(|bar| bar.datetime <= end);
+        Ok(series[from..to.max(from)].to_vec())
+    }
+
+    fn load_tick_data(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<Vec<TickData>> {
+        let stored = self.ticks.read().unwrap();
+        let Some(series) = stored.get(&(symbol.to_string(), exchange)) else {
+            return Ok(Vec::new());
+        };
+        let from = series.partition_point(|tick| tick.datetime < start);
+        let to = series.partition_point(|tick| tick.datetime <= end);
+        Ok(series[from..to.max(from)].to_vec())
+    }
+
+    fn save_bar_data_with_progress(
+        &self,
+        bars: Vec<BarData>,
+        progress: &dyn Fn(usize, usize),
+    ) -> VnrsResult<()> {
+        let total = bars.len();
+        MemoryDatabase::save_bar_data(self, bars);
+        progress(total, total);
+        Ok(())
+    }
+
+    fn save_backtest_record(&self, mut record: BacktestRecord) -> VnrsResult<i64> {
+        let mut records = self.records.write().unwrap();
+        record.id = records.len() as i64 + 1;
+        records.push(record);
+        Ok(records.len() as i64)
+    }
+
+    fn load_backtest_records(&self, query: &BacktestQuery) -> VnrsResult<Vec<BacktestRecord>> {
+        Ok(self
+            .records
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|record| query.matches(record))
+            .map(|record| BacktestRecord {
+                trades: None,
+                ..record.clone()
+            })
+            .collect())
+    }
+
+    fn load_backtest_record(&self, id: i64) -> VnrsResult<Option<BacktestRecord>> {
+        let records = self.records.read().unwrap();
+        Ok(records.iter().find(|record| record.id == id).cloned())
+    }
+
+    fn save_optimization_trial(&self, trial: OptimizationTrial) -> VnrsResult<()> {
+        self.trials.write().unwrap().push(trial);
+        Ok(())
+    }
+
+    fn load_optimization_trials(&self, study_id: &str) -> VnrsResult<Vec<OptimizationTrial>> {
+        let trials = self.trials.read().unwrap();
+        Ok(trials
+            .iter()
+            .filter(|trial| trial.study_id == study_id)
+            .cloned()
+            .collect())
+    }
+
+    fn save_adjust_factors(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        factors: &AdjustFactors,
+    ) -> VnrsResult<()> {
+        let mut stored = self.adjust_factors.write().unwrap();
+        let series = stored.entry((symbol.to_string(), exchange)).or_default();
+        for (date, factor) in factors.iter() {
+            series.insert(date, *factor);
+        }
+        Ok(())
+    }
+
+    fn load_adjust_factors(&self, symbol: &str, exchange: Exchange) -> VnrsResult<AdjustFactors> {
+        let stored = self.adjust_factors.read().unwrap();
+        Ok(stored
+            .get(&(symbol.to_string(), exchange))
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::synthetic::SyntheticBars;
+
+    #[test]
+    fn memory_database_range() {
+        let bars = SyntheticBars::default().generate();
+        let database = MemoryDatabase::new();
+        database.save_bar_data(bars[10..].to_vec());
+        database.save_bar_data(bars[..20].to_vec());
+
+        let loaded = database
+            .load_bar_data(
+                "TEST",
+                Exchange::LOCAL,
+                Interval::MINUTE,
+                bars[5].datetime,
+                bars[14].datetime,
+            )
+            .unwrap();
+        assert_eq!(loaded.len(), 10);
+        assert_eq!(loaded[0].datetime, bars[5].datetime);
+        assert_eq!(loaded[9].close_price, bars[14].close_price);
+
+        let all = database
+            .load_bar_data(
+                "TEST",
+                Exchange::LOCAL,
+                Interval::MINUTE,
+                bars[0].datetime,
+                bars[999].datetime,
+            )
+            .unwrap();
+        assert_eq!(all.len(), 1000);
+        assert!(database
+            .load_bar_data(
+                "TEST",
+                Exchange::LOCAL,
+                Interval::DAILY,
+                bars[0].datetime,
+                bars[999].datetime
+            )
+            .unwrap()
+            .is_empty());
+    }
+
+    ///Shared by the tests of each database.
+    pub(super) fn check_backtest_records(database: &dyn BaseDatabase) {
+        let datetime = |day: u32| {
+            chrono::NaiveDate::from_ymd_opt(2024, 1, day)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        };
+        let record = |day: u32, strategy: &str, vt_symbol: &str| BacktestRecord {
+            created: datetime(day),
+            strategy: strategy.to_string(),
+            strategy_hash: "0123456789abcdef".to_string(),
+            setting: "fast_window:10".to_string(),
+            vt_symbol: vt_symbol.to_string(),
+            interval: Interval::MINUTE,
+            start: datetime(1),
+            end: datetime(31),
+            parameters: serde_json::json!({"rate": 2.5e-5}),
+            statistics: serde_json::json!({"sharpe_ratio": 1.5}),
+            trades: Some(serde_json::json!([{"tradeid": "1"}])),
+            ..Default::default()
+        };
+        let first = database
+            .save_backtest_record(record(2, "turtle", "IF888.CFFEX"))
+            .unwrap();
+        database
+            .save_backtest_record(record(3, "turtle", "rb888.SHFE"))
+            .unwrap();
+        database
+            .save_backtest_record(record(4, "boll", "IF888.CFFEX"))
+            .unwrap();
+
+        let runs = database
+            .load_backtest_records(&BacktestQuery {
+                strategy: Some("turtle".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].vt_symbol, "rb888.SHFE");
+        assert!(runs.iter().all(|run| run.trades.is_none()));
+
+        let runs = database
+            .load_backtest_records(&BacktestQuery {
+                vt_symbol: Some("IF888.CFFEX".to_string()),
+                start: Some(datetime(3)),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].strategy, "boll");
+
+        let loaded = database.load_backtest_record(first).unwrap().unwrap();
+        assert_eq!(
+            loaded,
+            BacktestRecord {
+                id: first,
+                ..record(2, "turtle", "IF888.CFFEX")
             }
+        );
+        assert!(database
+            .load_backtest_record(first + 100)
+            .unwrap()
+            .is_none());
+    }
+
+    ///Shared by the tests of each database.
+    pub(super) fn check_optimization_trials(database: &dyn BaseDatabase) {
+        let trial = |study_id: &str, setting: &str, error: Option<&str>| OptimizationTrial {
+            study_id: study_id.to_string(),
+            created: chrono::NaiveDate::from_ymd_opt(2024, 1, 2)
+                .unwrap()
+                .and_hms_opt(9, 30, 0)
+                .unwrap(),
+            setting: setting.to_string(),
+            statistics: match error {
+                Some(_) => Value::Null,
+                None => serde_json::json!({"sharpe_ratio": 1.5}),
+            },
+            error: error.map(|error| error.to_string()),
+            duration: 2.5,
+        };
+        let trials = [
+            trial("ma", "fast_window:10", None),
+            trial("boll", "boll_window:20", None),
+            trial("ma", "fast_window:5", Some("no bars")),
+        ];
+        for trial in trials.iter().cloned() {
+            database.save_optimization_trial(trial).unwrap();
+        }
+
+        assert_eq!(
+            database.load_optimization_trials("ma").unwrap(),
+            vec![trials[0].clone(), trials[2].clone()]
+        );
+        assert!(database.load_optimization_trials("rsi").unwrap().is_empty());
+    }
+
+    ///Shared by the tests of each database.
+    pub(super) fn check_adjust_factors(database: &dyn BaseDatabase) {
+        let bars = SyntheticBars {
+            symbol: "600000".to_string(),
+            exchange: Exchange::SSE,
+            interval: Interval::DAILY,
+            count: 5,
+            ..Default::default()
+        }
+        .generate();
+        database.save_bar_data(bars.clone()).unwrap();
+        // A 2 for 1 split taking effect on the fourth day
+        let factors: AdjustFactors = [
+            (bars[0].datetime.date(), 1.0),
+            (bars[3].datetime.date(), 2.0),
+        ]
+        .into_iter()
+        .collect();
+        database
+            .save_adjust_factors("600000", Exchange::SSE, &factors)
+            .unwrap();
+        assert_eq!(
+            database
+                .load_adjust_factors("600000", Exchange::SSE)
+                .unwrap(),
+            factors
+        );
+        assert!(database
+            .load_adjust_factors("600001", Exchange::SSE)
+            .unwrap()
+            .is_empty());
+
+        let load = |adjust: AdjustMode| {
+            database
+                .load_adjusted_bar_data(
+                    "600000",
+                    Exchange::SSE,
+                    Interval::DAILY,
+                    bars[0].datetime,
+                    bars[4].datetime,
+                    adjust,
+                )
+                .unwrap()
         };
+        let closes = |adjust: AdjustMode| -> Vec<f64> {
+            load(adjust).iter().map(|bar| bar.close_price).collect()
+        };
+        let raw: Vec<f64> = bars.iter().map(|bar| bar.close_price).collect();
+        assert_eq!(closes(AdjustMode::None), raw);
+        let forward = closes(AdjustMode::Forward);
+        let backward = closes(AdjustMode::Backward);
+        for i in 0..5 {
+            let split = if i < 3 { 1.0 } else { 2.0 };
+            assert!((forward[i] - raw[i] * split / 2.0).abs() < 1e-9);
+            assert!((backward[i] - raw[i] * split).abs() < 1e-9);
+        }
 
-        let s = self.rt.block_on(
-            sqlx::query("SELECT symbol,exchange,datetime,interval,volume,turnover,open_interest,open_price,high_price,low_price,close_price FROM dbbardata WHERE symbol=? and exchange=? and interval=? and datetime>=? and datetime<=? ORDER BY datetime")
-                    .bind(symbol).bind(exchange.to_string()).bind(interval_str).bind(start).bind(end)
-                    .fetch_all(&self.pool)).unwrap();
-        let mut bars = Vec::new();
-        for db_bar in s.iter() {
-            bars.push(BarData {
-                symbol: db_bar.get::<String, usize>(0),
-                exchange: Exchange::from_str(&db_bar.get::<String, usize>(1)).unwrap(),
-                datetime: db_bar.get::<NaiveDateTime, usize>(2),
-                interval: match db_bar.get::<&str, usize>(3) {
-                    "d" => Interval::DAILY,
-                    "1m" => Interval::MINUTE,
-                    _ => {
-                        unreachable!("invalid interval")
-                    }
-                },
-                volume: db_bar.get::<f64, usize>(4),
-                turnover: db_bar.get::<f64, usize>(5),
-                open_interest: db_bar.get::<f64, usize>(6),
-                open_price: db_bar.get::<f64, usize>(7),
-                high_price: db_bar.get::<f64, usize>(8),
-                low_price: db_bar.get::<f64, usize>(9),
-                close_price: db_bar.get::<f64, usize>(10),
-                gateway_name: "DB",
-            });
+        // Volumes move the other way, the turnover is as traded
+        for (bar, adjusted) in bars.iter().zip(load(AdjustMode::Forward)) {
+            let split = if bar.datetime < bars[3].datetime {
+                1.0
+            } else {
+                2.0
+            };
+            assert!((adjusted.volume - bar.volume * 2.0 / split).abs() < 1e-9);
+            assert_eq!(adjusted.turnover, bar.turnover);
         }
-        bars
+    }
+
+    #[test]
+    fn backtest_records() {
+        check_backtest_records(&MemoryDatabase::new());
+    }
+
+    #[test]
+    fn optimization_trials() {
+        check_optimization_trials(&MemoryDatabase::new());
+    }
+
+    #[test]
+    fn adjust_factors() {
+        check_adjust_factors(&MemoryDatabase::new());
     }
 }
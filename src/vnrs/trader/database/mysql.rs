@@ -0,0 +1,371 @@
+/*!MySQL database in the tables of vnpy's mysql database, enabled by the `mysql` feature. */
+use chrono::{Local, NaiveDateTime};
+use serde_json::Value;
+use sqlx::mysql::{MySqlConnectOptions, MySqlPool};
+use sqlx::{QueryBuilder, Row};
+
+use super::{
+    parse_column, AdjustFactors, BacktestQuery, BacktestRecord, BaseDatabase, Migration,
+    OptimizationTrial, BAR_CHUNK_SIZE,
+};
+use crate::error::VnrsResult;
+use crate::vnrs::trader::constant::{Exchange, Interval};
+use crate::vnrs::trader::object::{BarData, TickData};
+
+pub struct MysqlDatabase {
+    pool: MySqlPool,
+    rt: tokio::runtime::Runtime,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "bar and tick tables",
+        sql: "CREATE TABLE IF NOT EXISTS dbbardata (id INTEGER PRIMARY KEY AUTO_INCREMENT, symbol VARCHAR(255), exchange VARCHAR(255), datetime DATETIME(3), `interval` VARCHAR(255), volume DOUBLE, turnover DOUBLE, open_interest DOUBLE, open_price DOUBLE, high_price DOUBLE, low_price DOUBLE, close_price DOUBLE, UNIQUE KEY dbbardata_symbol_exchange_interval_datetime (symbol, exchange, `interval`, datetime));
+CREATE TABLE IF NOT EXISTS dbtickdata (id INTEGER PRIMARY KEY AUTO_INCREMENT, symbol VARCHAR(255), exchange VARCHAR(255), datetime DATETIME(3), name VARCHAR(255), volume DOUBLE, turnover DOUBLE, open_interest DOUBLE, last_price DOUBLE, last_volume DOUBLE, limit_up DOUBLE, limit_down DOUBLE, open_price DOUBLE, high_price DOUBLE, low_price DOUBLE, pre_close DOUBLE, bid_price_1 DOUBLE, bid_price_2 DOUBLE, bid_price_3 DOUBLE, bid_price_4 DOUBLE, bid_price_5 DOUBLE, ask_price_1 DOUBLE, ask_price_2 DOUBLE, ask_price_3 DOUBLE, ask_price_4 DOUBLE, ask_price_5 DOUBLE, bid_volume_1 DOUBLE, bid_volume_2 DOUBLE, bid_volume_3 DOUBLE, bid_volume_4 DOUBLE, bid_volume_5 DOUBLE, ask_volume_1 DOUBLE, ask_volume_2 DOUBLE, ask_volume_3 DOUBLE, ask_volume_4 DOUBLE, ask_volume_5 DOUBLE, localtime DATETIME(3) NULL, UNIQUE KEY dbtickdata_symbol_exchange_datetime (symbol, exchange, datetime));",
+    },
+    Migration {
+        version: 2,
+        description: "backtest record table",
+        sql: "CREATE TABLE IF NOT EXISTS dbbacktestrecord (id BIGINT PRIMARY KEY AUTO_INCREMENT, created DATETIME, strategy VARCHAR(255), strategy_hash VARCHAR(255), setting TEXT, vt_symbol VARCHAR(255), `interval` VARCHAR(255), backtest_start DATETIME, backtest_end DATETIME, parameters TEXT, statistics TEXT, trades LONGTEXT);",
+    },
+    Migration {
+        version: 3,
+        description: "adjustment factor table",
+        sql: "CREATE TABLE IF NOT EXISTS dbadjustfactor (id INTEGER PRIMARY KEY AUTO_INCREMENT, symbol VARCHAR(255), exchange VARCHAR(255), date DATE, factor DOUBLE, UNIQUE KEY dbadjustfactor_symbol_exchange_date (symbol, exchange, date));",
+    },
+    Migration {
+        version: 4,
+        description: "optimization trial table",
+        sql: "CREATE TABLE IF NOT EXISTS dboptimizationtrial (id BIGINT PRIMARY KEY AUTO_INCREMENT, study_id VARCHAR(255), created DATETIME, setting TEXT, statistics TEXT, error TEXT NULL, duration DOUBLE, KEY dboptimizationtrial_study_id (study_id));",
+    },
+];
+
+impl MysqlDatabase {
+    ///Connect and bring the schema of the database up to date.
+    pub fn connect(options: MySqlConnectOptions) -> VnrsResult<MysqlDatabase> {
+        // A multi-threaded runtime lets threads sharing the database block_on queries
+        // concurrently, each on its own connection of the pool
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()?;
+        let pool = rt.block_on(MySqlPool::connect_with(options))?;
+        let database = MysqlDatabase { pool, rt };
+        database.rt.block_on(database.migrate())?;
+        Ok(database)
+    }
+
+    ///Connection options from the database.* settings.
+    pub fn options_from_settings() -> MySqlConnectOptions {
+        let settings = super::get_settings();
+        MySqlConnectOptions::new()
+            .host(&settings["database.host"])
+            .port(settings["database.port"].parse().unwrap_or(3306))
+            .username(&settings["database.user"])
+            .password(&settings["database.password"])
+            .database(&settings["database.database"])
+    }
+
+    ///Apply the migrations newer than the version of the database.
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("CREATE TABLE IF NOT EXISTS dbschemaversion (version BIGINT PRIMARY KEY, description VARCHAR(255), applied DATETIME)")
+            .execute(&self.pool)
+            .await?;
+        let version: i64 =
+            sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM dbschemaversion")
+                .fetch_one(&self.pool)
+                .await?;
+        for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+            let mut tx = self.pool.begin().await?;
+            sqlx::raw_sql(migration.sql).execute(&mut *tx).await?;
+            sqlx::query("INSERT INTO dbschemaversion (version,description,applied) VALUES (?,?,?)")
+                .bind(migration.version)
+                .bind(migration.description)
+                .bind(Local::now().naive_local())
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    fn load_records(
+        &self,
+        sql: &str,
+        binds: Vec<Option<String>>,
+    ) -> VnrsResult<Vec<BacktestRecord>> {
+        let rows = self.rt.block_on(async {
+            let mut query = sqlx::query(sql);
+            for bind in binds {
+                query = query.bind(bind);
+            }
+            query.fetch_all(&self.pool).await
+        })?;
+        let json = |text: String| serde_json::from_str(&text).unwrap_or(Value::Null);
+        Ok(rows
+            .iter()
+            .map(|row| BacktestRecord {
+                id: row.get("id"),
+                created: row.get("created"),
+                strategy: row.get("strategy"),
+                strategy_hash: row.get("strategy_hash"),
+                setting: row.get("setting"),
+                vt_symbol: row.get("vt_symbol"),
+                interval: row
+                    .get::<&str, &str>("interval")
+                    .parse()
+                    .unwrap_or_default(),
+                start: row.get("backtest_start"),
+                end: row.get("backtest_end"),
+                parameters: json(row.get("parameters")),
+                statistics: json(row.get("statistics")),
+                trades: row
+                    .try_get::<Option<String>, &str>("trades")
+                    .ok()
+                    .flatten()
+                    .map(json),
+            })
+            .collect())
+    }
+}
+
+impl BaseDatabase for MysqlDatabase {
+    fn load_bar_data(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<Vec<BarData>> {
+        let s = self.rt.block_on(
+            sqlx::query("SELECT symbol,exchange,datetime,`interval`,volume,turnover,open_interest,open_price,high_price,low_price,close_price FROM dbbardata WHERE symbol=? and exchange=? and `interval`=? and datetime>=? and datetime<=? ORDER BY datetime")
+                    .bind(symbol).bind(exchange.to_string()).bind(interval.to_string()).bind(start).bind(end)
+                    .fetch_all(&self.pool))?;
+        let mut bars = Vec::new();
+        for db_bar in s.iter() {
+            bars.push(
+                BarData {
+                    symbol: db_bar.get::<String, usize>(0).into(),
+                    exchange: parse_column("exchange", &db_bar.get::<String, usize>(1))?,
+                    datetime: db_bar.get::<NaiveDateTime, usize>(2),
+                    interval: parse_column("interval", db_bar.get::<&str, usize>(3))?,
+                    volume: db_bar.get::<f64, usize>(4),
+                    turnover: db_bar.get::<f64, usize>(5),
+                    open_interest: db_bar.get::<f64, usize>(6),
+                    open_price: db_bar.get::<f64, usize>(7),
+                    high_price: db_bar.get::<f64, usize>(8),
+                    low_price: db_bar.get::<f64, usize>(9),
+                    close_price: db_bar.get::<f64, usize>(10),
+                    gateway_name: "DB".into(),
+                }
+                .validated()?,
+            );
+        }
+        Ok(bars)
+    }
+
+    fn load_tick_data(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<Vec<TickData>> {
+        let s = self.rt.block_on(
+            sqlx::query("SELECT * FROM dbtickdata WHERE symbol=? and exchange=? and datetime>=? and datetime<=? ORDER BY datetime")
+                    .bind(symbol).bind(exchange.to_string()).bind(start).bind(end)
+                    .fetch_all(&self.pool))?;
+        let mut ticks = Vec::new();
+        for db_tick in s.iter() {
+            let float = |name: &str| db_tick.get::<f64, &str>(name);
+            ticks.push(
+                TickData {
+                    symbol: db_tick.get::<String, &str>("symbol").into(),
+                    exchange: parse_column("exchange", &db_tick.get::<String, &str>("exchange"))?,
+                    datetime: db_tick.get::<NaiveDateTime, &str>("datetime"),
+                    name: db_tick.get::<String, &str>("name"),
+                    volume: float("volume"),
+                    turnover: float("turnover"),
+                    open_interest: float("open_interest"),
+                    last_price: float("last_price"),
+                    last_volume: float("last_volume"),
+                    limit_up: float("limit_up"),
+                    limit_down: float("limit_down"),
+                    open_price: float("open_price"),
+                    high_price: float("high_price"),
+                    low_price: float("low_price"),
+                    pre_close: float("pre_close"),
+                    bid_price_1: float("bid_price_1"),
+                    bid_price_2: float("bid_price_2"),
+                    bid_price_3: float("bid_price_3"),
+                    bid_price_4: float("bid_price_4"),
+                    bid_price_5: float("bid_price_5"),
+                    ask_price_1: float("ask_price_1"),
+                    ask_price_2: float("ask_price_2"),
+                    ask_price_3: float("ask_price_3"),
+                    ask_price_4: float("ask_price_4"),
+                    ask_price_5: float("ask_price_5"),
+                    bid_volume_1: float("bid_volume_1"),
+                    bid_volume_2: float("bid_volume_2"),
+                    bid_volume_3: float("bid_volume_3"),
+                    bid_volume_4: float("bid_volume_4"),
+                    bid_volume_5: float("bid_volume_5"),
+                    ask_volume_1: float("ask_volume_1"),
+                    ask_volume_2: float("ask_volume_2"),
+                    ask_volume_3: float("ask_volume_3"),
+                    ask_volume_4: float("ask_volume_4"),
+                    ask_volume_5: float("ask_volume_5"),
+                    localtime: db_tick
+                        .get::<Option<NaiveDateTime>, &str>("localtime")
+                        .unwrap_or_default(),
+                    gateway_name: "DB".into(),
+                }
+                .validated()?,
+            );
+        }
+        Ok(ticks)
+    }
+
+    fn save_bar_data_with_progress(
+        &self,
+        bars: Vec<BarData>,
+        progress: &dyn Fn(usize, usize),
+    ) -> VnrsResult<()> {
+        let total = bars.len();
+        let result: Result<(), sqlx::Error> = self.rt.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            let mut saved = 0;
+            for chunk in bars.chunks(BAR_CHUNK_SIZE) {
+                // Replaces the bar at the same time through vnpy's unique index
+                let mut query = QueryBuilder::new("REPLACE INTO dbbardata (symbol,exchange,datetime,`interval`,volume,turnover,open_interest,open_price,high_price,low_price,close_price) ");
+                query.push_values(chunk, |mut row, bar| {
+                    row.push_bind(bar.symbol.as_str())
+                        .push_bind(bar.exchange.to_string())
+                        .push_bind(bar.datetime)
+                        .push_bind(bar.interval.to_string())
+                        .push_bind(bar.volume)
+                        .push_bind(bar.turnover)
+                        .push_bind(bar.open_interest)
+                        .push_bind(bar.open_price)
+                        .push_bind(bar.high_price)
+                        .push_bind(bar.low_price)
+                        .push_bind(bar.close_price);
+                });
+                query.build().execute(&mut *tx).await?;
+                saved += chunk.len();
+                progress(saved, total);
+            }
+            tx.commit().await
+        });
+        Ok(result?)
+    }
+
+    fn save_backtest_record(&self, record: BacktestRecord) -> VnrsResult<i64> {
+        let result: Result<i64, sqlx::Error> = self.rt.block_on(async {
+            let done = sqlx::query("INSERT INTO dbbacktestrecord (created,strategy,strategy_hash,setting,vt_symbol,`interval`,backtest_start,backtest_end,parameters,statistics,trades) VALUES (?,?,?,?,?,?,?,?,?,?,?)")
+                .bind(record.created).bind(&record.strategy).bind(&record.strategy_hash).bind(&record.setting)
+                .bind(&record.vt_symbol).bind(record.interval.to_string()).bind(record.start).bind(record.end)
+                .bind(record.parameters.to_string()).bind(record.statistics.to_string())
+                .bind(record.trades.as_ref().map(|trades| trades.to_string()))
+                .execute(&self.pool).await?;
+            Ok(done.last_insert_id() as i64)
+        });
+        Ok(result?)
+    }
+
+    fn load_backtest_records(&self, query: &BacktestQuery) -> VnrsResult<Vec<BacktestRecord>> {
+        // MySQL has no numbered parameters, each filter value is bound twice
+        let datetime = |dt: Option<NaiveDateTime>| dt.map(|dt| dt.to_string());
+        let binds = [
+            query.strategy.clone(),
+            query.vt_symbol.clone(),
+            datetime(query.start),
+            datetime(query.end),
+        ];
+        self.load_records(
+            "SELECT id,created,strategy,strategy_hash,setting,vt_symbol,`interval`,backtest_start,backtest_end,parameters,statistics FROM dbbacktestrecord WHERE (? IS NULL OR strategy=?) AND (? IS NULL OR vt_symbol=?) AND (? IS NULL OR created>=?) AND (? IS NULL OR created<=?) ORDER BY id DESC",
+            binds
+                .into_iter()
+                .flat_map(|bind| [bind.clone(), bind])
+                .collect(),
+        )
+    }
+
+    fn load_backtest_record(&self, id: i64) -> VnrsResult<Option<BacktestRecord>> {
+        self.load_records(
+            "SELECT * FROM dbbacktestrecord WHERE id=?",
+            vec![Some(id.to_string())],
+        )
+        .map(|mut records| records.pop())
+    }
+
+    fn save_optimization_trial(&self, trial: OptimizationTrial) -> VnrsResult<()> {
+        let result = self.rt.block_on(
+            sqlx::query("INSERT INTO dboptimizationtrial (study_id,created,setting,statistics,error,duration) VALUES (?,?,?,?,?,?)")
+                .bind(&trial.study_id).bind(trial.created).bind(&trial.setting)
+                .bind(trial.statistics.to_string()).bind(&trial.error).bind(trial.duration)
+                .execute(&self.pool),
+        );
+        result?;
+        Ok(())
+    }
+
+    fn load_optimization_trials(&self, study_id: &str) -> VnrsResult<Vec<OptimizationTrial>> {
+        let rows = self.rt.block_on(
+            sqlx::query("SELECT study_id,created,setting,statistics,error,duration FROM dboptimizationtrial WHERE study_id=? ORDER BY id")
+                .bind(study_id)
+                .fetch_all(&self.pool),
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| OptimizationTrial {
+                study_id: row.get("study_id"),
+                created: row.get("created"),
+                setting: row.get("setting"),
+                statistics: serde_json::from_str(row.get("statistics")).unwrap_or(Value::Null),
+                error: row.get("error"),
+                duration: row.get("duration"),
+            })
+            .collect())
+    }
+
+    fn save_adjust_factors(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        factors: &AdjustFactors,
+    ) -> VnrsResult<()> {
+        let factors: Vec<_> = factors.iter().collect();
+        let result: Result<(), sqlx::Error> = self.rt.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            for chunk in factors.chunks(BAR_CHUNK_SIZE) {
+                let mut query =
+                    QueryBuilder::new("REPLACE INTO dbadjustfactor (symbol,exchange,date,factor) ");
+                query.push_values(chunk, |mut row, (date, factor)| {
+                    row.push_bind(symbol)
+                        .push_bind(exchange.to_string())
+                        .push_bind(*date)
+                        .push_bind(**factor);
+                });
+                query.build().execute(&mut *tx).await?;
+            }
+            tx.commit().await
+        });
+        Ok(result?)
+    }
+
+    fn load_adjust_factors(&self, symbol: &str, exchange: Exchange) -> VnrsResult<AdjustFactors> {
+        let rows = self.rt.block_on(
+            sqlx::query("SELECT date,factor FROM dbadjustfactor WHERE symbol=? AND exchange=?")
+                .bind(symbol)
+                .bind(exchange.to_string())
+                .fetch_all(&self.pool),
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("date"), row.get("factor")))
+            .collect())
+    }
+}
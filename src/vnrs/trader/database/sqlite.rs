@@ -0,0 +1,550 @@
+/*!SQLite database in the tables of vnpy's sqlite database, enabled by the `sqlite` feature. */
+use chrono::{Local, NaiveDateTime};
+use serde_json::Value;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqliteSynchronous};
+use sqlx::{QueryBuilder, Row};
+use std::collections::HashMap;
+
+use super::{
+    parse_column, AdjustFactors, BacktestQuery, BacktestRecord, BaseDatabase, Migration,
+    OptimizationTrial, BAR_CHUNK_SIZE,
+};
+use crate::error::{VnrsError, VnrsResult};
+use crate::vnrs::trader::constant::{Exchange, Interval};
+use crate::vnrs::trader::object::{BarData, TickData};
+
+pub struct SqliteDatabase {
+    pool: SqlitePool,
+    rt: tokio::runtime::Runtime,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "bar and tick tables",
+        sql: "CREATE TABLE IF NOT EXISTS dbbardata (id INTEGER PRIMARY KEY AUTOINCREMENT, symbol VARCHAR(255), exchange VARCHAR(255), datetime DATETIME, interval VARCHAR(255), volume REAL, turnover REAL, open_interest REAL, open_price REAL, high_price REAL, low_price REAL, close_price REAL);
+CREATE UNIQUE INDEX IF NOT EXISTS dbbardata_symbol_exchange_interval_datetime ON dbbardata (symbol, exchange, interval, datetime);
+CREATE TABLE IF NOT EXISTS dbtickdata (id INTEGER PRIMARY KEY AUTOINCREMENT, symbol VARCHAR(255), exchange VARCHAR(255), datetime DATETIME, name VARCHAR(255), volume REAL, turnover REAL, open_interest REAL, last_price REAL, last_volume REAL, limit_up REAL, limit_down REAL, open_price REAL, high_price REAL, low_price REAL, pre_close REAL, bid_price_1 REAL, bid_price_2 REAL, bid_price_3 REAL, bid_price_4 REAL, bid_price_5 REAL, ask_price_1 REAL, ask_price_2 REAL, ask_price_3 REAL, ask_price_4 REAL, ask_price_5 REAL, bid_volume_1 REAL, bid_volume_2 REAL, bid_volume_3 REAL, bid_volume_4 REAL, bid_volume_5 REAL, ask_volume_1 REAL, ask_volume_2 REAL, ask_volume_3 REAL, ask_volume_4 REAL, ask_volume_5 REAL, localtime DATETIME NULL);
+CREATE UNIQUE INDEX IF NOT EXISTS dbtickdata_symbol_exchange_datetime ON dbtickdata (symbol, exchange, datetime);",
+    },
+    Migration {
+        version: 2,
+        description: "backtest record table",
+        sql: "CREATE TABLE IF NOT EXISTS dbbacktestrecord (id INTEGER PRIMARY KEY AUTOINCREMENT, created DATETIME, strategy TEXT, strategy_hash TEXT, setting TEXT, vt_symbol TEXT, interval TEXT, backtest_start DATETIME, backtest_end DATETIME, parameters TEXT, statistics TEXT, trades TEXT);",
+    },
+    Migration {
+        version: 3,
+        description: "adjustment factor table",
+        sql: "CREATE TABLE IF NOT EXISTS dbadjustfactor (id INTEGER PRIMARY KEY AUTOINCREMENT, symbol VARCHAR(255), exchange VARCHAR(255), date DATE, factor REAL);
+CREATE UNIQUE INDEX IF NOT EXISTS dbadjustfactor_symbol_exchange_date ON dbadjustfactor (symbol, exchange, date);",
+    },
+    Migration {
+        version: 4,
+        description: "optimization trial table",
+        sql: "CREATE TABLE IF NOT EXISTS dboptimizationtrial (id INTEGER PRIMARY KEY AUTOINCREMENT, study_id TEXT, created DATETIME, setting TEXT, statistics TEXT, error TEXT NULL, duration REAL);
+CREATE INDEX IF NOT EXISTS dboptimizationtrial_study_id ON dboptimizationtrial (study_id);",
+    },
+];
+
+impl SqliteDatabase {
+    ///Open the database file, created if missing, and bring its schema up to date.
+    pub fn connect(options: SqliteConnectOptions) -> VnrsResult<SqliteDatabase> {
+        // A multi-threaded runtime lets threads sharing the database block_on queries
+        // concurrently, each on its own connection of the pool
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()?;
+        let pool = rt.block_on(SqlitePool::connect_with(options))?;
+        Self::from_pool(pool, rt)
+    }
+
+    ///Connection options from the database.* settings, with the journal_mode, synchronous,
+    ///cache_size and mmap_size pragmas.
+    pub fn options_from_settings() -> VnrsResult<SqliteConnectOptions> {
+        options_from(&super::get_settings())
+    }
+
+    fn from_pool(pool: SqlitePool, rt: tokio::runtime::Runtime) -> VnrsResult<SqliteDatabase> {
+        let database = SqliteDatabase { pool, rt };
+        database.rt.block_on(database.migrate())?;
+        Ok(database)
+    }
+
+    ///Apply the migrations newer than the version of the database.
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("CREATE TABLE IF NOT EXISTS dbschemaversion (version INTEGER PRIMARY KEY, description TEXT, applied DATETIME)")
+            .execute(&self.pool)
+            .await?;
+        let version: i64 =
+            sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM dbschemaversion")
+                .fetch_one(&self.pool)
+                .await?;
+        for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+            let mut tx = self.pool.begin().await?;
+            sqlx::raw_sql(migration.sql).execute(&mut *tx).await?;
+            sqlx::query("INSERT INTO dbschemaversion (version,description,applied) VALUES (?,?,?)")
+                .bind(migration.version)
+                .bind(migration.description)
+                .bind(Local::now().naive_local())
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    fn load_records(
+        &self,
+        sql: &str,
+        binds: [Option<String>; 4],
+    ) -> VnrsResult<Vec<BacktestRecord>> {
+        let rows = self.rt.block_on(async {
+            let mut query = sqlx::query(sql);
+            for bind in binds {
+                query = query.bind(bind);
+            }
+            query.fetch_all(&self.pool).await
+        })?;
+        let json = |text: String| serde_json::from_str(&text).unwrap_or(Value::Null);
+        Ok(rows
+            .iter()
+            .map(|row| BacktestRecord {
+                id: row.get("id"),
+                created: row.get("created"),
+                strategy: row.get("strategy"),
+                strategy_hash: row.get("strategy_hash"),
+                setting: row.get("setting"),
+                vt_symbol: row.get("vt_symbol"),
+                interval: row
+                    .get::<&str, &str>("interval")
+                    .parse()
+                    .unwrap_or_default(),
+                start: row.get("backtest_start"),
+                end: row.get("backtest_end"),
+                parameters: json(row.get("parameters")),
+                statistics: json(row.get("statistics")),
+                trades: row
+                    .try_get::<Option<String>, &str>("trades")
+                    .ok()
+                    .flatten()
+                    .map(json),
+            })
+            .collect())
+    }
+}
+
+fn options_from(settings: &HashMap<&'static str, String>) -> VnrsResult<SqliteConnectOptions> {
+    let invalid = |key: &str| VnrsError::Setting(format!("invalid {} {}", key, settings[key]));
+    let journal_mode: SqliteJournalMode = settings["database.journal_mode"]
+        .parse()
+        .map_err(|_| invalid("database.journal_mode"))?;
+    let synchronous: SqliteSynchronous = settings["database.synchronous"]
+        .parse()
+        .map_err(|_| invalid("database.synchronous"))?;
+    // Parsed as numbers as pragma values are put into the SQL as they are
+    let cache_size: i64 = settings["database.cache_size"]
+        .parse()
+        .map_err(|_| invalid("database.cache_size"))?;
+    let mmap_size: i64 = settings["database.mmap_size"]
+        .parse()
+        .map_err(|_| invalid("database.mmap_size"))?;
+
+    Ok(SqliteConnectOptions::new()
+        .filename(&settings["database.database"])
+        .create_if_missing(true)
+        .journal_mode(journal_mode)
+        .synchronous(synchronous)
+        .pragma("cache_size", cache_size.to_string())
+        .pragma("mmap_size", mmap_size.to_string()))
+}
+
+impl BaseDatabase for SqliteDatabase {
+    fn load_bar_data(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<Vec<BarData>> {
+        let s = self.rt.block_on(
+            sqlx::query("SELECT symbol,exchange,datetime,interval,volume,turnover,open_interest,open_price,high_price,low_price,close_price FROM dbbardata WHERE symbol=? and exchange=? and interval=? and datetime>=? and datetime<=? ORDER BY datetime")
+                    .bind(symbol).bind(exchange.to_string()).bind(interval.to_string()).bind(start).bind(end)
+                    .fetch_all(&self.pool))?;
+        let mut bars = Vec::new();
+        for db_bar in s.iter() {
+            bars.push(
+                BarData {
+                    symbol: db_bar.get::<String, usize>(0).into(),
+                    exchange: parse_column("exchange", &db_bar.get::<String, usize>(1))?,
+                    datetime: db_bar.get::<NaiveDateTime, usize>(2),
+                    interval: parse_column("interval", db_bar.get::<&str, usize>(3))?,
+                    volume: db_bar.get::<f64, usize>(4),
+                    turnover: db_bar.get::<f64, usize>(5),
+                    open_interest: db_bar.get::<f64, usize>(6),
+                    open_price: db_bar.get::<f64, usize>(7),
+                    high_price: db_bar.get::<f64, usize>(8),
+                    low_price: db_bar.get::<f64, usize>(9),
+                    close_price: db_bar.get::<f64, usize>(10),
+                    gateway_name: "DB".into(),
+                }
+                .validated()?,
+            );
+        }
+        Ok(bars)
+    }
+    fn load_tick_data(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<Vec<TickData>> {
+        let s = self.rt.block_on(
+            sqlx::query("SELECT * FROM dbtickdata WHERE symbol=? and exchange=? and datetime>=? and datetime<=? ORDER BY datetime")
+                    .bind(symbol).bind(exchange.to_string()).bind(start).bind(end)
+                    .fetch_all(&self.pool))?;
+        let mut ticks = Vec::new();
+        for db_tick in s.iter() {
+            ticks.push(
+                TickData {
+                    symbol: db_tick.get::<String, &str>("symbol").into(),
+                    exchange: parse_column("exchange", &db_tick.get::<String, &str>("exchange"))?,
+                    datetime: db_tick.get::<NaiveDateTime, &str>("datetime"),
+                    name: db_tick.get::<String, &str>("name"),
+                    volume: db_tick.get::<f64, &str>("volume"),
+                    turnover: db_tick.get::<f64, &str>("turnover"),
+                    open_interest: db_tick.get::<f64, &str>("open_interest"),
+                    last_price: db_tick.get::<f64, &str>("last_price"),
+                    last_volume: db_tick.get::<f64, &str>("last_volume"),
+                    limit_up: db_tick.get::<f64, &str>("limit_up"),
+                    limit_down: db_tick.get::<f64, &str>("limit_down"),
+                    open_price: db_tick.get::<f64, &str>("open_price"),
+                    high_price: db_tick.get::<f64, &str>("high_price"),
+                    low_price: db_tick.get::<f64, &str>("low_price"),
+                    pre_close: db_tick.get::<f64, &str>("pre_close"),
+                    bid_price_1: db_tick.get::<f64, &str>("bid_price_1"),
+                    bid_price_2: db_tick.get::<f64, &str>("bid_price_2"),
+                    bid_price_3: db_tick.get::<f64, &str>("bid_price_3"),
+                    bid_price_4: db_tick.get::<f64, &str>("bid_price_4"),
+                    bid_price_5: db_tick.get::<f64, &str>("bid_price_5"),
+                    ask_price_1: db_tick.get::<f64, &str>("ask_price_1"),
+                    ask_price_2: db_tick.get::<f64, &str>("ask_price_2"),
+                    ask_price_3: db_tick.get::<f64, &str>("ask_price_3"),
+                    ask_price_4: db_tick.get::<f64, &str>("ask_price_4"),
+                    ask_price_5: db_tick.get::<f64, &str>("ask_price_5"),
+                    bid_volume_1: db_tick.get::<f64, &str>("bid_volume_1"),
+                    bid_volume_2: db_tick.get::<f64, &str>("bid_volume_2"),
+                    bid_volume_3: db_tick.get::<f64, &str>("bid_volume_3"),
+                    bid_volume_4: db_tick.get::<f64, &str>("bid_volume_4"),
+                    bid_volume_5: db_tick.get::<f64, &str>("bid_volume_5"),
+                    ask_volume_1: db_tick.get::<f64, &str>("ask_volume_1"),
+                    ask_volume_2: db_tick.get::<f64, &str>("ask_volume_2"),
+                    ask_volume_3: db_tick.get::<f64, &str>("ask_volume_3"),
+                    ask_volume_4: db_tick.get::<f64, &str>("ask_volume_4"),
+                    ask_volume_5: db_tick.get::<f64, &str>("ask_volume_5"),
+                    localtime: db_tick
+                        .get::<Option<NaiveDateTime>, &str>("localtime")
+                        .unwrap_or_default(),
+                    gateway_name: "DB".into(),
+                }
+                .validated()?,
+            );
+        }
+        Ok(ticks)
+    }
+
+    fn save_bar_data_with_progress(
+        &self,
+        bars: Vec<BarData>,
+        progress: &dyn Fn(usize, usize),
+    ) -> VnrsResult<()> {
+        let total = bars.len();
+        let result: Result<(), sqlx::Error> = self.rt.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            let mut saved = 0;
+            for chunk in bars.chunks(BAR_CHUNK_SIZE) {
+                let mut query = QueryBuilder::new("INSERT OR REPLACE INTO dbbardata (symbol,exchange,datetime,interval,volume,turnover,open_interest,open_price,high_price,low_price,close_price) ");
+                query.push_values(chunk, |mut row, bar| {
+                    row.push_bind(bar.symbol.as_str())
+                        .push_bind(bar.exchange.to_string())
+                        .push_bind(bar.datetime)
+                        .push_bind(bar.interval.to_string())
+                        .push_bind(bar.volume)
+                        .push_bind(bar.turnover)
+                        .push_bind(bar.open_interest)
+                        .push_bind(bar.open_price)
+                        .push_bind(bar.high_price)
+                        .push_bind(bar.low_price)
+                        .push_bind(bar.close_price);
+                });
+                query.build().execute(&mut *tx).await?;
+                saved += chunk.len();
+                progress(saved, total);
+            }
+            tx.commit().await
+        });
+        Ok(result?)
+    }
+
+    fn save_backtest_record(&self, record: BacktestRecord) -> VnrsResult<i64> {
+        let result: Result<i64, sqlx::Error> = self.rt.block_on(async {
+            let done = sqlx::query("INSERT INTO dbbacktestrecord (created,strategy,strategy_hash,setting,vt_symbol,interval,backtest_start,backtest_end,parameters,statistics,trades) VALUES (?,?,?,?,?,?,?,?,?,?,?)")
+                .bind(record.created).bind(&record.strategy).bind(&record.strategy_hash).bind(&record.setting)
+                .bind(&record.vt_symbol).bind(record.interval.to_string()).bind(record.start).bind(record.end)
+                .bind(record.parameters.to_string()).bind(record.statistics.to_string())
+                .bind(record.trades.as_ref().map(|trades| trades.to_string()))
+                .execute(&self.pool).await?;
+            Ok(done.last_insert_rowid())
+        });
+        Ok(result?)
+    }
+
+    fn load_backtest_records(&self, query: &BacktestQuery) -> VnrsResult<Vec<BacktestRecord>> {
+        let datetime = |dt: Option<NaiveDateTime>| dt.map(|dt| dt.to_string());
+        self.load_records(
+            "SELECT id,created,strategy,strategy_hash,setting,vt_symbol,interval,backtest_start,backtest_end,parameters,statistics FROM dbbacktestrecord WHERE (?1 IS NULL OR strategy=?1) AND (?2 IS NULL OR vt_symbol=?2) AND (?3 IS NULL OR created>=?3) AND (?4 IS NULL OR created<=?4) ORDER BY id DESC",
+            [
+                query.strategy.clone(),
+                query.vt_symbol.clone(),
+                datetime(query.start),
+                datetime(query.end),
+            ],
+        )
+    }
+
+    fn load_backtest_record(&self, id: i64) -> VnrsResult<Option<BacktestRecord>> {
+        self.load_records(
+            "SELECT * FROM dbbacktestrecord WHERE id=?1",
+            [Some(id.to_string()), None, None, None],
+        )
+        .map(|mut records| records.pop())
+    }
+
+    fn save_optimization_trial(&self, trial: OptimizationTrial) -> VnrsResult<()> {
+        let result = self.rt.block_on(
+            sqlx::query("INSERT INTO dboptimizationtrial (study_id,created,setting,statistics,error,duration) VALUES (?,?,?,?,?,?)")
+                .bind(&trial.study_id).bind(trial.created).bind(&trial.setting)
+                .bind(trial.statistics.to_string()).bind(&trial.error).bind(trial.duration)
+                .execute(&self.pool),
+        );
+        result?;
+        Ok(())
+    }
+
+    fn load_optimization_trials(&self, study_id: &str) -> VnrsResult<Vec<OptimizationTrial>> {
+        let rows = self.rt.block_on(
+            sqlx::query("SELECT study_id,created,setting,statistics,error,duration FROM dboptimizationtrial WHERE study_id=? ORDER BY id")
+                .bind(study_id)
+                .fetch_all(&self.pool),
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| OptimizationTrial {
+                study_id: row.get("study_id"),
+                created: row.get("created"),
+                setting: row.get("setting"),
+                statistics: serde_json::from_str(row.get("statistics")).unwrap_or(Value::Null),
+                error: row.get("error"),
+                duration: row.get("duration"),
+            })
+            .collect())
+    }
+
+    fn save_adjust_factors(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        factors: &AdjustFactors,
+    ) -> VnrsResult<()> {
+        let factors: Vec<_> = factors.iter().collect();
+        let result: Result<(), sqlx::Error> = self.rt.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            for chunk in factors.chunks(BAR_CHUNK_SIZE) {
+                let mut query = QueryBuilder::new(
+                    "INSERT OR REPLACE INTO dbadjustfactor (symbol,exchange,date,factor) ",
+                );
+                query.push_values(chunk, |mut row, (date, factor)| {
+                    row.push_bind(symbol)
+                        .push_bind(exchange.to_string())
+                        .push_bind(*date)
+                        .push_bind(**factor);
+                });
+                query.build().execute(&mut *tx).await?;
+            }
+            tx.commit().await
+        });
+        Ok(result?)
+    }
+
+    fn load_adjust_factors(&self, symbol: &str, exchange: Exchange) -> VnrsResult<AdjustFactors> {
+        let rows = self.rt.block_on(
+            sqlx::query("SELECT date,factor FROM dbadjustfactor WHERE symbol=? AND exchange=?")
+                .bind(symbol)
+                .bind(exchange.to_string())
+                .fetch_all(&self.pool),
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("date"), row.get("factor")))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::database::tests::{
+        check_adjust_factors, check_backtest_records, check_optimization_trials,
+    };
+    use crate::vnrs::trader::synthetic::SyntheticBars;
+    use std::cell::RefCell;
+
+    fn memory_database() -> SqliteDatabase {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let pool = rt
+            .block_on(
+                sqlx::sqlite::SqlitePoolOptions::new()
+                    .max_connections(1)
+                    .connect("sqlite::memory:"),
+            )
+            .unwrap();
+        SqliteDatabase::from_pool(pool, rt).unwrap()
+    }
+
+    #[test]
+    fn backtest_records() {
+        check_backtest_records(&memory_database());
+    }
+
+    #[test]
+    fn optimization_trials() {
+        check_optimization_trials(&memory_database());
+    }
+
+    #[test]
+    fn adjust_factors() {
+        check_adjust_factors(&memory_database());
+    }
+
+    #[test]
+    fn migrations_applied_once() {
+        let database = memory_database();
+        // Connecting again finds the schema up to date
+        database.rt.block_on(database.migrate()).unwrap();
+        let versions: Vec<i64> = database
+            .rt
+            .block_on(
+                sqlx::query_scalar("SELECT version FROM dbschemaversion ORDER BY version")
+                    .fetch_all(&database.pool),
+            )
+            .unwrap();
+        assert_eq!(versions, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn pragmas_from_settings() {
+        let path = std::env::temp_dir().join(format!("vnrs_pragmas_{}.db", std::process::id()));
+        let mut settings = (*crate::vnrs::trader::setting::get_settings()).clone();
+        settings.insert("database.database", path.to_string_lossy().into_owned());
+        settings.insert("database.cache_size", "-2000".to_string());
+
+        let database = SqliteDatabase::connect(options_from(&settings).unwrap()).unwrap();
+        let journal_mode: String = database
+            .rt
+            .block_on(sqlx::query_scalar("PRAGMA journal_mode").fetch_one(&database.pool))
+            .unwrap();
+        let cache_size: i64 = database
+            .rt
+            .block_on(sqlx::query_scalar("PRAGMA cache_size").fetch_one(&database.pool))
+            .unwrap();
+        assert_eq!((journal_mode.as_str(), cache_size), ("wal", -2000));
+        drop(database);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        settings.insert("database.synchronous", "SOMETIMES".to_string());
+        assert!(matches!(
+            options_from(&settings),
+            Err(VnrsError::Setting(_))
+        ));
+        settings.insert("database.synchronous", "FULL".to_string());
+        settings.insert("database.mmap_size", "0; DROP TABLE dbbardata".to_string());
+        assert!(options_from(&settings).is_err());
+    }
+
+    #[test]
+    fn bulk_save_bars() {
+        let database = memory_database();
+        let bars = SyntheticBars {
+            count: 2500,
+            ..Default::default()
+        }
+        .generate();
+        let calls = RefCell::new(Vec::new());
+        database
+            .save_bar_data_with_progress(bars.clone(), &|saved, total| {
+                calls.borrow_mut().push((saved, total))
+            })
+            .unwrap();
+        assert_eq!(
+            calls.into_inner(),
+            vec![(1000, 2500), (2000, 2500), (2500, 2500)]
+        );
+
+        // Saving again replaces the bars at the same time
+        database.save_bar_data(bars[2000..].to_vec()).unwrap();
+        let loaded = database
+            .load_bar_data(
+                "TEST",
+                Exchange::LOCAL,
+                Interval::MINUTE,
+                bars[0].datetime,
+                bars[2499].datetime,
+            )
+            .unwrap();
+        assert_eq!(loaded.len(), 2500);
+        assert_eq!(loaded[2499].close_price, bars[2499].close_price);
+    }
+
+    #[test]
+    fn concurrent_loads() {
+        let path = std::env::temp_dir().join(format!("vnrs_concurrent_{}.db", std::process::id()));
+        let mut settings = (*crate::vnrs::trader::setting::get_settings()).clone();
+        settings.insert("database.database", path.to_string_lossy().into_owned());
+        let database =
+            std::sync::Arc::new(SqliteDatabase::connect(options_from(&settings).unwrap()).unwrap());
+        let bars = SyntheticBars {
+            count: 2000,
+            ..Default::default()
+        }
+        .generate();
+        database.save_bar_data(bars.clone()).unwrap();
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let database = database.clone();
+                let (start, end) = (bars[0].datetime, bars[1999].datetime);
+                std::thread::spawn(move || {
+                    database
+                        .load_bar_data("TEST", Exchange::LOCAL, Interval::MINUTE, start, end)
+                        .unwrap()
+                })
+            })
+            .collect();
+        for worker in workers {
+            let loaded = worker.join().unwrap();
+            assert_eq!(loaded.len(), 2000);
+            assert_eq!(loaded[1999].close_price, bars[1999].close_price);
+        }
+        drop(database);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
@@ -0,0 +1,54 @@
+/*!History data from an external datafeed, used to fill what the database is missing. */
+use std::sync::{Arc, Mutex};
+
+use chrono::NaiveDateTime;
+
+use super::constant::{Exchange, Interval};
+use super::object::{BarData, TickData};
+use super::setting::get_settings;
+use crate::error::VnrsResult;
+
+static DATAFEED: Mutex<Option<Arc<dyn BaseDatafeed + Send + Sync>>> = Mutex::new(None);
+
+pub trait BaseDatafeed {
+    ///Empty if the datafeed has no such data, VnrsError::Datafeed if the query failed.
+    fn query_bar_history(
+        &self,
+        symbol: &str,
+        exchange: Exchange,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<Vec<BarData>>;
+
+    fn query_tick_history(
+        &self,
+        _symbol: &str,
+        _exchange: Exchange,
+        _start: NaiveDateTime,
+        _end: NaiveDateTime,
+    ) -> VnrsResult<Vec<TickData>> {
+        Ok(Vec::new())
+    }
+}
+
+///Use datafeed for all later get_datafeed calls in the process.
+pub fn set_datafeed(datafeed: Arc<dyn BaseDatafeed + Send + Sync>) {
+    *DATAFEED.lock().unwrap() = Some(datafeed);
+}
+
+///The datafeed set by set_datafeed, None when there is none or datafeed.name is empty.
+pub fn get_datafeed() -> Option<Arc<dyn BaseDatafeed + Send + Sync>> {
+    if let Some(datafeed) = DATAFEED.lock().unwrap().clone() {
+        return Some(datafeed);
+    }
+
+    let datafeed_name = &get_settings()["datafeed.name"];
+    if !datafeed_name.is_empty() {
+        log::warn!(
+            "unsupported datafeed {}, no datafeed is used",
+            datafeed_name
+        );
+    }
+    None
+}
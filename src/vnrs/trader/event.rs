@@ -0,0 +1,15 @@
+/*!Event type string used in the trading platform. */
+pub use crate::vnrs::event::EVENT_TIMER;
+
+pub const EVENT_TICK: &str = "eTick.";
+pub const EVENT_BAR: &str = "eBar.";
+pub const EVENT_TRADE: &str = "eTrade.";
+pub const EVENT_ORDER: &str = "eOrder.";
+pub const EVENT_POSITION: &str = "ePosition.";
+pub const EVENT_ACCOUNT: &str = "eAccount.";
+pub const EVENT_QUOTE: &str = "eQuote.";
+pub const EVENT_CONTRACT: &str = "eContract.";
+pub const EVENT_LOG: &str = "eLog";
+pub const EVENT_REPLAY: &str = "eReplay";
+///Put after the settings were reloaded, data the Vec<&'static str> of changed keys.
+pub const EVENT_SETTING: &str = "eSetting";
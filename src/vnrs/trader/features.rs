@@ -0,0 +1,221 @@
+/*!Microstructure features of a tick stream for tick mode strategies: the side of the trades
+between two ticks, order flow imbalance of the best quotes, VWAP and the microprice. The
+rolling ones are over the last n ticks and, like the indicators, None until n ticks were seen. */
+use super::constant::Direction;
+use super::indicators::RollingStats;
+use super::object::TickData;
+
+///Features updated tick by tick, see the module docs.
+#[derive(Debug, Clone)]
+pub struct TickFeatureEngine {
+    last_tick: Option<TickData>,
+    ///Side of the last trade, kept for trades at an unchanged price.
+    trade_direction: Option<Direction>,
+    traded_volume: f64,
+    traded_turnover: f64,
+    ofi: RollingStats,
+    buy_volume: RollingStats,
+    sell_volume: RollingStats,
+    microprice: RollingStats,
+}
+
+impl TickFeatureEngine {
+    pub fn new(n: usize) -> Self {
+        TickFeatureEngine {
+            last_tick: None,
+            trade_direction: None,
+            traded_volume: 0.0,
+            traded_turnover: 0.0,
+            ofi: RollingStats::new(n),
+            buy_volume: RollingStats::new(n),
+            sell_volume: RollingStats::new(n),
+            microprice: RollingStats::new(n),
+        }
+    }
+
+    pub fn update_tick(&mut self, tick: &TickData) {
+        let Some(last) = self.last_tick.replace(tick.clone()) else {
+            if let Some(microprice) = microprice(tick) {
+                self.microprice.update(microprice);
+            }
+            return;
+        };
+
+        // Cumulative volume starts over with a new session
+        let volume = if tick.volume >= last.volume {
+            tick.volume - last.volume
+        } else {
+            tick.volume
+        };
+        let (mut buy, mut sell) = (0.0, 0.0);
+        if volume > 0.0 {
+            let direction = infer_direction(
+                tick.last_price,
+                &last,
+                self.trade_direction.unwrap_or(Direction::NET),
+            );
+            match direction {
+                Direction::LONG => buy = volume,
+                Direction::SHORT => sell = volume,
+                _ => {}
+            }
+            if direction != Direction::NET {
+                self.trade_direction = Some(direction);
+            }
+            self.traded_volume += volume;
+            self.traded_turnover += volume * tick.last_price;
+        }
+        self.buy_volume.update(buy);
+        self.sell_volume.update(sell);
+        self.ofi.update(order_flow_imbalance(&last, tick));
+        if let Some(microprice) = microprice(tick) {
+            self.microprice.update(microprice);
+        }
+    }
+
+    ///Side inferred for the last trade, None before any volume traded.
+    pub fn trade_direction(&self) -> Option<Direction> {
+        self.trade_direction
+    }
+
+    ///Order flow imbalance of the best quotes summed over the last n ticks, positive when
+    ///bids were added or asks taken.
+    pub fn ofi(&self) -> Option<f64> {
+        self.ofi.sum()
+    }
+
+    ///Buy minus sell volume over their sum in the last n ticks, between -1 and 1.
+    pub fn trade_imbalance(&self) -> Option<f64> {
+        let buy = self.buy_volume.sum()?;
+        let sell = self.sell_volume.sum()?;
+        (buy + sell > 0.0).then(|| (buy - sell) / (buy + sell))
+    }
+
+    ///Volume weighted price of all trades seen.
+    pub fn vwap(&self) -> Option<f64> {
+        (self.traded_volume > 0.0).then(|| self.traded_turnover / self.traded_volume)
+    }
+
+    ///Microprice of the last tick.
+    pub fn microprice(&self) -> Option<f64> {
+        microprice(self.last_tick.as_ref()?)
+    }
+
+    ///Mean microprice of the last n ticks.
+    pub fn rolling_microprice(&self) -> Option<f64> {
+        self.microprice.mean()
+    }
+}
+
+///Side of a trade at price against the quotes of the tick before: at or above the ask is a
+///buy, at or below the bid a sell, else the tick test against the last price, where an
+///unchanged price keeps the side of the trade before.
+pub fn infer_direction(price: f64, last: &TickData, last_direction: Direction) -> Direction {
+    if last.ask_price_1 > 0.0 && price >= last.ask_price_1 {
+        Direction::LONG
+    } else if last.bid_price_1 > 0.0 && price <= last.bid_price_1 {
+        Direction::SHORT
+    } else if price > last.last_price {
+        Direction::LONG
+    } else if price < last.last_price {
+        Direction::SHORT
+    } else {
+        last_direction
+    }
+}
+
+///Order flow imbalance from last to tick of Cont, Kukanov and Stoikov: bid volume added at an
+///unchanged or higher bid minus volume removed at an unchanged or lower one, less the same for
+///the ask.
+pub fn order_flow_imbalance(last: &TickData, tick: &TickData) -> f64 {
+    let mut ofi = 0.0;
+    if tick.bid_price_1 >= last.bid_price_1 {
+        ofi += tick.bid_volume_1;
+    }
+    if tick.bid_price_1 <= last.bid_price_1 {
+        ofi -= last.bid_volume_1;
+    }
+    if tick.ask_price_1 <= last.ask_price_1 {
+        ofi -= tick.ask_volume_1;
+    }
+    if tick.ask_price_1 >= last.ask_price_1 {
+        ofi += last.ask_volume_1;
+    }
+    ofi
+}
+
+///Mid price weighted toward the side with less volume, where the price is more likely to
+///move. None without volume at both best quotes.
+pub fn microprice(tick: &TickData) -> Option<f64> {
+    let volume = tick.bid_volume_1 + tick.ask_volume_1;
+    (tick.bid_volume_1 > 0.0 && tick.ask_volume_1 > 0.0).then(|| {
+        (tick.bid_price_1 * tick.ask_volume_1 + tick.ask_price_1 * tick.bid_volume_1) / volume
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(last_price: f64, volume: f64, bid: (f64, f64), ask: (f64, f64)) -> TickData {
+        TickData {
+            last_price,
+            volume,
+            bid_price_1: bid.0,
+            bid_volume_1: bid.1,
+            ask_price_1: ask.0,
+            ask_volume_1: ask.1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn features_of_ticks() {
+        let ticks = [
+            tick(100.0, 10.0, (99.8, 5.0), (100.0, 5.0)),
+            // Lifts the ask
+            tick(100.0, 14.0, (99.8, 6.0), (100.2, 2.0)),
+            // Between the quotes, up from the last price
+            tick(100.1, 16.0, (100.0, 3.0), (100.2, 1.0)),
+            // Unchanged price keeps the side
+            tick(100.1, 17.0, (100.0, 3.0), (100.2, 1.0)),
+            // Hits the bid
+            tick(100.0, 23.0, (99.8, 4.0), (100.0, 4.0)),
+        ];
+        let mut engine = TickFeatureEngine::new(3);
+        let mut directions = Vec::new();
+        for tick in &ticks {
+            engine.update_tick(tick);
+            directions.push(engine.trade_direction());
+        }
+        assert_eq!(
+            directions,
+            vec![
+                None,
+                Some(Direction::LONG),
+                Some(Direction::LONG),
+                Some(Direction::LONG),
+                Some(Direction::SHORT)
+            ]
+        );
+
+        // Volumes of 2, 1 and 6 in the last 3 ticks
+        assert_eq!(engine.trade_imbalance(), Some((3.0 - 6.0) / 9.0));
+        let vwap = (4.0 * 100.0 + 2.0 * 100.1 + 100.1 + 6.0 * 100.0) / 13.0;
+        assert!((engine.vwap().unwrap() - vwap).abs() < 1e-12);
+        assert!((engine.microprice().unwrap() - 99.9).abs() < 1e-12);
+
+        assert_eq!(order_flow_imbalance(&ticks[0], &ticks[1]), 6.0 - 5.0 + 5.0);
+        assert_eq!(order_flow_imbalance(&ticks[1], &ticks[2]), 3.0 - 1.0 + 2.0);
+        let ofi: f64 = ticks[1..]
+            .windows(2)
+            .map(|pair| order_flow_imbalance(&pair[0], &pair[1]))
+            .sum();
+        assert!((engine.ofi().unwrap() - ofi).abs() < 1e-12);
+
+        let mut engine = TickFeatureEngine::new(3);
+        engine.update_tick(&ticks[0]);
+        assert_eq!((engine.ofi(), engine.rolling_microprice()), (None, None));
+        assert_eq!(engine.vwap(), None);
+    }
+}
@@ -0,0 +1,106 @@
+/*!Exchange rates between the currencies of contracts, for pnl and equity in one base currency.
+Rates are fixed, or dated e.g. from the daily bars of an FX pair queried from the datafeed, a
+date without a rate of its own using the last one before it. */
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDate;
+
+use super::constant::Currency;
+use super::object::BarData;
+
+///Table of rates, each the amount of to one unit of from buys.
+#[derive(Debug, Clone, Default)]
+pub struct FxRates {
+    fixed: HashMap<(Currency, Currency), f64>,
+    daily: HashMap<(Currency, Currency), BTreeMap<NaiveDate, f64>>,
+}
+
+impl FxRates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Rate used on dates without a dated one.
+    pub fn set_rate(&mut self, from: Currency, to: Currency, rate: f64) {
+        self.fixed.insert((from, to), rate);
+    }
+
+    pub fn set_daily_rate(&mut self, from: Currency, to: Currency, date: NaiveDate, rate: f64) {
+        self.daily.entry((from, to)).or_default().insert(date, rate);
+    }
+
+    ///Closes of bars of the from/to pair as the rates of their dates, e.g. the daily bars of
+    ///USDCNY from BaseDatafeed::query_bar_history.
+    pub fn add_bars(&mut self, from: Currency, to: Currency, bars: &[BarData]) {
+        for bar in bars {
+            self.set_daily_rate(from, to, bar.datetime.date(), bar.close_price);
+        }
+    }
+
+    ///Rate from one currency to another on date, 1 for the same currency. The inverse of the
+    ///rate of the opposite pair is used when only that one is known.
+    pub fn rate(&self, from: Currency, to: Currency, date: NaiveDate) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+        self.direct_rate(from, to, date).or_else(|| {
+            self.direct_rate(to, from, date)
+                .filter(|rate| *rate != 0.0)
+                .map(|rate| 1.0 / rate)
+        })
+    }
+
+    fn direct_rate(&self, from: Currency, to: Currency, date: NaiveDate) -> Option<f64> {
+        self.daily
+            .get(&(from, to))
+            .and_then(|rates| rates.range(..=date).next_back())
+            .map(|(_, rate)| *rate)
+            .or_else(|| self.fixed.get(&(from, to)).copied())
+    }
+
+    ///amount in from converted to to at the rate of date.
+    pub fn convert(
+        &self,
+        amount: f64,
+        from: Currency,
+        to: Currency,
+        date: NaiveDate,
+    ) -> Option<f64> {
+        Some(amount * self.rate(from, to, date)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn fixed_and_dated_rates() {
+        let mut rates = FxRates::new();
+        rates.set_rate(Currency::USD, Currency::CNY, 7.0);
+        rates.set_daily_rate(Currency::USD, Currency::CNY, date("2024-01-03"), 7.2);
+        rates.set_rate(Currency::CNY, Currency::HKD, 1.1);
+
+        let day = date("2024-01-02");
+        assert_eq!(rates.rate(Currency::HKD, Currency::HKD, day), Some(1.0));
+        assert_eq!(rates.rate(Currency::USD, Currency::CNY, day), Some(7.0));
+        assert_eq!(
+            rates.rate(Currency::USD, Currency::CNY, date("2024-01-05")),
+            Some(7.2)
+        );
+        assert_eq!(
+            rates.convert(72.0, Currency::CNY, Currency::USD, date("2024-01-04")),
+            Some(10.0)
+        );
+        assert_eq!(
+            rates.rate(Currency::HKD, Currency::CNY, day),
+            Some(1.0 / 1.1)
+        );
+        // No chaining through a third currency
+        assert_eq!(rates.rate(Currency::USD, Currency::HKD, day), None);
+    }
+}
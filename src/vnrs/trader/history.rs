@@ -0,0 +1,147 @@
+/*!Compact columnar store of bar history for long backtests. */
+use std::mem::size_of;
+
+use chrono::NaiveDateTime;
+
+use super::constant::{Exchange, Interval};
+use super::object::{BarData, GatewayName, Symbol};
+
+///Bytes a bar takes in a BarHistory.
+pub const BAR_BYTES: usize = size_of::<NaiveDateTime>() + 7 * size_of::<f64>();
+
+///Bars of one symbol and interval held as one array per field, with symbol, exchange and
+///interval stored once instead of per bar. BarData is materialized on access.
+#[derive(Debug, Default, Clone)]
+pub struct BarHistory {
+    symbol: Symbol,
+    exchange: Exchange,
+    interval: Interval,
+    gateway_name: GatewayName,
+
+    datetime: Vec<NaiveDateTime>,
+    volume: Vec<f64>,
+    turnover: Vec<f64>,
+    open_interest: Vec<f64>,
+    open_price: Vec<f64>,
+    high_price: Vec<f64>,
+    low_price: Vec<f64>,
+    close_price: Vec<f64>,
+}
+
+impl BarHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Symbol, exchange and interval are taken from the first bar, bars must all share them.
+    pub fn from_bars(bars: impl IntoIterator<Item = BarData>) -> Self {
+        let mut history = Self::new();
+        history.extend(bars);
+        history
+    }
+
+    pub fn extend(&mut self, bars: impl IntoIterator<Item = BarData>) {
+        let bars = bars.into_iter();
+        self.reserve(bars.size_hint().0);
+        for bar in bars {
+            self.push(&bar);
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.datetime.reserve(additional);
+        self.volume.reserve(additional);
+        self.turnover.reserve(additional);
+        self.open_interest.reserve(additional);
+        self.open_price.reserve(additional);
+        self.high_price.reserve(additional);
+        self.low_price.reserve(additional);
+        self.close_price.reserve(additional);
+    }
+
+    pub fn push(&mut self, bar: &BarData) {
+        if self.is_empty() {
+            self.symbol = bar.symbol;
+            self.exchange = bar.exchange;
+            self.interval = bar.interval;
+            self.gateway_name = bar.gateway_name;
+        }
+
+        self.datetime.push(bar.datetime);
+        self.volume.push(bar.volume);
+        self.turnover.push(bar.turnover);
+        self.open_interest.push(bar.open_interest);
+        self.open_price.push(bar.open_price);
+        self.high_price.push(bar.high_price);
+        self.low_price.push(bar.low_price);
+        self.close_price.push(bar.close_price);
+    }
+
+    pub fn len(&self) -> usize {
+        self.datetime.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.datetime.is_empty()
+    }
+
+    ///Bytes allocated for the bars, including the capacity reserved for more.
+    pub fn memory_size(&self) -> usize {
+        self.datetime.capacity() * size_of::<NaiveDateTime>()
+            + [
+                &self.volume,
+                &self.turnover,
+                &self.open_interest,
+                &self.open_price,
+                &self.high_price,
+                &self.low_price,
+                &self.close_price,
+            ]
+            .iter()
+            .map(|column| column.capacity() * size_of::<f64>())
+            .sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn get(&self, index: usize) -> Option<BarData> {
+        let mut bar = BarData::default();
+        self.read_into(index, &mut bar).then_some(bar)
+    }
+
+    ///Write bar at index into an existing BarData, false if index is out of range.
+    pub fn read_into(&self, index: usize, bar: &mut BarData) -> bool {
+        if index >= self.len() {
+            return false;
+        }
+
+        bar.symbol = self.symbol;
+        bar.exchange = self.exchange;
+        bar.interval = self.interval;
+        bar.gateway_name = self.gateway_name;
+
+        bar.datetime = self.datetime[index];
+        bar.volume = self.volume[index];
+        bar.turnover = self.turnover[index];
+        bar.open_interest = self.open_interest[index];
+        bar.open_price = self.open_price[index];
+        bar.high_price = self.high_price[index];
+        bar.low_price = self.low_price[index];
+        bar.close_price = self.close_price[index];
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = BarData> + '_ {
+        (0..self.len()).filter_map(|i| self.get(i))
+    }
+
+    pub fn datetimes(&self) -> &[NaiveDateTime] {
+        &self.datetime
+    }
+
+    pub fn close_prices(&self) -> &[f64] {
+        &self.close_price
+    }
+}
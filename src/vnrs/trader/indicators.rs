@@ -0,0 +1,386 @@
+/*!Streaming indicators updated in O(1), or amortized O(1) for rolling extremes, per bar,
+instead of recomputing over the whole array of an ArrayManager every bar. Each returns None
+until it has seen enough values.
+
+ATR and RSI smooth over all the values seen, while the ArrayManager versions start over from the
+oldest bar in the arrays, so the two drift apart slightly once the arrays are full. */
+use std::collections::VecDeque;
+
+use super::object::BarData;
+
+///Exponential moving average seeded with the simple average of the first n values.
+#[derive(Debug, Clone)]
+pub struct Ema {
+    n: usize,
+    alpha: f64,
+    count: usize,
+    value: f64,
+}
+
+impl Ema {
+    pub fn new(n: usize) -> Self {
+        Ema {
+            n,
+            alpha: 2.0 / (n as f64 + 1.0),
+            count: 0,
+            value: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, value: f64) -> Option<f64> {
+        self.count += 1;
+        if self.count <= self.n {
+            self.value += (value - self.value) / self.count as f64;
+        } else {
+            self.value += self.alpha * (value - self.value);
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        (self.n > 0 && self.count >= self.n).then_some(self.value)
+    }
+}
+
+///Sum, mean and population standard deviation of the last n values, the variance kept by
+///Welford's method adjusted for the value leaving the window.
+#[derive(Debug, Clone)]
+pub struct RollingStats {
+    n: usize,
+    window: VecDeque<f64>,
+    sum: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl RollingStats {
+    pub fn new(n: usize) -> Self {
+        RollingStats {
+            n,
+            window: VecDeque::with_capacity(n + 1),
+            sum: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, value: f64) {
+        if self.n == 0 {
+            return;
+        }
+        self.window.push_back(value);
+        self.sum += value;
+        if self.window.len() > self.n {
+            let old = self.window.pop_front().unwrap();
+            self.sum -= old;
+            let mean = self.mean + (value - old) / self.n as f64;
+            self.m2 += (value - old) * (value - mean + old - self.mean);
+            self.mean = mean;
+        } else {
+            let delta = value - self.mean;
+            self.mean += delta / self.window.len() as f64;
+            self.m2 += delta * (value - self.mean);
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.n > 0 && self.window.len() == self.n
+    }
+
+    pub fn sum(&self) -> Option<f64> {
+        self.is_ready().then_some(self.sum)
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        self.is_ready().then_some(self.mean)
+    }
+
+    pub fn std(&self) -> Option<f64> {
+        // Rounding can leave a tiny negative m2 for a constant window
+        self.is_ready()
+            .then(|| (self.m2.max(0.0) / self.n as f64).sqrt())
+    }
+}
+
+///Largest (or smallest) of the last n values, from a deque of the candidates in
+///decreasing (or increasing) order.
+#[derive(Debug, Clone)]
+pub struct RollingExtreme {
+    n: usize,
+    max: bool,
+    count: usize,
+    candidates: VecDeque<(usize, f64)>,
+}
+
+impl RollingExtreme {
+    pub fn max(n: usize) -> Self {
+        RollingExtreme {
+            n,
+            max: true,
+            count: 0,
+            candidates: VecDeque::new(),
+        }
+    }
+
+    pub fn min(n: usize) -> Self {
+        RollingExtreme {
+            max: false,
+            ..Self::max(n)
+        }
+    }
+
+    pub fn update(&mut self, value: f64) -> Option<f64> {
+        let dominated = |candidate: f64| {
+            if self.max {
+                candidate <= value
+            } else {
+                candidate >= value
+            }
+        };
+        while self
+            .candidates
+            .back()
+            .is_some_and(|(_, candidate)| dominated(*candidate))
+        {
+            self.candidates.pop_back();
+        }
+        self.candidates.push_back((self.count, value));
+        self.count += 1;
+        while self
+            .candidates
+            .front()
+            .is_some_and(|(index, _)| index + self.n < self.count)
+        {
+            self.candidates.pop_front();
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        if self.n == 0 || self.count < self.n {
+            return None;
+        }
+        self.candidates.front().map(|(_, value)| *value)
+    }
+}
+
+///Average true range with Wilder smoothing, seeded with the average of the first n true
+///ranges. The first bar has no previous close and gives no true range.
+#[derive(Debug, Clone)]
+pub struct Atr {
+    n: usize,
+    count: usize,
+    pre_close: Option<f64>,
+    value: f64,
+}
+
+impl Atr {
+    pub fn new(n: usize) -> Self {
+        Atr {
+            n,
+            count: 0,
+            pre_close: None,
+            value: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, bar: &BarData) -> Option<f64> {
+        let pre_close = self.pre_close.replace(bar.close_price);
+        if let Some(pre_close) = pre_close {
+            let true_range = (bar.high_price - bar.low_price)
+                .max((bar.high_price - pre_close).abs())
+                .max((bar.low_price - pre_close).abs());
+            self.count += 1;
+            if self.count <= self.n {
+                self.value += (true_range - self.value) / self.count as f64;
+            } else {
+                self.value = (self.value * (self.n - 1) as f64 + true_range) / self.n as f64;
+            }
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        (self.n > 0 && self.count >= self.n).then_some(self.value)
+    }
+}
+
+///Relative strength index with Wilder smoothing, seeded with the average gain and loss of
+///the first n changes.
+#[derive(Debug, Clone)]
+pub struct Rsi {
+    n: usize,
+    count: usize,
+    pre_close: Option<f64>,
+    gain: f64,
+    loss: f64,
+}
+
+impl Rsi {
+    pub fn new(n: usize) -> Self {
+        Rsi {
+            n,
+            count: 0,
+            pre_close: None,
+            gain: 0.0,
+            loss: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, close: f64) -> Option<f64> {
+        let pre_close = self.pre_close.replace(close);
+        if let Some(pre_close) = pre_close {
+            let change = close - pre_close;
+            self.count += 1;
+            if self.count <= self.n {
+                let count = self.count as f64;
+                self.gain += (change.max(0.0) - self.gain) / count;
+                self.loss += ((-change).max(0.0) - self.loss) / count;
+            } else {
+                let n = self.n as f64;
+                self.gain = (self.gain * (n - 1.0) + change.max(0.0)) / n;
+                self.loss = (self.loss * (n - 1.0) + (-change).max(0.0)) / n;
+            }
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        if self.n == 0 || self.count < self.n {
+            return None;
+        }
+        if self.gain + self.loss == 0.0 {
+            Some(0.0)
+        } else {
+            Some(100.0 * self.gain / (self.gain + self.loss))
+        }
+    }
+}
+
+///Donchian channel of the last n bars, (highest high, lowest low).
+#[derive(Debug, Clone)]
+pub struct Donchian {
+    up: RollingExtreme,
+    down: RollingExtreme,
+}
+
+impl Donchian {
+    pub fn new(n: usize) -> Self {
+        Donchian {
+            up: RollingExtreme::max(n),
+            down: RollingExtreme::min(n),
+        }
+    }
+
+    pub fn update(&mut self, bar: &BarData) -> Option<(f64, f64)> {
+        self.up.update(bar.high_price);
+        self.down.update(bar.low_price);
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<(f64, f64)> {
+        self.up.value().zip(self.down.value())
+    }
+}
+
+///Indicator an ArrayManager can keep up to date on every update_bar, by window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Indicator {
+    Sma(usize),
+    Std(usize),
+    Ema(usize),
+    Atr(usize),
+    Rsi(usize),
+    DonchianUp(usize),
+    DonchianDown(usize),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Streaming {
+    Stats(RollingStats),
+    Ema(Ema),
+    Atr(Atr),
+    Rsi(Rsi),
+    Extreme(RollingExtreme),
+}
+
+impl Streaming {
+    pub(crate) fn new(indicator: Indicator) -> Self {
+        match indicator {
+            Indicator::Sma(n) | Indicator::Std(n) => Streaming::Stats(RollingStats::new(n)),
+            Indicator::Ema(n) => Streaming::Ema(Ema::new(n)),
+            Indicator::Atr(n) => Streaming::Atr(Atr::new(n)),
+            Indicator::Rsi(n) => Streaming::Rsi(Rsi::new(n)),
+            Indicator::DonchianUp(n) => Streaming::Extreme(RollingExtreme::max(n)),
+            Indicator::DonchianDown(n) => Streaming::Extreme(RollingExtreme::min(n)),
+        }
+    }
+
+    pub(crate) fn update(&mut self, indicator: Indicator, bar: &BarData) {
+        match (self, indicator) {
+            (Streaming::Stats(stats), _) => stats.update(bar.close_price),
+            (Streaming::Ema(ema), _) => {
+                ema.update(bar.close_price);
+            }
+            (Streaming::Atr(atr), _) => {
+                atr.update(bar);
+            }
+            (Streaming::Rsi(rsi), _) => {
+                rsi.update(bar.close_price);
+            }
+            (Streaming::Extreme(extreme), Indicator::DonchianUp(_)) => {
+                extreme.update(bar.high_price);
+            }
+            (Streaming::Extreme(extreme), _) => {
+                extreme.update(bar.low_price);
+            }
+        }
+    }
+
+    pub(crate) fn value(&self, indicator: Indicator) -> Option<f64> {
+        match (self, indicator) {
+            (Streaming::Stats(stats), Indicator::Std(_)) => stats.std(),
+            (Streaming::Stats(stats), _) => stats.mean(),
+            (Streaming::Ema(ema), _) => ema.value(),
+            (Streaming::Atr(atr), _) => atr.value(),
+            (Streaming::Rsi(rsi), _) => rsi.value(),
+            (Streaming::Extreme(extreme), _) => extreme.value(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_windows() {
+        let values = [3.0, 1.0, 4.0, 1.0, 5.0, 9.0, 2.0, 6.0];
+        let mut stats = RollingStats::new(3);
+        let mut max = RollingExtreme::max(3);
+        let mut min = RollingExtreme::min(3);
+        for (i, value) in values.iter().enumerate() {
+            stats.update(*value);
+            max.update(*value);
+            min.update(*value);
+            if i < 2 {
+                assert_eq!((stats.mean(), max.value(), min.value()), (None, None, None));
+                continue;
+            }
+
+            let window = &values[i - 2..=i];
+            let mean = window.iter().sum::<f64>() / 3.0;
+            let std = (window.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / 3.0).sqrt();
+            assert!((stats.mean().unwrap() - mean).abs() < 1e-12);
+            assert!((stats.std().unwrap() - std).abs() < 1e-12);
+            assert_eq!(max.value(), window.iter().copied().reduce(f64::max));
+            assert_eq!(min.value(), window.iter().copied().reduce(f64::min));
+        }
+
+        let mut ema = Ema::new(3);
+        assert_eq!(ema.update(1.0), None);
+        ema.update(2.0);
+        assert_eq!(ema.update(3.0), Some(2.0));
+        assert_eq!(ema.update(6.0), Some(4.0));
+    }
+}
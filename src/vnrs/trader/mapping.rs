@@ -0,0 +1,203 @@
+/*!Symbol conventions of gateways and datafeeds, e.g. rb2410 vs RB2410 or BTCUSDT vs BTC-USDT.
+Symbols in vt_symbols and the database are canonical, a SymbolMapper converts them to and from
+the convention of each gateway. */
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use super::constant::Exchange;
+use super::object::{OrderRequest, Symbol};
+use super::utility::load_json;
+use crate::error::VnrsResult;
+
+///Name of the mapping file in the trader dir, a JSON object of SymbolRules keyed by gateway.
+pub const SYMBOL_MAPPING_FILENAME: &str = "symbol_mapping.json";
+
+static SYMBOL_MAPPER: OnceLock<SymbolMapper> = OnceLock::new();
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SymbolCase {
+    #[default]
+    KEEP,
+    UPPER,
+    LOWER,
+}
+
+impl SymbolCase {
+    fn apply(&self, symbol: &str) -> String {
+        match self {
+            SymbolCase::KEEP => symbol.to_string(),
+            SymbolCase::UPPER => symbol.to_uppercase(),
+            SymbolCase::LOWER => symbol.to_lowercase(),
+        }
+    }
+}
+
+///Convention of one gateway. Overrides map canonical symbols to gateway symbols and win over
+///the other rules in both directions.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SymbolRule {
+    ///Case of the symbols on the gateway.
+    pub case: SymbolCase,
+    ///Case of the canonical symbols, restored from gateway symbols.
+    pub canonical_case: SymbolCase,
+    ///Separator the gateway puts before the quote asset, canonical symbols have none.
+    pub separator: String,
+    ///Quote assets the separator goes before, e.g. USDT.
+    pub quotes: Vec<String>,
+    pub overrides: HashMap<String, String>,
+}
+
+impl SymbolRule {
+    pub fn to_gateway(&self, symbol: &str) -> String {
+        if let Some(gateway_symbol) = self.overrides.get(symbol) {
+            return gateway_symbol.clone();
+        }
+
+        let mut gateway_symbol = symbol.to_string();
+        if !self.separator.is_empty() {
+            let upper = symbol.to_uppercase();
+            if let Some(quote) = self
+                .quotes
+                .iter()
+                .find(|quote| upper.len() > quote.len() && upper.ends_with(&quote.to_uppercase()))
+            {
+                gateway_symbol.insert_str(symbol.len() - quote.len(), &self.separator);
+            }
+        }
+        self.case.apply(&gateway_symbol)
+    }
+
+    pub fn from_gateway(&self, symbol: &str) -> String {
+        if let Some((canonical, _)) = self.overrides.iter().find(|(_, s)| *s == symbol) {
+            return canonical.clone();
+        }
+
+        let canonical = if self.separator.is_empty() {
+            symbol.to_string()
+        } else {
+            symbol.replace(&self.separator, "")
+        };
+        self.canonical_case.apply(&canonical)
+    }
+}
+
+///Rules of the gateways and datafeeds keyed by their name, symbols of gateways without a rule
+///are kept as they are.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SymbolMapper {
+    rules: HashMap<String, SymbolRule>,
+}
+
+impl SymbolMapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Load rules from a JSON object of SymbolRules keyed by gateway name.
+    pub fn load_json<P: AsRef<Path>>(path: P) -> VnrsResult<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn add_rule(&mut self, gateway_name: &str, rule: SymbolRule) {
+        self.rules.insert(gateway_name.to_string(), rule);
+    }
+
+    pub fn get_rule(&self, gateway_name: &str) -> Option<&SymbolRule> {
+        self.rules.get(gateway_name)
+    }
+
+    ///Symbol of the gateway for a canonical symbol.
+    pub fn to_gateway(&self, gateway_name: &str, symbol: &str) -> String {
+        match self.rules.get(gateway_name) {
+            Some(rule) => rule.to_gateway(symbol),
+            None => symbol.to_string(),
+        }
+    }
+
+    ///Canonical symbol for a symbol of the gateway.
+    pub fn from_gateway(&self, gateway_name: &str, symbol: &str) -> String {
+        match self.rules.get(gateway_name) {
+            Some(rule) => rule.from_gateway(symbol),
+            None => symbol.to_string(),
+        }
+    }
+
+    ///vt_symbol of a symbol received from the gateway.
+    pub fn vt_symbol(&self, gateway_name: &str, symbol: &str, exchange: Exchange) -> String {
+        format!("{}.{}", self.from_gateway(gateway_name, symbol), exchange)
+    }
+
+    ///The request with its symbol in the convention of the gateway it is routed to.
+    pub fn route_order_request(&self, gateway_name: &str, req: &OrderRequest) -> OrderRequest {
+        OrderRequest {
+            symbol: Symbol::new(&self.to_gateway(gateway_name, &req.symbol)),
+            ..req.clone()
+        }
+    }
+}
+
+///Mapper of the symbol_mapping.json in the trader dir, without rules if the file is missing.
+pub fn get_symbol_mapper() -> &'static SymbolMapper {
+    SYMBOL_MAPPER.get_or_init(|| {
+        serde_json::from_value(load_json(SYMBOL_MAPPING_FILENAME)).unwrap_or_else(|e| {
+            log::warn!("{} 无效，不转换代码：{}", SYMBOL_MAPPING_FILENAME, e);
+            SymbolMapper::new()
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gateway_conventions() {
+        let mut mapper: SymbolMapper = serde_json::from_str(
+            r#"{
+                "CTP": {"case": "UPPER", "canonical_case": "LOWER", "overrides": {"ma409": "MA409"}},
+                "OKX": {"separator": "-", "quotes": ["USDT", "USD"]}
+            }"#,
+        )
+        .unwrap();
+        mapper.add_rule(
+            "XT",
+            SymbolRule {
+                case: SymbolCase::LOWER,
+                canonical_case: SymbolCase::UPPER,
+                separator: "_".to_string(),
+                quotes: vec!["USDT".to_string()],
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(mapper.to_gateway("CTP", "rb2410"), "RB2410");
+        assert_eq!(mapper.from_gateway("CTP", "RB2410"), "rb2410");
+        assert_eq!(mapper.from_gateway("CTP", "MA409"), "ma409");
+        assert_eq!(mapper.to_gateway("OKX", "BTCUSDT"), "BTC-USDT");
+        assert_eq!(mapper.to_gateway("OKX", "ETHBTC"), "ETHBTC");
+        assert_eq!(mapper.from_gateway("OKX", "BTC-USDT"), "BTCUSDT");
+        assert_eq!(mapper.to_gateway("XT", "BTCUSDT"), "btc_usdt");
+        assert_eq!(
+            mapper.vt_symbol("XT", "btc_usdt", Exchange::LOCAL),
+            "BTCUSDT.LOCAL"
+        );
+        // No rule for the gateway
+        assert_eq!(mapper.to_gateway("SIM", "rb2410"), "rb2410");
+
+        let req = OrderRequest {
+            symbol: "rb2410".into(),
+            exchange: Exchange::SHFE,
+            volume: 1.0,
+            ..Default::default()
+        };
+        let routed = mapper.route_order_request("CTP", &req);
+        assert_eq!(routed.symbol, "RB2410");
+        assert_eq!(routed.volume, 1.0);
+    }
+}
@@ -1,15 +1,19 @@
 /*！Basic data structure used for general trading function in the trading platform.*/
 use chrono::NaiveDateTime;
 use log::Level;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     collections::{HashMap, HashSet},
     ffi::{c_char, CString},
-    sync::{Mutex, OnceLock},
+    fmt,
+    ops::Deref,
+    sync::{Mutex, OnceLock, RwLock},
 };
 
 use super::constant::{
-    Direction, Exchange, Interval, Offset, OptionType, OrderType, Product, Status,
+    Currency, Direction, Exchange, Interval, Offset, OptionType, OrderType, Product, Status,
 };
+use crate::error::{VnrsError, VnrsResult};
 
 pub static ACTIVE_STATUSES: OnceLock<HashSet<Status>> = OnceLock::new();
 pub fn get_active_statuses() -> &'static HashSet<Status> {
@@ -20,12 +24,154 @@ pub fn get_active_statuses() -> &'static HashSet<Status> {
     })
 }
 
-#[derive(Debug, Default)]
+///Process wide table of interned strings, shared by Symbol and GatewayName. Names are never
+///freed, the table grows with the number of distinct names.
+struct InternTable {
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, u32>,
+}
+
+static INTERN_TABLE: OnceLock<RwLock<InternTable>> = OnceLock::new();
+
+fn intern_table() -> &'static RwLock<InternTable> {
+    INTERN_TABLE.get_or_init(|| {
+        RwLock::new(InternTable {
+            names: vec![""],
+            ids: HashMap::from([("", 0)]),
+        })
+    })
+}
+
+fn intern(name: &str) -> u32 {
+    if let Some(&id) = intern_table().read().unwrap().ids.get(name) {
+        return id;
+    }
+
+    let mut table = intern_table().write().unwrap();
+    if let Some(&id) = table.ids.get(name) {
+        return id;
+    }
+    let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+    let id = table.names.len() as u32;
+    table.names.push(name);
+    table.ids.insert(name, id);
+    id
+}
+
+fn resolve(id: u32) -> &'static str {
+    intern_table().read().unwrap().names[id as usize]
+}
+
+///Implement a copyable interned string type, a u32 newtype whose default is the empty string.
+macro_rules! interned_str {
+    ($name:ident) => {
+        impl $name {
+            pub fn new(name: &str) -> Self {
+                $name(intern(name))
+            }
+
+            pub fn as_str(&self) -> &'static str {
+                resolve(self.0)
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                self.as_str()
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(self.as_str(), f)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(name: &str) -> Self {
+                $name::new(name)
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(name: String) -> Self {
+                $name::new(&name)
+            }
+        }
+
+        impl From<&String> for $name {
+            fn from(name: &String) -> Self {
+                $name::new(name)
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.as_str() == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.as_str() == *other
+            }
+        }
+
+        impl PartialEq<String> for $name {
+            fn eq(&self, other: &String) -> bool {
+                self.as_str() == other
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                Ok($name::new(&String::deserialize(deserializer)?))
+            }
+        }
+    };
+}
+
+///Interned symbol, a copyable id into a process wide table of symbol strings, so that data
+///objects of the few symbols in a backtest don't each allocate a String.
+///
+///The default symbol is the empty string.
+#[repr(transparent)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
 
+interned_str!(Symbol);
+
+///Interned name of the gateway a data object came from, so names read at runtime, e.g. from a
+///config, can be set on data objects without leaking a &'static str for each.
+///
+///The default name is the empty string.
+#[repr(transparent)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GatewayName(u32);
+
+interned_str!(GatewayName);
+
+///gateway_name isn't deserialized, the receiver sets its own.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct TickData {
-    pub gateway_name: &'static str,
+    #[serde(skip_deserializing)]
+    pub gateway_name: GatewayName,
 
-    pub symbol: String,
+    pub symbol: Symbol,
     pub exchange: Exchange,
     pub datetime: NaiveDateTime,
 
@@ -67,19 +213,117 @@ pub struct TickData {
     pub ask_volume_4: f64,
     pub ask_volume_5: f64,
 
-    localtime: NaiveDateTime,
+    pub localtime: NaiveDateTime,
 }
 impl TickData {
     pub fn vt_symbol(&self) -> String {
         format!("{}.{}", self.symbol, self.exchange.to_string())
     }
+
+    ///(price, volume) of the bid levels, best first.
+    pub fn bid_levels(&self) -> [(f64, f64); 5] {
+        [
+            (self.bid_price_1, self.bid_volume_1),
+            (self.bid_price_2, self.bid_volume_2),
+            (self.bid_price_3, self.bid_volume_3),
+            (self.bid_price_4, self.bid_volume_4),
+            (self.bid_price_5, self.bid_volume_5),
+        ]
+    }
+
+    ///(price, volume) of the ask levels, best first.
+    pub fn ask_levels(&self) -> [(f64, f64); 5] {
+        [
+            (self.ask_price_1, self.ask_volume_1),
+            (self.ask_price_2, self.ask_volume_2),
+            (self.ask_price_3, self.ask_volume_3),
+            (self.ask_price_4, self.ask_volume_4),
+            (self.ask_price_5, self.ask_volume_5),
+        ]
+    }
+
+    ///Check prices are finite and not negative, volumes not negative, high not below low and
+    ///the best bid not above the best ask. Zero prices are treated as missing.
+    pub fn validate(&self) -> VnrsResult<()> {
+        let prices = [
+            ("last_price", self.last_price),
+            ("open_price", self.open_price),
+            ("high_price", self.high_price),
+            ("low_price", self.low_price),
+            ("pre_close", self.pre_close),
+            ("limit_up", self.limit_up),
+            ("limit_down", self.limit_down),
+        ];
+        let volumes = [
+            ("volume", self.volume),
+            ("turnover", self.turnover),
+            ("open_interest", self.open_interest),
+            ("last_volume", self.last_volume),
+        ];
+        for (name, value) in prices.into_iter().chain(volumes) {
+            check_non_negative(self, name, value)?;
+        }
+        for (price, volume) in self.bid_levels().into_iter().chain(self.ask_levels()) {
+            check_non_negative(self, "depth price", price)?;
+            check_non_negative(self, "depth volume", volume)?;
+        }
+
+        if self.high_price > 0.0 && self.low_price > self.high_price {
+            return Err(invalid(
+                self,
+                format!("low {} above high {}", self.low_price, self.high_price),
+            ));
+        }
+        if self.bid_price_1 > 0.0 && self.ask_price_1 > 0.0 && self.bid_price_1 > self.ask_price_1 {
+            return Err(invalid(
+                self,
+                format!("bid {} above ask {}", self.bid_price_1, self.ask_price_1),
+            ));
+        }
+        Ok(())
+    }
+
+    ///Self if it passes validate.
+    pub fn validated(self) -> VnrsResult<Self> {
+        self.validate()?;
+        Ok(self)
+    }
+}
+
+///Data checked by validate, named by its vt_symbol and datetime in errors.
+trait Validated {
+    fn describe(&self) -> String;
+}
+
+impl Validated for TickData {
+    fn describe(&self) -> String {
+        format!("tick {} {}", self.vt_symbol(), self.datetime)
+    }
+}
+
+impl Validated for BarData {
+    fn describe(&self) -> String {
+        format!("bar {} {}", self.vt_symbol(), self.datetime)
+    }
+}
+
+fn invalid(data: &impl Validated, reason: String) -> VnrsError {
+    VnrsError::Data(format!("{}: {}", data.describe(), reason))
+}
+
+fn check_non_negative(data: &impl Validated, name: &str, value: f64) -> VnrsResult<()> {
+    if value.is_finite() && value >= 0.0 {
+        Ok(())
+    } else {
+        Err(invalid(data, format!("{} is {}", name, value)))
+    }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct BarData {
-    pub gateway_name: &'static str,
+    pub gateway_name: GatewayName,
 
-    pub symbol: String,
+    pub symbol: Symbol,
     pub exchange: Exchange,
     pub datetime: NaiveDateTime,
 
@@ -94,9 +338,76 @@ pub struct BarData {
 }
 
 impl BarData {
+    ///Bar checked by validate, turnover and open interest zero.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        gateway_name: GatewayName,
+        symbol: Symbol,
+        exchange: Exchange,
+        datetime: NaiveDateTime,
+        interval: Interval,
+        open_price: f64,
+        high_price: f64,
+        low_price: f64,
+        close_price: f64,
+        volume: f64,
+    ) -> VnrsResult<Self> {
+        BarData {
+            gateway_name,
+            symbol,
+            exchange,
+            datetime,
+            interval,
+            volume,
+            turnover: 0.0,
+            open_interest: 0.0,
+            open_price,
+            high_price,
+            low_price,
+            close_price,
+        }
+        .validated()
+    }
+
     pub fn vt_symbol(&self) -> String {
         format!("{}.{}", self.symbol, self.exchange.to_string())
     }
+
+    ///Check prices are finite and not negative, volume, turnover and open interest not
+    ///negative, and high and low bound the open and close.
+    pub fn validate(&self) -> VnrsResult<()> {
+        let fields = [
+            ("open_price", self.open_price),
+            ("high_price", self.high_price),
+            ("low_price", self.low_price),
+            ("close_price", self.close_price),
+            ("volume", self.volume),
+            ("turnover", self.turnover),
+            ("open_interest", self.open_interest),
+        ];
+        for (name, value) in fields {
+            check_non_negative(self, name, value)?;
+        }
+
+        let body_high = self.open_price.max(self.close_price);
+        let body_low = self.open_price.min(self.close_price);
+        if self.high_price < body_high || self.low_price > body_low {
+            return Err(invalid(
+                self,
+                format!(
+                    "open {} close {} outside low {} high {}",
+                    self.open_price, self.close_price, self.low_price, self.high_price
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    ///Self if it passes validate.
+    pub fn validated(self) -> VnrsResult<Self> {
+        self.validate()?;
+        Ok(self)
+    }
 }
 
 #[derive(Debug)]
@@ -105,11 +416,13 @@ pub enum MixData {
     BarData(BarData),
 }
 
-#[derive(Debug, Default, Clone)]
+///gateway_name isn't deserialized, the receiver sets its own.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct OrderData {
-    pub gateway_name: &'static str,
+    #[serde(skip_deserializing)]
+    pub gateway_name: GatewayName,
 
-    pub symbol: String,
+    pub symbol: Symbol,
     pub exchange: Exchange,
     pub orderid: String,
 
@@ -122,7 +435,6 @@ pub struct OrderData {
     pub status: Status,
     pub datetime: NaiveDateTime,
     pub reference: String,
-
 }
 
 impl OrderData {
@@ -147,11 +459,13 @@ impl OrderData {
     // }
 }
 
-#[derive(Debug, Clone)]
+///gateway_name isn't deserialized, the receiver sets its own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TradeData {
-    pub gateway_name: &'static str,
+    #[serde(skip_deserializing)]
+    pub gateway_name: GatewayName,
 
-    pub symbol: String,
+    pub symbol: Symbol,
     pub exchange: Exchange,
     pub orderid: String,
     pub tradeid: String,
@@ -161,6 +475,9 @@ pub struct TradeData {
     pub price: f64,
     pub volume: f64,
     pub datetime: NaiveDateTime,
+    ///Reference of the order traded.
+    #[serde(default)]
+    pub reference: String,
 }
 
 impl TradeData {
@@ -177,21 +494,35 @@ impl TradeData {
     }
 }
 
+#[derive(Debug, Default, Clone)]
 pub struct PositionData {
-    symbol: String,
-    exchange: Exchange,
-    direction: Direction,
+    pub gateway_name: GatewayName,
 
-    volume: f64,
-    frozen: f64,
-    price: f64,
-    pnl: f64,
-    yd_volume: f64,
+    pub symbol: Symbol,
+    pub exchange: Exchange,
+    pub direction: Direction,
+
+    pub volume: f64,
+    pub frozen: f64,
+    pub price: f64,
+    pub pnl: f64,
+    pub yd_volume: f64,
+}
+
+impl PositionData {
+    pub fn vt_symbol(&self) -> String {
+        format!("{}.{}", self.symbol, self.exchange.to_string())
+    }
+
+    pub fn vt_positionid(&self) -> String {
+        format!(
+            "{}.{}.{:?}",
+            self.gateway_name,
+            self.vt_symbol(),
+            self.direction
+        )
+    }
 }
-//     def __post_init__(self) -> None:
-//         """"""
-//         self.vt_symbol: String, = f"{self.symbol}.{self.exchange.value}"
-//         self.vt_positionid: String, = f"{self.gateway_name}.{self.vt_symbol}.{self.direction.value}"
 
 pub struct AccountData {
     accountid: String,
@@ -204,35 +535,67 @@ pub struct AccountData {
 //         self.available: float = self.balance - self.frozen
 //         self.vt_accountid: String, = f"{self.gateway_name}.{self.accountid}"
 
+#[derive(Debug, Clone)]
 pub struct LogData {
-    msg: String,
-    level: Level,
+    pub msg: String,
+    pub level: Level,
+    ///Engine or strategy which wrote the log
+    pub source: String,
+    pub time: NaiveDateTime,
 }
 
-//     def __post_init__(self) -> None:
-//         """"""
-//         self.time: datetime = datetime.now()
+impl LogData {
+    pub fn new(msg: &str, level: Level, source: &str, time: NaiveDateTime) -> Self {
+        LogData {
+            msg: msg.to_string(),
+            level,
+            source: source.to_string(),
+            time,
+        }
+    }
+}
 
+impl std::fmt::Display for LogData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.time, self.level, self.source, self.msg
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct ContractData {
-    symbol: String,
-    exchange: Exchange,
-    name: String,
-    product: Product,
-    size: f64,
-    pricetick: f64,
+    pub gateway_name: GatewayName,
 
-    min_volume: f64,      // minimum trading volume of the contract
-    stop_supported: bool, // whether server supports stop order
-    net_position: bool,   // whether gateway uses net position volume
-    history_data: bool,   // whether gateway provides bar history data
+    pub symbol: Symbol,
+    pub exchange: Exchange,
+    pub name: String,
+    pub product: Product,
+    pub size: f64,
+    pub pricetick: f64,
+    ///Currency prices and pnl of the contract are in.
+    pub currency: Currency,
+
+    pub min_volume: f64,      // minimum trading volume of the contract
+    pub stop_supported: bool, // whether server supports stop order
+    pub net_position: bool,   // whether gateway uses net position volume
+    pub history_data: bool,   // whether gateway provides bar history data
+
+    pub option_strike: f64,
+    pub option_underlying: String, // vt_symbol of underlying contract
+    pub option_type: Option<OptionType>,
+    pub option_listed: Option<NaiveDateTime>,
+    pub option_expiry: Option<NaiveDateTime>,
+    pub option_portfolio: String,
+    pub option_index: String, // for identifying options with same strike price
+}
 
-    option_strike: f64,
-    option_underlying: String, // vt_symbol of underlying contract
-    option_type: OptionType,
-    option_listed: NaiveDateTime,
-    option_expiry: NaiveDateTime,
-    option_portfolio: String,
-    option_index: String, // for identifying options with same strike price
+impl ContractData {
+    pub fn vt_symbol(&self) -> String {
+        format!("{}.{}", self.symbol, self.exchange)
+    }
 }
 
 //     def __post_init__(self) -> None:
@@ -293,46 +656,45 @@ pub struct ContractData {
 //         """"""
 //         self.vt_symbol: String, = f"{self.symbol}.{self.exchange.value}"
 
-// @dataclass
-// class OrderRequest:
-//     """
-//     Request sending to specific gateway for creating a new order.
-//     """
-
-//     symbol: String,
-//     exchange: Exchange
-//     direction: Direction
-//     type: OrderType
-//     volume: float
-//     price: f64,
-//     offset: Offset = Offset.NONE
-//     reference: String, = ""
+///Request sending to specific gateway for creating a new order.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OrderRequest {
+    pub symbol: Symbol,
+    pub exchange: Exchange,
+    pub direction: Direction,
+    pub type_: OrderType,
+    pub volume: f64,
+    pub price: f64,
+    pub offset: Offset,
+    pub reference: String,
+}
 
-//     def __post_init__(self) -> None:
-//         """"""
-//         self.vt_symbol: String, = f"{self.symbol}.{self.exchange.value}"
+impl OrderRequest {
+    pub fn vt_symbol(&self) -> String {
+        format!("{}.{}", self.symbol, self.exchange)
+    }
 
-//     def create_order_data(self, orderid: String,, gateway_name: String,) -> OrderData:
-//         """
-//         Create order data from request.
-//         """
-//         order: OrderData = OrderData(
-//             symbol=self.symbol,
-//             exchange=self.exchange,
-//             orderid=orderid,
-//             type=self.type,
-//             direction=self.direction,
-//             offset=self.offset,
-//             price=self.price,
-//             volume=self.volume,
-//             reference=self.reference,
-//             gateway_name=gateway_name,
-//         )
-//         return order
+    ///Create order data from request.
+    pub fn create_order_data(&self, orderid: &str, gateway_name: GatewayName) -> OrderData {
+        OrderData {
+            symbol: self.symbol,
+            exchange: self.exchange,
+            orderid: orderid.to_string(),
+            type_: self.type_,
+            direction: self.direction,
+            offset: self.offset,
+            price: self.price,
+            volume: self.volume,
+            reference: self.reference.clone(),
+            gateway_name,
+            ..Default::default()
+        }
+    }
+}
 
 pub struct CancelRequest {
     orderid: String,
-    symbol: String,
+    symbol: Symbol,
     exchange: Exchange,
 }
 impl CancelRequest {
@@ -395,3 +757,62 @@ impl CancelRequest {
 //             gateway_name=gateway_name,
 //         )
 //         return quote
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gateway_names_are_interned() {
+        let configured = String::from("CTP_SIMNOW");
+        let name = GatewayName::from(&configured);
+        assert_eq!(name, GatewayName::new("CTP_SIMNOW"));
+        assert_eq!(name, "CTP_SIMNOW");
+        assert_eq!(GatewayName::default(), "");
+
+        let order = OrderData {
+            gateway_name: name,
+            orderid: "7".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(order.vt_orderid(), "CTP_SIMNOW.7");
+    }
+
+    #[test]
+    fn bars_and_ticks_are_validated() {
+        let bar = |open, high, low, close, volume| {
+            BarData::new(
+                "DB".into(),
+                "IF888".into(),
+                Exchange::CFFEX,
+                NaiveDateTime::default(),
+                Interval::MINUTE,
+                open,
+                high,
+                low,
+                close,
+                volume,
+            )
+        };
+        assert!(bar(100.0, 102.0, 99.0, 101.0, 10.0).is_ok());
+        assert!(bar(100.0, 102.0, 99.0, 103.0, 10.0).is_err());
+        assert!(bar(100.0, 102.0, 100.5, 101.0, 10.0).is_err());
+        assert!(bar(100.0, 102.0, 99.0, 101.0, -1.0).is_err());
+        assert!(bar(100.0, f64::NAN, 99.0, 101.0, 10.0).is_err());
+
+        let mut tick = TickData {
+            last_price: 100.0,
+            bid_price_1: 99.8,
+            bid_volume_1: 5.0,
+            ask_price_1: 100.2,
+            ask_volume_1: 3.0,
+            ..Default::default()
+        };
+        assert!(tick.validate().is_ok());
+        tick.bid_price_1 = 100.4;
+        assert!(matches!(tick.validate(), Err(VnrsError::Data(_))));
+        tick.bid_price_1 = 99.8;
+        tick.ask_volume_1 = -3.0;
+        assert!(tick.validated().is_err());
+    }
+}
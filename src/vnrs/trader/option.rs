@@ -0,0 +1,292 @@
+/*!Option pricing with Black-76 for options on futures and Black-Scholes for options on spot,
+with greeks and implied volatility. Time is in years, rates and volatilities annualized. */
+use chrono::NaiveDateTime;
+
+use super::constant::OptionType;
+use super::object::ContractData;
+
+const DAYS_PER_YEAR: f64 = 365.0;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PricingModel {
+    ///Underlying is a futures price, e.g. commodity and index futures options.
+    #[default]
+    Black76,
+    ///Underlying is a spot price without dividend, e.g. ETF options.
+    BlackScholes,
+}
+
+///Price and sensitivities of one option. Theta is per calendar day and vega per 1% of
+///volatility, delta and gamma are against the underlying price.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Greeks {
+    pub price: f64,
+    pub delta: f64,
+    pub gamma: f64,
+    pub theta: f64,
+    pub vega: f64,
+}
+
+///Years from now to expiry counting calendar days, 0 once expired.
+pub fn time_to_expiry(now: NaiveDateTime, expiry: NaiveDateTime) -> f64 {
+    let seconds = (expiry - now).num_seconds().max(0) as f64;
+    seconds / 86400.0 / DAYS_PER_YEAR
+}
+
+pub fn calculate_price(
+    model: PricingModel,
+    option_type: OptionType,
+    s: f64,
+    k: f64,
+    r: f64,
+    t: f64,
+    v: f64,
+) -> f64 {
+    calculate_greeks(model, option_type, s, k, r, t, v).price
+}
+
+pub fn calculate_greeks(
+    model: PricingModel,
+    option_type: OptionType,
+    s: f64,
+    k: f64,
+    r: f64,
+    t: f64,
+    v: f64,
+) -> Greeks {
+    let discount = (-r * t).exp();
+    //Black-Scholes is Black-76 on the forward s * e^(rt)
+    let (forward, delta_scale) = match model {
+        PricingModel::Black76 => (s, discount),
+        PricingModel::BlackScholes => (s / discount, 1.0),
+    };
+    let sign = match option_type {
+        OptionType::CALL => 1.0,
+        OptionType::PUT => -1.0,
+    };
+
+    if t <= 0.0 || v <= 0.0 {
+        //No time value left, the option is worth its discounted intrinsic value
+        let intrinsic = (sign * (forward - k)).max(0.0);
+        let delta = if intrinsic > 0.0 {
+            sign * delta_scale
+        } else {
+            0.0
+        };
+        return Greeks {
+            price: discount * intrinsic,
+            delta,
+            ..Default::default()
+        };
+    }
+
+    let std = v * t.sqrt();
+    let d1 = ((forward / k).ln() + 0.5 * std * std) / std;
+    let d2 = d1 - std;
+    let price = discount * sign * (forward * norm_cdf(sign * d1) - k * norm_cdf(sign * d2));
+    let delta = sign * delta_scale * norm_cdf(sign * d1);
+    let gamma = delta_scale * norm_pdf(d1) / (s * std);
+    let vega = discount * forward * norm_pdf(d1) * t.sqrt();
+    let decay = -discount * forward * norm_pdf(d1) * v / (2.0 * t.sqrt());
+    let theta = match model {
+        PricingModel::Black76 => decay + r * price,
+        PricingModel::BlackScholes => decay - sign * r * discount * k * norm_cdf(sign * d2),
+    };
+
+    Greeks {
+        price,
+        delta,
+        gamma,
+        theta: theta / DAYS_PER_YEAR,
+        vega: vega / 100.0,
+    }
+}
+
+///Volatility giving the option price, NaN if the price is outside the no-arbitrage bounds.
+pub fn calculate_impv(
+    model: PricingModel,
+    option_type: OptionType,
+    price: f64,
+    s: f64,
+    k: f64,
+    r: f64,
+    t: f64,
+) -> f64 {
+    if t <= 0.0 || !price.is_finite() {
+        return f64::NAN;
+    }
+    let lower = calculate_price(model, option_type, s, k, r, t, 0.0);
+    let upper = match (model, option_type) {
+        (PricingModel::Black76, OptionType::CALL) => s * (-r * t).exp(),
+        (PricingModel::BlackScholes, OptionType::CALL) => s,
+        (_, OptionType::PUT) => k * (-r * t).exp(),
+    };
+    if price < lower || price >= upper {
+        return f64::NAN;
+    }
+
+    //Newton's method kept inside a bisection bracket so it can't run away
+    let (mut low, mut high) = (0.0, 10.0);
+    let mut v = 0.5;
+    for _ in 0..100 {
+        let greeks = calculate_greeks(model, option_type, s, k, r, t, v);
+        let diff = greeks.price - price;
+        if diff.abs() < 1e-10 {
+            break;
+        }
+        if diff > 0.0 {
+            high = v;
+        } else {
+            low = v;
+        }
+        let vega = greeks.vega * 100.0;
+        let next = v - diff / vega;
+        v = if vega > 0.0 && next > low && next < high {
+            next
+        } else {
+            (low + high) / 2.0
+        };
+    }
+    v
+}
+
+///Greeks of an option contract at now, None if the contract isn't an option.
+pub fn contract_greeks(
+    contract: &ContractData,
+    model: PricingModel,
+    underlying_price: f64,
+    r: f64,
+    v: f64,
+    now: NaiveDateTime,
+) -> Option<Greeks> {
+    let option_type = contract.option_type?;
+    let t = time_to_expiry(now, contract.option_expiry?);
+    Some(calculate_greeks(
+        model,
+        option_type,
+        underlying_price,
+        contract.option_strike,
+        r,
+        t,
+        v,
+    ))
+}
+
+pub fn norm_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+///Standard normal distribution function, Hart's double precision approximation.
+pub fn norm_cdf(x: f64) -> f64 {
+    let y = x.abs();
+    let tail = if y > 37.0 {
+        0.0
+    } else if y < 7.07106781186547 {
+        let horner = |coefficients: &[f64]| coefficients.iter().fold(0.0, |acc, c| acc * y + c);
+        let numerator = horner(&[
+            3.52624965998911e-2,
+            0.700383064443688,
+            6.37396220353165,
+            33.912866078383,
+            112.079291497871,
+            221.213596169931,
+            220.206867912376,
+        ]);
+        let denominator = horner(&[
+            8.83883476483184e-2,
+            1.75566716318264,
+            16.064177579207,
+            86.7807322029461,
+            296.564248779674,
+            637.333633378831,
+            793.826512519948,
+            440.413735824752,
+        ]);
+        (-y * y / 2.0).exp() * numerator / denominator
+    } else {
+        let fraction = y + 1.0 / (y + 2.0 / (y + 3.0 / (y + 4.0 / (y + 0.65))));
+        (-y * y / 2.0).exp() / fraction / 2.506628274631
+    };
+    if x > 0.0 {
+        1.0 - tail
+    } else {
+        tail
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() < tolerance
+    }
+
+    #[test]
+    fn prices_match_reference_values() {
+        assert_eq!(norm_cdf(0.0), 0.5);
+        assert!(close(norm_cdf(1.96), 0.9750021048517795, 1e-12));
+        assert!(close(norm_cdf(-3.0), 0.0013498980316301, 1e-12));
+
+        let bs = PricingModel::BlackScholes;
+        let call = calculate_price(bs, OptionType::CALL, 100.0, 100.0, 0.05, 1.0, 0.2);
+        let put = calculate_price(bs, OptionType::PUT, 100.0, 100.0, 0.05, 1.0, 0.2);
+        assert!(close(call, 10.450583572185565, 1e-9));
+        assert!(close(put, 5.573526022256971, 1e-9));
+
+        //Put-call parity on the futures price
+        let b76 = PricingModel::Black76;
+        let call = calculate_price(b76, OptionType::CALL, 4000.0, 3900.0, 0.02, 0.25, 0.25);
+        let put = calculate_price(b76, OptionType::PUT, 4000.0, 3900.0, 0.02, 0.25, 0.25);
+        assert!(close(
+            call - put,
+            (4000.0 - 3900.0) * (-0.02f64 * 0.25).exp(),
+            1e-9
+        ));
+    }
+
+    #[test]
+    fn greeks_match_finite_differences() {
+        for model in [PricingModel::Black76, PricingModel::BlackScholes] {
+            for option_type in [OptionType::CALL, OptionType::PUT] {
+                let price = |s: f64, t: f64, v: f64| {
+                    calculate_price(model, option_type, s, 105.0, 0.03, t, v)
+                };
+                let greeks = calculate_greeks(model, option_type, 100.0, 105.0, 0.03, 0.5, 0.3);
+                let h = 1e-3;
+                let delta = (price(100.0 + h, 0.5, 0.3) - price(100.0 - h, 0.5, 0.3)) / (2.0 * h);
+                let gamma = (price(100.0 + h, 0.5, 0.3) - 2.0 * greeks.price
+                    + price(100.0 - h, 0.5, 0.3))
+                    / (h * h);
+                let vega = (price(100.0, 0.5, 0.3 + h) - price(100.0, 0.5, 0.3 - h)) / (2.0 * h);
+                let theta = (price(100.0, 0.5 - h, 0.3) - price(100.0, 0.5 + h, 0.3)) / (2.0 * h);
+                assert!(close(greeks.delta, delta, 1e-6));
+                assert!(close(greeks.gamma, gamma, 1e-4));
+                assert!(close(greeks.vega * 100.0, vega, 1e-4));
+                assert!(close(greeks.theta * DAYS_PER_YEAR, theta, 1e-4));
+            }
+        }
+    }
+
+    #[test]
+    fn implied_volatility_round_trips() {
+        for model in [PricingModel::Black76, PricingModel::BlackScholes] {
+            for (option_type, k) in [(OptionType::CALL, 90.0), (OptionType::PUT, 120.0)] {
+                let price = calculate_price(model, option_type, 100.0, k, 0.02, 0.1, 0.35);
+                let impv = calculate_impv(model, option_type, price, 100.0, k, 0.02, 0.1);
+                assert!(close(impv, 0.35, 1e-8));
+            }
+        }
+        //Below intrinsic value
+        let impv = calculate_impv(
+            PricingModel::Black76,
+            OptionType::CALL,
+            5.0,
+            110.0,
+            100.0,
+            0.0,
+            0.5,
+        );
+        assert!(impv.is_nan());
+    }
+}
@@ -0,0 +1,339 @@
+/*!Replay recorded ticks and bars from the database through the event engine. Ticks and bars
+are held in separate vectors and merged by datetime as they are published. */
+use chrono::NaiveDateTime;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::clock::SimClock;
+use super::constant::Interval;
+use super::database::get_database;
+use super::event::{EVENT_BAR, EVENT_REPLAY, EVENT_TICK};
+use super::object::{BarData, MixData, TickData};
+use super::utility::extract_vt_symbol;
+use crate::error::{VnrsError, VnrsResult};
+use crate::vnrs::event::{Event, EventEngine};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    ///Keep the original spacing between data timestamps
+    #[default]
+    Realtime,
+    ///Spacing divided by the factor, e.g. 10.0 for 10x
+    Multiple(f64),
+    ///Publish as fast as handlers consume
+    Max,
+}
+
+///Ticks and bars to replay, each sorted by datetime.
+#[derive(Debug, Default, Clone)]
+pub struct ReplayData {
+    pub ticks: Vec<TickData>,
+    pub bars: Vec<BarData>,
+}
+
+impl ReplayData {
+    pub fn len(&self) -> usize {
+        self.ticks.len() + self.bars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn first_datetime(&self) -> Option<NaiveDateTime> {
+        let tick = self.ticks.first().map(|tick| tick.datetime);
+        let bar = self.bars.first().map(|bar| bar.datetime);
+        tick.into_iter().chain(bar).min()
+    }
+
+    ///Pass the ticks and bars in datetime order to on_tick and on_bar, ticks first at equal
+    ///datetimes. pace is called with the datetime of each item before it is passed on, and
+    ///returning false stops the merge. Returns whether all items were passed on.
+    fn merge(
+        self,
+        mut pace: impl FnMut(NaiveDateTime) -> bool,
+        mut on_tick: impl FnMut(TickData),
+        mut on_bar: impl FnMut(BarData),
+    ) -> bool {
+        let mut ticks = self.ticks.into_iter().peekable();
+        let mut bars = self.bars.into_iter().peekable();
+        loop {
+            let (datetime, is_tick) = match (ticks.peek(), bars.peek()) {
+                (Some(tick), Some(bar)) if tick.datetime <= bar.datetime => (tick.datetime, true),
+                (_, Some(bar)) => (bar.datetime, false),
+                (Some(tick), None) => (tick.datetime, true),
+                (None, None) => return true,
+            };
+            if !pace(datetime) {
+                return false;
+            }
+            if is_tick {
+                on_tick(ticks.next().unwrap());
+            } else {
+                on_bar(bars.next().unwrap());
+            }
+        }
+    }
+}
+
+impl From<Vec<MixData>> for ReplayData {
+    fn from(data: Vec<MixData>) -> Self {
+        let mut replay_data = ReplayData::default();
+        for item in data {
+            match item {
+                MixData::TickData(tick) => replay_data.ticks.push(tick),
+                MixData::BarData(bar) => replay_data.bars.push(bar),
+            }
+        }
+        replay_data
+    }
+}
+
+///Publish recorded data as EVENT_TICK/EVENT_BAR events, the same way a gateway does when live.
+///An EVENT_REPLAY event with data "finished" or "stopped" is put when the replay ends.
+pub struct ReplayEngine {
+    event_engine: Arc<EventEngine>,
+    speed: ReplaySpeed,
+    clock: Option<Arc<SimClock>>,
+    active: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ReplayEngine {
+    pub fn new(event_engine: Arc<EventEngine>) -> Self {
+        ReplayEngine {
+            event_engine,
+            speed: ReplaySpeed::default(),
+            clock: None,
+            active: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+
+    ///Speed of the next replay, an error for a factor that isn't a positive finite number.
+    pub fn set_speed(&mut self, speed: ReplaySpeed) -> VnrsResult<()> {
+        if let ReplaySpeed::Multiple(factor) = speed {
+            if !factor.is_finite() || factor <= 0.0 {
+                return Err(VnrsError::Setting(format!(
+                    "invalid replay speed factor {}",
+                    factor
+                )));
+            }
+        }
+        self.speed = speed;
+        Ok(())
+    }
+
+    ///Clock advanced to the datetime of each item before it is published.
+    pub fn set_clock(&mut self, clock: Arc<SimClock>) {
+        self.clock = Some(clock);
+    }
+
+    pub fn replay_ticks(
+        &mut self,
+        vt_symbol: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<()> {
+        let (symbol, exchange) = extract_vt_symbol(vt_symbol)?;
+        let ticks = get_database()?.load_tick_data(&symbol, exchange, start, end)?;
+        self.replay_data(ReplayData {
+            ticks,
+            bars: Vec::new(),
+        });
+        Ok(())
+    }
+
+    pub fn replay_bars(
+        &mut self,
+        vt_symbol: &str,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<()> {
+        let (symbol, exchange) = extract_vt_symbol(vt_symbol)?;
+        let bars = get_database()?.load_bar_data(&symbol, exchange, interval, start, end)?;
+        self.replay_data(ReplayData {
+            ticks: Vec::new(),
+            bars,
+        });
+        Ok(())
+    }
+
+    ///Replay data already loaded, sorted by datetime, on a background thread.
+    pub fn replay(&mut self, data: Vec<MixData>) {
+        self.replay_data(data.into());
+    }
+
+    ///Replay ticks and bars already loaded on a background thread.
+    pub fn replay_data(&mut self, data: ReplayData) {
+        self.stop();
+        self.active.store(true, Ordering::SeqCst);
+
+        let event_engine = self.event_engine.clone();
+        let active = self.active.clone();
+        let speed = self.speed;
+        let clock = self.clock.clone();
+        self.thread = Some(thread::spawn(move || {
+            let start_time = Instant::now();
+            let first_datetime = data.first_datetime();
+
+            let pace = |datetime: NaiveDateTime| {
+                if !active.load(Ordering::SeqCst) {
+                    return false;
+                }
+
+                if let Some(first_datetime) = first_datetime {
+                    let elapsed = (datetime - first_datetime).to_std().unwrap_or_default();
+                    let target = match speed {
+                        ReplaySpeed::Realtime => Some(elapsed),
+                        ReplaySpeed::Multiple(factor) => Some(elapsed.div_f64(factor)),
+                        ReplaySpeed::Max => None,
+                    };
+                    if let Some(wait) = target.and_then(|t| t.checked_sub(start_time.elapsed())) {
+                        sleep_while_active(wait, &active);
+                    }
+                }
+
+                if let Some(clock) = &clock {
+                    clock.advance_to(datetime);
+                }
+                true
+            };
+            let finished = data.merge(
+                pace,
+                |tick| put_tick(&event_engine, tick),
+                |bar| put_bar(&event_engine, bar),
+            );
+
+            if !finished {
+                event_engine.put(Event::new(EVENT_REPLAY, "stopped"));
+                return;
+            }
+            active.store(false, Ordering::SeqCst);
+            event_engine.put(Event::new(EVENT_REPLAY, "finished"));
+        }));
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    ///Stop the running replay and wait for its thread to exit.
+    pub fn stop(&mut self) {
+        self.active.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ReplayEngine {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+///Sleep in short steps so that stop is not blocked by a long gap in the data.
+fn sleep_while_active(duration: Duration, active: &AtomicBool) {
+    let step = Duration::from_millis(100);
+    let deadline = Instant::now() + duration;
+    while active.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        thread::sleep(step.min(deadline - now));
+    }
+}
+
+fn put_tick(event_engine: &EventEngine, tick: TickData) {
+    let vt_symbol = tick.vt_symbol();
+    let event = Event::new(EVENT_TICK, tick);
+    let symbol_event = Event {
+        type_: format!("{}{}", EVENT_TICK, vt_symbol),
+        data: event.data.clone(),
+    };
+    event_engine.put(event);
+    event_engine.put(symbol_event);
+}
+
+fn put_bar(event_engine: &EventEngine, bar: BarData) {
+    let vt_symbol = bar.vt_symbol();
+    let event = Event::new(EVENT_BAR, bar);
+    let symbol_event = Event {
+        type_: format!("{}{}", EVENT_BAR, vt_symbol),
+        data: event.data.clone(),
+    };
+    event_engine.put(event);
+    event_engine.put(symbol_event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+
+    #[test]
+    fn ticks_and_bars_merged_by_datetime() {
+        let at = |minute: u32| {
+            NaiveDateTime::parse_from_str(&format!("2024-09-30 09:{:02}", minute), "%Y-%m-%d %H:%M")
+                .unwrap()
+        };
+        let tick = |minute| TickData {
+            datetime: at(minute),
+            ..Default::default()
+        };
+        let bar = |minute| BarData {
+            datetime: at(minute),
+            ..Default::default()
+        };
+        let data = ReplayData::from(vec![
+            MixData::TickData(tick(1)),
+            MixData::BarData(bar(0)),
+            MixData::TickData(tick(2)),
+            MixData::BarData(bar(2)),
+            MixData::TickData(tick(3)),
+        ]);
+        assert_eq!((data.ticks.len(), data.bars.len()), (3, 2));
+        assert_eq!(data.first_datetime(), Some(at(0)));
+
+        let mut order = Vec::new();
+        let sequence = RefCell::new(Vec::new());
+        let finished = data.clone().merge(
+            |datetime| {
+                order.push(datetime);
+                true
+            },
+            |tick| sequence.borrow_mut().push(("tick", tick.datetime)),
+            |bar| sequence.borrow_mut().push(("bar", bar.datetime)),
+        );
+        assert!(finished);
+        assert_eq!(order, vec![at(0), at(1), at(2), at(2), at(3)]);
+        assert_eq!(sequence.borrow()[2], ("tick", at(2)));
+        assert_eq!(sequence.borrow()[3], ("bar", at(2)));
+
+        // Pace returning false stops before the item is passed on
+        let passed = Cell::new(0);
+        let finished = data.merge(
+            |datetime| datetime < at(2),
+            |_| passed.set(passed.get() + 1),
+            |_| passed.set(passed.get() + 1),
+        );
+        assert!(!finished);
+        assert_eq!(passed.get(), 2);
+    }
+
+    #[test]
+    fn speed_factor_validated() {
+        let mut engine = ReplayEngine::new(Arc::new(EventEngine::default()));
+        for factor in [0.0, -2.0, f64::NAN, f64::INFINITY] {
+            assert!(engine.set_speed(ReplaySpeed::Multiple(factor)).is_err());
+        }
+        assert_eq!(engine.speed, ReplaySpeed::Realtime);
+        engine.set_speed(ReplaySpeed::Multiple(0.5)).unwrap();
+        assert_eq!(engine.speed, ReplaySpeed::Multiple(0.5));
+    }
+}
@@ -0,0 +1,172 @@
+/*!Encryption at rest of secret settings, e.g. gateway API keys and email passwords, enabled by
+the `secrets` feature. An encrypted value is "enc:" and the base64 of a random salt, a random
+nonce and the ChaCha20-Poly1305 ciphertext of the value, under a key derived from the salt and
+the passphrase with Argon2id. The passphrase is VNRS_SECRET_KEY, or else the one stored in the
+OS keychain by `vnrs secret key`. */
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::error::{VnrsError, VnrsResult};
+
+pub const SECRET_KEY_ENV: &str = "VNRS_SECRET_KEY";
+
+pub const SECRET_PREFIX: &str = "enc:";
+
+///Service and user of the passphrase in the OS keychain.
+pub const KEYCHAIN_SERVICE: &str = "vnrs";
+pub const KEYCHAIN_USER: &str = "secret_key";
+
+const SALT_LEN: usize = 16;
+
+const NONCE_LEN: usize = 12;
+
+fn keychain_entry() -> VnrsResult<keyring::Entry> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_USER)
+        .map_err(|e| VnrsError::Setting(format!("no keychain: {}", e)))
+}
+
+///Passphrase of the keys encrypting secret settings, one per salt.
+pub struct SecretKey {
+    passphrase: String,
+}
+
+impl SecretKey {
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        SecretKey {
+            passphrase: passphrase.to_string(),
+        }
+    }
+
+    ///Key of the passphrase in VNRS_SECRET_KEY, None if it isn't set.
+    pub fn from_env() -> Option<Self> {
+        let passphrase = std::env::var(SECRET_KEY_ENV).ok()?;
+        (!passphrase.is_empty()).then(|| Self::from_passphrase(&passphrase))
+    }
+
+    ///Key of the passphrase in the OS keychain, None if none is stored.
+    pub fn from_keychain() -> Option<Self> {
+        let passphrase = keychain_entry().ok()?.get_password().ok()?;
+        (!passphrase.is_empty()).then(|| Self::from_passphrase(&passphrase))
+    }
+
+    ///Key of VNRS_SECRET_KEY, or else of the OS keychain.
+    pub fn load() -> Option<Self> {
+        Self::from_env().or_else(Self::from_keychain)
+    }
+
+    ///Store passphrase in the OS keychain, for load to find without VNRS_SECRET_KEY.
+    pub fn store_in_keychain(passphrase: &str) -> VnrsResult<()> {
+        if passphrase.is_empty() {
+            return Err(VnrsError::Setting("empty passphrase".to_string()));
+        }
+        keychain_entry()?
+            .set_password(passphrase)
+            .map_err(|e| VnrsError::Setting(format!("keychain not updated: {}", e)))
+    }
+
+    fn cipher(&self, salt: &[u8]) -> VnrsResult<ChaCha20Poly1305> {
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| VnrsError::Setting(format!("no key derived: {}", e)))?;
+        Ok(ChaCha20Poly1305::new(&key))
+    }
+
+    ///plaintext as an encrypted setting value.
+    pub fn encrypt(&self, plaintext: &str) -> VnrsResult<String> {
+        let mut sealed = vec![0; SALT_LEN + NONCE_LEN];
+        getrandom::getrandom(&mut sealed)
+            .map_err(|e| VnrsError::Setting(format!("no random salt and nonce: {}", e)))?;
+        let (salt, nonce) = sealed.split_at(SALT_LEN);
+        let ciphertext = self
+            .cipher(salt)?
+            .encrypt(Nonce::from_slice(nonce), plaintext.as_bytes())
+            .map_err(|_| VnrsError::Setting("setting not encrypted".to_string()))?;
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", SECRET_PREFIX, STANDARD.encode(sealed)))
+    }
+
+    ///Plaintext of an encrypted setting value, fails if it was encrypted with another key or
+    ///altered.
+    pub fn decrypt(&self, value: &str) -> VnrsResult<String> {
+        let invalid = || VnrsError::Setting("invalid encrypted setting".to_string());
+        let encoded = value.strip_prefix(SECRET_PREFIX).ok_or_else(invalid)?;
+        let sealed = STANDARD.decode(encoded).map_err(|_| invalid())?;
+        if sealed.len() < SALT_LEN + NONCE_LEN {
+            return Err(invalid());
+        }
+        let (salt, rest) = sealed.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher(salt)?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                VnrsError::Setting("encrypted setting of another key or altered".to_string())
+            })?;
+        String::from_utf8(plaintext).map_err(|_| invalid())
+    }
+}
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(SECRET_PREFIX)
+}
+
+///value decrypted if it is encrypted, as it is otherwise. An encrypted value that can't be
+///decrypted, e.g. without a key, is also left as it is with the error logged.
+pub fn reveal(name: &str, value: String, key: Option<&SecretKey>) -> String {
+    if !is_encrypted(&value) {
+        return value;
+    }
+    let Some(key) = key else {
+        log::error!(
+            "配置项{}已加密，但未设置{}或系统钥匙串密钥",
+            name,
+            SECRET_KEY_ENV
+        );
+        return value;
+    };
+    match key.decrypt(&value) {
+        Ok(plaintext) => plaintext,
+        Err(e) => {
+            log::error!("配置项{}解密失败：{}", name, e);
+            value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let key = SecretKey::from_passphrase("0123456789abcdef");
+        let sealed = key.encrypt("email password").unwrap();
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.contains("password"));
+        // A fresh nonce every time
+        assert_ne!(key.encrypt("email password").unwrap(), sealed);
+        assert_eq!(key.decrypt(&sealed).unwrap(), "email password");
+        assert_eq!(
+            reveal("email.password", sealed.clone(), Some(&key)),
+            "email password"
+        );
+        assert_eq!(reveal("email.sender", "a@b.c".to_string(), None), "a@b.c");
+
+        let other = SecretKey::from_passphrase("fedcba9876543210");
+        assert!(other.decrypt(&sealed).is_err());
+        assert_eq!(
+            reveal("email.password", sealed.clone(), Some(&other)),
+            sealed
+        );
+
+        let mut altered = STANDARD.decode(&sealed[SECRET_PREFIX.len()..]).unwrap();
+        altered[SALT_LEN + NONCE_LEN] ^= 1;
+        let altered = format!("{}{}", SECRET_PREFIX, STANDARD.encode(altered));
+        assert!(key.decrypt(&altered).is_err());
+        assert!(key.decrypt("enc:AAAA").is_err());
+    }
+}
@@ -1,38 +1,334 @@
+/*!Global settings, the defaults below updated from vt_setting.json in the trader dir. A
+SettingWatcher reloads them when the file changes, so long running processes pick up e.g. new
+email receivers without a restart.
+
+The file may also hold named profiles as "profile.<name>" objects, e.g. "profile.research"
+with a memory database and "profile.prod" with mysql, whose values replace the others when
+the profile is selected by the VNRS_PROFILE environment variable or set_profile. */
 use std::{
     collections::HashMap,
-    sync::{Mutex, OnceLock},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock, Weak},
 };
 
-pub static SETTINGS: OnceLock<HashMap<&'static str, String>> = OnceLock::new();
-
-pub fn get_settings() -> &'static HashMap<&'static str, String> {
-    SETTINGS.get_or_init(|| {
-        [
-            ("font.family", "微软雅黑".to_string()),
-            ("font.size", 12.to_string()),
-            ("log.active", "True".to_string()),
-            ("log.level", "CRITICAL".to_string()),
-            ("log.console", "True".to_string()),
-            ("log.file", "True".to_string()),
-            ("email.server", "smtp.qq.com".to_string()),
-            ("email.port", "465".to_string()),
-            ("email.username", "".to_string()),
-            ("email.password", "".to_string()),
-            ("email.sender", "".to_string()),
-            ("email.receiver", "".to_string()),
-            ("datafeed.name", "".to_string()),
-            ("datafeed.username", "".to_string()),
-            ("datafeed.password", "".to_string()),
-            ("database.timezone", "LOCAL".to_string()),
-            ("database.name", "sqlite".to_string()),
-            ("database.database", "database.db".to_string()),
-            ("database.host", "".to_string()),
-            ("database.port", 0.to_string()),
-            ("database.user", "".to_string()),
-            ("database.password", "".to_string()),
-        ]
-        .iter()
-        .cloned()
-        .collect::<HashMap<&'static str, String>>()
-    })
+use log::LevelFilter;
+use serde_json::Value;
+
+use super::event::{EVENT_SETTING, EVENT_TIMER};
+#[cfg(feature = "secrets")]
+use super::secret::{is_encrypted, reveal, SecretKey};
+use super::utility::{get_file_path, save_json};
+use crate::error::{VnrsError, VnrsResult};
+use crate::vnrs::event::{Event, EventEngine, HandlerType};
+
+pub type Settings = HashMap<&'static str, String>;
+
+pub const SETTING_FILENAME: &str = "vt_setting.json";
+
+///Environment variable naming the profile used unless set_profile was called.
+pub const PROFILE_ENV: &str = "VNRS_PROFILE";
+
+static SETTINGS: RwLock<Option<Arc<Settings>>> = RwLock::new(None);
+
+static PROFILE: RwLock<Option<String>> = RwLock::new(None);
+
+fn default_settings() -> Settings {
+    [
+        ("font.family", "微软雅黑".to_string()),
+        ("font.size", 12.to_string()),
+        ("log.active", "True".to_string()),
+        ("log.level", "CRITICAL".to_string()),
+        ("log.console", "True".to_string()),
+        ("log.file", "True".to_string()),
+        ("email.server", "smtp.qq.com".to_string()),
+        ("email.port", "465".to_string()),
+        ("email.username", "".to_string()),
+        ("email.password", "".to_string()),
+        ("email.sender", "".to_string()),
+        ("email.receiver", "".to_string()),
+        ("datafeed.name", "".to_string()),
+        ("datafeed.username", "".to_string()),
+        ("datafeed.password", "".to_string()),
+        ("datafeed.save", "False".to_string()),
+        ("database.timezone", "LOCAL".to_string()),
+        ("database.name", "sqlite".to_string()),
+        ("database.database", "database.db".to_string()),
+        ("database.host", "".to_string()),
+        ("database.port", 0.to_string()),
+        ("database.user", "".to_string()),
+        ("database.password", "".to_string()),
+        // SQLite pragmas applied on connect, cache_size in pages or -KiB
+        ("database.journal_mode", "WAL".to_string()),
+        ("database.synchronous", "NORMAL".to_string()),
+        ("database.cache_size", "-64000".to_string()),
+        ("database.mmap_size", "268435456".to_string()),
+        // Strategy dylibs the servers load by name, relative to the trader dir
+        ("strategy.dir", "strategies".to_string()),
+        // Shared by optimize-worker and the DistributedOptimizers it serves, required to serve
+        ("optimize.token", "".to_string()),
+        // Local copy of plotly.js the dashboard serves to browsers without internet access,
+        // relative to the trader dir, empty for the CDN
+        ("web.plotly_js", "".to_string()),
+    ]
+    .into_iter()
+    .collect()
+}
+
+///The defaults updated from the settings file content, JSON as written by vnpy, and then
+///from the profile of that name if given. Booleans become "True" or "False", and keys that
+///aren't settings are ignored with a warning. Encrypted values are decrypted with the key in
+///VNRS_SECRET_KEY or the OS keychain if the `secrets` feature is enabled.
+pub fn parse_settings(content: &str, profile: Option<&str>) -> Settings {
+    let mut settings = default_settings();
+    let Ok(Value::Object(mut values)) = serde_json::from_str::<Value>(content) else {
+        return settings;
+    };
+    let profile_values = profile.and_then(|name| {
+        let values = values.remove(&format!("profile.{}", name));
+        if values.is_none() {
+            log::warn!("配置方案不存在：{}", name);
+        }
+        values
+    });
+    values.retain(|key, _| !key.starts_with("profile."));
+    update_settings(&mut settings, values);
+    if let Some(Value::Object(values)) = profile_values {
+        update_settings(&mut settings, values);
+    }
+    #[cfg(feature = "secrets")]
+    {
+        // The keychain is only asked when a value needs it
+        let key = settings
+            .values()
+            .any(|value| is_encrypted(value))
+            .then(SecretKey::load)
+            .flatten();
+        for (name, value) in settings.iter_mut() {
+            *value = reveal(name, std::mem::take(value), key.as_ref());
+        }
+    }
+    settings
+}
+
+fn update_settings(settings: &mut Settings, values: serde_json::Map<String, Value>) {
+    for (key, value) in values {
+        let Some(setting) = settings.get_mut(key.as_str()) else {
+            log::warn!("未知配置项：{}", key);
+            continue;
+        };
+        *setting = match value {
+            Value::String(text) => text,
+            Value::Bool(true) => "True".to_string(),
+            Value::Bool(false) => "False".to_string(),
+            other => other.to_string(),
+        };
+    }
+}
+
+///Settings of the active profile in the settings file of the trader dir.
+fn read_settings() -> Settings {
+    let content = fs::read_to_string(get_file_path(SETTING_FILENAME)).unwrap_or_default();
+    parse_settings(&content, get_profile().as_deref())
+}
+
+///Write value at key into the settings file, in the active profile if there is one, and
+///reload the settings. Fails for keys that aren't settings or a settings file that isn't
+///valid JSON, which is left as it is.
+pub fn save_setting(key: &str, value: &str) -> VnrsResult<()> {
+    if !default_settings().contains_key(key) {
+        return Err(VnrsError::Setting(format!("unknown setting {}", key)));
+    }
+    let path = get_file_path(SETTING_FILENAME);
+    let mut content = match fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)?,
+        Err(_) => Value::Object(Default::default()),
+    };
+    let Value::Object(values) = &mut content else {
+        return Err(VnrsError::Setting(format!(
+            "{} is not a JSON object",
+            SETTING_FILENAME
+        )));
+    };
+    let target = match get_profile() {
+        Some(profile) => values
+            .entry(format!("profile.{}", profile))
+            .or_insert_with(|| Value::Object(Default::default())),
+        None => &mut content,
+    };
+    target[key] = Value::String(value.to_string());
+    save_json(SETTING_FILENAME, &content)?;
+    set_settings(read_settings());
+    Ok(())
+}
+
+///Name of the active profile, given to set_profile or else by VNRS_PROFILE, None for the
+///settings outside of profiles.
+pub fn get_profile() -> Option<String> {
+    if let Some(profile) = PROFILE.read().unwrap().clone() {
+        return Some(profile);
+    }
+    std::env::var(PROFILE_ENV)
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+///Switch to the named profile, e.g. from a --profile flag, and reload the settings for it.
+///Returns the keys whose values changed.
+pub fn set_profile(name: &str) -> Vec<&'static str> {
+    *PROFILE.write().unwrap() = Some(name.to_string());
+    set_settings(read_settings())
+}
+
+///Current settings, read from the settings file on first use. Hold on to them only as long as
+///a change shouldn't be seen.
+pub fn get_settings() -> Arc<Settings> {
+    if let Some(settings) = SETTINGS.read().unwrap().clone() {
+        return settings;
+    }
+    SETTINGS
+        .write()
+        .unwrap()
+        .get_or_insert_with(|| Arc::new(read_settings()))
+        .clone()
+}
+
+///Replace the settings, returns the keys whose values changed in order.
+pub fn set_settings(settings: Settings) -> Vec<&'static str> {
+    let mut current = SETTINGS.write().unwrap();
+    let mut changed: Vec<&'static str> = match current.as_deref() {
+        Some(old) => settings
+            .iter()
+            .filter(|(key, value)| old.get(*key) != Some(*value))
+            .map(|(key, _)| *key)
+            .collect(),
+        None => settings.keys().copied().collect(),
+    };
+    changed.sort_unstable();
+    *current = Some(Arc::new(settings));
+    changed
+}
+
+///Filter of the log crate for a log.level setting, by name as in vnpy or by number.
+pub fn level_filter(level: &str) -> Option<LevelFilter> {
+    match level.to_uppercase().as_str() {
+        "CRITICAL" | "FATAL" | "ERROR" | "50" | "40" => Some(LevelFilter::Error),
+        "WARNING" | "WARN" | "30" => Some(LevelFilter::Warn),
+        "INFO" | "20" => Some(LevelFilter::Info),
+        "DEBUG" | "10" => Some(LevelFilter::Debug),
+        "NOTSET" | "0" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+///Reloads the settings when the content of the settings file changes.
+pub struct SettingWatcher {
+    path: PathBuf,
+    content: Option<String>,
+}
+
+impl SettingWatcher {
+    ///Watch the settings file in the trader dir.
+    pub fn new() -> Self {
+        Self::with_path(get_file_path(SETTING_FILENAME))
+    }
+
+    ///Watch the settings file at path, taking its current content as already loaded.
+    pub fn with_path(path: PathBuf) -> Self {
+        let content = fs::read_to_string(&path).ok();
+        SettingWatcher { path, content }
+    }
+
+    ///Reload the settings if the file changed since the last check, returns the keys whose
+    ///values changed. A removed file leaves the settings as they are.
+    pub fn check(&mut self) -> Vec<&'static str> {
+        let Ok(content) = fs::read_to_string(&self.path) else {
+            return Vec::new();
+        };
+        if self.content.as_ref() == Some(&content) {
+            return Vec::new();
+        }
+        let changed = set_settings(parse_settings(&content, get_profile().as_deref()));
+        self.content = Some(content);
+        if changed.contains(&"log.level") {
+            if let Some(filter) = level_filter(&get_settings()["log.level"]) {
+                log::set_max_level(filter);
+            }
+        }
+        changed
+    }
+
+    ///Check the file on every timer event, putting an EVENT_SETTING event after each reload
+    ///that changed settings. Returns the handler for unregistering.
+    pub fn register(watcher: Arc<Mutex<Self>>, event_engine: &Arc<EventEngine>) -> HandlerType {
+        let engine: Weak<EventEngine> = Arc::downgrade(event_engine);
+        let handler: HandlerType = Arc::new(move |_: &Event| {
+            let Some(event_engine) = engine.upgrade() else {
+                return;
+            };
+            let changed = watcher.lock().unwrap().check();
+            if !changed.is_empty() {
+                log::info!("配置已重新加载：{}", changed.join(","));
+                event_engine.put(Event::new(EVENT_SETTING, changed));
+            }
+        });
+        event_engine.register(EVENT_TIMER, handler.clone());
+        handler
+    }
+}
+
+impl Default for SettingWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_on_change() {
+        let settings = parse_settings(
+            r#"{"email.port": 587, "datafeed.save": true, "foo": 1}"#,
+            None,
+        );
+        assert_eq!(settings["email.port"], "587");
+        assert_eq!(settings["datafeed.save"], "True");
+        assert_eq!(settings.len(), default_settings().len());
+        assert_eq!(level_filter("warning"), Some(LevelFilter::Warn));
+
+        let path = std::env::temp_dir().join(format!("vt_setting_{}.json", std::process::id()));
+        fs::write(&path, r#"{"email.receiver": "a@example.com"}"#).unwrap();
+        let mut watcher = SettingWatcher::with_path(path.clone());
+        assert!(watcher.check().is_empty());
+
+        fs::write(&path, r#"{"email.receiver": "b@example.com"}"#).unwrap();
+        assert_eq!(watcher.check(), vec!["email.receiver"]);
+        assert_eq!(get_settings()["email.receiver"], "b@example.com");
+        assert!(watcher.check().is_empty());
+
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.check().is_empty());
+        assert_eq!(get_settings()["email.receiver"], "b@example.com");
+    }
+
+    #[test]
+    fn profiles_replace_settings() {
+        let content = r#"{
+            "database.name": "sqlite",
+            "log.level": "INFO",
+            "profile.research": {"database.name": "memory", "datafeed.save": false},
+            "profile.prod": {"database.name": "mysql", "log.level": "WARNING"}
+        }"#;
+        let base = parse_settings(content, None);
+        assert_eq!(base["database.name"], "sqlite");
+        let research = parse_settings(content, Some("research"));
+        assert_eq!(research["database.name"], "memory");
+        assert_eq!(research["log.level"], "INFO");
+        let prod = parse_settings(content, Some("prod"));
+        assert_eq!(prod["database.name"], "mysql");
+        assert_eq!(prod["log.level"], "WARNING");
+        assert_eq!(parse_settings(content, Some("missing")), base);
+    }
 }
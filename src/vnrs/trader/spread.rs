@@ -0,0 +1,238 @@
+/*!Synthetic bars of spreads and bases, arithmetic combinations of the bars of stored symbols
+like IF - IH, futures minus their index or a perpetual swap minus spot. They are saved under a
+LOCAL symbol, so research and spread backtests load them like the bars of any other symbol, and
+brought up to date by synthesizing only the bars after the last one saved. */
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use super::constant::{Exchange, Interval};
+use super::database::BaseDatabase;
+use super::object::BarData;
+use super::timeseries::TimeSeries;
+use super::utility::extract_vt_symbol;
+use crate::error::{VnrsError, VnrsResult};
+
+///A stored symbol and its ratio in a spread, negative for the legs subtracted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpreadLeg {
+    pub vt_symbol: String,
+    pub ratio: f64,
+}
+
+///Bars of name.LOCAL whose prices are the sum of the leg prices times their ratios, at the
+///datetimes all legs have a bar at. Open and close are exact, high and low those of open and
+///close since the legs' extremes needn't happen together. Volume is the spread units the
+///thinnest leg traded. Spread prices may be negative.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpreadDefinition {
+    pub name: String,
+    pub interval: Interval,
+    pub legs: Vec<SpreadLeg>,
+}
+
+impl SpreadDefinition {
+    pub fn new(name: &str, interval: Interval) -> Self {
+        SpreadDefinition {
+            name: name.to_string(),
+            interval,
+            legs: Vec::new(),
+        }
+    }
+
+    pub fn with_leg(mut self, vt_symbol: &str, ratio: f64) -> Self {
+        self.legs.push(SpreadLeg {
+            vt_symbol: vt_symbol.to_string(),
+            ratio,
+        });
+        self
+    }
+
+    ///Difference of two symbols, e.g. IF - IH or a perpetual swap minus spot.
+    pub fn difference(name: &str, interval: Interval, long: &str, short: &str) -> Self {
+        Self::new(name, interval)
+            .with_leg(long, 1.0)
+            .with_leg(short, -1.0)
+    }
+
+    pub fn vt_symbol(&self) -> String {
+        format!("{}.{}", self.name, Exchange::LOCAL)
+    }
+
+    ///Spread bars of the bars of each leg, in the order of legs.
+    pub fn synthesize(&self, leg_bars: &[Vec<BarData>]) -> Vec<BarData> {
+        let Some(first) = leg_bars.first() else {
+            return Vec::new();
+        };
+        let series: Vec<TimeSeries<&BarData>> = leg_bars
+            .iter()
+            .map(|bars| bars.iter().map(|bar| (bar.datetime, bar)).collect())
+            .collect();
+        first
+            .iter()
+            .filter_map(|bar| {
+                let bars = series
+                    .iter()
+                    .map(|series| series.get(bar.datetime).copied())
+                    .collect::<Option<Vec<&BarData>>>()?;
+                Some(self.combine(bar.datetime, &bars))
+            })
+            .collect()
+    }
+
+    fn combine(&self, datetime: NaiveDateTime, bars: &[&BarData]) -> BarData {
+        let price = |price: fn(&BarData) -> f64| -> f64 {
+            self.legs
+                .iter()
+                .zip(bars)
+                .map(|(leg, bar)| leg.ratio * price(bar))
+                .sum()
+        };
+        let open_price = price(|bar| bar.open_price);
+        let close_price = price(|bar| bar.close_price);
+        let volume = self
+            .legs
+            .iter()
+            .zip(bars)
+            .filter(|(leg, _)| leg.ratio != 0.0)
+            .map(|(leg, bar)| bar.volume / leg.ratio.abs())
+            .fold(f64::INFINITY, f64::min);
+        BarData {
+            gateway_name: "SPREAD".into(),
+            symbol: self.name.as_str().into(),
+            exchange: Exchange::LOCAL,
+            datetime,
+            interval: self.interval,
+            volume: if volume.is_finite() { volume } else { 0.0 },
+            open_price,
+            high_price: open_price.max(close_price),
+            low_price: open_price.min(close_price),
+            close_price,
+            ..Default::default()
+        }
+    }
+
+    ///Bars of each leg from start to end in database.
+    pub fn load_legs(
+        &self,
+        database: &dyn BaseDatabase,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<Vec<Vec<BarData>>> {
+        if self.legs.is_empty() {
+            return Err(VnrsError::Setting(format!(
+                "spread {} has no legs",
+                self.name
+            )));
+        }
+        self.legs
+            .iter()
+            .map(|leg| {
+                let (symbol, exchange) = extract_vt_symbol(&leg.vt_symbol)?;
+                database.load_bar_data(&symbol, exchange, self.interval, start, end)
+            })
+            .collect()
+    }
+
+    ///Synthesize the spread from start to end from the legs in database and save it there,
+    ///replacing bars saved before. Returns the bars saved.
+    pub fn build(
+        &self,
+        database: &dyn BaseDatabase,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<usize> {
+        let bars = self.synthesize(&self.load_legs(database, start, end)?);
+        let count = bars.len();
+        if count > 0 {
+            database.save_bar_data(bars)?;
+        }
+        Ok(count)
+    }
+
+    ///Synthesize and save the bars after the last one saved up to end, or from start when
+    ///none is saved yet, e.g. after every download of the legs. Returns the bars saved.
+    pub fn update(
+        &self,
+        database: &dyn BaseDatabase,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<usize> {
+        let saved =
+            database.load_bar_data(&self.name, Exchange::LOCAL, self.interval, start, end)?;
+        let bars: Vec<BarData> = match saved.last() {
+            Some(last) => self
+                .synthesize(&self.load_legs(database, last.datetime, end)?)
+                .into_iter()
+                .filter(|bar| bar.datetime > last.datetime)
+                .collect(),
+            None => self.synthesize(&self.load_legs(database, start, end)?),
+        };
+        let count = bars.len();
+        if count > 0 {
+            database.save_bar_data(bars)?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::database::MemoryDatabase;
+    use crate::vnrs::trader::synthetic::SyntheticBars;
+
+    #[test]
+    fn spread_saved_and_updated() {
+        let legs = |count| {
+            let leg = |symbol: &str, start_price, seed| {
+                SyntheticBars {
+                    symbol: symbol.to_string(),
+                    exchange: Exchange::CFFEX,
+                    count,
+                    start_price,
+                    seed,
+                    ..Default::default()
+                }
+                .generate()
+            };
+            (leg("IF2412", 3900.0, 1), leg("IH2412", 2700.0, 2))
+        };
+        let (if_bars, mut ih_bars) = legs(20);
+        // A bar missing in one leg has no spread bar
+        ih_bars.remove(3);
+        let database = MemoryDatabase::new();
+        database.save_bar_data(if_bars.clone());
+        database.save_bar_data(ih_bars.clone());
+
+        let spread =
+            SpreadDefinition::difference("IF-IH", Interval::MINUTE, "IF2412.CFFEX", "IH2412.CFFEX");
+        assert_eq!(spread.vt_symbol(), "IF-IH.LOCAL");
+        let start = if_bars[0].datetime;
+        let end = if_bars[19].datetime;
+        assert_eq!(spread.build(&database, start, end).unwrap(), 19);
+
+        let bars = database
+            .load_bar_data("IF-IH", Exchange::LOCAL, Interval::MINUTE, start, end)
+            .unwrap();
+        assert_eq!(bars.len(), 19);
+        assert!(bars.iter().all(|bar| bar.datetime != if_bars[3].datetime));
+        let bar = &bars[5];
+        assert_eq!(
+            bar.close_price,
+            if_bars[6].close_price - ih_bars[5].close_price
+        );
+        assert_eq!(bar.volume, if_bars[6].volume.min(ih_bars[5].volume));
+
+        // New bars of the legs, only the ones after the last spread bar are synthesized
+        let (if_bars, ih_bars) = legs(30);
+        database.save_bar_data(if_bars.clone());
+        database.save_bar_data(ih_bars);
+        let end = if_bars[29].datetime;
+        assert_eq!(spread.update(&database, start, end).unwrap(), 10);
+        assert_eq!(spread.update(&database, start, end).unwrap(), 0);
+        let bars = database
+            .load_bar_data("IF-IH", Exchange::LOCAL, Interval::MINUTE, start, end)
+            .unwrap();
+        assert_eq!(bars.len(), 29);
+    }
+}
@@ -0,0 +1,259 @@
+/*!Bars as standard Rust iterators and streams, to compose with itertools or tokio-stream
+instead of the callbacks of a strategy. History is read from the database a page at a time by
+HistoryCursor, live bars published on the event engine are received by LiveBars, or by
+LiveBarStream in async code with the stream feature. */
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+
+use chrono::{NaiveDateTime, TimeDelta};
+
+use super::constant::{Exchange, Interval};
+use super::database::BaseDatabase;
+use super::event::EVENT_BAR;
+use super::object::BarData;
+use crate::error::VnrsError;
+use crate::vnrs::event::{Event, EventEngine, HandlerType};
+
+///Bars of start to end from the database in datetime order, loaded one page of time at a
+///time so that long histories aren't held in memory at once. Iteration stops at the first
+///failed load, whose error is kept in error.
+pub struct HistoryCursor {
+    database: Arc<dyn BaseDatabase + Send + Sync>,
+    symbol: String,
+    exchange: Exchange,
+    interval: Interval,
+    end: NaiveDateTime,
+    page: TimeDelta,
+    ///Start of the next page to load, None when done.
+    next_start: Option<NaiveDateTime>,
+    buffer: std::vec::IntoIter<BarData>,
+    error: Option<VnrsError>,
+}
+
+impl HistoryCursor {
+    pub fn new(
+        database: Arc<dyn BaseDatabase + Send + Sync>,
+        symbol: &str,
+        exchange: Exchange,
+        interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Self {
+        HistoryCursor {
+            database,
+            symbol: symbol.to_string(),
+            exchange,
+            interval,
+            end,
+            page: TimeDelta::days(30),
+            next_start: Some(start),
+            buffer: Vec::new().into_iter(),
+            error: None,
+        }
+    }
+
+    ///Time span loaded at once, 30 days by default.
+    pub fn with_page(mut self, page: TimeDelta) -> Self {
+        self.page = page.max(TimeDelta::seconds(1));
+        self
+    }
+
+    ///Error of the load that stopped the iteration.
+    pub fn error(&self) -> Option<&VnrsError> {
+        self.error.as_ref()
+    }
+}
+
+impl Iterator for HistoryCursor {
+    type Item = BarData;
+
+    fn next(&mut self) -> Option<BarData> {
+        loop {
+            if let Some(bar) = self.buffer.next() {
+                return Some(bar);
+            }
+            let start = self.next_start?;
+            if start > self.end {
+                self.next_start = None;
+                return None;
+            }
+            // Pages are inclusive at both ends, the next one starts a second later
+            let page_end = (start + self.page - TimeDelta::seconds(1)).min(self.end);
+            self.next_start = Some(page_end + TimeDelta::seconds(1));
+            match self.database.load_bar_data(
+                &self.symbol,
+                self.exchange,
+                self.interval,
+                start,
+                page_end,
+            ) {
+                Ok(bars) => self.buffer = bars.into_iter(),
+                Err(e) => {
+                    self.error = Some(e);
+                    self.next_start = None;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+///Handler registered on the event engine until dropped.
+struct Subscription {
+    event_engine: Arc<EventEngine>,
+    type_: String,
+    handler: HandlerType,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.event_engine.unregister(&self.type_, &self.handler);
+    }
+}
+
+///Register forward for the bars of vt_symbol published on event_engine.
+fn subscribe(
+    event_engine: &Arc<EventEngine>,
+    vt_symbol: &str,
+    forward: impl Fn(BarData) + Send + Sync + 'static,
+) -> Subscription {
+    let handler: HandlerType = Arc::new(move |event: &Event| {
+        if let Some(bar) = event.data::<BarData>() {
+            forward(bar.clone());
+        }
+    });
+    let type_ = format!("{}{}", EVENT_BAR, vt_symbol);
+    event_engine.register(&type_, handler.clone());
+    Subscription {
+        event_engine: event_engine.clone(),
+        type_,
+        handler,
+    }
+}
+
+///Blocking iterator over the live bars of a symbol from the time it is created, next waits for
+///the next bar. Dropping it unregisters from the event engine.
+pub struct LiveBars {
+    receiver: Receiver<BarData>,
+    _subscription: Subscription,
+}
+
+impl LiveBars {
+    pub fn new(event_engine: &Arc<EventEngine>, vt_symbol: &str) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let sender = std::sync::Mutex::new(sender);
+        let subscription = subscribe(event_engine, vt_symbol, move |bar| {
+            let _ = sender.lock().unwrap().send(bar);
+        });
+        LiveBars {
+            receiver,
+            _subscription: subscription,
+        }
+    }
+
+    ///Next bar if one has arrived, without waiting.
+    pub fn try_next(&self) -> Option<BarData> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl Iterator for LiveBars {
+    type Item = BarData;
+
+    fn next(&mut self) -> Option<BarData> {
+        self.receiver.recv().ok()
+    }
+}
+
+///Stream of the live bars of a symbol, see LiveBars.
+#[cfg(feature = "stream")]
+pub struct LiveBarStream {
+    receiver: tokio::sync::mpsc::UnboundedReceiver<BarData>,
+    _subscription: Subscription,
+}
+
+#[cfg(feature = "stream")]
+impl LiveBarStream {
+    pub fn new(event_engine: &Arc<EventEngine>, vt_symbol: &str) -> Self {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let subscription = subscribe(event_engine, vt_symbol, move |bar| {
+            let _ = sender.send(bar);
+        });
+        LiveBarStream {
+            receiver,
+            _subscription: subscription,
+        }
+    }
+}
+
+#[cfg(feature = "stream")]
+impl tokio_stream::Stream for LiveBarStream {
+    type Item = BarData;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<BarData>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::database::MemoryDatabase;
+    use crate::vnrs::trader::synthetic::SyntheticBars;
+    use std::time::Duration;
+
+    #[test]
+    fn history_by_pages_and_live_bars() {
+        let start = NaiveDateTime::parse_from_str("2024-01-01 00:00", "%Y-%m-%d %H:%M").unwrap();
+        let bars = SyntheticBars {
+            symbol: "IF888".to_string(),
+            exchange: Exchange::CFFEX,
+            interval: Interval::HOUR,
+            start,
+            count: 24 * 10,
+            ..Default::default()
+        }
+        .generate();
+        let database = MemoryDatabase::new();
+        database.save_bar_data(bars.clone());
+
+        let end = start + TimeDelta::days(7);
+        let cursor = HistoryCursor::new(
+            Arc::new(database),
+            "IF888",
+            Exchange::CFFEX,
+            Interval::HOUR,
+            start,
+            end,
+        )
+        .with_page(TimeDelta::days(2));
+        let datetimes: Vec<NaiveDateTime> = cursor.map(|bar| bar.datetime).collect();
+        let expected: Vec<NaiveDateTime> = bars
+            .iter()
+            .map(|bar| bar.datetime)
+            .filter(|datetime| *datetime <= end)
+            .collect();
+        assert_eq!(datetimes, expected);
+
+        let event_engine = Arc::new(EventEngine::new(1));
+        let mut live = LiveBars::new(&event_engine, "IF888.CFFEX");
+        event_engine.start();
+        for bar in &bars[..3] {
+            let event = Event {
+                type_: format!("{}{}", EVENT_BAR, bar.vt_symbol()),
+                ..Event::new(EVENT_BAR, bar.clone())
+            };
+            event_engine.put(event);
+        }
+        event_engine.put(Event::new("eBar.rb888.SHFE", bars[3].clone()));
+        let received: Vec<BarData> = live.by_ref().take(3).collect();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[2].datetime, bars[2].datetime);
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(live.try_next().is_none());
+        event_engine.stop();
+    }
+}
@@ -0,0 +1,186 @@
+/*!Deterministic synthetic bar data for tests and benchmarks, the same seed always gives the
+same series. Load it into a MemoryDatabase to backtest without a database file. */
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+
+use super::constant::{Exchange, Interval};
+use super::object::{BarData, Symbol};
+use super::utility::round_to;
+
+///How the close price moves from bar to bar, on top of the random shock.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum PriceModel {
+    #[default]
+    RandomWalk,
+    ///Fixed return per bar, e.g. 0.0001 for a slow uptrend.
+    Trend(f64),
+    ///Pulled back toward mean by the fraction speed of the distance each bar.
+    MeanReversion { mean: f64, speed: f64 },
+}
+
+///Settings of a synthetic series, fill in the fields needed and call generate.
+#[derive(Debug, Clone)]
+pub struct SyntheticBars {
+    pub symbol: String,
+    pub exchange: Exchange,
+    pub interval: Interval,
+    pub start: NaiveDateTime,
+    pub count: usize,
+    pub start_price: f64,
+    ///Standard deviation of the return per bar.
+    pub volatility: f64,
+    pub model: PriceModel,
+    pub seed: u64,
+    ///Prices are rounded to it if positive.
+    pub pricetick: f64,
+}
+
+impl Default for SyntheticBars {
+    fn default() -> Self {
+        SyntheticBars {
+            symbol: "TEST".to_string(),
+            exchange: Exchange::LOCAL,
+            interval: Interval::MINUTE,
+            start: NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+            count: 1000,
+            start_price: 100.0,
+            volatility: 0.001,
+            model: PriceModel::RandomWalk,
+            seed: 0,
+            pricetick: 0.0,
+        }
+    }
+}
+
+impl SyntheticBars {
+    pub fn generate(&self) -> Vec<BarData> {
+        let mut rng = SplitMix64(self.seed);
+        let symbol = Symbol::new(&self.symbol);
+        let step = match self.interval {
+            Interval::TICK => TimeDelta::seconds(1),
+            Interval::HOUR => TimeDelta::hours(1),
+            Interval::DAILY => TimeDelta::days(1),
+            Interval::WEEKLY => TimeDelta::weeks(1),
+            Interval::SECONDS(seconds) => TimeDelta::seconds(seconds as i64),
+            _ => TimeDelta::minutes(1),
+        };
+        let round = |price: f64| {
+            if self.pricetick > 0.0 {
+                round_to(price, self.pricetick)
+            } else {
+                price
+            }
+        };
+
+        let mut price = self.start_price;
+        let mut open_price = round(price);
+        let mut bars = Vec::with_capacity(self.count);
+        for i in 0..self.count {
+            let drift = match self.model {
+                PriceModel::RandomWalk => 0.0,
+                PriceModel::Trend(drift) => drift,
+                PriceModel::MeanReversion { mean, speed } => speed * (mean - price) / price,
+            };
+            price *= 1.0 + drift + self.volatility * rng.next_normal();
+            price = price.max(f64::EPSILON);
+
+            let close_price = round(price);
+            let high_price =
+                round(open_price.max(close_price) * (1.0 + self.volatility * rng.next_f64()));
+            let low_price =
+                round(open_price.min(close_price) * (1.0 - self.volatility * rng.next_f64()));
+            let volume = (100.0 * (1.0 + rng.next_f64())).round();
+
+            bars.push(BarData {
+                gateway_name: "DB".into(),
+                symbol,
+                exchange: self.exchange,
+                datetime: self.start + step * i as i32,
+                interval: self.interval,
+                volume,
+                turnover: volume * close_price,
+                open_interest: 0.0,
+                open_price,
+                high_price,
+                low_price,
+                close_price,
+            });
+            open_price = close_price;
+        }
+        bars
+    }
+}
+
+///SplitMix64, small and good enough for test data and sampling without a rand dependency.
+pub(crate) struct SplitMix64(pub u64);
+
+impl SplitMix64 {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    ///Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    ///Standard normal by the Box-Muller transform.
+    pub fn next_normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_series() {
+        let synthetic = SyntheticBars {
+            seed: 7,
+            ..Default::default()
+        };
+        let first = synthetic.generate();
+        let second = synthetic.generate();
+        assert_eq!(first.len(), 1000);
+        assert!(first
+            .iter()
+            .zip(&second)
+            .all(|(a, b)| a.close_price == b.close_price && a.datetime == b.datetime));
+
+        let other = SyntheticBars {
+            seed: 8,
+            ..Default::default()
+        }
+        .generate();
+        assert_ne!(first[999].close_price, other[999].close_price);
+    }
+
+    #[test]
+    fn bars_are_consistent() {
+        let bars = SyntheticBars {
+            model: PriceModel::MeanReversion {
+                mean: 100.0,
+                speed: 0.05,
+            },
+            pricetick: 0.2,
+            ..Default::default()
+        }
+        .generate();
+        for bar in &bars {
+            assert!(bar.low_price <= bar.open_price.min(bar.close_price));
+            assert!(bar.high_price >= bar.open_price.max(bar.close_price));
+            let ticks = bar.close_price / 0.2;
+            assert!((ticks - ticks.round()).abs() < 1e-6);
+        }
+        assert!(bars.windows(2).all(|w| w[0].datetime < w[1].datetime));
+    }
+}
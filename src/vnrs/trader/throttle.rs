@@ -0,0 +1,343 @@
+/*!Flow control of a gateway: order and cancel counters of a trading day, kept on disk so
+that they survive restarts within the day, and a RateLimiter holding back requests over the
+per second limits of the exchange.
+
+Standalone utilities, the crate has no gateway base to build them into: a gateway counts its
+requests in FlowCounters and sends them through a RateLimiter itself. */
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use super::clock::Clock;
+use super::utility::get_file_path;
+use crate::error::{VnrsError, VnrsResult};
+
+pub const FLOW_COUNTERS_FILENAME: &str = "flow_counters.json";
+
+///Orders and cancels sent per vt_symbol on date. Every change is written to the file before
+///returning, replacing it atomically so a crash never leaves a partial file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowCounters {
+    pub date: NaiveDate,
+    pub orders: HashMap<String, u32>,
+    pub cancels: HashMap<String, u32>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl FlowCounters {
+    ///Counters of date from the file in the trader dir.
+    pub fn load(date: NaiveDate) -> Self {
+        Self::load_from(get_file_path(FLOW_COUNTERS_FILENAME), date)
+    }
+
+    ///Counters of date from path, empty if the file is missing, invalid or of another day.
+    pub fn load_from(path: PathBuf, date: NaiveDate) -> Self {
+        let saved = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<FlowCounters>(&content).ok())
+            .filter(|counters| counters.date == date);
+        match saved {
+            Some(counters) => FlowCounters { path, ..counters },
+            None => FlowCounters {
+                date,
+                orders: HashMap::new(),
+                cancels: HashMap::new(),
+                path,
+            },
+        }
+    }
+
+    pub fn order_count(&self, vt_symbol: &str) -> u32 {
+        self.orders.get(vt_symbol).copied().unwrap_or_default()
+    }
+
+    pub fn cancel_count(&self, vt_symbol: &str) -> u32 {
+        self.cancels.get(vt_symbol).copied().unwrap_or_default()
+    }
+
+    ///Count an order sent, returns the count of the day.
+    pub fn add_order(&mut self, vt_symbol: &str) -> VnrsResult<u32> {
+        *self.orders.entry(vt_symbol.to_string()).or_default() += 1;
+        self.save()?;
+        Ok(self.order_count(vt_symbol))
+    }
+
+    ///Count a cancel sent, returns the count of the day.
+    pub fn add_cancel(&mut self, vt_symbol: &str) -> VnrsResult<u32> {
+        *self.cancels.entry(vt_symbol.to_string()).or_default() += 1;
+        self.save()?;
+        Ok(self.cancel_count(vt_symbol))
+    }
+
+    ///Start counting a new day, e.g. when the trading day changes without a restart.
+    pub fn reset(&mut self, date: NaiveDate) -> VnrsResult<()> {
+        self.date = date;
+        self.orders.clear();
+        self.cancels.clear();
+        self.save()
+    }
+
+    fn save(&self) -> VnrsResult<()> {
+        let temp_path = self.path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string(self)?)?;
+        fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+///Token bucket budget of an endpoint: up to burst requests at once, refilled at per_second.
+///Checked by new, also when deserialized.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RateBudgetSetting")]
+pub struct RateBudget {
+    burst: f64,
+    per_second: f64,
+}
+
+///RateBudget as written in a config file, before it is checked.
+#[derive(Deserialize)]
+struct RateBudgetSetting {
+    burst: f64,
+    per_second: f64,
+}
+
+impl TryFrom<RateBudgetSetting> for RateBudget {
+    type Error = VnrsError;
+
+    fn try_from(setting: RateBudgetSetting) -> VnrsResult<Self> {
+        RateBudget::new(setting.burst, setting.per_second)
+    }
+}
+
+impl RateBudget {
+    ///Error unless burst holds at least one request and per_second is positive, a bucket
+    ///that never refills would hold its requests forever.
+    pub fn new(burst: f64, per_second: f64) -> VnrsResult<Self> {
+        if !(burst.is_finite() && burst >= 1.0) {
+            return Err(VnrsError::Setting(format!("invalid rate burst {}", burst)));
+        }
+        if !(per_second.is_finite() && per_second > 0.0) {
+            return Err(VnrsError::Setting(format!(
+                "invalid rate {} per second",
+                per_second
+            )));
+        }
+        Ok(RateBudget { burst, per_second })
+    }
+
+    pub fn burst(&self) -> f64 {
+        self.burst
+    }
+
+    pub fn per_second(&self) -> f64 {
+        self.per_second
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    budget: RateBudget,
+    tokens: f64,
+    updated: NaiveDateTime,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: NaiveDateTime) {
+        let elapsed = (now - self.updated).num_microseconds().unwrap_or(i64::MAX) as f64 / 1e6;
+        if elapsed > 0.0 {
+            self.tokens = (self.tokens + elapsed * self.budget.per_second).min(self.budget.burst);
+            self.updated = now;
+        }
+    }
+}
+
+///Requests of a gateway held to a budget per endpoint, e.g. "order" and "cancel" of the REST
+///API. A request over the budget waits in the queue of its endpoint and is released in
+///order as the bucket refills, so bursts of orders never break the limits of the exchange.
+pub struct RateLimiter<T> {
+    clock: Arc<dyn Clock>,
+    buckets: HashMap<String, Bucket>,
+    queues: HashMap<String, VecDeque<T>>,
+}
+
+impl<T> RateLimiter<T> {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        RateLimiter {
+            clock,
+            buckets: HashMap::new(),
+            queues: HashMap::new(),
+        }
+    }
+
+    ///Limit endpoint to budget, starting with a full bucket. Endpoints without a budget are
+    ///not limited.
+    pub fn set_budget(&mut self, endpoint: &str, budget: RateBudget) {
+        let bucket = Bucket {
+            budget,
+            tokens: budget.burst,
+            updated: self.clock.now(),
+        };
+        self.buckets.insert(endpoint.to_string(), bucket);
+    }
+
+    ///request back if it may be sent now, else None with the request queued behind the
+    ///others of endpoint.
+    pub fn submit(&mut self, endpoint: &str, request: T) -> Option<T> {
+        let queue = self.queues.entry(endpoint.to_string()).or_default();
+        let Some(bucket) = self.buckets.get_mut(endpoint) else {
+            return Some(request);
+        };
+        bucket.refill(self.clock.now());
+        if queue.is_empty() && bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Some(request);
+        }
+        queue.push_back(request);
+        None
+    }
+
+    ///Queued requests the budgets allow now as (endpoint, request), in queue order within
+    ///each endpoint. Call regularly, e.g. on every timer event, and send them.
+    pub fn release(&mut self) -> Vec<(String, T)> {
+        let now = self.clock.now();
+        let mut released = Vec::new();
+        for (endpoint, queue) in self.queues.iter_mut() {
+            let Some(bucket) = self.buckets.get_mut(endpoint) else {
+                released.extend(queue.drain(..).map(|request| (endpoint.clone(), request)));
+                continue;
+            };
+            bucket.refill(now);
+            while bucket.tokens >= 1.0 {
+                let Some(request) = queue.pop_front() else {
+                    break;
+                };
+                bucket.tokens -= 1.0;
+                released.push((endpoint.clone(), request));
+            }
+        }
+        released
+    }
+
+    ///Requests waiting for the budget of endpoint.
+    pub fn queued(&self, endpoint: &str) -> usize {
+        self.queues.get(endpoint).map_or(0, VecDeque::len)
+    }
+
+    ///Earliest datetime a queued request can be released at, None with empty queues or one
+    ///too far out to represent.
+    pub fn next_release(&self) -> Option<NaiveDateTime> {
+        self.queues
+            .iter()
+            .filter(|(_, queue)| !queue.is_empty())
+            .filter_map(|(endpoint, _)| {
+                let bucket = self.buckets.get(endpoint)?;
+                let missing = (1.0 - bucket.tokens).max(0.0);
+                let wait = (missing / bucket.budget.per_second * 1e6).ceil();
+                if !(0.0..i64::MAX as f64).contains(&wait) {
+                    return None;
+                }
+                bucket
+                    .updated
+                    .checked_add_signed(TimeDelta::microseconds(wait as i64))
+            })
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::clock::SimClock;
+
+    #[test]
+    fn counters_survive_restart_within_day() {
+        let path =
+            std::env::temp_dir().join(format!("vnrs_flow_counters_{}.json", std::process::id()));
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        let today = date("2024-09-30");
+
+        let mut counters = FlowCounters::load_from(path.clone(), today);
+        counters.add_order("rb2410.SHFE").unwrap();
+        counters.add_order("rb2410.SHFE").unwrap();
+        counters.add_cancel("rb2410.SHFE").unwrap();
+        drop(counters);
+
+        // Restarted on the same day
+        let mut counters = FlowCounters::load_from(path.clone(), today);
+        assert_eq!(counters.order_count("rb2410.SHFE"), 2);
+        assert_eq!(counters.add_cancel("rb2410.SHFE").unwrap(), 2);
+
+        // Counting starts over the next day
+        let counters = FlowCounters::load_from(path.clone(), date("2024-10-08"));
+        assert_eq!(counters.order_count("rb2410.SHFE"), 0);
+        assert_eq!(counters.cancel_count("rb2410.SHFE"), 0);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn requests_over_budget_wait() {
+        let at = |s: &str| {
+            NaiveDateTime::parse_from_str(&format!("2024-09-30 {}", s), "%Y-%m-%d %H:%M:%S%.3f")
+                .unwrap()
+        };
+        let clock = Arc::new(SimClock::new(at("09:00:00.000")));
+        let mut limiter = RateLimiter::new(clock.clone());
+        limiter.set_budget("order", RateBudget::new(2.0, 4.0).unwrap());
+
+        assert_eq!(limiter.submit("order", 1), Some(1));
+        assert_eq!(limiter.submit("order", 2), Some(2));
+        assert_eq!(limiter.submit("order", 3), None);
+        assert_eq!(limiter.submit("order", 4), None);
+        // Other endpoints aren't limited
+        assert_eq!(limiter.submit("query", 5), Some(5));
+        assert_eq!(limiter.queued("order"), 2);
+        assert_eq!(limiter.next_release(), Some(at("09:00:00.250")));
+        assert!(limiter.release().is_empty());
+
+        clock.advance_to(at("09:00:00.250"));
+        assert_eq!(limiter.release(), vec![("order".to_string(), 3)]);
+        // Queued requests keep their turn
+        assert_eq!(limiter.submit("order", 6), None);
+        clock.advance_to(at("09:00:01.000"));
+        let released: Vec<i32> = limiter.release().into_iter().map(|(_, r)| r).collect();
+        assert_eq!(released, vec![4, 6]);
+        assert_eq!(limiter.next_release(), None);
+
+        // A bucket refilling too slowly doesn't overflow the release time
+        limiter.set_budget("cancel", RateBudget::new(1.0, 1e-300).unwrap());
+        assert_eq!(limiter.submit("cancel", 7), Some(7));
+        assert_eq!(limiter.submit("cancel", 8), None);
+        assert_eq!(limiter.next_release(), None);
+    }
+
+    #[test]
+    fn invalid_budgets_are_rejected() {
+        assert!(RateBudget::new(2.0, 0.0).is_err());
+        assert!(RateBudget::new(2.0, -1.0).is_err());
+        assert!(RateBudget::new(0.0, 4.0).is_err());
+        assert!(RateBudget::new(2.0, f64::NAN).is_err());
+        assert!(RateBudget::new(f64::INFINITY, 4.0).is_err());
+
+        let budget: RateBudget = serde_json::from_str(r#"{"burst":2,"per_second":4}"#).unwrap();
+        assert_eq!(budget, RateBudget::new(2.0, 4.0).unwrap());
+        assert_eq!(
+            serde_json::to_string(&budget).unwrap(),
+            r#"{"burst":2.0,"per_second":4.0}"#
+        );
+        for json in [
+            r#"{"burst":1,"per_second":0}"#,
+            r#"{"burst":0,"per_second":4}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<RateBudget>(json).is_err(),
+                "{}",
+                json
+            );
+        }
+    }
+}
@@ -0,0 +1,195 @@
+/*!Values sorted by datetime (or date) with binary searched lookups, and the alignment, join
+and resample operations needed to line up series sampled on different timestamps, e.g. a
+risk-free rate history against the days of a backtest. */
+use std::collections::BTreeSet;
+use std::ops::RangeBounds;
+
+use chrono::NaiveDateTime;
+
+///Sorted (key, value) pairs with unique keys, a later value replacing an earlier one of the
+///same key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeSeries<T, K = NaiveDateTime> {
+    keys: Vec<K>,
+    values: Vec<T>,
+}
+
+impl<T, K> Default for TimeSeries<T, K> {
+    fn default() -> Self {
+        TimeSeries {
+            keys: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<T, K: Ord + Copy> TimeSeries<T, K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Insert value at key, replacing the value already there. Appending in order is O(1).
+    pub fn insert(&mut self, key: K, value: T) {
+        if self.keys.last().is_none_or(|last| *last < key) {
+            self.keys.push(key);
+            self.values.push(value);
+            return;
+        }
+        match self.keys.binary_search(&key) {
+            Ok(index) => self.values[index] = value,
+            Err(index) => {
+                self.keys.insert(index, key);
+                self.values.insert(index, value);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (K, &T)> {
+        self.keys.iter().copied().zip(&self.values)
+    }
+
+    pub fn first(&self) -> Option<(K, &T)> {
+        self.iter().next()
+    }
+
+    pub fn last(&self) -> Option<(K, &T)> {
+        Some((*self.keys.last()?, self.values.last()?))
+    }
+
+    ///Value at exactly key.
+    pub fn get(&self, key: K) -> Option<&T> {
+        let index = self.keys.binary_search(&key).ok()?;
+        Some(&self.values[index])
+    }
+
+    ///Last value at or before key, the value in effect at key.
+    pub fn asof(&self, key: K) -> Option<&T> {
+        let index = self.keys.partition_point(|k| *k <= key);
+        index.checked_sub(1).map(|index| &self.values[index])
+    }
+
+    ///Values with keys in range.
+    pub fn range(&self, range: impl RangeBounds<K>) -> impl Iterator<Item = (K, &T)> {
+        self.iter().filter(move |(key, _)| range.contains(key))
+    }
+
+    ///Value at exactly each of keys.
+    pub fn reindex(&self, keys: &[K]) -> Vec<Option<&T>> {
+        keys.iter().map(|key| self.get(*key)).collect()
+    }
+
+    ///Value in effect at each of keys, the last one at or before it.
+    pub fn align(&self, keys: &[K]) -> Vec<Option<&T>> {
+        keys.iter().map(|key| self.asof(*key)).collect()
+    }
+
+    ///Pairs of the values at keys present in both series.
+    pub fn join<'a, U>(&'a self, other: &'a TimeSeries<U, K>) -> TimeSeries<(&'a T, &'a U), K> {
+        self.iter()
+            .filter_map(|(key, value)| Some((key, (value, other.get(key)?))))
+            .collect()
+    }
+
+    ///One value per bucket of consecutive keys, bucket mapping each key to the key of its
+    ///bucket, e.g. its date, and aggregate reducing the values of a bucket.
+    pub fn resample<U, B: Ord + Copy>(
+        &self,
+        bucket: impl Fn(K) -> B,
+        aggregate: impl Fn(&[T]) -> U,
+    ) -> TimeSeries<U, B> {
+        let mut resampled = TimeSeries::new();
+        let mut start = 0;
+        while start < self.len() {
+            let key = bucket(self.keys[start]);
+            let end = start
+                + self.keys[start..]
+                    .iter()
+                    .take_while(|k| bucket(**k) == key)
+                    .count();
+            resampled.insert(key, aggregate(&self.values[start..end]));
+            start = end;
+        }
+        resampled
+    }
+
+    ///Sorted union of the keys of all series.
+    pub fn union_keys<'a>(series: impl IntoIterator<Item = &'a Self>) -> Vec<K>
+    where
+        Self: 'a,
+    {
+        series
+            .into_iter()
+            .flat_map(|series| series.keys.iter().copied())
+            .collect::<BTreeSet<K>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+impl<T, K: Ord + Copy> FromIterator<(K, T)> for TimeSeries<T, K> {
+    fn from_iter<I: IntoIterator<Item = (K, T)>>(iter: I) -> Self {
+        let mut series = TimeSeries::new();
+        for (key, value) in iter {
+            series.insert(key, value);
+        }
+        series
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, Timelike};
+
+    #[test]
+    fn lookup_align_and_resample() {
+        let at = |hour: u32, minute: u32| {
+            NaiveDate::from_ymd_opt(2024, 1, 22)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap()
+        };
+        let series: TimeSeries<f64> = [(at(9, 2), 2.0), (at(9, 0), 1.0), (at(10, 0), 3.0)]
+            .into_iter()
+            .chain([(at(9, 2), 2.5)])
+            .collect();
+        assert_eq!(series.keys(), &[at(9, 0), at(9, 2), at(10, 0)]);
+        assert_eq!(series.get(at(9, 2)), Some(&2.5));
+        assert_eq!(series.get(at(9, 1)), None);
+        assert_eq!(series.asof(at(9, 1)), Some(&1.0));
+        assert_eq!(series.asof(at(8, 59)), None);
+        assert_eq!(
+            series.align(&[at(8, 0), at(9, 30), at(11, 0)]),
+            vec![None, Some(&2.5), Some(&3.0)]
+        );
+        assert_eq!(series.range(at(9, 1)..).count(), 2);
+
+        let other: TimeSeries<i32> = [(at(9, 2), 7), (at(9, 3), 8)].into_iter().collect();
+        let joined = series.join(&other);
+        assert_eq!(joined.len(), 1);
+        assert_eq!(joined.get(at(9, 2)), Some(&(&2.5, &7)));
+        assert_eq!(
+            TimeSeries::union_keys([&series, &series]),
+            series.keys().to_vec()
+        );
+
+        let hourly = series.resample(|key| key.hour(), |values| values.iter().sum::<f64>());
+        assert_eq!(hourly.values(), &[3.5, 3.0]);
+    }
+}
@@ -1,32 +1,486 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::hash::BuildHasher;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::OnceLock;
+use std::time::SystemTime;
 
-use chrono::Timelike;
+use chrono::{Datelike, NaiveDateTime, TimeDelta, Timelike};
+#[cfg(feature = "dylib-strategies")]
 use libloading;
 use rust_decimal::prelude::*;
+use serde_json::Value;
 
-use crate::vnrs::trader::constant::Exchange;
+use crate::error::{VnrsError, VnrsResult};
+use crate::vnrs::trader::constant::{Exchange, Interval};
 
+use super::indicators::{Indicator, Streaming};
 use super::object::{BarData, MixData, TickData};
 
 ///:return: (symbol, exchange)
-pub fn extract_vt_symbol(vt_symbol: &str) -> (String, Exchange) {
-    let vec_str: Vec<&str> = vt_symbol.rsplitn(2, ".").collect();
-    let (symbol, exchange_str) = (vec_str[1], vec_str[0]);
-    return (
-        symbol.to_string(),
-        Exchange::from_str(exchange_str).unwrap(),
-    );
+pub fn extract_vt_symbol(vt_symbol: &str) -> VnrsResult<(String, Exchange)> {
+    let invalid = || VnrsError::Setting(format!("invalid vt_symbol {}", vt_symbol));
+    let (symbol, exchange_str) = vt_symbol.rsplit_once('.').ok_or_else(invalid)?;
+    let exchange = Exchange::from_str(exchange_str).map_err(|_| invalid())?;
+    Ok((symbol.to_string(), exchange))
 }
 
-///Round price to price tick value.
+static TRADER_DIR: OnceLock<PathBuf> = OnceLock::new();
 
+///Get path where trader is running in, ".vntrader" in the working directory if it exists,
+///otherwise ".vntrader" in the home directory.
+pub fn get_trader_dir() -> &'static PathBuf {
+    TRADER_DIR.get_or_init(|| {
+        let cwd_dir = std::env::current_dir()
+            .unwrap_or_default()
+            .join(".vntrader");
+        if cwd_dir.is_dir() {
+            return cwd_dir;
+        }
+        let home = std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        let home_dir = home.join(".vntrader");
+        let _ = fs::create_dir_all(&home_dir);
+        home_dir
+    })
+}
+
+///Get path for temp file with filename.
+pub fn get_file_path(filename: &str) -> PathBuf {
+    get_trader_dir().join(filename)
+}
+
+///Load data from json file in temp path, an empty object if missing or invalid.
+pub fn load_json(filename: &str) -> Value {
+    fs::read_to_string(get_file_path(filename))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_else(|| Value::Object(Default::default()))
+}
+
+///Save data into json file in temp path.
+pub fn save_json(filename: &str, data: &Value) -> VnrsResult<()> {
+    fs::write(get_file_path(filename), serde_json::to_string_pretty(data)?)?;
+    Ok(())
+}
+
+static ENGINE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+///Number identifying an engine, unique within the process.
+pub fn new_engine_id() -> u64 {
+    ENGINE_COUNT.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+///Random UUID (version 4) identifying a backtest run, so that ids of runs in different
+///processes never collide.
+pub fn new_run_id() -> String {
+    let hash = |salt: u64| {
+        let entropy = (salt, new_engine_id(), std::process::id(), SystemTime::now());
+        RandomState::new().hash_one(entropy) as u128
+    };
+    let mut value = hash(0) << 64 | hash(1);
+    // Version 4 and variant 1 bits
+    value = (value & !(0xf << 76)) | (0x4 << 76);
+    value = (value & !(0x3 << 62)) | (0x2 << 62);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        value >> 96,
+        (value >> 80) & 0xffff,
+        (value >> 64) & 0xffff,
+        (value >> 48) & 0xffff,
+        value & 0xffff_ffff_ffff
+    )
+}
+
+///Sequential id generator, ids are "{prefix}_{count}" so that generators with
+///different prefixes never collide.
+#[derive(Debug, Default, Clone)]
+pub struct IdGenerator {
+    prefix: String,
+    count: i64,
+}
+
+impl IdGenerator {
+    pub fn new(prefix: &str) -> Self {
+        IdGenerator {
+            prefix: prefix.to_string(),
+            count: 0,
+        }
+    }
+
+    pub fn next_id(&mut self) -> String {
+        self.count += 1;
+        format!("{}_{}", self.prefix, self.count)
+    }
+
+    ///Number of ids generated since the last reset.
+    pub fn count(&self) -> i64 {
+        self.count
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    ///Continue numbering after count, e.g. when resuming from a checkpoint.
+    pub fn set_count(&mut self, count: i64) {
+        self.count = count;
+    }
+
+    ///Sequence number of an id, for sorting ids in generation order.
+    pub fn sequence(id: &str) -> i64 {
+        id.rsplit('_').next().unwrap().parse().unwrap_or_default()
+    }
+}
+
+///Set of active ids from an IdGenerator, iterated in generation order without sorting.
+#[derive(Debug, Default, Clone)]
+pub struct ActiveIds {
+    ids: BTreeMap<i64, String>,
+}
+
+impl ActiveIds {
+    pub fn insert(&mut self, id: String) {
+        self.ids.insert(IdGenerator::sequence(&id), id);
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let sequence = IdGenerator::sequence(id);
+        if self.get(sequence) == Some(id) {
+            self.ids.remove(&sequence);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.get(IdGenerator::sequence(id)) == Some(id)
+    }
+
+    ///Id of a sequence number, None once the id is removed.
+    pub fn get(&self, sequence: i64) -> Option<&str> {
+        self.ids.get(&sequence).map(String::as_str)
+    }
+
+    pub fn sequences(&self) -> impl Iterator<Item = i64> + '_ {
+        self.ids.keys().copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.ids.values().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+    }
+}
+
+///Round price to price tick value. Value is returned as is when it can't be rounded, e.g.
+///NaN or a zero target.
 pub fn round_to(value: f64, target: f64) -> f64 {
-    let value: Decimal = Decimal::from_str(&value.to_string()).unwrap();
-    let target: Decimal = Decimal::from_str(&target.to_string()).unwrap();
-    ((value / target).round() * target)
+    let (Ok(decimal_value), Ok(decimal_target)) = (
+        Decimal::from_str(&value.to_string()),
+        Decimal::from_str(&target.to_string()),
+    ) else {
+        return value;
+    };
+    if decimal_target.is_zero() {
+        return value;
+    }
+    ((decimal_value / decimal_target).round() * decimal_target)
         .to_string()
         .parse()
-        .unwrap()
+        .unwrap_or(value)
+}
+
+///Aggregate bars of a smaller interval into bars of `window` units of interval, the way
+///BarGenerator builds window bars. Windows are aligned to the clock, e.g. 5 minute bars
+///start at minutes divisible by 5 and 4 hour bars at 0:00, 4:00, 8:00...
+///Other intervals aggregate by calendar day and ignore the window.
+///
+///Input must be sorted by datetime, the last window is kept even if incomplete.
+pub fn resample_bars(bars: &[BarData], interval: Interval, window: i64) -> Vec<BarData> {
+    let mut builder = WindowBarBuilder::new(interval, window);
+    let mut result: Vec<BarData> = Vec::new();
+    for bar in bars {
+        // Windows are only finished by the first bar of the next one
+        result.extend(builder.update_bar(bar, bar.datetime));
+    }
+    result.extend(builder.finish());
+    result
+}
+
+///Build bars of an interval that is_alt_bar from bars of a smaller interval sorted by
+///datetime, see AltBarBuilder. The last range or volume bar is kept even if incomplete.
+pub fn resample_alt_bars(
+    bars: &[BarData],
+    interval: Interval,
+    pricetick: f64,
+) -> VnrsResult<Vec<BarData>> {
+    let mut builder = AltBarBuilder::new(interval, pricetick)?;
+    let mut result: Vec<BarData> = Vec::new();
+    for bar in bars {
+        result.extend(builder.update_bar(bar));
+    }
+    result.extend(builder.finish());
+    Ok(result)
+}
+
+///Datetime the clock aligned window of datetime starts at, see resample_bars.
+fn window_start(datetime: NaiveDateTime, interval: Interval, window: i64) -> NaiveDateTime {
+    let date = datetime.date();
+    match interval {
+        Interval::MINUTE => {
+            let minutes = (datetime.hour() * 60 + datetime.minute()) as i64;
+            let minutes = minutes - minutes % window;
+            date.and_hms_opt((minutes / 60) as u32, (minutes % 60) as u32, 0)
+                .unwrap()
+        }
+        Interval::HOUR => {
+            let hour = datetime.hour() as i64;
+            date.and_hms_opt((hour - hour % window) as u32, 0, 0)
+                .unwrap()
+        }
+        Interval::SECONDS(seconds) => {
+            let length = seconds as i64 * window;
+            let seconds = datetime.num_seconds_from_midnight() as i64;
+            date.and_hms_opt(0, 0, 0).unwrap() + TimeDelta::seconds(seconds - seconds % length)
+        }
+        // Weeks start on Monday
+        Interval::WEEKLY => {
+            date.and_hms_opt(0, 0, 0).unwrap()
+                - TimeDelta::days(date.weekday().num_days_from_monday() as i64)
+        }
+        _ => date.and_hms_opt(0, 0, 0).unwrap(),
+    }
+}
+
+fn window_end(start: NaiveDateTime, interval: Interval, window: i64) -> NaiveDateTime {
+    match interval {
+        Interval::MINUTE => start + TimeDelta::minutes(window),
+        Interval::HOUR => start + TimeDelta::hours(window),
+        Interval::SECONDS(seconds) => start + TimeDelta::seconds(seconds as i64 * window),
+        Interval::WEEKLY => start + TimeDelta::weeks(1),
+        _ => start + TimeDelta::days(1),
+    }
+}
+
+///Streaming resample_bars, building window bars as bars of a smaller interval arrive.
+#[derive(Debug, Clone)]
+pub struct WindowBarBuilder {
+    pub interval: Interval,
+    pub window: i64,
+    ///(window start, window bar) being built.
+    current: Option<(NaiveDateTime, BarData)>,
+}
+
+impl WindowBarBuilder {
+    pub fn new(interval: Interval, window: i64) -> Self {
+        WindowBarBuilder {
+            interval,
+            window: window.max(1),
+            current: None,
+        }
+    }
+
+    ///Add a bar ending at bar_end, returns the window bars it finishes: the window it ends,
+    ///or the previous window when it starts a new one, e.g. after an early session close.
+    pub fn update_bar(&mut self, bar: &BarData, bar_end: NaiveDateTime) -> Vec<BarData> {
+        let mut finished = Vec::new();
+        let start = window_start(bar.datetime, self.interval, self.window);
+        match &mut self.current {
+            Some((current_start, window_bar)) if *current_start == start => {
+                window_bar.high_price = window_bar.high_price.max(bar.high_price);
+                window_bar.low_price = window_bar.low_price.min(bar.low_price);
+                window_bar.close_price = bar.close_price;
+                window_bar.volume += bar.volume;
+                window_bar.turnover += bar.turnover;
+                window_bar.open_interest = bar.open_interest;
+            }
+            _ => {
+                finished.extend(self.finish());
+                self.current = Some((
+                    start,
+                    BarData {
+                        datetime: start,
+                        interval: self.interval,
+                        ..bar.clone()
+                    },
+                ));
+            }
+        }
+
+        if bar_end >= window_end(start, self.interval, self.window) {
+            finished.extend(self.finish());
+        }
+        finished
+    }
+
+    ///Take the window bar being built, if any.
+    pub fn finish(&mut self) -> Option<BarData> {
+        self.current.take().map(|(_, window_bar)| window_bar)
+    }
+}
+
+///Builds bars closing on price or volume instead of time, tagged with their Interval:
+///RENKO bricks, RANGE bars and VOLUME bars, from bars of a smaller interval in a backtest or
+///from ticks live. Source bars aren't split, so a range or volume bar holds at least, and may
+///go past, its size.
+#[derive(Debug, Clone)]
+pub struct AltBarBuilder {
+    pub interval: Interval,
+    ///Price distance of RENKO and RANGE, volume of VOLUME.
+    size: f64,
+    ///Bar being built, for RENKO the volume and start datetime of the next brick.
+    current: Option<BarData>,
+    ///Close of the last RENKO brick.
+    anchor: Option<f64>,
+    ///(volume, turnover) of the last tick, which are cumulative.
+    last_tick: Option<(f64, f64)>,
+}
+
+impl AltBarBuilder {
+    ///Builder of bars of interval, prices in ticks of pricetick. Setting error unless the
+    ///interval is_alt_bar and the price size is positive.
+    pub fn new(interval: Interval, pricetick: f64) -> VnrsResult<Self> {
+        let size = match interval {
+            Interval::RENKO(ticks) | Interval::RANGE(ticks) => ticks as f64 * pricetick,
+            Interval::VOLUME(volume) => volume as f64,
+            _ => 0.0,
+        };
+        if size.is_nan() || size <= 0.0 {
+            return Err(VnrsError::Setting(format!(
+                "invalid alternative bar interval {} with pricetick {}",
+                interval, pricetick
+            )));
+        }
+        Ok(AltBarBuilder {
+            interval,
+            size,
+            current: None,
+            anchor: None,
+            last_tick: None,
+        })
+    }
+
+    ///Add a bar of a smaller interval, returns the bars it finishes.
+    pub fn update_bar(&mut self, bar: &BarData) -> Vec<BarData> {
+        self.update(bar.clone())
+    }
+
+    ///Add a tick, its volume and turnover the change from the previous tick. Ticks without a
+    ///last price are skipped.
+    pub fn update_tick(&mut self, tick: &TickData) -> Vec<BarData> {
+        if tick.last_price == 0.0 {
+            return Vec::new();
+        }
+        let (volume, turnover) = match self.last_tick {
+            Some((volume, turnover)) => (
+                (tick.volume - volume).max(0.0),
+                (tick.turnover - turnover).max(0.0),
+            ),
+            None => (0.0, 0.0),
+        };
+        self.last_tick = Some((tick.volume, tick.turnover));
+        self.update(BarData {
+            gateway_name: tick.gateway_name,
+            symbol: tick.symbol,
+            exchange: tick.exchange,
+            datetime: tick.datetime,
+            volume,
+            turnover,
+            open_interest: tick.open_interest,
+            open_price: tick.last_price,
+            high_price: tick.last_price,
+            low_price: tick.last_price,
+            close_price: tick.last_price,
+            ..Default::default()
+        })
+    }
+
+    ///Take the range or volume bar being built, if any. Renko has no partial brick.
+    pub fn finish(&mut self) -> Option<BarData> {
+        if matches!(self.interval, Interval::RENKO(_)) {
+            return None;
+        }
+        self.current.take()
+    }
+
+    fn update(&mut self, piece: BarData) -> Vec<BarData> {
+        let close_price = piece.close_price;
+        let current = match &mut self.current {
+            Some(current) => {
+                current.high_price = current.high_price.max(piece.high_price);
+                current.low_price = current.low_price.min(piece.low_price);
+                current.close_price = piece.close_price;
+                current.volume += piece.volume;
+                current.turnover += piece.turnover;
+                current.open_interest = piece.open_interest;
+                current
+            }
+            None => self.current.insert(BarData {
+                interval: self.interval,
+                ..piece
+            }),
+        };
+
+        match self.interval {
+            Interval::RANGE(_) if current.high_price - current.low_price >= self.size => {
+                self.current.take().into_iter().collect()
+            }
+            Interval::VOLUME(_) if current.volume >= self.size => {
+                self.current.take().into_iter().collect()
+            }
+            Interval::RENKO(_) => self.renko_bricks(close_price),
+            _ => Vec::new(),
+        }
+    }
+
+    ///Bricks of size the close moved from the last brick, the first close only sets where
+    ///bricks start. The first new brick takes the volume since the last brick and the
+    ///datetime of the first bar after it, like time bars are stamped with their start.
+    fn renko_bricks(&mut self, close_price: f64) -> Vec<BarData> {
+        let Some(mut anchor) = self.anchor else {
+            self.anchor = Some(close_price);
+            return Vec::new();
+        };
+
+        let mut bricks: Vec<BarData> = Vec::new();
+        while (close_price - anchor).abs() >= self.size {
+            let close = anchor + self.size * (close_price - anchor).signum();
+            let template = match bricks.last() {
+                None => self.current.take().unwrap(),
+                Some(last) => BarData {
+                    volume: 0.0,
+                    turnover: 0.0,
+                    ..last.clone()
+                },
+            };
+            bricks.push(BarData {
+                open_price: anchor,
+                high_price: anchor.max(close),
+                low_price: anchor.min(close),
+                close_price: close,
+                ..template
+            });
+            anchor = close;
+        }
+        self.anchor = Some(anchor);
+        bricks
+    }
 }
 
 #[derive(Debug)]
@@ -111,22 +565,38 @@ impl BarGenerator {
     //     self.last_tick = tick
 }
 
+#[cfg(feature = "dylib-strategies")]
 struct TaLib {
     pub sma: libloading::Symbol<'static, unsafe extern "C" fn(*const f64, i32, i32, *mut f64)>,
 }
 
-static TALIB_DYLIB: OnceLock<libloading::Library> = OnceLock::new();
-fn get_talib_dylib() -> &'static libloading::Library {
-    TALIB_DYLIB.get_or_init(|| unsafe { libloading::Library::new("TALIBDYLIB").unwrap() })
+#[cfg(feature = "dylib-strategies")]
+static TALIB_DYLIB: OnceLock<Result<libloading::Library, String>> = OnceLock::new();
+#[cfg(feature = "dylib-strategies")]
+fn get_talib_dylib() -> Result<&'static libloading::Library, String> {
+    TALIB_DYLIB
+        .get_or_init(|| unsafe {
+            libloading::Library::new("TALIBDYLIB").map_err(|e| e.to_string())
+        })
+        .as_ref()
+        .map_err(|e| e.clone())
 }
-static TALIB: OnceLock<TaLib> = OnceLock::new();
-fn get_talib() -> &'static TaLib {
-    TALIB.get_or_init(|| unsafe {
-        let sma: libloading::Symbol<'static, unsafe extern "C" fn(*const f64, i32, i32, *mut f64)> =
-            get_talib_dylib().get(b"sma").unwrap();
+// Load errors are kept, so a missing TA-Lib fails each call the same way
+#[cfg(feature = "dylib-strategies")]
+static TALIB: OnceLock<Result<TaLib, String>> = OnceLock::new();
+#[cfg(feature = "dylib-strategies")]
+fn get_talib() -> VnrsResult<&'static TaLib> {
+    TALIB
+        .get_or_init(|| unsafe {
+            let sma: libloading::Symbol<
+                'static,
+                unsafe extern "C" fn(*const f64, i32, i32, *mut f64),
+            > = get_talib_dylib()?.get(b"sma").map_err(|e| e.to_string())?;
 
-        TaLib { sma }
-    })
+            Ok(TaLib { sma })
+        })
+        .as_ref()
+        .map_err(|e| VnrsError::Ffi(format!("TA-Lib unavailable: {}", e)))
 }
 
 #[derive(Debug)]
@@ -142,6 +612,8 @@ pub struct ArrayManager {
     pub volume_array: Vec<f64>,
     pub turnover_array: Vec<f64>,
     pub open_interest_array: Vec<f64>,
+
+    streams: HashMap<Indicator, Streaming>,
 }
 
 impl ArrayManager {
@@ -157,9 +629,23 @@ impl ArrayManager {
             volume_array: vec![0f64; size],
             turnover_array: vec![0f64; size],
             open_interest_array: vec![0f64; size],
+            streams: HashMap::new(),
         }
     }
 
+    ///Keep indicator up to date on every update_bar from now on, in O(1) per bar. It only
+    ///sees bars updated after it is tracked, so track in on_init before loading bars.
+    pub fn track(&mut self, indicator: Indicator) {
+        self.streams
+            .entry(indicator)
+            .or_insert_with(|| Streaming::new(indicator));
+    }
+
+    ///Value of a tracked indicator, None if it isn't tracked or hasn't seen enough bars.
+    pub fn streaming(&self, indicator: Indicator) -> Option<f64> {
+        self.streams.get(&indicator)?.value(indicator)
+    }
+
     pub fn update_bar(&mut self, bar: &BarData) {
         self.count += 1;
         if (!self.inited) && self.count >= self.size {
@@ -180,19 +666,728 @@ impl ArrayManager {
         self.volume_array.push(bar.volume);
         self.turnover_array.push(bar.turnover);
         self.open_interest_array.push(bar.open_interest);
+
+        for (indicator, stream) in self.streams.iter_mut() {
+            stream.update(*indicator, bar);
+        }
     }
 
-    pub fn sma_array(&mut self, n: i64) -> Vec<f64> {
+    ///Simple moving average of the closes from the TA-Lib dylib.
+    #[cfg(feature = "dylib-strategies")]
+    pub fn sma_array(&mut self, n: i64) -> VnrsResult<Vec<f64>> {
+        let talib = get_talib()?;
         unsafe {
             let mut ret = Vec::new();
             ret.resize(self.close_array.len(), 0f64);
-            (get_talib().sma)(
+            (talib.sma)(
                 self.close_array.as_ptr(),
                 self.close_array.len() as i32,
                 n as i32,
                 ret.as_mut_ptr(),
             );
-            ret
+            Ok(ret)
+        }
+    }
+
+    ///Number of bars in the arrays that came from update_bar, the rest are zero padding.
+    pub fn filled(&self) -> usize {
+        self.count.min(self.size)
+    }
+
+    ///Whether the last bars of the arrays all came from update_bar, the window an indicator
+    ///needs. Scalar indicators are NaN and their _opt variants None until it is.
+    pub fn ready(&self, bars: usize) -> bool {
+        bars > 0 && bars <= self.filled()
+    }
+
+    ///Simple moving average of the last n closes. Unlike sma_array, indicators below are
+    ///computed in Rust and don't need the TA-Lib dylib.
+    pub fn sma(&self, n: usize) -> f64 {
+        if !self.ready(n) {
+            return f64::NAN;
+        }
+        mean(&self.close_array[self.size - n..])
+    }
+
+    ///Population standard deviation of the last n closes.
+    pub fn std(&self, n: usize) -> f64 {
+        if !self.ready(n) {
+            return f64::NAN;
         }
+        std_dev(&self.close_array[self.size - n..])
+    }
+
+    ///Realized volatility of the log returns over the last n bars, per bar.
+    pub fn realized_volatility(&self, n: usize) -> f64 {
+        if !self.ready(n + 1) {
+            return f64::NAN;
+        }
+        realized_volatility(&self.close_array[self.size - n - 1..])
+    }
+
+    ///Z-score of the last close against the last n closes.
+    pub fn zscore(&self, n: usize) -> f64 {
+        if !self.ready(n) {
+            return f64::NAN;
+        }
+        zscore(&self.close_array[self.size - n..])
+    }
+
+    ///Average true range with Wilder smoothing, NaN for the padding and the first n bars
+    ///after it.
+    pub fn atr_array(&self, n: usize) -> Vec<f64> {
+        let mut result = vec![f64::NAN; self.size];
+        let start = self.size - self.filled();
+        if n == 0 || !self.ready(n + 1) {
+            return result;
+        }
+
+        let true_range = |i: usize| {
+            let pre_close = self.close_array[i - 1];
+            (self.high_array[i] - self.low_array[i])
+                .max((self.high_array[i] - pre_close).abs())
+                .max((self.low_array[i] - pre_close).abs())
+        };
+        let mut atr = (start + 1..=start + n).map(true_range).sum::<f64>() / n as f64;
+        result[start + n] = atr;
+        for (i, value) in result.iter_mut().enumerate().skip(start + n + 1) {
+            atr = (atr * (n - 1) as f64 + true_range(i)) / n as f64;
+            *value = atr;
+        }
+        result
+    }
+
+    pub fn atr(&self, n: usize) -> f64 {
+        self.atr_array(n)[self.size - 1]
+    }
+
+    ///Relative strength index of closes with Wilder smoothing, seeded with the average of the
+    ///first n changes after the padding.
+    pub fn rsi(&self, n: usize) -> f64 {
+        if !self.ready(n + 1) {
+            return f64::NAN;
+        }
+
+        let start = self.size - self.filled();
+        let change = |i: usize| self.close_array[i] - self.close_array[i - 1];
+        let seed = start + 1..=start + n;
+        let mut gain = seed.clone().map(|i| change(i).max(0.0)).sum::<f64>() / n as f64;
+        let mut loss = seed.map(|i| (-change(i)).max(0.0)).sum::<f64>() / n as f64;
+        for i in start + n + 1..self.size {
+            gain = (gain * (n - 1) as f64 + change(i).max(0.0)) / n as f64;
+            loss = (loss * (n - 1) as f64 + (-change(i)).max(0.0)) / n as f64;
+        }
+
+        if gain + loss == 0.0 {
+            0.0
+        } else {
+            100.0 * gain / (gain + loss)
+        }
+    }
+
+    ///Commodity channel index of the typical price over the last n bars.
+    pub fn cci(&self, n: usize) -> f64 {
+        if !self.ready(n) {
+            return f64::NAN;
+        }
+
+        let typical_prices: Vec<f64> = (self.size - n..self.size)
+            .map(|i| (self.high_array[i] + self.low_array[i] + self.close_array[i]) / 3.0)
+            .collect();
+        let mean = mean(&typical_prices);
+        let deviation = typical_prices
+            .iter()
+            .map(|price| (price - mean).abs())
+            .sum::<f64>()
+            / n as f64;
+
+        if deviation == 0.0 {
+            0.0
+        } else {
+            (typical_prices[n - 1] - mean) / (0.015 * deviation)
+        }
+    }
+
+    ///Bollinger band of the last n closes, (up, down).
+    pub fn boll(&self, n: usize, dev: f64) -> (f64, f64) {
+        let mid = self.sma(n);
+        let std = self.std(n);
+        (mid + std * dev, mid - std * dev)
+    }
+
+    ///Donchian channel of the last n bars, (highest high, lowest low).
+    pub fn donchian(&self, n: usize) -> (f64, f64) {
+        if !self.ready(n) {
+            return (f64::NAN, f64::NAN);
+        }
+
+        let up = self.high_array[self.size - n..]
+            .iter()
+            .fold(f64::MIN, |a, &b| a.max(b));
+        let down = self.low_array[self.size - n..]
+            .iter()
+            .fold(f64::MAX, |a, &b| a.min(b));
+        (up, down)
+    }
+
+    pub fn sma_opt(&self, n: usize) -> Option<f64> {
+        finite(self.sma(n))
+    }
+
+    pub fn std_opt(&self, n: usize) -> Option<f64> {
+        finite(self.std(n))
+    }
+
+    pub fn realized_volatility_opt(&self, n: usize) -> Option<f64> {
+        finite(self.realized_volatility(n))
+    }
+
+    pub fn zscore_opt(&self, n: usize) -> Option<f64> {
+        finite(self.zscore(n))
+    }
+
+    pub fn atr_opt(&self, n: usize) -> Option<f64> {
+        finite(self.atr(n))
+    }
+
+    pub fn rsi_opt(&self, n: usize) -> Option<f64> {
+        finite(self.rsi(n))
+    }
+
+    pub fn cci_opt(&self, n: usize) -> Option<f64> {
+        finite(self.cci(n))
+    }
+
+    pub fn boll_opt(&self, n: usize, dev: f64) -> Option<(f64, f64)> {
+        let (up, down) = self.boll(n, dev);
+        finite(up).zip(finite(down))
+    }
+
+    pub fn donchian_opt(&self, n: usize) -> Option<(f64, f64)> {
+        let (up, down) = self.donchian(n);
+        finite(up).zip(finite(down))
+    }
+}
+
+///ArrayManagers of the symbols of a portfolio strategy keyed by vt_symbol, all of the same
+///size and tracking the same streaming indicators.
+#[derive(Debug)]
+pub struct PortfolioArrayManager {
+    pub size: usize,
+    managers: BTreeMap<String, ArrayManager>,
+    tracked: Vec<Indicator>,
+}
+
+impl PortfolioArrayManager {
+    pub fn new(size: usize) -> Self {
+        PortfolioArrayManager {
+            size,
+            managers: BTreeMap::new(),
+            tracked: Vec::new(),
+        }
+    }
+
+    ///Track indicator in the ArrayManager of every symbol, including those added later.
+    pub fn track(&mut self, indicator: Indicator) {
+        if !self.tracked.contains(&indicator) {
+            self.tracked.push(indicator);
+        }
+        for am in self.managers.values_mut() {
+            am.track(indicator);
+        }
+    }
+
+    ///Update the ArrayManager of the symbol of bar, adding one for a new symbol.
+    pub fn update_bar(&mut self, bar: &BarData) {
+        let size = self.size;
+        let tracked = &self.tracked;
+        self.managers
+            .entry(bar.vt_symbol())
+            .or_insert_with(|| {
+                let mut am = ArrayManager::new(size);
+                for indicator in tracked {
+                    am.track(*indicator);
+                }
+                am
+            })
+            .update_bar(bar);
+    }
+
+    ///Update with the bars of all symbols at one datetime, as passed to on_bars.
+    pub fn update_bars<'a>(&mut self, bars: impl IntoIterator<Item = &'a BarData>) {
+        for bar in bars {
+            self.update_bar(bar);
+        }
+    }
+
+    pub fn get(&self, vt_symbol: &str) -> Option<&ArrayManager> {
+        self.managers.get(vt_symbol)
+    }
+
+    pub fn vt_symbols(&self) -> impl Iterator<Item = &String> {
+        self.managers.keys()
+    }
+
+    ///Whether there is a symbol and every symbol has filled its arrays.
+    pub fn inited(&self) -> bool {
+        !self.managers.is_empty() && self.managers.values().all(|am| am.inited)
+    }
+
+    ///Value of each symbol at its latest bar, symbols with a NaN value are left out.
+    pub fn cross_section(&self, value: impl Fn(&ArrayManager) -> f64) -> BTreeMap<String, f64> {
+        self.managers
+            .iter()
+            .map(|(vt_symbol, am)| (vt_symbol.clone(), value(am)))
+            .filter(|(_, value)| !value.is_nan())
+            .collect()
+    }
+
+    ///Rank of each symbol by value across symbols, 1 for the lowest, ties sharing their
+    ///average rank.
+    pub fn rank(&self, value: impl Fn(&ArrayManager) -> f64) -> BTreeMap<String, f64> {
+        let section = self.cross_section(value);
+        let mut sorted: Vec<(&String, f64)> = section.iter().map(|(k, v)| (k, *v)).collect();
+        sorted.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let mut ranks = BTreeMap::new();
+        let mut start = 0;
+        while start < sorted.len() {
+            let end = start
+                + sorted[start..]
+                    .iter()
+                    .take_while(|(_, value)| *value == sorted[start].1)
+                    .count();
+            let rank = (start + end + 1) as f64 / 2.0;
+            for (vt_symbol, _) in &sorted[start..end] {
+                ranks.insert((*vt_symbol).clone(), rank);
+            }
+            start = end;
+        }
+        ranks
+    }
+
+    ///Z-score of each symbol by value across symbols, 0 if the values don't vary.
+    pub fn zscore(&self, value: impl Fn(&ArrayManager) -> f64) -> BTreeMap<String, f64> {
+        let section = self.cross_section(value);
+        let values: Vec<f64> = section.values().copied().collect();
+        let (mean, std) = (mean(&values), std_dev(&values));
+        section
+            .into_iter()
+            .map(|(vt_symbol, value)| {
+                let zscore = if std == 0.0 {
+                    0.0
+                } else {
+                    (value - mean) / std
+                };
+                (vt_symbol, zscore)
+            })
+            .collect()
+    }
+}
+
+///Some value unless it is NaN or infinite.
+fn finite(value: f64) -> Option<f64> {
+    value.is_finite().then_some(value)
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+///Population standard deviation.
+fn std_dev(values: &[f64]) -> f64 {
+    let mean = mean(values);
+    (values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / values.len() as f64)
+        .sqrt()
+}
+
+///Standard deviation of the log returns of closes, per bar and not annualized, NaN with
+///fewer than two closes.
+pub fn realized_volatility(closes: &[f64]) -> f64 {
+    if closes.len() < 2 {
+        return f64::NAN;
+    }
+    let returns: Vec<f64> = closes.windows(2).map(|w| (w[1] / w[0]).ln()).collect();
+    std_dev(&returns)
+}
+
+///Distance of the last value from the mean of values in standard deviations, 0 if they
+///don't vary.
+pub fn zscore(values: &[f64]) -> f64 {
+    let Some(last) = values.last() else {
+        return f64::NAN;
+    };
+    let std = std_dev(values);
+    if std == 0.0 {
+        0.0
+    } else {
+        (last - mean(values)) / std
+    }
+}
+
+///Whole volume losing at most risk when the price moves one stop distance, e.g. 2 ATR,
+///against it. size is the contract size, 0 volume if the stop distance isn't positive.
+pub fn atr_position_size(risk: f64, stop_distance: f64, size: f64) -> f64 {
+    let loss_per_unit = stop_distance * size;
+    if loss_per_unit.is_nan() || loss_per_unit <= 0.0 || !risk.is_finite() {
+        return 0.0;
+    }
+    (risk / loss_per_unit).floor().max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn invalid_input_is_an_error() {
+        let (symbol, exchange) = extract_vt_symbol("IF888.CFFEX").unwrap();
+        assert_eq!((symbol.as_str(), exchange), ("IF888", Exchange::CFFEX));
+        assert!(matches!(
+            extract_vt_symbol("IF888"),
+            Err(VnrsError::Setting(_))
+        ));
+        assert!(extract_vt_symbol("IF888.NOWHERE").is_err());
+
+        assert_eq!(round_to(4000.13, 0.2), 4000.2);
+        assert_eq!(round_to(4000.13, 0.0), 4000.13);
+        assert!(round_to(f64::NAN, 0.2).is_nan());
+    }
+
+    #[test]
+    fn engine_ids_are_unique() {
+        let ids: HashSet<u64> = (0..100).map(|_| new_engine_id()).collect();
+        assert_eq!(ids.len(), 100);
+    }
+
+    #[test]
+    fn run_ids_are_random_uuids() {
+        let ids: HashSet<String> = (0..100).map(|_| new_run_id()).collect();
+        assert_eq!(ids.len(), 100);
+        for id in &ids {
+            let groups: Vec<usize> = id.split('-').map(str::len).collect();
+            assert_eq!(groups, [8, 4, 4, 4, 12]);
+            assert_eq!(&id[14..15], "4");
+            assert!("89ab".contains(&id[19..20]));
+        }
+    }
+
+    #[test]
+    fn ids_do_not_collide_across_engines() {
+        // Two engines running the same strategy, each restarting its count per run
+        let mut first = IdGenerator::new(&format!("{}_double_ma_strategy", new_engine_id()));
+        let mut second = IdGenerator::new(&format!("{}_double_ma_strategy", new_engine_id()));
+
+        let mut ids = HashSet::new();
+        for generator in [&mut first, &mut second] {
+            for _ in 0..2 {
+                generator.reset();
+                for _ in 0..50 {
+                    ids.insert(generator.next_id());
+                }
+            }
+        }
+        assert_eq!(ids.len(), 100);
+    }
+
+    #[test]
+    fn sequence_follows_generation_order() {
+        let mut generator = IdGenerator::new("1_my_strategy");
+        let first = generator.next_id();
+        let second = generator.next_id();
+        assert_eq!(IdGenerator::sequence(&first), 1);
+        assert_eq!(IdGenerator::sequence(&second), 2);
+        assert_eq!(generator.count(), 2);
+    }
+
+    #[test]
+    fn window_bars_finish_with_their_last_bar() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 22)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        // 10:00 to 11:29, then the session resumes at 13:00
+        let bars: Vec<BarData> = (0..90)
+            .chain(180..181)
+            .map(|minute| BarData {
+                datetime: start + TimeDelta::minutes(minute),
+                high_price: minute as f64,
+                close_price: minute as f64,
+                volume: 1.0,
+                ..Default::default()
+            })
+            .collect();
+
+        let mut builder = WindowBarBuilder::new(Interval::HOUR, 1);
+        let finished: Vec<(usize, BarData)> = bars
+            .iter()
+            .enumerate()
+            .flat_map(|(i, bar)| {
+                let bar_end = bar.datetime + TimeDelta::minutes(1);
+                builder
+                    .update_bar(bar, bar_end)
+                    .into_iter()
+                    .map(move |window_bar| (i, window_bar))
+            })
+            .collect();
+
+        assert_eq!(finished.len(), 2);
+        let (i, hour_bar) = &finished[0];
+        assert_eq!(*i, 59);
+        assert_eq!(hour_bar.datetime, start);
+        assert_eq!(hour_bar.interval, Interval::HOUR);
+        assert_eq!(hour_bar.close_price, 59.0);
+        assert_eq!(hour_bar.volume, 60.0);
+        // The 11:00 bar is cut short by the break
+        let (i, hour_bar) = &finished[1];
+        assert_eq!(*i, 90);
+        assert_eq!(hour_bar.close_price, 89.0);
+        assert_eq!(hour_bar.volume, 30.0);
+
+        assert_eq!(resample_bars(&bars, Interval::HOUR, 1).len(), 3);
+    }
+
+    #[test]
+    fn second_and_weekly_windows() {
+        // Wednesday 2024-01-03 09:00:03, one bar a second
+        let start = NaiveDate::from_ymd_opt(2024, 1, 3)
+            .unwrap()
+            .and_hms_opt(9, 0, 3)
+            .unwrap();
+        let bars: Vec<BarData> = (0..20)
+            .map(|second| BarData {
+                datetime: start + TimeDelta::seconds(second),
+                volume: 1.0,
+                ..Default::default()
+            })
+            .collect();
+        let window_bars = resample_bars(&bars, Interval::SECONDS(5), 1);
+        assert_eq!(window_bars.len(), 5);
+        assert_eq!(window_bars[0].interval, Interval::SECONDS(5));
+        assert_eq!(window_bars[0].volume, 2.0);
+        assert_eq!(window_bars[1].datetime, start + TimeDelta::seconds(2));
+        assert_eq!(window_bars[1].volume, 5.0);
+
+        // Daily bars from Wednesday to the next Tuesday
+        let days: Vec<BarData> = (0..7)
+            .map(|day| BarData {
+                datetime: start + TimeDelta::days(day),
+                volume: 1.0,
+                ..Default::default()
+            })
+            .collect();
+        let weeks = resample_bars(&days, Interval::WEEKLY, 1);
+        assert_eq!(weeks.len(), 2);
+        assert_eq!(
+            weeks[1].datetime,
+            NaiveDate::from_ymd_opt(2024, 1, 8)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        );
+        assert_eq!((weeks[0].volume, weeks[1].volume), (5.0, 2.0));
+    }
+
+    #[test]
+    fn volatility_and_sizing() {
+        let closes = [100.0, 110.0, 100.0, 110.0];
+        // Log returns alternate between ln(1.1) and -ln(1.1)
+        let volatility = realized_volatility(&closes);
+        let expected = (1.1f64).ln() * (8.0f64 / 9.0).sqrt();
+        assert!((volatility - expected).abs() < 1e-12);
+        assert!(realized_volatility(&[100.0]).is_nan());
+
+        assert_eq!(zscore(&[1.0, 2.0, 3.0]), (1.5f64).sqrt());
+        assert_eq!(zscore(&[5.0, 5.0]), 0.0);
+
+        // 10000 at risk, 2 ATR of 15 points on a contract of 300
+        assert_eq!(atr_position_size(10_000.0, 30.0, 300.0), 1.0);
+        assert_eq!(atr_position_size(10_000.0, 0.0, 300.0), 0.0);
+    }
+
+    #[test]
+    fn indicators_ignore_padding() {
+        // StockCharts RSI example, TA-Lib RSI(14) of it ends 62.88
+        let closes = [
+            44.34, 44.09, 44.15, 43.61, 44.33, 44.83, 45.10, 45.42, 45.84, 46.08, 45.89, 46.03,
+            45.61, 46.28, 46.28, 46.00, 46.03, 46.41, 46.22, 45.64, 46.21,
+        ];
+        let managers = |size: usize, count: usize| {
+            let mut am = ArrayManager::new(size);
+            for close in &closes[..count] {
+                am.update_bar(&BarData {
+                    open_price: *close,
+                    high_price: close + 0.3,
+                    low_price: close - 0.4,
+                    close_price: *close,
+                    ..Default::default()
+                });
+            }
+            am
+        };
+
+        let padded = managers(30, closes.len());
+        let full = managers(closes.len(), closes.len());
+        assert!(!padded.inited);
+        assert_eq!(padded.filled(), 21);
+        assert!((padded.rsi(14) - 62.88).abs() < 0.01);
+        assert_eq!(padded.rsi(14), full.rsi(14));
+        assert_eq!(padded.atr(5), full.atr(5));
+        assert!(padded.atr_array(5)[..14].iter().all(|v| v.is_nan()));
+        assert!((padded.sma(3) - (46.22 + 45.64 + 46.21) / 3.0).abs() < 1e-12);
+
+        // Windows reaching into the padding are NaN rather than computed over zeros
+        let short = managers(30, 14);
+        assert!(short.rsi(14).is_nan());
+        assert_eq!(short.rsi_opt(14), None);
+        assert_eq!(short.sma_opt(15), None);
+        assert!(short.sma_opt(14).is_some());
+        assert_eq!(short.donchian_opt(20), None);
+        assert_eq!(short.boll_opt(20, 2.0), None);
+        assert!(short.cci(0).is_nan());
+        assert!(short.realized_volatility(14).is_nan());
+    }
+
+    #[test]
+    fn streaming_indicators_match_arrays() {
+        let mut am = ArrayManager::new(30);
+        let indicators = [
+            Indicator::Sma(5),
+            Indicator::Std(5),
+            Indicator::Atr(5),
+            Indicator::Rsi(14),
+            Indicator::DonchianUp(10),
+            Indicator::DonchianDown(10),
+        ];
+        for indicator in indicators {
+            am.track(indicator);
+        }
+
+        // Wilder smoothing of the arrays restarts from the oldest bar, so they only match
+        // until the arrays are full
+        for i in 0..30 {
+            let close = 100.0 + (i as f64 * 0.7).sin() * 5.0 + i as f64 * 0.1;
+            am.update_bar(&BarData {
+                open_price: close - 0.2,
+                high_price: close + 0.5,
+                low_price: close - 0.8,
+                close_price: close,
+                ..Default::default()
+            });
+            let (up, down) = am.donchian(10);
+            let expected = [am.sma(5), am.std(5), am.atr(5), am.rsi(14), up, down];
+            for (indicator, expected) in indicators.iter().zip(expected) {
+                match am.streaming(*indicator) {
+                    Some(value) => assert!((value - expected).abs() < 1e-9, "{:?}", indicator),
+                    None => assert!(expected.is_nan(), "{:?}", indicator),
+                }
+            }
+        }
+        assert_eq!(am.streaming(Indicator::Ema(5)), None);
+    }
+
+    #[test]
+    fn portfolio_cross_section() {
+        let bar = |symbol: &str, close: f64| BarData {
+            symbol: symbol.into(),
+            exchange: Exchange::SHFE,
+            open_price: close,
+            high_price: close,
+            low_price: close,
+            close_price: close,
+            ..Default::default()
+        };
+        let mut portfolio = PortfolioArrayManager::new(2);
+        portfolio.track(Indicator::Sma(2));
+        portfolio.update_bars(&[bar("rb888", 10.0), bar("hc888", 20.0)]);
+        assert!(!portfolio.inited());
+        portfolio.update_bars(&[bar("rb888", 12.0), bar("hc888", 22.0), bar("cu888", 12.0)]);
+        assert!(!portfolio.inited());
+        assert_eq!(
+            portfolio
+                .get("rb888.SHFE")
+                .unwrap()
+                .streaming(Indicator::Sma(2)),
+            Some(11.0)
+        );
+        // cu888 joined late and has no average yet
+        let sma = portfolio.cross_section(|am| am.sma(2));
+        assert_eq!(sma.len(), 2);
+
+        let close = |am: &ArrayManager| am.close_array[am.size - 1];
+        let ranks = portfolio.rank(close);
+        assert_eq!(ranks["hc888.SHFE"], 3.0);
+        assert_eq!(ranks["rb888.SHFE"], 1.5);
+        assert_eq!(ranks["cu888.SHFE"], 1.5);
+
+        let zscores = portfolio.zscore(close);
+        assert!((zscores["hc888.SHFE"] - 2.0f64.sqrt()).abs() < 1e-12);
+        assert!(zscores.values().sum::<f64>().abs() < 1e-12);
+    }
+
+    #[test]
+    fn renko_range_and_volume_bars() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 22)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        let bars: Vec<BarData> = [100.0, 101.0, 103.5, 102.0, 99.0, 99.5]
+            .iter()
+            .enumerate()
+            .map(|(minute, close)| BarData {
+                datetime: start + TimeDelta::minutes(minute as i64),
+                interval: Interval::MINUTE,
+                volume: 10.0,
+                open_price: *close,
+                high_price: close + 0.5,
+                low_price: close - 0.5,
+                close_price: *close,
+                ..Default::default()
+            })
+            .collect();
+
+        // Bricks of 4 ticks of 0.5
+        let renko = resample_alt_bars(&bars, Interval::RENKO(4), 0.5).unwrap();
+        let closes: Vec<f64> = renko.iter().map(|bar| bar.close_price).collect();
+        assert_eq!(closes, vec![102.0, 100.0]);
+        assert_eq!(renko[0].interval, Interval::RENKO(4));
+        assert_eq!((renko[0].open_price, renko[0].volume), (100.0, 30.0));
+        // Stamped with the first bar after the previous brick
+        assert_eq!(renko[1].datetime, start + TimeDelta::minutes(3));
+
+        let range = resample_alt_bars(&bars, Interval::RANGE(6), 0.5).unwrap();
+        assert_eq!(range.len(), 3);
+        assert_eq!((range[0].high_price, range[0].low_price), (104.0, 99.5));
+        assert_eq!(range[1].datetime, start + TimeDelta::minutes(3));
+        assert_eq!(range[2].volume, 10.0);
+
+        let volume = resample_alt_bars(&bars, Interval::VOLUME(25), 0.5).unwrap();
+        let volumes: Vec<f64> = volume.iter().map(|bar| bar.volume).collect();
+        assert_eq!(volumes, vec![30.0, 30.0]);
+
+        assert!(AltBarBuilder::new(Interval::MINUTE, 0.5).is_err());
+        assert!(AltBarBuilder::new(Interval::RENKO(4), 0.0).is_err());
+
+        // Live ticks add the change of the cumulative volume
+        let mut builder = AltBarBuilder::new(Interval::VOLUME(5), 0.5).unwrap();
+        let tick = |volume: f64, price: f64| TickData {
+            datetime: start,
+            volume,
+            last_price: price,
+            ..Default::default()
+        };
+        assert!(builder.update_tick(&tick(100.0, 10.0)).is_empty());
+        assert!(builder.update_tick(&tick(103.0, 11.0)).is_empty());
+        let finished = builder.update_tick(&tick(106.0, 9.0));
+        assert_eq!(finished[0].volume, 6.0);
+        assert_eq!((finished[0].high_price, finished[0].low_price), (11.0, 9.0));
     }
 }
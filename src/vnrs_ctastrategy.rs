@@ -1,3 +1,63 @@
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
 pub mod backtesting;
 pub mod base;
-pub mod template;
\ No newline at end of file
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod batch;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod capacity;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod clustering;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod comparison;
+pub mod contract;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod crossval;
+#[cfg(feature = "web")]
+pub mod dashboard;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod determinism;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod diff;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod excursion;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod execution;
+pub mod fill;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod hook;
+#[cfg(feature = "ipc")]
+pub mod ipc;
+pub mod lifecycle;
+#[cfg(feature = "notebook")]
+pub mod notebook;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod observer;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod optimize;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod portfolio;
+pub mod quick;
+pub mod reconcile;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod report;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod research;
+pub mod returns;
+#[cfg(feature = "rpc")]
+pub mod rpc_service;
+pub mod schedule;
+pub mod sdk;
+pub mod settlement;
+pub mod supervisor;
+pub mod target;
+pub mod template;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod tpe;
+pub mod trace;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+pub mod vnpy;
\ No newline at end of file
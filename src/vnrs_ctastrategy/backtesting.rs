@@ -1,36 +1,74 @@
 use chrono;
-use chrono::{Datelike, Days, Local, NaiveDate, NaiveDateTime, TimeDelta};
-use polars::lazy::dsl::{col, lit, when};
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use log::Level;
 use polars::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::any::Any;
 use std::borrow::BorrowMut;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::ffi::{c_char, CStr};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ffi::c_char;
+use std::fmt;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
-use std::rc::Rc;
-use std::str::FromStr;
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use strum::EnumString;
 
 use super::base::{
-    get_interval_delta_map, BacktestingMode, EngineType, ExternClass, ExternInstance, StopOrder,
-    StopOrderStatus, VTable, INTERVAL_DELTA_MAP, STOPORDER_PREFIX,
+    format_setting, from_c_str, get_interval_delta, parse_setting, strategy_name, validate_setting,
+    BacktestingMode, BracketOrder, EngineContext, EngineType, ExternClass, ExternInstance,
+    LotRounding, PositionDetail, StopOrder, StopOrderStatus, TimeInForce, VTable, DATA_FILENAME,
+    EVENT_CTA_LOG, INTERVAL_DELTA_MAP, STOPORDER_PREFIX,
 };
+use super::clustering::TimeOfDayReport;
+use super::contract::{builtin_spec, ContractRegistry};
+use super::excursion::{self, ExcursionTracker, RoundTrip};
+use super::fill::{Fill, FillHook};
+use super::hook::{EventHook, HookEvent};
+use super::lifecycle::OrderTimeline;
+use super::observer::BacktestObserver;
+use super::quick::{FnStrategy, QuickContext, FN_STRATEGY_BARS};
+use super::report::{statistics_rows, StatisticsFormatter};
+use super::research::FactorRecorder;
+use super::returns::ReturnsAnalyzer;
+use super::sdk::{self, strategy_class, CtaStrategy};
+use super::settlement::{DayVwap, Settlement, SettlementConfig, SettlementPrice};
+use super::target::TargetPosGateway;
 use super::template::CtaTemplate;
-use crate::vnrs::trader::constant::{Direction, Exchange, Interval, Offset, Status};
-use crate::vnrs::trader::database::get_database;
-use crate::vnrs::trader::object::{BarData, MixData, OrderData, TickData, TradeData};
-use crate::vnrs::trader::utility::{extract_vt_symbol, round_to};
+use super::trace::{TraceOrder, TraceRecord, TraceWriter};
+use crate::error::{VnrsError, VnrsResult};
+use crate::vnrs::event::{Event, EventEngine};
+use crate::vnrs::trader::audit::AuditRecorder;
+use crate::vnrs::trader::cache::BarCache;
+use crate::vnrs::trader::calendar::{self, TradingCalendar};
+use crate::vnrs::trader::constant::{
+    Currency, Direction, Exchange, Interval, IntervalKind, Offset, OrderType, Status,
+};
+use crate::vnrs::trader::converter::OffsetConverter;
+use crate::vnrs::trader::database::{adjust_bars, get_database, AdjustMode, BacktestRecord};
+use crate::vnrs::trader::datafeed::get_datafeed;
+use crate::vnrs::trader::fx::FxRates;
+use crate::vnrs::trader::history::{BarHistory, BAR_BYTES};
+use crate::vnrs::trader::mapping::get_symbol_mapper;
+use crate::vnrs::trader::object::{
+    BarData, ContractData, GatewayName, LogData, OrderData, OrderRequest, PositionData, Symbol,
+    TickData, TradeData,
+};
+use crate::vnrs::trader::setting::get_settings;
+use crate::vnrs::trader::timeseries::TimeSeries;
+use crate::vnrs::trader::utility::{
+    extract_vt_symbol, load_json, new_run_id, resample_alt_bars, resample_bars, round_to,
+    save_json, ActiveIds, IdGenerator, WindowBarBuilder,
+};
 
 #[derive(Default)]
 pub struct BacktestingEngine {
     engine_type: EngineType,
-    gateway_name: &'static str,
+    gateway_name: GatewayName,
 
     vt_symbol: String,
-    symbol: String,
+    symbol: Symbol,
     exchange: Exchange,
     pub start: NaiveDateTime,
     pub end: NaiveDateTime,
@@ -39,56 +77,275 @@ pub struct BacktestingEngine {
     pub size: f64,
     pub pricetick: f64,
     pub capital: f64,
+    ///Net position held before the first bar.
+    pub initial_pos: f64,
+    ///Price the initial position is marked from, the open of the first bar.
+    initial_price: f64,
+    ///Annual risk-free rate as a fraction, 0.02 for 2%.
     risk_free: f64,
-    annual_days: i64,
+    ///Annual rates from each date on, overriding risk_free from the first date.
+    risk_free_series: TimeSeries<f64, NaiveDate>,
+    ///Derived from the market of the symbol if None.
+    annual_days: Option<i64>,
     half_life: i64,
     mode: BacktestingMode,
 
     strategy_class: Arc<ExternClass>,
     strategy: ExternInstance,
+    setting: String,
     tick: TickData,
     bar: BarData,
     datetime: NaiveDateTime,
 
     pub interval: Interval,
+    ///(source interval, window) when bars are aggregated on load.
+    resample: Option<(Interval, i64)>,
+    ///Calendar and day start daily bars are resampled by, calendar days if None.
+    daily_sessions: Option<(TradingCalendar, NaiveTime)>,
+    ///Adjustment of the prices of stock bars for splits and dividends.
+    adjust: AdjustMode,
+    ///Builders of the bars of secondary intervals pushed along the replayed bars.
+    window_bars: Vec<WindowBarBuilder>,
+    bar_cache: Option<Arc<BarCache>>,
     days: i32,
     //     callback: Callable = None
-    history_data: Arc<RwLock<Vec<MixData>>>,
-    stop_order_count: i64,
-    stop_orders: HashMap<String, Rc<RefCell<StopOrder>>>,
-    active_stop_orders: HashMap<String, Rc<RefCell<StopOrder>>>,
+    history_data: BarHistory,
+    ///Ticks replayed instead of history_data in tick mode.
+    tick_data: Vec<TickData>,
+    ///Bytes of history load_data keeps in memory before it streams the rest, see
+    ///set_memory_limit.
+    memory_limit: Option<usize>,
+    ///Batches of history left to load during replay when it's streamed.
+    stream: Option<HistoryStream>,
+    ///Failure loading a batch of the streamed history, returned by run_backtesting.
+    load_error: Option<VnrsError>,
+    ///Random id of the run scoping its order and trade ids, kept by checkpoints so that the
+    ///ids of a restored run go on from those before.
+    run_id: String,
+    stop_order_ids: IdGenerator,
+    stop_orders: HashMap<String, StopOrder>,
+    active_stop_orders: ActiveIds,
+
+    limit_order_ids: IdGenerator,
+    limit_orders: HashMap<String, OrderData>,
+    active_limit_orders: ActiveIds,
+    ///Sequences of the active orders being crossed, kept to reuse its allocation.
+    cross_sequences: Vec<i64>,
+    ///Exit legs of bracket orders by vt_orderid of their entry, until it's traded.
+    bracket_entries: HashMap<String, BracketOrder>,
+    ///vt_orderids of exit legs to cancel once the order of the key is filled.
+    oco_orders: HashMap<String, Vec<String>>,
+    ///Datetime resting orders are cancelled at, by vt_orderid or stop_orderid.
+    order_expiries: HashMap<String, NaiveDateTime>,
+
+    trade_ids: IdGenerator,
+    trades: HashMap<String, TradeData>,
+    ///Net position booked from trades, mirrored into the strategy.
+    pos: f64,
+
+    logs: Vec<LogData>,
+    ///Bars of history data replayed, replay resumes after them when restored from a checkpoint.
+    replay_count: usize,
+    ///Set from the start of a replay until the strategy is stopped after the last bar.
+    replay: Option<ReplayRun>,
+    ///Bar the history data is materialized into during replay.
+    replay_bar: BarData,
+    ///Set by pause, cleared when the replay stops for it.
+    paused: Arc<AtomicBool>,
+    ///Set by cancel, stays set so that the replay isn't started again.
+    cancelled: Arc<AtomicBool>,
+    ///Setting updates pushed through a SettingQueue, applied before the next bar.
+    pending_settings: Arc<Mutex<Vec<String>>>,
+    ///Records setting updates of the strategy, see set_audit_recorder.
+    audit_recorder: Option<Arc<AuditRecorder>>,
+    ///Warm-up bars requested by the strategy in on_init, pushed into on_bar after it returns.
+    warmup_bars: Vec<BarData>,
+    ///Schedule of on_timer calls, off unless set_timer is called.
+    timer: Timer,
+    risk_limits: RiskLimits,
+    ///Margin ratio when equity is sampled on every bar, see set_equity_sampling.
+    equity_sampling: Option<f64>,
+    equity_samples: Vec<EquitySample>,
+    exposure: ExposureCounters,
+    excursion: ExcursionTracker,
+    ///Factors emitted by the strategy in research mode.
+    factor_recorder: Option<FactorRecorder>,
+    ///Lifecycle of every limit order of the run.
+    order_timeline: OrderTimeline,
+    ///Set once a risk limit is breached, no more orders are accepted after it.
+    risk_halt: Option<RiskHalt>,
+    ///Strategy variables of a restored checkpoint, applied once the strategy is inited.
+    resume_variables: Option<Vec<(String, String)>>,
+    ///(bars between checkpoints, callback receiving the serialized state).
+    checkpoint_callback: Option<(usize, CheckpointCallback)>,
+    daily_results: BTreeMap<NaiveDate, DailyResult>,
+    daily_df: Option<DataFrame>,
+    context: Option<Box<EngineContext>>,
+
+    output_callback: Option<OutputCallback>,
+    progress_callback: Option<ProgressCallback>,
+    ///Receives every order update and trade as it happens.
+    event_hook: Option<EventHook>,
+    observers: Vec<Box<dyn BacktestObserver>>,
+    ///Writer of the state after every bar when debugging.
+    debug_trace: Option<TraceWriter>,
+    high_balance: f64,
+    ///Net pnl of the trading days before the one being replayed, final once a day is over.
+    closed_net_pnl: f64,
+    sync_data: bool,
+    perf_counters: bool,
+    ///Fill orders across the depth of the tick instead of at level 1, tick mode only.
+    depth_fill: bool,
+    ///Coefficient of the square root market impact on fill prices, 0 without impact.
+    volume_impact: f64,
+    ///Adjust fill prices after the impact, in order.
+    fill_hooks: Vec<FillHook>,
+    ///Annual rates charged on the borrowed notional of long and short positions.
+    financing_rates: (f64, f64),
+    statistics_formatter: StatisticsFormatter,
+    ///Volume step of orders and the position, min_volume of the contract if 0.
+    lot_size: f64,
+    lot_rounding: LotRounding,
+    offset_converter: OffsetConverter,
+    event_engine: Option<Arc<EventEngine>>,
+    trading_day: TradingDayBoundary,
+    ///Daily settlement, off unless set_settlement is called.
+    settlement: Option<SettlementConfig>,
+    settlements: Vec<Settlement>,
+    settlement_vwap: DayVwap,
+    ///Trading date settled last, its close is the settlement price from then on.
+    settled_date: Option<NaiveDate>,
+    contract: ContractData,
+    contract_registry: Option<Arc<ContractRegistry>>,
+    ///Currency pnl is converted into and the rates to convert with, None to keep the pnl in
+    ///the currency of the contract.
+    base_currency: Option<(Currency, FxRates)>,
+}
 
-    limit_order_count: i64,
-    limit_orders: HashMap<String, Rc<RefCell<OrderData>>>,
-    active_limit_orders: HashMap<String, Rc<RefCell<OrderData>>>,
+///Volume below which a lot counts as closed.
+const POS_TOLERANCE: f64 = 1e-9;
 
-    trade_count: i64,
-    trades: HashMap<String, Rc<RefCell<TradeData>>>,
+type OutputCallback = Box<dyn Fn(&str) + Send + Sync>;
+type ProgressCallback = Box<dyn Fn(&BacktestingProgress) + Send + Sync>;
+type CheckpointCallback = Box<dyn Fn(&str) + Send + Sync>;
+
+///Batches of history loaded during replay instead of by load_data, once the bars loaded are
+///over the memory limit. Only the batch being replayed is kept in history_data.
+struct HistoryStream {
+    interval: Interval,
+    ///Range of the next batch.
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    batch: TimeDelta,
+    interval_delta: TimeDelta,
+    ///Bars of the batches before the one in history_data.
+    offset: usize,
+}
+
+///Progress callbacks over a replay, the output is printed every tenth of it.
+const PROGRESS_STEPS: usize = 100;
+
+///Weight of the latest progress step in the moving average of the replay throughput.
+const THROUGHPUT_SMOOTHING: f64 = 0.2;
+
+///Replay started and not yet finished, with the counts of its perf counters.
+struct ReplayRun {
+    started: Instant,
+    order_count: i64,
+    resume_from: usize,
+    progress_at: Instant,
+    progress_count: usize,
+    printed_tenths: usize,
+    bars_per_sec: f64,
+}
+
+impl ReplayRun {
+    ///Moving average of the bars replayed per second, updated with the bars replayed so far.
+    fn throughput(&mut self, replay_count: usize) -> f64 {
+        let now = Instant::now();
+        let seconds = now.duration_since(self.progress_at).as_secs_f64();
+        if seconds > 0.0 && replay_count > self.progress_count {
+            let rate = (replay_count - self.progress_count) as f64 / seconds;
+            self.bars_per_sec = if self.bars_per_sec > 0.0 {
+                THROUGHPUT_SMOOTHING * rate + (1.0 - THROUGHPUT_SMOOTHING) * self.bars_per_sec
+            } else {
+                rate
+            };
+        }
+        self.progress_at = now;
+        self.progress_count = replay_count;
+        self.bars_per_sec
+    }
+}
+
+type DailyColumn = (&'static str, fn(&DailyResult) -> f64);
+
+///Handle to change the parameters of the running strategy from another thread, e.g. a control
+///API. Updates are checked when pushed and applied in order before the next bar is replayed.
+#[derive(Clone)]
+pub struct SettingQueue {
+    parameters: Arc<Vec<(String, String)>>,
+    pending: Arc<Mutex<Vec<String>>>,
+}
 
-    logs: Vec<String>,
-    daily_results: HashMap<NaiveDate, DailyResult>,
-    daily_df: Option<Rc<RefCell<DataFrame>>>,
-    v_table: Option<VTable>,
+impl SettingQueue {
+    ///Queue a "name:value,name:value" update, an error if it doesn't fit the parameters of the
+    ///strategy class.
+    pub fn push(&self, setting: &str) -> VnrsResult<()> {
+        validate_setting(&self.parameters, &parse_setting(setting))?;
+        self.pending.lock().unwrap().push(setting.to_string());
+        Ok(())
+    }
 }
 
+// Engine can be moved into a worker thread, share it across threads behind a Mutex.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<BacktestingEngine>();
+};
+
 impl BacktestingEngine {
     pub fn new() -> Self {
         let mut this = BacktestingEngine {
             engine_type: EngineType::BACKTESTING,
-            gateway_name: "BACKTESTING",
-            v_table: None,
+            gateway_name: "BACKTESTING".into(),
+            run_id: new_run_id(),
+            context: None,
             ..Default::default()
         };
-        this.v_table = Some(VTable {
+        this.context = Some(EngineContext::new(VTable {
             abi_load_bar: BacktestingEngine::abi_load_bar,
             abi_drop_vec_bar_data: BacktestingEngine::abi_drop_vec_bar_data,
             abi_send_order: BacktestingEngine::abi_send_order,
             abi_drop_vec_string: BacktestingEngine::abi_drop_vec_string,
             abi_cancel_all: BacktestingEngine::abi_cancel_all,
-        });
-        eprintln!("this p:{:p}", &this);
+            abi_get_pos: BacktestingEngine::abi_get_pos,
+            abi_get_position_detail: BacktestingEngine::abi_get_position_detail,
+            abi_get_pricetick: BacktestingEngine::abi_get_pricetick,
+            abi_get_size: BacktestingEngine::abi_get_size,
+            abi_get_engine_type: BacktestingEngine::abi_get_engine_type,
+            abi_get_stop_order: BacktestingEngine::abi_get_stop_order,
+            abi_drop_stop_order: BacktestingEngine::abi_drop_stop_order,
+            abi_get_order: BacktestingEngine::abi_get_order,
+            abi_drop_order_data: BacktestingEngine::abi_drop_order_data,
+            abi_get_trades_by_order: BacktestingEngine::abi_get_trades_by_order,
+            abi_drop_vec_trade_data: BacktestingEngine::abi_drop_vec_trade_data,
+            abi_emit_factor: BacktestingEngine::abi_emit_factor,
+            abi_get_contract: BacktestingEngine::abi_get_contract,
+            abi_drop_contract_data: BacktestingEngine::abi_drop_contract_data,
+            abi_send_bracket_order: BacktestingEngine::abi_send_bracket_order,
+            abi_cancel_order: BacktestingEngine::abi_cancel_order,
+            abi_set_expiry: BacktestingEngine::abi_set_expiry,
+            abi_load_bar_callback: BacktestingEngine::abi_load_bar_callback,
+            abi_send_order_with_reference: BacktestingEngine::abi_send_order_with_reference,
+            abi_load_bar_sized: BacktestingEngine::abi_load_bar_sized,
+        }));
         this
     }
+    ///capital is the cash the backtest starts with. A size or pricetick of 0 is inferred from
+    ///vt_symbol for common futures and crypto pairs, see contract::builtin_spec, and an
+    ///annual_days of 0 from the exchange and product, see set_annual_days.
+    #[allow(clippy::too_many_arguments)]
     pub fn set_parameters(
         &mut self,
         vt_symbol: &str,
@@ -104,11 +361,11 @@ impl BacktestingEngine {
         risk_free: f64,
         annual_days: i64,
         half_life: i64,
-    ) {
+    ) -> VnrsResult<()> {
+        let (symbol, exchange) = self.check(extract_vt_symbol(vt_symbol))?;
         self.vt_symbol = vt_symbol.to_string();
-        let v: Vec<&str> = vt_symbol.split(".").collect();
-        self.symbol = v[0].to_string();
-        self.exchange = Exchange::from_str(v[1]).unwrap();
+        self.symbol = symbol.as_str().into();
+        self.exchange = exchange;
         self.interval = interval;
         self.start = start;
         self.end = end;
@@ -119,8 +376,168 @@ impl BacktestingEngine {
         self.capital = capital;
         self.mode = mode;
         self.risk_free = risk_free;
-        self.annual_days = annual_days;
+        self.annual_days = (annual_days > 0).then_some(annual_days);
         self.half_life = half_life;
+        self.apply_builtin_spec();
+
+        self.set_contract(ContractData {
+            gateway_name: self.gateway_name,
+            symbol: self.symbol,
+            exchange: self.exchange,
+            size: self.size,
+            pricetick: self.pricetick,
+            currency: builtin_spec(&self.vt_symbol).map_or(Currency::CNY, |spec| spec.currency),
+            ..Default::default()
+        });
+        self.apply_contract_registry();
+        Ok(())
+    }
+
+    ///Fill in a size or pricetick of 0 from the built-in spec of the product, and a rate of 0
+    ///too when the size is, as it wasn't given for this contract.
+    fn apply_builtin_spec(&mut self) {
+        if self.size != 0.0 && self.pricetick != 0.0 {
+            return;
+        }
+        let Some(spec) = builtin_spec(&self.vt_symbol) else {
+            self.output(&format!(
+                "{}没有内置合约参数，请指定合约乘数和价格跳动",
+                self.vt_symbol
+            ));
+            return;
+        };
+        if self.size == 0.0 {
+            self.size = spec.size;
+            if self.rate == 0.0 {
+                self.rate = spec.rate.unwrap_or_default();
+            }
+        }
+        if self.pricetick == 0.0 {
+            self.pricetick = spec.pricetick;
+        }
+        self.output(&format!(
+            "使用{}内置合约参数：合约乘数{}，价格跳动{}，手续费率{}",
+            spec.name, self.size, self.pricetick, self.rate
+        ));
+    }
+
+    ///Annual risk-free rates in effect from each date on, e.g. from a yield history, for
+    ///backtests spanning years of different rates. Days before the first date use the
+    ///risk_free given to set_parameters.
+    pub fn set_risk_free_series(&mut self, rates: impl IntoIterator<Item = (NaiveDate, f64)>) {
+        self.risk_free_series = rates.into_iter().collect();
+    }
+
+    ///Log return of the risk-free rate over one trading day of date, the annual rate
+    ///compounded over get_annual_days days.
+    fn daily_risk_free(&self, date: NaiveDate) -> f64 {
+        let annual_rate = *self.risk_free_series.asof(date).unwrap_or(&self.risk_free);
+        (1.0 + annual_rate).ln() / self.get_annual_days() as f64
+    }
+
+    ///Annualize statistics over annual_days trading days a year, None derives them from the
+    ///exchange and product of the contract like an annual_days of 0 given to set_parameters.
+    pub fn set_annual_days(&mut self, annual_days: Option<i64>) {
+        self.annual_days = annual_days.filter(|&days| days > 0);
+    }
+
+    ///Trading days a year statistics are annualized with, the annual_days given to
+    ///set_parameters or else the default of the exchange and product of the contract.
+    pub fn get_annual_days(&self) -> i64 {
+        self.annual_days
+            .unwrap_or_else(|| calendar::get_annual_days(self.exchange, self.contract.product))
+    }
+
+    ///Build bars of the interval given to set_parameters from bars of source_interval, e.g.
+    ///MINUTE with a window of 5 for 5 minute bars, or HOUR from MINUTE with a window of 1,
+    ///for databases holding only 1 minute bars. Renko, range and volume bars ignore the window.
+    pub fn set_resample(&mut self, source_interval: Interval, window: i64) {
+        self.resample = Some((source_interval, window));
+    }
+
+    ///Bars of the interval given to set_parameters built from bars of the resample source
+    ///interval, renko, range and volume bars sized by the pricetick.
+    fn resample_bars(&self, bars: &[BarData], window: i64) -> VnrsResult<Vec<BarData>> {
+        if self.interval.is_alt_bar() {
+            resample_alt_bars(bars, self.interval, self.pricetick)
+        } else if let (Interval::DAILY, Some((calendar, day_start))) =
+            (self.interval, &self.daily_sessions)
+        {
+            Ok(calendar::daily_bars(bars, calendar, *day_start))
+        } else {
+            Ok(resample_bars(bars, self.interval, window))
+        }
+    }
+
+    ///Resample daily bars by the trading days of calendar instead of calendar days, with
+    ///night sessions from day_start counted as the next trading day, e.g.
+    ///CHINA_FUTURES_DAY_START.
+    pub fn set_daily_sessions(&mut self, calendar: TradingCalendar, day_start: NaiveTime) {
+        self.daily_sessions = Some((calendar, day_start));
+    }
+
+    ///Net position carried into the backtest, e.g. the book of a live account, marked from
+    ///the open of the first bar.
+    pub fn set_initial_pos(&mut self, initial_pos: f64) {
+        self.initial_pos = initial_pos;
+    }
+
+    ///Load the bars of SSE and SZSE stocks with prices adjusted for splits and dividends by
+    ///the adjustment factors in the database.
+    pub fn set_adjust_mode(&mut self, adjust: AdjustMode) {
+        self.adjust = adjust;
+    }
+
+    ///Load bars of the interval given to set_parameters as stored in the database.
+    pub fn clear_resample(&mut self) {
+        self.resample = None;
+    }
+
+    ///Also push bars of window units of a larger interval into the strategy's on_bar, told
+    ///apart from the replayed bars by bar.interval. They are built from the replayed bars and
+    ///pushed right after the bar finishing them, orders are only matched on replayed bars.
+    pub fn add_bar_interval(&mut self, interval: Interval, window: i64) {
+        self.window_bars
+            .push(WindowBarBuilder::new(interval, window));
+    }
+
+    ///Check the cache before the database in load_data, bars loaded from the database are
+    ///saved into the cache. The cache is shared, e.g. by the engines of a batch.
+    pub fn set_bar_cache(&mut self, cache: Arc<BarCache>) {
+        self.bar_cache = Some(cache);
+    }
+
+    ///Take size, pricetick, min_volume, rate and slippage of the symbol from the registry
+    ///instead of set_parameters, symbols missing in the registry keep their parameters.
+    pub fn set_contract_registry(&mut self, registry: Arc<ContractRegistry>) {
+        self.contract_registry = Some(registry);
+        self.apply_contract_registry();
+    }
+
+    fn apply_contract_registry(&mut self) {
+        let Some(registry) = self.contract_registry.clone() else {
+            return;
+        };
+        if let Some(contract) = registry.get_contract(&self.vt_symbol) {
+            self.set_contract(contract.clone());
+        }
+        if let Some(rate) = registry.get_rate(&self.vt_symbol) {
+            self.rate = rate;
+        }
+        if let Some(slippage) = registry.get_slippage(&self.vt_symbol) {
+            self.slippage = slippage;
+        }
+    }
+
+    ///Use the full contract specification of the traded symbol, size and pricetick override
+    ///those given to set_parameters and min_volume is checked on every order.
+    pub fn set_contract(&mut self, contract: ContractData) {
+        self.size = contract.size;
+        self.pricetick = contract.pricetick;
+        self.contract = contract.clone();
+        self.offset_converter = OffsetConverter::new(move |vt_symbol| {
+            (vt_symbol == contract.vt_symbol()).then(|| contract.clone())
+        });
     }
 
     fn clear_data(&mut self) {
@@ -129,63 +546,245 @@ impl BacktestingEngine {
         self.bar = BarData::default();
         self.datetime = NaiveDateTime::default();
 
-        self.stop_order_count = 0;
+        self.stop_order_ids.reset();
         self.stop_orders.clear();
         self.active_stop_orders.clear();
 
-        self.limit_order_count = 0;
+        self.limit_order_ids.reset();
         self.limit_orders.clear();
         self.active_limit_orders.clear();
+        self.bracket_entries.clear();
+        self.oco_orders.clear();
+        self.order_expiries.clear();
+        self.timer = Timer {
+            interval: self.timer.interval,
+            ..Default::default()
+        };
 
-        self.trade_count = 0;
+        self.trade_ids.reset();
         self.trades.clear();
+        self.pos = 0.0;
+        self.initial_price = 0.0;
 
         self.logs.clear();
+        self.risk_halt = None;
+        self.equity_samples.clear();
+        self.exposure = ExposureCounters::default();
+        self.excursion = ExcursionTracker::default();
+        if let Some(recorder) = &mut self.factor_recorder {
+            recorder.clear();
+        }
+        self.order_timeline = OrderTimeline::default();
+        self.replay_count = 0;
+        self.replay = None;
+        self.resume_variables = None;
         self.daily_results.clear();
+        self.settlements.clear();
+        self.settlement_vwap.clear();
+        self.settled_date = None;
+    }
+
+    pub fn get_vt_symbol(&self) -> &str {
+        &self.vt_symbol
+    }
+
+    ///UUID of the run, the prefix of its order and trade ids.
+    pub fn get_run_id(&self) -> &str {
+        &self.run_id
     }
 
-    pub fn add_strategy(&mut self, strategy_class: Arc<ExternClass>, setting: &str) {
+    pub fn add_strategy(
+        &mut self,
+        strategy_class: Arc<ExternClass>,
+        setting: &str,
+    ) -> VnrsResult<()> {
         self.strategy_class = strategy_class.clone();
-        let strategy_name = strategy_class
-            .clone()
-            .filename
-            .clone()
-            .into_string()
-            .unwrap();
-        self.strategy = ExternInstance::new(
+        self.setting = setting.to_string();
+        let strategy_name = strategy_class.filename.to_string_lossy().into_owned();
+        self.scope_ids(&strategy_name);
+
+        let strategy = ExternInstance::new(
             self.strategy_class.clone(),
-            (self.v_table.as_ref().unwrap() as *const VTable as usize) as *const VTable,
+            &self.context.as_ref().unwrap().v_table,
             strategy_name,
             &self.vt_symbol,
             setting,
         );
+        self.strategy = self.check(strategy)?;
+        Ok(())
+    }
+
+    ///Add a strategy built into the host from its value rather than a setting, e.g. one
+    ///holding a closure, without a dylib.
+    pub fn add_strategy_instance<S: CtaStrategy + Send>(&mut self, name: &str, strategy: S) {
+        self.strategy_class = Arc::new(strategy_class::<S>(name));
+        self.setting = String::new();
+        self.scope_ids(name);
+
+        let v_table = &self.context.as_ref().unwrap().v_table as *const VTable;
+        self.strategy = unsafe {
+            let instance = sdk::abi::new_with(v_table, name, &self.vt_symbol, |_| strategy);
+            ExternInstance::from_raw(self.strategy_class.clone(), instance, name.to_string())
+        };
+    }
+
+    ///Add a strategy that is only a closure over the bars, see quick::FnStrategy.
+    pub fn add_strategy_fn<F>(&mut self, on_bar: F)
+    where
+        F: FnMut(&mut QuickContext, &BarData) + Send + 'static,
+    {
+        self.add_strategy_instance("strategy_fn", FnStrategy::from_fn(FN_STRATEGY_BARS, on_bar));
+    }
+
+    fn scope_ids(&mut self, name: &str) {
+        // Scope ids by run and strategy so that ids of runs never collide. Only the name of a
+        // dylib is used, its path would put slashes and dots into vt_orderids.
+        let scope = format!("{}_{}", self.run_id, strategy_name(name));
+        self.stop_order_ids = IdGenerator::new(&format!("{}.{}", STOPORDER_PREFIX, scope));
+        self.limit_order_ids = IdGenerator::new(&scope);
+        self.trade_ids = IdGenerator::new(&scope);
+    }
+
+    ///Pass result on, putting the error into the output first.
+    fn check<T>(&self, result: VnrsResult<T>) -> VnrsResult<T> {
+        if let Err(e) = &result {
+            self.output(&e.to_string());
+        }
+        result
     }
 
-    pub fn load_data(&mut self) {
+    ///Load the history of the range, the error is put into the output too.
+    pub fn load_data(&mut self) -> VnrsResult<()> {
         self.output("开始加载历史数据");
         if self.end == NaiveDateTime::default() {
             self.end = Local::now().naive_local();
         }
         if self.start >= self.end {
             self.output("起始日期必须小于结束日期");
-            return;
+            return Err(VnrsError::Setting(format!(
+                "start {} is not before end {}",
+                self.start, self.end
+            )));
+        }
+        self.history_data.clear(); // Clear previously loaded history data
+        self.tick_data.clear();
+        self.replay_count = 0;
+        self.replay = None;
+        self.stream = None;
+        self.load_error = None;
+
+        if self.mode == BacktestingMode::TICK {
+            self.tick_data = self.check(load_tick_data(
+                &self.symbol,
+                self.exchange,
+                self.start,
+                self.end,
+            ))?;
+            self.output(&format!(
+                "历史数据加载完成，数据量：{}",
+                self.tick_data.len()
+            ));
+            return Ok(());
+        }
+
+        let load_interval = self.resample.map_or(self.interval, |(source, _)| source);
+        if let Ok(delta) = interval_delta(load_interval) {
+            // Bars are only there in trading hours, the range gives an upper bound
+            let count = (self.end - self.start).num_seconds() / delta.num_seconds().max(1) + 1;
+            self.output(&format!(
+                "预计数据量不超过：{}，内存占用不超过{}",
+                count,
+                format_bytes(count as usize * BAR_BYTES)
+            ));
+        }
+        let mut bars = match self.load_cached_bars(load_interval) {
+            Some(bars) => {
+                self.output("从缓存加载历史数据");
+                bars
+            }
+            None => {
+                let (bars, stream) = self.check(self.load_bars_in_batches(load_interval))?;
+                if let Some(stream) = stream {
+                    self.output(&format!(
+                        "历史数据超过内存上限{}，剩余数据在回放时分批加载",
+                        format_bytes(self.memory_limit.unwrap_or_default())
+                    ));
+                    self.stream = Some(stream);
+                    self.history_data.extend(bars);
+                    return Ok(());
+                }
+                self.save_cached_bars(load_interval, &bars);
+                bars
+            }
+        };
+
+        // Windows are built after loading so that no window is split between batches
+        if let Some((_, window)) = self.resample {
+            bars = self.check(self.resample_bars(&bars, window))?;
         }
-        self.history_data.write().unwrap().clear(); // Clear previously loaded history data
+        self.history_data.extend(bars);
+
+        self.output(&format!(
+            "历史数据加载完成，数据量：{}，内存占用：{}",
+            self.history_data.len(),
+            format_bytes(self.history_memory())
+        ));
+        Ok(())
+    }
+
+    ///Keep at most limit bytes of history in memory, None for no limit, the default. Once the
+    ///bars load_data has loaded are over it, the rest of the range is loaded batch by batch
+    ///as the replay reaches it, and get_history_data only has the batch being replayed. Bars
+    ///that are resampled on load are always loaded at once.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    ///Bytes the loaded history takes, only the batch being replayed when it's streamed.
+    pub fn history_memory(&self) -> usize {
+        self.history_data.memory_size()
+    }
 
+    ///Replay bars instead of loading them with load_data, e.g. synthetic ones or bars loaded
+    ///once for several runs.
+    pub fn set_history_data(&mut self, bars: Vec<BarData>) {
+        self.history_data.clear();
+        self.history_data.extend(bars);
+        self.replay_count = 0;
+        self.replay = None;
+        self.stream = None;
+    }
+
+    ///Ticks replayed in tick mode instead of loading them with load_data.
+    pub fn set_tick_data(&mut self, ticks: Vec<TickData>) {
+        self.tick_data = ticks;
+        self.replay_count = 0;
+        self.replay = None;
+        self.stream = None;
+    }
+
+    ///Bars of the range, and the batches left once they are over the memory limit.
+    fn load_bars_in_batches(
+        &self,
+        load_interval: Interval,
+    ) -> VnrsResult<(Vec<BarData>, Option<HistoryStream>)> {
         // Load 30 days of data each time and allow for progress update
         let total_days = (self.end - self.start).num_days();
         let progress_days = (total_days / 10).max(1);
         let progress_delta = TimeDelta::days(progress_days);
-        let interval_delta = get_interval_delta_map()
-            .get(&self.interval)
-            .unwrap()
-            .clone();
+        let interval_delta = interval_delta(load_interval)?;
+        let mut bars: Vec<BarData> = Vec::new();
 
         let mut start = self.start;
         let mut end = self.start + progress_delta;
         let mut progress: f64 = 0.0;
 
         while start < self.end {
+            if self.is_cancelled() {
+                return Err(VnrsError::Engine(
+                    "backtest cancelled while loading data".to_string(),
+                ));
+            }
             let progress_bar = "#".repeat((progress * 10.0 + 1.0) as usize);
             self.output(
                 format!(
@@ -197,912 +796,5270 @@ impl BacktestingEngine {
             );
 
             end = end.min(self.end); // Make sure end time stays within set range
-
-            if self.mode == BacktestingMode::BAR {
-                let data: Vec<BarData> =
-                    load_bar_data(&self.symbol, self.exchange, self.interval, start, end);
-                self.history_data
-                    .write()
-                    .unwrap()
-                    .extend(data.into_iter().map(|bar_data| MixData::BarData(bar_data)));
-            }
-            //     else:
-            //         data: List[TickData] = load_tick_data(
-            //             self.symbol,
-            //             self.exchange,
-            //             start,
-            //             end
-            //         )
+            bars.extend(self.load_batch(load_interval, start, end)?);
 
             progress += progress_days as f64 / total_days as f64;
             progress = progress.min(1.0);
 
             start = end + interval_delta;
-            end += progress_delta
+            end += progress_delta;
+
+            // Resampled windows must not be split between batches
+            let limit = self.memory_limit.filter(|_| self.resample.is_none());
+            if limit.is_some_and(|limit| bars.len() * BAR_BYTES > limit) && start < self.end {
+                let stream = HistoryStream {
+                    interval: load_interval,
+                    start,
+                    end,
+                    batch: progress_delta,
+                    interval_delta,
+                    offset: 0,
+                };
+                return Ok((bars, Some(stream)));
+            }
         }
 
-        self.output(
-            format!(
-                "历史数据加载完成，数据量：{}",
-                self.history_data.read().unwrap().len()
-            )
-            .as_str(),
-        );
+        Ok((bars, None))
     }
 
-    pub fn run_backtesting(&mut self) {
-        let func: fn(&mut BacktestingEngine, &MixData);
-        if self.mode == BacktestingMode::BAR {
-            func = BacktestingEngine::new_bar;
-        } else {
-            func = BacktestingEngine::new_tick;
+    fn load_batch(
+        &self,
+        load_interval: Interval,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> VnrsResult<Vec<BarData>> {
+        if self.mode != BacktestingMode::BAR {
+            return Ok(Vec::new());
         }
-        self.strategy
-            .on_init(self as *const BacktestingEngine as usize);
-        *self.strategy.get_inited_mut() = true;
-        self.output("策略初始化完成");
-
-        self.strategy.on_start();
-        *self.strategy.get_trading_mut() = true;
-        self.output("开始回放历史数据");
-
-        let total_size: usize = self.history_data.read().unwrap().len();
-        let batch_size: usize = (total_size / 10).max(1);
+        load_bar_data(
+            &self.symbol,
+            self.exchange,
+            load_interval,
+            start,
+            end,
+            self.adjust,
+        )
+        //     else:
+        //         data: List[TickData] = load_tick_data(
+        //             self.symbol,
+        //             self.exchange,
+        //             start,
+        //             end
+        //         )
+    }
 
-        let cloned_history_data = self.history_data.clone();
-        let ref_vec_history_data = cloned_history_data.read().unwrap();
-        for (ix, i) in (0..total_size).step_by(batch_size).enumerate() {
-            let batch_data;
-            if i + batch_size >= self.history_data.read().unwrap().len() {
-                batch_data = &ref_vec_history_data[i..];
-            } else {
-                batch_data = &ref_vec_history_data[i..i + batch_size];
+    ///Whether there is a bar left to replay, loading the next batches of a streamed history
+    ///once the bars loaded are replayed.
+    fn has_next_bar(&mut self) -> bool {
+        if self.mode == BacktestingMode::TICK {
+            return self.replay_count < self.tick_data.len();
+        }
+        loop {
+            let Some(stream) = &mut self.stream else {
+                return self.replay_count < self.history_data.len();
+            };
+            if self.replay_count < stream.offset + self.history_data.len() {
+                return true;
             }
-            let this = self as *const BacktestingEngine as *mut BacktestingEngine;
-            for data in batch_data {
-                func(self, data);
+            if stream.start >= self.end {
+                return false;
+            }
+            let (interval, start, end) = (stream.interval, stream.start, stream.end.min(self.end));
+            stream.offset += self.history_data.len();
+            stream.start = end + stream.interval_delta;
+            stream.end += stream.batch;
+            self.history_data.clear();
+            match self.load_batch(interval, start, end) {
+                Ok(bars) => self.history_data.extend(bars),
+                Err(e) => {
+                    self.output(&format!("历史数据加载失败：{}", e));
+                    self.load_error = Some(e);
+                    if let Some(stream) = &mut self.stream {
+                        stream.start = self.end;
+                    }
+                }
             }
-            let progress = (ix as f64 / 10.0).min(1.0);
-            let progress_bar = "=".repeat(ix + 1);
-            self.output(&format!(
-                "回放进度：{} [{:.0}%]",
-                progress_bar,
-                progress * 100.0
-            ));
         }
-        self.strategy.on_stop();
-        self.output("历史数据回放结束");
-        eprintln!("{}", self.trade_count);
     }
 
-    pub fn calculate_result(&mut self) -> Rc<RefCell<DataFrame>> {
-        self.output("开始计算逐日盯市盈亏");
+    ///Index in history_data of the next bar to replay.
+    fn history_index(&self) -> usize {
+        self.replay_count - self.stream.as_ref().map_or(0, |stream| stream.offset)
+    }
 
-        if self.trades.len() == 0 {
-            self.output("回测成交记录为空");
+    ///Bars of the history, estimated from the range loaded so far while it's streamed.
+    fn history_len(&self) -> usize {
+        if self.mode == BacktestingMode::TICK {
+            return self.tick_data.len();
+        }
+        let Some(stream) = &self.stream else {
+            return self.history_data.len();
+        };
+        let loaded = stream.offset + self.history_data.len();
+        if stream.start >= self.end {
+            return loaded;
         }
+        let covered = (stream.start - self.start).num_seconds().max(1) as f64;
+        let total = (self.end - self.start).num_seconds() as f64;
+        ((loaded as f64 * total / covered) as usize).max(loaded)
+    }
 
-        // Add trade data into daily reuslt.
-        for trade in self.trades.values() {
-            let d = trade.borrow().datetime.date();
-            let daily_result = self.daily_results.get_mut(&d).unwrap();
-            daily_result.add_trade(trade.clone())
+    fn load_cached_bars(&self, interval: Interval) -> Option<Vec<BarData>> {
+        if self.mode != BacktestingMode::BAR {
+            return None;
         }
+        self.bar_cache.as_ref()?.load(
+            &self.symbol,
+            self.exchange,
+            interval,
+            self.start,
+            self.end,
+            self.cache_adjust(),
+        )
+    }
 
-        // Calculate daily result by iteration.
-        let mut pre_close = 0.0;
-        let mut start_pos = 0.0;
+    ///Adjustment the loaded bars got, bars of exchanges that aren't adjusted are as stored.
+    fn cache_adjust(&self) -> AdjustMode {
+        if self.adjust.applies_to(self.exchange) {
+            self.adjust
+        } else {
+            AdjustMode::None
+        }
+    }
 
-        let mut sorted: Vec<&mut DailyResult> = self.daily_results.values_mut().collect();
-        sorted.sort_by_key(|item| item.date);
-        for daily_result in sorted {
-            daily_result.calculate_pnl(pre_close, start_pos, self.size, self.rate, self.slippage);
+    fn save_cached_bars(&self, interval: Interval, bars: &[BarData]) {
+        let Some(cache) = &self.bar_cache else {
+            return;
+        };
+        if bars.is_empty() {
+            return;
+        }
+        if let Err(e) = cache.save(
+            &self.symbol,
+            self.exchange,
+            interval,
+            self.start,
+            self.end,
+            self.cache_adjust(),
+            bars,
+        ) {
+            self.output(&format!("历史数据缓存失败：{}", e));
+        }
+    }
 
-            pre_close = daily_result.close_price;
-            start_pos = daily_result.end_pos;
+    ///Replay the history until it ends, is paused or cancelled. An error if there's no
+    ///strategy or a batch of a streamed history fails to load, the replay then ends there.
+    pub fn run_backtesting(&mut self) -> VnrsResult<()> {
+        if self.take_cancel() {
+            return Ok(());
+        }
+        if self.replay.is_none() {
+            self.start_replay()?;
+        }
+        while self.has_next_bar() {
+            self.replay_next();
+            if self.take_cancel() || self.take_pause() {
+                return self.take_load_error();
+            }
         }
+        self.finish_replay();
+        self.take_load_error()
+    }
 
-        // Generate dataframe
-        let mut date: Vec<NaiveDate> = Vec::new();
-        let mut close_price = Vec::new();
-        let mut pre_close = Vec::new();
-        let mut trade_count = Vec::new();
-        let mut start_pos = Vec::new();
-        let mut end_pos = Vec::new();
-        let mut turnover = Vec::new();
-        let mut commission = Vec::new();
-        let mut slippage = Vec::new();
-        let mut trading_pnl = Vec::new();
-        let mut holding_pnl = Vec::new();
-        let mut total_pnl = Vec::new();
-        let mut net_pnl = Vec::new();
-        for daily_result in self.daily_results.values() {
-            date.push(daily_result.date);
-            close_price.push(daily_result.close_price);
-            pre_close.push(daily_result.pre_close);
-            trade_count.push(daily_result.trade_count);
-            start_pos.push(daily_result.start_pos);
-            end_pos.push(daily_result.end_pos);
-            turnover.push(daily_result.turnover);
-            commission.push(daily_result.commission);
-            slippage.push(daily_result.slippage);
-            trading_pnl.push(daily_result.trading_pnl);
-            holding_pnl.push(daily_result.holding_pnl);
-            total_pnl.push(daily_result.total_pnl);
-            net_pnl.push(daily_result.net_pnl);
-        }
-        self.daily_df = Some(Rc::new(RefCell::new(df!(
-            "date"=>&date,"close_price"=>&close_price,"pre_close"=>&pre_close,"trade_count"=>&trade_count,
-            "start_pos"=>&start_pos,"end_pos"=>&end_pos,"turnover"=>&turnover,"commission"=>&commission,
-            "slippage"=>&slippage,"trading_pnl"=>&trading_pnl,"holding_pnl"=>&holding_pnl,
-            "total_pnl"=>&total_pnl,"net_pnl"=>&net_pnl
-        ).unwrap())));
-        (*self.daily_df.clone().unwrap())
-            .borrow_mut()
-            .sort_in_place(["date"], Default::default())
-            .unwrap();
+    fn take_load_error(&mut self) -> VnrsResult<()> {
+        self.load_error.take().map_or(Ok(()), Err)
+    }
 
-        self.output("逐日盯市盈亏计算完成");
-        self.daily_df.clone().unwrap()
+    ///Replay the next bar, starting the replay with the strategy's on_init and on_start if it
+    ///hasn't started. The strategy is stopped after the last bar. Returns false if there was
+    ///no bar left to replay.
+    pub fn step(&mut self) -> bool {
+        if self.take_cancel() || (self.replay.is_none() && self.start_replay().is_err()) {
+            return false;
+        }
+        let replayed = self.has_next_bar();
+        if replayed {
+            self.replay_next();
+        }
+        if !self.has_next_bar() {
+            self.finish_replay();
+        }
+        replayed
     }
 
-    pub fn calculate_statistics(&mut self, mut df: Option<Rc<RefCell<DataFrame>>>, output: bool) {
-        self.output("开始计算策略统计指标");
+    ///Replay the bars up to and including datetime, or until paused, then return with the
+    ///engine and strategy state at that moment. Returns the number of bars replayed.
+    pub fn run_until(&mut self, datetime: NaiveDateTime) -> usize {
+        let mut count = 0;
+        while self.has_next_bar() && self.next_datetime() <= datetime {
+            self.step();
+            count += 1;
+            if self.take_cancel() || self.take_pause() {
+                break;
+            }
+        }
+        count
+    }
 
-        // Check DataFrame input exterior
-        if df.is_none() {
-            df = self.daily_df.clone();
+    ///Datetime of the next bar or tick to replay, there must be one.
+    fn next_datetime(&self) -> NaiveDateTime {
+        if self.mode == BacktestingMode::TICK {
+            return self.tick_data[self.replay_count].datetime;
         }
+        self.history_data.datetimes()[self.history_index()]
+    }
 
-        // Init all statistics default value
-        let mut start_date = NaiveDate::default();
-        let mut end_date = NaiveDate::default();
-        let mut total_days: i64 = 0;
-        let mut profit_days: i64 = 0;
-        let mut loss_days: i64 = 0;
-        let mut end_balance: f64 = 0.0;
-        let mut max_drawdown: f64 = 0.0;
-        let mut max_ddpercent: f64 = 0.0;
-        let mut max_drawdown_duration: i64 = 0;
-        let mut total_net_pnl: f64 = 0.0;
-        let mut daily_net_pnl: f64 = 0.0;
-        let mut total_commission: f64 = 0.0;
-        let mut daily_commission: f64 = 0.0;
-        let mut total_slippage: f64 = 0.0;
-        let mut daily_slippage: f64 = 0.0;
-        let mut total_turnover: f64 = 0.0;
-        let mut daily_turnover: f64 = 0.0;
-        let mut total_trade_count: i64 = 0;
-        let mut daily_trade_count: f64 = 0.0;
-        let mut total_return: f64 = 0.0;
-        let mut annual_return: f64 = 0.0;
-        let mut daily_return: f64 = 0.0;
-        let mut return_std: f64 = 0.0;
-        let mut sharpe_ratio: f64 = 0.0;
-        let mut ewm_sharpe: f64 = 0.0;
-        let mut return_drawdown_ratio: f64 = 0.0;
+    ///Stop run_backtesting or run_until after the bar being replayed, e.g. from a callback.
+    ///Calling either again, or step, continues the replay.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
 
-        // Check if balance is always positive
-        let positive_balance: bool = false;
-        let mut dfo: DataFrame;
+    ///Flag pausing the replay when set, for pausing from another thread.
+    pub fn pause_handle(&self) -> Arc<AtomicBool> {
+        self.paused.clone()
+    }
 
-        if !df.is_none() {
-            // Calculate balance related time series data
-            let cloned_df = df.clone().unwrap();
-            let refmut_df = (*cloned_df).borrow_mut();
-            dfo = refmut_df
-                .clone()
-                .lazy()
-                .with_column(col("net_pnl").alias("balance").cum_sum(false) + lit(self.capital))
-                .collect()
-                .unwrap();
+    ///End the replay after the bar being replayed as if the history data ended there, or stop
+    ///load_data after the batch being loaded. The strategy is stopped and the trades and daily
+    ///results so far stay there for calculate_result and calculate_statistics. Unlike a paused
+    ///replay, a cancelled one can't be continued.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
 
-            // When balance falls below 0, set daily return to 0
-            dfo = dfo
-                .clone()
-                .lazy()
-                .with_column(
-                    col("balance")
-                        .alias("pre_balance")
-                        .shift_and_fill(1, self.capital),
-                )
-                .collect()
-                .unwrap();
-            dfo = dfo
-                .clone()
-                .lazy()
-                .with_column(col("balance").alias("x") / col("pre_balance"))
-                .collect()
-                .unwrap();
-            let x: Vec<f64> = dfo["x"].f64().unwrap().into_no_null_iter().collect();
-            let x: Vec<f64> = x
-                .iter()
-                .map(|x| if *x < 0.0 { 0.0 } else { f64::ln(*x) })
-                .collect();
-            dfo.with_column(Series::new("return", &x)).unwrap();
-
-            let balance: Vec<f64> = dfo["balance"].f64().unwrap().into_no_null_iter().collect();
-            let mut highlevel = Vec::new();
-            let mut drawdown = Vec::new();
-            let mut ddpercent = Vec::new();
-            let mut max = 0f64;
-            balance.iter().for_each(|x| {
-                max = max.max(*x);
-                highlevel.push(max);
-                let dd = *x - max;
-                drawdown.push(dd);
-                ddpercent.push(dd / max * 100f64);
-            });
-            dfo.with_column(Series::new("highlevel", &highlevel))
-                .unwrap();
-            dfo.with_column(Series::new("drawdown", &drawdown)).unwrap();
-            dfo.with_column(Series::new("ddpercent", &ddpercent))
-                .unwrap();
+    ///Flag cancelling the replay when set, for cancelling from another thread.
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
 
-            // All balance value needs to be positive
-            let positive_balance = balance.iter().all(|x| *x > 0f64);
-            if !positive_balance {
-                self.output("回测中出现爆仓（资金小于等于0），无法计算策略统计指标");
-            }
+    ///Cancel the replay when token is set instead of with the flag of cancel_handle, e.g. to
+    ///cancel the engines of several runs at once.
+    pub fn set_cancel_token(&mut self, token: Arc<AtomicBool>) {
+        self.cancelled = token;
+    }
 
-            // Calculate statistics value
-            if positive_balance {
-                // Calculate statistics value
-                let dates: Vec<i32> = dfo["date"].date().unwrap().into_no_null_iter().collect();
-                start_date = NaiveDate::from_ymd_opt(1970, 1, 1)
-                    .unwrap()
-                    .checked_add_days(Days::new(*dates.first().unwrap() as u64))
-                    .unwrap();
-                end_date = NaiveDate::from_ymd_opt(1970, 1, 1)
-                    .unwrap()
-                    .checked_add_days(Days::new(*dates.last().unwrap() as u64))
-                    .unwrap();
+    ///Whether the run was cancelled, its results then stop at the bar it was cancelled on.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
 
-                total_days = dfo.height() as i64;
-                profit_days = dfo
-                    .clone()
-                    .lazy()
-                    .filter(col("net_pnl").gt(0))
-                    .collect()
-                    .unwrap()
-                    .height() as i64;
-                loss_days = dfo
-                    .clone()
-                    .lazy()
-                    .filter(col("net_pnl").lt(0))
-                    .collect()
-                    .unwrap()
-                    .height() as i64;
-
-                end_balance = dfo["balance"].f64().unwrap().last().unwrap();
-                max_drawdown = dfo["drawdown"].f64().unwrap().min().unwrap();
-                max_ddpercent = dfo["ddpercent"].f64().unwrap().min().unwrap();
-
-                let max_drawdown_end_idx = dfo["drawdown"].arg_min();
-                let max_drawdown_end = max_drawdown_end_idx.map(|idx| {
-                    NaiveDate::from_ymd_opt(1970, 1, 1)
-                        .unwrap()
-                        .checked_add_days(Days::new(dates[idx] as u64))
-                        .unwrap()
-                });
+    ///Change parameters of the running strategy with a "name:value,name:value" setting,
+    ///checked against the parameters of its class. The strategy applies them in its
+    ///update_setting, an error if it doesn't take updates.
+    pub fn update_setting(&mut self, setting: &str) -> VnrsResult<()> {
+        let update = parse_setting(setting);
+        self.check(validate_setting(
+            &self.strategy_class.get_parameters(),
+            &update,
+        ))?;
+        if !self.strategy.update_setting(&update) {
+            return self.check(Err(VnrsError::Setting(format!(
+                "strategy {} doesn't take setting updates",
+                self.strategy.strategy_name
+            ))));
+        }
 
-                if max_drawdown_end.is_some() {
-                    let before_max_drawdown = dfo
-                        .clone()
-                        .lazy()
-                        .filter(col("date").lt_eq(lit(max_drawdown_end.unwrap())))
-                        .collect()
-                        .unwrap();
-                    let max_drawdown_start_idx = before_max_drawdown["balance"].arg_max().unwrap();
-                    let max_drawdown_start = NaiveDate::from_ymd_opt(1970, 1, 1)
-                        .unwrap()
-                        .checked_add_days(Days::new(dates[max_drawdown_start_idx] as u64))
-                        .unwrap();
-                    max_drawdown_duration =
-                        ((max_drawdown_end.unwrap() - max_drawdown_start) as TimeDelta).num_days();
-                } else {
-                    max_drawdown_duration = 0;
-                }
+        let mut merged = parse_setting(&self.setting);
+        for (name, value) in &update {
+            match merged.iter_mut().find(|(parameter, _)| parameter == name) {
+                Some((_, old)) => *old = value.clone(),
+                None => merged.push((name.clone(), value.clone())),
+            }
+        }
+        self.setting = format_setting(&merged);
+
+        let update = format_setting(&update);
+        self.output(&format!("策略参数修改：{}", update));
+        if let Some(recorder) = &self.audit_recorder {
+            self.check(recorder.record_setting_update(
+                self.gateway_name.as_str(),
+                &self.strategy.strategy_name,
+                &update,
+            ))?;
+        }
+        Ok(())
+    }
 
-                total_net_pnl = dfo["net_pnl"].sum().unwrap();
-                daily_net_pnl = total_net_pnl / total_days as f64;
+    ///Queue of setting updates of the strategy added last, to push from another thread.
+    pub fn setting_queue(&self) -> SettingQueue {
+        SettingQueue {
+            parameters: Arc::new(self.strategy_class.get_parameters()),
+            pending: self.pending_settings.clone(),
+        }
+    }
 
-                total_commission = dfo["commission"].sum().unwrap();
-                daily_commission = total_commission / total_days as f64;
-
-                total_slippage = dfo["slippage"].sum().unwrap();
-                daily_slippage = total_slippage / total_days as f64;
-
-                total_turnover = dfo["turnover"].sum().unwrap();
-                daily_turnover = total_turnover / total_days as f64;
-
-                total_trade_count = dfo["trade_count"].sum().unwrap();
-                daily_trade_count = total_trade_count as f64 / total_days as f64;
-
-                total_return = (end_balance / self.capital - 1.0) * 100.0;
-                annual_return = total_return / (total_days as f64) * self.annual_days as f64;
-                daily_return = dfo["return"].mean().unwrap() * 100.0;
-                return_std = dfo["return"].std(0).unwrap() * 100.0;
-
-                if return_std != 0.0 {
-                    let daily_risk_free = self.risk_free / f64::sqrt(self.annual_days as f64);
-                    sharpe_ratio = (daily_return - daily_risk_free) / return_std
-                        * f64::sqrt(self.annual_days as f64);
-                }
-
-                //     ewm_window: ExponentialMovingWindow = df["return"].ewm(halflife=self.half_life)
-                //     ewm_mean: Series = ewm_window.mean() * 100
-                //     ewm_std: Series = ewm_window.std() * 100
-                //     ewm_sharpe: float = ((ewm_mean - daily_risk_free) / ewm_std)[-1] * np.sqrt(self.annual_days)
-                // else:
-                //     sharpe_ratio: float = 0
-                //     ewm_sharpe: float = 0
-
-                if max_ddpercent != 0.0 {
-                    return_drawdown_ratio = -total_return / max_ddpercent;
-                } else {
-                    return_drawdown_ratio = 0.0;
-                }
-            }
-        }
-        // Output
-        if output {
-            self.output(&"-".repeat(30));
-            self.output(&format!("首个交易日：\t{}", start_date));
-            self.output(&format!("最后交易日：\t{}", end_date));
-
-            self.output(&format!("总交易日：\t{}", total_days));
-            self.output(&format!("盈利交易日：\t{}", profit_days));
-            self.output(&format!("亏损交易日：\t{}", loss_days));
-
-            self.output(&format!("起始资金：\t{:.2}", self.capital));
-            self.output(&format!("结束资金：\t{:.2}", end_balance));
-
-            self.output(&format!("总收益率：\t{:.2}%", total_return));
-            self.output(&format!("年化收益：\t{:.2}%", annual_return));
-            self.output(&format!("最大回撤: \t{:.2}", max_drawdown));
-            self.output(&format!("百分比最大回撤: {:.2}%", max_ddpercent));
-            self.output(&format!("最长回撤天数: \t{}", max_drawdown_duration));
-
-            self.output(&format!("总盈亏：\t{:.2}", total_net_pnl));
-            self.output(&format!("总手续费：\t{:.2}", total_commission));
-            self.output(&format!("总滑点：\t{:.2}", total_slippage));
-            self.output(&format!("总成交金额：\t{:.2}", total_turnover));
-            self.output(&format!("总成交笔数：\t{}", total_trade_count));
-
-            self.output(&format!("日均盈亏：\t{:.2}", daily_net_pnl));
-            self.output(&format!("日均手续费：\t{:.2}", daily_commission));
-            self.output(&format!("日均滑点：\t{:.2}", daily_slippage));
-            self.output(&format!("日均成交金额：\t{:.2}", daily_turnover));
-            self.output(&format!("日均成交笔数：\t{}", daily_trade_count));
+    ///Record every setting update applied to the strategy into recorder.
+    pub fn set_audit_recorder(&mut self, recorder: Arc<AuditRecorder>) {
+        self.audit_recorder = Some(recorder);
+    }
 
-            self.output(&format!("日均收益率：\t{:.2}%", daily_return));
-            self.output(&format!("收益标准差：\t{:.2}%", return_std));
-            self.output(&format!("Sharpe Ratio：\t{:.2}", sharpe_ratio));
-            // self.output(&format!("EWM Sharpe：\t{:.2}", ewm_sharpe));
-            self.output(&format!("收益回撤比：\t{:.2}", return_drawdown_ratio));
+    fn apply_pending_settings(&mut self) {
+        let pending = std::mem::take(&mut *self.pending_settings.lock().unwrap());
+        for setting in pending {
+            // Failures are already in the output, the replay goes on with the old setting
+            let _ = self.update_setting(&setting);
         }
     }
 
-    fn update_daily_close(&mut self, price: f64) {
-        let d = self.datetime.date();
-
-        self.daily_results
-            .entry(d)
-            .and_modify(|e| e.close_price = price)
-            .or_insert(DailyResult::new(d, price));
+    ///(bars replayed, bars loaded).
+    pub fn replay_position(&self) -> (usize, usize) {
+        (self.replay_count, self.history_len())
     }
 
-    fn new_bar(&mut self, bar: &MixData) {
-        if let MixData::BarData(bar) = bar {
-            self.bar = bar.clone();
-            self.datetime = self.bar.datetime;
-
-            self.cross_limit_order();
-            self.cross_stop_order();
-            self.strategy.on_bar(bar);
-
-            self.update_daily_close(self.bar.close_price);
+    ///Finish the replay if it was cancelled, true if it was.
+    fn take_cancel(&mut self) -> bool {
+        if !self.is_cancelled() {
+            return false;
+        }
+        if self.replay.is_some() {
+            self.output(&format!(
+                "回测已取消，已回放{}/{}根K线",
+                self.replay_count,
+                self.history_len()
+            ));
+            self.finish_replay();
         }
+        true
     }
 
-    fn new_tick(&mut self, tick: &MixData) {}
-
-    fn cross_limit_order(&mut self) {
-        let long_cross_price;
-        let short_cross_price;
-        let long_best_price;
-        let short_best_price;
-        if self.mode == BacktestingMode::BAR {
-            long_cross_price = self.bar.low_price;
-            short_cross_price = self.bar.high_price;
-            long_best_price = self.bar.open_price;
-            short_best_price = self.bar.open_price;
-        } else {
-            long_cross_price = self.tick.ask_price_1;
-            short_cross_price = self.tick.bid_price_1;
-            long_best_price = long_cross_price;
-            short_best_price = short_cross_price;
+    fn take_pause(&self) -> bool {
+        let paused = self.paused.swap(false, Ordering::SeqCst);
+        if paused {
+            self.output("回放已暂停");
         }
+        paused
+    }
 
-        let value_list: Vec<Rc<RefCell<OrderData>>> = self
-            .active_limit_orders
-            .values()
-            .map(|v| v.clone())
-            .collect();
-        for order in value_list {
-            let mut order = (*order).borrow_mut();
-            // Push order update with status "not traded" (pending).
-            if order.status == Status::SUBMITTING {
-                order.status = Status::NOTTRADED;
-                self.strategy.on_order(&order);
-            }
-
-            // Check whether limit orders can be filled.
-            let long_cross: bool = order.direction == Direction::LONG
-                && order.price >= long_cross_price
-                && long_cross_price > 0.0;
-
-            let short_cross: bool = order.direction == Direction::SHORT
-                && order.price <= short_cross_price
-                && short_cross_price > 0.0;
-
-            if !long_cross && !short_cross {
-                continue;
-            }
-
-            // Push order udpate with status "all traded" (filled).
-            order.traded = order.volume;
-            order.status = Status::ALLTRADED;
-            self.strategy.on_order(&order);
-
-            if self.active_limit_orders.contains_key(&order.vt_orderid()) {
-                self.active_limit_orders.remove(&order.vt_orderid());
+    ///Initialize and start the strategy, an error if there is no strategy to run.
+    fn start_replay(&mut self) -> VnrsResult<()> {
+        if !self.strategy.is_loaded() {
+            self.output("策略未添加，无法开始回测");
+            return Err(VnrsError::Engine("no strategy added".to_string()));
+        }
+        if self.replay_count == 0 {
+            self.seed_initial_pos();
+            self.pos = self.initial_pos;
+            self.strategy.set_pos(self.pos);
+            if let Some(recorder) = &mut self.factor_recorder {
+                recorder.clear();
             }
+        }
+        let handle = self.bind_context();
+        self.strategy.on_init(handle);
+        for bar in std::mem::take(&mut self.warmup_bars) {
+            self.strategy.on_bar(&bar);
+            self.push_window_bars(&bar);
+        }
+        self.restore_strategy_data();
+        if let Some(variables) = self.resume_variables.take() {
+            self.strategy.set_pos(self.pos);
+            self.strategy.set_variables(&variables);
+            self.output("策略状态恢复完成");
+        }
+        self.strategy.set_inited(true);
+        self.output("策略初始化完成");
 
-            // Push trade update
-            self.trade_count += 1;
+        self.strategy.on_start();
+        self.strategy.set_trading(self.risk_halt.is_none());
+        self.output("开始回放历史数据");
+        if self.replay_count == 0 {
+            self.high_balance = self.capital;
+        }
+        // Days of a restored state before the one resumed in
+        let date = self.trading_day.trading_date(self.datetime);
+        self.closed_net_pnl = self
+            .daily_results
+            .range(..date)
+            .map(|(_, r)| r.net_pnl)
+            .sum();
+        let started = Instant::now();
+        self.replay = Some(ReplayRun {
+            started,
+            order_count: self.limit_order_ids.count() + self.stop_order_ids.count(),
+            resume_from: self.replay_count,
+            progress_at: started,
+            progress_count: self.replay_count,
+            printed_tenths: self.replay_count * 10 / self.history_len().max(1),
+            bars_per_sec: 0.0,
+        });
+        Ok(())
+    }
 
-            let trade_price;
-            let pos_change;
-            if long_cross {
-                trade_price = order.price.min(long_best_price);
-                pos_change = order.volume;
-            } else {
-                trade_price = order.price.max(short_best_price);
-                pos_change = -order.volume;
+    fn replay_next(&mut self) {
+        // The engine may have moved since the replay started, e.g. between steps
+        self.bind_context();
+        self.apply_pending_settings();
+        let index = self.replay_count;
+        let history_index = self.history_index();
+        // Bars are materialized one at a time into the same BarData
+        let history_data = std::mem::take(&mut self.history_data);
+        let mut bar = std::mem::take(&mut self.replay_bar);
+        if self.mode == BacktestingMode::BAR && history_data.read_into(history_index, &mut bar) {
+            self.new_bar(&bar);
+            self.put_trace();
+        }
+        self.replay_bar = bar;
+        self.history_data = history_data;
+        if self.mode == BacktestingMode::TICK {
+            let ticks = std::mem::take(&mut self.tick_data);
+            if let Some(tick) = ticks.get(index) {
+                self.new_tick(tick);
+                self.put_trace();
             }
+            self.tick_data = ticks;
+        }
+        self.replay_count = index + 1;
+        self.put_checkpoint();
 
-            let trade = Rc::new(RefCell::new(TradeData {
-                symbol: order.symbol.to_string(),
-                exchange: order.exchange,
-                orderid: order.orderid.to_string(),
-                tradeid: self.trade_count.to_string(),
-                direction: order.direction,
-                offset: order.offset,
-                price: trade_price,
-                volume: order.volume,
-                datetime: self.datetime,
-                gateway_name: self.gateway_name,
-            }));
-
-            *self.strategy.get_pos_mut() += pos_change;
-            self.strategy.on_trade(&trade.borrow());
-
-            self.trades
-                .insert(trade.borrow().vt_tradeid(), trade.clone());
+        let total_size = self.history_len();
+        let batch_size = (total_size / PROGRESS_STEPS).max(1);
+        if self.replay_count.is_multiple_of(batch_size) || self.replay_count == total_size {
+            self.put_progress(total_size);
         }
     }
 
-    fn cross_stop_order(&mut self) {
-        let long_cross_price;
-        let short_cross_price;
-        let long_best_price;
-        let short_best_price;
-        if self.mode == BacktestingMode::BAR {
-            long_cross_price = self.bar.high_price;
-            short_cross_price = self.bar.low_price;
-            long_best_price = self.bar.open_price;
-            short_best_price = self.bar.open_price;
-        } else {
-            long_cross_price = self.tick.last_price;
-            short_cross_price = self.tick.last_price;
-            long_best_price = long_cross_price;
-            short_best_price = short_cross_price;
+    fn finish_replay(&mut self) {
+        let Some(replay) = self.replay.take() else {
+            return;
+        };
+        self.bind_context();
+        self.strategy.on_stop();
+        self.sync_strategy_data();
+        self.settle_day();
+        self.close_day();
+        if let Some(trace) = self.debug_trace.take() {
+            if let Err(e) = trace.finish() {
+                self.output(&format!("调试跟踪保存失败：{}", e));
+            }
         }
+        self.output("历史数据回放结束");
 
-        let value_list: Vec<Rc<RefCell<StopOrder>>> = self
-            .active_stop_orders
-            .values()
-            .map(|v| v.clone())
-            .collect();
-        for stop_order in value_list {
-            let mut stop_order = (*stop_order).borrow_mut();
-            // Check whether stop order can be triggered.
-            let long_cross: bool =
-                stop_order.direction == Direction::LONG && stop_order.price <= long_cross_price;
+        if self.perf_counters {
+            let seconds = replay.started.elapsed().as_secs_f64().max(f64::EPSILON);
+            let orders =
+                self.limit_order_ids.count() + self.stop_order_ids.count() - replay.order_count;
+            let bars = self.replay_count.saturating_sub(replay.resume_from);
+            self.output(&format!(
+                "回放性能：{} 根K线，{:.0} 根/秒，{} 笔委托，{:.0} 笔/秒，耗时 {:.3} 秒",
+                bars,
+                bars as f64 / seconds,
+                orders,
+                orders as f64 / seconds,
+                seconds
+            ));
+        }
+    }
 
-            let short_cross: bool =
-                stop_order.direction == Direction::SHORT && stop_order.price >= short_cross_price;
+    ///Bind the strategy context to the current address of the engine, which may have
+    ///moved since the strategy was added.
+    fn bind_context(&mut self) -> usize {
+        let engine = self as *mut BacktestingEngine;
+        let context = self.context.as_mut().unwrap();
+        context.bind(engine);
+        context.handle()
+    }
 
-            if !long_cross && !short_cross {
-                continue;
-            }
+    ///Boundary of the trading days daily results are aggregated over, calendar days of the
+    ///data time by default.
+    pub fn set_trading_day(&mut self, trading_day: TradingDayBoundary) {
+        self.trading_day = trading_day;
+    }
 
-            // Create order data.
-            self.limit_order_count += 1;
+    ///Settle every trading day at config.time like a futures broker, see settlement. Days
+    ///are marked to their settlement price from then on.
+    pub fn set_settlement(&mut self, config: SettlementConfig) {
+        self.settlement = Some(config);
+    }
 
-            let order = Rc::new(RefCell::new(OrderData {
-                symbol: self.symbol.to_string(),
-                exchange: self.exchange,
-                orderid: self.limit_order_count.to_string(),
-                direction: stop_order.direction,
-                offset: stop_order.offset,
-                price: stop_order.price,
-                volume: stop_order.volume,
-                traded: stop_order.volume,
-                status: Status::ALLTRADED,
-                gateway_name: self.gateway_name,
-                datetime: self.datetime,
-                ..Default::default()
-            }));
+    ///Settlements of the days of the last run, empty unless set_settlement is on.
+    pub fn get_settlements(&self) -> &[Settlement] {
+        &self.settlements
+    }
 
-            self.limit_orders
-                .insert(order.borrow().vt_orderid(), order.clone());
+    ///Sample equity on every bar for the intraday drawdown and margin calls in statistics.
+    ///margin_ratio is the margin required per unit of contract value, 0 for no margin calls.
+    pub fn set_equity_sampling(&mut self, margin_ratio: f64) {
+        self.equity_sampling = Some(margin_ratio);
+    }
 
-            // Create trade data.
-            let trade_price;
-            let pos_change;
-            if long_cross {
-                trade_price = stop_order.price.max(long_best_price);
-                pos_change = order.borrow().volume;
-            } else {
-                trade_price = stop_order.price.min(short_best_price);
-                pos_change = -order.borrow().volume;
-            }
+    ///Equity sampled on every bar of the last run, empty unless set_equity_sampling is on.
+    pub fn get_equity_samples(&self) -> &[EquitySample] {
+        &self.equity_samples
+    }
 
-            self.trade_count += 1;
+    ///Halt trading once a limit is breached, see RiskLimits.
+    pub fn set_risk_limits(&mut self, risk_limits: RiskLimits) {
+        self.risk_limits = risk_limits;
+    }
 
-            let trade = Rc::new(RefCell::new(TradeData {
-                symbol: order.borrow().symbol.to_string(),
-                exchange: order.borrow().exchange,
-                orderid: order.borrow().orderid.clone(),
-                tradeid: self.trade_count.to_string(),
-                direction: order.borrow().direction,
-                offset: order.borrow().offset,
-                price: trade_price,
-                volume: order.borrow().volume,
-                datetime: self.datetime,
-                gateway_name: self.gateway_name,
-            }));
+    ///Risk limit breached during the last run, if any.
+    pub fn get_risk_halt(&self) -> Option<&RiskHalt> {
+        self.risk_halt.as_ref()
+    }
 
-            self.trades
-                .insert(trade.borrow().vt_tradeid(), trade.clone());
+    ///Call the strategy's on_timer while replaying, after on_bar of the bars it's due on.
+    pub fn set_timer(&mut self, interval: TimerInterval) {
+        self.timer = Timer::new(interval);
+    }
 
-            // Update stop order.
-            stop_order.vt_orderids.push(order.borrow().vt_orderid());
-            stop_order.status = StopOrderStatus::TRIGGERED;
+    ///Output replay throughput, bars and orders per second, at the end of run_backtesting.
+    pub fn set_perf_counters(&mut self, perf_counters: bool) {
+        self.perf_counters = perf_counters;
+    }
 
-            if self
-                .active_stop_orders
-                .contains_key(&stop_order.stop_orderid)
-            {
-                self.active_stop_orders.remove(&stop_order.stop_orderid);
-            }
+    ///In tick mode, fill orders against the 5 levels of the tick at the volume weighted price
+    ///of the levels they take, instead of all at level 1. Limit orders are part traded up to
+    ///the volume listed within their price and wait for the following ticks for the rest, the
+    ///volume of triggered stop orders beyond the listed depth is cancelled.
+    pub fn set_depth_fill(&mut self, depth_fill: bool) {
+        self.depth_fill = depth_fill;
+    }
 
-            // Push update to strategy.
-            self.strategy.on_stop_order(&stop_order);
-            self.strategy.on_order(&order.borrow());
+    ///Move the price of every fill against the order by coefficient * price * sqrt(volume /
+    ///market volume), the volume of the bar or the last volume of the tick, so that larger
+    ///orders pay more on top of the fixed slippage. Limit orders may fill past their price
+    ///then. 0 disables the impact, the default.
+    pub fn set_volume_impact(&mut self, coefficient: f64) {
+        self.volume_impact = coefficient;
+    }
 
-            *self.strategy.get_pos_mut() += pos_change;
-            self.strategy.on_trade(&trade.borrow());
-        }
+    ///How calculate_statistics prints the statistics, Chinese and tab separated by default.
+    pub fn set_statistics_formatter(&mut self, formatter: StatisticsFormatter) {
+        self.statistics_formatter = formatter;
     }
 
-    fn load_bar(
-        &mut self,
-        vt_symbol: &str,
-        days: i64,
-        interval: Interval,
-        // callback: Callable,
-        use_database: bool,
-    ) -> Vec<BarData> {
-        let init_end = self.start - get_interval_delta_map()[&interval];
-        let init_start = self.start.checked_sub_days(Days::new(days as u64)).unwrap();
+    ///Charge positions carried into a day the annual long_rate or short_rate on their
+    ///borrowed notional at the close before, for each calendar day since the day before over
+    ///a 365 day year. A short position borrows its whole notional, a long one what exceeds
+    ///the balance, e.g. leveraged spot. The cost is debited from the net pnl of the day.
+    pub fn set_financing(&mut self, long_rate: f64, short_rate: f64) {
+        self.financing_rates = (long_rate, short_rate);
+    }
 
-        let (symbol, exchange) = extract_vt_symbol(vt_symbol);
+    ///Convert daily pnl, costs and turnover from the currency of the contract into base at
+    ///the rate of each day, so that capital, balance and statistics are all in base. Days
+    ///without a rate in rates keep the amounts of the contract currency.
+    pub fn set_base_currency(&mut self, base: Currency, rates: FxRates) {
+        self.base_currency = Some((base, rates));
+    }
 
-        let bars: Vec<BarData> = load_bar_data(&symbol, exchange, interval, init_start, init_end);
+    ///Let hook adjust the price of every fill after the volume impact, e.g. with a custom
+    ///transaction-cost model. Hooks run in the order they are added.
+    pub fn add_fill_hook(&mut self, hook: FillHook) {
+        self.fill_hooks.push(hook);
+    }
 
-        return bars;
+    pub fn clear_fill_hooks(&mut self) {
+        self.fill_hooks.clear();
     }
 
-    fn load_tick(&mut self, vt_symbol: &str, days: i64) -> Vec<TickData> {
-        vec![]
+    fn hooked_price(&self, fill: Fill, price: f64) -> f64 {
+        self.fill_hooks
+            .iter()
+            .fold(price, |price, hook| hook(&fill, price))
     }
 
-    fn send_order(
-        &mut self,
-        strategy: *mut CtaTemplate,
-        direction: Direction,
-        offset: Offset,
-        price: f64,
-        volume: f64,
-        stop: bool,
-        lock: bool,
-        net: bool,
-    ) -> Vec<String> {
-        let price: f64 = round_to(price, self.pricetick);
-        let vt_orderid;
-        if stop {
-            vt_orderid = self.send_stop_order(direction, offset, price, volume);
+    fn impact_price(&self, direction: Direction, price: f64, volume: f64) -> f64 {
+        let market_volume = if self.mode == BacktestingMode::BAR {
+            self.bar.volume
         } else {
-            vt_orderid = self.send_limit_order(direction, offset, price, volume);
+            self.tick.last_volume
+        };
+        if self.volume_impact <= 0.0 || market_volume <= 0.0 {
+            return price;
+        }
+        let impact = self.volume_impact * price * (volume / market_volume).sqrt();
+        match direction {
+            Direction::LONG => price + impact,
+            _ => price - impact,
         }
-        vec![vt_orderid]
     }
 
-    fn send_stop_order(
-        &mut self,
-        direction: Direction,
-        offset: Offset,
-        price: f64,
-        volume: f64,
-    ) -> String {
-        self.stop_order_count += 1;
+    ///Round the volume of orders to whole lots of lot_size, e.g. 1 for futures or 0.001 for a
+    ///crypto pair, the min_volume of the contract if 0. Without a lot size volumes are taken
+    ///as they are.
+    pub fn set_lot_rounding(&mut self, lot_size: f64, rounding: LotRounding) {
+        self.lot_size = lot_size;
+        self.lot_rounding = rounding;
+    }
 
-        let stop_order = Rc::new(RefCell::new(StopOrder {
-            vt_symbol: self.vt_symbol.to_string(),
-            direction: direction,
-            offset: offset,
-            price: price,
-            volume: volume,
-            datetime: self.datetime,
-            stop_orderid: format!("{}.{}", STOPORDER_PREFIX, self.stop_order_count),
-            strategy_name: self.strategy.strategy_name.clone(),
-            ..Default::default()
-        }));
+    fn get_lot_size(&self) -> f64 {
+        if self.lot_size > 0.0 {
+            self.lot_size
+        } else {
+            self.contract.min_volume
+        }
+    }
 
-        self.active_stop_orders
-            .insert(stop_order.borrow().stop_orderid.clone(), stop_order.clone());
-        self.stop_orders
-            .insert(stop_order.borrow().stop_orderid.clone(), stop_order.clone());
+    ///volume in whole lots by the lot rounding, an error for Reject if it isn't.
+    fn round_volume(&self, volume: f64) -> Result<f64, String> {
+        let lot_size = self.get_lot_size();
+        if lot_size <= 0.0 || !volume.is_finite() {
+            return Ok(volume);
+        }
+        let lots = volume / lot_size;
+        let whole = lots.round();
+        let lots = if (lots - whole).abs() <= 1e-9 * whole.abs().max(1.0) {
+            whole
+        } else {
+            match self.lot_rounding {
+                LotRounding::Down => lots.floor(),
+                LotRounding::Nearest => whole,
+                LotRounding::Up => lots.ceil(),
+                LotRounding::Reject => {
+                    return Err(format!(
+                        "委托数量{}不是交易单位{}的整数倍",
+                        volume, lot_size
+                    ))
+                }
+            }
+        };
+        Ok(round_to(lots * lot_size, lot_size))
+    }
 
-        let ret = stop_order.borrow().stop_orderid.clone();
-        ret
+    ///Whether orders fill against the depth of the tick, see set_depth_fill.
+    fn fills_depth(&self) -> bool {
+        self.depth_fill && self.mode == BacktestingMode::TICK
     }
 
-    fn send_limit_order(
-        &mut self,
+    ///(volume filled, volume weighted price) of taking volume from the opposite side of the
+    ///tick, not past limit if given, see depth_fill. None outside tick mode with depth fill.
+    fn depth_price(
+        &self,
         direction: Direction,
-        offset: Offset,
-        price: f64,
         volume: f64,
-    ) -> String {
-        self.limit_order_count += 1;
-
-        let order = Rc::new(RefCell::new(OrderData {
-            symbol: self.symbol.to_string(),
-            exchange: self.exchange,
-            orderid: self.limit_order_count.to_string(),
-            direction: direction,
-            offset: offset,
-            price: price,
-            volume: volume,
-            status: Status::SUBMITTING,
-            gateway_name: self.gateway_name,
-            datetime: self.datetime,
-            ..Default::default()
-        }));
-
-        self.active_limit_orders
-            .insert(order.borrow().vt_orderid(), order.clone());
-        self.limit_orders
-            .insert(order.borrow().vt_orderid(), order.clone());
+        limit: Option<f64>,
+    ) -> Option<(f64, f64)> {
+        if !self.fills_depth() {
+            return None;
+        }
+        match direction {
+            Direction::LONG => depth_fill(&self.tick.ask_levels(), volume, |price| {
+                limit.is_none_or(|limit| price <= limit)
+            }),
+            _ => depth_fill(&self.tick.bid_levels(), volume, |price| {
+                limit.is_none_or(|limit| price >= limit)
+            }),
+        }
+    }
 
-        let ret = order.borrow().vt_orderid();
-        ret
+    ///Variables restored into the strategy after its on_init, e.g. saved by another engine.
+    pub fn set_strategy_variables(&mut self, variables: Vec<(String, String)>) {
+        self.resume_variables = Some(variables);
     }
 
-    ///Cancel order by vt_orderid.
-    fn cancel_order(&mut self, strategy: *mut CtaTemplate, vt_orderid: String) {
-        if vt_orderid.starts_with(STOPORDER_PREFIX) {
-            self.cancel_stop_order(strategy, vt_orderid);
-        } else {
-            self.cancel_limit_order(strategy, vt_orderid);
-        }
+    ///Persist strategy variables to DATA_FILENAME on every trade and on stop, and restore
+    ///them after on_init, the same way the live engine keeps state across restarts.
+    pub fn set_sync_data(&mut self, sync_data: bool) {
+        self.sync_data = sync_data;
     }
 
-    fn cancel_stop_order(&mut self, strategy: *mut CtaTemplate, vt_orderid: String) {
-        if !self.active_stop_orders.contains_key(&vt_orderid) {
+    ///Save pos and variables of the strategy into the data file.
+    fn sync_strategy_data(&self) {
+        if !self.sync_data {
             return;
         }
-        let stop_order = self.active_stop_orders.remove(&vt_orderid).unwrap();
 
-        (*stop_order).borrow_mut().status = StopOrderStatus::CANCELLED;
-        self.strategy.on_stop_order(&stop_order.borrow());
+        let mut data = serde_json::Map::new();
+        data.insert("pos".to_string(), self.pos.into());
+        for (name, value) in self.strategy.get_variables() {
+            data.insert(name, value.into());
+        }
+
+        let mut strategy_data = load_json(DATA_FILENAME);
+        strategy_data[&self.strategy.strategy_name] = serde_json::Value::Object(data);
+        if let Err(e) = save_json(DATA_FILENAME, &strategy_data) {
+            self.output(&format!("策略数据保存失败：{}", e));
+        }
     }
 
-    fn cancel_limit_order(&mut self, strategy: *mut CtaTemplate, vt_orderid: String) {
-        if !self.active_limit_orders.contains_key(&vt_orderid) {
+    ///Restore pos and variables of the strategy from the data file.
+    fn restore_strategy_data(&mut self) {
+        if !self.sync_data {
             return;
         }
-        let order = self.active_limit_orders.remove(&vt_orderid).unwrap();
 
-        (*order).borrow_mut().status = Status::CANCELLED;
-        self.strategy.on_order(&order.borrow());
-    }
+        let strategy_data = load_json(DATA_FILENAME);
+        let Some(data) = strategy_data[&self.strategy.strategy_name].as_object() else {
+            return;
+        };
 
-    ///Cancel all orders, both limit and stop.
-    fn cancel_all(&mut self, strategy: *mut CtaTemplate) {
-        let vt_orderids: Vec<String> = self.active_limit_orders.keys().map(|k| k.clone()).collect();
-        for vt_orderid in vt_orderids {
-            self.cancel_limit_order(strategy, vt_orderid);
+        let mut variables = Vec::new();
+        for (name, value) in data {
+            if name == "pos" {
+                self.pos = value.as_f64().unwrap_or_default();
+                self.strategy.set_pos(self.pos);
+            } else {
+                let value = match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                variables.push((name.clone(), value));
+            }
         }
+        self.strategy.set_variables(&variables);
+        self.output("策略数据恢复完成");
+    }
 
-        let stop_orderids: Vec<String> =
-            self.active_stop_orders.keys().map(|k| k.clone()).collect();
+    ///Receive the serialized state every interval bars during run_backtesting, e.g. to write
+    ///it to a file that restore_state can resume from.
+    pub fn set_checkpoint_callback(
+        &mut self,
+        interval: usize,
+        callback: impl Fn(&str) + Send + Sync + 'static,
+    ) {
+        self.checkpoint_callback = Some((interval.max(1), Box::new(callback)));
+    }
+
+    ///Dump the strategy variables, position and open orders after every bar of the next run
+    ///into a gzip compressed trace file at path, read back with trace::load_trace.
+    pub fn set_debug_trace(&mut self, path: impl AsRef<std::path::Path>) -> VnrsResult<()> {
+        self.debug_trace = Some(TraceWriter::create(path)?);
+        Ok(())
+    }
+
+    fn put_trace(&mut self) {
+        if self.debug_trace.is_none() {
+            return;
+        }
+        let mut orders: Vec<TraceOrder> = self
+            .active_limit_orders
+            .iter()
+            .filter_map(|vt_orderid| self.limit_orders.get(vt_orderid))
+            .map(|order| TraceOrder {
+                vt_orderid: order.vt_orderid(),
+                direction: order.direction,
+                offset: order.offset,
+                price: order.price,
+                volume: order.volume,
+                traded: order.traded,
+            })
+            .collect();
+        orders.extend(
+            self.active_stop_orders
+                .iter()
+                .filter_map(|stop_orderid| self.stop_orders.get(stop_orderid))
+                .map(|stop_order| TraceOrder {
+                    vt_orderid: stop_order.stop_orderid.clone(),
+                    direction: stop_order.direction,
+                    offset: stop_order.offset,
+                    price: stop_order.price,
+                    volume: stop_order.volume,
+                    traded: 0.0,
+                }),
+        );
+        let record = TraceRecord {
+            datetime: self.datetime,
+            pos: self.pos,
+            variables: self.strategy.get_variables(),
+            orders,
+        };
+        let result = self.debug_trace.as_mut().unwrap().write(&record);
+        if let Err(e) = result {
+            self.debug_trace = None;
+            self.output(&format!("调试跟踪写入失败，停止跟踪：{}", e));
+        }
+    }
+
+    fn put_checkpoint(&self) {
+        let Some((interval, callback)) = &self.checkpoint_callback else {
+            return;
+        };
+        if !self.replay_count.is_multiple_of(*interval) {
+            return;
+        }
+        match self.serialize_state() {
+            Ok(state) => callback(&state),
+            Err(e) => self.output(&format!("检查点保存失败：{}", e)),
+        }
+    }
+
+    ///Serialize the replay cursor, active orders, position, strategy variables and daily
+    ///results to JSON. Finished orders are not kept, trades are kept in the daily results.
+    pub fn serialize_state(&self) -> serde_json::Result<String> {
+        let state = EngineState {
+            run_id: self.run_id.clone(),
+            datetime: self.datetime,
+            replay_count: self.replay_count,
+            pos: self.pos,
+            high_balance: self.high_balance,
+            limit_order_count: self.limit_order_ids.count(),
+            stop_order_count: self.stop_order_ids.count(),
+            trade_count: self.trade_ids.count(),
+            active_limit_orders: self
+                .active_limit_orders
+                .iter()
+                .filter_map(|vt_orderid| self.limit_orders.get(vt_orderid))
+                .cloned()
+                .collect(),
+            active_stop_orders: self
+                .active_stop_orders
+                .iter()
+                .filter_map(|stop_orderid| self.stop_orders.get(stop_orderid))
+                .cloned()
+                .collect(),
+            bracket_entries: self.bracket_entries.clone(),
+            oco_orders: self.oco_orders.clone(),
+            order_expiries: self.order_expiries.clone(),
+            risk_halt: self.risk_halt.clone(),
+            initial_price: self.initial_price,
+            exposure: self.exposure,
+            excursion: self.excursion.clone(),
+            variables: self.strategy.get_variables(),
+            daily_results: self.daily_results.values().cloned().collect(),
+            settlements: self.settlements.clone(),
+        };
+        serde_json::to_string(&state)
+    }
+
+    ///Restore a state from serialize_state, run_backtesting then resumes after the last bar
+    ///it covers. Call after load_data and add_strategy with the same settings as the
+    ///checkpointed run. Indicators inside the strategy are rebuilt by its on_init.
+    pub fn restore_state(&mut self, state: &str) -> serde_json::Result<()> {
+        let state: EngineState = serde_json::from_str(state)?;
+
+        // Ids go on from the counts of the checkpoint with the same prefix
+        if !state.run_id.is_empty() {
+            self.run_id = state.run_id;
+            let strategy_name = self.strategy.strategy_name.clone();
+            self.scope_ids(&strategy_name);
+        }
+        self.datetime = state.datetime;
+        self.replay_count = state.replay_count;
+        self.pos = state.pos;
+        self.high_balance = state.high_balance;
+        self.limit_order_ids.set_count(state.limit_order_count);
+        self.stop_order_ids.set_count(state.stop_order_count);
+        self.trade_ids.set_count(state.trade_count);
+        self.resume_variables = Some(state.variables);
+
+        // Replay trades into the offset converter day by day to rebuild today and yesterday
+        // positions, then freeze positions of the active orders.
+        self.trades.clear();
+        self.daily_results.clear();
+        self.initial_price = state.initial_price;
+        self.seed_initial_pos();
+        for mut daily_result in state.daily_results {
+            self.offset_converter.roll_day();
+            for trade in daily_result.trades.iter_mut() {
+                trade.gateway_name = self.gateway_name;
+                self.offset_converter.update_trade(trade);
+                self.trades.insert(trade.vt_tradeid(), trade.clone());
+            }
+            self.daily_results.insert(daily_result.date, daily_result);
+        }
+
+        self.limit_orders.clear();
+        self.active_limit_orders.clear();
+        for mut order in state.active_limit_orders {
+            order.gateway_name = self.gateway_name;
+            let vt_orderid = order.vt_orderid();
+            self.offset_converter.update_order(&order);
+            self.active_limit_orders.insert(vt_orderid.clone());
+            self.limit_orders.insert(vt_orderid, order);
+        }
+
+        self.stop_orders.clear();
+        self.active_stop_orders.clear();
+        for stop_order in state.active_stop_orders {
+            self.active_stop_orders
+                .insert(stop_order.stop_orderid.clone());
+            self.stop_orders
+                .insert(stop_order.stop_orderid.clone(), stop_order);
+        }
+        self.bracket_entries = state.bracket_entries;
+        self.oco_orders = state.oco_orders;
+        self.order_expiries = state.order_expiries;
+        self.risk_halt = state.risk_halt;
+        self.exposure = state.exposure;
+        self.excursion = state.excursion;
+        self.settled_date = state.settlements.last().map(|settlement| settlement.date);
+        self.settlements = state.settlements;
+        Ok(())
+    }
+
+    ///Redirect engine output messages to callback instead of printing to stdout.
+    pub fn set_output_callback(&mut self, callback: impl Fn(&str) + Send + Sync + 'static) {
+        self.output_callback = Some(Box::new(callback));
+    }
+
+    ///Receive a BacktestingProgress snapshot after each replayed batch.
+    pub fn set_progress_callback(
+        &mut self,
+        callback: impl Fn(&BacktestingProgress) + Send + Sync + 'static,
+    ) {
+        self.progress_callback = Some(Box::new(callback));
+    }
+
+    ///Pass every order update and trade to hook as it happens during replay, e.g. a
+    ///jsonl_hook or http_hook.
+    pub fn set_event_hook(&mut self, hook: EventHook) {
+        self.event_hook = Some(hook);
+    }
+
+    ///Pass the bars, orders, trades and closed days of the replay to observer, see
+    ///BacktestObserver.
+    pub fn add_observer(&mut self, observer: impl BacktestObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    fn put_progress(&mut self, total_size: usize) {
+        let bars = self.replay_count;
+        let Some(replay) = self.replay.as_mut() else {
+            return;
+        };
+        let bars_per_sec = replay.throughput(bars);
+        let tenths = bars * 10 / total_size;
+        let printed = tenths > replay.printed_tenths;
+        if printed {
+            replay.printed_tenths = tenths;
+        }
+
+        let mut snapshot = BacktestingProgress {
+            progress: (bars as f64 / total_size as f64).min(1.0),
+            datetime: self.datetime,
+            pos: self.pos,
+            bars,
+            total_bars: total_size,
+            bars_per_sec,
+            eta: if bars_per_sec > 0.0 {
+                total_size.saturating_sub(bars) as f64 / bars_per_sec
+            } else {
+                0.0
+            },
+            ..Default::default()
+        };
+        if printed {
+            self.output(&format!("回放进度：{} [{}]", "=".repeat(tenths), snapshot));
+        }
+        if self.progress_callback.is_none() {
+            return;
+        }
+
+        // Daily results are marked to the latest close as bars are replayed
+        let balance = self.replay_balance();
+        self.high_balance = self.high_balance.max(balance);
+        snapshot.balance = balance;
+        snapshot.drawdown = balance - self.high_balance;
+        snapshot.trade_count = self.trade_ids.count();
+        if let Some(callback) = &self.progress_callback {
+            callback(&snapshot);
+        }
+    }
+
+    pub fn calculate_result(&mut self) -> DataFrame {
+        self.calculate_daily_pnl();
+
+        // Generate dataframe, daily results are already sorted by date
+        let results = &self.daily_results;
+        let column = |name: &str, f: fn(&DailyResult) -> f64| {
+            Float64Chunked::from_iter_values(name, results.values().map(f)).into_series()
+        };
+        let daily_df = DataFrame::new(vec![
+            Int32Chunked::from_iter_values(
+                "date",
+                results
+                    .values()
+                    .map(|result| (result.date - NaiveDate::default()).num_days() as i32),
+            )
+            .into_date()
+            .into_series(),
+            column("close_price", |result| result.close_price),
+            column("pre_close", |result| result.pre_close),
+            Int64Chunked::from_iter_values(
+                "trade_count",
+                results.values().map(|result| result.trade_count),
+            )
+            .into_series(),
+            column("start_pos", |result| result.start_pos),
+            column("end_pos", |result| result.end_pos),
+            column("turnover", |result| result.turnover),
+            column("commission", |result| result.commission),
+            column("slippage", |result| result.slippage),
+            column("financing", |result| result.financing),
+            column("trading_pnl", |result| result.trading_pnl),
+            column("holding_pnl", |result| result.holding_pnl),
+            column("total_pnl", |result| result.total_pnl),
+            column("net_pnl", |result| result.net_pnl),
+            column("fx_rate", |result| result.fx_rate),
+        ])
+        .unwrap();
+        self.daily_df = Some(daily_df.clone());
+
+        self.output("逐日盯市盈亏计算完成");
+        daily_df
+    }
+
+    ///Daily net pnl broken into holding pnl, trading pnl, commission, slippage and financing,
+    ///costs as negative values so that the components of a day add up to its net pnl, each
+    ///with its cum_ running total. Taken from the daily results after calculate_result or
+    ///calculate_result_statistics.
+    pub fn get_pnl_decomposition(&self) -> DataFrame {
+        let results = &self.daily_results;
+        let components: [DailyColumn; 6] = [
+            ("holding_pnl", |result| result.holding_pnl),
+            ("trading_pnl", |result| result.trading_pnl),
+            ("commission", |result| -result.commission),
+            ("slippage", |result| -result.slippage),
+            ("financing", |result| -result.financing),
+            ("net_pnl", |result| result.net_pnl),
+        ];
+
+        let mut columns = vec![Int32Chunked::from_iter_values(
+            "date",
+            results
+                .values()
+                .map(|result| (result.date - NaiveDate::default()).num_days() as i32),
+        )
+        .into_date()
+        .into_series()];
+        for (name, f) in components {
+            columns.push(
+                Float64Chunked::from_iter_values(name, results.values().map(f)).into_series(),
+            );
+        }
+        for (name, f) in components {
+            let cumulative = results.values().scan(0.0, |total, result| {
+                *total += f(result);
+                Some(*total)
+            });
+            columns.push(
+                Float64Chunked::from_iter_values(&format!("cum_{name}"), cumulative).into_series(),
+            );
+        }
+        DataFrame::new(columns).unwrap()
+    }
+
+    ///Statistics straight from daily results without building the daily DataFrame, for
+    ///optimization runs where only the statistics are kept. get_daily_df returns None after.
+    pub fn calculate_result_statistics(&mut self, output: bool) -> BacktestingStatistics {
+        self.calculate_daily_pnl();
+        self.daily_df = None;
+        self.output("逐日盯市盈亏计算完成");
+
+        self.output("开始计算策略统计指标");
+        let daily = (!self.daily_results.is_empty())
+            .then(|| DailySeries::from_results(self.daily_results.values()));
+        self.statistics_from_daily(daily.as_ref(), output)
+    }
+
+    fn calculate_daily_pnl(&mut self) {
+        self.output("开始计算逐日盯市盈亏");
+
+        if self.trades.len() == 0 {
+            self.output("回测成交记录为空");
+        }
+
+        // Trades are already in daily results, recalculate all days in case costs changed.
+        let (mut pre_close, mut start_pos) = self.initial_close();
+        let mut pre_date: Option<NaiveDate> = None;
+        let mut balance = self.capital;
+
+        for daily_result in self.daily_results.values_mut() {
+            let fx_rate = fx_rate(&self.base_currency, &self.contract, daily_result.date);
+            daily_result.calculate_pnl(
+                pre_close,
+                start_pos,
+                self.size,
+                self.rate,
+                self.slippage,
+                fx_rate,
+            );
+            let days = pre_date.map_or(1, |date| (daily_result.date - date).num_days());
+            daily_result.charge_financing(self.size, self.financing_rates, balance, days);
+
+            pre_close = daily_result.close_price;
+            start_pos = daily_result.end_pos;
+            pre_date = Some(daily_result.date);
+            balance += daily_result.net_pnl;
+        }
+    }
+
+    pub fn calculate_statistics(
+        &mut self,
+        df: Option<DataFrame>,
+        output: bool,
+    ) -> BacktestingStatistics {
+        self.output("开始计算策略统计指标");
+
+        // Check DataFrame input exterior
+        let df = df.or_else(|| self.daily_df.clone());
+        // A DataFrame missing daily columns counts as no data
+        let daily = df
+            .filter(|df| df.height() > 0)
+            .and_then(|df| self.check(DailySeries::from_df(&df)).ok());
+        self.statistics_from_daily(daily.as_ref(), output)
+    }
+
+    fn statistics_from_daily(
+        &self,
+        daily: Option<&DailySeries>,
+        output: bool,
+    ) -> BacktestingStatistics {
+        // Init all statistics default value
+        let mut start_date = NaiveDate::default();
+        let mut end_date = NaiveDate::default();
+        let mut total_days: i64 = 0;
+        let mut profit_days: i64 = 0;
+        let mut loss_days: i64 = 0;
+        let mut end_balance: f64 = 0.0;
+        let mut max_drawdown: f64 = 0.0;
+        let mut max_ddpercent: f64 = 0.0;
+        let mut max_drawdown_duration: i64 = 0;
+        let mut total_net_pnl: f64 = 0.0;
+        let mut daily_net_pnl: f64 = 0.0;
+        let mut total_commission: f64 = 0.0;
+        let mut daily_commission: f64 = 0.0;
+        let mut total_slippage: f64 = 0.0;
+        let mut daily_slippage: f64 = 0.0;
+        let mut total_financing: f64 = 0.0;
+        let mut total_turnover: f64 = 0.0;
+        let mut daily_turnover: f64 = 0.0;
+        let mut total_trade_count: i64 = 0;
+        let mut daily_trade_count: f64 = 0.0;
+        let mut total_return: f64 = 0.0;
+        let mut annual_return: f64 = 0.0;
+        let mut daily_return: f64 = 0.0;
+        let mut return_std: f64 = 0.0;
+        let mut sharpe_ratio: f64 = 0.0;
+        let ewm_sharpe: f64 = 0.0;
+        let mut return_drawdown_ratio: f64 = 0.0;
+        let mut position_days_ratio: f64 = 0.0;
+        let mut annual_turnover: f64 = 0.0;
+
+        // Statistics are computed over plain Vecs in one pass per column, without
+        // materializing intermediate DataFrames.
+        if let Some(daily) = daily {
+            let net_pnl = &daily.net_pnl;
+
+            // Calculate balance related time series data
+            let mut balance = Vec::with_capacity(net_pnl.len());
+            let mut returns = Vec::with_capacity(net_pnl.len());
+            let mut drawdown = Vec::with_capacity(net_pnl.len());
+            let mut ddpercent = Vec::with_capacity(net_pnl.len());
+            let mut pre_balance = self.capital;
+            let mut cum_pnl = 0f64;
+            let mut highlevel = 0f64;
+            for pnl in net_pnl {
+                cum_pnl += pnl;
+                let value = cum_pnl + self.capital;
+
+                // When balance falls below 0, set daily return to 0
+                let x = value / pre_balance;
+                returns.push(if x < 0.0 { 0.0 } else { x.ln() });
+
+                highlevel = highlevel.max(value);
+                drawdown.push(value - highlevel);
+                ddpercent.push((value - highlevel) / highlevel * 100f64);
+
+                balance.push(value);
+                pre_balance = value;
+            }
+
+            // All balance value needs to be positive
+            let positive_balance = balance.iter().all(|x| *x > 0f64);
+            if !positive_balance {
+                self.output("回测中出现爆仓（资金小于等于0），无法计算策略统计指标");
+            }
+
+            // Calculate statistics value
+            if positive_balance {
+                let dates = &daily.dates;
+                start_date = *dates.first().unwrap();
+                end_date = *dates.last().unwrap();
+
+                total_days = net_pnl.len() as i64;
+                profit_days = net_pnl.iter().filter(|pnl| **pnl > 0.0).count() as i64;
+                loss_days = net_pnl.iter().filter(|pnl| **pnl < 0.0).count() as i64;
+
+                end_balance = *balance.last().unwrap();
+                max_drawdown = drawdown.iter().copied().fold(f64::INFINITY, f64::min);
+                max_ddpercent = ddpercent.iter().copied().fold(f64::INFINITY, f64::min);
+
+                // Drawdown starts at the highest balance before its deepest point
+                let max_drawdown_end_idx = first_position(&drawdown, |x, best| x < best);
+                let max_drawdown_start_idx =
+                    first_position(&balance[..=max_drawdown_end_idx], |x, best| x > best);
+                max_drawdown_duration =
+                    (dates[max_drawdown_end_idx] - dates[max_drawdown_start_idx]).num_days();
+
+                total_net_pnl = net_pnl.iter().sum();
+                daily_net_pnl = total_net_pnl / total_days as f64;
+
+                total_commission = daily.commission;
+                daily_commission = total_commission / total_days as f64;
+
+                total_slippage = daily.slippage;
+                daily_slippage = total_slippage / total_days as f64;
+
+                total_financing = daily.financing;
+
+                total_turnover = daily.turnover;
+                daily_turnover = total_turnover / total_days as f64;
+
+                total_trade_count = daily.trade_count;
+                daily_trade_count = total_trade_count as f64 / total_days as f64;
+
+                position_days_ratio = daily.position_days as f64 / total_days as f64 * 100.0;
+                // Times the average balance traded a year
+                let average_balance = balance.iter().sum::<f64>() / total_days as f64;
+                annual_turnover = total_turnover / average_balance / total_days as f64
+                    * self.get_annual_days() as f64;
+
+                total_return = (end_balance / self.capital - 1.0) * 100.0;
+                annual_return = total_return / (total_days as f64) * self.get_annual_days() as f64;
+
+                let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+                let variance =
+                    returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / returns.len() as f64;
+                daily_return = mean * 100.0;
+                return_std = variance.sqrt() * 100.0;
+
+                // Mean excess return over the risk-free return of each day, both log returns
+                if return_std != 0.0 {
+                    let excess_return = returns
+                        .iter()
+                        .zip(dates)
+                        .map(|(r, date)| r - self.daily_risk_free(*date))
+                        .sum::<f64>()
+                        / returns.len() as f64;
+                    sharpe_ratio = excess_return * 100.0 / return_std
+                        * f64::sqrt(self.get_annual_days() as f64);
+                }
+
+                //     ewm_window: ExponentialMovingWindow = df["return"].ewm(halflife=self.half_life)
+                //     ewm_mean: Series = ewm_window.mean() * 100
+                //     ewm_std: Series = ewm_window.std() * 100
+                //     ewm_sharpe: float = ((ewm_mean - daily_risk_free) / ewm_std)[-1] * np.sqrt(self.annual_days)
+                // else:
+                //     sharpe_ratio: float = 0
+                //     ewm_sharpe: float = 0
+
+                if max_ddpercent != 0.0 {
+                    return_drawdown_ratio = -total_return / max_ddpercent;
+                } else {
+                    return_drawdown_ratio = 0.0;
+                }
+            }
+        }
+        let analyzer = ReturnsAnalyzer::new(self.get_annual_days(), self.risk_free);
+        let intraday =
+            IntradayStatistics::from_samples(&self.equity_samples, self.capital, &analyzer);
+        let (close_price, fx_rate) = self
+            .daily_results
+            .values()
+            .last()
+            .map_or((0.0, 1.0), |result| (result.close_price, result.fx_rate));
+        // Converted at the rate of the last day
+        let (long_net_pnl, short_net_pnl) = long_short_pnl(
+            self.initial_close(),
+            &self.get_all_trades(),
+            close_price,
+            self.size,
+            self.rate,
+            self.slippage,
+        );
+        let (long_net_pnl, short_net_pnl) = (long_net_pnl * fx_rate, short_net_pnl * fx_rate);
+
+        let statistics = BacktestingStatistics {
+            start_date,
+            end_date,
+            total_days,
+            profit_days,
+            loss_days,
+            capital: self.capital,
+            end_balance,
+            max_drawdown,
+            max_ddpercent,
+            max_drawdown_duration,
+            total_net_pnl,
+            daily_net_pnl,
+            total_commission,
+            daily_commission,
+            total_slippage,
+            daily_slippage,
+            total_financing,
+            total_turnover,
+            daily_turnover,
+            total_trade_count,
+            daily_trade_count,
+            total_return,
+            annual_return,
+            daily_return,
+            return_std,
+            sharpe_ratio,
+            ewm_sharpe,
+            return_drawdown_ratio,
+            position_days_ratio,
+            position_bars_ratio: self.exposure.position_bars_ratio(),
+            average_holding_bars: self.exposure.average_holding_bars(),
+            annual_turnover,
+            long_net_pnl,
+            short_net_pnl,
+            intraday_max_drawdown: intraday.max_drawdown,
+            intraday_max_ddpercent: intraday.max_ddpercent,
+            margin_call_count: intraday.margin_call_count,
+            intraday_sharpe_ratio: intraday.sharpe_ratio,
+            risk_halt: self.risk_halt.clone(),
+        };
+
+        if output {
+            let rows = statistics_rows(&statistics, self.equity_sampling.is_some());
+            for line in self.statistics_formatter.render(&rows) {
+                self.output(&line);
+            }
+        }
+        statistics
+    }
+
+    fn update_daily_close(&mut self, price: f64) {
+        let d = self.trading_day.trading_date(self.datetime);
+        if self.settled_date == Some(d) {
+            // Locked at the settlement price, later bars of the day don't move it
+            self.update_daily_pnl(d);
+            return;
+        }
+
+        self.daily_results
+            .entry(d)
+            .and_modify(|e| e.close_price = price)
+            .or_insert(DailyResult::new(d, price));
+        self.update_daily_pnl(d);
+    }
+
+    ///Add a trade to the result of its trading day and update the pnl of that day.
+    fn record_trade(&mut self, trade: &TradeData) {
+        let d = self.trading_day.trading_date(trade.datetime);
+        let price = if self.mode == BacktestingMode::BAR {
+            self.bar.close_price
+        } else {
+            self.tick.last_price
+        };
+
+        self.daily_results
+            .entry(d)
+            .or_insert_with(|| DailyResult::new(d, price))
+            .add_trade(trade.clone());
+        self.update_daily_pnl(d);
+        self.excursion.update_trade(trade, self.size);
+        self.order_timeline.update_trade(trade);
+        if let Some(hook) = &self.event_hook {
+            hook(&HookEvent::Trade(trade));
+        }
+        for observer in &mut self.observers {
+            observer.on_trade(trade);
+        }
+    }
+
+    ///Recalculate the pnl of one day from the close and position of the day before, days
+    ///before it are final once a later day has started.
+    fn update_daily_pnl(&mut self, date: NaiveDate) {
+        let (pre_close, start_pos) = self
+            .daily_results
+            .range(..date)
+            .next_back()
+            .map_or(self.initial_close(), |(_, result)| {
+                (result.close_price, result.end_pos)
+            });
+        let days = self
+            .daily_results
+            .range(..date)
+            .next_back()
+            .map_or(1, |(pre_date, _)| (date - *pre_date).num_days());
+        // Only summed when long positions are financed, this runs on every bar
+        let balance = if self.financing_rates.0 != 0.0 {
+            self.capital
+                + self
+                    .daily_results
+                    .range(..date)
+                    .map(|(_, result)| result.net_pnl)
+                    .sum::<f64>()
+        } else {
+            self.capital
+        };
+        let fx_rate = fx_rate(&self.base_currency, &self.contract, date);
+        if let Some(daily_result) = self.daily_results.get_mut(&date) {
+            daily_result.calculate_pnl(
+                pre_close,
+                start_pos,
+                self.size,
+                self.rate,
+                self.slippage,
+                fx_rate,
+            );
+            daily_result.charge_financing(self.size, self.financing_rates, balance, days);
+        }
+    }
+
+    ///Close and position the first day starts from.
+    fn initial_close(&self) -> (f64, f64) {
+        if self.initial_pos != 0.0 {
+            (self.initial_price, self.initial_pos)
+        } else {
+            (0.0, 0.0)
+        }
+    }
+
+    ///Book the initial position as yesterday position, so that the strategy can close it.
+    fn seed_initial_pos(&mut self) {
+        if self.initial_pos == 0.0 {
+            return;
+        }
+        self.offset_converter.update_position(&PositionData {
+            gateway_name: self.gateway_name,
+            symbol: self.symbol,
+            exchange: self.exchange,
+            direction: if self.initial_pos > 0.0 {
+                Direction::LONG
+            } else {
+                Direction::SHORT
+            },
+            volume: self.initial_pos.abs(),
+            yd_volume: self.initial_pos.abs(),
+            ..Default::default()
+        });
+    }
+
+    ///Daily results up to the current bar in date order, the last day is updated on every
+    ///trade and close.
+    pub fn get_daily_results(&self) -> impl Iterator<Item = &DailyResult> {
+        self.daily_results.values()
+    }
+
+    ///Net pnl of all days so far, marked to the latest close.
+    pub fn get_net_pnl(&self) -> f64 {
+        self.daily_results
+            .values()
+            .map(|result| result.net_pnl)
+            .sum()
+    }
+
+    ///Balance marked to the latest close while replaying, without summing over all days.
+    fn replay_balance(&self) -> f64 {
+        let date = self.trading_day.trading_date(self.datetime);
+        let today = self.daily_results.get(&date).map_or(0.0, |r| r.net_pnl);
+        self.capital + self.closed_net_pnl + today
+    }
+
+    ///Settle and close the trading day of the last bar or tick if datetime is in another one.
+    fn roll_day(&mut self, datetime: NaiveDateTime) {
+        let date = self.trading_day.trading_date(self.datetime);
+        if self.trading_day.trading_date(datetime) != date {
+            self.settle_day();
+            self.settlement_vwap.clear();
+            self.offset_converter.roll_day();
+            self.close_day();
+            self.closed_net_pnl += self.daily_results.get(&date).map_or(0.0, |r| r.net_pnl);
+        }
+    }
+
+    fn new_tick(&mut self, tick: &TickData) {
+        self.roll_day(tick.datetime);
+        self.tick.clone_from(tick);
+        self.datetime = self.tick.datetime;
+        if self.initial_pos != 0.0 && self.initial_price == 0.0 {
+            self.initial_price = self.tick.last_price;
+            self.excursion
+                .carry_position(self.datetime, self.initial_pos, self.initial_price);
+        }
+
+        self.excursion
+            .update_bar(self.tick.last_price, self.tick.last_price);
+        self.expire_orders();
+        self.cross_limit_order();
+        self.cross_stop_order();
+        self.strategy.on_tick(tick);
+        if self.timer.update(self.datetime) {
+            self.strategy.on_timer();
+        }
+
+        self.update_daily_close(self.tick.last_price);
+        self.exposure.update(self.pos);
+        self.check_risk_limits();
+    }
+
+    fn new_bar(&mut self, bar: &BarData) {
+        self.roll_day(bar.datetime);
+        self.bar.clone_from(bar);
+        self.datetime = self.bar.datetime;
+        if self.initial_pos != 0.0 && self.initial_price == 0.0 {
+            self.initial_price = self.bar.open_price;
+            self.excursion
+                .carry_position(self.datetime, self.initial_pos, self.initial_price);
+        }
+
+        self.excursion
+            .update_bar(self.bar.high_price, self.bar.low_price);
+        self.expire_orders();
+        self.cross_limit_order();
+        self.cross_stop_order();
+        if let Some(recorder) = &mut self.factor_recorder {
+            recorder.record_bar(self.datetime, self.bar.close_price);
+        }
+        self.strategy.on_bar(bar);
+        self.push_window_bars(bar);
+        if self.timer.update(self.datetime) {
+            self.strategy.on_timer();
+        }
+
+        self.update_daily_close(self.bar.close_price);
+        if let Some(config) = self.settlement {
+            self.settlement_vwap.update_bar(bar);
+            let date = self.trading_day.trading_date(self.datetime);
+            let settle_at =
+                date.and_time(config.time) - TimeDelta::hours(self.trading_day.utc_offset as i64);
+            if self.datetime >= settle_at {
+                self.settle_day();
+            }
+        }
+        self.exposure.update(self.pos);
+        if let Some(margin_ratio) = self.equity_sampling {
+            self.sample_equity(margin_ratio);
+        }
+        self.check_risk_limits();
+        for observer in &mut self.observers {
+            observer.on_bar_processed(bar, self.pos);
+        }
+    }
+
+    ///Pass the result of the trading day of the last bar to the observers, it is final once
+    ///the replay moves on to another day.
+    fn close_day(&mut self) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let date = self.trading_day.trading_date(self.datetime);
+        if let Some(result) = self.daily_results.get(&date) {
+            for observer in &mut self.observers {
+                observer.on_day_closed(result);
+            }
+        }
+    }
+
+    ///Lock the pnl of the trading day of the last bar at the settlement price, once a day,
+    ///and take the margin of the position held from the balance.
+    fn settle_day(&mut self) {
+        let Some(config) = self.settlement else {
+            return;
+        };
+        let date = self.trading_day.trading_date(self.datetime);
+        if self.settled_date == Some(date) || !self.daily_results.contains_key(&date) {
+            return;
+        }
+        let price = match config.price {
+            SettlementPrice::Close => self.bar.close_price,
+            SettlementPrice::Vwap => self
+                .settlement_vwap
+                .price(self.size)
+                .map_or(self.bar.close_price, |price| {
+                    round_to(price, self.pricetick)
+                }),
+        };
+        if let Some(result) = self.daily_results.get_mut(&date) {
+            result.close_price = price;
+        }
+        self.update_daily_pnl(date);
+        self.settled_date = Some(date);
+        // Positions opened today are yesterday positions after settlement
+        self.offset_converter.roll_day();
+
+        let balance = self.replay_balance();
+        let margin = self.pos.abs()
+            * price
+            * self.size
+            * config.margin_ratio
+            * fx_rate(&self.base_currency, &self.contract, date);
+        let settlement = Settlement {
+            date,
+            datetime: self.datetime,
+            price,
+            pos: self.pos,
+            net_pnl: self.daily_results[&date].net_pnl,
+            balance,
+            margin,
+            available: balance - margin,
+        };
+        self.output(&format!(
+            "{}结算：结算价{}，持仓{}，当日盈亏{:.2}，权益{:.2}，保证金{:.2}，可用{:.2}",
+            date, price, settlement.pos, settlement.net_pnl, balance, margin, settlement.available
+        ));
+        if settlement.available < 0.0 {
+            let msg = format!("保证金不足：权益{:.2}低于保证金{:.2}", balance, margin);
+            self.write_log_level(&msg, Level::Warn);
+        }
+        self.settlements.push(settlement);
+    }
+
+    ///Record the balance marked to the bar close, warning when it falls below the margin.
+    fn sample_equity(&mut self, margin_ratio: f64) {
+        let balance = self.replay_balance();
+        let margin = self.pos.abs()
+            * self.bar.close_price
+            * self.size
+            * margin_ratio
+            * fx_rate(&self.base_currency, &self.contract, self.datetime.date());
+
+        let in_margin_call = self
+            .equity_samples
+            .last()
+            .is_some_and(EquitySample::is_margin_call);
+        let sample = EquitySample {
+            datetime: self.datetime,
+            balance,
+            margin,
+        };
+        if sample.is_margin_call() && !in_margin_call {
+            let msg = format!("保证金不足：权益{:.2}低于保证金{:.2}", balance, margin);
+            self.write_log_level(&msg, Level::Warn);
+        }
+        self.equity_samples.push(sample);
+    }
+
+    ///Halt trading once the balance marked to the latest close breaches a risk limit.
+    fn check_risk_limits(&mut self) {
+        if self.risk_halt.is_some() || self.risk_limits == RiskLimits::default() {
+            return;
+        }
+
+        let balance = self.replay_balance();
+        self.high_balance = self.high_balance.max(balance);
+        let ddpercent = (self.high_balance - balance) / self.high_balance * 100.0;
+        let daily_loss = self
+            .daily_results
+            .values()
+            .next_back()
+            .map_or(0.0, |result| -result.net_pnl);
+
+        let reason = match self.risk_limits {
+            RiskLimits {
+                max_ddpercent: Some(max_ddpercent),
+                ..
+            } if ddpercent >= max_ddpercent => {
+                format!("回撤{:.2}%超过上限{:.2}%", ddpercent, max_ddpercent)
+            }
+            RiskLimits {
+                max_daily_loss: Some(max_daily_loss),
+                ..
+            } if daily_loss >= max_daily_loss => {
+                format!("当日亏损{:.2}超过上限{:.2}", daily_loss, max_daily_loss)
+            }
+            _ => return,
+        };
+        self.halt_trading(reason);
+    }
+
+    ///Cancel all orders, close the position at the next bar and stop the strategy trading.
+    fn halt_trading(&mut self, reason: String) {
+        self.write_log_level(&format!("触发风控：{}，停止交易", reason), Level::Warn);
+        self.cancel_all(std::ptr::null_mut());
+
+        // Prices cross any bar or tick, the position is closed at the open of the next bar or
+        // the best price of the next tick
+        if self.pos > 0.0 {
+            let volume = self.pos;
+            self.send_order(
+                std::ptr::null_mut(),
+                Direction::SHORT,
+                Offset::CLOSE,
+                0.0,
+                volume,
+                false,
+                false,
+                false,
+                "risk_halt",
+            );
+        } else if self.pos < 0.0 {
+            let price = if self.mode == BacktestingMode::BAR {
+                self.bar.high_price * 2.0
+            } else if self.tick.limit_up > 0.0 {
+                self.tick.limit_up
+            } else {
+                self.tick.ask_price_1.max(self.tick.last_price) * 2.0
+            };
+            let price = round_to(price, self.pricetick);
+            let volume = -self.pos;
+            self.send_order(
+                std::ptr::null_mut(),
+                Direction::LONG,
+                Offset::CLOSE,
+                price,
+                volume,
+                false,
+                false,
+                false,
+                "risk_halt",
+            );
+        }
+
+        self.strategy.set_trading(false);
+        self.risk_halt = Some(RiskHalt {
+            datetime: self.datetime,
+            reason,
+        });
+    }
+
+    ///Push the bars of secondary intervals finished by a bar into the strategy.
+    fn push_window_bars(&mut self, bar: &BarData) {
+        if self.window_bars.is_empty() {
+            return;
+        }
+
+        let bar_end = bar.datetime + get_interval_delta(self.interval).unwrap_or_default();
+        for builder in self.window_bars.iter_mut() {
+            for window_bar in builder.update_bar(bar, bar_end) {
+                self.strategy.on_bar(&window_bar);
+            }
+        }
+    }
+
+    ///Cancel the resting orders whose expiry has been reached.
+    fn expire_orders(&mut self) {
+        if self.order_expiries.is_empty() {
+            return;
+        }
+
+        let mut expired: Vec<String> = self
+            .order_expiries
+            .iter()
+            .filter(|(_, expiry)| **expiry <= self.datetime)
+            .map(|(vt_orderid, _)| vt_orderid.clone())
+            .collect();
+        expired.sort_by_cached_key(|vt_orderid| {
+            (IdGenerator::sequence(vt_orderid), vt_orderid.clone())
+        });
+        for vt_orderid in expired {
+            self.order_expiries.remove(&vt_orderid);
+            self.cancel_order(std::ptr::null_mut(), vt_orderid);
+        }
+    }
+
+    fn cross_limit_order(&mut self) {
+        let long_cross_price;
+        let short_cross_price;
+        let long_best_price;
+        let short_best_price;
+        if self.mode == BacktestingMode::BAR {
+            long_cross_price = self.bar.low_price;
+            short_cross_price = self.bar.high_price;
+            long_best_price = self.bar.open_price;
+            short_best_price = self.bar.open_price;
+        } else {
+            long_cross_price = self.tick.ask_price_1;
+            short_cross_price = self.tick.bid_price_1;
+            long_best_price = long_cross_price;
+            short_best_price = short_cross_price;
+        }
+
+        if self.active_limit_orders.is_empty() {
+            return;
+        }
+
+        let mut sequences = std::mem::take(&mut self.cross_sequences);
+        sequences.clear();
+        sequences.extend(self.active_limit_orders.sequences());
+        for &sequence in &sequences {
+            // Order may have been cancelled by strategy callbacks of orders crossed earlier.
+            let Some(vt_orderid) = self.active_limit_orders.get(sequence) else {
+                continue;
+            };
+
+            // Check whether limit orders can be filled, pending orders are left untouched.
+            let order = &self.limit_orders[vt_orderid];
+            let submitting = order.status == Status::SUBMITTING;
+            let long_cross: bool = order.direction == Direction::LONG
+                && order.price >= long_cross_price
+                && long_cross_price > 0.0;
+
+            let short_cross: bool = order.direction == Direction::SHORT
+                && order.price <= short_cross_price
+                && short_cross_price > 0.0;
+
+            if !submitting && !long_cross && !short_cross {
+                continue;
+            }
+            let vt_orderid = vt_orderid.to_string();
+            let mut order = order.clone();
+
+            // Push order update with status "not traded" (pending).
+            if submitting {
+                order.status = Status::NOTTRADED;
+                self.put_order(&order);
+                if !self.active_limit_orders.contains(&vt_orderid) {
+                    continue;
+                }
+            }
+
+            if !long_cross && !short_cross {
+                continue;
+            }
+
+            // Volume beyond the depth of the tick is left for the following ticks
+            let remaining = order.volume - order.traded;
+            let best_price = if long_cross {
+                order.price.min(long_best_price)
+            } else {
+                order.price.max(short_best_price)
+            };
+            let (volume, trade_price) =
+                match self.depth_price(order.direction, remaining, Some(order.price)) {
+                    Some(fill) => fill,
+                    None if self.fills_depth() => continue,
+                    None => (remaining, best_price),
+                };
+            let pos_change = if long_cross { volume } else { -volume };
+
+            // Push order udpate with status "all traded" (filled) or "part traded".
+            order.traded += volume;
+            order.status = if order.traded < order.volume {
+                Status::PARTTRADED
+            } else {
+                Status::ALLTRADED
+            };
+            self.put_order(&order);
+
+            // Push trade update
+            let trade_price = self.impact_price(order.direction, trade_price, volume);
+            let trade_price = self.hooked_price(
+                Fill {
+                    direction: order.direction,
+                    offset: order.offset,
+                    volume,
+                    order_price: order.price,
+                    stop: false,
+                    bar: &self.bar,
+                    tick: &self.tick,
+                    pricetick: self.pricetick,
+                },
+                trade_price,
+            );
+
+            let trade = TradeData {
+                symbol: order.symbol,
+                exchange: order.exchange,
+                orderid: order.orderid.to_string(),
+                tradeid: self.trade_ids.next_id(),
+                direction: order.direction,
+                offset: order.offset,
+                price: trade_price,
+                volume,
+                datetime: self.datetime,
+                reference: order.reference.clone(),
+                gateway_name: self.gateway_name,
+            };
+
+            self.offset_converter.update_trade(&trade);
+            self.update_pos(pos_change);
+            self.strategy.on_trade(&trade);
+            self.sync_strategy_data();
+
+            self.record_trade(&trade);
+            self.trades.insert(trade.vt_tradeid(), trade);
+            self.update_bracket(&vt_orderid, volume, order.status == Status::ALLTRADED);
+        }
+        self.cross_sequences = sequences;
+    }
+
+    fn cross_stop_order(&mut self) {
+        let long_cross_price;
+        let short_cross_price;
+        let long_best_price;
+        let short_best_price;
+        if self.mode == BacktestingMode::BAR {
+            long_cross_price = self.bar.high_price;
+            short_cross_price = self.bar.low_price;
+            long_best_price = self.bar.open_price;
+            short_best_price = self.bar.open_price;
+        } else {
+            long_cross_price = self.tick.last_price;
+            short_cross_price = self.tick.last_price;
+            long_best_price = long_cross_price;
+            short_best_price = short_cross_price;
+        }
+
+        if self.active_stop_orders.is_empty() {
+            return;
+        }
+
+        let mut sequences = std::mem::take(&mut self.cross_sequences);
+        sequences.clear();
+        sequences.extend(self.active_stop_orders.sequences());
+        for &sequence in &sequences {
+            // Stop order may have been cancelled by strategy callbacks of orders triggered earlier.
+            let Some(stop_orderid) = self.active_stop_orders.get(sequence) else {
+                continue;
+            };
+            let stop_order = &self.stop_orders[stop_orderid];
+
+            // Check whether stop order can be triggered.
+            let long_cross: bool =
+                stop_order.direction == Direction::LONG && stop_order.price <= long_cross_price;
+
+            let short_cross: bool =
+                stop_order.direction == Direction::SHORT && stop_order.price >= short_cross_price;
+
+            if !long_cross && !short_cross {
+                continue;
+            }
+            let mut stop_order = stop_order.clone();
+
+            // Convert offset with lock and net flags, keep waiting if position isn't enough.
+            let req = OrderRequest {
+                symbol: self.symbol,
+                exchange: self.exchange,
+                direction: stop_order.direction,
+                type_: OrderType::STOP,
+                volume: stop_order.volume,
+                price: stop_order.price,
+                offset: stop_order.offset,
+                reference: stop_order.reference.clone(),
+            };
+            let req_list =
+                self.offset_converter
+                    .convert_order_request(&req, stop_order.lock, stop_order.net);
+            if req_list.is_empty() {
+                continue;
+            }
+
+            // Triggered stop orders take the book at any price, volume beyond its depth is
+            // cancelled like the rest of a market order
+            let depth = self.depth_price(stop_order.direction, stop_order.volume, None);
+            if depth.is_none() && self.fills_depth() {
+                continue;
+            }
+            let (mut fillable, best_price) = match depth {
+                Some((volume, price)) => (volume, Some(price)),
+                None => (stop_order.volume, None),
+            };
+            let trade_price = if long_cross {
+                stop_order.price.max(best_price.unwrap_or(long_best_price))
+            } else {
+                stop_order.price.min(best_price.unwrap_or(short_best_price))
+            };
+            let trade_price = self.impact_price(stop_order.direction, trade_price, fillable);
+            let trade_price = self.hooked_price(
+                Fill {
+                    direction: stop_order.direction,
+                    offset: stop_order.offset,
+                    volume: fillable,
+                    order_price: stop_order.price,
+                    stop: true,
+                    bar: &self.bar,
+                    tick: &self.tick,
+                    pricetick: self.pricetick,
+                },
+                trade_price,
+            );
+
+            let mut orders = Vec::new();
+            let mut trades = Vec::new();
+            for req in req_list {
+                let traded = req.volume.min(fillable);
+                fillable -= traded;
+
+                // Create order data.
+                let order = OrderData {
+                    symbol: self.symbol,
+                    exchange: self.exchange,
+                    orderid: self.limit_order_ids.next_id(),
+                    direction: req.direction,
+                    offset: req.offset,
+                    price: req.price,
+                    volume: req.volume,
+                    traded,
+                    status: if traded < req.volume {
+                        Status::CANCELLED
+                    } else {
+                        Status::ALLTRADED
+                    },
+                    gateway_name: self.gateway_name,
+                    datetime: self.datetime,
+                    reference: req.reference,
+                    ..Default::default()
+                };
+
+                // Create trade data.
+                let trade = (traded > 0.0).then(|| TradeData {
+                    symbol: order.symbol,
+                    exchange: order.exchange,
+                    orderid: order.orderid.clone(),
+                    tradeid: self.trade_ids.next_id(),
+                    direction: order.direction,
+                    offset: order.offset,
+                    price: trade_price,
+                    volume: traded,
+                    datetime: self.datetime,
+                    reference: order.reference.clone(),
+                    gateway_name: self.gateway_name,
+                });
+
+                stop_order.vt_orderids.push(order.vt_orderid());
+                orders.push(order);
+                trades.push(trade);
+            }
+
+            // Update stop order and push update to strategy.
+            stop_order.status = StopOrderStatus::TRIGGERED;
+            self.put_stop_order(&stop_order);
+            let stop_orderid = stop_order.stop_orderid;
+            let volume: f64 = trades.iter().flatten().map(|t| t.volume).sum();
+
+            for (order, trade) in orders.iter().zip(trades) {
+                self.put_order(order);
+                let Some(trade) = trade else {
+                    continue;
+                };
+
+                let pos_change = if long_cross {
+                    trade.volume
+                } else {
+                    -trade.volume
+                };
+                self.offset_converter.update_trade(&trade);
+                self.update_pos(pos_change);
+                self.strategy.on_trade(&trade);
+                self.sync_strategy_data();
+
+                self.record_trade(&trade);
+                self.trades.insert(trade.vt_tradeid(), trade);
+            }
+            self.update_bracket(&stop_orderid, volume, true);
+        }
+        self.cross_sequences = sequences;
+    }
+
+    ///Send exit legs for a fill of a bracket entry, or take a fill of an exit leg off its
+    ///sibling legs. Links of the order are dropped once it's done.
+    fn update_bracket(&mut self, vt_orderid: &str, volume: f64, done: bool) {
+        if let Some(siblings) = self.oco_orders.get(vt_orderid).cloned() {
+            self.reduce_legs(&siblings, volume);
+        }
+        let bracket = self.bracket_entries.get(vt_orderid).cloned();
+        if done {
+            self.oco_orders.remove(vt_orderid);
+            self.bracket_entries.remove(vt_orderid);
+        }
+
+        // Each fill of the entry gets legs of its own, so a part traded entry is covered
+        let Some(bracket) = bracket else {
+            return;
+        };
+        let take_profit = self.send_order(
+            std::ptr::null_mut(),
+            bracket.direction,
+            Offset::CLOSE,
+            bracket.take_profit,
+            volume,
+            false,
+            bracket.lock,
+            bracket.net,
+            &bracket.reference,
+        );
+        let stop_loss = self.send_order(
+            std::ptr::null_mut(),
+            bracket.direction,
+            Offset::CLOSE,
+            bracket.stop_loss,
+            volume,
+            true,
+            bracket.lock,
+            bracket.net,
+            &bracket.reference,
+        );
+        for vt_orderid in &take_profit {
+            self.oco_orders
+                .insert(vt_orderid.clone(), stop_loss.clone());
+        }
+        for stop_orderid in &stop_loss {
+            self.oco_orders
+                .insert(stop_orderid.clone(), take_profit.clone());
+        }
+    }
+
+    ///Take volume off active exit legs in turn, a leg with nothing left is cancelled.
+    fn reduce_legs(&mut self, legs: &[String], mut volume: f64) {
+        for leg in legs {
+            if volume <= POS_TOLERANCE {
+                break;
+            }
+            let remaining = if let Some(stop_order) = self.stop_orders.get(leg) {
+                if stop_order.status != StopOrderStatus::WAITING {
+                    continue;
+                }
+                let mut stop_order = stop_order.clone();
+                let reduced = volume.min(stop_order.volume);
+                volume -= reduced;
+                stop_order.volume -= reduced;
+                if stop_order.volume > POS_TOLERANCE {
+                    self.put_stop_order(&stop_order);
+                }
+                stop_order.volume
+            } else if let Some(order) = self.limit_orders.get(leg) {
+                if !order.is_active() {
+                    continue;
+                }
+                let mut order = order.clone();
+                let reduced = volume.min(order.volume - order.traded);
+                volume -= reduced;
+                order.volume -= reduced;
+                if order.volume - order.traded > POS_TOLERANCE {
+                    self.put_order(&order);
+                }
+                order.volume - order.traded
+            } else {
+                continue;
+            };
+            if remaining <= POS_TOLERANCE {
+                self.cancel_order(std::ptr::null_mut(), leg.clone());
+            }
+        }
+    }
+
+    ///Store the latest order status and push it to the strategy.
+    fn put_order(&mut self, order: &OrderData) {
+        let vt_orderid = order.vt_orderid();
+        if !order.is_active() {
+            self.active_limit_orders.remove(&vt_orderid);
+            self.order_expiries.remove(&vt_orderid);
+            // Fills so far have their exit legs, only a fully traded order has one to go
+            if order.status != Status::ALLTRADED {
+                self.bracket_entries.remove(&vt_orderid);
+                self.oco_orders.remove(&vt_orderid);
+            }
+        }
+        self.limit_orders.insert(vt_orderid, order.clone());
+
+        self.offset_converter.update_order(order);
+        self.order_timeline.update_order(order, self.datetime);
+        if let Some(hook) = &self.event_hook {
+            hook(&HookEvent::Order(order));
+        }
+        for observer in &mut self.observers {
+            observer.on_order(order);
+        }
+        self.strategy.on_order(order);
+    }
+
+    ///Store the latest stop order status and push it to the strategy.
+    fn put_stop_order(&mut self, stop_order: &StopOrder) {
+        if stop_order.status != StopOrderStatus::WAITING {
+            self.active_stop_orders.remove(&stop_order.stop_orderid);
+            self.order_expiries.remove(&stop_order.stop_orderid);
+        }
+        if stop_order.status == StopOrderStatus::CANCELLED {
+            self.oco_orders.remove(&stop_order.stop_orderid);
+        }
+        self.stop_orders
+            .insert(stop_order.stop_orderid.clone(), stop_order.clone());
+
+        self.strategy.on_stop_order(stop_order);
+    }
+
+    ///Bars before the backtest start for the strategy to initialize with, empty with the
+    ///error logged if they can't be loaded.
+    fn load_bar(
+        &mut self,
+        vt_symbol: &str,
+        days: i64,
+        interval: Interval,
+        // callback: Callable,
+        use_database: bool,
+    ) -> Vec<BarData> {
+        match self.try_load_bar(vt_symbol, days, interval, use_database) {
+            Ok(bars) => bars,
+            Err(e) => {
+                self.write_log_level(&format!("历史数据加载失败：{}", e), Level::Error);
+                Vec::new()
+            }
+        }
+    }
+
+    ///load_bar of an interval passed over the ABI as its kind and size.
+    fn load_bar_kind(
+        &mut self,
+        vt_symbol: &str,
+        days: i64,
+        kind: IntervalKind,
+        size: u32,
+        use_database: bool,
+    ) -> Vec<BarData> {
+        match Interval::from_kind(kind, size) {
+            Some(interval) => self.load_bar(vt_symbol, days, interval, use_database),
+            None => {
+                self.write_log_level(
+                    &format!("历史数据加载失败：无效的K线周期{:?}，大小{}", kind, size),
+                    Level::Error,
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    fn try_load_bar(
+        &self,
+        vt_symbol: &str,
+        days: i64,
+        interval: Interval,
+        use_database: bool,
+    ) -> VnrsResult<Vec<BarData>> {
+        let load_interval = match self.resample {
+            Some((source, _)) if interval == self.interval => source,
+            _ => interval,
+        };
+        let init_end = self.start - interval_delta(load_interval)?;
+        let init_start = self
+            .start
+            .checked_sub_days(Days::new(days as u64))
+            .ok_or_else(|| VnrsError::Setting(format!("invalid days {}", days)))?;
+
+        let (symbol, exchange) = extract_vt_symbol(vt_symbol)?;
+
+        match self.resample {
+            Some((source, window)) if interval == self.interval => self.resample_bars(
+                &load_history_data(
+                    &symbol,
+                    exchange,
+                    source,
+                    init_start,
+                    init_end,
+                    use_database,
+                    self.adjust,
+                )?,
+                window,
+            ),
+            _ => load_history_data(
+                &symbol,
+                exchange,
+                interval,
+                init_start,
+                init_end,
+                use_database,
+                self.adjust,
+            ),
+        }
+    }
+
+    fn load_tick(&mut self, vt_symbol: &str, days: i64) -> Vec<TickData> {
+        vec![]
+    }
+
+    fn send_order(
+        &mut self,
+        strategy: *mut CtaTemplate,
+        direction: Direction,
+        offset: Offset,
+        price: f64,
+        volume: f64,
+        stop: bool,
+        lock: bool,
+        net: bool,
+        reference: &str,
+    ) -> Vec<String> {
+        if self.risk_halt.is_some() {
+            let msg = "风控已停止交易";
+            return vec![self.reject_order(direction, offset, price, volume, reference, msg)];
+        }
+        let volume = match self.round_volume(volume) {
+            Ok(rounded) => {
+                if rounded != volume {
+                    let msg = format!("委托数量{}按交易单位调整为{}", volume, rounded);
+                    self.write_log_level(&msg, Level::Debug);
+                }
+                rounded
+            }
+            Err(msg) => {
+                return vec![self.reject_order(direction, offset, price, volume, reference, &msg)]
+            }
+        };
+        if let Err(msg) = self.validate_order(price, volume) {
+            return vec![self.reject_order(direction, offset, price, volume, reference, &msg)];
+        }
+
+        let req = OrderRequest {
+            symbol: self.symbol,
+            exchange: self.exchange,
+            direction,
+            type_: if stop {
+                OrderType::STOP
+            } else {
+                OrderType::LIMIT
+            },
+            volume,
+            price: round_to(price, self.pricetick),
+            offset,
+            reference: reference.to_string(),
+        };
+        if stop {
+            return vec![self.send_stop_order(&req, lock, net)];
+        }
+
+        let req_list = self.offset_converter.convert_order_request(&req, lock, net);
+        if req_list.is_empty() {
+            let msg = "委托转换失败，可平仓位不足";
+            return vec![self.reject_order(direction, offset, req.price, volume, reference, msg)];
+        }
+
+        let mut vt_orderids = Vec::new();
+        for req in req_list {
+            let vt_orderid = self.send_limit_order(&req);
+            self.offset_converter
+                .update_order_request(&req, &vt_orderid);
+            vt_orderids.push(vt_orderid);
+        }
+        vt_orderids
+    }
+
+    ///Send an entry limit order to open, each trade of it is then closed by a take profit
+    ///limit order and a stop loss stop order, filling either one cancels the other.
+    #[allow(clippy::too_many_arguments)]
+    fn send_bracket_order(
+        &mut self,
+        strategy: *mut CtaTemplate,
+        direction: Direction,
+        price: f64,
+        volume: f64,
+        take_profit: f64,
+        stop_loss: f64,
+        lock: bool,
+        net: bool,
+        reference: &str,
+    ) -> Vec<String> {
+        let (exit_direction, valid) = match direction {
+            Direction::LONG => (Direction::SHORT, take_profit > price && stop_loss < price),
+            _ => (Direction::LONG, take_profit < price && stop_loss > price),
+        };
+        if !valid {
+            let msg = format!(
+                "止盈价{}或止损价{}与开仓价{}方向不符",
+                take_profit, stop_loss, price
+            );
+            return vec![self.reject_order(
+                direction,
+                Offset::OPEN,
+                price,
+                volume,
+                reference,
+                &msg,
+            )];
+        }
+
+        let vt_orderids = self.send_order(
+            strategy,
+            direction,
+            Offset::OPEN,
+            price,
+            volume,
+            false,
+            lock,
+            net,
+            reference,
+        );
+        for vt_orderid in &vt_orderids {
+            if self.active_limit_orders.contains(vt_orderid) {
+                let bracket = BracketOrder {
+                    direction: exit_direction,
+                    take_profit,
+                    stop_loss,
+                    lock,
+                    net,
+                    reference: reference.to_string(),
+                };
+                self.bracket_entries.insert(vt_orderid.clone(), bracket);
+            }
+        }
+        vt_orderids
+    }
+
+    ///Check volume, price and price tick alignment of an order before accepting it.
+    fn validate_order(&self, price: f64, volume: f64) -> Result<(), String> {
+        if !volume.is_finite() || volume <= 0.0 {
+            return Err(format!("委托数量无效：{}", volume));
+        }
+        if !price.is_finite() || price < 0.0 {
+            return Err(format!("委托价格无效：{}", price));
+        }
+        if volume < self.contract.min_volume {
+            return Err(format!(
+                "委托数量{}小于最小交易数量{}",
+                volume, self.contract.min_volume
+            ));
+        }
+        if self.pricetick > 0.0 {
+            let ticks = price / self.pricetick;
+            if (ticks - ticks.round()).abs() > 1e-6 {
+                return Err(format!(
+                    "委托价格{}不是最小价格变动{}的整数倍",
+                    price, self.pricetick
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    ///Record an order rejected by validation and push it to the strategy.
+    fn reject_order(
+        &mut self,
+        direction: Direction,
+        offset: Offset,
+        price: f64,
+        volume: f64,
+        reference: &str,
+        msg: &str,
+    ) -> String {
+        let order = OrderData {
+            symbol: self.symbol,
+            exchange: self.exchange,
+            orderid: self.limit_order_ids.next_id(),
+            direction,
+            offset,
+            price,
+            volume,
+            status: Status::REJECTED,
+            gateway_name: self.gateway_name,
+            datetime: self.datetime,
+            reference: reference.to_string(),
+            ..Default::default()
+        };
+        self.write_log_level(&format!("委托被拒绝：{}", msg), Level::Warn);
+        self.put_order(&order);
+        order.vt_orderid()
+    }
+
+    fn send_stop_order(&mut self, req: &OrderRequest, lock: bool, net: bool) -> String {
+        let stop_order = StopOrder {
+            vt_symbol: self.vt_symbol.to_string(),
+            direction: req.direction,
+            offset: req.offset,
+            price: req.price,
+            volume: req.volume,
+            datetime: self.datetime,
+            stop_orderid: self.stop_order_ids.next_id(),
+            strategy_name: self.strategy.strategy_name.clone(),
+            lock,
+            net,
+            reference: req.reference.clone(),
+            ..Default::default()
+        };
+
+        let stop_orderid = stop_order.stop_orderid.clone();
+        self.active_stop_orders.insert(stop_orderid.clone());
+        self.stop_orders.insert(stop_orderid.clone(), stop_order);
+
+        stop_orderid
+    }
+
+    fn send_limit_order(&mut self, req: &OrderRequest) -> String {
+        let mut order = req.create_order_data(&self.limit_order_ids.next_id(), self.gateway_name);
+        order.status = Status::SUBMITTING;
+        order.datetime = self.datetime;
+
+        let vt_orderid = order.vt_orderid();
+        self.order_timeline.update_order(&order, self.datetime);
+        if let Some(hook) = &self.event_hook {
+            hook(&HookEvent::Order(&order));
+        }
+        for observer in &mut self.observers {
+            observer.on_order(&order);
+        }
+        self.active_limit_orders.insert(vt_orderid.clone());
+        self.limit_orders.insert(vt_orderid.clone(), order);
+
+        vt_orderid
+    }
+
+    ///Cancel order by vt_orderid.
+    fn cancel_order(&mut self, strategy: *mut CtaTemplate, vt_orderid: String) {
+        if vt_orderid.starts_with(STOPORDER_PREFIX) {
+            self.cancel_stop_order(strategy, vt_orderid);
+        } else {
+            self.cancel_limit_order(strategy, vt_orderid);
+        }
+    }
+
+    fn cancel_stop_order(&mut self, strategy: *mut CtaTemplate, vt_orderid: String) {
+        if !self.active_stop_orders.contains(&vt_orderid) {
+            return;
+        }
+        let mut stop_order = self.stop_orders[&vt_orderid].clone();
+
+        stop_order.status = StopOrderStatus::CANCELLED;
+        self.put_stop_order(&stop_order);
+    }
+
+    fn cancel_limit_order(&mut self, strategy: *mut CtaTemplate, vt_orderid: String) {
+        if !self.active_limit_orders.contains(&vt_orderid) {
+            return;
+        }
+        let mut order = self.limit_orders[&vt_orderid].clone();
+
+        order.status = Status::CANCELLED;
+        self.put_order(&order);
+    }
+
+    ///Cancel a resting limit or stop order once its time in force is over. DAY orders
+    ///expire at the start of the next trading day, GTD orders at the first bar at or after
+    ///expiry.
+    fn set_expiry(&mut self, vt_orderid: &str, time_in_force: TimeInForce, expiry: NaiveDateTime) {
+        if !self.active_limit_orders.contains(vt_orderid)
+            && !self.active_stop_orders.contains(vt_orderid)
+        {
+            return;
+        }
+
+        let expiry = match time_in_force {
+            TimeInForce::GTC => {
+                self.order_expiries.remove(vt_orderid);
+                return;
+            }
+            TimeInForce::DAY => self.trading_day.next_day_start(self.datetime),
+            TimeInForce::GTD => expiry,
+        };
+        self.order_expiries.insert(vt_orderid.to_string(), expiry);
+    }
+
+    ///set_expiry with the expiry in milliseconds since the epoch as passed over the ABI. A GTD
+    ///expiry out of range is logged and leaves the order as it was.
+    fn set_expiry_millis(&mut self, vt_orderid: &str, time_in_force: TimeInForce, expiry: i64) {
+        let expiry = match DateTime::from_timestamp_millis(expiry) {
+            Some(expiry) => expiry.naive_utc(),
+            None if time_in_force == TimeInForce::GTD => {
+                self.write_log(&format!(
+                    "委托{}的过期时间{}无效，保持原有期限",
+                    vt_orderid, expiry
+                ));
+                return;
+            }
+            // Only GTD orders have an expiry
+            None => NaiveDateTime::default(),
+        };
+        self.set_expiry(vt_orderid, time_in_force, expiry);
+    }
+
+    ///Cancel all orders, both limit and stop.
+    fn cancel_all(&mut self, strategy: *mut CtaTemplate) {
+        let vt_orderids: Vec<String> = self.active_limit_orders.iter().map(String::from).collect();
+        for vt_orderid in vt_orderids {
+            self.cancel_limit_order(strategy, vt_orderid);
+        }
+
+        let stop_orderids: Vec<String> = self.active_stop_orders.iter().map(String::from).collect();
         for vt_orderid in stop_orderids {
             self.cancel_stop_order(strategy, vt_orderid);
         }
     }
 
-    fn write_log(&mut self, msg: &str) {
-        let msg = format!("{}\t{}", self.datetime, msg);
-        self.logs.push(msg);
+    fn update_pos(&mut self, pos_change: f64) {
+        self.pos += pos_change;
+        // Keep fractional positions on the lot grid instead of accumulating float error
+        let lot_size = self.get_lot_size();
+        if lot_size > 0.0 {
+            self.pos = round_to(self.pos, lot_size);
+        }
+        self.strategy.set_pos(self.pos);
+    }
+
+    pub fn get_engine_type(&self) -> EngineType {
+        self.engine_type
+    }
+
+    ///Net position of the strategy.
+    pub fn get_pos(&self) -> f64 {
+        self.pos
+    }
+
+    pub fn get_position_detail(&self) -> PositionDetail {
+        match self.offset_converter.get_holding(&self.vt_symbol) {
+            Some(holding) => PositionDetail::from_holding(self.pos, holding),
+            None => PositionDetail::from_net(self.pos),
+        }
+    }
+
+    fn write_log(&mut self, msg: &str) {
+        self.write_log_level(msg, Level::Info);
+    }
+
+    ///Record a log of the strategy at backtesting time, also put as EVENT_CTA_LOG if an
+    ///event engine is set.
+    fn write_log_level(&mut self, msg: &str, level: Level) {
+        let log = LogData::new(msg, level, &self.strategy.strategy_name, self.datetime);
+        if let Some(event_engine) = &self.event_engine {
+            event_engine.put(Event::new(EVENT_CTA_LOG, log.clone()));
+        }
+        self.logs.push(log);
+    }
+
+    ///Logs written during the last run, in time sequence.
+    pub fn get_logs(&self) -> &[LogData] {
+        &self.logs
+    }
+
+    ///Put strategy logs into the event engine while running.
+    pub fn set_event_engine(&mut self, event_engine: Arc<EventEngine>) {
+        self.event_engine = Some(event_engine);
+    }
+
+    fn output(&self, msg: &str) {
+        match &self.output_callback {
+            Some(callback) => callback(msg),
+            None => println!("{datetime}\t{msg}", datetime = Local::now(), msg = msg),
+        }
+    }
+
+    ///Round trips closed so far with their max favorable and adverse excursions.
+    pub fn get_round_trips(&self) -> &[RoundTrip] {
+        self.excursion.round_trips()
+    }
+
+    ///One row per round trip, see excursion::round_trips_df.
+    pub fn get_round_trips_df(&self) -> DataFrame {
+        excursion::round_trips_df(self.excursion.round_trips())
+    }
+
+    ///MFE and MAE distributions of the round trips, see excursion::excursion_distribution.
+    pub fn get_excursion_distribution(&self) -> DataFrame {
+        excursion::excursion_distribution(self.excursion.round_trips())
+    }
+
+    ///Round trips and their pnl by hour of day and weekday of entry.
+    pub fn get_time_of_day_report(&self) -> TimeOfDayReport {
+        TimeOfDayReport::new(self.excursion.round_trips())
+    }
+
+    ///Return the daily result DataFrame produced by the last calculate_result call.
+    pub fn get_daily_df(&self) -> Option<DataFrame> {
+        self.daily_df.clone()
+    }
+
+    ///Record of this run for the results database, with the trades if with_trades.
+    pub fn backtest_record(
+        &self,
+        statistics: &BacktestingStatistics,
+        with_trades: bool,
+    ) -> BacktestRecord {
+        BacktestRecord {
+            id: 0,
+            created: Local::now().naive_local(),
+            strategy: self.strategy_class.filename.to_string_lossy().into_owned(),
+            strategy_hash: self.strategy_class.file_hash(),
+            setting: self.setting.clone(),
+            vt_symbol: self.vt_symbol.clone(),
+            interval: self.interval,
+            start: self.start,
+            end: self.end,
+            parameters: serde_json::json!({
+                "rate": self.rate,
+                "slippage": self.slippage,
+                "size": self.size,
+                "pricetick": self.pricetick,
+                "capital": self.capital,
+                "mode": format!("{:?}", self.mode),
+                "risk_free": self.risk_free,
+                "annual_days": self.get_annual_days(),
+                "half_life": self.half_life,
+                "run_id": self.run_id,
+            }),
+            statistics: serde_json::to_value(statistics).unwrap_or_default(),
+            trades: with_trades
+                .then(|| serde_json::to_value(self.get_all_trades()).unwrap_or_default()),
+        }
+    }
+
+    ///Save this run to the database, returns its id. Fails if the database doesn't keep
+    ///backtest runs.
+    pub fn save_backtest_record(
+        &self,
+        statistics: &BacktestingStatistics,
+        with_trades: bool,
+    ) -> VnrsResult<i64> {
+        let record = self.backtest_record(statistics, with_trades);
+        let id =
+            self.check(get_database().and_then(|database| database.save_backtest_record(record)))?;
+        self.output(&format!("回测记录已保存，编号：{}", id));
+        Ok(id)
+    }
+
+    ///Bars loaded by load_data in bar mode, in time sequence.
+    pub fn get_history_data(&self) -> &BarHistory {
+        &self.history_data
+    }
+
+    ///Record the factors the strategy emits on each bar with the forward returns over horizons
+    ///in bars, e.g. &[1, 5, 20], see the research module. Empty horizons turn it off.
+    pub fn set_factor_research(&mut self, horizons: &[usize]) {
+        self.factor_recorder = (!horizons.is_empty()).then(|| FactorRecorder::new(horizons));
+    }
+
+    ///Record the value of a factor on the current bar, ignored outside research mode.
+    pub fn emit_factor(&mut self, name: &str, value: f64) {
+        if let Some(recorder) = &mut self.factor_recorder {
+            recorder.record_factor(name, value);
+        }
+    }
+
+    ///Factors and forward returns by bar of the last run, None outside research mode.
+    pub fn factor_df(&self) -> Option<DataFrame> {
+        self.factor_recorder.as_ref().map(FactorRecorder::df)
+    }
+
+    ///Return all trade data of current backtesting result, in trade sequence.
+    pub fn get_all_trades(&self) -> Vec<TradeData> {
+        let mut trades: Vec<TradeData> = self.trades.values().cloned().collect();
+        trades.sort_by_key(|t| (t.datetime, IdGenerator::sequence(&t.tradeid)));
+        trades
+    }
+
+    ///Return all limit order data of current backtesting result, in order sequence.
+    pub fn get_all_orders(&self) -> Vec<OrderData> {
+        let mut orders: Vec<OrderData> = self.limit_orders.values().cloned().collect();
+        orders.sort_by_key(|o| (o.datetime, IdGenerator::sequence(&o.orderid)));
+        orders
+    }
+
+    ///Lifecycle of each limit order of the run: submission, acknowledgement, fills and
+    ///cancellation at the datetimes of the bars or ticks they happened on.
+    pub fn get_order_timeline(&self) -> &OrderTimeline {
+        &self.order_timeline
+    }
+
+    ///Latest status of a stop order, its vt_orderids are the orders sent once triggered.
+    pub fn get_stop_order(&self, stop_orderid: &str) -> Option<StopOrder> {
+        self.stop_orders.get(stop_orderid).cloned()
+    }
+
+    ///Latest status of a limit order, including the ones sent by triggered stop orders.
+    pub fn get_order(&self, vt_orderid: &str) -> Option<OrderData> {
+        self.limit_orders.get(vt_orderid).cloned()
+    }
+
+    ///Specification of vt_symbol: the traded contract, else the one in the contract registry,
+    ///else the built-in spec of its product. An empty vt_symbol is the traded contract.
+    pub fn get_contract(&self, vt_symbol: &str) -> Option<ContractData> {
+        if vt_symbol.is_empty() || vt_symbol == self.vt_symbol {
+            return Some(self.contract.clone());
+        }
+        if let Some(contract) = self
+            .contract_registry
+            .as_ref()
+            .and_then(|registry| registry.get_contract(vt_symbol))
+        {
+            return Some(contract.clone());
+        }
+        builtin_spec(vt_symbol).and_then(|spec| spec.to_contract().ok())
+    }
+
+    ///Trades of an order, in trade sequence.
+    pub fn get_trades_by_order(&self, vt_orderid: &str) -> Vec<TradeData> {
+        let mut trades: Vec<TradeData> = self
+            .trades
+            .values()
+            .filter(|t| t.vt_orderid() == vt_orderid)
+            .cloned()
+            .collect();
+        trades.sort_by_key(|t| (t.datetime, IdGenerator::sequence(&t.tradeid)));
+        trades
+    }
+
+    ///# Safety
+    ///vt_symbol is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_load_bar(
+        this: usize,
+        vt_symbol: *const c_char,
+        days: i64,
+        interval: IntervalKind,
+        // callback: Callable,
+        use_database: bool,
+    ) -> *mut Vec<BarData> {
+        BacktestingEngine::abi_load_bar_sized(this, vt_symbol, days, interval, 0, use_database)
+    }
+
+    ///# Safety
+    ///vt_symbol is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_load_bar_sized(
+        this: usize,
+        vt_symbol: *const c_char,
+        days: i64,
+        interval: IntervalKind,
+        size: u32,
+        use_database: bool,
+    ) -> *mut Vec<BarData> {
+        unsafe {
+            let s = from_c_str(vt_symbol);
+            Box::into_raw(Box::new(EngineContext::engine(this).load_bar_kind(
+                &s,
+                days,
+                interval,
+                size,
+                use_database,
+            )))
+        }
+    }
+
+    ///# Safety
+    ///vec was returned by abi_load_bar or abi_load_bar_sized and isn't used after.
+    pub unsafe extern "C" fn abi_drop_vec_bar_data(vec: *mut Vec<BarData>) {
+        drop(unsafe { Box::from_raw(vec) });
+    }
+
+    ///# Safety
+    ///vt_symbol is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_load_bar_callback(
+        this: usize,
+        vt_symbol: *const c_char,
+        days: i64,
+        interval: IntervalKind,
+        size: u32,
+        use_database: bool,
+    ) {
+        unsafe {
+            let s = from_c_str(vt_symbol);
+            let engine = EngineContext::engine(this);
+            let bars = engine.load_bar_kind(&s, days, interval, size, use_database);
+            engine.warmup_bars.extend(bars);
+        }
+    }
+
+    pub extern "C" fn abi_send_order(
+        this: usize,
+        strategy: *mut CtaTemplate,
+        direction: Direction,
+        offset: Offset,
+        price: f64,
+        volume: f64,
+        stop: bool,
+        lock: bool,
+        net: bool,
+    ) -> *mut Vec<String> {
+        unsafe {
+            Box::into_raw(Box::new(EngineContext::engine(this).send_order(
+                strategy, direction, offset, price, volume, stop, lock, net, "",
+            )))
+        }
+    }
+
+    ///# Safety
+    ///reference is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_send_order_with_reference(
+        this: usize,
+        strategy: *mut CtaTemplate,
+        direction: Direction,
+        offset: Offset,
+        price: f64,
+        volume: f64,
+        stop: bool,
+        lock: bool,
+        net: bool,
+        reference: *const c_char,
+    ) -> *mut Vec<String> {
+        unsafe {
+            let reference = from_c_str(reference);
+            Box::into_raw(Box::new(EngineContext::engine(this).send_order(
+                strategy, direction, offset, price, volume, stop, lock, net, &reference,
+            )))
+        }
+    }
+
+    ///# Safety
+    ///reference is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_send_bracket_order(
+        this: usize,
+        strategy: *mut CtaTemplate,
+        direction: Direction,
+        price: f64,
+        volume: f64,
+        take_profit: f64,
+        stop_loss: f64,
+        lock: bool,
+        net: bool,
+        reference: *const c_char,
+    ) -> *mut Vec<String> {
+        unsafe {
+            let reference = from_c_str(reference);
+            Box::into_raw(Box::new(EngineContext::engine(this).send_bracket_order(
+                strategy,
+                direction,
+                price,
+                volume,
+                take_profit,
+                stop_loss,
+                lock,
+                net,
+                &reference,
+            )))
+        }
+    }
+
+    ///# Safety
+    ///vec was returned by one of the abi_send_* functions and isn't used after.
+    pub unsafe extern "C" fn abi_drop_vec_string(vec: *mut Vec<String>) {
+        drop(unsafe { Box::from_raw(vec) });
+    }
+
+    ///# Safety
+    ///vt_orderid is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_cancel_order(
+        this: usize,
+        strategy: *mut CtaTemplate,
+        vt_orderid: *const c_char,
+    ) {
+        unsafe {
+            let vt_orderid = from_c_str(vt_orderid);
+            EngineContext::engine(this).cancel_order(strategy, vt_orderid);
+        }
+    }
+
+    pub extern "C" fn abi_cancel_all(this: usize, strategy: *mut CtaTemplate) {
+        unsafe {
+            EngineContext::engine(this).cancel_all(strategy);
+        }
+    }
+
+    ///# Safety
+    ///vt_orderid is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_set_expiry(
+        this: usize,
+        _strategy: *mut CtaTemplate,
+        vt_orderid: *const c_char,
+        time_in_force: TimeInForce,
+        expiry: i64,
+    ) {
+        unsafe {
+            let vt_orderid = from_c_str(vt_orderid);
+            EngineContext::engine(this).set_expiry_millis(&vt_orderid, time_in_force, expiry);
+        }
+    }
+
+    pub extern "C" fn abi_get_pos(this: usize, _strategy: *mut CtaTemplate) -> f64 {
+        unsafe { EngineContext::engine(this).get_pos() }
+    }
+
+    pub extern "C" fn abi_get_position_detail(
+        this: usize,
+        _strategy: *mut CtaTemplate,
+    ) -> PositionDetail {
+        unsafe { EngineContext::engine(this).get_position_detail() }
+    }
+
+    pub extern "C" fn abi_get_pricetick(this: usize, _strategy: *mut CtaTemplate) -> f64 {
+        unsafe { EngineContext::engine(this).pricetick }
+    }
+
+    pub extern "C" fn abi_get_size(this: usize, _strategy: *mut CtaTemplate) -> f64 {
+        unsafe { EngineContext::engine(this).size }
+    }
+
+    pub extern "C" fn abi_get_engine_type(this: usize) -> EngineType {
+        unsafe { EngineContext::engine(this).get_engine_type() }
+    }
+
+    ///Null if there's no such stop order.
+    ///
+    ///# Safety
+    ///stop_orderid is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_get_stop_order(
+        this: usize,
+        _strategy: *mut CtaTemplate,
+        stop_orderid: *const c_char,
+    ) -> *mut StopOrder {
+        unsafe {
+            let stop_orderid = from_c_str(stop_orderid);
+            match EngineContext::engine(this).get_stop_order(&stop_orderid) {
+                Some(stop_order) => Box::into_raw(Box::new(stop_order)),
+                None => std::ptr::null_mut(),
+            }
+        }
+    }
+
+    ///# Safety
+    ///stop_order is a non-null pointer returned by abi_get_stop_order and isn't used after.
+    pub unsafe extern "C" fn abi_drop_stop_order(stop_order: *mut StopOrder) {
+        drop(unsafe { Box::from_raw(stop_order) });
+    }
+
+    ///Null if there's no such order.
+    ///
+    ///# Safety
+    ///vt_orderid is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_get_order(
+        this: usize,
+        _strategy: *mut CtaTemplate,
+        vt_orderid: *const c_char,
+    ) -> *mut OrderData {
+        unsafe {
+            let vt_orderid = from_c_str(vt_orderid);
+            match EngineContext::engine(this).get_order(&vt_orderid) {
+                Some(order) => Box::into_raw(Box::new(order)),
+                None => std::ptr::null_mut(),
+            }
+        }
+    }
+
+    ///# Safety
+    ///order is a non-null pointer returned by abi_get_order and isn't used after.
+    pub unsafe extern "C" fn abi_drop_order_data(order: *mut OrderData) {
+        drop(unsafe { Box::from_raw(order) });
+    }
+
+    ///Null if the contract is unknown.
+    ///
+    ///# Safety
+    ///vt_symbol is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_get_contract(
+        this: usize,
+        vt_symbol: *const c_char,
+    ) -> *mut ContractData {
+        unsafe {
+            let vt_symbol = from_c_str(vt_symbol);
+            match EngineContext::engine(this).get_contract(&vt_symbol) {
+                Some(contract) => Box::into_raw(Box::new(contract)),
+                None => std::ptr::null_mut(),
+            }
+        }
+    }
+
+    ///# Safety
+    ///contract is a non-null pointer returned by abi_get_contract and isn't used after.
+    pub unsafe extern "C" fn abi_drop_contract_data(contract: *mut ContractData) {
+        drop(unsafe { Box::from_raw(contract) });
+    }
+
+    ///# Safety
+    ///vt_orderid is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_get_trades_by_order(
+        this: usize,
+        _strategy: *mut CtaTemplate,
+        vt_orderid: *const c_char,
+    ) -> *mut Vec<TradeData> {
+        unsafe {
+            let vt_orderid = from_c_str(vt_orderid);
+            Box::into_raw(Box::new(
+                EngineContext::engine(this).get_trades_by_order(&vt_orderid),
+            ))
+        }
+    }
+
+    ///# Safety
+    ///vec was returned by abi_get_trades_by_order and isn't used after.
+    pub unsafe extern "C" fn abi_drop_vec_trade_data(vec: *mut Vec<TradeData>) {
+        drop(unsafe { Box::from_raw(vec) });
+    }
+
+    ///# Safety
+    ///name is null or a NUL terminated string valid for the call.
+    pub unsafe extern "C" fn abi_emit_factor(
+        this: usize,
+        _strategy: *mut CtaTemplate,
+        name: *const c_char,
+        value: f64,
+    ) {
+        unsafe {
+            let name = from_c_str(name);
+            EngineContext::engine(this).emit_factor(&name, value);
+        }
+    }
+}
+
+///Orders of a TargetPosExecutor driven from the host, sent outside of any strategy.
+impl TargetPosGateway for BacktestingEngine {
+    fn pos(&self) -> f64 {
+        self.pos
+    }
+
+    fn pricetick(&self) -> f64 {
+        self.pricetick
+    }
+
+    fn send_order(
+        &mut self,
+        direction: Direction,
+        offset: Offset,
+        price: f64,
+        volume: f64,
+    ) -> Vec<String> {
+        let strategy = std::ptr::null_mut();
+        let price = round_to(price, self.pricetick);
+        BacktestingEngine::send_order(
+            self, strategy, direction, offset, price, volume, false, false, false, "",
+        )
+    }
+
+    fn cancel_order(&mut self, vt_orderid: &str) {
+        BacktestingEngine::cancel_order(self, std::ptr::null_mut(), vt_orderid.to_string());
+    }
+
+    fn is_active(&self, vt_orderid: &str) -> bool {
+        self.active_limit_orders.contains(vt_orderid)
+    }
+}
+
+///Boundary between trading days, e.g. for 24/7 crypto data or futures night sessions.
+///
+///The trading date of a datetime is the date of `datetime + utc_offset - day_start`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradingDayBoundary {
+    ///Hours added to data time to get the local time days are counted in, e.g. 8 to count
+    ///UTC data in Beijing days.
+    pub utc_offset: i32,
+    ///Local hour a trading day starts at. 8 counts 08:00 to 08:00 as the day it starts on,
+    ///-3 counts a session from 21:00 as the next day, like futures night sessions.
+    pub day_start: i32,
+}
+
+impl TradingDayBoundary {
+    pub fn new(utc_offset: i32, day_start: i32) -> Self {
+        TradingDayBoundary {
+            utc_offset,
+            day_start,
+        }
+    }
+
+    pub fn trading_date(&self, datetime: NaiveDateTime) -> NaiveDate {
+        (datetime + TimeDelta::hours((self.utc_offset - self.day_start) as i64)).date()
+    }
+
+    ///Data time the trading day after the one of datetime starts at.
+    pub fn next_day_start(&self, datetime: NaiveDateTime) -> NaiveDateTime {
+        let next_date = self.trading_date(datetime) + Days::new(1);
+        next_date.and_time(NaiveTime::MIN)
+            - TimeDelta::hours((self.utc_offset - self.day_start) as i64)
+    }
+}
+
+///Circuit breakers of a run. Once one is breached the engine cancels all orders, closes
+///the position at the next bar and rejects any further order.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RiskLimits {
+    ///Drawdown of the balance from its peak, in percent.
+    pub max_ddpercent: Option<f64>,
+    ///Loss of the current trading day, marked to the latest close.
+    pub max_daily_loss: Option<f64>,
+}
+
+///Balance of a bar marked to its close, with the margin of the position held.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EquitySample {
+    pub datetime: NaiveDateTime,
+    pub balance: f64,
+    pub margin: f64,
+}
+
+impl EquitySample {
+    pub fn is_margin_call(&self) -> bool {
+        self.balance < self.margin
+    }
+}
+
+///Bars replayed, those closing with a position and the holdings they make up.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct ExposureCounters {
+    bars: i64,
+    position_bars: i64,
+    holdings: i64,
+    last_pos: f64,
+}
+
+impl ExposureCounters {
+    fn update(&mut self, pos: f64) {
+        self.bars += 1;
+        if pos != 0.0 {
+            self.position_bars += 1;
+            // A holding starts from flat or when the position reverses
+            if self.last_pos == 0.0 || self.last_pos.signum() != pos.signum() {
+                self.holdings += 1;
+            }
+        }
+        self.last_pos = pos;
+    }
+
+    fn position_bars_ratio(&self) -> f64 {
+        if self.bars > 0 {
+            self.position_bars as f64 / self.bars as f64 * 100.0
+        } else {
+            0.0
+        }
+    }
+
+    fn average_holding_bars(&self) -> f64 {
+        if self.holdings > 0 {
+            self.position_bars as f64 / self.holdings as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct IntradayStatistics {
+    max_drawdown: f64,
+    max_ddpercent: f64,
+    margin_call_count: i64,
+    sharpe_ratio: f64,
+}
+
+impl IntradayStatistics {
+    fn from_samples(samples: &[EquitySample], capital: f64, analyzer: &ReturnsAnalyzer) -> Self {
+        let mut statistics = IntradayStatistics::default();
+        let mut high_balance = capital;
+        let mut in_margin_call = false;
+        for sample in samples {
+            high_balance = high_balance.max(sample.balance);
+            let drawdown = sample.balance - high_balance;
+            statistics.max_drawdown = statistics.max_drawdown.min(drawdown);
+            statistics.max_ddpercent = statistics
+                .max_ddpercent
+                .min(drawdown / high_balance * 100.0);
+
+            if sample.is_margin_call() && !in_margin_call {
+                statistics.margin_call_count += 1;
+            }
+            in_margin_call = sample.is_margin_call();
+        }
+
+        // Annualized by the samples per day, not by trading days
+        let balances: Vec<(NaiveDateTime, f64)> = samples
+            .iter()
+            .map(|sample| (sample.datetime, sample.balance))
+            .collect();
+        statistics.sharpe_ratio = analyzer.analyze(&balances).sharpe_ratio;
+        statistics
+    }
+}
+
+///Risk limit breach recorded in the logs and statistics of a run.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RiskHalt {
+    pub datetime: NaiveDateTime,
+    pub reason: String,
+}
+
+///How often the strategy's on_timer is called during a backtest, values below 1 count as 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimerInterval {
+    ///Every n bars replayed.
+    Bars(usize),
+    ///On the first bar at least n simulated seconds after the last call, at most once a bar.
+    Seconds(i64),
+}
+
+#[derive(Debug, Default, Clone)]
+struct Timer {
+    interval: Option<TimerInterval>,
+    bar_count: usize,
+    next_datetime: Option<NaiveDateTime>,
+}
+
+impl Timer {
+    fn new(interval: TimerInterval) -> Self {
+        Timer {
+            interval: Some(interval),
+            ..Default::default()
+        }
+    }
+
+    ///Advance the timer to a bar at datetime, true if on_timer is due.
+    fn update(&mut self, datetime: NaiveDateTime) -> bool {
+        match self.interval {
+            None => false,
+            Some(TimerInterval::Bars(bars)) => {
+                self.bar_count += 1;
+                if self.bar_count < bars {
+                    return false;
+                }
+                self.bar_count = 0;
+                true
+            }
+            Some(TimerInterval::Seconds(seconds)) => {
+                let seconds = TimeDelta::seconds(seconds.max(1));
+                let next_datetime = *self.next_datetime.get_or_insert(datetime + seconds);
+                if datetime < next_datetime {
+                    return false;
+                }
+                self.next_datetime = Some(datetime + seconds);
+                true
+            }
+        }
+    }
+}
+
+///Replay progress reported to the progress callback during run_backtesting, at every
+///hundredth of the history data.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct BacktestingProgress {
+    pub progress: f64, // replayed fraction of history data, 0.0 to 1.0
+    pub datetime: NaiveDateTime,
+    pub pos: f64,
+    pub balance: f64,
+    pub drawdown: f64,
+    pub trade_count: i64,
+    pub bars: usize, // bars of history data replayed
+    pub total_bars: usize,
+    pub bars_per_sec: f64, // moving average of the replay throughput
+    pub eta: f64,          // seconds left at bars_per_sec, 0.0 until it's known
+}
+
+impl fmt::Display for BacktestingProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.0}%，{}/{} 根K线，{:.0} 根/秒，剩余 {:.0} 秒",
+            self.progress * 100.0,
+            self.bars,
+            self.total_bars,
+            self.bars_per_sec,
+            self.eta.ceil()
+        )
+    }
+}
+
+///Checkpoint of a running backtest, see BacktestingEngine::serialize_state.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EngineState {
+    ///Empty in checkpoints from before runs had ids.
+    #[serde(default)]
+    pub run_id: String,
+    pub datetime: NaiveDateTime,
+    pub replay_count: usize, // bars of history data replayed
+    pub pos: f64,
+    pub high_balance: f64,
+    pub limit_order_count: i64,
+    pub stop_order_count: i64,
+    pub trade_count: i64,
+    pub active_limit_orders: Vec<OrderData>,
+    pub active_stop_orders: Vec<StopOrder>,
+    #[serde(default)]
+    pub bracket_entries: HashMap<String, BracketOrder>,
+    #[serde(default)]
+    pub oco_orders: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub order_expiries: HashMap<String, NaiveDateTime>,
+    #[serde(default)]
+    pub risk_halt: Option<RiskHalt>,
+    #[serde(default)]
+    pub initial_price: f64,
+    #[serde(default)]
+    pub exposure: ExposureCounters,
+    #[serde(default)]
+    pub excursion: ExcursionTracker,
+    pub variables: Vec<(String, String)>,
+    pub daily_results: Vec<DailyResult>,
+    #[serde(default)]
+    pub settlements: Vec<Settlement>,
+}
+
+///Statistics of a finished backtest, as returned by calculate_statistics.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BacktestingStatistics {
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub total_days: i64,
+    pub profit_days: i64,
+    pub loss_days: i64,
+    pub capital: f64,
+    pub end_balance: f64,
+    pub max_drawdown: f64,
+    pub max_ddpercent: f64,
+    pub max_drawdown_duration: i64,
+    pub total_net_pnl: f64,
+    pub daily_net_pnl: f64,
+    pub total_commission: f64,
+    pub daily_commission: f64,
+    pub total_slippage: f64,
+    pub daily_slippage: f64,
+    ///Financing cost of positions carried overnight, see set_financing.
+    #[serde(default)]
+    pub total_financing: f64,
+    pub total_turnover: f64,
+    pub daily_turnover: f64,
+    pub total_trade_count: i64,
+    pub daily_trade_count: f64,
+    pub total_return: f64,
+    pub annual_return: f64,
+    pub daily_return: f64,
+    pub return_std: f64,
+    pub sharpe_ratio: f64,
+    pub ewm_sharpe: f64,
+    pub return_drawdown_ratio: f64,
+    ///Percent of days holding a position at some time of the day.
+    #[serde(default)]
+    pub position_days_ratio: f64,
+    ///Percent of bars closing with a position.
+    #[serde(default)]
+    pub position_bars_ratio: f64,
+    ///Bars a position is held on average, from open or reversal to flat or reversal.
+    #[serde(default)]
+    pub average_holding_bars: f64,
+    ///Turnover a year over the average balance.
+    #[serde(default)]
+    pub annual_turnover: f64,
+    ///Net pnl of long and short positions, closing trades matched first in first out, open
+    ///positions marked to the last close.
+    #[serde(default)]
+    pub long_net_pnl: f64,
+    #[serde(default)]
+    pub short_net_pnl: f64,
+    ///Drawdown of the equity sampled on every bar, 0 unless equity sampling is on.
+    #[serde(default)]
+    pub intraday_max_drawdown: f64,
+    #[serde(default)]
+    pub intraday_max_ddpercent: f64,
+    ///Times the sampled equity fell below the margin of the position.
+    #[serde(default)]
+    pub margin_call_count: i64,
+    ///Sharpe ratio of the sampled equity, annualized by the samples of a day.
+    #[serde(default)]
+    pub intraday_sharpe_ratio: f64,
+    ///Risk limit breached during the run, if any.
+    #[serde(default)]
+    pub risk_halt: Option<RiskHalt>,
+}
+
+///Mark-to-market result of one trading day.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DailyResult {
+    pub date: NaiveDate,
+    pub close_price: f64,
+    pub pre_close: f64,
+
+    pub trades: Vec<TradeData>,
+    pub trade_count: i64,
+
+    pub start_pos: f64,
+    pub end_pos: f64,
+
+    pub turnover: f64,
+    pub commission: f64,
+    pub slippage: f64,
+    ///Financing cost of the position carried into the day, see set_financing.
+    #[serde(default)]
+    pub financing: f64,
+
+    pub trading_pnl: f64,
+    pub holding_pnl: f64,
+    pub total_pnl: f64,
+    pub net_pnl: f64,
+    ///Rate the amounts above were converted into the base currency at, 1 without one.
+    #[serde(default = "no_conversion")]
+    pub fx_rate: f64,
+}
+
+fn no_conversion() -> f64 {
+    1.0
+}
+
+impl DailyResult {
+    pub fn new(date: NaiveDate, close_price: f64) -> Self {
+        DailyResult {
+            date,
+            close_price,
+            fx_rate: 1.0,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_trade(&mut self, trade: TradeData) {
+        self.trades.push(trade)
+    }
+
+    fn calculate_pnl(
+        &mut self,
+        pre_close: f64,
+        start_pos: f64,
+        size: f64,
+        rate: f64,
+        slippage: f64,
+        fx_rate: f64,
+    ) {
+        // If no pre_close provided on the first day,
+        // use value 1 to avoid zero division error
+        if pre_close != 0.0 {
+            self.pre_close = pre_close;
+        } else {
+            self.pre_close = 1.0;
+        }
+
+        // Holding pnl is the pnl from holding position at day start
+        self.start_pos = start_pos;
+        self.end_pos = start_pos;
+
+        self.holding_pnl = self.start_pos * (self.close_price - self.pre_close) * size;
+
+        // Trading pnl is the pnl from new trade during the day, recalculated from scratch
+        // so the day can be updated as trades come in
+        self.trade_count = self.trades.len() as i64;
+        self.trading_pnl = 0.0;
+        self.slippage = 0.0;
+        self.turnover = 0.0;
+        self.commission = 0.0;
+
+        for trade in &self.trades {
+            let pos_change;
+            if trade.direction == Direction::LONG {
+                pos_change = trade.volume;
+            } else {
+                pos_change = -trade.volume;
+            }
+
+            self.end_pos += pos_change;
+
+            let turnover = trade.volume * size * trade.price;
+            self.trading_pnl += pos_change * (self.close_price - trade.price) * size;
+            self.slippage += trade.volume * size * slippage;
+
+            self.turnover += turnover;
+            self.commission += turnover * rate;
+        }
+
+        // Amounts in the base currency, prices and positions stay as they are
+        self.fx_rate = fx_rate;
+        self.holding_pnl *= fx_rate;
+        self.trading_pnl *= fx_rate;
+        self.slippage *= fx_rate;
+        self.turnover *= fx_rate;
+        self.commission *= fx_rate;
+
+        // Net pnl takes account of commission and slippage cost
+        self.total_pnl = self.trading_pnl + self.holding_pnl;
+        self.net_pnl = self.total_pnl - self.commission - self.slippage;
+        self.financing = 0.0;
+    }
+
+    ///Debit the financing of the start position over days at the annual (long, short) rates,
+    ///a long position only borrowing what exceeds balance. After calculate_pnl.
+    fn charge_financing(
+        &mut self,
+        size: f64,
+        (long_rate, short_rate): (f64, f64),
+        balance: f64,
+        days: i64,
+    ) {
+        let notional = self.start_pos * self.pre_close * size * self.fx_rate;
+        let borrowed = if notional > 0.0 {
+            (notional - balance.max(0.0)).max(0.0) * long_rate
+        } else {
+            -notional * short_rate
+        };
+        self.financing = borrowed * days as f64 / 365.0;
+        self.net_pnl -= self.financing;
+    }
+}
+
+///Daily columns statistics are computed from, net_pnl by day and totals of the others.
+struct DailySeries {
+    dates: Vec<NaiveDate>,
+    net_pnl: Vec<f64>,
+    commission: f64,
+    slippage: f64,
+    financing: f64,
+    turnover: f64,
+    trade_count: i64,
+    ///Days starting, ending or trading with a position.
+    position_days: i64,
+}
+
+impl DailySeries {
+    fn from_df(df: &DataFrame) -> VnrsResult<Self> {
+        let column = |name: &str| -> VnrsResult<Vec<f64>> {
+            Ok(df
+                .column(name)?
+                .cast(&DataType::Float64)?
+                .f64()?
+                .into_no_null_iter()
+                .collect())
+        };
+        Ok(DailySeries {
+            dates: df
+                .column("date")?
+                .date()?
+                .as_date_iter()
+                .map(|date| date.unwrap_or_default())
+                .collect(),
+            net_pnl: column("net_pnl")?,
+            commission: column("commission")?.iter().sum(),
+            slippage: column("slippage")?.iter().sum(),
+            // Missing in daily DataFrames from before financing
+            financing: column("financing").map_or(0.0, |financing| financing.iter().sum()),
+            turnover: column("turnover")?.iter().sum(),
+            trade_count: df.column("trade_count")?.sum().unwrap_or_default(),
+            position_days: column("start_pos")?
+                .iter()
+                .zip(column("end_pos")?)
+                .zip(column("trade_count")?)
+                .filter(|((start_pos, end_pos), trade_count)| {
+                    **start_pos != 0.0 || *end_pos != 0.0 || *trade_count > 0.0
+                })
+                .count() as i64,
+        })
+    }
+
+    fn from_results<'a>(results: impl Iterator<Item = &'a DailyResult>) -> Self {
+        let mut daily = DailySeries {
+            dates: Vec::new(),
+            net_pnl: Vec::new(),
+            commission: 0.0,
+            slippage: 0.0,
+            financing: 0.0,
+            turnover: 0.0,
+            trade_count: 0,
+            position_days: 0,
+        };
+        for result in results {
+            daily.dates.push(result.date);
+            daily.net_pnl.push(result.net_pnl);
+            daily.commission += result.commission;
+            daily.slippage += result.slippage;
+            daily.financing += result.financing;
+            daily.turnover += result.turnover;
+            daily.trade_count += result.trade_count;
+            if result.start_pos != 0.0 || result.end_pos != 0.0 || result.trade_count > 0 {
+                daily.position_days += 1;
+            }
+        }
+        daily
+    }
+}
+
+///Rate from the currency of contract into the base currency on date, 1 without a base
+///currency or a rate for the day.
+fn fx_rate(
+    base_currency: &Option<(Currency, FxRates)>,
+    contract: &ContractData,
+    date: NaiveDate,
+) -> f64 {
+    base_currency
+        .as_ref()
+        .and_then(|(base, rates)| rates.rate(contract.currency, *base, date))
+        .unwrap_or(1.0)
+}
+
+///Net pnl of long and short positions of trades in trade sequence, starting from the
+///(price, pos) lot carried in. Closing volume is matched with the earliest open lots, costs go
+///to the side of the lot opened or closed.
+fn long_short_pnl(
+    (initial_price, initial_pos): (f64, f64),
+    trades: &[TradeData],
+    close_price: f64,
+    size: f64,
+    rate: f64,
+    slippage: f64,
+) -> (f64, f64) {
+    // Open lots as (signed volume, price)
+    let mut lots: VecDeque<(f64, f64)> = VecDeque::new();
+    if initial_pos != 0.0 {
+        lots.push_back((initial_pos, initial_price));
+    }
+    let mut pnl = [0.0, 0.0];
+    let side = |volume: f64| usize::from(volume < 0.0);
+
+    for trade in trades {
+        let cost = trade.price * size * rate + size * slippage;
+        let mut volume = match trade.direction {
+            Direction::LONG => trade.volume,
+            _ => -trade.volume,
+        };
+        while volume.abs() > POS_TOLERANCE {
+            match lots.front_mut() {
+                Some(lot) if lot.0.signum() != volume.signum() => {
+                    let matched = lot.0.abs().min(volume.abs());
+                    let sign = lot.0.signum();
+                    pnl[side(lot.0)] +=
+                        matched * sign * (trade.price - lot.1) * size - matched * cost;
+                    lot.0 -= matched * sign;
+                    volume += matched * sign;
+                    if lot.0.abs() <= POS_TOLERANCE {
+                        lots.pop_front();
+                    }
+                }
+                _ => {
+                    pnl[side(volume)] -= volume.abs() * cost;
+                    lots.push_back((volume, trade.price));
+                    volume = 0.0;
+                }
+            }
+        }
+    }
+
+    for (volume, price) in lots {
+        pnl[side(volume)] += volume * (close_price - price) * size;
+    }
+    (pnl[0], pnl[1])
+}
+
+///(volume filled, volume weighted price) of filling volume from levels, best first, taking
+///only levels whose price is within. Volume beyond the depth of those levels is left unfilled,
+///None if no level is within.
+fn depth_fill(
+    levels: &[(f64, f64)],
+    volume: f64,
+    within: impl Fn(f64) -> bool,
+) -> Option<(f64, f64)> {
+    let mut remaining = volume;
+    let mut value = 0.0;
+    for &(price, level_volume) in levels {
+        if price <= 0.0 || level_volume <= 0.0 || !within(price) || remaining <= 0.0 {
+            break;
+        }
+        let filled = remaining.min(level_volume);
+        value += filled * price;
+        remaining -= filled;
+    }
+    let filled = volume - remaining;
+    (filled > 0.0).then(|| (filled, value / filled))
+}
+
+///Index of the first value that no later value beats, e.g. the first minimum.
+fn first_position(values: &[f64], beats: fn(f64, f64) -> bool) -> usize {
+    let mut best = 0;
+    for (i, value) in values.iter().enumerate() {
+        if beats(*value, values[best]) {
+            best = i;
+        }
+    }
+    best
+}
+
+fn load_tick_data(
+    symbol: &str,
+    exchange: Exchange,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+) -> VnrsResult<Vec<TickData>> {
+    get_database()?.load_tick_data(symbol, exchange, start, end)
+}
+
+fn load_bar_data(
+    symbol: &str,
+    exchange: Exchange,
+    interval: Interval,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    adjust: AdjustMode,
+) -> VnrsResult<Vec<BarData>> {
+    let db = get_database()?;
+    db.load_adjusted_bar_data(symbol, exchange, interval, start, end, adjust)
+}
+
+fn interval_delta(interval: Interval) -> VnrsResult<TimeDelta> {
+    get_interval_delta(interval)
+        .ok_or_else(|| VnrsError::Setting(format!("unsupported interval {}", interval)))
+}
+
+///Bytes in MB for the output.
+fn format_bytes(bytes: usize) -> String {
+    format!("{:.1}MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+///Bars from the datafeed unless use_database, falling back to the database when there is no
+///datafeed or it has no data. Fetched bars are saved to the database if datafeed.save is True,
+///as fetched before they are adjusted.
+fn load_history_data(
+    symbol: &str,
+    exchange: Exchange,
+    interval: Interval,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    use_database: bool,
+    adjust: AdjustMode,
+) -> VnrsResult<Vec<BarData>> {
+    if let Some(datafeed) = get_datafeed().filter(|_| !use_database) {
+        // The datafeed is queried in its own symbol convention
+        let datafeed_symbol =
+            get_symbol_mapper().to_gateway(&get_settings()["datafeed.name"], symbol);
+        let mut bars =
+            datafeed.query_bar_history(&datafeed_symbol, exchange, interval, start, end)?;
+        let canonical = Symbol::new(symbol);
+        for bar in bars.iter_mut() {
+            bar.symbol = canonical;
+        }
+        if !bars.is_empty() {
+            if get_settings()["datafeed.save"] == "True" {
+                get_database()?.save_bar_data(bars.clone())?;
+            }
+            if adjust.applies_to(exchange) {
+                let factors = get_database()?.load_adjust_factors(symbol, exchange)?;
+                adjust_bars(&mut bars, &factors, adjust);
+            }
+            return Ok(bars);
+        }
+    }
+
+    load_bar_data(symbol, exchange, interval, start, end, adjust)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::database::{
+        set_test_database, AdjustFactors, BaseDatabase, MemoryDatabase,
+    };
+    use crate::vnrs::trader::datafeed::{set_datafeed, BaseDatafeed};
+    use crate::vnrs::trader::synthetic::SyntheticBars;
+    use crate::vnrs_ctastrategy::fill::adverse_ticks_in_fast_market;
+    use crate::vnrs_ctastrategy::target::TargetPosExecutor;
+    use proptest::prelude::*;
+
+    struct SyntheticDatafeed(Vec<BarData>);
+
+    impl BaseDatafeed for SyntheticDatafeed {
+        fn query_bar_history(
+            &self,
+            _symbol: &str,
+            _exchange: Exchange,
+            _interval: Interval,
+            start: NaiveDateTime,
+            end: NaiveDateTime,
+        ) -> VnrsResult<Vec<BarData>> {
+            let bars: Vec<BarData> = self
+                .0
+                .iter()
+                .filter(|bar| bar.datetime >= start && bar.datetime <= end)
+                .cloned()
+                .collect();
+            Ok(bars)
+        }
+    }
+
+    #[test]
+    fn load_history_from_datafeed() {
+        let bars = SyntheticBars::default().generate();
+        // The database is missing the first half of the history
+        let database = MemoryDatabase::new();
+        database.save_bar_data(bars[500..].to_vec());
+        let _database = set_test_database(Arc::new(database));
+        set_datafeed(Arc::new(SyntheticDatafeed(bars.clone())));
+
+        let (start, end) = (bars[0].datetime, bars[999].datetime);
+        let load = |use_database: bool| {
+            load_history_data(
+                "TEST",
+                Exchange::LOCAL,
+                Interval::MINUTE,
+                start,
+                end,
+                use_database,
+                AdjustMode::None,
+            )
+            .unwrap()
+        };
+        let from_database = load(true);
+        assert_eq!(from_database.len(), 500);
+
+        let from_datafeed = load(false);
+        assert_eq!(from_datafeed.len(), 1000);
+        assert_eq!(from_datafeed[0].datetime, start);
+    }
+
+    #[test]
+    fn cached_bars_keep_their_adjustment() {
+        let bars = SyntheticBars {
+            symbol: "600000".to_string(),
+            exchange: Exchange::SSE,
+            interval: Interval::DAILY,
+            count: 10,
+            ..Default::default()
+        }
+        .generate();
+        let database = MemoryDatabase::new();
+        database.save_bar_data(bars.clone());
+        // A 2 for 1 split halfway through
+        let factors: AdjustFactors = [
+            (bars[0].datetime.date(), 1.0),
+            (bars[5].datetime.date(), 2.0),
+        ]
+        .into_iter()
+        .collect();
+        database
+            .save_adjust_factors("600000", Exchange::SSE, &factors)
+            .unwrap();
+        let _database = set_test_database(Arc::new(database));
+        let dir = std::env::temp_dir().join(format!("vnrs_adjust_cache_{}", std::process::id()));
+        let cache = Arc::new(BarCache::new(&dir));
+
+        // (close prices, whether they came from the cache)
+        let load = |adjust: AdjustMode| {
+            let logs = Arc::new(Mutex::new(Vec::new()));
+            let output = logs.clone();
+            let mut engine = BacktestingEngine::new();
+            engine.set_output_callback(move |msg| output.lock().unwrap().push(msg.to_string()));
+            engine
+                .set_parameters(
+                    "600000.SSE",
+                    Interval::DAILY,
+                    bars[0].datetime,
+                    bars[9].datetime,
+                    0.0,
+                    0.0,
+                    1.0,
+                    0.01,
+                    1_000_000.0,
+                    BacktestingMode::BAR,
+                    0.0,
+                    240,
+                    120,
+                )
+                .unwrap();
+            engine.set_adjust_mode(adjust);
+            engine.set_bar_cache(cache.clone());
+            engine.load_data().unwrap();
+            let closes: Vec<f64> = engine
+                .get_history_data()
+                .iter()
+                .map(|bar| bar.close_price)
+                .collect();
+            let cached = logs
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|msg| msg == "从缓存加载历史数据");
+            (closes, cached)
+        };
+
+        let stored: Vec<f64> = bars.iter().map(|bar| bar.close_price).collect();
+        let (forward, cached) = load(AdjustMode::Forward);
+        assert!(!cached);
+        assert!((forward[0] - stored[0] / 2.0).abs() < 1e-9);
+        assert_eq!(load(AdjustMode::Forward), (forward, true));
+
+        // The forward adjusted bars in the cache aren't taken for the unadjusted ones
+        let (raw, cached) = load(AdjustMode::None);
+        assert!(!cached);
+        assert_eq!(raw, stored[..raw.len()]);
+        assert_eq!(load(AdjustMode::None), (raw, true));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn errors_reach_the_output() {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = BacktestingEngine::new();
+        let output = logs.clone();
+        engine.set_output_callback(move |msg| output.lock().unwrap().push(msg.to_string()));
+
+        let start = NaiveDate::from_ymd_opt(2024, 1, 22).unwrap().into();
+        let result = engine.set_parameters(
+            "IF888",
+            Interval::MINUTE,
+            start,
+            start,
+            0.0,
+            0.0,
+            300.0,
+            0.2,
+            1_000_000.0,
+            BacktestingMode::BAR,
+            0.0,
+            240,
+            120,
+        );
+        assert!(matches!(result, Err(VnrsError::Setting(_))));
+
+        // Without a strategy the run stops instead of panicking
+        assert!(engine.run_backtesting().is_err());
+        let logs = logs.lock().unwrap();
+        assert_eq!(logs[0], "setting error: invalid vt_symbol IF888");
+        assert!(logs.iter().any(|msg| msg == "策略未添加，无法开始回测"));
+    }
+
+    fn cross_bar(engine: &mut BacktestingEngine, minute: i64, high: f64, low: f64) {
+        let datetime = NaiveDate::from_ymd_opt(2024, 1, 22)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap()
+            + TimeDelta::minutes(minute);
+        engine.bar = BarData {
+            datetime,
+            open_price: (high + low) / 2.0,
+            high_price: high,
+            low_price: low,
+            close_price: (high + low) / 2.0,
+            ..Default::default()
+        };
+        engine.datetime = datetime;
+        engine.excursion.update_bar(high, low);
+        engine.expire_orders();
+        engine.cross_limit_order();
+        engine.cross_stop_order();
+        engine.update_daily_close(engine.bar.close_price);
+        engine.exposure.update(engine.pos);
+        engine.check_risk_limits();
+    }
+
+    fn test_engine() -> BacktestingEngine {
+        let mut engine = BacktestingEngine::new();
+        let start = NaiveDate::from_ymd_opt(2024, 1, 22).unwrap().into();
+        engine
+            .set_parameters(
+                "IF888.CFFEX",
+                Interval::MINUTE,
+                start,
+                start,
+                0.0,
+                0.0,
+                300.0,
+                0.2,
+                1_000_000.0,
+                BacktestingMode::BAR,
+                0.0,
+                240,
+                120,
+            )
+            .unwrap();
+        // Scoped by add_strategy otherwise, cancel_order tells stop orders by their prefix
+        engine.stop_order_ids = IdGenerator::new(STOPORDER_PREFIX);
+        engine
     }
 
-    fn output(&self, msg: &str) {
-        println!("{datetime}\t{msg}", datetime = Local::now(), msg = msg);
+    #[test]
+    fn bracket_order_cancels_sibling_leg() {
+        let mut engine = test_engine();
+
+        let null = std::ptr::null_mut();
+        let rejected = engine.send_bracket_order(
+            null,
+            Direction::LONG,
+            100.0,
+            1.0,
+            95.0,
+            110.0,
+            false,
+            false,
+            "",
+        );
+        assert_eq!(
+            engine.get_order(&rejected[0]).unwrap().status,
+            Status::REJECTED
+        );
+
+        let entry = engine.send_bracket_order(
+            null,
+            Direction::LONG,
+            100.0,
+            1.0,
+            110.0,
+            95.0,
+            false,
+            false,
+            "bracket",
+        );
+        // Entry is traded, exit legs are sent
+        cross_bar(&mut engine, 30, 101.0, 99.0);
+        assert_eq!(engine.get_trades_by_order(&entry[0]).len(), 1);
+        assert_eq!(engine.active_limit_orders.len(), 1);
+        assert_eq!(engine.active_stop_orders.len(), 1);
+
+        // Take profit is filled, stop loss is cancelled
+        cross_bar(&mut engine, 31, 111.0, 105.0);
+        let trades = engine.get_all_trades();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[1].price, 110.0);
+        assert_eq!(trades[1].reference, "bracket");
+        assert_eq!(engine.get_pos(), 0.0);
+        let stop_order = engine.stop_orders.values().next().unwrap();
+        assert_eq!(stop_order.status, StopOrderStatus::CANCELLED);
+        assert!(engine.oco_orders.is_empty());
+
+        // Nothing left to trade
+        cross_bar(&mut engine, 32, 120.0, 80.0);
+        assert_eq!(engine.get_all_trades().len(), 2);
     }
 
-    pub extern "C" fn abi_load_bar(
-        this: usize,
-        vt_symbol: *const c_char,
-        days: i64,
-        interval: Interval,
-        // callback: Callable,
-        use_database: bool,
-    ) -> *mut Vec<BarData> {
-        unsafe {
-            let s = CStr::from_ptr(vt_symbol).to_owned().into_string().unwrap();
-            Box::into_raw(Box::new(
-                std::mem::transmute::<usize, &mut BacktestingEngine>(this).load_bar(
-                    &s,
-                    days,
-                    interval,
-                    use_database,
-                ),
-            ))
+    #[test]
+    fn bracket_legs_follow_partial_fills() {
+        let mut engine = test_engine();
+        engine.mode = BacktestingMode::TICK;
+        engine.set_depth_fill(true);
+        let cross_tick = |engine: &mut BacktestingEngine, second: i64, bid: (f64, f64)| {
+            engine.tick = TickData {
+                datetime: NaiveDate::from_ymd_opt(2024, 1, 22)
+                    .unwrap()
+                    .and_hms_opt(9, 0, 0)
+                    .unwrap()
+                    + TimeDelta::seconds(second),
+                last_price: bid.0,
+                bid_price_1: bid.0,
+                bid_volume_1: bid.1,
+                ask_price_1: bid.0 + 0.2,
+                ask_volume_1: 2.0,
+                ..Default::default()
+            };
+            engine.datetime = engine.tick.datetime;
+            engine.cross_limit_order();
+            engine.cross_stop_order();
+            engine.update_daily_close(engine.tick.last_price);
+        };
+
+        let null = std::ptr::null_mut();
+        let entry = engine.send_bracket_order(
+            null,
+            Direction::LONG,
+            100.0,
+            4.0,
+            110.0,
+            95.0,
+            false,
+            false,
+            "bracket",
+        );
+        // 2 of 4 are listed, the rest of the entry is cancelled
+        cross_tick(&mut engine, 0, (99.8, 10.0));
+        assert_eq!(engine.get_pos(), 2.0);
+        engine.cancel_order(null, entry[0].clone());
+        assert!(engine.bracket_entries.is_empty());
+        let take_profit = engine
+            .active_limit_orders
+            .iter()
+            .next()
+            .unwrap()
+            .to_string();
+        let stop_loss = engine.active_stop_orders.iter().next().unwrap().to_string();
+        assert_eq!(engine.limit_orders[&take_profit].volume, 2.0);
+        assert_eq!(engine.stop_orders[&stop_loss].volume, 2.0);
+
+        // Take profit fills 1, the stop loss is left with the other 1
+        cross_tick(&mut engine, 1, (110.0, 1.0));
+        assert_eq!(engine.get_pos(), 1.0);
+        assert_eq!(engine.stop_orders[&stop_loss].volume, 1.0);
+
+        // Stop loss closes the rest without going short, the take profit has nothing left
+        cross_tick(&mut engine, 2, (94.0, 10.0));
+        assert_eq!(engine.get_pos(), 0.0);
+        assert_eq!(engine.limit_orders[&take_profit].status, Status::CANCELLED);
+        assert!(engine.oco_orders.is_empty());
+        assert!(engine.active_limit_orders.is_empty());
+    }
+
+    #[test]
+    fn volumes_rounded_to_lots() {
+        let mut engine = test_engine();
+        cross_bar(&mut engine, 0, 101.0, 99.0);
+        let null = std::ptr::null_mut();
+        let buy = |engine: &mut BacktestingEngine, volume: f64| {
+            let vt_orderids = engine.send_order(
+                null,
+                Direction::LONG,
+                Offset::OPEN,
+                100.0,
+                volume,
+                false,
+                false,
+                false,
+                "",
+            );
+            engine.get_order(&vt_orderids[0]).unwrap()
+        };
+
+        engine.set_lot_rounding(0.001, LotRounding::Down);
+        assert_eq!(buy(&mut engine, 0.0125).volume, 0.012);
+        buy(&mut engine, 0.1);
+        buy(&mut engine, 0.2);
+        cross_bar(&mut engine, 1, 101.0, 99.0);
+        // On the lot grid, not 0.312000...01
+        assert_eq!(engine.get_pos(), 0.312);
+
+        engine.set_lot_rounding(0.001, LotRounding::Reject);
+        assert_eq!(buy(&mut engine, 0.0125).status, Status::REJECTED);
+        assert_eq!(buy(&mut engine, 0.013).status, Status::SUBMITTING);
+
+        // Futures trade whole contracts, the min_volume of the contract without a lot size
+        engine.contract.min_volume = 1.0;
+        engine.set_lot_rounding(0.0, LotRounding::Nearest);
+        assert_eq!(buy(&mut engine, 1.5).volume, 2.0);
+        engine.set_lot_rounding(0.0, LotRounding::Down);
+        assert_eq!(buy(&mut engine, 0.5).status, Status::REJECTED);
+        assert_eq!(buy(&mut engine, 2.7).volume, 2.0);
+    }
+
+    #[test]
+    fn target_position_is_worked() {
+        let mut engine = test_engine();
+        cross_bar(&mut engine, 0, 101.0, 99.0);
+        let mut executor = TargetPosExecutor::new(1.0);
+        executor.set_target(2.0);
+        executor.on_bar(&mut engine, 100.0);
+        let first = executor.active_orderids()[0].clone();
+        assert_eq!(engine.get_order(&first).unwrap().price, 100.2);
+
+        // Not filled, cancelled and sent again at the new close
+        cross_bar(&mut engine, 1, 102.0, 101.0);
+        executor.on_bar(&mut engine, 101.4);
+        assert_eq!(engine.get_order(&first).unwrap().status, Status::CANCELLED);
+        let second = executor.active_orderids()[0].clone();
+        assert_eq!(engine.get_order(&second).unwrap().price, 101.6);
+        cross_bar(&mut engine, 2, 102.0, 101.0);
+        assert_eq!(engine.get_pos(), 2.0);
+        executor.on_bar(&mut engine, 101.5);
+        assert!(executor.active_orderids().is_empty());
+
+        // The long position is closed before the short one is opened
+        executor.set_target(-1.0);
+        executor.on_bar(&mut engine, 101.5);
+        cross_bar(&mut engine, 3, 102.0, 101.0);
+        assert_eq!(engine.get_pos(), 0.0);
+        executor.on_bar(&mut engine, 101.5);
+        cross_bar(&mut engine, 4, 102.0, 101.0);
+        assert_eq!(engine.get_pos(), -1.0);
+        let offsets: Vec<Offset> = engine
+            .get_all_trades()
+            .iter()
+            .map(|trade| trade.offset)
+            .collect();
+        assert_eq!(offsets[2], Offset::OPEN);
+        assert_ne!(offsets[1], Offset::OPEN);
+    }
+
+    #[test]
+    fn expired_orders_are_cancelled() {
+        let mut engine = test_engine();
+        cross_bar(&mut engine, 0, 101.0, 99.0);
+
+        let null = std::ptr::null_mut();
+        let gtd = engine.send_order(
+            null,
+            Direction::LONG,
+            Offset::OPEN,
+            90.0,
+            1.0,
+            false,
+            false,
+            false,
+            "",
+        );
+        let expiry = engine.datetime + TimeDelta::minutes(2);
+        engine.set_expiry_millis(
+            &gtd[0],
+            TimeInForce::GTD,
+            expiry.and_utc().timestamp_millis(),
+        );
+        // An invalid expiry keeps the one set before
+        engine.set_expiry_millis(&gtd[0], TimeInForce::GTD, i64::MAX);
+        assert_eq!(engine.order_expiries[&gtd[0]], expiry);
+        assert!(engine.get_logs().last().unwrap().msg.contains("无效"));
+        let day = engine.send_order(
+            null,
+            Direction::LONG,
+            Offset::OPEN,
+            110.0,
+            1.0,
+            true,
+            false,
+            false,
+            "",
+        );
+        engine.set_expiry(&day[0], TimeInForce::DAY, NaiveDateTime::default());
+
+        cross_bar(&mut engine, 1, 101.0, 99.0);
+        assert_eq!(engine.get_order(&gtd[0]).unwrap().status, Status::NOTTRADED);
+        cross_bar(&mut engine, 2, 101.0, 99.0);
+        assert_eq!(engine.get_order(&gtd[0]).unwrap().status, Status::CANCELLED);
+        let lifecycle = engine.get_order_timeline().get(&gtd[0]).unwrap();
+        let sent = lifecycle.submitted().unwrap();
+        assert_eq!(lifecycle.acknowledged(), Some(sent + TimeDelta::minutes(1)));
+        assert_eq!(lifecycle.cancelled(), Some(sent + TimeDelta::minutes(2)));
+
+        // The stop order would trigger, but the trading day it was sent on is over
+        cross_bar(&mut engine, 24 * 60, 120.0, 99.0);
+        let stop_order = engine.get_stop_order(&day[0]).unwrap();
+        assert_eq!(stop_order.status, StopOrderStatus::CANCELLED);
+        assert!(engine.get_all_trades().is_empty());
+        assert!(engine.order_expiries.is_empty());
+    }
+
+    #[test]
+    fn timer_interval() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 22)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        let minutes = |timer: &mut Timer| -> Vec<i64> {
+            (0..10)
+                .filter(|&minute| timer.update(start + TimeDelta::minutes(minute)))
+                .collect()
+        };
+
+        assert_eq!(minutes(&mut Timer::new(TimerInterval::Bars(3))), [2, 5, 8]);
+        assert_eq!(
+            minutes(&mut Timer::new(TimerInterval::Seconds(150))),
+            [3, 6, 9]
+        );
+        // Faster than the bars, called once a bar
+        assert_eq!(minutes(&mut Timer::new(TimerInterval::Seconds(1))).len(), 9);
+        assert!(minutes(&mut Timer::default()).is_empty());
+    }
+
+    #[test]
+    fn progress_with_throughput_and_eta() {
+        let bars = SyntheticBars {
+            symbol: "IF888".to_string(),
+            exchange: Exchange::CFFEX,
+            count: 250,
+            start_price: 3500.0,
+            pricetick: 0.2,
+            ..Default::default()
         }
+        .generate();
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let progress = reported.clone();
+        let printed = Arc::new(Mutex::new(0));
+        let output = printed.clone();
+        let mut engine = test_engine();
+        engine.set_output_callback(move |msg| {
+            if msg.starts_with("回放进度") {
+                *output.lock().unwrap() += 1;
+            }
+        });
+        engine.set_progress_callback(move |snapshot| {
+            progress.lock().unwrap().push(snapshot.clone());
+        });
+        engine.set_history_data(bars);
+        engine.add_strategy_fn(|_, _| {});
+        engine.run_backtesting().unwrap();
+
+        let reported = reported.lock().unwrap();
+        // Every 2 bars, printed every tenth
+        assert_eq!(reported.len(), 125);
+        assert_eq!(*printed.lock().unwrap(), 10);
+        assert!(reported.windows(2).all(|pair| pair[0].bars < pair[1].bars));
+        let last = reported.last().unwrap();
+        assert_eq!((last.bars, last.total_bars), (250, 250));
+        assert_eq!(last.progress, 1.0);
+        assert_eq!(last.eta, 0.0);
+        assert!(last.bars_per_sec > 0.0);
     }
 
-    pub extern "C" fn abi_drop_vec_bar_data(vec: *mut Vec<BarData>) {
-        drop(unsafe { Box::from_raw(vec) });
+    #[test]
+    fn cancelled_replay_keeps_partial_results() {
+        let bars = SyntheticBars {
+            symbol: "IF888".to_string(),
+            exchange: Exchange::CFFEX,
+            count: 500,
+            start_price: 3500.0,
+            pricetick: 0.2,
+            ..Default::default()
+        }
+        .generate();
+        let mut engine = test_engine();
+        engine.set_output_callback(|_| {});
+        engine.set_history_data(bars);
+        let cancel = engine.cancel_handle();
+        let mut count = 0;
+        engine.add_strategy_fn(move |context, bar| {
+            count += 1;
+            if count == 10 {
+                context.buy(bar.close_price + 5.0, 1.0);
+            } else if count == 100 {
+                cancel.store(true, Ordering::SeqCst);
+            }
+        });
+        engine.run_backtesting().unwrap();
+
+        assert!(engine.is_cancelled());
+        assert_eq!(engine.replay_position(), (100, 500));
+        // Can't be continued
+        engine.run_backtesting().unwrap();
+        assert!(!engine.step());
+        assert_eq!(engine.replay_position(), (100, 500));
+        let statistics = engine.calculate_result_statistics(false);
+        assert_eq!(statistics.total_trade_count, 1);
     }
 
-    pub extern "C" fn abi_send_order(
-        this: usize,
-        strategy: *mut CtaTemplate,
-        direction: Direction,
-        offset: Offset,
+    #[test]
+    fn risk_limit_halts_trading() {
+        let mut engine = test_engine();
+        engine.set_risk_limits(RiskLimits {
+            max_daily_loss: Some(3000.0),
+            ..Default::default()
+        });
+        cross_bar(&mut engine, 0, 101.0, 99.0);
+
+        let null = std::ptr::null_mut();
+        engine.send_order(
+            null,
+            Direction::LONG,
+            Offset::OPEN,
+            100.0,
+            1.0,
+            false,
+            false,
+            false,
+            "",
+        );
+        cross_bar(&mut engine, 1, 101.0, 99.0);
+        assert_eq!(engine.get_pos(), 1.0);
+        assert!(engine.get_risk_halt().is_none());
+
+        // Closing at 90 loses 10 points of 300
+        cross_bar(&mut engine, 2, 95.0, 85.0);
+        assert!(engine.get_risk_halt().is_some());
+        let rejected = engine.send_order(
+            null,
+            Direction::LONG,
+            Offset::OPEN,
+            90.0,
+            1.0,
+            false,
+            false,
+            false,
+            "",
+        );
+        assert_eq!(
+            engine.get_order(&rejected[0]).unwrap().status,
+            Status::REJECTED
+        );
+
+        // Position is closed at the next open
+        cross_bar(&mut engine, 3, 91.0, 89.0);
+        assert_eq!(engine.get_pos(), 0.0);
+        let trades = engine.get_all_trades();
+        assert_eq!(trades[1].price, 90.0);
+        assert_eq!(trades[1].reference, "risk_halt");
+        let statistics = engine.calculate_result_statistics(false);
+        assert!(statistics.risk_halt.is_some());
+    }
+
+    #[test]
+    fn risk_limit_halts_short_in_tick_mode() {
+        let mut engine = test_engine();
+        engine.mode = BacktestingMode::TICK;
+        engine.set_risk_limits(RiskLimits {
+            max_daily_loss: Some(3000.0),
+            ..Default::default()
+        });
+        let cross_tick = |engine: &mut BacktestingEngine, second: i64, price: f64| {
+            engine.tick = TickData {
+                datetime: NaiveDate::from_ymd_opt(2024, 1, 22)
+                    .unwrap()
+                    .and_hms_opt(9, 0, 0)
+                    .unwrap()
+                    + TimeDelta::seconds(second),
+                last_price: price,
+                bid_price_1: price - 0.2,
+                ask_price_1: price + 0.2,
+                ..Default::default()
+            };
+            engine.datetime = engine.tick.datetime;
+            engine.cross_limit_order();
+            engine.cross_stop_order();
+            engine.update_daily_close(engine.tick.last_price);
+            engine.check_risk_limits();
+        };
+        cross_tick(&mut engine, 0, 100.0);
+
+        engine.send_order(
+            std::ptr::null_mut(),
+            Direction::SHORT,
+            Offset::OPEN,
+            99.0,
+            1.0,
+            false,
+            false,
+            false,
+            "",
+        );
+        cross_tick(&mut engine, 1, 100.0);
+        assert_eq!(engine.get_pos(), -1.0);
+
+        // Rising 10 points loses 3000
+        cross_tick(&mut engine, 2, 110.0);
+        assert!(engine.get_risk_halt().is_some());
+
+        // Bought back at the ask of the next tick
+        cross_tick(&mut engine, 3, 111.0);
+        assert_eq!(engine.get_pos(), 0.0);
+        let trades = engine.get_all_trades();
+        assert_eq!(trades[1].price, 111.2);
+        assert_eq!(trades[1].reference, "risk_halt");
+    }
+
+    #[test]
+    fn intraday_drawdown_and_margin_calls() {
+        let samples: Vec<EquitySample> = [
+            (100.0, 50.0),
+            (120.0, 50.0),
+            (40.0, 50.0),
+            (30.0, 50.0),
+            (110.0, 0.0),
+        ]
+        .into_iter()
+        .map(|(balance, margin)| EquitySample {
+            balance,
+            margin,
+            ..Default::default()
+        })
+        .collect();
+        let statistics =
+            IntradayStatistics::from_samples(&samples, 100.0, &ReturnsAnalyzer::new(240, 0.0));
+        assert_eq!(statistics.max_drawdown, -90.0);
+        assert_eq!(statistics.max_ddpercent, -75.0);
+        // Two samples in a row below the margin are one margin call
+        assert_eq!(statistics.margin_call_count, 1);
+    }
+
+    #[test]
+    fn pnl_decomposition_adds_up() {
+        let mut engine = test_engine();
+        engine.rate = 0.0001;
+        engine.slippage = 0.2;
+        cross_bar(&mut engine, 0, 101.0, 99.0);
+
+        let null = std::ptr::null_mut();
+        engine.send_order(
+            null,
+            Direction::LONG,
+            Offset::OPEN,
+            100.0,
+            1.0,
+            false,
+            false,
+            false,
+            "",
+        );
+        cross_bar(&mut engine, 1, 101.0, 99.0);
+        cross_bar(&mut engine, 2, 104.0, 102.0);
+        engine.calculate_result_statistics(false);
+
+        let df = engine.get_pnl_decomposition();
+        let value = |name: &str| df.column(name).unwrap().f64().unwrap().get(0).unwrap();
+        assert!(value("commission") < 0.0);
+        assert_eq!(value("slippage"), -0.2 * 300.0);
+        let components: f64 = ["holding_pnl", "trading_pnl", "commission", "slippage"]
+            .iter()
+            .map(|name| value(name))
+            .sum();
+        assert!((components - value("net_pnl")).abs() < 1e-9);
+        assert_eq!(value("cum_net_pnl"), engine.get_net_pnl());
+    }
+
+    #[test]
+    fn exposure_and_long_short_split() {
+        let mut engine = test_engine();
+        let null = std::ptr::null_mut();
+        let send = |engine: &mut BacktestingEngine, direction, offset, price, volume| {
+            engine.send_order(
+                null, direction, offset, price, volume, false, false, false, "",
+            );
+        };
+        cross_bar(&mut engine, 0, 101.0, 99.0);
+        send(&mut engine, Direction::LONG, Offset::OPEN, 100.0, 2.0);
+        cross_bar(&mut engine, 1, 101.0, 99.0);
+        cross_bar(&mut engine, 2, 104.0, 102.0);
+        // Reverse into a short position
+        send(&mut engine, Direction::SHORT, Offset::CLOSE, 103.0, 2.0);
+        send(&mut engine, Direction::SHORT, Offset::OPEN, 103.0, 1.0);
+        cross_bar(&mut engine, 3, 104.0, 102.0);
+        cross_bar(&mut engine, 4, 100.0, 98.0);
+        assert_eq!(engine.get_pos(), -1.0);
+
+        let statistics = engine.calculate_result_statistics(false);
+        assert_eq!(statistics.position_days_ratio, 100.0);
+        assert_eq!(statistics.position_bars_ratio, 80.0);
+        assert_eq!(statistics.average_holding_bars, 2.0);
+        assert_eq!(statistics.long_net_pnl, 2.0 * 3.0 * 300.0);
+        assert_eq!(statistics.short_net_pnl, 4.0 * 300.0);
+        assert_eq!(
+            statistics.long_net_pnl + statistics.short_net_pnl,
+            statistics.total_net_pnl
+        );
+        let turnover = (2.0 * 100.0 + 3.0 * 103.0) * 300.0;
+        let expected = turnover / statistics.end_balance * 240.0;
+        assert!((statistics.annual_turnover - expected).abs() < 1e-9);
+
+        // The long trip reached 104 and never fell below its fill at 100
+        let round_trips = engine.get_round_trips();
+        assert_eq!(round_trips.len(), 1);
+        assert_eq!((round_trips[0].mfe, round_trips[0].mae), (4.0, 0.0));
+    }
+
+    #[test]
+    fn initial_position_is_carried() {
+        let mut engine = test_engine();
+        engine.initial_pos = 2.0;
+        engine.initial_price = 100.0;
+        engine.seed_initial_pos();
+        engine.pos = engine.initial_pos;
+        assert_eq!(engine.get_position_detail().long_yd, 2.0);
+
+        cross_bar(&mut engine, 0, 103.0, 101.0);
+        assert_eq!(engine.get_net_pnl(), 2.0 * 2.0 * 300.0);
+
+        // The carried position can be closed like any other
+        let null = std::ptr::null_mut();
+        engine.send_order(
+            null,
+            Direction::SHORT,
+            Offset::CLOSE,
+            102.0,
+            2.0,
+            false,
+            false,
+            false,
+            "",
+        );
+        cross_bar(&mut engine, 1, 103.0, 101.0);
+        assert_eq!(engine.get_pos(), 0.0);
+        assert_eq!(engine.get_net_pnl(), 2.0 * 2.0 * 300.0);
+        let statistics = engine.calculate_result_statistics(false);
+        assert_eq!(statistics.long_net_pnl, 2.0 * 2.0 * 300.0);
+        assert_eq!(statistics.short_net_pnl, 0.0);
+    }
+
+    #[test]
+    fn unconvertible_orders_rejected() {
+        let mut engine = BacktestingEngine::new();
+        engine.set_output_callback(|_| {});
+        let start = NaiveDate::from_ymd_opt(2024, 1, 22).unwrap().into();
+        engine
+            .set_parameters(
+                "rb888.SHFE",
+                Interval::MINUTE,
+                start,
+                start,
+                0.0,
+                0.0,
+                10.0,
+                1.0,
+                1_000_000.0,
+                BacktestingMode::BAR,
+                0.0,
+                240,
+                120,
+            )
+            .unwrap();
+
+        // Nothing to close on SHFE, the strategy gets the order back rejected
+        let vt_orderids = engine.send_order(
+            std::ptr::null_mut(),
+            Direction::SHORT,
+            Offset::CLOSE,
+            3500.0,
+            1.0,
+            false,
+            false,
+            false,
+            "",
+        );
+        assert_eq!(vt_orderids.len(), 1);
+        let order = engine.get_order(&vt_orderids[0]).unwrap();
+        assert_eq!(order.status, Status::REJECTED);
+        assert_eq!(order.volume, 1.0);
+    }
+
+    #[test]
+    fn pnl_converted_into_base_currency() {
+        let mut engine = test_engine();
+        assert_eq!(engine.contract.currency, Currency::CNY);
+        engine.contract.currency = Currency::HKD;
+        let mut rates = FxRates::new();
+        rates.set_rate(Currency::CNY, Currency::HKD, 1.25);
+        engine.set_base_currency(Currency::CNY, rates);
+        engine.initial_pos = 2.0;
+        engine.initial_price = 100.0;
+        engine.seed_initial_pos();
+        engine.pos = engine.initial_pos;
+
+        cross_bar(&mut engine, 0, 103.0, 101.0);
+        assert_eq!(engine.get_net_pnl(), 2.0 * 2.0 * 300.0 * 0.8);
+        let statistics = engine.calculate_result_statistics(false);
+        assert_eq!(statistics.long_net_pnl, 2.0 * 2.0 * 300.0 * 0.8);
+        assert_eq!(engine.daily_results.values().next().unwrap().fx_rate, 0.8);
+    }
+
+    #[test]
+    fn carried_positions_pay_financing() {
+        let mut engine = test_engine();
+        engine.set_financing(0.05, 0.0365);
+        engine.initial_pos = -2.0;
+        engine.initial_price = 100.0;
+        engine.seed_initial_pos();
+        engine.pos = engine.initial_pos;
+
+        cross_bar(&mut engine, 0, 103.0, 101.0);
+        let financing = 2.0 * 100.0 * 300.0 * 0.0365 / 365.0;
+        assert!((engine.get_net_pnl() - (-1200.0 - financing)).abs() < 1e-9);
+        let statistics = engine.calculate_result_statistics(false);
+        assert!((statistics.total_financing - financing).abs() < 1e-9);
+
+        // A long position only borrows beyond the balance, over the weekend too
+        let mut result = DailyResult {
+            start_pos: 2.0,
+            pre_close: 100.0,
+            ..DailyResult::new(NaiveDate::from_ymd_opt(2024, 1, 22).unwrap(), 100.0)
+        };
+        result.charge_financing(300.0, (0.0365, 0.0), 20_000.0, 3);
+        assert!((result.financing - 40_000.0 * 0.0365 * 3.0 / 365.0).abs() < 1e-9);
+        result.charge_financing(300.0, (0.0365, 0.0), 100_000.0, 3);
+        assert_eq!(result.financing, 0.0);
+    }
+
+    ///Buys volume at price on the first tick.
+    struct DepthTaker {
         price: f64,
         volume: f64,
-        stop: bool,
-        lock: bool,
-        net: bool,
-    ) -> *mut Vec<String> {
-        unsafe {
-            Box::into_raw(Box::new(
-                std::mem::transmute::<usize, &mut BacktestingEngine>(this)
-                    .send_order(strategy, direction, offset, price, volume, stop, lock, net),
-            ))
+    }
+
+    impl CtaStrategy for DepthTaker {
+        fn new(_context: &sdk::StrategyContext, setting: &[(String, String)]) -> Self {
+            DepthTaker {
+                price: sdk::setting_value(setting, "price", 100.2),
+                volume: sdk::setting_value(setting, "volume", 8.0),
+            }
         }
+
+        fn on_tick(&mut self, context: &mut sdk::StrategyContext, _tick: &TickData) {
+            if self.volume > 0.0 {
+                context.buy(self.price, self.volume, false);
+                self.volume = 0.0;
+            }
+        }
+
+        fn on_bar(&mut self, _context: &mut sdk::StrategyContext, _bar: &BarData) {}
     }
 
-    pub extern "C" fn abi_drop_vec_string(vec: *mut Vec<String>) {
-        drop(unsafe { Box::from_raw(vec) });
+    #[test]
+    fn orders_fill_across_depth() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 22)
+            .unwrap()
+            .and_hms_opt(9, 30, 0)
+            .unwrap();
+        let tick = |second: i64, asks: [(f64, f64); 3]| TickData {
+            datetime: start + TimeDelta::seconds(second),
+            last_price: asks[0].0,
+            ask_price_1: asks[0].0,
+            ask_volume_1: asks[0].1,
+            ask_price_2: asks[1].0,
+            ask_volume_2: asks[1].1,
+            ask_price_3: asks[2].0,
+            ask_volume_3: asks[2].1,
+            ..Default::default()
+        };
+        let book = [(100.0, 2.0), (100.2, 3.0), (100.4, 10.0)];
+        let mut engine = test_engine();
+        engine.set_output_callback(|_| {});
+        engine.mode = BacktestingMode::TICK;
+        engine.set_depth_fill(true);
+        engine.set_tick_data(vec![
+            tick(0, book),
+            tick(1, book),
+            tick(2, [(100.2, 1.0), (100.4, 10.0), (0.0, 0.0)]),
+            tick(3, [(100.4, 10.0), (0.0, 0.0), (0.0, 0.0)]),
+            tick(4, [(100.0, 5.0), (0.0, 0.0), (0.0, 0.0)]),
+        ]);
+        engine.add_strategy_instance(
+            "DepthTaker",
+            DepthTaker {
+                price: 100.2,
+                volume: 8.0,
+            },
+        );
+        engine.run_backtesting().unwrap();
+
+        // 2 at 100 and 3 at 100.2, then what is listed within the limit as it comes
+        let trades: Vec<(f64, f64)> = engine
+            .get_all_trades()
+            .iter()
+            .map(|trade| (trade.volume, trade.price))
+            .collect();
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[0].0, 5.0);
+        assert!((trades[0].1 - (2.0 * 100.0 + 3.0 * 100.2) / 5.0).abs() < 1e-9);
+        assert_eq!(trades[1], (1.0, 100.2));
+        assert_eq!(trades[2], (2.0, 100.0));
+        let order = &engine.get_all_orders()[0];
+        assert_eq!(order.traded, 8.0);
+        assert_eq!(order.status, Status::ALLTRADED);
+        assert_eq!(engine.pos, 8.0);
+
+        assert_eq!(
+            depth_fill(&book, 20.0, |p| p <= 100.2),
+            Some((5.0, 500.6 / 5.0))
+        );
+        assert_eq!(depth_fill(&book, 1.0, |p| p < 100.0), None);
     }
 
-    pub extern "C" fn abi_cancel_all(this: usize, strategy: *mut CtaTemplate) {
-        unsafe {
-            std::mem::transmute::<usize, &mut BacktestingEngine>(this).cancel_all(strategy);
-        }
+    #[test]
+    fn fills_pay_volume_impact() {
+        let mut engine = test_engine();
+        engine.mode = BacktestingMode::TICK;
+        engine.set_volume_impact(0.01);
+        engine.tick = TickData {
+            ask_price_1: 100.0,
+            bid_price_1: 99.8,
+            last_volume: 100.0,
+            ..Default::default()
+        };
+
+        let null = std::ptr::null_mut();
+        engine.send_order(
+            null,
+            Direction::LONG,
+            Offset::OPEN,
+            101.0,
+            4.0,
+            false,
+            false,
+            false,
+            "",
+        );
+        engine.cross_limit_order();
+        // 1% of the price times the square root of taking 4% of the volume
+        let trades = engine.get_all_trades();
+        assert!((trades[0].price - 100.2).abs() < 1e-9);
     }
-}
 
-#[derive(Default)]
-struct DailyResult {
-    date: NaiveDate,
-    close_price: f64,
-    pre_close: f64,
+    #[test]
+    fn fill_hooks_adjust_prices_in_fast_markets() {
+        let mut engine = test_engine();
+        engine.add_fill_hook(adverse_ticks_in_fast_market(2.0, 0.01));
+        // Stop orders pay one more tick on top
+        engine.add_fill_hook(Box::new(|fill: &Fill, price: f64| {
+            if fill.stop {
+                fill.adverse(price, fill.pricetick)
+            } else {
+                price
+            }
+        }));
 
-    trades: Vec<Rc<RefCell<TradeData>>>,
-    trade_count: i64,
+        let null = std::ptr::null_mut();
+        let send = |engine: &mut BacktestingEngine, direction, price, stop| {
+            engine.send_order(
+                null,
+                direction,
+                Offset::OPEN,
+                price,
+                1.0,
+                stop,
+                false,
+                false,
+                "",
+            );
+        };
+        // Range of 0.5% is calm, of 2% fast
+        send(&mut engine, Direction::LONG, 101.0, false);
+        cross_bar(&mut engine, 30, 100.3, 99.8);
+        send(&mut engine, Direction::SHORT, 99.0, false);
+        cross_bar(&mut engine, 31, 101.0, 99.0);
+        send(&mut engine, Direction::LONG, 100.4, true);
+        cross_bar(&mut engine, 32, 100.5, 100.0);
+
+        let prices: Vec<f64> = engine
+            .get_all_trades()
+            .iter()
+            .map(|trade| trade.price)
+            .collect();
+        assert_eq!(prices.len(), 3);
+        assert!((prices[0] - 100.05).abs() < 1e-9);
+        assert!((prices[1] - 99.6).abs() < 1e-9);
+        assert!((prices[2] - 100.6).abs() < 1e-9);
+    }
 
-    start_pos: f64,
-    end_pos: f64,
+    ///Holds volume long, scaling in when it is raised.
+    struct ScaleIn {
+        volume: f64,
+    }
 
-    turnover: f64,
-    commission: f64,
-    slippage: f64,
+    impl CtaStrategy for ScaleIn {
+        fn parameters() -> Vec<(String, String)> {
+            vec![
+                ("volume".to_string(), "1".to_string()),
+                ("enabled".to_string(), "true".to_string()),
+            ]
+        }
 
-    trading_pnl: f64,
-    holding_pnl: f64,
-    total_pnl: f64,
-    net_pnl: f64,
-}
+        fn new(_context: &sdk::StrategyContext, setting: &[(String, String)]) -> Self {
+            ScaleIn {
+                volume: sdk::setting_value(setting, "volume", 1.0),
+            }
+        }
 
-impl DailyResult {
-    pub fn new(date: NaiveDate, close_price: f64) -> Self {
-        DailyResult {
-            date,
-            close_price,
-            ..Default::default()
+        fn on_bar(&mut self, context: &mut sdk::StrategyContext, bar: &BarData) {
+            if context.pos() < self.volume {
+                context.buy(bar.close_price + 10.0, self.volume - context.pos(), false);
+            }
+        }
+
+        fn update_setting(
+            &mut self,
+            _context: &mut sdk::StrategyContext,
+            setting: &[(String, String)],
+        ) -> bool {
+            self.volume = sdk::setting_value(setting, "volume", self.volume);
+            true
         }
     }
 
-    pub fn add_trade(&mut self, trade: Rc<RefCell<TradeData>>) {
-        self.trades.push(trade)
+    #[test]
+    fn trading_days_of_sessions_and_time_zones() {
+        let at = |day: u32, hour: u32, minute: u32| {
+            NaiveDate::from_ymd_opt(2024, 1, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap()
+        };
+        let date = |day| NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+
+        // UTC data counted in Beijing days, 16:00 UTC is midnight there
+        let beijing = TradingDayBoundary::new(8, 0);
+        assert_eq!(beijing.trading_date(at(22, 15, 59)), date(22));
+        assert_eq!(beijing.trading_date(at(22, 16, 0)), date(23));
+        assert_eq!(beijing.next_day_start(at(22, 10, 0)), at(22, 16, 0));
+
+        // Days from 08:00 to 08:00 count as the day they start on
+        let morning = TradingDayBoundary::new(0, 8);
+        assert_eq!(morning.trading_date(at(23, 7, 59)), date(22));
+        assert_eq!(morning.trading_date(at(23, 8, 0)), date(23));
+        assert_eq!(morning.next_day_start(at(23, 7, 59)), at(23, 8, 0));
+
+        // A night session from 21:00 counts as the next day
+        let night = TradingDayBoundary::new(0, -3);
+        assert_eq!(night.trading_date(at(22, 14, 59)), date(22));
+        assert_eq!(night.trading_date(at(22, 21, 0)), date(23));
+        assert_eq!(night.trading_date(at(23, 2, 30)), date(23));
+        assert_eq!(night.next_day_start(at(22, 14, 59)), at(22, 21, 0));
+
+        // Daily results of a replay follow the boundary
+        let bars: Vec<BarData> = [at(22, 14, 59), at(22, 21, 0), at(22, 23, 0), at(23, 9, 0)]
+            .into_iter()
+            .map(|datetime| BarData {
+                datetime,
+                open_price: 100.0,
+                high_price: 100.0,
+                low_price: 100.0,
+                close_price: 100.0,
+                ..Default::default()
+            })
+            .collect();
+        let mut engine = test_engine();
+        engine.set_output_callback(|_| {});
+        engine.set_trading_day(night);
+        engine.set_history_data(bars);
+        engine.add_strategy_instance("ScaleIn", ScaleIn { volume: 1.0 });
+        engine.run_backtesting().unwrap();
+        let dates: Vec<NaiveDate> = engine
+            .get_daily_results()
+            .map(|result| result.date)
+            .collect();
+        assert_eq!(dates, [date(22), date(23)]);
     }
 
-    fn calculate_pnl(
-        &mut self,
-        pre_close: f64,
-        start_pos: f64,
-        size: f64,
-        rate: f64,
-        slippage: f64,
-    ) {
-        // If no pre_close provided on the first day,
-        // use value 1 to avoid zero division error
-        if pre_close != 0.0 {
-            self.pre_close = pre_close;
-        } else {
-            self.pre_close = 1.0;
+    #[test]
+    fn setting_updates_of_running_strategy() {
+        use crate::vnrs::trader::audit::{AuditEntry, AuditLog};
+        use crate::vnrs::trader::clock::SimClock;
+
+        let bars = SyntheticBars {
+            symbol: "IF888".to_string(),
+            exchange: Exchange::CFFEX,
+            count: 30,
+            start_price: 3500.0,
+            pricetick: 0.2,
+            ..Default::default()
         }
+        .generate();
+        let dir = std::env::temp_dir().join(format!("vnrs_setting_{}", std::process::id()));
+        let recorder = Arc::new(
+            AuditRecorder::new(&dir)
+                .unwrap()
+                .with_clock(Arc::new(SimClock::new(bars[0].datetime))),
+        );
+        let mut engine = test_engine();
+        engine.set_output_callback(|_| {});
+        engine.set_audit_recorder(recorder.clone());
+        engine.set_history_data(bars.clone());
+        engine.add_strategy_instance("ScaleIn", ScaleIn { volume: 1.0 });
+        let queue = engine.setting_queue();
+
+        engine.run_until(bars[9].datetime);
+        assert!(engine.update_setting("size:2").is_err());
+        assert!(engine.update_setting("enabled:yes").is_err());
+        engine.update_setting("volume:3").unwrap();
+        engine.run_until(bars[19].datetime);
+        assert!(queue.push("volume:many").is_err());
+        assert!(queue.push("").is_err());
+        queue.push("volume:5").unwrap();
+        engine.run_backtesting().unwrap();
+
+        let volumes: Vec<f64> = engine
+            .get_all_trades()
+            .iter()
+            .map(|trade| trade.volume)
+            .collect();
+        assert_eq!(volumes, vec![1.0, 2.0, 2.0]);
+        assert_eq!(engine.setting, "volume:5");
+
+        recorder.flush().unwrap();
+        let log = AuditLog::load(&dir, bars[0].datetime.date()).unwrap();
+        let updates: Vec<&str> = log
+            .records
+            .iter()
+            .filter_map(|record| match &record.entry {
+                AuditEntry::SettingUpdate { setting, .. } => Some(setting.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(updates, vec!["volume:3", "volume:5"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 
-        // Holding pnl is the pnl from holding position at day start
-        self.start_pos = start_pos;
-        self.end_pos = start_pos;
-
-        self.holding_pnl = self.start_pos * (self.close_price - self.pre_close) * size;
+    ///Looks up the contracts of CONTRACT_SYMBOLS on its first bar.
+    struct ContractLookup;
+    const CONTRACT_SYMBOLS: [&str; 4] = ["", "rb2410.SHFE", "au2412.SHFE", "XYZ.SHFE"];
+    static CONTRACTS: Mutex<Vec<Option<ContractData>>> = Mutex::new(Vec::new());
 
-        // Trading pnl is the pnl from new trade during the day
-        self.trade_count = self.trades.len() as i64;
+    impl CtaStrategy for ContractLookup {
+        fn new(_context: &sdk::StrategyContext, _setting: &[(String, String)]) -> Self {
+            ContractLookup
+        }
 
-        for trade in &self.trades {
-            let pos_change;
-            if trade.borrow().direction == Direction::LONG {
-                pos_change = trade.borrow().volume;
-            } else {
-                pos_change = -trade.borrow().volume;
+        fn on_bar(&mut self, context: &mut sdk::StrategyContext, _bar: &BarData) {
+            let mut contracts = CONTRACTS.lock().unwrap();
+            if contracts.is_empty() {
+                for vt_symbol in CONTRACT_SYMBOLS {
+                    contracts.push(context.get_contract(vt_symbol));
+                }
             }
+        }
+    }
 
-            self.end_pos += pos_change;
+    #[test]
+    fn contracts_looked_up_by_strategies() {
+        let bars = SyntheticBars {
+            symbol: "IF888".to_string(),
+            exchange: Exchange::CFFEX,
+            count: 5,
+            start_price: 3500.0,
+            pricetick: 0.2,
+            ..Default::default()
+        }
+        .generate();
+        let mut registry = ContractRegistry::new();
+        registry.add_contract(ContractData {
+            symbol: "rb2410".into(),
+            exchange: Exchange::SHFE,
+            size: 10.0,
+            pricetick: 1.0,
+            min_volume: 5.0,
+            ..Default::default()
+        });
+        let mut engine = test_engine();
+        engine.set_output_callback(|_| {});
+        engine.set_contract_registry(Arc::new(registry));
+        engine.set_history_data(bars);
+        engine.add_strategy_instance("ContractLookup", ContractLookup);
+        engine.run_backtesting().unwrap();
+
+        let contracts = CONTRACTS.lock().unwrap();
+        let traded = contracts[0].as_ref().unwrap();
+        assert_eq!(traded.vt_symbol(), "IF888.CFFEX");
+        assert_eq!((traded.size, traded.pricetick), (300.0, 0.2));
+        // From the registry, else from the built-in specs
+        assert_eq!(contracts[1].as_ref().unwrap().min_volume, 5.0);
+        let builtin = contracts[2].as_ref().unwrap();
+        assert_eq!((builtin.size, builtin.pricetick), (1000.0, 0.02));
+        assert!(contracts[3].is_none());
+    }
 
-            let turnover = trade.borrow().volume * size * trade.borrow().price;
-            self.trading_pnl += pos_change * (self.close_price - trade.borrow().price) * size;
-            self.slippage += trade.borrow().volume * size * slippage;
+    #[test]
+    fn days_settle_at_vwap_with_margin() {
+        let bar = |datetime: &str, open: f64, close: f64, volume: f64| BarData {
+            symbol: "IF888".into(),
+            exchange: Exchange::CFFEX,
+            interval: Interval::MINUTE,
+            datetime: NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap(),
+            open_price: open,
+            high_price: open.max(close),
+            low_price: open.min(close),
+            close_price: close,
+            volume,
+            turnover: close * volume * 300.0,
+            ..Default::default()
+        };
+        let bars = vec![
+            bar("2024-01-22 09:30", 100.0, 100.0, 10.0),
+            bar("2024-01-22 14:00", 101.0, 102.0, 10.0),
+            bar("2024-01-22 15:00", 102.0, 104.0, 30.0),
+            // After the settlement, the day stays marked to it
+            bar("2024-01-22 15:10", 104.0, 110.0, 5.0),
+            bar("2024-01-23 09:30", 105.0, 105.0, 10.0),
+            bar("2024-01-23 15:00", 105.0, 107.0, 10.0),
+        ];
+        let mut engine = test_engine();
+        engine.set_output_callback(|_| {});
+        engine.set_settlement(SettlementConfig {
+            margin_ratio: 0.1,
+            ..Default::default()
+        });
+        engine.set_history_data(bars);
+        engine.add_strategy_instance("ScaleIn", ScaleIn { volume: 1.0 });
+        engine.run_backtesting().unwrap();
+
+        let settlements = engine.get_settlements();
+        assert_eq!(settlements.len(), 2);
+        // (100 * 10 + 102 * 10 + 104 * 30) / 50, bought at the open of 101
+        assert!((settlements[0].price - 102.8).abs() < 1e-9);
+        assert!((settlements[0].net_pnl - 1.8 * 300.0).abs() < 1e-6);
+        assert!((settlements[0].margin - 102.8 * 300.0 * 0.1).abs() < 1e-6);
+        assert!((settlements[0].available - (1_000_540.0 - settlements[0].margin)).abs() < 1e-6);
+        let first_day = engine.get_daily_results().next().unwrap();
+        assert!((first_day.close_price - 102.8).abs() < 1e-9);
+        assert!((settlements[1].price - 106.0).abs() < 1e-9);
+        assert!((settlements[1].net_pnl - 3.2 * 300.0).abs() < 1e-6);
+        assert!((settlements[1].balance - 1_001_500.0).abs() < 1e-6);
+    }
 
-            self.turnover += turnover;
-            self.commission += turnover * rate;
-        }
+    #[test]
+    fn statistics_of_daily_pnl() {
+        let date = |day: u32| NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+        // Balances 1.01M, 1.03M, 0.98M, 0.97M, 1.00M, 1.04M over a weekend
+        let daily = DailySeries {
+            dates: [2, 3, 4, 5, 8, 9].into_iter().map(date).collect(),
+            net_pnl: vec![10_000.0, 20_000.0, -50_000.0, -10_000.0, 30_000.0, 40_000.0],
+            commission: 0.0,
+            slippage: 0.0,
+            financing: 0.0,
+            turnover: 0.0,
+            trade_count: 0,
+            position_days: 0,
+        };
+        let engine = test_engine();
+        let statistics = engine.statistics_from_daily(Some(&daily), false);
+
+        assert_eq!(statistics.total_days, 6);
+        assert_eq!((statistics.profit_days, statistics.loss_days), (4, 2));
+        assert!((statistics.end_balance - 1_040_000.0).abs() < 1e-6);
+        // From the high of 1.03M on the 3rd to the low of 0.97M on the 5th
+        assert!((statistics.max_drawdown + 60_000.0).abs() < 1e-6);
+        assert!((statistics.max_ddpercent + 60_000.0 / 1_030_000.0 * 100.0).abs() < 1e-9);
+        assert_eq!(statistics.max_drawdown_duration, 2);
+        // Log returns with the population standard deviation
+        assert!((statistics.daily_return - 1.04f64.ln() / 6.0 * 100.0).abs() < 1e-9);
+        assert!((statistics.return_std - 2.964_949_447).abs() < 1e-6);
+        assert!((statistics.sharpe_ratio - 3.415_486_426).abs() < 1e-6);
+    }
 
-        // Net pnl takes account of commission and slippage cost
-        self.total_pnl = self.trading_pnl + self.holding_pnl;
-        self.net_pnl = self.total_pnl - self.commission - self.slippage;
+    #[test]
+    fn sharpe_over_dated_risk_free() {
+        let date = |day: u32| NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+        let daily = DailySeries {
+            dates: (2..=5).map(date).collect(),
+            net_pnl: vec![10_000.0, -5_000.0, 20_000.0, 10_000.0],
+            commission: 0.0,
+            slippage: 0.0,
+            financing: 0.0,
+            turnover: 0.0,
+            trade_count: 0,
+            position_days: 0,
+        };
+        let mut engine = test_engine();
+        let base = engine.statistics_from_daily(Some(&daily), false);
+
+        // 3% a year from the 4th, the 2nd and 3rd use risk_free
+        engine.set_risk_free_series(BTreeMap::from([(date(4), 0.03)]));
+        let statistics = engine.statistics_from_daily(Some(&daily), false);
+        let daily_rate = 1.03f64.ln() / 240.0 / 2.0;
+        let expected = base.sharpe_ratio - daily_rate * 100.0 / base.return_std * f64::sqrt(240.0);
+        assert!((statistics.sharpe_ratio - expected).abs() < 1e-9);
     }
-}
 
-fn load_bar_data(
-    symbol: &str,
-    exchange: Exchange,
-    interval: Interval,
-    start: NaiveDateTime,
-    end: NaiveDateTime,
-) -> Vec<BarData> {
-    let db = get_database();
+    ///Order of a random stream, priced in ticks from the close of the bar before.
+    #[derive(Debug, Clone)]
+    struct RandomOrder {
+        direction: Direction,
+        offset: Offset,
+        ticks: i32,
+        volume: f64,
+        stop: bool,
+    }
+
+    fn random_order() -> impl Strategy<Value = RandomOrder> {
+        (
+            any::<bool>(),
+            any::<bool>(),
+            -10i32..=10,
+            1u32..=3,
+            any::<bool>(),
+        )
+            .prop_map(|(long, open, ticks, volume, stop)| RandomOrder {
+                direction: if long {
+                    Direction::LONG
+                } else {
+                    Direction::SHORT
+                },
+                offset: if open { Offset::OPEN } else { Offset::CLOSE },
+                ticks,
+                volume: volume as f64,
+                stop,
+            })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        ///Bars of a random walk over several days, each after a few random orders.
+        #[test]
+        fn matching_and_pnl_invariants(
+            steps in prop::collection::vec(
+                (prop::collection::vec(random_order(), 0..3), -2.0f64..2.0, 0.2f64..3.0),
+                1..40,
+            )
+        ) {
+            let mut engine = test_engine();
+            engine.rate = 2.5e-5;
+            engine.slippage = 0.2;
+            let null = std::ptr::null_mut();
+            let mut mid = 100.0;
+            let mut ranges = Vec::new();
+            for (i, (orders, step, half_range)) in steps.iter().enumerate() {
+                for order in orders {
+                    // mid is the close of the bar before
+                    let price = round_to(mid + order.ticks as f64 * 0.2, 0.2);
+                    engine.send_order(
+                        null,
+                        order.direction,
+                        order.offset,
+                        price,
+                        order.volume,
+                        order.stop,
+                        false,
+                        false,
+                        "",
+                    );
+                }
+                mid += step;
+                // 6 hours apart, so that the bars span several trading days
+                let minute = i as i64 * 360;
+                cross_bar(&mut engine, minute, mid + half_range, mid - half_range);
+                ranges.push((engine.datetime, mid - half_range, mid + half_range));
+            }
+
+            let trades = engine.get_all_trades();
+            for trade in &trades {
+                let (_, low, high) = ranges
+                    .iter()
+                    .find(|(datetime, ..)| *datetime == trade.datetime)
+                    .unwrap();
+                prop_assert!(*low - 1e-9 <= trade.price && trade.price <= *high + 1e-9);
+            }
+
+            let signed = |trade: &TradeData| match trade.direction {
+                Direction::LONG => trade.volume,
+                _ => -trade.volume,
+            };
+            let pos: f64 = trades.iter().map(signed).sum();
+            prop_assert!((engine.get_pos() - pos).abs() < 1e-9);
+
+            for result in engine.get_daily_results() {
+                prop_assert!(result.commission >= 0.0 && result.slippage >= 0.0);
+            }
 
-    return db.load_bar_data(symbol, exchange, interval, start, end);
+            // Cash paid for the trades, the position marked to the last close and the costs
+            // add up to the pnl of the days
+            let size = engine.size;
+            let close = engine.bar.close_price;
+            let expected: f64 = trades
+                .iter()
+                .map(|trade| {
+                    signed(trade) * (close - trade.price) * size
+                        - trade.volume * size * (trade.price * engine.rate + engine.slippage)
+                })
+                .sum();
+            prop_assert!((engine.get_net_pnl() - expected).abs() < 1e-6);
+        }
+    }
 }
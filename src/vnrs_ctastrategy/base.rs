@@ -1,20 +1,33 @@
 use crate::vnrs::trader::{
-    constant::{Direction, Interval, Offset},
-    object::{BarData, OrderData, TickData, TradeData},
+    constant::{Direction, Interval, IntervalKind, Offset},
+    converter::PositionHolding,
+    object::{BarData, ContractData, OrderData, TickData, TradeData},
 };
 use chrono::{DateTime, Duration, Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    ffi::{c_char, CString, OsStr, OsString},
-    sync::{Arc, OnceLock},
+    ffi::{c_char, CStr, CString},
+    sync::OnceLock,
+};
+#[cfg(feature = "dylib-strategies")]
+use std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Mutex},
 };
 
-use super::{backtesting::BacktestingEngine, template::CtaTemplate};
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+use super::backtesting::BacktestingEngine;
+use super::template::CtaTemplate;
+use crate::error::{VnrsError, VnrsResult};
 
 pub const APP_NAME: &'static str = "CtaStrategy";
 pub const STOPORDER_PREFIX: &'static str = "STOP";
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StopOrderStatus {
     WAITING,
     CANCELLED,
@@ -27,6 +40,8 @@ impl Default for StopOrderStatus {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
 pub enum EngineType {
     LIVE,
     BACKTESTING,
@@ -37,7 +52,7 @@ impl Default for EngineType {
     }
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BacktestingMode {
     BAR = 1,
     TICK = 2,
@@ -49,7 +64,7 @@ impl Default for BacktestingMode {
     }
 }
 
-#[derive(Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct StopOrder {
     pub vt_symbol: String,
     pub direction: Direction,
@@ -63,11 +78,60 @@ pub struct StopOrder {
     pub net: bool,
     pub vt_orderids: Vec<String>,
     pub status: StopOrderStatus,
+    ///Copied to the orders and trades of the stop order when triggered.
+    #[serde(default)]
+    pub reference: String,
+}
+
+///How long a resting order lives before the engine cancels it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(C)]
+pub enum TimeInForce {
+    ///Good till cancelled.
+    #[default]
+    GTC,
+    ///Cancelled once the trading day it was sent on is over.
+    DAY,
+    ///Good till the expiry datetime given with it.
+    GTD,
+}
+
+///How the volume of an order that isn't a whole number of lots is adjusted when sent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LotRounding {
+    ///Round down to whole lots, an order of less than a lot is rejected.
+    #[default]
+    Down,
+    Nearest,
+    Up,
+    ///Reject the order.
+    Reject,
 }
 
+///Exit legs of a bracket order, sent for each fill of its entry order: a take profit limit
+///order and a stop loss stop order, a fill of either one is taken off the other.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BracketOrder {
+    ///Direction of the exit legs, opposite to the entry.
+    pub direction: Direction,
+    pub take_profit: f64,
+    pub stop_loss: f64,
+    pub lock: bool,
+    pub net: bool,
+    pub reference: String,
+}
+
+pub const DATA_FILENAME: &str = "cta_strategy_data.json";
+
 pub const EVENT_CTA_LOG: &'static str = "eCtaLog";
 pub const EVENT_CTA_STRATEGY: &'static str = "eCtaStrategy";
 pub const EVENT_CTA_STOPORDER: &'static str = "eCtaStopOrder";
+///Data is a PositionMismatch found by the PositionReconciler.
+pub const EVENT_CTA_RECONCILE: &str = "eCtaReconcile";
+///Data is a ScheduleChange of the StrategyScheduler.
+pub const EVENT_CTA_SCHEDULE: &str = "eCtaSchedule";
+///Data is a SupervisorAlarm of the Supervisor.
+pub const EVENT_CTA_SUPERVISOR: &str = "eCtaSupervisor";
 
 pub static INTERVAL_DELTA_MAP: OnceLock<HashMap<Interval, Duration>> = OnceLock::new();
 
@@ -78,143 +142,544 @@ pub fn get_interval_delta_map() -> &'static HashMap<Interval, Duration> {
             (Interval::MINUTE, Duration::minutes(1)),
             (Interval::HOUR, Duration::hours(1)),
             (Interval::DAILY, Duration::days(1)),
+            (Interval::WEEKLY, Duration::weeks(1)),
         ]
         .into_iter()
         .collect()
     })
 }
 
+///Length of a bar of interval, None for NONE.
+pub fn get_interval_delta(interval: Interval) -> Option<Duration> {
+    match interval {
+        Interval::SECONDS(seconds) => Some(Duration::seconds(seconds as i64)),
+        _ => get_interval_delta_map().get(&interval).copied(),
+    }
+}
+
+#[cfg(feature = "dylib-strategies")]
+///Error loading a strategy dylib.
+#[derive(Debug)]
+pub enum StrategyLoadError {
+    ///The library itself couldn't be loaded.
+    Library(OsString, libloading::Error),
+    ///Required callbacks are missing, each with its lookup error.
+    AbiIncomplete(OsString, Vec<(&'static str, libloading::Error)>),
+}
+
+#[cfg(feature = "dylib-strategies")]
+impl fmt::Display for StrategyLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StrategyLoadError::Library(filename, e) => {
+                write!(f, "failed to load strategy {:?}: {}", filename, e)
+            }
+            StrategyLoadError::AbiIncomplete(filename, missing) => {
+                let names: Vec<&str> = missing.iter().map(|(name, _)| *name).collect();
+                write!(
+                    f,
+                    "strategy ABI incomplete, {:?} is missing {}",
+                    filename,
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "dylib-strategies")]
+impl std::error::Error for StrategyLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StrategyLoadError::Library(_, e) => Some(e),
+            StrategyLoadError::AbiIncomplete(_, missing) => missing
+                .first()
+                .map(|(_, e)| e as &(dyn std::error::Error + 'static)),
+        }
+    }
+}
+
+#[cfg(feature = "dylib-strategies")]
+///Take a required symbol, recording the error if it's missing.
+fn require<T>(
+    name: &'static str,
+    symbol: Result<T, libloading::Error>,
+    missing: &mut Vec<(&'static str, libloading::Error)>,
+) -> Option<T> {
+    symbol.map_err(|e| missing.push((name, e))).ok()
+}
+
+#[cfg(feature = "dylib-strategies")]
 #[derive(Default)]
 pub struct ExternClass {
     pub filename: OsString,
+    ///Keeps the library loaded as long as its functions are in use, None for a strategy built
+    ///into the host, see sdk::strategy_class.
+    #[allow(dead_code)]
     lib: Option<libloading::Library>,
     pub func_new: Option<
-        libloading::Symbol<
-            'static,
-            extern "C" fn(
-                cta_engine: *const VTable,
-                strategy_name: *const c_char,
-                vt_symbol: *const c_char,
-                setting: *const c_char,
-            ) -> *mut CtaTemplate,
-        >,
+        extern "C" fn(
+            cta_engine: *const VTable,
+            strategy_name: *const c_char,
+            vt_symbol: *const c_char,
+            setting: *const c_char,
+        ) -> *mut CtaTemplate,
     >,
-    pub func_on_init: Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, usize)>>,
-    pub func_on_start: Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate)>>,
-    pub func_on_stop: Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate)>>,
-    pub func_on_tick:
-        Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, *const TickData)>>,
-    pub func_on_bar:
-        Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, *const BarData)>>,
-    pub func_on_order:
-        Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, *const OrderData)>>,
-    pub func_on_trade:
-        Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, *const TradeData)>>,
-    pub func_on_stop_order:
-        Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, *const StopOrder)>>,
-    pub func_get_inited_mut:
-        Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate) -> *mut bool>>,
-    pub func_get_trading_mut:
-        Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate) -> *mut bool>>,
-    pub func_get_pos_mut:
-        Option<libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate) -> *mut f64>>,
+    pub func_drop: Option<extern "C" fn(*mut CtaTemplate)>,
+    pub func_on_init: Option<extern "C" fn(*mut CtaTemplate, usize)>,
+    pub func_on_start: Option<extern "C" fn(*mut CtaTemplate)>,
+    pub func_on_stop: Option<extern "C" fn(*mut CtaTemplate)>,
+    ///Optional, like on_order, on_trade and on_stop_order.
+    pub func_on_tick: Option<extern "C" fn(*mut CtaTemplate, *const TickData)>,
+    pub func_on_bar: Option<extern "C" fn(*mut CtaTemplate, *const BarData)>,
+    pub func_on_order: Option<extern "C" fn(*mut CtaTemplate, *const OrderData)>,
+    pub func_on_trade: Option<extern "C" fn(*mut CtaTemplate, *const TradeData)>,
+    pub func_on_stop_order: Option<extern "C" fn(*mut CtaTemplate, *const StopOrder)>,
+    pub func_on_timer: Option<extern "C" fn(*mut CtaTemplate)>,
+    pub func_get_inited_mut: Option<extern "C" fn(*mut CtaTemplate) -> *mut bool>,
+    pub func_get_trading_mut: Option<extern "C" fn(*mut CtaTemplate) -> *mut bool>,
+    ///Optional, the engine mirrors the position it books into the strategy's pos field.
+    pub func_get_pos_mut: Option<extern "C" fn(*mut CtaTemplate) -> *mut f64>,
+    ///Optional, returns default parameters as "name:value,name:value" in a static string.
+    pub func_get_parameters: Option<extern "C" fn() -> *const c_char>,
+    ///Optional, returns variables as "name:value,name:value", valid until the next call into the strategy.
+    pub func_get_variables: Option<extern "C" fn(*mut CtaTemplate) -> *const c_char>,
+    ///Optional, restores variables from "name:value,name:value", the string is only borrowed for the call.
+    pub func_set_variables: Option<extern "C" fn(*mut CtaTemplate, *const c_char)>,
+    ///Optional, applies parameters "name:value,name:value" to a running strategy, false if
+    ///it doesn't take them.
+    pub func_update_setting: Option<extern "C" fn(*mut CtaTemplate, *const c_char) -> bool>,
+    ///Optional, starts a released instance over as abi_new would create it with the same
+    ///arguments, false if the strategy can't be reused.
+    pub func_reset: Option<
+        extern "C" fn(
+            strategy: *mut CtaTemplate,
+            cta_engine: *const VTable,
+            strategy_name: *const c_char,
+            vt_symbol: *const c_char,
+            setting: *const c_char,
+        ) -> bool,
+    >,
+    ///Instances released by ExternInstance, reset for the next one instead of created anew.
+    pool: Mutex<Vec<PooledInstance>>,
+    ///Set once abi_reset returned false, instances are dropped from then on.
+    reset_refused: AtomicBool,
+}
+
+///Directory of the strategy dylibs a server loads for its remote clients. Clients name a
+///strategy, e.g. "double_ma_strategy", which is only looked up here, never loaded from a path
+///of their choosing.
+#[cfg(feature = "dylib-strategies")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StrategyDirectory {
+    pub dir: PathBuf,
 }
 
+#[cfg(feature = "dylib-strategies")]
+impl StrategyDirectory {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        StrategyDirectory { dir: dir.into() }
+    }
+
+    ///Directory of the "strategy.dir" setting, relative to the trader dir unless absolute.
+    pub fn from_settings() -> Self {
+        let dir = crate::vnrs::trader::setting::get_settings()["strategy.dir"].clone();
+        Self::new(crate::vnrs::trader::utility::get_trader_dir().join(dir))
+    }
+
+    ///Path of the dylib of the strategy name, an error for a name that isn't a plain file
+    ///name, e.g. a path, or that has no dylib in the directory.
+    pub fn resolve(&self, name: &str) -> VnrsResult<PathBuf> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(VnrsError::Setting(format!(
+                "invalid strategy name {:?}",
+                name
+            )));
+        }
+        let path = self.dir.join(libloading::library_filename(name));
+        if !path.is_file() {
+            return Err(VnrsError::Setting(format!(
+                "strategy {} not found in {}",
+                name,
+                self.dir.display()
+            )));
+        }
+        Ok(path)
+    }
+
+    ///Class of the strategy name, see ExternClass::load.
+    pub fn load(&self, name: &str) -> VnrsResult<Arc<ExternClass>> {
+        Ok(ExternClass::load(self.resolve(name)?)?)
+    }
+}
+
+///Name of the strategy of a dylib filename, e.g. double_ma_strategy for
+///target/release/libdouble_ma_strategy.so, as a StrategyDirectory resolves it.
+#[cfg(feature = "dylib-strategies")]
+pub fn strategy_name(filename: &str) -> String {
+    let stem = Path::new(filename)
+        .file_stem()
+        .map_or(filename.into(), |stem| stem.to_string_lossy());
+    stem.strip_prefix(std::env::consts::DLL_PREFIX)
+        .filter(|_| !std::env::consts::DLL_PREFIX.is_empty())
+        .unwrap_or(&stem)
+        .to_string()
+}
+
+///Instance waiting in the pool of its class, called by no one until it's taken out.
+#[cfg(feature = "dylib-strategies")]
+struct PooledInstance(*mut CtaTemplate);
+
+#[cfg(feature = "dylib-strategies")]
+unsafe impl Send for PooledInstance {}
+
+#[cfg(feature = "dylib-strategies")]
+///Look up a function in the strategy dylib, valid as long as the ExternClass keeps the
+///library loaded.
+macro_rules! get_symbol {
+    ($lib:expr, $name:literal, $ty:ty) => {
+        $lib.get::<$ty>($name).map(|symbol| *symbol)
+    };
+}
+
+#[cfg(feature = "dylib-strategies")]
 impl ExternClass {
-    pub fn new<P: AsRef<OsStr>>(filename: P) -> Self {
+    ///Load a strategy dylib. Optional callbacks may be absent, missing required ones are
+    ///reported together in StrategyLoadError::AbiIncomplete.
+    pub fn new<P: AsRef<OsStr>>(filename: P) -> Result<Self, StrategyLoadError> {
+        let mut missing = Vec::new();
         unsafe {
-            let the_lib = libloading::Library::new(filename.as_ref().to_owned()).unwrap();
-            let func_new = std::mem::transmute::<
-                libloading::Symbol<
-                    '_,
-                    unsafe extern "C" fn(
-                        cta_engine: *const VTable,
-                        strategy_name: *const c_char,
-                        vt_symbol: *const c_char,
-                        setting: *const c_char,
-                    ) -> *mut CtaTemplate,
-                >,
-                libloading::Symbol<
-                    'static,
+            let the_lib = libloading::Library::new(filename.as_ref())
+                .map_err(|e| StrategyLoadError::Library(filename.as_ref().to_owned(), e))?;
+
+            let func_new = require(
+                "abi_new",
+                get_symbol!(
+                    the_lib,
+                    b"abi_new",
                     extern "C" fn(
                         cta_engine: *const VTable,
                         strategy_name: *const c_char,
                         vt_symbol: *const c_char,
                         setting: *const c_char,
-                    ) -> *mut CtaTemplate,
-                >,
-            >(the_lib.get(b"abi_new").unwrap());
-            let func_on_init = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate, usize)>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, usize)>,
-            >(the_lib.get(b"abi_on_init").unwrap());
-            let func_on_start = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate)>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate)>,
-            >(the_lib.get(b"abi_on_start").unwrap());
-            let func_on_stop = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate)>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate)>,
-            >(the_lib.get(b"abi_on_stop").unwrap());
-            let func_on_tick = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate, *const TickData)>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, *const TickData)>,
-            >(the_lib.get(b"abi_on_tick").unwrap());
-            let func_on_bar = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate, *const BarData)>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, *const BarData)>,
-            >(the_lib.get(b"abi_on_bar").unwrap());
-            let func_on_order = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate, *const OrderData)>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, *const OrderData)>,
-            >(the_lib.get(b"abi_on_order").unwrap());
-            let func_on_trade = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate, *const TradeData)>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, *const TradeData)>,
-            >(the_lib.get(b"abi_on_trade").unwrap());
-            let func_on_stop_order = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate, *const StopOrder)>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate, *const StopOrder)>,
-            >(the_lib.get(b"abi_on_stop_order").unwrap());
-            let func_get_inited_mut = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate) -> *mut bool>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate) -> *mut bool>,
-            >(the_lib.get(b"abi_get_inited_mut").unwrap());
-            let func_get_trading_mut = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate) -> *mut bool>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate) -> *mut bool>,
-            >(the_lib.get(b"abi_get_trading_mut").unwrap());
-            let func_get_pos_mut = std::mem::transmute::<
-                libloading::Symbol<'_, unsafe extern "C" fn(*mut CtaTemplate) -> *mut f64>,
-                libloading::Symbol<'static, extern "C" fn(*mut CtaTemplate) -> *mut f64>,
-            >(the_lib.get(b"abi_get_pos_mut").unwrap());
-
-            ExternClass {
+                    ) -> *mut CtaTemplate
+                ),
+                &mut missing,
+            );
+            let func_drop = require(
+                "abi_drop",
+                get_symbol!(the_lib, b"abi_drop", extern "C" fn(*mut CtaTemplate)),
+                &mut missing,
+            );
+            let func_on_init = require(
+                "abi_on_init",
+                get_symbol!(
+                    the_lib,
+                    b"abi_on_init",
+                    extern "C" fn(*mut CtaTemplate, usize)
+                ),
+                &mut missing,
+            );
+            let func_on_start = require(
+                "abi_on_start",
+                get_symbol!(the_lib, b"abi_on_start", extern "C" fn(*mut CtaTemplate)),
+                &mut missing,
+            );
+            let func_on_stop = require(
+                "abi_on_stop",
+                get_symbol!(the_lib, b"abi_on_stop", extern "C" fn(*mut CtaTemplate)),
+                &mut missing,
+            );
+            let func_on_bar = require(
+                "abi_on_bar",
+                get_symbol!(
+                    the_lib,
+                    b"abi_on_bar",
+                    extern "C" fn(*mut CtaTemplate, *const BarData)
+                ),
+                &mut missing,
+            );
+            let func_get_inited_mut = require(
+                "abi_get_inited_mut",
+                get_symbol!(
+                    the_lib,
+                    b"abi_get_inited_mut",
+                    extern "C" fn(*mut CtaTemplate) -> *mut bool
+                ),
+                &mut missing,
+            );
+            let func_get_trading_mut = require(
+                "abi_get_trading_mut",
+                get_symbol!(
+                    the_lib,
+                    b"abi_get_trading_mut",
+                    extern "C" fn(*mut CtaTemplate) -> *mut bool
+                ),
+                &mut missing,
+            );
+
+            let func_on_tick = get_symbol!(
+                the_lib,
+                b"abi_on_tick",
+                extern "C" fn(*mut CtaTemplate, *const TickData)
+            )
+            .ok();
+            let func_on_order = get_symbol!(
+                the_lib,
+                b"abi_on_order",
+                extern "C" fn(*mut CtaTemplate, *const OrderData)
+            )
+            .ok();
+            let func_on_trade = get_symbol!(
+                the_lib,
+                b"abi_on_trade",
+                extern "C" fn(*mut CtaTemplate, *const TradeData)
+            )
+            .ok();
+            let func_on_stop_order = get_symbol!(
+                the_lib,
+                b"abi_on_stop_order",
+                extern "C" fn(*mut CtaTemplate, *const StopOrder)
+            )
+            .ok();
+            let func_on_timer =
+                get_symbol!(the_lib, b"abi_on_timer", extern "C" fn(*mut CtaTemplate)).ok();
+            let func_get_pos_mut = get_symbol!(
+                the_lib,
+                b"abi_get_pos_mut",
+                extern "C" fn(*mut CtaTemplate) -> *mut f64
+            )
+            .ok();
+            let func_get_parameters = get_symbol!(
+                the_lib,
+                b"abi_get_parameters",
+                extern "C" fn() -> *const c_char
+            )
+            .ok();
+            let func_get_variables = get_symbol!(
+                the_lib,
+                b"abi_get_variables",
+                extern "C" fn(*mut CtaTemplate) -> *const c_char
+            )
+            .ok();
+            let func_set_variables = get_symbol!(
+                the_lib,
+                b"abi_set_variables",
+                extern "C" fn(*mut CtaTemplate, *const c_char)
+            )
+            .ok();
+            let func_update_setting = get_symbol!(
+                the_lib,
+                b"abi_update_setting",
+                extern "C" fn(*mut CtaTemplate, *const c_char) -> bool
+            )
+            .ok();
+            let func_reset = get_symbol!(
+                the_lib,
+                b"abi_reset",
+                extern "C" fn(
+                    *mut CtaTemplate,
+                    *const VTable,
+                    *const c_char,
+                    *const c_char,
+                    *const c_char,
+                ) -> bool
+            )
+            .ok();
+
+            if !missing.is_empty() {
+                return Err(StrategyLoadError::AbiIncomplete(
+                    filename.as_ref().to_owned(),
+                    missing,
+                ));
+            }
+
+            Ok(ExternClass {
                 filename: filename.as_ref().to_owned(),
                 lib: Some(the_lib),
-                func_new: Some(func_new),
-                func_on_init: Some(func_on_init),
-                func_on_start: Some(func_on_start),
-                func_on_stop: Some(func_on_stop),
-                func_on_tick: Some(func_on_tick),
-                func_on_bar: Some(func_on_bar),
-                func_on_order: Some(func_on_order),
-                func_on_trade: Some(func_on_trade),
-                func_on_stop_order: Some(func_on_stop_order),
-                func_get_inited_mut: Some(func_get_inited_mut),
-                func_get_trading_mut: Some(func_get_trading_mut),
-                func_get_pos_mut: Some(func_get_pos_mut),
+                func_new,
+                func_drop,
+                func_on_init,
+                func_on_start,
+                func_on_stop,
+                func_on_tick,
+                func_on_bar,
+                func_on_order,
+                func_on_trade,
+                func_on_stop_order,
+                func_on_timer,
+                func_get_inited_mut,
+                func_get_trading_mut,
+                func_get_pos_mut,
+                func_get_parameters,
+                func_get_variables,
+                func_set_variables,
+                func_update_setting,
+                func_reset,
+                pool: Mutex::default(),
+                reset_refused: AtomicBool::new(false),
+            })
+        }
+    }
+
+    ///Class of the strategy dylib filename, loaded once per process and shared by every later
+    ///call, e.g. by the backtests of an optimization. A dylib rebuilt since is only picked up
+    ///by a new process.
+    pub fn load<P: AsRef<OsStr>>(filename: P) -> Result<Arc<Self>, StrategyLoadError> {
+        static CLASSES: OnceLock<Mutex<HashMap<OsString, Arc<ExternClass>>>> = OnceLock::new();
+        let mut classes = CLASSES.get_or_init(Default::default).lock().unwrap();
+        if let Some(class) = classes.get(filename.as_ref()) {
+            return Ok(class.clone());
+        }
+        let class = Arc::new(ExternClass::new(filename.as_ref())?);
+        classes.insert(filename.as_ref().to_owned(), class.clone());
+        Ok(class)
+    }
+
+    ///Instance from the pool reset with the arguments of abi_new, None if there is none or it
+    ///refuses.
+    fn take_pooled(
+        &self,
+        cta_engine: *const VTable,
+        strategy_name: &CStr,
+        vt_symbol: &CStr,
+        setting: &CStr,
+    ) -> Option<*mut CtaTemplate> {
+        let func_reset = self.func_reset?;
+        let PooledInstance(instance) = self.pool.lock().unwrap().pop()?;
+        if func_reset(
+            instance,
+            cta_engine,
+            strategy_name.as_ptr(),
+            vt_symbol.as_ptr(),
+            setting.as_ptr(),
+        ) {
+            return Some(instance);
+        }
+        self.reset_refused.store(true, Ordering::Relaxed);
+        if let Some(func_drop) = &self.func_drop {
+            func_drop(instance);
+        }
+        None
+    }
+
+    ///Keep instance for take_pooled if the strategy can be reset, drop it otherwise.
+    fn release(&self, instance: *mut CtaTemplate) {
+        if self.reuses_instances() {
+            self.pool.lock().unwrap().push(PooledInstance(instance));
+        } else if let Some(func_drop) = &self.func_drop {
+            func_drop(instance);
+        }
+    }
+
+    ///Whether released instances are reset for reuse, until the strategy refuses a reset.
+    pub fn reuses_instances(&self) -> bool {
+        self.func_reset.is_some() && !self.reset_refused.load(Ordering::Relaxed)
+    }
+
+    ///Instances waiting in the pool.
+    pub fn pooled(&self) -> usize {
+        self.pool.lock().unwrap().len()
+    }
+
+    ///FNV-1a hash of the strategy library in hex, empty if the file can't be read.
+    pub fn file_hash(&self) -> String {
+        let Ok(bytes) = std::fs::read(&self.filename) else {
+            return String::new();
+        };
+        let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        format!("{:016x}", hash)
+    }
+
+    ///Default parameters of the strategy class, empty if the dylib doesn't export abi_get_parameters.
+    pub fn get_parameters(&self) -> Vec<(String, String)> {
+        match &self.func_get_parameters {
+            Some(func) => {
+                let setting = unsafe { from_c_str(func()) };
+                parse_setting(&setting)
             }
+            None => Vec::new(),
         }
     }
 }
 
-impl Drop for ExternClass {
-    fn drop(&mut self) {
-        println!("class dropped");
+///Strings crossing the strategy ABI follow one ownership convention:
+///- `*const c_char` arguments are NUL terminated and only borrowed for the duration of the
+///  call, a strategy copies them to keep them.
+///- Strings returned by a strategy stay owned by it, valid until its next call.
+///- Values boxed by the host, like the Vec<String> from abi_send_order, are released
+///  through the matching abi_drop_* callback.
+pub fn to_c_string(s: &str) -> CString {
+    // Interior NUL would silently truncate the string on the other side, drop it instead
+    CString::new(s.replace('\0', "")).unwrap()
+}
+
+///Copy a string borrowed from the other side of the ABI, empty for a null pointer.
+///
+///# Safety
+///The pointer must be null or point to a NUL terminated string valid for the call.
+pub unsafe fn from_c_str(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
+
+///Parse a "name:value,name:value" strategy setting string.
+pub fn parse_setting(setting: &str) -> Vec<(String, String)> {
+    setting
+        .split(',')
+        .filter_map(|item| item.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+///Check an update of a running strategy against the default parameters of its class: every
+///name must be a parameter and numbers and booleans must stay numbers and booleans.
+pub fn validate_setting(
+    parameters: &[(String, String)],
+    update: &[(String, String)],
+) -> VnrsResult<()> {
+    if update.is_empty() {
+        return Err(VnrsError::Setting("empty setting update".to_string()));
     }
+    for (name, value) in update {
+        let Some((_, default)) = parameters.iter().find(|(parameter, _)| parameter == name) else {
+            return Err(VnrsError::Setting(format!("unknown parameter {}", name)));
+        };
+        let same_kind = if default.parse::<f64>().is_ok() {
+            value.parse::<f64>().is_ok()
+        } else if default.parse::<bool>().is_ok() {
+            value.parse::<bool>().is_ok()
+        } else {
+            true
+        };
+        if !same_kind {
+            return Err(VnrsError::Setting(format!(
+                "invalid value {} of parameter {}, default {}",
+                value, name, default
+            )));
+        }
+    }
+    Ok(())
+}
+
+///Format parameters back into a "name:value,name:value" strategy setting string.
+pub fn format_setting(parameters: &[(String, String)]) -> String {
+    parameters
+        .iter()
+        .map(|(name, value)| format!("{}:{}", name, value))
+        .collect::<Vec<String>>()
+        .join(",")
 }
 
+#[cfg(feature = "dylib-strategies")]
 #[derive(Default)]
 pub struct ExternInstance {
     class: Arc<ExternClass>,
@@ -222,6 +687,7 @@ pub struct ExternInstance {
     pub strategy_name: String,
 }
 
+#[cfg(feature = "dylib-strategies")]
 impl ExternInstance {
     pub fn new(
         class: Arc<ExternClass>,
@@ -229,75 +695,197 @@ impl ExternInstance {
         strategy_name: String,
         vt_symbol: &str,
         setting: &str,
-    ) -> Self {
-        let inst;
-        inst = class.func_new.as_ref().unwrap()(
+    ) -> VnrsResult<Self> {
+        let Some(func_new) = &class.func_new else {
+            return Err(VnrsError::Ffi(format!(
+                "strategy {:?} has no abi_new",
+                class.filename
+            )));
+        };
+        // Kept alive until abi_new returns, the strategy copies what it needs.
+        let c_strategy_name = to_c_string(&strategy_name);
+        let c_vt_symbol = to_c_string(vt_symbol);
+        let c_setting = to_c_string(setting);
+        if let Some(inst) =
+            class.take_pooled(cta_engine, &c_strategy_name, &c_vt_symbol, &c_setting)
+        {
+            return Ok(ExternInstance {
+                class: class.clone(),
+                instance: Some(inst),
+                strategy_name,
+            });
+        }
+        let inst = func_new(
             cta_engine,
-            CString::new(strategy_name.clone()).unwrap().as_ptr(),
-            CString::new(vt_symbol).unwrap().as_ptr(),
-            CString::new(setting).unwrap().as_ptr(),
+            c_strategy_name.as_ptr(),
+            c_vt_symbol.as_ptr(),
+            c_setting.as_ptr(),
         );
-        ExternInstance {
+        if inst.is_null() {
+            return Err(VnrsError::Ffi(format!(
+                "abi_new of strategy {} returned null",
+                strategy_name
+            )));
+        }
+        Ok(ExternInstance {
             class: class.clone(),
             instance: Some(inst),
             strategy_name: strategy_name,
+        })
+    }
+
+    ///Instance created by the host rather than by abi_new of class, e.g. from a strategy value
+    ///by sdk::abi::new_with.
+    ///
+    ///# Safety
+    ///instance must be a strategy of class, released by its func_drop.
+    pub unsafe fn from_raw(
+        class: Arc<ExternClass>,
+        instance: *mut CtaTemplate,
+        strategy_name: String,
+    ) -> Self {
+        ExternInstance {
+            class,
+            instance: (!instance.is_null()).then_some(instance),
+            strategy_name,
         }
     }
 
+    ///Whether a strategy instance was created, callbacks below are no-ops otherwise.
+    pub fn is_loaded(&self) -> bool {
+        self.instance.is_some()
+    }
+
     pub fn on_init(&self, cta_engine_ptr: usize) {
-        self.class.func_on_init.as_ref().unwrap()(self.instance.unwrap(), cta_engine_ptr)
+        if let (Some(func), Some(instance)) = (&self.class.func_on_init, self.instance) {
+            func(instance, cta_engine_ptr)
+        }
     }
 
     pub fn on_start(&self) {
-        self.class.func_on_start.as_ref().unwrap()(self.instance.unwrap())
+        if let (Some(func), Some(instance)) = (&self.class.func_on_start, self.instance) {
+            func(instance)
+        }
     }
 
     pub fn on_stop(&self) {
-        self.class.func_on_stop.as_ref().unwrap()(self.instance.unwrap())
+        if let (Some(func), Some(instance)) = (&self.class.func_on_stop, self.instance) {
+            func(instance)
+        }
     }
 
     pub fn on_tick(&self, tick: &TickData) {
-        self.class.func_on_tick.as_ref().unwrap()(self.instance.unwrap(), tick)
+        if let (Some(func), Some(instance)) = (&self.class.func_on_tick, self.instance) {
+            func(instance, tick)
+        }
     }
 
     pub fn on_bar(&self, bar: &BarData) {
-        self.class.func_on_bar.as_ref().unwrap()(self.instance.unwrap(), bar)
+        if let (Some(func), Some(instance)) = (&self.class.func_on_bar, self.instance) {
+            func(instance, bar)
+        }
     }
 
     pub fn on_order(&self, order: &OrderData) {
-        self.class.func_on_order.as_ref().unwrap()(self.instance.unwrap(), order)
+        if let (Some(func), Some(instance)) = (&self.class.func_on_order, self.instance) {
+            func(instance, order)
+        }
     }
 
     pub fn on_trade(&self, trade: &TradeData) {
-        self.class.func_on_trade.as_ref().unwrap()(self.instance.unwrap(), trade)
+        if let (Some(func), Some(instance)) = (&self.class.func_on_trade, self.instance) {
+            func(instance, trade)
+        }
     }
 
     pub fn on_stop_order(&self, stop_order: &StopOrder) {
-        self.class.func_on_stop_order.as_ref().unwrap()(self.instance.unwrap(), stop_order)
+        if let (Some(func), Some(instance)) = (&self.class.func_on_stop_order, self.instance) {
+            func(instance, stop_order)
+        }
+    }
+
+    pub fn on_timer(&self) {
+        if let (Some(func), Some(instance)) = (&self.class.func_on_timer, self.instance) {
+            func(instance)
+        }
     }
 
-    pub fn get_inited_mut(&self) -> &mut bool {
-        unsafe { &mut *self.class.func_get_inited_mut.as_ref().unwrap()(self.instance.unwrap()) }
+    ///Mark the strategy inited once on_init and warmup are done.
+    pub fn set_inited(&self, inited: bool) {
+        if let (Some(func), Some(instance)) = (&self.class.func_get_inited_mut, self.instance) {
+            unsafe { *func(instance) = inited };
+        }
     }
 
-    pub fn get_trading_mut(&self) -> &mut bool {
-        unsafe { &mut *self.class.func_get_trading_mut.as_ref().unwrap()(self.instance.unwrap()) }
+    ///Stop or resume sending orders from the strategy, a no-op without a loaded strategy.
+    pub fn set_trading(&self, trading: bool) {
+        if let (Some(func), Some(instance)) = (&self.class.func_get_trading_mut, self.instance) {
+            unsafe { *func(instance) = trading };
+        }
     }
 
-    pub fn get_pos_mut(&self) -> &mut f64 {
-        unsafe { &mut *self.class.func_get_pos_mut.as_ref().unwrap()(self.instance.unwrap()) }
+    ///Mirror the engine position into the strategy, a no-op if the dylib doesn't export
+    ///abi_get_pos_mut.
+    pub fn set_pos(&self, pos: f64) {
+        if let (Some(func), Some(instance)) = (&self.class.func_get_pos_mut, self.instance) {
+            unsafe { *func(instance) = pos };
+        }
+    }
+
+    ///Current variables of the strategy, empty if the dylib doesn't export abi_get_variables.
+    pub fn get_variables(&self) -> Vec<(String, String)> {
+        match (&self.class.func_get_variables, self.instance) {
+            (Some(func), Some(instance)) => {
+                let variables = unsafe { from_c_str(func(instance)) };
+                parse_setting(&variables)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    ///Restore variables of the strategy, ignored if the dylib doesn't export abi_set_variables.
+    pub fn set_variables(&self, variables: &[(String, String)]) {
+        if let (Some(func), Some(instance)) = (&self.class.func_set_variables, self.instance) {
+            let variables = to_c_string(&format_setting(variables));
+            func(instance, variables.as_ptr());
+        }
+    }
+
+    ///Apply new parameters to the running strategy, false if the dylib doesn't export
+    ///abi_update_setting or the strategy rejects them.
+    pub fn update_setting(&self, setting: &[(String, String)]) -> bool {
+        match (&self.class.func_update_setting, self.instance) {
+            (Some(func), Some(instance)) => {
+                let setting = to_c_string(&format_setting(setting));
+                func(instance, setting.as_ptr())
+            }
+            _ => false,
+        }
     }
 }
 
+#[cfg(feature = "dylib-strategies")]
+// The strategy instance is only called from the engine owning it, so it may move with the engine
+// into another thread, but must not be called from two threads at once.
+unsafe impl Send for ExternInstance {}
+
+#[cfg(feature = "dylib-strategies")]
 impl Drop for ExternInstance {
     fn drop(&mut self) {
-        if self.class.lib.is_none() {
-            return;
+        if let Some(instance) = self.instance {
+            self.class.release(instance);
         }
-        unsafe {
-            let func_drop: libloading::Symbol<unsafe extern "C" fn(*mut CtaTemplate)> =
-                self.class.lib.as_ref().unwrap().get(b"abi_drop").unwrap();
-            func_drop(self.instance.unwrap());
+    }
+}
+
+#[cfg(feature = "dylib-strategies")]
+impl Drop for ExternClass {
+    fn drop(&mut self) {
+        // Before the library is unloaded with the fields
+        if let Some(func_drop) = &self.func_drop {
+            for PooledInstance(instance) in self.pool.get_mut().unwrap().drain(..) {
+                func_drop(instance);
+            }
         }
     }
 }
@@ -305,15 +893,15 @@ impl Drop for ExternInstance {
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct VTable {
-    pub abi_load_bar: extern "C" fn(
+    pub abi_load_bar: unsafe extern "C" fn(
         usize,
         *const c_char,
         i64,
-        Interval,
+        IntervalKind,
         // Callable,
         bool,
     ) -> *mut Vec<BarData>,
-    pub abi_drop_vec_bar_data: extern "C" fn(vec: *mut Vec<BarData>),
+    pub abi_drop_vec_bar_data: unsafe extern "C" fn(vec: *mut Vec<BarData>),
     pub abi_send_order: extern "C" fn(
         usize,
         *mut CtaTemplate,
@@ -325,6 +913,339 @@ pub struct VTable {
         bool,
         bool,
     ) -> *mut Vec<String>,
-    pub abi_drop_vec_string: extern "C" fn(vec: *mut Vec<String>),
+    pub abi_drop_vec_string: unsafe extern "C" fn(vec: *mut Vec<String>),
     pub abi_cancel_all: extern "C" fn(this: usize, strategy: *mut CtaTemplate),
+    pub abi_get_pos: extern "C" fn(this: usize, strategy: *mut CtaTemplate) -> f64,
+    pub abi_get_position_detail:
+        extern "C" fn(this: usize, strategy: *mut CtaTemplate) -> PositionDetail,
+    pub abi_get_pricetick: extern "C" fn(this: usize, strategy: *mut CtaTemplate) -> f64,
+    pub abi_get_size: extern "C" fn(this: usize, strategy: *mut CtaTemplate) -> f64,
+    pub abi_get_engine_type: extern "C" fn(this: usize) -> EngineType,
+    ///Null if there's no such stop order, else released with abi_drop_stop_order.
+    pub abi_get_stop_order: unsafe extern "C" fn(
+        this: usize,
+        strategy: *mut CtaTemplate,
+        *const c_char,
+    ) -> *mut StopOrder,
+    pub abi_drop_stop_order: unsafe extern "C" fn(stop_order: *mut StopOrder),
+    ///Null if there's no such order, else released with abi_drop_order_data.
+    pub abi_get_order: unsafe extern "C" fn(
+        this: usize,
+        strategy: *mut CtaTemplate,
+        *const c_char,
+    ) -> *mut OrderData,
+    pub abi_drop_order_data: unsafe extern "C" fn(order: *mut OrderData),
+    pub abi_get_trades_by_order: unsafe extern "C" fn(
+        this: usize,
+        strategy: *mut CtaTemplate,
+        *const c_char,
+    ) -> *mut Vec<TradeData>,
+    pub abi_drop_vec_trade_data: unsafe extern "C" fn(vec: *mut Vec<TradeData>),
+    ///Record the value of a named factor on the current bar, ignored outside research mode.
+    pub abi_emit_factor:
+        unsafe extern "C" fn(this: usize, strategy: *mut CtaTemplate, *const c_char, f64),
+    // New entries go last, strategies built against an older VTable keep the offsets above
+    ///Specification of a contract by vt_symbol, the traded one for an empty vt_symbol. Null if
+    ///it's unknown, else released with abi_drop_contract_data.
+    pub abi_get_contract: unsafe extern "C" fn(this: usize, *const c_char) -> *mut ContractData,
+    pub abi_drop_contract_data: unsafe extern "C" fn(contract: *mut ContractData),
+    ///Send an entry limit order with the take profit and stop loss prices of its exit legs,
+    ///returns the vt_orderids of the entry.
+    pub abi_send_bracket_order: unsafe extern "C" fn(
+        usize,
+        *mut CtaTemplate,
+        Direction,
+        f64,
+        f64,
+        f64,
+        f64,
+        bool,
+        bool,
+        *const c_char,
+    ) -> *mut Vec<String>,
+    ///Cancel a limit or stop order by vt_orderid, ignored if it's no longer active.
+    pub abi_cancel_order:
+        unsafe extern "C" fn(this: usize, strategy: *mut CtaTemplate, *const c_char),
+    ///Set the time in force of a resting limit or stop order, expiry is the GTD datetime as
+    ///milliseconds since the epoch and ignored otherwise.
+    pub abi_set_expiry: unsafe extern "C" fn(
+        this: usize,
+        strategy: *mut CtaTemplate,
+        *const c_char,
+        TimeInForce,
+        i64,
+    ),
+    ///Like abi_load_bar, but the bars are pushed into the strategy's on_bar once on_init
+    ///returns.
+    pub abi_load_bar_callback:
+        unsafe extern "C" fn(usize, *const c_char, i64, IntervalKind, u32, bool),
+    ///Like abi_send_order, tagging the orders with a reference copied to their OrderData and
+    ///TradeData.
+    pub abi_send_order_with_reference: unsafe extern "C" fn(
+        usize,
+        *mut CtaTemplate,
+        Direction,
+        Offset,
+        f64,
+        f64,
+        bool,
+        bool,
+        bool,
+        *const c_char,
+    ) -> *mut Vec<String>,
+    ///Like abi_load_bar, with the size of the seconds, renko, range and volume intervals after
+    ///their kind.
+    pub abi_load_bar_sized: unsafe extern "C" fn(
+        usize,
+        *const c_char,
+        i64,
+        IntervalKind,
+        u32,
+        bool,
+    ) -> *mut Vec<BarData>,
+}
+
+///Long/short split of the position booked by the engine, returned by value over the ABI.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PositionDetail {
+    ///Net position, long minus short.
+    pub pos: f64,
+
+    pub long_pos: f64,
+    pub long_yd: f64,
+    pub long_td: f64,
+
+    pub short_pos: f64,
+    pub short_yd: f64,
+    pub short_td: f64,
+
+    ///Volume held by active close orders.
+    pub long_pos_frozen: f64,
+    pub short_pos_frozen: f64,
+}
+
+impl PositionDetail {
+    pub fn from_holding(pos: f64, holding: &PositionHolding) -> Self {
+        PositionDetail {
+            pos,
+            long_pos: holding.long_pos,
+            long_yd: holding.long_yd,
+            long_td: holding.long_td,
+            short_pos: holding.short_pos,
+            short_yd: holding.short_yd,
+            short_td: holding.short_td,
+            long_pos_frozen: holding.long_pos_frozen,
+            short_pos_frozen: holding.short_pos_frozen,
+        }
+    }
+
+    ///Detail of a net position contract, all volume counted as today.
+    pub fn from_net(pos: f64) -> Self {
+        let long_pos = pos.max(0.0);
+        let short_pos = (-pos).max(0.0);
+        PositionDetail {
+            pos,
+            long_pos,
+            long_td: long_pos,
+            short_pos,
+            short_td: short_pos,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+///Engine handle passed to strategies, heap allocated so that its address stays valid
+///when the engine moves. VTable callbacks receive the handle back as `this`. The VTable is
+///the first field, so the handle equals the VTable pointer given to abi_new.
+#[repr(C)]
+pub struct EngineContext {
+    pub v_table: VTable,
+    engine: *mut BacktestingEngine,
+}
+
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+// The engine pointer is only dereferenced by callbacks made on the thread running the engine.
+unsafe impl Send for EngineContext {}
+
+#[cfg(all(feature = "backtest-stats", feature = "dylib-strategies"))]
+impl EngineContext {
+    pub fn new(v_table: VTable) -> Box<Self> {
+        Box::new(EngineContext {
+            v_table,
+            engine: std::ptr::null_mut(),
+        })
+    }
+
+    ///Point the context at the engine, before calling into the strategy.
+    pub fn bind(&mut self, engine: *mut BacktestingEngine) {
+        self.engine = engine;
+    }
+
+    ///Handle given to the strategy in on_init.
+    pub fn handle(&self) -> usize {
+        self as *const EngineContext as usize
+    }
+
+    ///Engine bound to the context behind a handle.
+    ///
+    ///# Safety
+    ///The handle must come from a live context whose engine is running a strategy callback.
+    pub unsafe fn engine<'a>(handle: usize) -> &'a mut BacktestingEngine {
+        let context = &*(handle as *const EngineContext);
+        &mut *context.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn c_string_round_trip() {
+        let c_string = to_c_string("IF888.CFFEX");
+        assert_eq!(unsafe { from_c_str(c_string.as_ptr()) }, "IF888.CFFEX");
+
+        let c_string = to_c_string("中文策略");
+        assert_eq!(unsafe { from_c_str(c_string.as_ptr()) }, "中文策略");
+    }
+
+    #[test]
+    fn c_string_drops_interior_nul() {
+        let c_string = to_c_string("fast_window:10\0,slow_window:20");
+        assert_eq!(
+            unsafe { from_c_str(c_string.as_ptr()) },
+            "fast_window:10,slow_window:20"
+        );
+    }
+
+    #[test]
+    fn c_str_null_is_empty() {
+        assert_eq!(unsafe { from_c_str(std::ptr::null()) }, "");
+    }
+
+    #[test]
+    fn c_str_is_copied() {
+        // The copy stays valid after the borrowed string is released
+        let c_string = to_c_string("setting");
+        let copied = unsafe { from_c_str(c_string.as_ptr()) };
+        drop(c_string);
+        assert_eq!(copied, "setting");
+    }
+
+    #[cfg(feature = "dylib-strategies")]
+    #[test]
+    fn released_instances_are_reset() {
+        use crate::vnrs_ctastrategy::sdk::{
+            setting_value, strategy_class, CtaStrategy, StrategyContext,
+        };
+        use std::sync::atomic::AtomicUsize;
+
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+        struct Window(usize, bool);
+        impl CtaStrategy for Window {
+            fn new(_context: &StrategyContext, setting: &[(String, String)]) -> Self {
+                CREATED.fetch_add(1, Ordering::SeqCst);
+                Window(setting_value(setting, "window", 0), true)
+            }
+            fn reset(&mut self, _context: &StrategyContext, setting: &[(String, String)]) -> bool {
+                self.0 = setting_value(setting, "window", 0);
+                self.1
+            }
+            fn on_bar(&mut self, _context: &mut StrategyContext, _bar: &BarData) {}
+            fn variables(&self) -> Vec<(String, String)> {
+                vec![("window".to_string(), self.0.to_string())]
+            }
+        }
+
+        let class = Arc::new(strategy_class::<Window>("window"));
+        let new = |setting: &str| {
+            ExternInstance::new(
+                class.clone(),
+                std::ptr::null(),
+                "window".to_string(),
+                "IF888.CFFEX",
+                setting,
+            )
+            .unwrap()
+        };
+        let window = |instance: &ExternInstance| instance.get_variables()[0].1.clone();
+
+        drop(new("window:5"));
+        assert_eq!(class.pooled(), 1);
+        let first = new("window:10");
+        assert_eq!(window(&first), "10");
+        // Only released instances are reused
+        let second = new("window:20");
+        assert_eq!(CREATED.load(Ordering::SeqCst), 2);
+        drop(first);
+        drop(second);
+        assert_eq!(class.pooled(), 2);
+    }
+
+    #[cfg(feature = "dylib-strategies")]
+    #[test]
+    fn strategies_resolved_by_name_only() {
+        let dir = std::env::temp_dir().join(format!("vnrs_strategies_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dylib = dir.join(libloading::library_filename("double_ma_strategy"));
+        std::fs::write(&dylib, b"").unwrap();
+        let strategies = StrategyDirectory::new(&dir);
+
+        assert_eq!(strategies.resolve("double_ma_strategy").unwrap(), dylib);
+        assert!(strategies.resolve("atr_rsi_strategy").is_err());
+        for name in ["", "../double_ma_strategy", "/tmp/evil", "a.so", "a\\b"] {
+            assert!(strategies.resolve(name).is_err(), "{} resolved", name);
+        }
+        assert_eq!(
+            strategy_name(&dylib.to_string_lossy()),
+            "double_ma_strategy"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ffi_layout() {
+        use std::mem::{offset_of, size_of};
+
+        // Enums passed by value are C ints with the values of the baseline ABI
+        for size in [
+            size_of::<IntervalKind>(),
+            size_of::<Direction>(),
+            size_of::<Offset>(),
+            size_of::<EngineType>(),
+            size_of::<TimeInForce>(),
+        ] {
+            assert_eq!(size, size_of::<std::ffi::c_int>());
+        }
+        assert_eq!(IntervalKind::MINUTE as i32, 1);
+        assert_eq!(IntervalKind::TICK as i32, 5);
+        assert_eq!(Direction::SHORT as i32, 2);
+        assert_eq!(Offset::CLOSEYESTERDAY as i32, 4);
+        assert_eq!(EngineType::BACKTESTING as i32, 1);
+
+        assert_eq!(size_of::<PositionDetail>(), 9 * size_of::<f64>());
+
+        // Entries are appended, the ones strategies were built against keep their offsets
+        let entry = size_of::<usize>();
+        assert_eq!(size_of::<VTable>(), 25 * entry);
+        assert_eq!(offset_of!(VTable, abi_load_bar), 0);
+        assert_eq!(offset_of!(VTable, abi_send_order), 2 * entry);
+        assert_eq!(offset_of!(VTable, abi_get_trades_by_order), 14 * entry);
+        assert_eq!(offset_of!(VTable, abi_load_bar_callback), 22 * entry);
+        assert_eq!(offset_of!(VTable, abi_load_bar_sized), 24 * entry);
+    }
+
+    #[test]
+    fn setting_updates_are_validated() {
+        let parameters = parse_setting("fast_window:10,trailing:true,mode:close");
+        let valid = |setting: &str| validate_setting(&parameters, &parse_setting(setting)).is_ok();
+        assert!(valid("fast_window:12.5,trailing:false"));
+        assert!(valid("mode:open"));
+        assert!(!valid(""));
+        assert!(!valid("slow_window:20"));
+        assert!(!valid("fast_window:ten"));
+        assert!(!valid("trailing:1"));
+    }
 }
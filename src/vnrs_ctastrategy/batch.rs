@@ -0,0 +1,568 @@
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::fs::File;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::backtesting::{BacktestingEngine, BacktestingStatistics};
+use super::base::{BacktestingMode, ExternClass};
+use super::contract::ContractRegistry;
+use super::portfolio::SymbolDaily;
+use crate::vnrs::trader::cache::BarCache;
+use crate::vnrs::trader::constant::Interval;
+
+///One backtest to be executed by the BatchRunner.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchJob {
+    pub vt_symbol: String,
+    pub interval: Interval,
+    pub strategy: String, // filename of the strategy dylib
+    pub setting: String,
+    ///Net position carried into the backtest, see BacktestingEngine::set_parameters.
+    pub initial_pos: f64,
+    ///Start and end of the backtest, the ones of the runner if None.
+    pub period: Option<(NaiveDateTime, NaiveDateTime)>,
+}
+
+impl BatchJob {
+    pub fn new(vt_symbol: &str, interval: Interval, strategy: &str, setting: &str) -> Self {
+        BatchJob {
+            vt_symbol: vt_symbol.to_string(),
+            interval,
+            strategy: strategy.to_string(),
+            setting: setting.to_string(),
+            initial_pos: 0.0,
+            period: None,
+        }
+    }
+}
+
+///Outcome of a BatchJob, failed jobs carry the error message instead of statistics.
+#[derive(Debug, Clone)]
+pub struct BatchResult {
+    pub job: BatchJob,
+    pub statistics: Result<BacktestingStatistics, String>,
+    ///Daily results of the job when the runner keeps them, for a PortfolioReport.
+    pub daily: Option<SymbolDaily>,
+    ///Time the backtest took.
+    pub duration: Duration,
+}
+
+///Statistics and kept daily results of a job, or the reason it failed.
+type JobOutcome = Result<(BacktestingStatistics, Option<SymbolDaily>), String>;
+
+///Run a list of backtests sharing the same parameters, optionally in parallel.
+///A failing job is recorded in its BatchResult and does not abort the batch.
+pub struct BatchRunner {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub rate: f64,
+    pub slippage: f64,
+    pub size: f64,
+    pub pricetick: f64,
+    pub capital: f64,
+    pub mode: BacktestingMode,
+    pub risk_free: f64,
+    ///Derived from the market of each job's symbol if None.
+    pub annual_days: Option<i64>,
+    pub half_life: i64,
+
+    jobs: Vec<BatchJob>,
+    max_workers: usize,
+    keep_daily_results: bool,
+    volume_impact: f64,
+    contract_registry: Option<Arc<ContractRegistry>>,
+    bar_cache: Option<Arc<BarCache>>,
+}
+
+impl Default for BatchRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BatchRunner {
+    pub fn new() -> Self {
+        BatchRunner {
+            start: NaiveDateTime::default(),
+            end: NaiveDateTime::default(),
+            rate: 0.0,
+            slippage: 0.0,
+            size: 1.0,
+            pricetick: 0.0,
+            capital: 1_000_000.0,
+            mode: BacktestingMode::BAR,
+            risk_free: 0.0,
+            annual_days: None,
+            half_life: 120,
+            jobs: Vec::new(),
+            max_workers: 1,
+            keep_daily_results: false,
+            volume_impact: 0.0,
+            contract_registry: None,
+            bar_cache: None,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_parameters(
+        &mut self,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        rate: f64,
+        slippage: f64,
+        size: f64,
+        pricetick: f64,
+        capital: f64,
+        mode: BacktestingMode,
+        risk_free: f64,
+        annual_days: Option<i64>,
+        half_life: i64,
+    ) {
+        self.start = start;
+        self.end = end;
+        self.rate = rate;
+        self.slippage = slippage;
+        self.size = size;
+        self.pricetick = pricetick;
+        self.capital = capital;
+        self.mode = mode;
+        self.risk_free = risk_free;
+        self.annual_days = annual_days;
+        self.half_life = half_life;
+    }
+
+    ///Contract specifications used by each job for its symbol, see
+    ///BacktestingEngine::set_contract_registry.
+    pub fn set_contract_registry(&mut self, registry: Arc<ContractRegistry>) {
+        self.contract_registry = Some(registry);
+    }
+
+    ///Bar cache shared by all jobs, see BacktestingEngine::set_bar_cache.
+    pub fn set_bar_cache(&mut self, cache: Arc<BarCache>) {
+        self.bar_cache = Some(cache);
+    }
+
+    ///Number of jobs executed concurrently, 1 runs the batch sequentially.
+    pub fn set_max_workers(&mut self, max_workers: usize) {
+        self.max_workers = max_workers.max(1);
+    }
+
+    pub fn max_workers(&self) -> usize {
+        self.max_workers
+    }
+
+    ///Keep the daily results of each job in its BatchResult, off by default to save memory.
+    pub fn set_keep_daily_results(&mut self, keep_daily_results: bool) {
+        self.keep_daily_results = keep_daily_results;
+    }
+
+    ///Market impact of the fills of each job, see BacktestingEngine::set_volume_impact.
+    pub fn set_volume_impact(&mut self, coefficient: f64) {
+        self.volume_impact = coefficient;
+    }
+
+    pub fn add_job(&mut self, job: BatchJob) {
+        self.jobs.push(job);
+    }
+
+    pub fn jobs(&self) -> &[BatchJob] {
+        &self.jobs
+    }
+
+    ///Execute all jobs, results are returned in the order the jobs were added.
+    pub fn run(&self) -> Vec<BatchResult> {
+        self.run_jobs(&self.jobs)
+    }
+
+    ///Execute jobs with the parameters of the runner instead of the jobs added to it, results
+    ///are returned in the order of jobs.
+    pub fn run_jobs(&self, jobs: &[BatchJob]) -> Vec<BatchResult> {
+        self.run_jobs_until(jobs, |_| true)
+    }
+
+    ///run_jobs passing each result to on_result as it finishes, no more jobs are started
+    ///once it returns false. Results of the jobs run are returned in the order of jobs.
+    pub fn run_jobs_until(
+        &self,
+        jobs: &[BatchJob],
+        on_result: impl Fn(&BatchResult) -> bool + Sync,
+    ) -> Vec<BatchResult> {
+        self.run_with(jobs, on_result, |job| self.run_job(job))
+    }
+
+    ///Run jobs through run_job on the workers, a panic of run_job fails its job only.
+    fn run_with(
+        &self,
+        jobs: &[BatchJob],
+        on_result: impl Fn(&BatchResult) -> bool + Sync,
+        run_job: impl Fn(&BatchJob) -> JobOutcome + Sync,
+    ) -> Vec<BatchResult> {
+        self.output(&format!(
+            "开始批量回测，任务数：{}，并行数：{}",
+            jobs.len(),
+            self.max_workers
+        ));
+
+        let results: Mutex<Vec<Option<BatchResult>>> = Mutex::new(vec![None; jobs.len()]);
+        let next_job = AtomicUsize::new(0);
+        let stopped = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            for _ in 0..self.max_workers.min(jobs.len()) {
+                scope.spawn(|| loop {
+                    let ix = next_job.fetch_add(1, Ordering::SeqCst);
+                    if ix >= jobs.len() || stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    let job = &jobs[ix];
+                    let started = Instant::now();
+                    let outcome = panic::catch_unwind(AssertUnwindSafe(|| run_job(job)))
+                        .map_err(panic_message)
+                        .and_then(|outcome| outcome);
+                    let (statistics, daily) = match outcome {
+                        Ok((statistics, daily)) => (Ok(statistics), daily),
+                        Err(msg) => (Err(msg), None),
+                    };
+                    if let Err(msg) = &statistics {
+                        self.output(&format!(
+                            "回测任务失败：{} {:?} {}，错误：{}",
+                            job.vt_symbol, job.interval, job.strategy, msg
+                        ));
+                    }
+
+                    let result = BatchResult {
+                        job: job.clone(),
+                        statistics,
+                        daily,
+                        duration: started.elapsed(),
+                    };
+                    if !on_result(&result) {
+                        stopped.store(true, Ordering::SeqCst);
+                    }
+                    results.lock().unwrap()[ix] = Some(result);
+                });
+            }
+        });
+
+        let results: Vec<BatchResult> = results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+        let failed = results.iter().filter(|r| r.statistics.is_err()).count();
+        self.output(&format!(
+            "批量回测完成，成功：{}，失败：{}",
+            results.len() - failed,
+            failed
+        ));
+        results
+    }
+
+    fn run_job(&self, job: &BatchJob) -> JobOutcome {
+        let strategy_class = ExternClass::load(&job.strategy).map_err(|e| e.to_string())?;
+        let mut engine = self.run_engine(job, strategy_class)?;
+        let statistics = engine.calculate_result_statistics(false);
+        let daily = self
+            .keep_daily_results
+            .then(|| SymbolDaily::from_engine(&job.vt_symbol, &engine));
+        Ok((statistics, daily))
+    }
+
+    ///Run the backtest of one job on the given strategy class and return the engine, before
+    ///any statistics are calculated.
+    pub(crate) fn run_engine(
+        &self,
+        job: &BatchJob,
+        strategy_class: Arc<ExternClass>,
+    ) -> Result<BacktestingEngine, String> {
+        let (start, end) = job.period.unwrap_or((self.start, self.end));
+        let mut engine = BacktestingEngine::new();
+        engine
+            .set_parameters(
+                &job.vt_symbol,
+                job.interval,
+                start,
+                end,
+                self.rate,
+                self.slippage,
+                self.size,
+                self.pricetick,
+                self.capital,
+                self.mode,
+                self.risk_free,
+                0,
+                self.half_life,
+            )
+            .map_err(|e| e.to_string())?;
+        engine.set_initial_pos(job.initial_pos);
+        engine.set_annual_days(self.annual_days);
+        if let Some(registry) = &self.contract_registry {
+            engine.set_contract_registry(registry.clone());
+        }
+        engine.set_volume_impact(self.volume_impact);
+        if let Some(cache) = &self.bar_cache {
+            engine.set_bar_cache(cache.clone());
+        }
+        engine
+            .add_strategy(strategy_class, &job.setting)
+            .map_err(|e| e.to_string())?;
+        engine.load_data().map_err(|e| e.to_string())?;
+        engine.run_backtesting().map_err(|e| e.to_string())?;
+        Ok(engine)
+    }
+
+    fn output(&self, msg: &str) {
+        println!("{datetime}\t{msg}", datetime = Local::now(), msg = msg);
+    }
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown error".to_string()
+    }
+}
+
+///Collect batch results into one summary DataFrame, one row per job.
+///Statistics columns of failed jobs are null and the error column holds the reason.
+pub fn summarize(results: &[BatchResult]) -> DataFrame {
+    let stats: Vec<Option<&BacktestingStatistics>> =
+        results.iter().map(|r| r.statistics.as_ref().ok()).collect();
+    let mut columns = vec![
+        Series::new(
+            "vt_symbol",
+            results
+                .iter()
+                .map(|r| r.job.vt_symbol.clone())
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "interval",
+            results
+                .iter()
+                .map(|r| r.job.interval.to_string())
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "strategy",
+            results
+                .iter()
+                .map(|r| r.job.strategy.clone())
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "setting",
+            results
+                .iter()
+                .map(|r| r.job.setting.clone())
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "error",
+            results
+                .iter()
+                .map(|r| r.statistics.as_ref().err().cloned())
+                .collect::<Vec<Option<String>>>(),
+        ),
+    ];
+    columns.extend(statistics_columns(&stats));
+    DataFrame::new(columns).unwrap()
+}
+
+///Statistics columns shared by summary and result exports, null where statistics is None.
+pub(crate) fn statistics_columns(stats: &[Option<&BacktestingStatistics>]) -> Vec<Series> {
+    let float_column = |name: &str, f: fn(&BacktestingStatistics) -> f64| {
+        Series::new(
+            name,
+            stats.iter().map(|s| s.map(f)).collect::<Vec<Option<f64>>>(),
+        )
+    };
+    let int_column = |name: &str, f: fn(&BacktestingStatistics) -> i64| {
+        Series::new(
+            name,
+            stats.iter().map(|s| s.map(f)).collect::<Vec<Option<i64>>>(),
+        )
+    };
+    let date_column = |name: &str, f: fn(&BacktestingStatistics) -> NaiveDate| {
+        Series::new(
+            name,
+            stats
+                .iter()
+                .map(|s| s.map(f))
+                .collect::<Vec<Option<NaiveDate>>>(),
+        )
+    };
+
+    vec![
+        date_column("start_date", |s| s.start_date),
+        date_column("end_date", |s| s.end_date),
+        int_column("total_days", |s| s.total_days),
+        int_column("profit_days", |s| s.profit_days),
+        int_column("loss_days", |s| s.loss_days),
+        float_column("capital", |s| s.capital),
+        float_column("end_balance", |s| s.end_balance),
+        float_column("max_drawdown", |s| s.max_drawdown),
+        float_column("max_ddpercent", |s| s.max_ddpercent),
+        int_column("max_drawdown_duration", |s| s.max_drawdown_duration),
+        float_column("total_net_pnl", |s| s.total_net_pnl),
+        float_column("daily_net_pnl", |s| s.daily_net_pnl),
+        float_column("total_commission", |s| s.total_commission),
+        float_column("daily_commission", |s| s.daily_commission),
+        float_column("total_slippage", |s| s.total_slippage),
+        float_column("daily_slippage", |s| s.daily_slippage),
+        float_column("total_turnover", |s| s.total_turnover),
+        float_column("daily_turnover", |s| s.daily_turnover),
+        int_column("total_trade_count", |s| s.total_trade_count),
+        float_column("daily_trade_count", |s| s.daily_trade_count),
+        float_column("total_return", |s| s.total_return),
+        float_column("annual_return", |s| s.annual_return),
+        float_column("daily_return", |s| s.daily_return),
+        float_column("return_std", |s| s.return_std),
+        float_column("sharpe_ratio", |s| s.sharpe_ratio),
+        float_column("ewm_sharpe", |s| s.ewm_sharpe),
+        float_column("return_drawdown_ratio", |s| s.return_drawdown_ratio),
+        float_column("position_days_ratio", |s| s.position_days_ratio),
+        float_column("position_bars_ratio", |s| s.position_bars_ratio),
+        float_column("average_holding_bars", |s| s.average_holding_bars),
+        float_column("annual_turnover", |s| s.annual_turnover),
+        float_column("long_net_pnl", |s| s.long_net_pnl),
+        float_column("short_net_pnl", |s| s.short_net_pnl),
+    ]
+}
+
+///Write the summary DataFrame to a CSV file.
+pub fn save_summary<P: AsRef<Path>>(df: &mut DataFrame, path: P) -> PolarsResult<()> {
+    let mut file = File::create(path)?;
+    CsvWriter::new(&mut file).finish(df)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{VnrsError, VnrsResult};
+    use crate::vnrs::trader::constant::Exchange;
+    use crate::vnrs::trader::database::{set_test_database, BaseDatabase};
+    use crate::vnrs::trader::object::{BarData, TickData};
+    use crate::vnrs_ctastrategy::sdk::{strategy_class, CtaStrategy, StrategyContext};
+
+    #[test]
+    fn results_in_job_order_with_failures_captured() {
+        let jobs: Vec<BatchJob> = (0..8)
+            .map(|i| BatchJob::new(&format!("IF{i}.CFFEX"), Interval::MINUTE, "s", ""))
+            .collect();
+        let mut runner = BatchRunner::new();
+        runner.set_max_workers(3);
+
+        // Earlier jobs finish last, a panic or error fails its own job only
+        let results = runner.run_with(
+            &jobs,
+            |_| true,
+            |job| {
+                let ix: u64 = job.vt_symbol[2..3].parse().unwrap();
+                thread::sleep(Duration::from_millis((8 - ix) * 10));
+                match ix {
+                    2 => panic!("strategy panicked"),
+                    5 => Err("setting rejected".to_string()),
+                    _ => Ok((
+                        BacktestingStatistics {
+                            total_net_pnl: ix as f64,
+                            ..Default::default()
+                        },
+                        None,
+                    )),
+                }
+            },
+        );
+
+        let symbols: Vec<&str> = results.iter().map(|r| r.job.vt_symbol.as_str()).collect();
+        let expected: Vec<&str> = jobs.iter().map(|job| job.vt_symbol.as_str()).collect();
+        assert_eq!(symbols, expected);
+        for (ix, result) in results.iter().enumerate() {
+            match ix {
+                2 => assert_eq!(result.statistics.as_ref().unwrap_err(), "strategy panicked"),
+                5 => assert_eq!(result.statistics.as_ref().unwrap_err(), "setting rejected"),
+                _ => assert_eq!(result.statistics.as_ref().unwrap().total_net_pnl, ix as f64),
+            }
+        }
+
+        // Intervals are in the text format of the CLI and the database
+        let summary = summarize(&results);
+        let intervals = summary.column("interval").unwrap().str().unwrap();
+        assert_eq!(intervals.get(0), Some("1m"));
+
+        // A strategy that cannot be loaded fails through the real job runner
+        let results = runner.run_jobs(&jobs[..1]);
+        assert!(results[0].statistics.is_err());
+    }
+
+    struct Idle;
+
+    impl CtaStrategy for Idle {
+        fn new(_context: &StrategyContext, _setting: &[(String, String)]) -> Self {
+            Idle
+        }
+
+        fn on_bar(&mut self, _context: &mut StrategyContext, _bar: &BarData) {}
+    }
+
+    struct FailingDatabase;
+
+    impl BaseDatabase for FailingDatabase {
+        fn load_bar_data(
+            &self,
+            _symbol: &str,
+            _exchange: Exchange,
+            _interval: Interval,
+            _start: NaiveDateTime,
+            _end: NaiveDateTime,
+        ) -> VnrsResult<Vec<BarData>> {
+            Err(VnrsError::Database("connection lost".to_string()))
+        }
+
+        fn load_tick_data(
+            &self,
+            _symbol: &str,
+            _exchange: Exchange,
+            _start: NaiveDateTime,
+            _end: NaiveDateTime,
+        ) -> VnrsResult<Vec<TickData>> {
+            Err(VnrsError::Database("connection lost".to_string()))
+        }
+    }
+
+    #[test]
+    fn failed_load_fails_the_job() {
+        let _database = set_test_database(Arc::new(FailingDatabase));
+        let mut runner = BatchRunner::new();
+        runner.start = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap().into();
+        runner.end = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap().into();
+        let class = Arc::new(strategy_class::<Idle>("idle"));
+        let jobs = [BatchJob::new("IF888.CFFEX", Interval::MINUTE, "idle", "")];
+
+        // Run as run_job does, on a class built into the host
+        let results = runner.run_with(
+            &jobs,
+            |_| true,
+            |job| {
+                let mut engine = runner.run_engine(job, class.clone())?;
+                Ok((engine.calculate_result_statistics(false), None))
+            },
+        );
+        assert_eq!(
+            results[0].statistics.as_ref().unwrap_err(),
+            "database error: connection lost"
+        );
+    }
+}
@@ -0,0 +1,192 @@
+/*!Capacity of a strategy: the backtest is run again with its order size scaled up while fills
+pay the volume impact of BacktestingEngine::set_volume_impact, and the degradation of return
+and Sharpe ratio shows how much capital the strategy can take before its edge is eaten up. */
+use polars::prelude::*;
+
+use super::backtesting::BacktestingStatistics;
+use super::base::{format_setting, parse_setting};
+use super::batch::{BatchJob, BatchRunner};
+use crate::error::{VnrsError, VnrsResult};
+
+///Backtest at one scale of the order size.
+#[derive(Debug, Clone)]
+pub struct CapacityPoint {
+    pub scale: f64,
+    pub statistics: Result<BacktestingStatistics, String>,
+}
+
+///Backtests by increasing scale.
+#[derive(Debug, Clone, Default)]
+pub struct CapacityReport {
+    pub points: Vec<CapacityPoint>,
+}
+
+impl CapacityReport {
+    ///One row per scale with its return, Sharpe ratio and efficiency, the net pnl per unit of
+    ///scale relative to the smallest scale. Failed runs are null.
+    pub fn df(&self) -> DataFrame {
+        let base = self.base_pnl_per_scale();
+        let column = |name: &str, f: &dyn Fn(&CapacityPoint, &BacktestingStatistics) -> f64| {
+            Series::new(
+                name,
+                self.points
+                    .iter()
+                    .map(|point| point.statistics.as_ref().ok().map(|s| f(point, s)))
+                    .collect::<Vec<Option<f64>>>(),
+            )
+        };
+        DataFrame::new(vec![
+            Series::new(
+                "scale",
+                self.points
+                    .iter()
+                    .map(|point| point.scale)
+                    .collect::<Vec<f64>>(),
+            ),
+            column("total_net_pnl", &|_, s| s.total_net_pnl),
+            column("total_slippage", &|_, s| s.total_slippage),
+            column("annual_return", &|_, s| s.annual_return),
+            column("max_ddpercent", &|_, s| s.max_ddpercent),
+            column("sharpe_ratio", &|_, s| s.sharpe_ratio),
+            column("efficiency", &|point, s| match base {
+                Some(base) if base != 0.0 => s.total_net_pnl / point.scale / base,
+                _ => f64::NAN,
+            }),
+        ])
+        .unwrap()
+    }
+
+    ///Largest scale before the Sharpe ratio first drops below min_fraction of the one at the
+    ///smallest scale, None if the smallest scale failed or has no positive Sharpe ratio.
+    pub fn capacity(&self, min_fraction: f64) -> Option<f64> {
+        let base = self.points.first()?.statistics.as_ref().ok()?.sharpe_ratio;
+        if base <= 0.0 {
+            return None;
+        }
+        let mut capacity = None;
+        for point in &self.points {
+            match &point.statistics {
+                Ok(statistics) if statistics.sharpe_ratio >= base * min_fraction => {
+                    capacity = Some(point.scale);
+                }
+                _ => break,
+            }
+        }
+        capacity
+    }
+
+    fn base_pnl_per_scale(&self) -> Option<f64> {
+        let point = self.points.first()?;
+        let statistics = point.statistics.as_ref().ok()?;
+        Some(statistics.total_net_pnl / point.scale)
+    }
+}
+
+///setting with the value of size_parameter multiplied by scale, rounded for a whole number.
+pub fn scale_setting(setting: &str, size_parameter: &str, scale: f64) -> VnrsResult<String> {
+    let mut parameters = parse_setting(setting);
+    let Some((_, value)) = parameters
+        .iter_mut()
+        .find(|(name, _)| name == size_parameter)
+    else {
+        return Err(VnrsError::Setting(format!(
+            "parameter {} not in the setting",
+            size_parameter
+        )));
+    };
+    *value = if let Ok(size) = value.parse::<i64>() {
+        ((size as f64 * scale).round() as i64).to_string()
+    } else if let Ok(size) = value.parse::<f64>() {
+        (size * scale).to_string()
+    } else {
+        return Err(VnrsError::Setting(format!(
+            "parameter {} isn't a number",
+            size_parameter
+        )));
+    };
+    Ok(format_setting(&parameters))
+}
+
+///Run job at each of scales, sorted ascending, scaling the order size parameter size_parameter
+///of its setting. runner holds the parameters of the backtests, e.g. the volume impact and the
+///workers, and must have no jobs of its own.
+pub fn estimate_capacity(
+    mut runner: BatchRunner,
+    job: &BatchJob,
+    size_parameter: &str,
+    scales: &[f64],
+) -> VnrsResult<CapacityReport> {
+    if !runner.jobs().is_empty() {
+        return Err(VnrsError::Setting(
+            "capacity runner must have no jobs".to_string(),
+        ));
+    }
+    let mut scales = scales.to_vec();
+    scales.sort_by(f64::total_cmp);
+    for &scale in &scales {
+        let mut scaled = job.clone();
+        scaled.setting = scale_setting(&job.setting, size_parameter, scale)?;
+        scaled.initial_pos = job.initial_pos * scale;
+        runner.add_job(scaled);
+    }
+
+    let points = scales
+        .into_iter()
+        .zip(runner.run())
+        .map(|(scale, result)| CapacityPoint {
+            scale,
+            statistics: result.statistics,
+        })
+        .collect();
+    Ok(CapacityReport { points })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capacity_from_degradation() {
+        assert_eq!(
+            scale_setting("fast_window:10,fixed_size:2", "fixed_size", 2.5).unwrap(),
+            "fast_window:10,fixed_size:5"
+        );
+        assert_eq!(
+            scale_setting("fixed_size:0.5", "fixed_size", 3.0).unwrap(),
+            "fixed_size:1.5"
+        );
+        assert!(scale_setting("fast_window:10", "fixed_size", 2.0).is_err());
+
+        let point = |scale: f64, total_net_pnl: f64, sharpe_ratio: f64| CapacityPoint {
+            scale,
+            statistics: Ok(BacktestingStatistics {
+                total_net_pnl,
+                sharpe_ratio,
+                ..Default::default()
+            }),
+        };
+        let report = CapacityReport {
+            points: vec![
+                point(1.0, 100.0, 2.0),
+                point(2.0, 190.0, 1.8),
+                point(4.0, 300.0, 1.2),
+                point(8.0, 200.0, 0.4),
+            ],
+        };
+        assert_eq!(report.capacity(0.5), Some(4.0));
+        assert_eq!(report.capacity(0.95), Some(1.0));
+
+        let df = report.df();
+        let efficiency: Vec<Option<f64>> = df
+            .column("efficiency")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            efficiency,
+            vec![Some(1.0), Some(0.95), Some(0.75), Some(0.25)]
+        );
+    }
+}
@@ -0,0 +1,192 @@
+/*!Round trips and their pnl bucketed by hour of day and weekday of entry, to find session
+effects such as losses concentrated in the night session. */
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Timelike, Weekday};
+use polars::prelude::*;
+
+use super::excursion::RoundTrip;
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TradeBucket {
+    pub count: i64,
+    pub win_count: i64,
+    ///Pnl before costs, see RoundTrip::pnl.
+    pub pnl: f64,
+}
+
+impl TradeBucket {
+    pub fn win_rate(&self) -> f64 {
+        if self.count > 0 {
+            self.win_count as f64 / self.count as f64 * 100.0
+        } else {
+            0.0
+        }
+    }
+
+    fn add(&mut self, trip: &RoundTrip) {
+        self.count += 1;
+        if trip.pnl > 0.0 {
+            self.win_count += 1;
+        }
+        self.pnl += trip.pnl;
+    }
+}
+
+///Buckets of round trips by (hour, weekday) of their entry datetime.
+#[derive(Debug, Default, Clone)]
+pub struct TimeOfDayReport {
+    buckets: BTreeMap<(u32, u32), TradeBucket>,
+}
+
+impl TimeOfDayReport {
+    pub fn new(round_trips: &[RoundTrip]) -> Self {
+        let mut report = TimeOfDayReport::default();
+        for trip in round_trips {
+            let datetime = trip.entry_datetime;
+            report
+                .buckets
+                .entry((datetime.hour(), datetime.weekday().num_days_from_monday()))
+                .or_default()
+                .add(trip);
+        }
+        report
+    }
+
+    pub fn get(&self, hour: u32, weekday: Weekday) -> Option<&TradeBucket> {
+        self.buckets.get(&(hour, weekday.num_days_from_monday()))
+    }
+
+    ///Heatmap of value with a row per hour having trips and a column per weekday, empty
+    ///buckets are null.
+    pub fn heatmap_df(&self, value: fn(&TradeBucket) -> f64) -> DataFrame {
+        let hours: Vec<u32> = self.hours();
+        let mut columns = vec![Series::new("hour", hours.clone())];
+        for weekday in WEEKDAYS {
+            let values: Vec<Option<f64>> = hours
+                .iter()
+                .map(|hour| self.get(*hour, weekday).map(value))
+                .collect();
+            columns.push(Series::new(&weekday.to_string(), values));
+        }
+        DataFrame::new(columns).unwrap()
+    }
+
+    ///Count, win rate and pnl of each hour having trips.
+    pub fn hourly_df(&self) -> DataFrame {
+        let hours = self.hours();
+        let buckets: Vec<TradeBucket> = hours
+            .iter()
+            .map(|hour| self.merge(|(h, _)| h == hour))
+            .collect();
+        Self::summary_df(Series::new("hour", hours), &buckets)
+    }
+
+    ///Count, win rate and pnl of each weekday.
+    pub fn weekday_df(&self) -> DataFrame {
+        let buckets: Vec<TradeBucket> = WEEKDAYS
+            .iter()
+            .map(|weekday| self.merge(|(_, d)| *d == weekday.num_days_from_monday()))
+            .collect();
+        let names: Vec<String> = WEEKDAYS.iter().map(Weekday::to_string).collect();
+        Self::summary_df(Series::new("weekday", names), &buckets)
+    }
+
+    fn hours(&self) -> Vec<u32> {
+        let mut hours: Vec<u32> = self.buckets.keys().map(|(hour, _)| *hour).collect();
+        hours.dedup();
+        hours
+    }
+
+    fn merge(&self, filter: impl Fn(&(u32, u32)) -> bool) -> TradeBucket {
+        let mut merged = TradeBucket::default();
+        for (_, bucket) in self.buckets.iter().filter(|(key, _)| filter(key)) {
+            merged.count += bucket.count;
+            merged.win_count += bucket.win_count;
+            merged.pnl += bucket.pnl;
+        }
+        merged
+    }
+
+    fn summary_df(key: Series, buckets: &[TradeBucket]) -> DataFrame {
+        DataFrame::new(vec![
+            key,
+            Series::new(
+                "count",
+                buckets.iter().map(|b| b.count).collect::<Vec<i64>>(),
+            ),
+            Series::new(
+                "win_rate",
+                buckets
+                    .iter()
+                    .map(TradeBucket::win_rate)
+                    .collect::<Vec<f64>>(),
+            ),
+            Series::new("pnl", buckets.iter().map(|b| b.pnl).collect::<Vec<f64>>()),
+        ])
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::constant::Direction;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    fn trip(datetime: NaiveDateTime, pnl: f64) -> RoundTrip {
+        RoundTrip {
+            direction: Direction::LONG,
+            entry_datetime: datetime,
+            exit_datetime: datetime,
+            entry_price: 100.0,
+            exit_price: 100.0,
+            volume: 1.0,
+            pnl,
+            mfe: 0.0,
+            mae: 0.0,
+        }
+    }
+
+    #[test]
+    fn buckets_by_hour_and_weekday() {
+        // 2024-01-22 is a Monday
+        let at = |day: u32, hour: u32| {
+            NaiveDate::from_ymd_opt(2024, 1, day)
+                .unwrap()
+                .and_hms_opt(hour, 30, 0)
+                .unwrap()
+        };
+        let report = TimeOfDayReport::new(&[
+            trip(at(22, 9), 100.0),
+            trip(at(22, 9), -40.0),
+            trip(at(29, 9), 20.0),
+            trip(at(26, 21), -300.0),
+        ]);
+
+        let monday = report.get(9, Weekday::Mon).unwrap();
+        assert_eq!((monday.count, monday.win_count, monday.pnl), (3, 2, 80.0));
+        assert!(report.get(9, Weekday::Tue).is_none());
+
+        let heatmap = report.heatmap_df(|bucket| bucket.pnl);
+        assert_eq!(heatmap.height(), 2);
+        let friday = heatmap.column("Fri").unwrap().f64().unwrap();
+        assert_eq!(friday.get(0), None);
+        assert_eq!(friday.get(1), Some(-300.0));
+
+        let hourly = report.hourly_df();
+        let pnl = hourly.column("pnl").unwrap().f64().unwrap();
+        assert_eq!(pnl.get(1), Some(-300.0));
+        assert_eq!(report.weekday_df().height(), 7);
+    }
+}
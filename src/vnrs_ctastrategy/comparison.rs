@@ -0,0 +1,245 @@
+/*!Side by side comparison of backtest runs, e.g. variants of one strategy: equity curves on
+the same dates, statistics table and correlation of daily returns. */
+use chrono::NaiveDate;
+use polars::prelude::*;
+use std::collections::BTreeMap;
+
+use super::backtesting::{BacktestingEngine, BacktestingStatistics};
+use super::batch::statistics_columns;
+use super::portfolio::correlation;
+use crate::vnrs::trader::database::BacktestRecord;
+use crate::vnrs::trader::timeseries::TimeSeries;
+
+///One run to compare.
+#[derive(Debug, Clone)]
+pub struct ComparisonEntry {
+    pub name: String,
+    pub statistics: BacktestingStatistics,
+    ///Net pnl by date, empty for a run saved without its daily results.
+    pub daily_pnl: TimeSeries<f64, NaiveDate>,
+}
+
+impl ComparisonEntry {
+    ///Take the daily results of a finished backtest with its statistics.
+    pub fn from_engine(
+        name: &str,
+        engine: &BacktestingEngine,
+        statistics: BacktestingStatistics,
+    ) -> Self {
+        ComparisonEntry {
+            name: name.to_string(),
+            statistics,
+            daily_pnl: engine
+                .get_daily_results()
+                .map(|result| (result.date, result.net_pnl))
+                .collect(),
+        }
+    }
+
+    ///Run saved in the database, only in the statistics table as records have no daily
+    ///results. None if its statistics can't be read.
+    pub fn from_record(record: &BacktestRecord) -> Option<Self> {
+        Some(ComparisonEntry {
+            name: format!("{} #{}", record.strategy, record.id),
+            statistics: serde_json::from_value(record.statistics.clone()).ok()?,
+            daily_pnl: TimeSeries::new(),
+        })
+    }
+}
+
+pub struct ComparisonReport {
+    entries: Vec<ComparisonEntry>,
+    dates: Vec<NaiveDate>,
+}
+
+impl ComparisonReport {
+    ///Entries of the same name are told apart by a suffix.
+    pub fn new(mut entries: Vec<ComparisonEntry>) -> Self {
+        let mut seen = BTreeMap::new();
+        for entry in entries.iter_mut() {
+            let count = seen.entry(entry.name.clone()).or_insert(0);
+            *count += 1;
+            if *count > 1 {
+                entry.name = format!("{} ({})", entry.name, count);
+            }
+        }
+
+        let dates = TimeSeries::union_keys(entries.iter().map(|entry| &entry.daily_pnl));
+        ComparisonReport { entries, dates }
+    }
+
+    ///Balance of each run by date, flat on days a run has no result. Runs without daily
+    ///results are left out.
+    pub fn equity_df(&self) -> DataFrame {
+        let mut columns = vec![self.date_series()];
+        for entry in self.daily_entries() {
+            columns.push(Series::new(&entry.name, self.balance(entry)));
+        }
+        DataFrame::new(columns).unwrap()
+    }
+
+    ///One row per statistic and one column per run.
+    pub fn statistics_df(&self) -> DataFrame {
+        let stats: Vec<Option<&BacktestingStatistics>> = self
+            .entries
+            .iter()
+            .map(|entry| Some(&entry.statistics))
+            .collect();
+        // Dates aren't numbers, they are in the equity curves
+        let rows: Vec<Series> = statistics_columns(&stats)
+            .into_iter()
+            .filter(|series| series.dtype() != &DataType::Date)
+            .map(|series| series.cast(&DataType::Float64).unwrap())
+            .collect();
+
+        let mut columns = vec![Series::new(
+            "statistic",
+            rows.iter()
+                .map(|row| row.name().to_string())
+                .collect::<Vec<String>>(),
+        )];
+        for (ix, entry) in self.entries.iter().enumerate() {
+            columns.push(Series::new(
+                &entry.name,
+                rows.iter()
+                    .map(|row| row.f64().unwrap().get(ix))
+                    .collect::<Vec<Option<f64>>>(),
+            ));
+        }
+        DataFrame::new(columns).unwrap()
+    }
+
+    ///Correlation matrix of the daily returns of the runs with daily results.
+    pub fn correlation_df(&self) -> DataFrame {
+        let entries: Vec<&ComparisonEntry> = self.daily_entries().collect();
+        let returns: Vec<Vec<f64>> = entries
+            .iter()
+            .map(|entry| {
+                let mut pre_balance = entry.statistics.capital;
+                self.balance(entry)
+                    .into_iter()
+                    .map(|balance| {
+                        let daily_return = if pre_balance != 0.0 {
+                            balance / pre_balance - 1.0
+                        } else {
+                            0.0
+                        };
+                        pre_balance = balance;
+                        daily_return
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut columns = vec![Series::new(
+            "name",
+            entries
+                .iter()
+                .map(|entry| entry.name.clone())
+                .collect::<Vec<String>>(),
+        )];
+        for (entry, a) in entries.iter().zip(&returns) {
+            columns.push(Series::new(
+                &entry.name,
+                returns
+                    .iter()
+                    .map(|b| correlation(a, b))
+                    .collect::<Vec<f64>>(),
+            ));
+        }
+        DataFrame::new(columns).unwrap()
+    }
+
+    fn daily_entries(&self) -> impl Iterator<Item = &ComparisonEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.daily_pnl.is_empty())
+    }
+
+    fn balance(&self, entry: &ComparisonEntry) -> Vec<f64> {
+        let mut balance = entry.statistics.capital;
+        entry
+            .daily_pnl
+            .reindex(&self.dates)
+            .into_iter()
+            .map(|pnl| {
+                balance += pnl.copied().unwrap_or(0.0);
+                balance
+            })
+            .collect()
+    }
+
+    fn date_series(&self) -> Series {
+        Int32Chunked::from_iter_values(
+            "date",
+            self.dates
+                .iter()
+                .map(|date| (*date - NaiveDate::default()).num_days() as i32),
+        )
+        .into_date()
+        .into_series()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, pnl: &[(u32, f64)]) -> ComparisonEntry {
+        ComparisonEntry {
+            name: name.to_string(),
+            statistics: BacktestingStatistics {
+                capital: 1000.0,
+                total_net_pnl: pnl.iter().map(|(_, pnl)| pnl).sum(),
+                ..Default::default()
+            },
+            daily_pnl: pnl
+                .iter()
+                .map(|(day, pnl)| (NaiveDate::from_ymd_opt(2024, 1, *day).unwrap(), *pnl))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn runs_side_by_side() {
+        // The last run is a saved one without daily results
+        let report = ComparisonReport::new(vec![
+            entry("fast", &[(2, 10.0), (3, 20.0), (4, -5.0)]),
+            entry("fast", &[(2, -10.0), (4, 30.0)]),
+            entry("saved", &[]),
+        ]);
+
+        let equity = report.equity_df();
+        assert_eq!(equity.get_column_names(), vec!["date", "fast", "fast (2)"]);
+        let balance: Vec<f64> = equity["fast (2)"]
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(balance, vec![990.0, 990.0, 1020.0]);
+
+        let statistics = report.statistics_df();
+        assert_eq!(statistics.width(), 4);
+        let names: Vec<&str> = statistics["statistic"]
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        let row = names
+            .iter()
+            .position(|name| *name == "total_net_pnl")
+            .unwrap();
+        assert_eq!(statistics["fast"].f64().unwrap().get(row), Some(25.0));
+        assert!(!names.contains(&"start_date"));
+
+        let correlation = report.correlation_df();
+        assert_eq!(correlation.height(), 2);
+        let fast: Vec<f64> = correlation["fast"]
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert!((fast[0] - 1.0).abs() < 1e-12);
+        assert!(fast[1] < 0.0);
+    }
+}
@@ -0,0 +1,290 @@
+/*!Contract specifications of the symbols in a backtest, keyed by vt_symbol. */
+#[cfg(feature = "backtest-stats")]
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::vnrs::trader::constant::{Currency, Exchange};
+use crate::vnrs::trader::object::ContractData;
+
+///One row of a contract specification file. Rate and slippage are optional and fall back to
+///the values given to set_parameters.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ContractSpec {
+    pub vt_symbol: String,
+    #[serde(default)]
+    pub name: String,
+    pub size: f64,
+    pub pricetick: f64,
+    #[serde(default)]
+    pub min_volume: f64,
+    #[serde(default)]
+    pub rate: Option<f64>,
+    #[serde(default)]
+    pub slippage: Option<f64>,
+    ///Currency of prices and pnl, CNY when not given.
+    #[serde(default)]
+    pub currency: Currency,
+}
+
+impl ContractSpec {
+    ///Contract of the spec, rate and slippage being costs of the backtest rather than of the
+    ///contract are left out.
+    pub fn to_contract(&self) -> Result<ContractData, String> {
+        let (symbol, exchange) = self
+            .vt_symbol
+            .rsplit_once('.')
+            .ok_or_else(|| format!("invalid vt_symbol {}", self.vt_symbol))?;
+        let exchange = Exchange::from_str(exchange)
+            .map_err(|_| format!("invalid exchange in vt_symbol {}", self.vt_symbol))?;
+        Ok(ContractData {
+            gateway_name: "BACKTESTING".into(),
+            symbol: symbol.into(),
+            exchange,
+            name: self.name.clone(),
+            size: self.size,
+            pricetick: self.pricetick,
+            min_volume: self.min_volume,
+            currency: self.currency,
+            ..Default::default()
+        })
+    }
+}
+
+///(product, exchange, size, pricetick, rate) of common contracts, product being the letters
+///of a futures symbol before its month or the whole symbol of a crypto pair. Rates are typical
+///costs as a fraction of turnover, check them against the fees of the broker.
+const BUILTIN_SPECS: [(&str, Exchange, f64, f64, f64); 50] = [
+    ("IF", Exchange::CFFEX, 300.0, 0.2, 2.3e-5),
+    ("IH", Exchange::CFFEX, 300.0, 0.2, 2.3e-5),
+    ("IC", Exchange::CFFEX, 200.0, 0.2, 2.3e-5),
+    ("IM", Exchange::CFFEX, 200.0, 0.2, 2.3e-5),
+    ("TS", Exchange::CFFEX, 20000.0, 0.002, 3e-6),
+    ("TF", Exchange::CFFEX, 10000.0, 0.005, 3e-6),
+    ("T", Exchange::CFFEX, 10000.0, 0.005, 3e-6),
+    ("TL", Exchange::CFFEX, 10000.0, 0.01, 3e-6),
+    ("rb", Exchange::SHFE, 10.0, 1.0, 1e-4),
+    ("hc", Exchange::SHFE, 10.0, 1.0, 1e-4),
+    ("au", Exchange::SHFE, 1000.0, 0.02, 2e-5),
+    ("ag", Exchange::SHFE, 15.0, 1.0, 5e-5),
+    ("cu", Exchange::SHFE, 5.0, 10.0, 5e-5),
+    ("al", Exchange::SHFE, 5.0, 5.0, 3e-5),
+    ("zn", Exchange::SHFE, 5.0, 5.0, 3e-5),
+    ("ni", Exchange::SHFE, 1.0, 10.0, 3e-5),
+    ("ru", Exchange::SHFE, 10.0, 5.0, 4.5e-5),
+    ("fu", Exchange::SHFE, 10.0, 1.0, 5e-5),
+    ("bu", Exchange::SHFE, 10.0, 1.0, 1e-4),
+    ("sp", Exchange::SHFE, 10.0, 2.0, 5e-5),
+    ("sc", Exchange::INE, 1000.0, 0.1, 2e-5),
+    ("i", Exchange::DCE, 100.0, 0.5, 1e-4),
+    ("m", Exchange::DCE, 10.0, 1.0, 5e-5),
+    ("y", Exchange::DCE, 10.0, 2.0, 5e-5),
+    ("p", Exchange::DCE, 10.0, 2.0, 2.5e-5),
+    ("c", Exchange::DCE, 10.0, 1.0, 5e-5),
+    ("j", Exchange::DCE, 100.0, 0.5, 1e-4),
+    ("jm", Exchange::DCE, 60.0, 0.5, 1e-4),
+    ("pp", Exchange::DCE, 5.0, 1.0, 5e-5),
+    ("v", Exchange::DCE, 5.0, 1.0, 5e-5),
+    ("l", Exchange::DCE, 5.0, 1.0, 5e-5),
+    ("eg", Exchange::DCE, 10.0, 1.0, 5e-5),
+    ("SR", Exchange::CZCE, 10.0, 1.0, 5e-5),
+    ("CF", Exchange::CZCE, 5.0, 5.0, 5e-5),
+    ("TA", Exchange::CZCE, 5.0, 2.0, 5e-5),
+    ("MA", Exchange::CZCE, 10.0, 1.0, 1e-4),
+    ("FG", Exchange::CZCE, 20.0, 1.0, 1e-4),
+    ("SA", Exchange::CZCE, 20.0, 1.0, 1e-4),
+    ("RM", Exchange::CZCE, 10.0, 1.0, 5e-5),
+    ("OI", Exchange::CZCE, 10.0, 1.0, 5e-5),
+    ("AP", Exchange::CZCE, 10.0, 1.0, 1e-4),
+    ("si", Exchange::GFEX, 5.0, 5.0, 1e-4),
+    ("lc", Exchange::GFEX, 1.0, 50.0, 8e-5),
+    ("BTC-USDT", Exchange::OKX, 1.0, 0.1, 1e-3),
+    ("ETH-USDT", Exchange::OKX, 1.0, 0.01, 1e-3),
+    ("SOL-USDT", Exchange::OKX, 1.0, 0.01, 1e-3),
+    ("BTC-USDT-SWAP", Exchange::OKX, 0.01, 0.1, 5e-4),
+    ("ETH-USDT-SWAP", Exchange::OKX, 0.1, 0.01, 5e-4),
+    ("SOL-USDT-SWAP", Exchange::OKX, 1.0, 0.01, 5e-4),
+    ("BTC-USD-SWAP", Exchange::OKX, 100.0, 0.1, 5e-4),
+];
+
+///Spec of the product of vt_symbol in the built-in table of common Chinese futures and crypto
+///pairs, e.g. of IF for IF2406.CFFEX or IF888.CFFEX. None for other products. Crypto pairs
+///quoted in USDT are taken to be in USD.
+pub fn builtin_spec(vt_symbol: &str) -> Option<ContractSpec> {
+    let (symbol, exchange) = vt_symbol.rsplit_once('.')?;
+    let exchange = Exchange::from_str(exchange).ok()?;
+    let letters = symbol
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .map_or(symbol, |end| &symbol[..end]);
+    BUILTIN_SPECS
+        .iter()
+        .find(|(product, product_exchange, ..)| {
+            *product_exchange == exchange && (*product == symbol || *product == letters)
+        })
+        .map(|&(product, exchange, size, pricetick, rate)| ContractSpec {
+            vt_symbol: vt_symbol.to_string(),
+            name: product.to_string(),
+            size,
+            pricetick,
+            rate: Some(rate),
+            currency: match exchange {
+                Exchange::OKX => Currency::USD,
+                _ => Currency::CNY,
+            },
+            ..Default::default()
+        })
+}
+
+///Registry of contract specifications, consulted by the engine for size, pricetick,
+///min_volume and trading costs of the symbol it runs.
+#[derive(Debug, Default, Clone)]
+pub struct ContractRegistry {
+    contracts: HashMap<String, ContractData>,
+    rates: HashMap<String, f64>,
+    slippages: HashMap<String, f64>,
+}
+
+impl ContractRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Load specs from a JSON array of ContractSpec objects.
+    pub fn load_json<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let specs: Vec<ContractSpec> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Self::from_specs(specs)
+    }
+
+    ///Load specs from a CSV file with a header of ContractSpec field names, vt_symbol, size
+    ///and pricetick are required.
+    #[cfg(feature = "backtest-stats")]
+    pub fn load_csv<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let df = CsvReadOptions::default()
+            .with_has_header(true)
+            .try_into_reader_with_file_path(Some(path.as_ref().to_path_buf()))?
+            .finish()?;
+
+        let float_column = |name: &str| -> PolarsResult<Option<Float64Chunked>> {
+            match df.column(name) {
+                Ok(column) => Ok(Some(column.cast(&DataType::Float64)?.f64()?.clone())),
+                Err(_) => Ok(None),
+            }
+        };
+        let vt_symbol = df.column("vt_symbol")?.str()?;
+        let name = df
+            .column("name")
+            .ok()
+            .map(|column| column.str())
+            .transpose()?;
+        let size = float_column("size")?.ok_or("size column is missing")?;
+        let pricetick = float_column("pricetick")?.ok_or("pricetick column is missing")?;
+        let min_volume = float_column("min_volume")?;
+        let rate = float_column("rate")?;
+        let slippage = float_column("slippage")?;
+        let currency = df
+            .column("currency")
+            .ok()
+            .map(|column| column.str())
+            .transpose()?;
+
+        let specs = (0..df.height())
+            .map(|i| {
+                Ok(ContractSpec {
+                    vt_symbol: vt_symbol.get(i).unwrap_or_default().to_string(),
+                    name: name
+                        .and_then(|name| name.get(i))
+                        .unwrap_or_default()
+                        .to_string(),
+                    size: size.get(i).unwrap_or_default(),
+                    pricetick: pricetick.get(i).unwrap_or_default(),
+                    min_volume: min_volume
+                        .as_ref()
+                        .and_then(|c| c.get(i))
+                        .unwrap_or_default(),
+                    rate: rate.as_ref().and_then(|c| c.get(i)),
+                    slippage: slippage.as_ref().and_then(|c| c.get(i)),
+                    currency: currency
+                        .and_then(|c| c.get(i))
+                        .map(Currency::from_str)
+                        .transpose()?
+                        .unwrap_or_default(),
+                })
+            })
+            .collect::<Result<_, strum::ParseError>>()?;
+        Self::from_specs(specs)
+    }
+
+    pub fn from_specs(specs: Vec<ContractSpec>) -> Result<Self, Box<dyn Error>> {
+        let mut registry = Self::new();
+        for spec in specs {
+            registry.add_spec(spec)?;
+        }
+        Ok(registry)
+    }
+
+    pub fn add_spec(&mut self, spec: ContractSpec) -> Result<(), String> {
+        if let Some(rate) = spec.rate {
+            self.rates.insert(spec.vt_symbol.clone(), rate);
+        }
+        if let Some(slippage) = spec.slippage {
+            self.slippages.insert(spec.vt_symbol.clone(), slippage);
+        }
+        self.add_contract(spec.to_contract()?);
+        Ok(())
+    }
+
+    pub fn add_contract(&mut self, contract: ContractData) {
+        self.contracts.insert(contract.vt_symbol(), contract);
+    }
+
+    pub fn get_contract(&self, vt_symbol: &str) -> Option<&ContractData> {
+        self.contracts.get(vt_symbol)
+    }
+
+    pub fn get_rate(&self, vt_symbol: &str) -> Option<f64> {
+        self.rates.get(vt_symbol).copied()
+    }
+
+    pub fn get_slippage(&self, vt_symbol: &str) -> Option<f64> {
+        self.slippages.get(vt_symbol).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.contracts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contracts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_specs() {
+        let spec = builtin_spec("IF2406.CFFEX").unwrap();
+        assert_eq!((spec.size, spec.pricetick), (300.0, 0.2));
+        assert_eq!(builtin_spec("IF888.CFFEX").unwrap().name, "IF");
+        assert_eq!(builtin_spec("T2409.CFFEX").unwrap().size, 10000.0);
+        assert_eq!(builtin_spec("TS2409.CFFEX").unwrap().size, 20000.0);
+        assert_eq!(builtin_spec("rb2410.SHFE").unwrap().pricetick, 1.0);
+        assert_eq!(builtin_spec("SR409.CZCE").unwrap().size, 10.0);
+        assert_eq!(builtin_spec("BTC-USDT-SWAP.OKX").unwrap().size, 0.01);
+        assert_eq!(builtin_spec("BTC-USDT.OKX").unwrap().size, 1.0);
+        assert_eq!(
+            builtin_spec("BTC-USDT.OKX").unwrap().currency,
+            Currency::USD
+        );
+        assert_eq!(builtin_spec("rb2410.SHFE").unwrap().currency, Currency::CNY);
+        // Products are told apart by exchange
+        assert!(builtin_spec("rb2410.DCE").is_none());
+        assert!(builtin_spec("600000.SSE").is_none());
+        assert!(builtin_spec("IF2406").is_none());
+    }
+}
@@ -0,0 +1,210 @@
+/*!Cross validation of a backtest over time: the date range is split into k consecutive folds,
+optionally with an embargo gap between them so positions and indicators of one fold don't leak
+into the next, and the same strategy setting is run on each fold. Statistics that only hold up
+in a few folds point to a setting fitted to one market regime. */
+use chrono::{NaiveDateTime, TimeDelta};
+use polars::prelude::*;
+
+use super::batch::{summarize, BatchJob, BatchResult, BatchRunner};
+use crate::error::{VnrsError, VnrsResult};
+
+///Split start to end into k periods of equal length. Every period after the first starts
+///embargo after the end of the one before, e.g. a few days to leave out the warm-up of the
+///strategy.
+pub fn split_folds(
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    k: usize,
+    embargo: TimeDelta,
+) -> VnrsResult<Vec<(NaiveDateTime, NaiveDateTime)>> {
+    if k == 0 || end <= start {
+        return Err(VnrsError::Setting(format!(
+            "can't split {} to {} into {} folds",
+            start, end, k
+        )));
+    }
+    let span = (end - start) / k as i32;
+    // A negative embargo would start each fold inside the one before
+    if embargo < TimeDelta::zero() {
+        return Err(VnrsError::Setting(format!("negative embargo {}", embargo)));
+    }
+    if embargo >= span {
+        return Err(VnrsError::Setting(format!(
+            "embargo {} not shorter than the folds of {}",
+            embargo, span
+        )));
+    }
+
+    Ok((0..k)
+        .map(|i| {
+            let fold_start = start + span * i as i32;
+            let fold_end = if i + 1 == k { end } else { fold_start + span };
+            if i == 0 {
+                (fold_start, fold_end)
+            } else {
+                (fold_start + embargo, fold_end)
+            }
+        })
+        .collect())
+}
+
+///Spread of the Sharpe ratio over the folds that ran.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FoldSummary {
+    pub folds: usize,
+    pub succeeded: usize,
+    ///Folds ending with a positive net pnl.
+    pub profitable: usize,
+    pub mean_sharpe: f64,
+    pub std_sharpe: f64,
+    pub min_sharpe: f64,
+    pub max_sharpe: f64,
+}
+
+///Results of a job run on each fold, in fold order.
+#[derive(Debug, Clone, Default)]
+pub struct FoldReport {
+    pub results: Vec<BatchResult>,
+}
+
+impl FoldReport {
+    ///summarize of the folds with their index, start and end in front.
+    pub fn df(&self) -> VnrsResult<DataFrame> {
+        let period = |f: fn(&(NaiveDateTime, NaiveDateTime)) -> NaiveDateTime| {
+            self.results
+                .iter()
+                .map(|result| result.job.period.as_ref().map(f))
+                .collect::<Vec<Option<NaiveDateTime>>>()
+        };
+        let mut columns = vec![
+            Series::new("fold", (0..self.results.len() as u32).collect::<Vec<u32>>()),
+            Series::new("fold_start", period(|period| period.0)),
+            Series::new("fold_end", period(|period| period.1)),
+        ];
+        columns.extend(summarize(&self.results).get_columns().iter().cloned());
+        Ok(DataFrame::new(columns)?)
+    }
+
+    pub fn summary(&self) -> FoldSummary {
+        let statistics: Vec<_> = self
+            .results
+            .iter()
+            .filter_map(|result| result.statistics.as_ref().ok())
+            .collect();
+        let mut summary = FoldSummary {
+            folds: self.results.len(),
+            succeeded: statistics.len(),
+            profitable: statistics
+                .iter()
+                .filter(|statistics| statistics.total_net_pnl > 0.0)
+                .count(),
+            ..Default::default()
+        };
+        if statistics.is_empty() {
+            return summary;
+        }
+
+        let sharpes: Vec<f64> = statistics.iter().map(|s| s.sharpe_ratio).collect();
+        let n = sharpes.len() as f64;
+        summary.mean_sharpe = sharpes.iter().sum::<f64>() / n;
+        summary.std_sharpe = (sharpes
+            .iter()
+            .map(|sharpe| (sharpe - summary.mean_sharpe).powi(2))
+            .sum::<f64>()
+            / n)
+            .sqrt();
+        summary.min_sharpe = sharpes.iter().copied().fold(f64::INFINITY, f64::min);
+        summary.max_sharpe = sharpes.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        summary
+    }
+}
+
+///Run job on each of folds, e.g. from split_folds. runner holds the parameters of the
+///backtests and must have no jobs of its own, its start and end are replaced by the folds.
+pub fn run_folds(
+    mut runner: BatchRunner,
+    job: &BatchJob,
+    folds: &[(NaiveDateTime, NaiveDateTime)],
+) -> VnrsResult<FoldReport> {
+    if !runner.jobs().is_empty() {
+        return Err(VnrsError::Setting(
+            "cross validation runner must have no jobs".to_string(),
+        ));
+    }
+    for &period in folds {
+        let mut fold = job.clone();
+        fold.period = Some(period);
+        runner.add_job(fold);
+    }
+    Ok(FoldReport {
+        results: runner.run(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::constant::Interval;
+    use crate::vnrs_ctastrategy::backtesting::BacktestingStatistics;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn folds_and_their_spread() {
+        let start = at("2024-01-01 00:00:00");
+        let end = at("2024-01-31 00:00:00");
+        let folds = split_folds(start, end, 3, TimeDelta::days(2)).unwrap();
+        assert_eq!(
+            folds,
+            vec![
+                (start, at("2024-01-11 00:00:00")),
+                (at("2024-01-13 00:00:00"), at("2024-01-21 00:00:00")),
+                (at("2024-01-23 00:00:00"), end),
+            ]
+        );
+        assert!(split_folds(start, end, 0, TimeDelta::zero()).is_err());
+        assert!(split_folds(start, end, 3, TimeDelta::days(10)).is_err());
+        assert!(split_folds(start, end, 3, TimeDelta::days(-2)).is_err());
+
+        let job = BatchJob::new("rb888.SHFE", Interval::MINUTE, "strategy", "");
+        let result = |period, total_net_pnl, sharpe_ratio| {
+            let mut job = job.clone();
+            job.period = Some(period);
+            BatchResult {
+                job,
+                statistics: Ok(BacktestingStatistics {
+                    total_net_pnl,
+                    sharpe_ratio,
+                    ..Default::default()
+                }),
+                daily: None,
+                duration: Default::default(),
+            }
+        };
+        let mut failed = result(folds[2], 0.0, 0.0);
+        failed.statistics = Err("no data".to_string());
+        let report = FoldReport {
+            results: vec![
+                result(folds[0], 100.0, 2.0),
+                result(folds[1], -20.0, -1.0),
+                failed,
+            ],
+        };
+        let summary = report.summary();
+        assert_eq!(summary.folds, 3);
+        assert_eq!(summary.succeeded, 2);
+        assert_eq!(summary.profitable, 1);
+        assert_eq!(summary.mean_sharpe, 0.5);
+        assert_eq!(summary.std_sharpe, 1.5);
+        assert_eq!((summary.min_sharpe, summary.max_sharpe), (-1.0, 2.0));
+
+        let df = report.df().unwrap();
+        assert_eq!(df.height(), 3);
+        assert_eq!(
+            df.get_column_names()[..3],
+            ["fold", "fold_start", "fold_end"]
+        );
+    }
+}
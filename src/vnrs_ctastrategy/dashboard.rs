@@ -0,0 +1,524 @@
+/*!Headless web dashboard serving backtest results over HTTP, enabled by the `web` feature. */
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::NaiveDateTime;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+use super::backtesting::{BacktestingEngine, BacktestingStatistics};
+use crate::vnrs::trader::constant::{Direction, Offset};
+use crate::vnrs::trader::history::BarHistory;
+use crate::vnrs::trader::object::TradeData;
+use crate::vnrs::trader::setting::get_settings;
+use crate::vnrs::trader::utility::get_trader_dir;
+
+///Most bars returned by one /api/bars request.
+const MAX_CHART_BARS: usize = 5000;
+
+///plotly.js the page loads unless the "web.plotly_js" setting names a local copy.
+const PLOTLY_CDN: &str = "https://cdn.plot.ly/plotly-2.35.2.min.js";
+
+///Snapshot of a finished backtest served by the dashboard.
+pub struct DashboardData {
+    pub statistics: BacktestingStatistics,
+    pub daily_df: DataFrame,
+    pub pnl_df: DataFrame,
+    ///Pnl by hour (rows) and weekday (columns) of entry.
+    pub heatmap_df: DataFrame,
+    pub trades: Vec<TradeData>,
+    pub bars: BarHistory,
+}
+
+impl DashboardData {
+    ///Take the snapshot from an engine after calculate_result and calculate_statistics.
+    pub fn from_engine(engine: &BacktestingEngine, statistics: BacktestingStatistics) -> Self {
+        DashboardData {
+            statistics,
+            daily_df: engine.get_daily_df().unwrap_or_default(),
+            pnl_df: engine.get_pnl_decomposition(),
+            heatmap_df: engine
+                .get_time_of_day_report()
+                .heatmap_df(|bucket| bucket.pnl),
+            trades: engine.get_all_trades(),
+            bars: engine.get_history_data().clone(),
+        }
+    }
+}
+
+struct DashboardState {
+    statistics: Value,
+    daily: Value,
+    pnl: Value,
+    heatmap: Value,
+    trades: Value,
+    bars: BarHistory,
+    markers: Vec<TradeMarker>,
+    ///Local plotly.js served at /plotly.min.js, redirected to PLOTLY_CDN if None.
+    plotly_js: Option<String>,
+}
+
+///Entry or exit a trade marks on the price chart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkerKind {
+    LongOpen,
+    LongClose,
+    ShortOpen,
+    ShortClose,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TradeMarker {
+    pub datetime: NaiveDateTime,
+    pub price: f64,
+    pub volume: f64,
+    pub kind: MarkerKind,
+    pub tradeid: String,
+}
+
+///Markers of trades in trade sequence. Trades without offset open or close by the net
+///position before them.
+pub fn trade_markers(trades: &[TradeData]) -> Vec<TradeMarker> {
+    let mut pos = 0.0;
+    trades
+        .iter()
+        .map(|trade| {
+            let opening = match trade.offset {
+                Offset::OPEN => true,
+                Offset::NONE => match trade.direction {
+                    Direction::LONG => pos >= 0.0,
+                    _ => pos <= 0.0,
+                },
+                _ => false,
+            };
+            let kind = match (trade.direction, opening) {
+                (Direction::LONG, true) => MarkerKind::LongOpen,
+                (Direction::LONG, false) => MarkerKind::ShortClose,
+                (_, true) => MarkerKind::ShortOpen,
+                (_, false) => MarkerKind::LongClose,
+            };
+            pos += match trade.direction {
+                Direction::LONG => trade.volume,
+                _ => -trade.volume,
+            };
+            TradeMarker {
+                datetime: trade.datetime,
+                price: trade.price,
+                volume: trade.volume,
+                kind,
+                tradeid: trade.tradeid.clone(),
+            }
+        })
+        .collect()
+}
+
+///Window of the price chart, the latest bars when neither end is given.
+#[derive(Debug, Default, Deserialize)]
+struct BarWindow {
+    start: Option<NaiveDateTime>,
+    end: Option<NaiveDateTime>,
+}
+
+///Build the dashboard routes:
+///- `/` embedded HTML page with equity, drawdown, pnl decomposition and time of day charts
+///- `/plotly.min.js` the file of the "web.plotly_js" setting, for browsers without internet
+///  access, else a redirect to the CDN
+///- `/api/statistics` statistics JSON
+///- `/api/daily` daily results, one array per column
+///- `/api/pnl` daily net pnl by component with running totals, one array per column
+///- `/api/heatmap` round trip pnl by hour and weekday of entry, one array per column
+///- `/api/trades` trade list
+///- `/api/bars?start=&end=` candles between start and end with the trade markers on them
+pub fn router(data: DashboardData) -> Router {
+    build_router(data, load_plotly_js(&get_settings()["web.plotly_js"]))
+}
+
+///Content of the plotly.js file at path, relative to the trader dir unless absolute, None
+///for an empty path or a file that can't be read.
+fn load_plotly_js(path: &str) -> Option<String> {
+    if path.is_empty() {
+        return None;
+    }
+    let path = get_trader_dir().join(path);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Some(content),
+        Err(e) => {
+            log::warn!("读取plotly.js失败，改用CDN：{}：{}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn build_router(data: DashboardData, plotly_js: Option<String>) -> Router {
+    let state = Arc::new(DashboardState {
+        statistics: serde_json::to_value(&data.statistics).unwrap(),
+        daily: dataframe_to_json(&data.daily_df),
+        pnl: dataframe_to_json(&data.pnl_df),
+        heatmap: dataframe_to_json(&data.heatmap_df),
+        trades: serde_json::to_value(&data.trades).unwrap(),
+        markers: trade_markers(&data.trades),
+        bars: data.bars,
+        plotly_js,
+    });
+
+    Router::new()
+        .route("/", get(index))
+        .route("/plotly.min.js", get(plotly))
+        .route("/api/statistics", get(statistics))
+        .route("/api/daily", get(daily))
+        .route("/api/pnl", get(pnl))
+        .route("/api/heatmap", get(heatmap))
+        .route("/api/trades", get(trades))
+        .route("/api/bars", get(bars))
+        .with_state(state)
+}
+
+///Serve the dashboard on addr (e.g. "0.0.0.0:8080"), blocking until the server exits.
+pub fn serve(data: DashboardData, addr: &str) -> std::io::Result<()> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(async {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        println!("回测结果页面：http://{}", listener.local_addr()?);
+        axum::serve(listener, router(data)).await
+    })
+}
+
+async fn index() -> Html<&'static str> {
+    Html(INDEX_HTML)
+}
+
+async fn plotly(State(state): State<Arc<DashboardState>>) -> Response {
+    match &state.plotly_js {
+        Some(plotly_js) => (
+            [
+                (header::CONTENT_TYPE, "text/javascript"),
+                (header::CACHE_CONTROL, "max-age=86400"),
+            ],
+            plotly_js.clone(),
+        )
+            .into_response(),
+        None => Redirect::temporary(PLOTLY_CDN).into_response(),
+    }
+}
+
+async fn statistics(State(state): State<Arc<DashboardState>>) -> Json<Value> {
+    Json(state.statistics.clone())
+}
+
+async fn daily(State(state): State<Arc<DashboardState>>) -> Json<Value> {
+    Json(state.daily.clone())
+}
+
+async fn pnl(State(state): State<Arc<DashboardState>>) -> Json<Value> {
+    Json(state.pnl.clone())
+}
+
+async fn heatmap(State(state): State<Arc<DashboardState>>) -> Json<Value> {
+    Json(state.heatmap.clone())
+}
+
+async fn trades(State(state): State<Arc<DashboardState>>) -> Json<Value> {
+    Json(state.trades.clone())
+}
+
+async fn bars(
+    State(state): State<Arc<DashboardState>>,
+    Query(window): Query<BarWindow>,
+) -> Json<Value> {
+    Json(bars_to_json(&state.bars, &state.markers, &window))
+}
+
+///Candles of the window, at most MAX_CHART_BARS from start, or up to end if start isn't
+///given, and the markers of trades from the first candle to the one after the last.
+fn bars_to_json(bars: &BarHistory, markers: &[TradeMarker], window: &BarWindow) -> Value {
+    let datetimes = bars.datetimes();
+    let mut first = window
+        .start
+        .map_or(0, |start| datetimes.partition_point(|dt| *dt < start));
+    let mut last = window
+        .end
+        .map_or(datetimes.len(), |end| {
+            datetimes.partition_point(|dt| *dt <= end)
+        })
+        .max(first);
+    if last - first > MAX_CHART_BARS {
+        if window.start.is_some() {
+            last = first + MAX_CHART_BARS;
+        } else {
+            first = last - MAX_CHART_BARS;
+        }
+    }
+
+    let format = |dt: &NaiveDateTime| dt.format("%Y-%m-%d %H:%M:%S").to_string();
+    let candles: Vec<_> = (first..last).filter_map(|ix| bars.get(ix)).collect();
+    let markers: Vec<&TradeMarker> = match (datetimes.get(first), last > first) {
+        (Some(from), true) => markers
+            .iter()
+            .filter(|marker| {
+                marker.datetime >= *from
+                    && datetimes.get(last).is_none_or(|to| marker.datetime < *to)
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    json!({
+        "datetime": candles.iter().map(|bar| format(&bar.datetime)).collect::<Vec<_>>(),
+        "open": candles.iter().map(|bar| bar.open_price).collect::<Vec<_>>(),
+        "high": candles.iter().map(|bar| bar.high_price).collect::<Vec<_>>(),
+        "low": candles.iter().map(|bar| bar.low_price).collect::<Vec<_>>(),
+        "close": candles.iter().map(|bar| bar.close_price).collect::<Vec<_>>(),
+        "markers": markers.iter().map(|marker| json!({
+            "datetime": format(&marker.datetime),
+            "price": marker.price,
+            "volume": marker.volume,
+            "kind": marker.kind,
+            "tradeid": marker.tradeid,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+///Convert a DataFrame into a column-oriented JSON object, dates become ISO strings.
+fn dataframe_to_json(df: &DataFrame) -> Value {
+    let mut columns = Map::new();
+    for series in df.get_columns() {
+        let values: Vec<Value> = match series.dtype() {
+            DataType::Date => series
+                .date()
+                .unwrap()
+                .as_date_iter()
+                .map(|d| d.map_or(Value::Null, |d| Value::from(d.to_string())))
+                .collect(),
+            _ => series
+                .cast(&DataType::Float64)
+                .unwrap()
+                .f64()
+                .unwrap()
+                .into_iter()
+                .map(|v| v.map_or(Value::Null, Value::from))
+                .collect(),
+        };
+        columns.insert(series.name().to_string(), Value::Array(values));
+    }
+    Value::Object(columns)
+}
+
+const INDEX_HTML: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>vnrs backtesting</title>
+<script src="/plotly.min.js"></script>
+<style>
+body { font-family: sans-serif; margin: 20px; }
+table { border-collapse: collapse; font-size: 13px; }
+td, th { border: 1px solid #ccc; padding: 2px 8px; text-align: right; }
+#layout { display: flex; gap: 20px; }
+#trades-box { max-height: 400px; overflow-y: auto; }
+</style>
+</head>
+<body>
+<div id="window">
+  <input type="datetime-local" id="start"> - <input type="datetime-local" id="end">
+  <button onclick="loadBars()">Show</button>
+</div>
+<div id="price" style="height:480px"></div>
+<div id="balance" style="height:360px"></div>
+<div id="drawdown" style="height:240px"></div>
+<div id="pnl" style="height:360px"></div>
+<div id="heatmap" style="height:480px"></div>
+<div id="layout">
+  <table id="statistics"></table>
+  <div id="trades-box"><table id="trades"></table></div>
+</div>
+<script>
+async function load() {
+  const [statistics, daily, pnl, heatmap, trades] = await Promise.all(
+    ["statistics", "daily", "pnl", "heatmap", "trades"].map(p => fetch("/api/" + p).then(r => r.json()))
+  );
+
+  const dates = daily.date || [];
+  let balance = [], drawdown = [], acc = statistics.capital, high = -Infinity;
+  (daily.net_pnl || []).forEach(pnl => {
+    acc += pnl;
+    high = Math.max(high, acc);
+    balance.push(acc);
+    drawdown.push(acc - high);
+  });
+
+  Plotly.newPlot("balance", [{x: dates, y: balance, type: "scatter", name: "balance"}],
+    {title: "Balance", margin: {t: 40}});
+  Plotly.newPlot("drawdown", [{x: dates, y: drawdown, type: "scatter", fill: "tozeroy",
+    name: "drawdown", line: {color: "#d62728"}}], {title: "Drawdown", margin: {t: 40}});
+
+  // Costs are negative, the stacked components of a date add up to the net pnl line
+  const components = ["holding_pnl", "trading_pnl", "commission", "slippage"];
+  Plotly.newPlot("pnl", components.map(name => ({x: pnl.date, y: pnl["cum_" + name],
+    type: "bar", name: name})).concat([{x: pnl.date, y: pnl.cum_net_pnl, type: "scatter",
+    name: "net_pnl", line: {color: "#000"}}]),
+    {title: "PnL Decomposition", barmode: "relative", margin: {t: 40}});
+
+  const weekdays = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+  const hours = heatmap.hour || [];
+  Plotly.newPlot("heatmap", [{x: weekdays, y: hours.map(h => h + ":00"),
+    z: hours.map((_, i) => weekdays.map(d => heatmap[d][i])), type: "heatmap",
+    colorscale: "RdBu", zmid: 0}], {title: "PnL by Entry Time", margin: {t: 40}});
+
+  document.getElementById("statistics").innerHTML = Object.entries(statistics)
+    .map(([k, v]) => `<tr><th>${k}</th><td>${typeof v === "number" ? v.toFixed(2) : v}</td></tr>`)
+    .join("");
+
+  const columns = ["datetime", "tradeid", "direction", "offset", "price", "volume", "reference"];
+  document.getElementById("trades").innerHTML =
+    "<tr>" + columns.map(c => `<th>${c}</th>`).join("") + "</tr>" +
+    trades.map(t => "<tr>" + columns.map(c => `<td>${t[c]}</td>`).join("") + "</tr>").join("");
+}
+const MARKER_STYLES = {
+  long_open: {name: "long open", symbol: "triangle-up", color: "#d62728"},
+  long_close: {name: "long close", symbol: "triangle-down-open", color: "#d62728"},
+  short_open: {name: "short open", symbol: "triangle-down", color: "#2ca02c"},
+  short_close: {name: "short close", symbol: "triangle-up-open", color: "#2ca02c"},
+};
+
+async function loadBars() {
+  const params = new URLSearchParams();
+  for (const key of ["start", "end"]) {
+    let value = document.getElementById(key).value;
+    if (value) {
+      params.set(key, value.length === 16 ? value + ":00" : value);
+    }
+  }
+  const bars = await fetch("/api/bars?" + params).then(r => r.json());
+
+  const traces = [{x: bars.datetime, open: bars.open, high: bars.high, low: bars.low,
+    close: bars.close, type: "candlestick", name: "price"}];
+  for (const [kind, style] of Object.entries(MARKER_STYLES)) {
+    const markers = bars.markers.filter(m => m.kind === kind);
+    traces.push({x: markers.map(m => m.datetime), y: markers.map(m => m.price),
+      text: markers.map(m => `${m.tradeid} ${m.volume}`), type: "scatter", mode: "markers",
+      name: style.name, marker: {symbol: style.symbol, color: style.color, size: 12}});
+  }
+  Plotly.newPlot("price", traces,
+    {title: "Price", margin: {t: 40}, xaxis: {rangeslider: {visible: false}}});
+}
+load();
+loadBars();
+</script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    ///Head and body of the responses of router to a GET of each of paths.
+    fn get(router: Router, paths: &[&str]) -> Vec<(String, String)> {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let listener = rt
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        rt.spawn(async move { axum::serve(listener, router).await });
+
+        paths
+            .iter()
+            .map(|path| {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                write!(
+                    stream,
+                    "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                    path
+                )
+                .unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                let (head, body) = response.split_once("\r\n\r\n").unwrap();
+                (head.to_string(), body.to_string())
+            })
+            .collect()
+    }
+
+    fn data() -> DashboardData {
+        let trade = TradeData {
+            gateway_name: "BACKTESTING".into(),
+            symbol: "IF888".into(),
+            exchange: Default::default(),
+            orderid: "1".to_string(),
+            tradeid: "1".to_string(),
+            direction: Direction::LONG,
+            offset: Offset::OPEN,
+            price: 100.0,
+            volume: 1.0,
+            datetime: NaiveDateTime::default(),
+            reference: String::new(),
+        };
+        DashboardData {
+            statistics: BacktestingStatistics {
+                capital: 1_000_000.0,
+                ..Default::default()
+            },
+            daily_df: df!("net_pnl" => [1.0, -2.0]).unwrap(),
+            pnl_df: DataFrame::default(),
+            heatmap_df: DataFrame::default(),
+            trades: vec![trade],
+            bars: BarHistory::new(),
+        }
+    }
+
+    #[test]
+    fn routes_serve_json_and_html() {
+        let responses = get(
+            build_router(data(), None),
+            &[
+                "/",
+                "/api/statistics",
+                "/api/daily",
+                "/api/trades",
+                "/api/bars",
+                "/plotly.min.js",
+            ],
+        );
+        for (head, _) in &responses[..5] {
+            assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        }
+        let json = |i: usize| serde_json::from_str::<Value>(&responses[i].1).unwrap();
+
+        assert!(responses[0].0.contains("content-type: text/html"));
+        assert!(responses[0].1.contains("src=\"/plotly.min.js\""));
+        assert_eq!(json(1)["capital"], 1_000_000.0);
+        assert_eq!(json(2), json!({"net_pnl": [1.0, -2.0]}));
+        assert_eq!(json(3)[0]["tradeid"], "1");
+        assert_eq!(json(4)["markers"], json!([]));
+        // Without a local copy plotly.js comes from the CDN
+        assert!(responses[5].0.starts_with("HTTP/1.1 307"));
+        assert!(responses[5].0.contains(PLOTLY_CDN));
+    }
+
+    #[test]
+    fn local_plotly_is_served() {
+        let responses = get(
+            build_router(data(), Some("/* plotly */".to_string())),
+            &["/plotly.min.js"],
+        );
+        let (head, body) = &responses[0];
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert!(head.contains("content-type: text/javascript"));
+        assert_eq!(body, "/* plotly */");
+        assert_eq!(load_plotly_js(""), None);
+    }
+}
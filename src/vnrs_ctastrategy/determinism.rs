@@ -0,0 +1,302 @@
+/*!Determinism audit of a backtest: the same job is run several times, each on a freshly loaded
+strategy class and concurrently as the batch runner would, and every run must give the trades,
+daily results and statistics of the first one bit for bit. Runs that differ are reported with
+the likely source: the clock, randomness, HashMap iteration order or statics shared between
+runs when fresh runs differ, or state kept over reset when only the run of a reused instance
+does. */
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+
+use chrono::Local;
+use serde_json::Value;
+
+use super::backtesting::BacktestingEngine;
+use super::base::ExternClass;
+use super::batch::{BatchJob, BatchRunner};
+use super::diff::{diff_results, ResultDiff, RunResult};
+use crate::error::{VnrsError, VnrsResult};
+
+///Why two runs of the same backtest differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NondeterminismSource {
+    ///Runs on fresh instances differ, e.g. the strategy reads the clock, draws random numbers,
+    ///iterates a HashMap or keeps state in statics.
+    Replay,
+    ///Only the run of an instance reset for reuse differs, reset keeps state of the last run.
+    StrategyState,
+    ///Trades and daily results match but the statistics don't.
+    Statistics,
+}
+
+impl fmt::Display for NondeterminismSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self {
+            NondeterminismSource::Replay => {
+                "fresh runs differ: clock, randomness, HashMap iteration order or statics"
+            }
+            NondeterminismSource::StrategyState => "reset instance keeps state of the last run",
+            NondeterminismSource::Statistics => "statistics differ on the same trades",
+        };
+        f.write_str(source)
+    }
+}
+
+///A run that differs from the first one.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    ///Index of the run, the run of the reset instance comes after the fresh ones.
+    pub run: usize,
+    pub source: NondeterminismSource,
+    pub diff: ResultDiff,
+    ///Names of the statistics that differ.
+    pub statistics: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeterminismReport {
+    ///Runs compared, including the one of the reset instance.
+    pub runs: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl DeterminismReport {
+    pub fn is_deterministic(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+
+struct Snapshot {
+    result: RunResult,
+    statistics: Value,
+}
+
+impl Snapshot {
+    fn take(mut engine: BacktestingEngine) -> VnrsResult<Self> {
+        let statistics = serde_json::to_value(engine.calculate_result_statistics(false))?;
+        Ok(Snapshot {
+            result: RunResult::from_engine(&engine),
+            statistics,
+        })
+    }
+
+    fn compare(&self, other: &Snapshot) -> (ResultDiff, Vec<String>) {
+        let diff = diff_results(&self.result, &other.result, 0.0);
+        let statistics = match (&self.statistics, &other.statistics) {
+            (Value::Object(left), Value::Object(right)) => left
+                .iter()
+                .filter(|(name, value)| right.get(name.as_str()) != Some(value))
+                .map(|(name, _)| name.clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        (diff, statistics)
+    }
+}
+
+///Run job runs times, at least twice, on the data and costs of runner and report the runs that
+///differ from the first one. Strategies that support reset get one more run on a reset
+///instance.
+pub fn audit_determinism(
+    runner: &BatchRunner,
+    job: &BatchJob,
+    runs: usize,
+) -> VnrsResult<DeterminismReport> {
+    audit(
+        runs,
+        runner.max_workers(),
+        || Ok(Arc::new(ExternClass::new(&job.strategy)?)),
+        |class| runner.run_engine(job, class).map_err(VnrsError::Engine),
+    )
+}
+
+fn audit<C, R>(runs: usize, workers: usize, new_class: C, run: R) -> VnrsResult<DeterminismReport>
+where
+    C: Fn() -> VnrsResult<Arc<ExternClass>> + Sync,
+    R: Fn(Arc<ExternClass>) -> VnrsResult<BacktestingEngine> + Sync,
+{
+    let runs = runs.max(2);
+    let classes = (0..runs)
+        .map(|_| new_class())
+        .collect::<VnrsResult<Vec<_>>>()?;
+
+    let mut snapshots = Vec::with_capacity(runs);
+    for chunk in classes.chunks(workers.max(1)) {
+        let taken = thread::scope(|scope| {
+            let handles: Vec<_> = chunk
+                .iter()
+                .map(|class| {
+                    let run = &run;
+                    scope.spawn(move || Snapshot::take(run(class.clone())?))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("backtest panicked"))
+                .collect::<VnrsResult<Vec<_>>>()
+        })?;
+        snapshots.extend(taken);
+    }
+
+    let mut report = DeterminismReport {
+        runs,
+        findings: Vec::new(),
+    };
+    // The instance of the first run waits in the pool of its class and is reset for one more
+    // run, unless the strategy refuses and it's just another fresh run.
+    if classes[0].pooled() > 0 {
+        snapshots.push(Snapshot::take(run(classes[0].clone())?)?);
+        report.runs += 1;
+    }
+    let reused = classes[0].reuses_instances();
+
+    let first = &snapshots[0];
+    for (i, snapshot) in snapshots.iter().enumerate().skip(1) {
+        let (diff, statistics) = first.compare(snapshot);
+        let source = if diff.is_empty() && statistics.is_empty() {
+            continue;
+        } else if i == runs && reused {
+            // State kept over reset only tells apart from replay when fresh runs agree
+            if !report.is_deterministic() {
+                continue;
+            }
+            NondeterminismSource::StrategyState
+        } else if diff.is_empty() {
+            NondeterminismSource::Statistics
+        } else {
+            NondeterminismSource::Replay
+        };
+        report.findings.push(Finding {
+            run: i,
+            source,
+            diff,
+            statistics,
+        });
+    }
+
+    if report.is_deterministic() {
+        output(&format!("确定性检查：{}次回测结果一致", report.runs));
+    }
+    for finding in &report.findings {
+        output(&format!(
+            "确定性检查：第{}次回测与第1次不一致，{}笔成交、{}个交易日、{}项统计指标不同（{}）",
+            finding.run + 1,
+            finding.diff.trade_mismatches.len(),
+            finding.diff.day_deltas.len(),
+            finding.statistics.len(),
+            finding.source
+        ));
+    }
+    Ok(report)
+}
+
+fn output(msg: &str) {
+    println!("{datetime}\t{msg}", datetime = Local::now(), msg = msg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::vnrs::trader::synthetic::SyntheticBars;
+    use crate::vnrs_ctastrategy::sdk::{strategy_class, CtaStrategy, StrategyContext};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn run_bars(class: Arc<ExternClass>) -> VnrsResult<BacktestingEngine> {
+        let bars = SyntheticBars {
+            symbol: "IF888".to_string(),
+            exchange: Exchange::CFFEX,
+            count: 30,
+            start_price: 3500.0,
+            pricetick: 0.2,
+            ..Default::default()
+        }
+        .generate();
+        let mut engine = BacktestingEngine::new();
+        engine.set_output_callback(|_| {});
+        engine.set_parameters(
+            "IF888.CFFEX",
+            Interval::MINUTE,
+            bars[0].datetime,
+            bars[bars.len() - 1].datetime,
+            0.0,
+            0.0,
+            300.0,
+            0.2,
+            1_000_000.0,
+            BacktestingMode::BAR,
+            0.0,
+            0,
+            120,
+        )?;
+        engine.set_history_data(bars);
+        engine.add_strategy(class, "")?;
+        engine.run_backtesting()?;
+        Ok(engine)
+    }
+
+    fn audit_strategy<S: CtaStrategy>(runs: usize) -> DeterminismReport {
+        audit(
+            runs,
+            2,
+            || Ok(Arc::new(strategy_class::<S>("audit"))),
+            run_bars,
+        )
+        .unwrap()
+    }
+
+    ///Buys on the fifth bar it sees.
+    struct Counting(usize);
+    impl CtaStrategy for Counting {
+        fn new(_context: &StrategyContext, _setting: &[(String, String)]) -> Self {
+            Counting(0)
+        }
+        fn reset(&mut self, _context: &StrategyContext, _setting: &[(String, String)]) -> bool {
+            true
+        }
+        fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData) {
+            self.0 += 1;
+            if self.0 == 5 {
+                context.buy(bar.close_price + 5.0, 1.0, false);
+            }
+        }
+    }
+
+    ///Buys on the fifth bar any instance sees.
+    struct Shared;
+    static SHARED_BARS: AtomicUsize = AtomicUsize::new(0);
+    impl CtaStrategy for Shared {
+        fn new(_context: &StrategyContext, _setting: &[(String, String)]) -> Self {
+            Shared
+        }
+        fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData) {
+            if SHARED_BARS.fetch_add(1, Ordering::SeqCst) == 4 {
+                context.buy(bar.close_price + 5.0, 1.0, false);
+            }
+        }
+    }
+
+    #[test]
+    fn sources_of_nondeterminism() {
+        // Reset keeps the bar count, only the run of the reused instance misses the trade
+        let report = audit_strategy::<Counting>(3);
+        assert_eq!(report.runs, 4);
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].run, 3);
+        assert_eq!(
+            report.findings[0].source,
+            NondeterminismSource::StrategyState
+        );
+        assert_eq!(report.findings[0].diff.trade_mismatches.len(), 1);
+        assert!(!report.findings[0].statistics.is_empty());
+
+        // Shared refuses reset, its last run is just another fresh one
+        let report = audit_strategy::<Shared>(2);
+        assert_eq!(report.runs, 3);
+        assert!(!report.is_deterministic());
+        assert!(report
+            .findings
+            .iter()
+            .all(|finding| finding.source == NondeterminismSource::Replay));
+    }
+}
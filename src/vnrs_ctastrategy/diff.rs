@@ -0,0 +1,269 @@
+/*!Comparison of the trades and daily results of two runs of the same backtest, e.g. before and
+after a change to the fill logic or a dependency bump. Runs are saved with RunResult::save and
+diffed with diff_results, which reports the first bar they diverge on, the trades that don't
+match and the days whose net pnl differs. */
+use std::fs;
+use std::path::Path;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::backtesting::{BacktestingEngine, DailyResult};
+use crate::error::VnrsResult;
+use crate::vnrs::trader::object::TradeData;
+
+///Trades and daily results of a run. The daily results don't repeat the trades of their day.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunResult {
+    pub trades: Vec<TradeData>,
+    pub daily_results: Vec<DailyResult>,
+}
+
+impl RunResult {
+    ///Result of the run of engine, after calculate_result or calculate_result_statistics.
+    pub fn from_engine(engine: &BacktestingEngine) -> Self {
+        RunResult {
+            trades: engine.get_all_trades(),
+            daily_results: engine
+                .get_daily_results()
+                .map(|result| DailyResult {
+                    trades: Vec::new(),
+                    ..result.clone()
+                })
+                .collect(),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> VnrsResult<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> VnrsResult<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+///Trade of index in trade sequence differing between the runs, None where a run has fewer
+///trades.
+#[derive(Debug, Clone)]
+pub struct TradeMismatch {
+    pub index: usize,
+    pub left: Option<TradeData>,
+    pub right: Option<TradeData>,
+}
+
+impl TradeMismatch {
+    ///The earlier datetime of the two trades.
+    pub fn datetime(&self) -> NaiveDateTime {
+        match (&self.left, &self.right) {
+            (Some(left), Some(right)) => left.datetime.min(right.datetime),
+            (Some(trade), None) | (None, Some(trade)) => trade.datetime,
+            (None, None) => NaiveDateTime::default(),
+        }
+    }
+}
+
+///Net pnl of a day in both runs, 0 in a run without the day.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DayDelta {
+    pub date: NaiveDate,
+    pub left_net_pnl: f64,
+    pub right_net_pnl: f64,
+}
+
+impl DayDelta {
+    pub fn delta(&self) -> f64 {
+        self.right_net_pnl - self.left_net_pnl
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ResultDiff {
+    ///Bar the runs first differ on, the datetime of the first mismatched trade or the start
+    ///of the first day with a different pnl if that is an earlier day, e.g. after a change of
+    ///costs. None for matching runs.
+    pub first_divergence: Option<NaiveDateTime>,
+    pub trade_mismatches: Vec<TradeMismatch>,
+    ///Days whose net pnl differs, or that only one run has, in date order.
+    pub day_deltas: Vec<DayDelta>,
+}
+
+impl ResultDiff {
+    pub fn is_empty(&self) -> bool {
+        self.first_divergence.is_none()
+    }
+
+    ///One row per day in day_deltas with the pnl of both runs and their delta.
+    pub fn daily_df(&self) -> DataFrame {
+        let column = |name: &str, f: fn(&DayDelta) -> f64| {
+            Series::new(name, self.day_deltas.iter().map(f).collect::<Vec<f64>>())
+        };
+        DataFrame::new(vec![
+            Series::new(
+                "date",
+                self.day_deltas
+                    .iter()
+                    .map(|day| day.date)
+                    .collect::<Vec<NaiveDate>>(),
+            ),
+            column("left_net_pnl", |day| day.left_net_pnl),
+            column("right_net_pnl", |day| day.right_net_pnl),
+            column("delta", DayDelta::delta),
+        ])
+        .unwrap()
+    }
+}
+
+///Trades are the same if datetime, direction, offset, price and volume are, order and trade
+///ids may be numbered differently.
+fn same_trade(left: &TradeData, right: &TradeData, tolerance: f64) -> bool {
+    left.datetime == right.datetime
+        && left.direction == right.direction
+        && left.offset == right.offset
+        && (left.price - right.price).abs() <= tolerance
+        && (left.volume - right.volume).abs() <= tolerance
+}
+
+///Differences of right from left, prices, volumes and pnl within tolerance counting as equal.
+pub fn diff_results(left: &RunResult, right: &RunResult, tolerance: f64) -> ResultDiff {
+    let trade_count = left.trades.len().max(right.trades.len());
+    let trade_mismatches: Vec<TradeMismatch> = (0..trade_count)
+        .filter_map(|index| {
+            let (l, r) = (left.trades.get(index), right.trades.get(index));
+            match (l, r) {
+                (Some(l), Some(r)) if same_trade(l, r, tolerance) => None,
+                _ => Some(TradeMismatch {
+                    index,
+                    left: l.cloned(),
+                    right: r.cloned(),
+                }),
+            }
+        })
+        .collect();
+
+    let mut days: Vec<NaiveDate> = left
+        .daily_results
+        .iter()
+        .chain(&right.daily_results)
+        .map(|result| result.date)
+        .collect();
+    days.sort();
+    days.dedup();
+    let net_pnl = |results: &[DailyResult], date: NaiveDate| {
+        results
+            .iter()
+            .find(|result| result.date == date)
+            .map(|result| result.net_pnl)
+    };
+    let day_deltas: Vec<DayDelta> = days
+        .into_iter()
+        .filter_map(|date| {
+            let l = net_pnl(&left.daily_results, date);
+            let r = net_pnl(&right.daily_results, date);
+            let differs = match (l, r) {
+                (Some(l), Some(r)) => (l - r).abs() > tolerance,
+                _ => true,
+            };
+            differs.then(|| DayDelta {
+                date,
+                left_net_pnl: l.unwrap_or_default(),
+                right_net_pnl: r.unwrap_or_default(),
+            })
+        })
+        .collect();
+
+    let first_trade = trade_mismatches.first().map(TradeMismatch::datetime);
+    let first_day = day_deltas.first().map(|day| day.date);
+    let first_divergence = match (first_trade, first_day) {
+        (Some(trade), Some(day)) if day < trade.date() => Some(day.into()),
+        (Some(trade), _) => Some(trade),
+        (None, day) => day.map(NaiveDateTime::from),
+    };
+    ResultDiff {
+        first_divergence,
+        trade_mismatches,
+        day_deltas,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::constant::{Direction, Offset};
+
+    fn trade(day: u32, price: f64, tradeid: &str) -> TradeData {
+        TradeData {
+            gateway_name: "BACKTESTING".into(),
+            symbol: "IF888".into(),
+            exchange: Default::default(),
+            orderid: tradeid.to_string(),
+            tradeid: tradeid.to_string(),
+            direction: Direction::LONG,
+            offset: Offset::OPEN,
+            price,
+            volume: 1.0,
+            datetime: NaiveDate::from_ymd_opt(2024, 1, day)
+                .unwrap()
+                .and_hms_opt(10, 0, 0)
+                .unwrap(),
+            reference: String::new(),
+        }
+    }
+
+    fn day(day: u32, net_pnl: f64) -> DailyResult {
+        DailyResult {
+            net_pnl,
+            ..DailyResult::new(NaiveDate::from_ymd_opt(2024, 1, day).unwrap(), 100.0)
+        }
+    }
+
+    #[test]
+    fn divergence_of_runs() {
+        let left = RunResult {
+            trades: vec![trade(2, 100.0, "1"), trade(3, 101.0, "2")],
+            daily_results: vec![day(2, 10.0), day(3, 20.0)],
+        };
+        // Ids don't count
+        let mut same = left.clone();
+        same.trades[0].tradeid = "7".to_string();
+        assert!(diff_results(&left, &same, 1e-9).is_empty());
+
+        let right = RunResult {
+            trades: vec![
+                trade(2, 100.0, "1"),
+                trade(3, 101.2, "2"),
+                trade(4, 99.0, "3"),
+            ],
+            daily_results: vec![day(2, 10.0), day(3, 14.0), day(4, -5.0)],
+        };
+        let diff = diff_results(&left, &right, 1e-9);
+        assert_eq!(diff.first_divergence, Some(left.trades[1].datetime));
+        let indexes: Vec<usize> = diff.trade_mismatches.iter().map(|m| m.index).collect();
+        assert_eq!(indexes, vec![1, 2]);
+        assert!(diff.trade_mismatches[1].left.is_none());
+
+        let df = diff.daily_df();
+        assert_eq!(df.height(), 2);
+        let deltas: Vec<Option<f64>> = df
+            .column("delta")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(deltas, vec![Some(-6.0), Some(-5.0)]);
+
+        // A cost change moves the pnl without touching the trades
+        let mut costlier = left.clone();
+        costlier.daily_results[1].net_pnl = 19.0;
+        let diff = diff_results(&left, &costlier, 1e-9);
+        assert!(diff.trade_mismatches.is_empty());
+        assert_eq!(
+            diff.first_divergence,
+            Some(NaiveDate::from_ymd_opt(2024, 1, 3).unwrap().into())
+        );
+        assert!(diff_results(&left, &costlier, 2.0).is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+/*!Maximum favorable and adverse excursion (MFE/MAE) of round-trip trades, the best and worst
+price reached while a position was held, for tuning take-profit and stop-loss levels. */
+use chrono::NaiveDateTime;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::vnrs::trader::constant::Direction;
+use crate::vnrs::trader::object::TradeData;
+
+const POS_TOLERANCE: f64 = 1e-9;
+
+type TripGroup = (&'static str, fn(&RoundTrip) -> bool);
+type TripColumn = (&'static str, fn(&RoundTrip) -> f64);
+
+///Quantiles of the excursion distributions.
+pub const EXCURSION_QUANTILES: [(&str, f64); 5] = [
+    ("p10", 0.1),
+    ("p25", 0.25),
+    ("p50", 0.5),
+    ("p75", 0.75),
+    ("p90", 0.9),
+];
+
+///Position held from flat, or a reversal, until flat or the next reversal. Prices are volume
+///weighted, mfe and mae are price distances from the entry price.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoundTrip {
+    pub direction: Direction,
+    pub entry_datetime: NaiveDateTime,
+    pub exit_datetime: NaiveDateTime,
+    pub entry_price: f64,
+    pub exit_price: f64,
+    ///Largest position of the trip.
+    pub volume: f64,
+    ///Pnl of the closing trades against the entry price, before costs.
+    pub pnl: f64,
+    pub mfe: f64,
+    pub mae: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OpenTrip {
+    direction: Direction,
+    entry_datetime: NaiveDateTime,
+    pos: f64,
+    entry_price: f64,
+    volume: f64,
+    closed_volume: f64,
+    exit_value: f64,
+    pnl: f64,
+    high: f64,
+    low: f64,
+}
+
+impl OpenTrip {
+    fn new(entry_datetime: NaiveDateTime, pos: f64, price: f64) -> Self {
+        OpenTrip {
+            direction: if pos > 0.0 {
+                Direction::LONG
+            } else {
+                Direction::SHORT
+            },
+            entry_datetime,
+            pos,
+            entry_price: price,
+            volume: pos.abs(),
+            closed_volume: 0.0,
+            exit_value: 0.0,
+            pnl: 0.0,
+            high: price,
+            low: price,
+        }
+    }
+
+    fn finish(self, exit_datetime: NaiveDateTime) -> RoundTrip {
+        let (mfe, mae) = match self.direction {
+            Direction::LONG => (self.high - self.entry_price, self.entry_price - self.low),
+            _ => (self.entry_price - self.low, self.high - self.entry_price),
+        };
+        RoundTrip {
+            direction: self.direction,
+            entry_datetime: self.entry_datetime,
+            exit_datetime,
+            entry_price: self.entry_price,
+            exit_price: self.exit_value / self.closed_volume,
+            volume: self.volume,
+            pnl: self.pnl,
+            mfe,
+            mae,
+        }
+    }
+}
+
+///Follows the position through trades and bars, keeping the price extremes of the open trip.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ExcursionTracker {
+    open: Option<OpenTrip>,
+    round_trips: Vec<RoundTrip>,
+}
+
+impl ExcursionTracker {
+    ///Extend the extremes of the open trip by a bar. Call before the trades of the bar, whose
+    ///high and low may come before or after the fills.
+    pub fn update_bar(&mut self, high: f64, low: f64) {
+        if let Some(trip) = &mut self.open {
+            trip.high = trip.high.max(high);
+            trip.low = trip.low.min(low);
+        }
+    }
+
+    pub fn update_trade(&mut self, trade: &TradeData, size: f64) {
+        let mut change = match trade.direction {
+            Direction::LONG => trade.volume,
+            _ => -trade.volume,
+        };
+
+        if let Some(trip) = &mut self.open {
+            trip.high = trip.high.max(trade.price);
+            trip.low = trip.low.min(trade.price);
+
+            if trip.pos.signum() == change.signum() {
+                let pos = trip.pos + change;
+                trip.entry_price = (trip.pos * trip.entry_price + change * trade.price) / pos;
+                trip.pos = pos;
+                trip.volume = trip.volume.max(pos.abs());
+                return;
+            }
+
+            let closed = trip.pos.abs().min(change.abs());
+            let sign = trip.pos.signum();
+            trip.pnl += closed * sign * (trade.price - trip.entry_price) * size;
+            trip.exit_value += closed * trade.price;
+            trip.closed_volume += closed;
+            trip.pos -= closed * sign;
+            change += closed * sign;
+            if trip.pos.abs() > POS_TOLERANCE {
+                return;
+            }
+            let trip = self.open.take().unwrap();
+            self.round_trips.push(trip.finish(trade.datetime));
+        }
+
+        // Rest of a reversal opens the next trip
+        if change.abs() > POS_TOLERANCE {
+            self.open = Some(OpenTrip::new(trade.datetime, change, trade.price));
+        }
+    }
+
+    ///Start with a position opened before the first trade, entered at price.
+    pub fn carry_position(&mut self, datetime: NaiveDateTime, pos: f64, price: f64) {
+        if pos != 0.0 {
+            self.open = Some(OpenTrip::new(datetime, pos, price));
+        }
+    }
+
+    ///Finished round trips in exit order, a position still open isn't included.
+    pub fn round_trips(&self) -> &[RoundTrip] {
+        &self.round_trips
+    }
+}
+
+///Convert round trips into a DataFrame, one row per trip.
+pub fn round_trips_df(round_trips: &[RoundTrip]) -> DataFrame {
+    let column = |name: &str, f: fn(&RoundTrip) -> f64| {
+        Series::new(name, round_trips.iter().map(f).collect::<Vec<f64>>())
+    };
+    DataFrame::new(vec![
+        Series::new(
+            "direction",
+            round_trips
+                .iter()
+                .map(|trip| format!("{:?}", trip.direction))
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "entry_datetime",
+            round_trips
+                .iter()
+                .map(|trip| trip.entry_datetime)
+                .collect::<Vec<NaiveDateTime>>(),
+        ),
+        Series::new(
+            "exit_datetime",
+            round_trips
+                .iter()
+                .map(|trip| trip.exit_datetime)
+                .collect::<Vec<NaiveDateTime>>(),
+        ),
+        column("entry_price", |trip| trip.entry_price),
+        column("exit_price", |trip| trip.exit_price),
+        column("volume", |trip| trip.volume),
+        column("pnl", |trip| trip.pnl),
+        column("mfe", |trip| trip.mfe),
+        column("mae", |trip| trip.mae),
+    ])
+    .unwrap()
+}
+
+///Distributions of mfe and mae over all, winning and losing trips, one row per metric and
+///group with the count, mean and EXCURSION_QUANTILES.
+pub fn excursion_distribution(round_trips: &[RoundTrip]) -> DataFrame {
+    let groups: [TripGroup; 3] = [
+        ("all", |_| true),
+        ("win", |trip| trip.pnl > 0.0),
+        ("loss", |trip| trip.pnl <= 0.0),
+    ];
+    let metrics: [TripColumn; 2] = [("mfe", |trip| trip.mfe), ("mae", |trip| trip.mae)];
+
+    let mut metric_column = Vec::new();
+    let mut group_column = Vec::new();
+    let mut counts = Vec::new();
+    let mut means = Vec::new();
+    let mut quantiles = vec![Vec::new(); EXCURSION_QUANTILES.len()];
+    for (metric, value) in metrics {
+        for (group, filter) in groups {
+            let mut values: Vec<f64> = round_trips
+                .iter()
+                .filter(|trip| filter(trip))
+                .map(value)
+                .collect();
+            values.sort_by(f64::total_cmp);
+
+            metric_column.push(metric);
+            group_column.push(group);
+            counts.push(values.len() as i64);
+            means.push(if values.is_empty() {
+                f64::NAN
+            } else {
+                values.iter().sum::<f64>() / values.len() as f64
+            });
+            for (column, (_, q)) in quantiles.iter_mut().zip(EXCURSION_QUANTILES) {
+                column.push(quantile(&values, q));
+            }
+        }
+    }
+
+    let mut columns = vec![
+        Series::new("metric", metric_column),
+        Series::new("group", group_column),
+        Series::new("count", counts),
+        Series::new("mean", means),
+    ];
+    for (values, (name, _)) in quantiles.into_iter().zip(EXCURSION_QUANTILES) {
+        columns.push(Series::new(name, values));
+    }
+    DataFrame::new(columns).unwrap()
+}
+
+///Quantile of sorted values interpolated between the closest ranks, NaN if empty.
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let rank = q * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::constant::{Exchange, Offset};
+    use chrono::NaiveDate;
+
+    fn trade(minute: u32, direction: Direction, price: f64, volume: f64) -> TradeData {
+        TradeData {
+            datetime: NaiveDate::from_ymd_opt(2024, 1, 22)
+                .unwrap()
+                .and_hms_opt(9, minute, 0)
+                .unwrap(),
+            gateway_name: "BACKTESTING".into(),
+            symbol: "IF888".into(),
+            exchange: Exchange::CFFEX,
+            orderid: minute.to_string(),
+            tradeid: minute.to_string(),
+            direction,
+            offset: Offset::NONE,
+            price,
+            volume,
+            reference: String::new(),
+        }
+    }
+
+    #[test]
+    fn excursions_of_round_trips() {
+        let mut tracker = ExcursionTracker::default();
+        tracker.update_trade(&trade(0, Direction::LONG, 100.0, 1.0), 10.0);
+        tracker.update_bar(103.0, 97.0);
+        // Adding to the position averages the entry price
+        tracker.update_trade(&trade(1, Direction::LONG, 98.0, 1.0), 10.0);
+        tracker.update_bar(106.0, 98.0);
+        // Reversal closes the long trip and opens a short one
+        tracker.update_trade(&trade(2, Direction::SHORT, 105.0, 3.0), 10.0);
+        tracker.update_bar(107.0, 101.0);
+        tracker.update_trade(&trade(3, Direction::LONG, 102.0, 1.0), 10.0);
+
+        let round_trips = tracker.round_trips();
+        assert_eq!(round_trips.len(), 2);
+        let long = &round_trips[0];
+        assert_eq!(long.entry_price, 99.0);
+        assert_eq!(long.volume, 2.0);
+        assert_eq!(long.pnl, 2.0 * 6.0 * 10.0);
+        assert_eq!((long.mfe, long.mae), (7.0, 2.0));
+        let short = &round_trips[1];
+        assert_eq!(short.direction, Direction::SHORT);
+        assert_eq!(short.pnl, 30.0);
+        assert_eq!((short.mfe, short.mae), (4.0, 2.0));
+
+        let distribution = excursion_distribution(round_trips);
+        assert_eq!(distribution.height(), 6);
+        let p50 = distribution.column("p50").unwrap().f64().unwrap();
+        assert_eq!(p50.get(0), Some(5.5));
+        assert!((quantile(&[1.0, 2.0, 3.0, 4.0, 5.0], 0.9) - 4.6).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,373 @@
+/*!Execution quality of live orders: each order in an audit log is replayed against the recorded
+ticks with the fill model of the backtester in tick mode and the model fill compared with the
+real one. The slippage measured calibrates the slippage and fill assumptions of backtests. */
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use polars::prelude::*;
+
+use crate::vnrs::trader::audit::{AuditEntry, AuditLog};
+use crate::vnrs::trader::constant::{Direction, Status};
+use crate::vnrs::trader::object::TickData;
+
+///One live order with the fill the backtester would have given it and the real one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionRecord {
+    pub vt_orderid: String,
+    pub vt_symbol: String,
+    pub direction: Direction,
+    pub price: f64,
+    pub volume: f64,
+    ///Recording time of the first update of the order.
+    pub submitted: NaiveDateTime,
+    ///Price and time of the model fill, None if no tick crossed the order while it was live.
+    pub model_fill: Option<(f64, NaiveDateTime)>,
+    ///Volume weighted price and time of the first trade, None if it didn't trade.
+    pub real_fill: Option<(f64, NaiveDateTime)>,
+    pub real_volume: f64,
+}
+
+impl ExecutionRecord {
+    ///Real price worse than the model one, in price units per unit of volume. Negative when
+    ///the market did better than the model.
+    pub fn slippage(&self) -> Option<f64> {
+        let (model_price, _) = self.model_fill?;
+        let (real_price, _) = self.real_fill?;
+        match self.direction {
+            Direction::LONG => Some(real_price - model_price),
+            _ => Some(model_price - real_price),
+        }
+    }
+
+    ///Seconds the real fill came after the model one.
+    pub fn delay(&self) -> Option<f64> {
+        let (_, model_time) = self.model_fill?;
+        let (_, real_time) = self.real_fill?;
+        Some((real_time - model_time).num_milliseconds() as f64 / 1000.0)
+    }
+}
+
+///Totals of an execution replay.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecutionSummary {
+    pub orders: usize,
+    pub model_filled: usize,
+    pub real_filled: usize,
+    ///Filled by the model but not for real, fills the backtest is too optimistic about.
+    pub missed: usize,
+    ///Filled for real but not by the model.
+    pub unexpected: usize,
+    ///Volume weighted slippage of orders filled both ways.
+    pub mean_slippage: f64,
+    pub mean_delay: f64,
+}
+
+impl ExecutionSummary {
+    pub fn new(records: &[ExecutionRecord]) -> Self {
+        let mut summary = ExecutionSummary {
+            orders: records.len(),
+            ..Default::default()
+        };
+        let mut volume = 0.0;
+        let mut both = 0;
+        for record in records {
+            match (record.model_fill.is_some(), record.real_fill.is_some()) {
+                (true, true) => {
+                    summary.model_filled += 1;
+                    summary.real_filled += 1;
+                }
+                (true, false) => {
+                    summary.model_filled += 1;
+                    summary.missed += 1;
+                }
+                (false, true) => {
+                    summary.real_filled += 1;
+                    summary.unexpected += 1;
+                }
+                (false, false) => {}
+            }
+            if let (Some(slippage), Some(delay)) = (record.slippage(), record.delay()) {
+                summary.mean_slippage += slippage * record.real_volume;
+                summary.mean_delay += delay;
+                volume += record.real_volume;
+                both += 1;
+            }
+        }
+        if volume > 0.0 {
+            summary.mean_slippage /= volume;
+        }
+        if both > 0 {
+            summary.mean_delay /= both as f64;
+        }
+        summary
+    }
+
+    ///Slippage setting of the backtest matching the measured one, not below 0.
+    pub fn suggested_slippage(&self) -> f64 {
+        self.mean_slippage.max(0.0)
+    }
+}
+
+///Replay the limit orders of log against ticks sorted by datetime, e.g. log.ticks() or ticks
+///loaded from the database. An order crosses the first tick after it was submitted and before
+///it was cancelled or rejected whose ask, for a buy, or bid, for a sell, reaches its price, and
+///fills at the better of its price and that quote like in the backtester.
+pub fn replay_executions(log: &AuditLog, ticks: &[TickData]) -> Vec<ExecutionRecord> {
+    let mut ticks_by_symbol: HashMap<String, Vec<&TickData>> = HashMap::new();
+    for tick in ticks {
+        ticks_by_symbol
+            .entry(tick.vt_symbol())
+            .or_default()
+            .push(tick);
+    }
+
+    // Submission, final update and its time of every order in the order of submission
+    let mut orders = Vec::new();
+    let mut order_index = HashMap::new();
+    // Volume, turnover and time of the first trade by order
+    let mut fills: HashMap<String, (f64, f64, NaiveDateTime)> = HashMap::new();
+    for record in &log.records {
+        match &record.entry {
+            AuditEntry::Order(order) => {
+                let vt_orderid = format!("{}.{}", record.gateway_name, order.orderid);
+                match order_index.get(&vt_orderid) {
+                    Some(&index) => {
+                        let (_, _, last, ended) = &mut orders[index];
+                        *last = order.clone();
+                        *ended = record.time;
+                    }
+                    None => {
+                        order_index.insert(vt_orderid.clone(), orders.len());
+                        orders.push((vt_orderid, record.time, order.clone(), record.time));
+                    }
+                }
+            }
+            AuditEntry::Trade(trade) => {
+                let vt_orderid = format!("{}.{}", record.gateway_name, trade.orderid);
+                let fill = fills
+                    .entry(vt_orderid)
+                    .or_insert((0.0, 0.0, trade.datetime));
+                fill.0 += trade.volume;
+                fill.1 += trade.price * trade.volume;
+            }
+            _ => {}
+        }
+    }
+
+    orders
+        .into_iter()
+        .map(|(vt_orderid, submitted, order, ended)| {
+            let vt_symbol = order.vt_symbol();
+            let until = match order.status {
+                Status::CANCELLED | Status::REJECTED => Some(ended),
+                _ => None,
+            };
+            let model_fill = ticks_by_symbol.get(&vt_symbol).and_then(|ticks| {
+                ticks
+                    .iter()
+                    .skip_while(|tick| tick.datetime < submitted)
+                    .take_while(|tick| until.is_none_or(|until| tick.datetime <= until))
+                    .find_map(|tick| match order.direction {
+                        Direction::LONG
+                            if tick.ask_price_1 > 0.0 && order.price >= tick.ask_price_1 =>
+                        {
+                            Some((order.price.min(tick.ask_price_1), tick.datetime))
+                        }
+                        Direction::SHORT
+                            if tick.bid_price_1 > 0.0 && order.price <= tick.bid_price_1 =>
+                        {
+                            Some((order.price.max(tick.bid_price_1), tick.datetime))
+                        }
+                        _ => None,
+                    })
+            });
+            let (real_fill, real_volume) = match fills.get(&vt_orderid) {
+                Some(&(volume, turnover, datetime)) if volume > 0.0 => {
+                    (Some((turnover / volume, datetime)), volume)
+                }
+                _ => (None, 0.0),
+            };
+            ExecutionRecord {
+                vt_orderid,
+                vt_symbol,
+                direction: order.direction,
+                price: order.price,
+                volume: order.volume,
+                submitted,
+                model_fill,
+                real_fill,
+                real_volume,
+            }
+        })
+        .collect()
+}
+
+///Execution records as a DataFrame, one row per order with nulls where an order wasn't filled.
+pub fn executions_df(records: &[ExecutionRecord]) -> DataFrame {
+    let column = |name: &str, f: fn(&ExecutionRecord) -> Option<f64>| {
+        Series::new(name, records.iter().map(f).collect::<Vec<Option<f64>>>())
+    };
+    DataFrame::new(vec![
+        Series::new(
+            "vt_orderid",
+            records
+                .iter()
+                .map(|record| record.vt_orderid.clone())
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "vt_symbol",
+            records
+                .iter()
+                .map(|record| record.vt_symbol.clone())
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "direction",
+            records
+                .iter()
+                .map(|record| format!("{:?}", record.direction))
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "submitted",
+            records
+                .iter()
+                .map(|record| record.submitted)
+                .collect::<Vec<NaiveDateTime>>(),
+        ),
+        column("price", |record| Some(record.price)),
+        column("volume", |record| Some(record.volume)),
+        column("model_price", |record| {
+            record.model_fill.map(|(price, _)| price)
+        }),
+        column("real_price", |record| {
+            record.real_fill.map(|(price, _)| price)
+        }),
+        column("real_volume", |record| Some(record.real_volume)),
+        column("slippage", ExecutionRecord::slippage),
+        column("delay", ExecutionRecord::delay),
+    ])
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::audit::AuditRecord;
+    use crate::vnrs::trader::constant::{Exchange, Offset};
+    use crate::vnrs::trader::object::{OrderRequest, TradeData};
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn tick(time: &str, bid: f64, ask: f64) -> TickData {
+        TickData {
+            symbol: "rb2410".into(),
+            exchange: Exchange::SHFE,
+            datetime: at(time),
+            bid_price_1: bid,
+            ask_price_1: ask,
+            ..Default::default()
+        }
+    }
+
+    fn record(time: &str, entry: AuditEntry) -> AuditRecord {
+        AuditRecord {
+            time: at(time),
+            gateway_name: "CTP".to_string(),
+            entry,
+        }
+    }
+
+    #[test]
+    fn compare_model_and_real_fills() {
+        let order = |orderid: &str, direction: Direction, price: f64, status: Status| {
+            let req = OrderRequest {
+                symbol: "rb2410".into(),
+                exchange: Exchange::SHFE,
+                direction,
+                price,
+                volume: 2.0,
+                ..Default::default()
+            };
+            let mut order = req.create_order_data(orderid, "CTP".into());
+            order.status = status;
+            AuditEntry::Order(order)
+        };
+        let trade = |orderid: &str, direction: Direction, price: f64, volume: f64, time: &str| {
+            AuditEntry::Trade(TradeData {
+                gateway_name: "CTP".into(),
+                symbol: "rb2410".into(),
+                exchange: Exchange::SHFE,
+                orderid: orderid.to_string(),
+                tradeid: format!("{}{}", orderid, price),
+                direction,
+                offset: Offset::OPEN,
+                price,
+                volume,
+                datetime: at(time),
+                reference: String::new(),
+            })
+        };
+        let log = AuditLog {
+            records: vec![
+                record(
+                    "2024-10-08 09:00:00",
+                    order("1", Direction::LONG, 3502.0, Status::NOTTRADED),
+                ),
+                record(
+                    "2024-10-08 09:00:01",
+                    trade("1", Direction::LONG, 3502.0, 1.0, "2024-10-08 09:00:01"),
+                ),
+                record(
+                    "2024-10-08 09:00:02",
+                    trade("1", Direction::LONG, 3504.0, 1.0, "2024-10-08 09:00:02"),
+                ),
+                record(
+                    "2024-10-08 09:00:02",
+                    order("1", Direction::LONG, 3502.0, Status::ALLTRADED),
+                ),
+                record(
+                    "2024-10-08 09:00:03",
+                    order("2", Direction::SHORT, 3510.0, Status::NOTTRADED),
+                ),
+                record(
+                    "2024-10-08 09:00:05",
+                    order("2", Direction::SHORT, 3510.0, Status::CANCELLED),
+                ),
+            ],
+        };
+        let ticks = vec![
+            tick("2024-10-08 08:59:59", 3499.0, 3500.0),
+            tick("2024-10-08 09:00:00", 3500.0, 3501.0),
+            tick("2024-10-08 09:00:04", 3508.0, 3509.0),
+            // After order 2 was cancelled
+            tick("2024-10-08 09:00:06", 3510.0, 3511.0),
+        ];
+        let records = replay_executions(&log, &ticks);
+        assert_eq!(records.len(), 2);
+
+        let first = &records[0];
+        assert_eq!(first.vt_orderid, "CTP.1");
+        assert_eq!(first.model_fill, Some((3501.0, at("2024-10-08 09:00:00"))));
+        assert_eq!(first.real_fill, Some((3503.0, at("2024-10-08 09:00:01"))));
+        assert_eq!(first.slippage(), Some(2.0));
+        assert_eq!(first.delay(), Some(1.0));
+        assert_eq!(records[1].model_fill, None);
+        assert_eq!(records[1].real_fill, None);
+
+        let summary = ExecutionSummary::new(&records);
+        assert_eq!(summary.orders, 2);
+        assert_eq!(summary.model_filled, 1);
+        assert_eq!(summary.real_filled, 1);
+        assert_eq!(summary.missed, 0);
+        assert_eq!(summary.mean_slippage, 2.0);
+        assert_eq!(summary.suggested_slippage(), 2.0);
+
+        let df = executions_df(&records);
+        assert_eq!(df.shape(), (2, 11));
+        assert_eq!(df.column("slippage").unwrap().null_count(), 1);
+    }
+}
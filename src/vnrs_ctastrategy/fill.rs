@@ -0,0 +1,52 @@
+/*!Fill price adjustments of the backtest, to try a transaction-cost model without changing the
+matching. A FillHook gets every fill about to happen with the bar or tick it happens on and
+returns the price to fill at instead. Hooks are added with BacktestingEngine::add_fill_hook and
+run in order after the volume impact, e.g. adverse_ticks_in_fast_market. */
+use crate::vnrs::trader::constant::{Direction, Offset};
+use crate::vnrs::trader::object::{BarData, TickData};
+
+///Fill of a limit order, or of a triggered stop order, before it is booked.
+#[derive(Debug, Clone, Copy)]
+pub struct Fill<'a> {
+    pub direction: Direction,
+    pub offset: Offset,
+    pub volume: f64,
+    ///Limit price of a limit order, trigger price of a stop order.
+    pub order_price: f64,
+    pub stop: bool,
+    ///Bar filled on in bar mode, default in tick mode.
+    pub bar: &'a BarData,
+    ///Tick filled on in tick mode, default in bar mode.
+    pub tick: &'a TickData,
+    pub pricetick: f64,
+}
+
+impl Fill<'_> {
+    ///price moved against the order by amount, up for a buy and down for a sell.
+    pub fn adverse(&self, price: f64, amount: f64) -> f64 {
+        match self.direction {
+            Direction::LONG => price + amount,
+            _ => price - amount,
+        }
+    }
+}
+
+///Price to fill at from the fill and the price the engine would fill at.
+pub type FillHook = Box<dyn Fn(&Fill, f64) -> f64 + Send + Sync>;
+
+///Move fills ticks pricetick against the order on bars whose high to low range exceeds
+///threshold of their open, e.g. 0.01, where the open price the engine fills at is stale.
+pub fn adverse_ticks_in_fast_market(ticks: f64, threshold: f64) -> FillHook {
+    Box::new(move |fill: &Fill, price: f64| {
+        let bar = fill.bar;
+        if bar.open_price <= 0.0 {
+            return price;
+        }
+        let range = (bar.high_price - bar.low_price) / bar.open_price;
+        if range > threshold {
+            fill.adverse(price, ticks * fill.pricetick)
+        } else {
+            price
+        }
+    })
+}
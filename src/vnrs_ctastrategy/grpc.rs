@@ -0,0 +1,489 @@
+/*!gRPC control API of the backtester, enabled by the `grpc` feature. */
+// tonic::Status is the error type of every handler, boxing it would only add conversions
+#![allow(clippy::result_large_err)]
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::{transport::Server, Request, Response, Status};
+
+use super::backtesting::{BacktestingEngine, BacktestingStatistics, SettingQueue};
+use super::base::{BacktestingMode, ExternClass, StrategyDirectory};
+use super::batch::panic_message;
+use crate::vnrs::trader::audit::AuditRecorder;
+use crate::vnrs::trader::constant::Interval;
+
+pub mod proto {
+    tonic::include_proto!("vnrs");
+}
+
+use proto::backtest_event::Event;
+use proto::backtester_server::{Backtester, BacktesterServer};
+use proto::{
+    BacktestEvent, BacktestId, BacktestReply, BacktestRequest, Progress, SettingUpdate, Statistics,
+};
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+///Finished backtests kept for clients fetching their results, see
+///BacktesterService::set_max_finished_runs.
+pub const MAX_FINISHED_RUNS: usize = 100;
+
+type EventSender = mpsc::UnboundedSender<Result<BacktestEvent, Status>>;
+
+type Runs = Arc<Mutex<HashMap<u64, Arc<Mutex<BacktestRun>>>>>;
+
+///Events and result of one backtest, shared between the worker thread and the service.
+#[derive(Default)]
+struct BacktestRun {
+    events: Vec<BacktestEvent>,
+    subscribers: Vec<EventSender>,
+    statistics: Option<Statistics>,
+    ///Setting updates of the strategy, once it is added.
+    settings: Option<SettingQueue>,
+    ///Cancels the replay of the engine, see BacktestingEngine::set_cancel_token.
+    cancelled: Arc<AtomicBool>,
+    finished: bool,
+}
+
+impl BacktestRun {
+    fn put_event(&mut self, event: Event) {
+        let event = BacktestEvent { event: Some(event) };
+        self.subscribers
+            .retain(|subscriber| subscriber.send(Ok(event.clone())).is_ok());
+        self.events.push(event);
+    }
+}
+
+///Backtester service, every backtest runs on its own worker thread. Strategies are named by
+///clients and loaded from the strategy directory of the service.
+pub struct BacktesterService {
+    runs: Runs,
+    next_id: AtomicU64,
+    strategies: StrategyDirectory,
+    max_finished_runs: usize,
+}
+
+impl BacktesterService {
+    pub fn new(strategies: StrategyDirectory) -> Self {
+        BacktesterService {
+            runs: Runs::default(),
+            next_id: AtomicU64::new(0),
+            strategies,
+            max_finished_runs: MAX_FINISHED_RUNS,
+        }
+    }
+
+    ///Number of finished backtests kept, the oldest ones are removed when a backtest starts.
+    pub fn set_max_finished_runs(&mut self, max_finished_runs: usize) {
+        self.max_finished_runs = max_finished_runs;
+    }
+
+    ///Remove the oldest finished runs beyond max_finished_runs, ids grow with the start time.
+    fn prune_finished(&self, runs: &mut HashMap<u64, Arc<Mutex<BacktestRun>>>) {
+        let mut finished: Vec<u64> = runs
+            .iter()
+            .filter(|(_, run)| run.lock().unwrap().finished)
+            .map(|(id, _)| *id)
+            .collect();
+        if finished.len() <= self.max_finished_runs {
+            return;
+        }
+        finished.sort_unstable();
+        for id in &finished[..finished.len() - self.max_finished_runs] {
+            runs.remove(id);
+        }
+    }
+
+    fn get_run(&self, id: u64) -> Result<Arc<Mutex<BacktestRun>>, Status> {
+        self.runs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("backtest {} not found", id)))
+    }
+}
+
+#[tonic::async_trait]
+impl Backtester for BacktesterService {
+    type StreamEventsStream = UnboundedReceiverStream<Result<BacktestEvent, Status>>;
+
+    async fn start_backtest(
+        &self,
+        request: Request<BacktestRequest>,
+    ) -> Result<Response<BacktestReply>, Status> {
+        let request = request.into_inner();
+        let interval = parse_interval(&request.interval)?;
+        let start = parse_datetime(&request.start)?;
+        let end = parse_datetime(&request.end)?;
+        let strategy = self
+            .strategies
+            .resolve(&request.strategy)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let run = Arc::new(Mutex::new(BacktestRun::default()));
+        {
+            let mut runs = self.runs.lock().unwrap();
+            self.prune_finished(&mut runs);
+            runs.insert(id, run.clone());
+        }
+
+        let runs = self.runs.clone();
+        thread::spawn(move || {
+            run_backtest(request, strategy, interval, start, end, run.clone());
+            // A cancelled run is forgotten once its partial statistics went out
+            if run.lock().unwrap().cancelled.load(Ordering::SeqCst) {
+                runs.lock().unwrap().remove(&id);
+            }
+        });
+
+        Ok(Response::new(BacktestReply {
+            id,
+            status: "started".to_string(),
+        }))
+    }
+
+    async fn stop_backtest(
+        &self,
+        request: Request<BacktestId>,
+    ) -> Result<Response<BacktestReply>, Status> {
+        let id = request.into_inner().id;
+        let run = self.get_run(id)?;
+        let run = run.lock().unwrap();
+        if run.finished {
+            return Err(Status::failed_precondition("backtest finished"));
+        }
+        // The worker ends the replay after its current bar, sends the statistics so far to the
+        // streams and removes the run
+        run.cancelled.store(true, Ordering::SeqCst);
+
+        Ok(Response::new(BacktestReply {
+            id,
+            status: "cancelled".to_string(),
+        }))
+    }
+
+    async fn stream_events(
+        &self,
+        request: Request<BacktestId>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let run = self.get_run(request.into_inner().id)?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let mut run = run.lock().unwrap();
+        for event in &run.events {
+            let _ = sender.send(Ok(event.clone()));
+        }
+        // Dropping the sender of a finished run ends the stream after the recorded events
+        if !run.finished {
+            run.subscribers.push(sender);
+        }
+
+        Ok(Response::new(UnboundedReceiverStream::new(receiver)))
+    }
+
+    async fn get_statistics(
+        &self,
+        request: Request<BacktestId>,
+    ) -> Result<Response<Statistics>, Status> {
+        let run = self.get_run(request.into_inner().id)?;
+        let run = run.lock().unwrap();
+        run.statistics
+            .clone()
+            .map(Response::new)
+            .ok_or_else(|| Status::failed_precondition("backtest not finished"))
+    }
+
+    async fn update_setting(
+        &self,
+        request: Request<SettingUpdate>,
+    ) -> Result<Response<BacktestReply>, Status> {
+        let request = request.into_inner();
+        let run = self.get_run(request.id)?;
+        let run = run.lock().unwrap();
+        if run.finished {
+            return Err(Status::failed_precondition("backtest finished"));
+        }
+        let settings = run
+            .settings
+            .as_ref()
+            .ok_or_else(|| Status::failed_precondition("strategy not added yet"))?;
+        settings
+            .push(&request.setting)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        Ok(Response::new(BacktestReply {
+            id: request.id,
+            status: "queued".to_string(),
+        }))
+    }
+}
+
+///Serve the backtester gRPC API on addr (e.g. "127.0.0.1:50051"), loading the strategies named
+///by clients from strategies, blocking until the server exits.
+pub fn serve(addr: &str, strategies: StrategyDirectory) -> Result<(), Box<dyn std::error::Error>> {
+    let addr = addr.parse()?;
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(
+        Server::builder()
+            .add_service(BacktesterServer::new(BacktesterService::new(strategies)))
+            .serve(addr),
+    )?;
+    Ok(())
+}
+
+fn run_backtest(
+    request: BacktestRequest,
+    strategy: PathBuf,
+    interval: Interval,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    run: Arc<Mutex<BacktestRun>>,
+) {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let strategy_class = ExternClass::load(&strategy).map_err(|e| e.to_string())?;
+        let mut engine = BacktestingEngine::new();
+        engine.set_cancel_token(run.lock().unwrap().cancelled.clone());
+
+        let log_run = run.clone();
+        engine.set_output_callback(move |msg| {
+            log_run
+                .lock()
+                .unwrap()
+                .put_event(Event::Log(msg.to_string()));
+        });
+        let progress_run = run.clone();
+        engine.set_progress_callback(move |progress| {
+            progress_run
+                .lock()
+                .unwrap()
+                .put_event(Event::Progress(Progress {
+                    progress: progress.progress,
+                    datetime: progress.datetime.to_string(),
+                    pos: progress.pos,
+                    balance: progress.balance,
+                    drawdown: progress.drawdown,
+                    trade_count: progress.trade_count,
+                    bars: progress.bars as u64,
+                    total_bars: progress.total_bars as u64,
+                    bars_per_sec: progress.bars_per_sec,
+                    eta: progress.eta,
+                }));
+        });
+
+        engine
+            .set_parameters(
+                &request.vt_symbol,
+                interval,
+                start,
+                end,
+                request.rate,
+                request.slippage,
+                request.size,
+                request.pricetick,
+                request.capital,
+                BacktestingMode::BAR,
+                request.risk_free,
+                // 0 derives it from the market of the symbol
+                request.annual_days,
+                request.half_life,
+            )
+            .map_err(|e| e.to_string())?;
+        engine.set_initial_pos(request.initial_pos);
+        engine
+            .add_strategy(strategy_class, &request.setting)
+            .map_err(|e| e.to_string())?;
+        // Setting updates are audited like live ones, the run goes on without the log
+        if let Ok(recorder) = AuditRecorder::new(AuditRecorder::default_dir()) {
+            engine.set_audit_recorder(Arc::new(recorder));
+        }
+        run.lock().unwrap().settings = Some(engine.setting_queue());
+        engine.load_data().map_err(|e| e.to_string())?;
+        engine.run_backtesting().map_err(|e| e.to_string())?;
+        engine.calculate_result();
+        Ok(engine.calculate_statistics(None, false))
+    }))
+    .map_err(panic_message)
+    .and_then(|statistics| statistics);
+
+    let mut run = run.lock().unwrap();
+    match result {
+        Ok(statistics) => {
+            let statistics = to_proto_statistics(&statistics);
+            run.statistics = Some(statistics.clone());
+            run.put_event(Event::Statistics(statistics));
+        }
+        Err(msg) => run.put_event(Event::Error(msg)),
+    }
+    run.finished = true;
+    run.subscribers.clear();
+}
+
+fn parse_interval(interval: &str) -> Result<Interval, Status> {
+    match interval.parse() {
+        Ok(interval @ (Interval::MINUTE | Interval::HOUR | Interval::DAILY)) => Ok(interval),
+        _ => Err(Status::invalid_argument(format!(
+            "unsupported interval {}",
+            interval
+        ))),
+    }
+}
+
+fn parse_datetime(datetime: &str) -> Result<NaiveDateTime, Status> {
+    NaiveDateTime::parse_from_str(datetime, DATETIME_FORMAT).map_err(|_| {
+        Status::invalid_argument(format!(
+            "invalid datetime {}, expected {}",
+            datetime, DATETIME_FORMAT
+        ))
+    })
+}
+
+fn to_proto_statistics(s: &BacktestingStatistics) -> Statistics {
+    Statistics {
+        start_date: s.start_date.to_string(),
+        end_date: s.end_date.to_string(),
+        total_days: s.total_days,
+        profit_days: s.profit_days,
+        loss_days: s.loss_days,
+        capital: s.capital,
+        end_balance: s.end_balance,
+        max_drawdown: s.max_drawdown,
+        max_ddpercent: s.max_ddpercent,
+        max_drawdown_duration: s.max_drawdown_duration,
+        total_net_pnl: s.total_net_pnl,
+        daily_net_pnl: s.daily_net_pnl,
+        total_commission: s.total_commission,
+        daily_commission: s.daily_commission,
+        total_slippage: s.total_slippage,
+        daily_slippage: s.daily_slippage,
+        total_turnover: s.total_turnover,
+        daily_turnover: s.daily_turnover,
+        total_trade_count: s.total_trade_count,
+        daily_trade_count: s.daily_trade_count,
+        total_return: s.total_return,
+        annual_return: s.annual_return,
+        daily_return: s.daily_return,
+        return_std: s.return_std,
+        sharpe_ratio: s.sharpe_ratio,
+        ewm_sharpe: s.ewm_sharpe,
+        return_drawdown_ratio: s.return_drawdown_ratio,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    ///Service over a directory holding a strategy file that fails to load, so that every
+    ///backtest finishes right away with an error.
+    fn service(name: &str) -> (BacktesterService, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("vnrs_grpc_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join(libloading::library_filename("broken_strategy")),
+            b"",
+        )
+        .unwrap();
+        (BacktesterService::new(StrategyDirectory::new(&dir)), dir)
+    }
+
+    fn request(strategy: &str) -> Request<BacktestRequest> {
+        Request::new(BacktestRequest {
+            strategy: strategy.to_string(),
+            vt_symbol: "IF888.CFFEX".to_string(),
+            interval: "1m".to_string(),
+            start: "2024-01-02 00:00:00".to_string(),
+            end: "2024-01-03 00:00:00".to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn start(service: &BacktesterService) -> u64 {
+        let reply = block_on(service.start_backtest(request("broken_strategy"))).unwrap();
+        let reply = reply.into_inner();
+        assert_eq!(reply.status, "started");
+        let run = service.get_run(reply.id).unwrap();
+        while !run.lock().unwrap().finished {
+            thread::sleep(Duration::from_millis(10));
+        }
+        reply.id
+    }
+
+    #[test]
+    fn start_runs_the_backtest() {
+        let (service, dir) = service("start");
+        let id = start(&service);
+
+        let run = service.get_run(id).unwrap();
+        let run = run.lock().unwrap();
+        assert!(matches!(
+            run.events.last().and_then(|event| event.event.as_ref()),
+            Some(Event::Error(_))
+        ));
+        assert!(run.statistics.is_none());
+
+        for strategy in ["missing_strategy", "../broken_strategy"] {
+            let status = block_on(service.start_backtest(request(strategy))).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stop_finished_run_is_rejected() {
+        let (service, dir) = service("stop");
+        let id = start(&service);
+
+        let status = block_on(service.stop_backtest(Request::new(BacktestId { id }))).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unknown_id_is_not_found() {
+        let (service, dir) = service("unknown");
+        let id = BacktestId { id: 42 };
+
+        let status = block_on(service.stop_backtest(Request::new(id.clone()))).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        let status = block_on(service.get_statistics(Request::new(id.clone()))).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        let status = block_on(service.stream_events(Request::new(id))).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn oldest_finished_runs_are_removed() {
+        let (mut service, dir) = service("prune");
+        service.set_max_finished_runs(1);
+        let first = start(&service);
+        let second = start(&service);
+        // Both are kept until another backtest starts
+        assert!(service.get_run(first).is_ok());
+
+        let third = start(&service);
+        assert!(service.get_run(first).is_err());
+        assert!(service.get_run(second).is_ok());
+        assert!(service.get_run(third).is_ok());
+        assert_eq!(service.runs.lock().unwrap().len(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
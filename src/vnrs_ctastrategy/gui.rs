@@ -0,0 +1,456 @@
+/*!Minimal egui desktop window for the backtester, enabled by the `gui` feature. */
+use chrono::{Days, NaiveDate, NaiveDateTime};
+use eframe::egui;
+use egui_plot::{Line, Plot, PlotPoints};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+use super::backtesting::{BacktestingEngine, BacktestingProgress, BacktestingStatistics};
+use super::base::{format_setting, BacktestingMode, ExternClass};
+use super::batch::panic_message;
+use crate::vnrs::chart::CandleChart;
+use crate::vnrs::trader::constant::Interval;
+use crate::vnrs::trader::object::TradeData;
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// Bars shown in the result chart, the latest ones of the backtest
+const CHART_BARS: usize = 1000;
+
+// Candidate CJK font files, the default egui fonts can't render Chinese logs
+const CJK_FONT_PATHS: [&str; 4] = [
+    "C:/Windows/Fonts/msyh.ttc",
+    "/System/Library/Fonts/PingFang.ttc",
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+];
+
+enum GuiEvent {
+    Log(String),
+    Progress(BacktestingProgress),
+    Finished(Box<BacktestResult>),
+    Failed(String),
+}
+
+struct BacktestResult {
+    statistics: BacktestingStatistics,
+    balance: Vec<(NaiveDate, f64)>,
+    trades: Vec<TradeData>,
+    chart: CandleChart,
+}
+
+///Backtester window: strategy selection, parameter form, run button and results.
+pub struct BacktesterApp {
+    strategy_path: String,
+    parameters: Vec<(String, String)>,
+
+    vt_symbol: String,
+    interval: Interval,
+    start: String,
+    end: String,
+    rate: f64,
+    slippage: f64,
+    size: f64,
+    pricetick: f64,
+    capital: f64,
+
+    receiver: Option<Receiver<GuiEvent>>,
+    cancel: Arc<AtomicBool>,
+    progress: BacktestingProgress,
+    logs: Vec<String>,
+    result: Option<BacktestResult>,
+}
+
+impl Default for BacktesterApp {
+    fn default() -> Self {
+        BacktesterApp {
+            strategy_path: String::new(),
+            parameters: Vec::new(),
+            vt_symbol: "IF888.CFFEX".to_string(),
+            interval: Interval::MINUTE,
+            start: "2020-01-01 00:00:00".to_string(),
+            end: "2020-12-31 23:59:59".to_string(),
+            rate: 2.5e-5,
+            slippage: 0.2,
+            size: 300.0,
+            pricetick: 0.2,
+            capital: 1_000_000.0,
+            receiver: None,
+            cancel: Arc::default(),
+            progress: BacktestingProgress::default(),
+            logs: Vec::new(),
+            result: None,
+        }
+    }
+}
+
+///Open the backtester window, blocking until it is closed.
+pub fn run_gui() -> eframe::Result<()> {
+    eframe::run_native(
+        "vnrs CTA回测",
+        eframe::NativeOptions::default(),
+        Box::new(|cc| {
+            install_cjk_font(&cc.egui_ctx);
+            Ok(Box::<BacktesterApp>::default())
+        }),
+    )
+}
+
+fn install_cjk_font(ctx: &egui::Context) {
+    let Some(data) = CJK_FONT_PATHS
+        .iter()
+        .find_map(|path| std::fs::read(path).ok())
+    else {
+        return;
+    };
+
+    let mut fonts = egui::FontDefinitions::default();
+    fonts.font_data.insert(
+        "cjk".to_string(),
+        Arc::new(egui::FontData::from_owned(data)),
+    );
+    for family in [egui::FontFamily::Proportional, egui::FontFamily::Monospace] {
+        fonts
+            .families
+            .entry(family)
+            .or_default()
+            .push("cjk".to_string());
+    }
+    ctx.set_fonts(fonts);
+}
+
+impl BacktesterApp {
+    fn load_strategy(&mut self) {
+        let path = self.strategy_path.clone();
+        match ExternClass::new(&path) {
+            Ok(class) => {
+                self.parameters = class.get_parameters();
+                self.logs.push(format!(
+                    "策略加载成功：{}，参数数量：{}",
+                    path,
+                    self.parameters.len()
+                ));
+            }
+            Err(e) => self.logs.push(format!("策略加载失败：{}", e)),
+        }
+    }
+
+    fn start_backtesting(&mut self) {
+        let start = NaiveDateTime::parse_from_str(&self.start, DATETIME_FORMAT);
+        let end = NaiveDateTime::parse_from_str(&self.end, DATETIME_FORMAT);
+        let (Ok(start), Ok(end)) = (start, end) else {
+            self.logs
+                .push(format!("日期格式错误，应为{}", DATETIME_FORMAT));
+            return;
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        self.receiver = Some(receiver);
+        self.cancel = Arc::default();
+        self.progress = BacktestingProgress::default();
+        self.result = None;
+
+        let strategy_path = self.strategy_path.clone();
+        let setting = format_setting(&self.parameters);
+        let vt_symbol = self.vt_symbol.clone();
+        let cancel = self.cancel.clone();
+        let (interval, rate, slippage, size, pricetick, capital) = (
+            self.interval,
+            self.rate,
+            self.slippage,
+            self.size,
+            self.pricetick,
+            self.capital,
+        );
+
+        thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut engine = BacktestingEngine::new();
+                engine.set_cancel_token(cancel);
+
+                let log_sender = sender.clone();
+                engine.set_output_callback(move |msg| {
+                    let _ = log_sender.send(GuiEvent::Log(msg.to_string()));
+                });
+                let progress_sender = sender.clone();
+                engine.set_progress_callback(move |progress| {
+                    let _ = progress_sender.send(GuiEvent::Progress(progress.clone()));
+                });
+
+                engine
+                    .set_parameters(
+                        &vt_symbol,
+                        interval,
+                        start,
+                        end,
+                        rate,
+                        slippage,
+                        size,
+                        pricetick,
+                        capital,
+                        BacktestingMode::BAR,
+                        0.0,
+                        0,
+                        120,
+                    )
+                    .unwrap_or_else(|e| panic!("{}", e));
+                let strategy_class =
+                    ExternClass::new(&strategy_path).unwrap_or_else(|e| panic!("{}", e));
+                engine
+                    .add_strategy(Arc::new(strategy_class), &setting)
+                    .unwrap_or_else(|e| panic!("{}", e));
+                engine.load_data().unwrap_or_else(|e| panic!("{}", e));
+                engine.run_backtesting().unwrap_or_else(|e| panic!("{}", e));
+                engine.calculate_result();
+                let statistics = engine.calculate_statistics(None, false);
+
+                BacktestResult {
+                    balance: balance_curve(&engine, capital),
+                    trades: engine.get_all_trades(),
+                    chart: price_chart(&engine, &vt_symbol),
+                    statistics,
+                }
+            }));
+
+            let _ = sender.send(match result {
+                Ok(result) => GuiEvent::Finished(Box::new(result)),
+                Err(payload) => GuiEvent::Failed(panic_message(payload)),
+            });
+        });
+    }
+
+    fn process_events(&mut self) {
+        let Some(receiver) = &self.receiver else {
+            return;
+        };
+
+        let mut finished = false;
+        for event in receiver.try_iter() {
+            match event {
+                GuiEvent::Log(msg) => self.logs.push(msg),
+                GuiEvent::Progress(progress) => self.progress = progress,
+                GuiEvent::Finished(result) => {
+                    self.progress.progress = 1.0;
+                    self.result = Some(*result);
+                    finished = true;
+                }
+                GuiEvent::Failed(msg) => {
+                    self.logs.push(format!("回测失败：{}", msg));
+                    finished = true;
+                }
+            }
+        }
+        if finished {
+            self.receiver = None;
+        }
+    }
+
+    fn setting_panel(&mut self, ui: &mut egui::Ui) {
+        ui.heading("策略");
+        ui.text_edit_singleline(&mut self.strategy_path);
+        if ui.button("加载策略").clicked() {
+            self.load_strategy();
+        }
+
+        egui::Grid::new("parameters").num_columns(2).show(ui, |ui| {
+            for (name, value) in self.parameters.iter_mut() {
+                ui.label(name.as_str());
+                ui.text_edit_singleline(value);
+                ui.end_row();
+            }
+        });
+
+        ui.separator();
+        ui.heading("回测参数");
+        egui::Grid::new("setting").num_columns(2).show(ui, |ui| {
+            ui.label("本地代码");
+            ui.text_edit_singleline(&mut self.vt_symbol);
+            ui.end_row();
+
+            ui.label("K线周期");
+            egui::ComboBox::from_id_salt("interval")
+                .selected_text(self.interval.to_string())
+                .show_ui(ui, |ui| {
+                    for interval in [Interval::MINUTE, Interval::HOUR, Interval::DAILY] {
+                        ui.selectable_value(&mut self.interval, interval, interval.to_string());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("开始日期");
+            ui.text_edit_singleline(&mut self.start);
+            ui.end_row();
+            ui.label("结束日期");
+            ui.text_edit_singleline(&mut self.end);
+            ui.end_row();
+
+            for (label, value) in [
+                ("手续费率", &mut self.rate),
+                ("交易滑点", &mut self.slippage),
+                ("合约乘数", &mut self.size),
+                ("价格跳动", &mut self.pricetick),
+                ("回测资金", &mut self.capital),
+            ] {
+                ui.label(label);
+                ui.add(egui::DragValue::new(value).speed(0.0));
+                ui.end_row();
+            }
+        });
+
+        let running = self.receiver.is_some();
+        if ui
+            .add_enabled(!running, egui::Button::new("开始回测"))
+            .clicked()
+        {
+            self.start_backtesting();
+        }
+        // The result of a cancelled backtest covers the bars replayed until then
+        if ui
+            .add_enabled(running, egui::Button::new("停止回测"))
+            .clicked()
+        {
+            self.cancel.store(true, Ordering::SeqCst);
+        }
+        let bar = egui::ProgressBar::new(self.progress.progress as f32);
+        if self.progress.total_bars > 0 {
+            ui.add(bar.text(self.progress.to_string()));
+        } else {
+            ui.add(bar.show_percentage());
+        }
+    }
+
+    fn result_panel(&self, ui: &mut egui::Ui) {
+        let Some(result) = &self.result else {
+            ui.label("暂无回测结果");
+            return;
+        };
+
+        let points: PlotPoints = result
+            .balance
+            .iter()
+            .map(|(date, balance)| [days_from_epoch(*date), *balance])
+            .collect();
+        Plot::new("balance")
+            .height(300.0)
+            .x_axis_formatter(|mark, _| date_from_days(mark.value).to_string())
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(points).name("账户净值"))
+            });
+        result.chart.show(ui, 300.0);
+
+        ui.columns(2, |columns| {
+            statistics_grid(&mut columns[0], &result.statistics);
+            trades_table(&mut columns[1], &result.trades);
+        });
+    }
+}
+
+impl eframe::App for BacktesterApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.process_events();
+        if self.receiver.is_some() {
+            ctx.request_repaint();
+        }
+
+        egui::SidePanel::left("setting").show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| self.setting_panel(ui));
+        });
+        egui::TopBottomPanel::bottom("logs")
+            .resizable(true)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for msg in &self.logs {
+                            ui.label(msg.as_str());
+                        }
+                    });
+            });
+        egui::CentralPanel::default().show(ctx, |ui| self.result_panel(ui));
+    }
+}
+
+fn balance_curve(engine: &BacktestingEngine, capital: f64) -> Vec<(NaiveDate, f64)> {
+    let Some(df) = engine.get_daily_df() else {
+        return Vec::new();
+    };
+
+    let dates = df["date"].date().unwrap().as_date_iter();
+    let net_pnl = df["net_pnl"].f64().unwrap().into_no_null_iter();
+    let mut balance = capital;
+    dates
+        .zip(net_pnl)
+        .map(|(date, pnl)| {
+            balance += pnl;
+            (date.unwrap_or_default(), balance)
+        })
+        .collect()
+}
+
+fn price_chart(engine: &BacktestingEngine, vt_symbol: &str) -> CandleChart {
+    let history = engine.get_history_data();
+    let first = history.len().saturating_sub(CHART_BARS);
+    CandleChart::new(
+        vt_symbol,
+        (first..history.len())
+            .filter_map(|ix| history.get(ix))
+            .collect(),
+    )
+}
+
+fn days_from_epoch(date: NaiveDate) -> f64 {
+    (date - NaiveDate::default()).num_days() as f64
+}
+
+fn date_from_days(days: f64) -> NaiveDate {
+    NaiveDate::default()
+        .checked_add_days(Days::new(days.max(0.0) as u64))
+        .unwrap_or_default()
+}
+
+fn statistics_grid(ui: &mut egui::Ui, s: &BacktestingStatistics) {
+    egui::Grid::new("statistics").striped(true).show(ui, |ui| {
+        for (label, value) in [
+            ("首个交易日", s.start_date.to_string()),
+            ("最后交易日", s.end_date.to_string()),
+            ("总交易日", s.total_days.to_string()),
+            ("起始资金", format!("{:.2}", s.capital)),
+            ("结束资金", format!("{:.2}", s.end_balance)),
+            ("总收益率", format!("{:.2}%", s.total_return)),
+            ("年化收益", format!("{:.2}%", s.annual_return)),
+            ("最大回撤", format!("{:.2}", s.max_drawdown)),
+            ("百分比最大回撤", format!("{:.2}%", s.max_ddpercent)),
+            ("总手续费", format!("{:.2}", s.total_commission)),
+            ("总成交笔数", s.total_trade_count.to_string()),
+            ("Sharpe Ratio", format!("{:.2}", s.sharpe_ratio)),
+            ("收益回撤比", format!("{:.2}", s.return_drawdown_ratio)),
+        ] {
+            ui.label(label);
+            ui.label(value);
+            ui.end_row();
+        }
+    });
+}
+
+fn trades_table(ui: &mut egui::Ui, trades: &[TradeData]) {
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        egui::Grid::new("trades").striped(true).show(ui, |ui| {
+            for header in ["时间", "方向", "开平", "价格", "数量"] {
+                ui.strong(header);
+            }
+            ui.end_row();
+
+            for trade in trades {
+                ui.label(trade.datetime.to_string());
+                ui.label(format!("{:?}", trade.direction));
+                ui.label(format!("{:?}", trade.offset));
+                ui.label(trade.price.to_string());
+                ui.label(trade.volume.to_string());
+                ui.end_row();
+            }
+        });
+    });
+}
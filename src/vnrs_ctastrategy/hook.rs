@@ -0,0 +1,141 @@
+/*!Orders and trades of a backtest streamed out as they happen during replay, so external
+systems such as a custom visualizer can follow a run without changes to the engine. Set a hook
+with BacktestingEngine::set_event_hook, e.g. jsonl_hook writing one JSON object a line or
+http_hook POSTing each event. */
+use std::fs::File;
+use std::io::{LineWriter, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::error::{VnrsError, VnrsResult};
+use crate::vnrs::trader::object::{OrderData, TradeData};
+
+///Order update or trade of a backtest, serialized as {"type": "order", "data": {...}}.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "lowercase")]
+pub enum HookEvent<'a> {
+    ///New order or a change of its status.
+    Order(&'a OrderData),
+    Trade(&'a TradeData),
+}
+
+pub type EventHook = Box<dyn Fn(&HookEvent) + Send + Sync>;
+
+///Hook appending the events to the JSON Lines file at path, created or truncated. Each line
+///is flushed as it is written so readers can tail the file.
+pub fn jsonl_hook(path: impl AsRef<Path>) -> VnrsResult<EventHook> {
+    let file = Mutex::new(LineWriter::new(File::create(path)?));
+    Ok(Box::new(move |event: &HookEvent| {
+        let mut file = file.lock().unwrap();
+        let result = serde_json::to_writer(&mut *file, event)
+            .map_err(VnrsError::from)
+            .and_then(|_| Ok(file.write_all(b"\n")?));
+        if let Err(e) = result {
+            log::warn!("回测事件写入失败：{}", e);
+        }
+    }))
+}
+
+///Hook POSTing each event as JSON to url, plain http://host[:port]/path only. A request that
+///fails is logged and the backtest goes on.
+pub fn http_hook(url: &str) -> VnrsResult<EventHook> {
+    let invalid = || VnrsError::Setting(format!("invalid hook url {}", url));
+    let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    address.to_socket_addrs().map_err(|_| invalid())?;
+    let (host, path) = (host.to_string(), path.to_string());
+    Ok(Box::new(move |event: &HookEvent| {
+        if let Err(e) = post(&address, &host, &path, event) {
+            log::warn!("回测事件推送失败：{}", e);
+        }
+    }))
+}
+
+fn post(address: &str, host: &str, path: &str, event: &HookEvent) -> VnrsResult<()> {
+    let body = serde_json::to_vec(event)?;
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        host,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+
+    let mut status = [0; 12];
+    stream.read_exact(&mut status)?;
+    // "HTTP/1.1 200"
+    match &status[9..] {
+        [b'2', _, _] => Ok(()),
+        code => Err(VnrsError::Setting(format!(
+            "hook responded with status {}",
+            String::from_utf8_lossy(code)
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+
+    #[test]
+    fn events_written_and_posted() {
+        let order = OrderData {
+            orderid: "1".to_string(),
+            price: 100.0,
+            volume: 2.0,
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("vnrs_hook_{}.jsonl", std::process::id()));
+        let hook = jsonl_hook(&path).unwrap();
+        hook(&HookEvent::Order(&order));
+        hook(&HookEvent::Order(&order));
+        let lines: Vec<serde_json::Value> = std::io::BufReader::new(File::open(&path).unwrap())
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "order");
+        assert_eq!(lines[0]["data"]["price"], 100.0);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            // The body is the last of the request and ends the event object
+            while !request.ends_with(b"}}") {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        http_hook(&url).unwrap()(&HookEvent::Order(&order));
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /events HTTP/1.1"));
+        assert!(request.contains("\"orderid\":\"1\""));
+        assert!(http_hook("https://example.com").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+/*!Arrow IPC export of backtest results, enabled by the `ipc` feature.
+
+Files are in Arrow IPC file format (Feather v2) and load directly in Python with
+`polars.read_ipc`, `pandas.read_feather` or `pyarrow.ipc.open_file`.
+*/
+use chrono::NaiveDateTime;
+use polars::prelude::*;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use super::backtesting::{BacktestingEngine, BacktestingStatistics};
+use super::batch::statistics_columns;
+use crate::vnrs::trader::object::TradeData;
+
+///Convert trades into a DataFrame, one row per trade.
+pub fn trades_to_df(trades: &[TradeData]) -> DataFrame {
+    DataFrame::new(vec![
+        Series::new(
+            "datetime",
+            trades
+                .iter()
+                .map(|t| t.datetime)
+                .collect::<Vec<NaiveDateTime>>(),
+        ),
+        Series::new(
+            "vt_symbol",
+            trades
+                .iter()
+                .map(|t| t.vt_symbol())
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "tradeid",
+            trades
+                .iter()
+                .map(|t| t.tradeid.clone())
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "orderid",
+            trades
+                .iter()
+                .map(|t| t.orderid.clone())
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "direction",
+            trades
+                .iter()
+                .map(|t| format!("{:?}", t.direction))
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "offset",
+            trades
+                .iter()
+                .map(|t| format!("{:?}", t.offset))
+                .collect::<Vec<String>>(),
+        ),
+        Series::new(
+            "price",
+            trades.iter().map(|t| t.price).collect::<Vec<f64>>(),
+        ),
+        Series::new(
+            "volume",
+            trades.iter().map(|t| t.volume).collect::<Vec<f64>>(),
+        ),
+        Series::new(
+            "reference",
+            trades
+                .iter()
+                .map(|t| t.reference.clone())
+                .collect::<Vec<String>>(),
+        ),
+    ])
+    .unwrap()
+}
+
+///Convert statistics into a single row DataFrame.
+pub fn statistics_to_df(statistics: &BacktestingStatistics) -> DataFrame {
+    DataFrame::new(statistics_columns(&[Some(statistics)])).unwrap()
+}
+
+///Write a DataFrame in Arrow IPC file format.
+pub fn write_ipc<W: Write>(df: &mut DataFrame, writer: W) -> PolarsResult<()> {
+    IpcWriter::new(writer).finish(df)
+}
+
+///Save daily.arrow, pnl.arrow, trades.arrow, round_trips.arrow and statistics.arrow of a
+///finished backtest into dir.
+pub fn save_results<P: AsRef<Path>>(
+    engine: &BacktestingEngine,
+    statistics: &BacktestingStatistics,
+    dir: P,
+) -> PolarsResult<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+
+    let mut daily_df = engine.get_daily_df().unwrap_or_default();
+    write_ipc(&mut daily_df, File::create(dir.join("daily.arrow"))?)?;
+
+    let mut pnl_df = engine.get_pnl_decomposition();
+    write_ipc(&mut pnl_df, File::create(dir.join("pnl.arrow"))?)?;
+
+    let mut trades_df = trades_to_df(&engine.get_all_trades());
+    write_ipc(&mut trades_df, File::create(dir.join("trades.arrow"))?)?;
+
+    let mut round_trips_df = engine.get_round_trips_df();
+    write_ipc(
+        &mut round_trips_df,
+        File::create(dir.join("round_trips.arrow"))?,
+    )?;
+
+    let mut statistics_df = statistics_to_df(statistics);
+    write_ipc(
+        &mut statistics_df,
+        File::create(dir.join("statistics.arrow"))?,
+    )
+}
@@ -0,0 +1,251 @@
+/*!Timeline of every limit order of a backtest, from submission through acknowledgement and
+fills to cancellation, for checking how and when the engine filled each order. */
+use std::collections::HashMap;
+
+use chrono::{NaiveDateTime, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::vnrs::trader::constant::{Direction, Offset, Status};
+use crate::vnrs::trader::object::{OrderData, TradeData};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OrderStage {
+    Submitted,
+    ///Accepted by the engine and waiting to be crossed.
+    Acknowledged,
+    Filled {
+        price: f64,
+        volume: f64,
+    },
+    Cancelled,
+    Rejected,
+}
+
+///Stage an order reached at the datetime of the bar or tick being replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OrderEvent {
+    pub datetime: NaiveDateTime,
+    pub stage: OrderStage,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderLifecycle {
+    pub vt_orderid: String,
+    pub direction: Direction,
+    pub offset: Offset,
+    pub price: f64,
+    pub volume: f64,
+    pub reference: String,
+    ///In time sequence.
+    pub events: Vec<OrderEvent>,
+}
+
+impl OrderLifecycle {
+    fn first(&self, stage: impl Fn(&OrderStage) -> bool) -> Option<NaiveDateTime> {
+        self.events
+            .iter()
+            .find(|event| stage(&event.stage))
+            .map(|event| event.datetime)
+    }
+
+    pub fn submitted(&self) -> Option<NaiveDateTime> {
+        self.first(|stage| *stage == OrderStage::Submitted)
+    }
+
+    pub fn acknowledged(&self) -> Option<NaiveDateTime> {
+        self.first(|stage| *stage == OrderStage::Acknowledged)
+    }
+
+    ///Datetime the order left the book, by its last fill, cancellation or rejection.
+    pub fn finished(&self) -> Option<NaiveDateTime> {
+        let filled = self.filled_volume() >= self.volume && self.volume > 0.0;
+        self.events
+            .iter()
+            .rev()
+            .find(|event| match event.stage {
+                OrderStage::Filled { .. } => filled,
+                OrderStage::Cancelled | OrderStage::Rejected => true,
+                _ => false,
+            })
+            .map(|event| event.datetime)
+    }
+
+    pub fn cancelled(&self) -> Option<NaiveDateTime> {
+        self.first(|stage| *stage == OrderStage::Cancelled)
+    }
+
+    ///(datetime, price, volume) of each fill.
+    pub fn fills(&self) -> impl Iterator<Item = (NaiveDateTime, f64, f64)> + '_ {
+        self.events.iter().filter_map(|event| match event.stage {
+            OrderStage::Filled { price, volume } => Some((event.datetime, price, volume)),
+            _ => None,
+        })
+    }
+
+    pub fn filled_volume(&self) -> f64 {
+        self.fills().map(|(_, _, volume)| volume).sum()
+    }
+
+    ///Volume weighted price of the fills, None if not filled.
+    pub fn average_fill_price(&self) -> Option<f64> {
+        let volume = self.filled_volume();
+        (volume > 0.0).then(|| {
+            self.fills()
+                .map(|(_, price, volume)| price * volume)
+                .sum::<f64>()
+                / volume
+        })
+    }
+
+    ///Time from submission to the first fill.
+    pub fn time_to_fill(&self) -> Option<TimeDelta> {
+        let (filled, _, _) = self.fills().next()?;
+        Some(filled - self.submitted()?)
+    }
+}
+
+///Lifecycles of the limit orders of a run, fed with every order update and trade.
+#[derive(Debug, Clone, Default)]
+pub struct OrderTimeline {
+    orders: HashMap<String, OrderLifecycle>,
+    ///vt_orderids in order of submission.
+    sequence: Vec<String>,
+}
+
+impl OrderTimeline {
+    ///Record the status of order at datetime, a first update of an order that is already
+    ///past submitting, e.g. sent by a triggered stop order, also counts as its submission.
+    pub fn update_order(&mut self, order: &OrderData, datetime: NaiveDateTime) {
+        let vt_orderid = order.vt_orderid();
+        let lifecycle = self.orders.entry(vt_orderid.clone()).or_insert_with(|| {
+            self.sequence.push(vt_orderid.clone());
+            OrderLifecycle {
+                vt_orderid,
+                direction: order.direction,
+                offset: order.offset,
+                price: order.price,
+                volume: order.volume,
+                reference: order.reference.clone(),
+                events: Vec::new(),
+            }
+        });
+        if lifecycle.events.is_empty() {
+            lifecycle.events.push(OrderEvent {
+                datetime,
+                stage: OrderStage::Submitted,
+            });
+        }
+        let stage = match order.status {
+            Status::NOTTRADED | Status::PARTTRADED | Status::ALLTRADED => OrderStage::Acknowledged,
+            Status::CANCELLED => OrderStage::Cancelled,
+            Status::REJECTED => OrderStage::Rejected,
+            _ => return,
+        };
+        if stage == OrderStage::Acknowledged && lifecycle.acknowledged().is_some() {
+            return;
+        }
+        lifecycle.events.push(OrderEvent { datetime, stage });
+    }
+
+    ///Record a fill of the order of trade.
+    pub fn update_trade(&mut self, trade: &TradeData) {
+        if let Some(lifecycle) = self.orders.get_mut(&trade.vt_orderid()) {
+            lifecycle.events.push(OrderEvent {
+                datetime: trade.datetime,
+                stage: OrderStage::Filled {
+                    price: trade.price,
+                    volume: trade.volume,
+                },
+            });
+        }
+    }
+
+    pub fn get(&self, vt_orderid: &str) -> Option<&OrderLifecycle> {
+        self.orders.get(vt_orderid)
+    }
+
+    ///Lifecycles in order of submission.
+    pub fn iter(&self) -> impl Iterator<Item = &OrderLifecycle> {
+        self.sequence
+            .iter()
+            .map(|vt_orderid| &self.orders[vt_orderid])
+    }
+
+    pub fn len(&self) -> usize {
+        self.sequence.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequence.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn order_lifecycles() {
+        let at = |minute: u32| {
+            NaiveDate::from_ymd_opt(2024, 1, 22)
+                .unwrap()
+                .and_hms_opt(9, minute, 0)
+                .unwrap()
+        };
+        let mut order = OrderData {
+            orderid: "1".to_string(),
+            direction: Direction::LONG,
+            price: 100.0,
+            volume: 3.0,
+            status: Status::SUBMITTING,
+            gateway_name: "BACKTESTING".into(),
+            ..Default::default()
+        };
+        let mut timeline = OrderTimeline::default();
+        timeline.update_order(&order, at(0));
+        order.status = Status::NOTTRADED;
+        timeline.update_order(&order, at(1));
+        order.status = Status::PARTTRADED;
+        timeline.update_order(&order, at(2));
+        let trade = |minute: u32, price: f64, volume: f64| TradeData {
+            symbol: order.symbol,
+            exchange: order.exchange,
+            orderid: "1".to_string(),
+            tradeid: minute.to_string(),
+            direction: order.direction,
+            offset: order.offset,
+            price,
+            volume,
+            datetime: at(minute),
+            reference: String::new(),
+            gateway_name: order.gateway_name,
+        };
+        timeline.update_trade(&trade(2, 99.0, 1.0));
+        timeline.update_trade(&trade(3, 100.0, 2.0));
+
+        let mut cancelled = OrderData {
+            orderid: "2".to_string(),
+            ..order.clone()
+        };
+        cancelled.status = Status::CANCELLED;
+        timeline.update_order(&cancelled, at(4));
+
+        let filled = timeline.get(&order.vt_orderid()).unwrap();
+        assert_eq!(filled.submitted(), Some(at(0)));
+        assert_eq!(filled.acknowledged(), Some(at(1)));
+        assert_eq!(filled.finished(), Some(at(3)));
+        assert_eq!(filled.filled_volume(), 3.0);
+        assert!((filled.average_fill_price().unwrap() - 299.0 / 3.0).abs() < 1e-12);
+        assert_eq!(filled.time_to_fill(), Some(TimeDelta::minutes(2)));
+        assert_eq!(filled.cancelled(), None);
+
+        let ids: Vec<&str> = timeline.iter().map(|l| l.vt_orderid.as_str()).collect();
+        assert_eq!(ids, vec![order.vt_orderid(), cancelled.vt_orderid()]);
+        let cancelled = timeline.get(&cancelled.vt_orderid()).unwrap();
+        assert_eq!(cancelled.submitted(), Some(at(4)));
+        assert_eq!(cancelled.cancelled(), Some(at(4)));
+        assert_eq!(cancelled.finished(), Some(at(4)));
+        assert_eq!(cancelled.average_fill_price(), None);
+    }
+}
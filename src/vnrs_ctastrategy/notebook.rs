@@ -0,0 +1,312 @@
+/*!One-liners for exploratory work in a Rust notebook, e.g. evcxr in Jupyter, with the
+`notebook` feature: the bars of a symbol as a DataFrame, a quick backtest of a closure or of a
+strategy type written in the notebook, without building a dylib, and a candle chart.
+
+```no_run
+use vnrs::prelude::*;
+use vnrs::vnrs_ctastrategy::notebook::ResearchSession;
+
+let mut session =
+    ResearchSession::new("IF888.CFFEX", Interval::MINUTE, "2024-01-02", "2024-03-29")?;
+let bars = session.load_bars()?;
+let run = session.backtest_fn(|context, bar| {
+    if bar.close_price > bar.open_price && context.pos() == 0.0 {
+        context.buy(bar.close_price, 1.0);
+    } else if bar.close_price < bar.open_price && context.pos() > 0.0 {
+        context.sell(bar.close_price, 1.0);
+    }
+})?;
+println!("{}", run.statistics.sharpe_ratio);
+session.plot("IF888.svg", &[])?;
+# Ok::<(), VnrsError>(())
+```
+*/
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use polars::prelude::*;
+
+use super::backtesting::{BacktestingEngine, BacktestingStatistics};
+use super::base::BacktestingMode;
+use super::quick::QuickContext;
+use super::report::{statistics_rows, StatisticsFormatter, StatisticsStyle};
+use super::sdk::{strategy_class, CtaStrategy};
+use crate::error::{VnrsError, VnrsResult};
+use crate::vnrs::chart::CandleChart;
+use crate::vnrs::trader::constant::Interval;
+use crate::vnrs::trader::database::get_database;
+use crate::vnrs::trader::object::{BarData, TradeData};
+use crate::vnrs::trader::utility::extract_vt_symbol;
+
+///Symbol, period and costs of the backtests of a notebook, with the bars loaded once for all
+///of them.
+pub struct ResearchSession {
+    pub vt_symbol: String,
+    pub interval: Interval,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    ///Commission rate, 0 for the built-in rate of the product.
+    pub rate: f64,
+    pub slippage: f64,
+    pub capital: f64,
+    bars: Option<Vec<BarData>>,
+}
+
+///Backtest run by a session, its engine left for the trades, daily results and other
+///details.
+pub struct ResearchRun {
+    pub engine: BacktestingEngine,
+    pub statistics: BacktestingStatistics,
+    ///Output of the engine, kept out of the notebook.
+    pub logs: Vec<String>,
+}
+
+///Parse a %Y-%m-%d date.
+fn parse_date(date: &str) -> VnrsResult<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| VnrsError::Setting(format!("invalid date {}", date)))
+}
+
+impl ResearchSession {
+    ///Session over the whole days start to end, as %Y-%m-%d.
+    pub fn new(vt_symbol: &str, interval: Interval, start: &str, end: &str) -> VnrsResult<Self> {
+        extract_vt_symbol(vt_symbol)?;
+        Ok(ResearchSession {
+            vt_symbol: vt_symbol.to_string(),
+            interval,
+            start: parse_date(start)?.into(),
+            end: parse_date(end)?.and_hms_opt(23, 59, 59).unwrap(),
+            rate: 0.0,
+            slippage: 0.0,
+            capital: 1_000_000.0,
+            bars: None,
+        })
+    }
+
+    pub fn with_costs(mut self, rate: f64, slippage: f64) -> Self {
+        self.rate = rate;
+        self.slippage = slippage;
+        self
+    }
+
+    pub fn with_capital(mut self, capital: f64) -> Self {
+        self.capital = capital;
+        self
+    }
+
+    ///Research these bars instead of loading them, e.g. synthetic ones.
+    pub fn with_bars(mut self, bars: Vec<BarData>) -> Self {
+        self.bars = Some(bars);
+        self
+    }
+
+    ///Bars of the session, loaded from the database the first time.
+    pub fn bars(&mut self) -> VnrsResult<&[BarData]> {
+        if self.bars.is_none() {
+            let (symbol, exchange) = extract_vt_symbol(&self.vt_symbol)?;
+            let bars = get_database()?.load_bar_data(
+                &symbol,
+                exchange,
+                self.interval,
+                self.start,
+                self.end,
+            )?;
+            self.bars = Some(bars);
+        }
+        Ok(self.bars.as_deref().unwrap_or_default())
+    }
+
+    ///One row per bar: datetime, open, high, low, close, volume, turnover and open_interest.
+    pub fn load_bars(&mut self) -> VnrsResult<DataFrame> {
+        let bars = self.bars()?;
+        let column = |name: &str, f: fn(&BarData) -> f64| {
+            Series::new(name, bars.iter().map(f).collect::<Vec<f64>>())
+        };
+        Ok(DataFrame::new(vec![
+            Series::new(
+                "datetime",
+                bars.iter()
+                    .map(|bar| bar.datetime)
+                    .collect::<Vec<NaiveDateTime>>(),
+            ),
+            column("open", |bar| bar.open_price),
+            column("high", |bar| bar.high_price),
+            column("low", |bar| bar.low_price),
+            column("close", |bar| bar.close_price),
+            column("volume", |bar| bar.volume),
+            column("turnover", |bar| bar.turnover),
+            column("open_interest", |bar| bar.open_interest),
+        ])?)
+    }
+
+    ///Backtest a strategy type of the notebook with its "name:value" setting.
+    pub fn backtest<S: CtaStrategy + Send>(&mut self, setting: &str) -> VnrsResult<ResearchRun> {
+        let name = std::any::type_name::<S>()
+            .rsplit("::")
+            .next()
+            .unwrap_or_default();
+        let class = Arc::new(strategy_class::<S>(name));
+        self.run(|engine| engine.add_strategy(class, setting))
+    }
+
+    ///Backtest a closure over the bars, see BacktestingEngine::add_strategy_fn.
+    pub fn backtest_fn<F>(&mut self, on_bar: F) -> VnrsResult<ResearchRun>
+    where
+        F: FnMut(&mut QuickContext, &BarData) + Send + 'static,
+    {
+        self.run(|engine| {
+            engine.add_strategy_fn(on_bar);
+            Ok(())
+        })
+    }
+
+    fn run(
+        &mut self,
+        add_strategy: impl FnOnce(&mut BacktestingEngine) -> VnrsResult<()>,
+    ) -> VnrsResult<ResearchRun> {
+        let bars = self.bars()?.to_vec();
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = BacktestingEngine::new();
+        let output = logs.clone();
+        engine.set_output_callback(move |msg| output.lock().unwrap().push(msg.to_string()));
+        engine.set_parameters(
+            &self.vt_symbol,
+            self.interval,
+            self.start,
+            self.end,
+            self.rate,
+            self.slippage,
+            0.0,
+            0.0,
+            self.capital,
+            BacktestingMode::BAR,
+            0.0,
+            0,
+            120,
+        )?;
+        add_strategy(&mut engine)?;
+        engine.set_history_data(bars);
+        engine.run_backtesting()?;
+        engine.calculate_result();
+        let statistics = engine.calculate_statistics(None, false);
+        let logs = std::mem::take(&mut *logs.lock().unwrap());
+        Ok(ResearchRun {
+            engine,
+            statistics,
+            logs,
+        })
+    }
+
+    ///Save a candle chart of the bars as SVG, with indicator lines aligned with the bars.
+    pub fn plot(
+        &mut self,
+        path: impl AsRef<Path>,
+        overlays: &[(&str, Vec<f64>)],
+    ) -> VnrsResult<()> {
+        let title = self.vt_symbol.clone();
+        let mut chart = CandleChart::new(&title, self.bars()?.to_vec());
+        for (name, values) in overlays {
+            chart.add_overlay(name, values.clone());
+        }
+        chart
+            .save_svg(path, 1200, 800)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        Ok(())
+    }
+}
+
+impl ResearchRun {
+    pub fn trades(&self) -> Vec<TradeData> {
+        self.engine.get_all_trades()
+    }
+
+    ///Daily results as calculate_result returns them.
+    pub fn daily_df(&self) -> Option<DataFrame> {
+        self.engine.get_daily_df()
+    }
+
+    ///Statistics table shown by evcxr as the value of a cell.
+    pub fn evcxr_display(&self) {
+        let formatter = StatisticsFormatter {
+            style: StatisticsStyle::Table,
+            ..Default::default()
+        };
+        let rows = statistics_rows(&self.statistics, false);
+        println!("EVCXR_BEGIN_CONTENT text/plain");
+        for line in formatter.render(&rows) {
+            println!("{}", line);
+        }
+        println!("EVCXR_END_CONTENT");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::constant::Exchange;
+    use crate::vnrs::trader::synthetic::SyntheticBars;
+    use crate::vnrs_ctastrategy::sdk::StrategyContext;
+
+    struct BuyAndHold {
+        volume: f64,
+    }
+
+    impl CtaStrategy for BuyAndHold {
+        fn new(_context: &StrategyContext, setting: &[(String, String)]) -> Self {
+            BuyAndHold {
+                volume: crate::vnrs_ctastrategy::sdk::setting_value(setting, "volume", 1.0),
+            }
+        }
+
+        fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData) {
+            if context.pos() == 0.0 {
+                context.buy(bar.close_price + 10.0, self.volume, false);
+            }
+        }
+    }
+
+    #[test]
+    fn quick_backtests_in_session() {
+        let session =
+            ResearchSession::new("IF888.CFFEX", Interval::MINUTE, "2024-01-02", "2024-01-05")
+                .unwrap();
+        let bars = SyntheticBars {
+            symbol: "IF888".to_string(),
+            exchange: Exchange::CFFEX,
+            start: session.start,
+            count: 600,
+            start_price: 3500.0,
+            pricetick: 0.2,
+            ..Default::default()
+        }
+        .generate();
+        let mut session = session.with_costs(2.3e-5, 0.2).with_bars(bars);
+
+        let df = session.load_bars().unwrap();
+        assert_eq!(df.shape(), (600, 8));
+
+        let run = session.backtest::<BuyAndHold>("volume:2").unwrap();
+        assert_eq!(run.trades().len(), 1);
+        assert_eq!(run.trades()[0].volume, 2.0);
+        assert!(!run.logs.is_empty());
+
+        let mut count = 0;
+        let run = session
+            .backtest_fn(move |context, bar| {
+                count += 1;
+                if count % 100 == 0 {
+                    let price = bar.close_price;
+                    if context.pos() == 0.0 {
+                        context.buy(price + 10.0, 1.0);
+                    } else {
+                        context.sell(price - 10.0, 1.0);
+                    }
+                }
+            })
+            .unwrap();
+        assert_eq!(run.trades().len(), 5);
+        assert_eq!(run.statistics.total_trade_count, 5);
+        assert!(run.daily_df().is_some());
+    }
+}
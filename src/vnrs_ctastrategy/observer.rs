@@ -0,0 +1,41 @@
+/*!Observers computing custom analytics during replay, e.g. factors of the bars or execution
+quality of the orders, without changes to the engine. Register them with
+BacktestingEngine::add_observer, keeping an Arc<Mutex<_>> clone to read the results after the
+run. */
+use std::sync::{Arc, Mutex};
+
+use super::backtesting::DailyResult;
+use crate::vnrs::trader::object::{BarData, OrderData, TradeData};
+
+///Callbacks of a replay, all doing nothing by default.
+pub trait BacktestObserver: Send {
+    ///After the engine and the strategy processed bar, with the position after it.
+    fn on_bar_processed(&mut self, _bar: &BarData, _pos: f64) {}
+
+    ///New order or a change of its status.
+    fn on_order(&mut self, _order: &OrderData) {}
+
+    fn on_trade(&mut self, _trade: &TradeData) {}
+
+    ///Final result of a trading day, once the first bar of the next day arrives or the replay
+    ///ends.
+    fn on_day_closed(&mut self, _result: &DailyResult) {}
+}
+
+impl<T: BacktestObserver> BacktestObserver for Arc<Mutex<T>> {
+    fn on_bar_processed(&mut self, bar: &BarData, pos: f64) {
+        self.lock().unwrap().on_bar_processed(bar, pos);
+    }
+
+    fn on_order(&mut self, order: &OrderData) {
+        self.lock().unwrap().on_order(order);
+    }
+
+    fn on_trade(&mut self, trade: &TradeData) {
+        self.lock().unwrap().on_trade(trade);
+    }
+
+    fn on_day_closed(&mut self, result: &DailyResult) {
+        self.lock().unwrap().on_day_closed(result);
+    }
+}
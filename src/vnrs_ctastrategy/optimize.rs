@@ -0,0 +1,1404 @@
+/*!Parameter optimization: every combination of an OptimizationSetting is backtested as a
+BatchJob and the results are ranked by a statistic or an Objective blending them, or by
+pareto_ranks over several objectives. Each result is reported with the best so far as it
+finishes, and the run stops early once the target stops improving if the setting has a patience.
+Combinations breaking a constraint between parameters, e.g. fast_window < slow_window, are never
+run. With a study id every result is saved to the database as it finishes, running the study
+again only runs the settings it is missing. run_optimization runs them on the threads of a
+BatchRunner, tpe::TpeOptimizer samples a few of them instead when every backtest is expensive;
+for sweeps too large for one machine, `vnrs optimize-worker --listen <addr>` serves
+OptimizationWorker on each machine and a DistributedOptimizer shards the combinations across
+them over TCP, one JSON line per request and reply. Workers only serve coordinators sending
+their "optimize.token" setting, and load the strategy by name from their own strategy directory
+and the bars from their own database. */
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use super::backtesting::BacktestingStatistics;
+use super::base::{strategy_name, BacktestingMode, StrategyDirectory};
+use super::batch::{BatchJob, BatchResult, BatchRunner};
+use crate::error::{VnrsError, VnrsResult};
+use crate::vnrs::trader::database::{get_database, BaseDatabase, OptimizationTrial};
+use crate::vnrs::trader::setting::get_settings;
+
+///Longest request line a worker reads, longer ones close the connection.
+const MAX_REQUEST_LEN: u64 = 16 * 1024 * 1024;
+///Coordinators a worker serves at once, others are turned away.
+const MAX_CONNECTIONS: usize = 16;
+
+///Values a parameter of an OptimizationSetting takes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Parameter {
+    ///Values tried one by one, the numbers of a grid or the choices of a categorical
+    ///parameter.
+    Values(Vec<String>),
+    ///Any integer from low to high inclusive.
+    Int { low: i64, high: i64 },
+    ///Any number from low to high. Only searched by the TPE optimizer, the grid tries the
+    ///two ends.
+    Float { low: f64, high: f64 },
+}
+
+impl Parameter {
+    ///Values of the grid, as many as value_count, only meant for grid searches of small
+    ///ranges.
+    pub fn values(&self) -> Vec<String> {
+        match self {
+            Parameter::Values(values) => values.clone(),
+            Parameter::Int { low, high } => (*low..=*high).map(|value| value.to_string()).collect(),
+            Parameter::Float { low, high } if low == high => vec![format_value(*low)],
+            Parameter::Float { low, high } => vec![format_value(*low), format_value(*high)],
+        }
+    }
+
+    ///Number of values of the grid, None if it doesn't fit in usize.
+    pub fn value_count(&self) -> Option<usize> {
+        match self {
+            Parameter::Values(values) => Some(values.len()),
+            Parameter::Int { low, high } => usize::try_from(*high as i128 - *low as i128 + 1).ok(),
+            Parameter::Float { low, high } if low == high => Some(1),
+            Parameter::Float { .. } => Some(2),
+        }
+    }
+
+    ///Whether value is one the parameter takes, without listing the values of ranges.
+    pub fn contains(&self, value: &str) -> bool {
+        match self {
+            Parameter::Values(values) => values.iter().any(|v| v == value),
+            Parameter::Int { low, high } => value
+                .parse::<i64>()
+                .is_ok_and(|value| (*low..=*high).contains(&value)),
+            Parameter::Float { low, high } => value
+                .parse::<f64>()
+                .is_ok_and(|value| (*low..=*high).contains(&value)),
+        }
+    }
+}
+
+///Number as a setting value, rounded so that 0.1 steps don't print as 0.30000000000000004.
+pub fn format_value(value: f64) -> String {
+    ((value * 1e9).round() / 1e9).to_string()
+}
+
+///Comparison of a Constraint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConstraintOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl ConstraintOp {
+    const SYMBOLS: [(&'static str, ConstraintOp); 6] = [
+        ("<=", ConstraintOp::Le),
+        (">=", ConstraintOp::Ge),
+        ("==", ConstraintOp::Eq),
+        ("!=", ConstraintOp::Ne),
+        ("<", ConstraintOp::Lt),
+        (">", ConstraintOp::Gt),
+    ];
+
+    fn holds(&self, ordering: std::cmp::Ordering) -> bool {
+        match self {
+            ConstraintOp::Lt => ordering.is_lt(),
+            ConstraintOp::Le => ordering.is_le(),
+            ConstraintOp::Gt => ordering.is_gt(),
+            ConstraintOp::Ge => ordering.is_ge(),
+            ConstraintOp::Eq => ordering.is_eq(),
+            ConstraintOp::Ne => ordering.is_ne(),
+        }
+    }
+}
+
+///Condition every setting tried must meet, left and right being names of parameters or
+///numbers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Constraint {
+    pub left: String,
+    pub op: ConstraintOp,
+    pub right: String,
+}
+
+impl Constraint {
+    ///Whether the values of setting meet the constraint. Numbers compare as numbers, other
+    ///values as text.
+    pub fn holds(&self, setting: &HashMap<&str, &str>) -> bool {
+        let operand = |side: &str| setting.get(side).copied().unwrap_or(side).to_string();
+        let (left, right) = (operand(&self.left), operand(&self.right));
+        let ordering = match (left.parse::<f64>(), right.parse::<f64>()) {
+            (Ok(left), Ok(right)) => left.total_cmp(&right),
+            _ => left.cmp(&right),
+        };
+        self.op.holds(ordering)
+    }
+}
+
+impl std::fmt::Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (symbol, _) = ConstraintOp::SYMBOLS
+            .iter()
+            .find(|(_, op)| *op == self.op)
+            .unwrap();
+        write!(f, "{} {} {}", self.left, symbol, self.right)
+    }
+}
+
+///Number to maximize computed from the statistics of a backtest, a statistic or a blend of
+///them such as sharpe_ratio - 0.1 * max_ddpercent.abs().
+#[derive(Clone)]
+pub struct Objective {
+    pub name: String,
+    func: Arc<dyn Fn(&BacktestingStatistics) -> f64 + Send + Sync>,
+}
+
+impl Objective {
+    pub fn new(
+        name: &str,
+        func: impl Fn(&BacktestingStatistics) -> f64 + Send + Sync + 'static,
+    ) -> Self {
+        Objective {
+            name: name.to_string(),
+            func: Arc::new(func),
+        }
+    }
+
+    ///The numeric field name of BacktestingStatistics, e.g. max_ddpercent to rank by the
+    ///drawdown, which is negative.
+    pub fn statistic(name: &str) -> Self {
+        let field = name.to_string();
+        Objective::new(name, move |statistics| {
+            target_value(statistics, &field).unwrap_or(f64::NAN)
+        })
+    }
+
+    ///Value for result, None if the backtest failed or the value isn't finite.
+    pub fn value(&self, result: &OptimizationResult) -> Option<f64> {
+        let value = (self.func)(result.statistics.as_ref().ok()?);
+        value.is_finite().then_some(value)
+    }
+}
+
+impl std::fmt::Debug for Objective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Objective")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+///Values of the parameters to optimize and the statistic to maximize.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OptimizationSetting {
+    pub parameters: Vec<(String, Parameter)>,
+    ///Field of BacktestingStatistics to maximize, e.g. sharpe_ratio, or the name of the
+    ///objective.
+    pub target_name: String,
+    ///Maximized instead of the field target_name if set, evaluated where the results are
+    ///collected so that workers never need it.
+    #[serde(skip)]
+    pub objective: Option<Objective>,
+    ///Stop after this many backtests in a row without a better target, None to run all.
+    #[serde(default)]
+    pub patience: Option<usize>,
+    ///Settings breaking any of them are skipped.
+    #[serde(default)]
+    pub constraints: Vec<Constraint>,
+    ///Name the results are saved under in the database as they finish, None to not save
+    ///them.
+    #[serde(default)]
+    pub study_id: Option<String>,
+}
+
+impl OptimizationSetting {
+    pub fn new(target_name: &str) -> Self {
+        OptimizationSetting {
+            parameters: Vec::new(),
+            target_name: target_name.to_string(),
+            objective: None,
+            patience: None,
+            constraints: Vec::new(),
+            study_id: None,
+        }
+    }
+
+    ///Maximize objective instead of a single statistic, target_name becoming its name.
+    pub fn set_objective(&mut self, objective: Objective) {
+        self.target_name = objective.name.clone();
+        self.objective = Some(objective);
+    }
+
+    ///Value to maximize of result, None if the backtest failed.
+    pub fn target(&self, result: &OptimizationResult) -> Option<f64> {
+        match &self.objective {
+            Some(objective) => objective.value(result),
+            None => result.target(&self.target_name),
+        }
+    }
+
+    ///Best first by the target, failed backtests last.
+    pub fn sort_results(&self, results: &mut [OptimizationResult]) {
+        results.sort_by(|a, b| {
+            let (a, b) = (self.target(a), self.target(b));
+            b.unwrap_or(f64::NEG_INFINITY)
+                .total_cmp(&a.unwrap_or(f64::NEG_INFINITY))
+        });
+    }
+
+    ///Save every result to the database under study_id as it finishes, and skip the settings
+    ///already saved under it when run again, e.g. to resume an interrupted optimization. The
+    ///same study_id is meant for the same strategy, job and parameters.
+    pub fn set_study(&mut self, study_id: &str) {
+        self.study_id = Some(study_id.to_string());
+    }
+
+    ///Stop early after patience backtests in a row without improving the target.
+    pub fn set_patience(&mut self, patience: usize) {
+        self.patience = Some(patience.max(1));
+    }
+
+    ///Try name from start to end inclusive by step.
+    pub fn add_parameter(&mut self, name: &str, start: f64, end: f64, step: f64) -> VnrsResult<()> {
+        if step <= 0.0 || end < start {
+            return Err(VnrsError::Setting(format!(
+                "invalid range {} to {} by {} of parameter {}",
+                start, end, step, name
+            )));
+        }
+        let count = ((end - start) / step + 1e-9).floor() as usize + 1;
+        let values = (0..count)
+            .map(|i| format_value(start + i as f64 * step))
+            .collect();
+        self.parameters
+            .push((name.to_string(), Parameter::Values(values)));
+        Ok(())
+    }
+
+    ///Try name with each of values, e.g. the names of modes.
+    pub fn add_choices(&mut self, name: &str, values: &[&str]) {
+        let values = values.iter().map(|value| value.to_string()).collect();
+        self.parameters
+            .push((name.to_string(), Parameter::Values(values)));
+    }
+
+    ///Search name over the integers from low to high inclusive.
+    pub fn add_int_range(&mut self, name: &str, low: i64, high: i64) -> VnrsResult<()> {
+        if high < low {
+            return Err(VnrsError::Setting(format!(
+                "invalid range {} to {} of parameter {}",
+                low, high, name
+            )));
+        }
+        self.parameters
+            .push((name.to_string(), Parameter::Int { low, high }));
+        Ok(())
+    }
+
+    ///Search name over the numbers from low to high, see Parameter::Float.
+    pub fn add_float_range(&mut self, name: &str, low: f64, high: f64) -> VnrsResult<()> {
+        if low.is_nan() || high.is_nan() || high < low {
+            return Err(VnrsError::Setting(format!(
+                "invalid range {} to {} of parameter {}",
+                low, high, name
+            )));
+        }
+        self.parameters
+            .push((name.to_string(), Parameter::Float { low, high }));
+        Ok(())
+    }
+
+    ///Only try settings meeting constraint, e.g. "fast_window < slow_window" or
+    ///"mode != slow", comparing with <, <=, >, >=, == or !=. Both sides of the comparison are
+    ///parameters added before, numbers, or choices of the parameter on the other side.
+    pub fn add_constraint(&mut self, constraint: &str) -> VnrsResult<()> {
+        let invalid = |reason: &str| {
+            VnrsError::Setting(format!("invalid constraint {}: {}", constraint, reason))
+        };
+        let (symbol, op, position) = ConstraintOp::SYMBOLS
+            .iter()
+            .find_map(|(symbol, op)| constraint.find(symbol).map(|i| (symbol, *op, i)))
+            .ok_or_else(|| invalid("no comparison"))?;
+        let left = constraint[..position].trim();
+        let right = constraint[position + symbol.len()..].trim();
+        let parameter = |side: &str| {
+            self.parameters
+                .iter()
+                .find(|(name, _)| name == side)
+                .map(|(_, parameter)| parameter)
+        };
+        for (side, other) in [(left, right), (right, left)] {
+            // A value is a number or a choice of the parameter it is compared with
+            let choice = parameter(other).is_some_and(|other| other.contains(side));
+            if parameter(side).is_none() && side.parse::<f64>().is_err() && !choice {
+                return Err(invalid(&format!("unknown parameter {:?}", side)));
+            }
+        }
+        self.constraints.push(Constraint {
+            left: left.to_string(),
+            op,
+            right: right.to_string(),
+        });
+        Ok(())
+    }
+
+    ///Whether the "name:value,name:value" setting meets every constraint.
+    pub fn satisfies(&self, setting: &str) -> bool {
+        let values: HashMap<&str, &str> = setting
+            .split(',')
+            .filter_map(|pair| pair.split_once(':'))
+            .collect();
+        self.constraints
+            .iter()
+            .all(|constraint| constraint.holds(&values))
+    }
+
+    ///Combinations of the grid meeting the constraints, usize::MAX for a grid too large to
+    ///count. With constraints every combination is generated, see grid_size for the count
+    ///without them.
+    pub fn combination_count(&self) -> usize {
+        if self.constraints.is_empty() {
+            self.grid_size().unwrap_or(usize::MAX)
+        } else {
+            self.generate_settings().len()
+        }
+    }
+
+    ///Combinations of the grid ignoring the constraints, None if there are more than usize
+    ///holds.
+    pub fn grid_size(&self) -> Option<usize> {
+        self.parameters
+            .iter()
+            .try_fold(1usize, |size, (_, parameter)| {
+                size.checked_mul(parameter.value_count()?)
+            })
+    }
+
+    ///Every combination meeting the constraints as a "name:value,name:value" setting, the last
+    ///parameter varying fastest.
+    pub fn generate_settings(&self) -> Vec<String> {
+        let mut settings = vec![Vec::new()];
+        for (name, parameter) in &self.parameters {
+            let values = parameter.values();
+            settings = settings
+                .into_iter()
+                .flat_map(|setting: Vec<String>| {
+                    values.iter().map(move |value| {
+                        let mut setting = setting.clone();
+                        setting.push(format!("{}:{}", name, value));
+                        setting
+                    })
+                })
+                .collect();
+        }
+        if self.parameters.is_empty() {
+            return Vec::new();
+        }
+        settings
+            .into_iter()
+            .map(|setting| setting.join(","))
+            .filter(|setting| self.satisfies(setting))
+            .collect()
+    }
+
+    ///Settings to run, logging the combinations skipped by the constraints.
+    fn valid_settings(&self) -> Vec<String> {
+        let settings = self.generate_settings();
+        let skipped = self
+            .grid_size()
+            .unwrap_or(usize::MAX)
+            .saturating_sub(settings.len());
+        if skipped > 0 && !self.parameters.is_empty() {
+            output(&format!(
+                "跳过{}组不满足约束的参数：{}",
+                skipped,
+                self.constraints
+                    .iter()
+                    .map(|constraint| constraint.to_string())
+                    .collect::<Vec<String>>()
+                    .join("，")
+            ));
+        }
+        settings
+    }
+}
+
+///Backtest of one setting, failed ones carry the error message instead of statistics.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptimizationResult {
+    pub setting: String,
+    pub statistics: Result<BacktestingStatistics, String>,
+    ///Seconds the backtest took.
+    #[serde(default)]
+    pub duration: f64,
+}
+
+impl OptimizationResult {
+    ///Value of the statistic named target_name, None if the backtest failed.
+    pub fn target(&self, target_name: &str) -> Option<f64> {
+        target_value(self.statistics.as_ref().ok()?, target_name)
+    }
+
+    fn to_trial(&self, study_id: &str) -> OptimizationTrial {
+        OptimizationTrial {
+            study_id: study_id.to_string(),
+            created: Local::now().naive_local(),
+            setting: self.setting.clone(),
+            statistics: self
+                .statistics
+                .as_ref()
+                .ok()
+                .and_then(|statistics| serde_json::to_value(statistics).ok())
+                .unwrap_or_default(),
+            error: self.statistics.as_ref().err().cloned(),
+            duration: self.duration,
+        }
+    }
+
+    fn from_trial(trial: OptimizationTrial) -> Self {
+        let statistics = match trial.error {
+            Some(error) => Err(error),
+            // Statistics that weren't finite are stored with nulls
+            None => serde_json::from_value(trial.statistics)
+                .map_err(|_| "statistics not finite".to_string()),
+        };
+        OptimizationResult {
+            setting: trial.setting,
+            statistics,
+            duration: trial.duration,
+        }
+    }
+}
+
+///Results of the optimization study_id saved in the database, in the order they finished,
+///e.g. to analyze a study without running it again.
+pub fn load_study(study_id: &str) -> VnrsResult<Vec<OptimizationResult>> {
+    let trials = get_database()?.load_optimization_trials(study_id)?;
+    Ok(trials
+        .into_iter()
+        .map(OptimizationResult::from_trial)
+        .collect())
+}
+
+///Value of the numeric field name of statistics.
+pub fn target_value(statistics: &BacktestingStatistics, name: &str) -> Option<f64> {
+    serde_json::to_value(statistics).ok()?.get(name)?.as_f64()
+}
+
+///Best first by target_name, failed backtests last.
+pub fn sort_results(results: &mut [OptimizationResult], target_name: &str) {
+    OptimizationSetting::new(target_name).sort_results(results);
+}
+
+///Pareto rank of each result over objectives, all maximized: 0 for the results no other result
+///beats on every objective, the Pareto front, 1 for the front of the rest and so on. None for
+///the results without a value for every objective.
+pub fn pareto_ranks(
+    results: &[OptimizationResult],
+    objectives: &[Objective],
+) -> Vec<Option<usize>> {
+    let values: Vec<Option<Vec<f64>>> = results
+        .iter()
+        .map(|result| {
+            objectives
+                .iter()
+                .map(|objective| objective.value(result))
+                .collect()
+        })
+        .collect();
+    let dominates = |a: &[f64], b: &[f64]| {
+        a.iter().zip(b).all(|(a, b)| a >= b) && a.iter().zip(b).any(|(a, b)| a > b)
+    };
+
+    let mut ranks = vec![None; results.len()];
+    let mut rest: Vec<usize> = (0..results.len())
+        .filter(|i| values[*i].is_some())
+        .collect();
+    let mut rank = 0;
+    while !rest.is_empty() {
+        let (front, dominated): (Vec<usize>, Vec<usize>) = rest.iter().partition(|i| {
+            let value = values[**i].as_ref().unwrap();
+            !rest
+                .iter()
+                .any(|j| dominates(values[*j].as_ref().unwrap(), value))
+        });
+        for i in front {
+            ranks[i] = Some(rank);
+        }
+        rest = dominated;
+        rank += 1;
+    }
+    ranks
+}
+
+///Results on the Pareto front of objectives in their order in results, e.g. the best
+///tradeoffs between Objective::statistic("total_return") and
+///Objective::statistic("max_ddpercent").
+pub fn pareto_front<'a>(
+    results: &'a [OptimizationResult],
+    objectives: &[Objective],
+) -> Vec<&'a OptimizationResult> {
+    results
+        .iter()
+        .zip(pareto_ranks(results, objectives))
+        .filter(|(_, rank)| *rank == Some(0))
+        .map(|(result, _)| result)
+        .collect()
+}
+
+///Progress of a running optimization, passed to its callback after every backtest.
+#[derive(Debug, Clone, Copy)]
+pub struct OptimizationProgress<'a> {
+    pub completed: usize,
+    pub total: usize,
+    ///Backtest just finished and its target, None if it failed.
+    pub result: &'a OptimizationResult,
+    pub target: Option<f64>,
+    ///Best setting so far and its target.
+    pub best: Option<(&'a str, f64)>,
+}
+
+///Print the progress to stdout, the callback of run_optimization.
+pub fn output_progress(progress: &OptimizationProgress) {
+    let target = progress
+        .target
+        .map_or("-".to_string(), |value| format!("{:.4}", value));
+    let best = progress.best.map_or("-".to_string(), |(setting, value)| {
+        format!("{} {:.4}", setting, value)
+    });
+    output(&format!(
+        "优化进度：{}/{}，{} {}，最优：{}",
+        progress.completed, progress.total, progress.result.setting, target, best
+    ));
+}
+
+///Best result so far of a running optimization, deciding when it stops early.
+pub(crate) struct Tracker<'a, F> {
+    optimization_setting: &'a OptimizationSetting,
+    total: usize,
+    completed: usize,
+    best: Option<(String, f64)>,
+    since_best: usize,
+    on_progress: F,
+    ///Database the results are saved to with the study id, None without a study.
+    study: Option<(String, Arc<dyn BaseDatabase + Send + Sync>)>,
+}
+
+impl<'a, F: Fn(&OptimizationProgress)> Tracker<'a, F> {
+    pub fn new(
+        optimization_setting: &'a OptimizationSetting,
+        total: usize,
+        on_progress: F,
+    ) -> Self {
+        Tracker {
+            optimization_setting,
+            total,
+            completed: 0,
+            best: None,
+            since_best: 0,
+            on_progress,
+            study: None,
+        }
+    }
+
+    ///Results saved by earlier runs of the study of the setting, counted as completed. Without
+    ///a database the study isn't saved and starts over.
+    pub fn resume(&mut self) -> Vec<OptimizationResult> {
+        let Some(study_id) = self.optimization_setting.study_id.clone() else {
+            return Vec::new();
+        };
+        let loaded = get_database().and_then(|database| {
+            let trials = database.load_optimization_trials(&study_id)?;
+            Ok((database, trials))
+        });
+        let (database, trials) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                output(&format!(
+                    "无法读取优化记录{}，结果不会保存：{}",
+                    study_id, e
+                ));
+                return Vec::new();
+            }
+        };
+        self.study = Some((study_id.clone(), database));
+
+        let results: Vec<OptimizationResult> = trials
+            .into_iter()
+            .map(OptimizationResult::from_trial)
+            .collect();
+        for result in &results {
+            self.completed += 1;
+            self.update_best(result);
+        }
+        if !results.is_empty() {
+            output(&format!(
+                "继续优化{}，已完成{}组参数",
+                study_id,
+                results.len()
+            ));
+        }
+        results
+    }
+
+    fn update_best(&mut self, result: &OptimizationResult) -> Option<f64> {
+        let target = self.optimization_setting.target(result);
+        match target {
+            Some(value) if self.best.as_ref().is_none_or(|(_, best)| value > *best) => {
+                self.best = Some((result.setting.clone(), value));
+                self.since_best = 0;
+            }
+            _ => self.since_best += 1,
+        }
+        target
+    }
+
+    ///Report result and save it to the study, false once the optimization should stop.
+    pub fn record(&mut self, result: &OptimizationResult) -> bool {
+        self.completed += 1;
+        let target = self.update_best(result);
+        if let Some((study_id, database)) = &self.study {
+            if let Err(e) = database.save_optimization_trial(result.to_trial(study_id)) {
+                output(&format!("保存优化结果失败：{} {}", result.setting, e));
+            }
+        }
+        (self.on_progress)(&OptimizationProgress {
+            completed: self.completed,
+            total: self.total,
+            result,
+            target,
+            best: self
+                .best
+                .as_ref()
+                .map(|(setting, value)| (setting.as_str(), *value)),
+        });
+
+        let stop = self
+            .optimization_setting
+            .patience
+            .is_some_and(|patience| self.since_best >= patience);
+        if stop {
+            output(&format!(
+                "连续{}组参数没有改进，提前停止优化",
+                self.since_best
+            ));
+        }
+        !stop
+    }
+}
+
+///Backtest job with every setting of optimization_setting on the threads of runner, best first.
+pub fn run_optimization(
+    runner: &BatchRunner,
+    job: &BatchJob,
+    optimization_setting: &OptimizationSetting,
+) -> Vec<OptimizationResult> {
+    run_optimization_with(runner, job, optimization_setting, output_progress)
+}
+
+///run_optimization passing the progress to on_progress after every backtest instead of
+///printing it, e.g. to send it down a channel. Only the settings run before an early stop are
+///in the results.
+pub fn run_optimization_with(
+    runner: &BatchRunner,
+    job: &BatchJob,
+    optimization_setting: &OptimizationSetting,
+    on_progress: impl Fn(&OptimizationProgress) + Send,
+) -> Vec<OptimizationResult> {
+    let mut settings = optimization_setting.valid_settings();
+    let mut tracker = Tracker::new(optimization_setting, settings.len(), on_progress);
+    let previous = tracker.resume();
+    skip_finished(&mut settings, &previous);
+    let tracker = Mutex::new(tracker);
+    let mut results = run_settings_until(runner, job, &settings, |result| {
+        tracker.lock().unwrap().record(result)
+    });
+    results.extend(previous);
+    optimization_setting.sort_results(&mut results);
+    results
+}
+
+///Remove the settings of previous results, run by an earlier run of the study.
+fn skip_finished(settings: &mut Vec<String>, previous: &[OptimizationResult]) {
+    let finished: HashSet<&str> = previous
+        .iter()
+        .map(|result| result.setting.as_str())
+        .collect();
+    settings.retain(|setting| !finished.contains(setting.as_str()));
+}
+
+///Results in the order of settings.
+pub(crate) fn run_settings(
+    runner: &BatchRunner,
+    job: &BatchJob,
+    settings: &[String],
+) -> Vec<OptimizationResult> {
+    run_settings_until(runner, job, settings, |_| true)
+}
+
+///Results of the settings run until on_result returns false, in the order of settings.
+pub(crate) fn run_settings_until(
+    runner: &BatchRunner,
+    job: &BatchJob,
+    settings: &[String],
+    on_result: impl Fn(&OptimizationResult) -> bool + Sync,
+) -> Vec<OptimizationResult> {
+    let jobs: Vec<BatchJob> = settings
+        .iter()
+        .map(|setting| BatchJob {
+            setting: setting.clone(),
+            ..job.clone()
+        })
+        .collect();
+    let to_result = |result: &BatchResult| OptimizationResult {
+        setting: result.job.setting.clone(),
+        statistics: result.statistics.clone(),
+        duration: result.duration.as_secs_f64(),
+    };
+    runner
+        .run_jobs_until(&jobs, |result| on_result(&to_result(result)))
+        .iter()
+        .map(to_result)
+        .collect()
+}
+
+///Backtesting parameters of a BatchRunner sent along with the jobs to a worker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunnerParameters {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub rate: f64,
+    pub slippage: f64,
+    pub size: f64,
+    pub pricetick: f64,
+    pub capital: f64,
+    pub mode: BacktestingMode,
+    pub risk_free: f64,
+    pub annual_days: Option<i64>,
+    pub half_life: i64,
+}
+
+impl RunnerParameters {
+    pub fn from_runner(runner: &BatchRunner) -> Self {
+        RunnerParameters {
+            start: runner.start,
+            end: runner.end,
+            rate: runner.rate,
+            slippage: runner.slippage,
+            size: runner.size,
+            pricetick: runner.pricetick,
+            capital: runner.capital,
+            mode: runner.mode,
+            risk_free: runner.risk_free,
+            annual_days: runner.annual_days,
+            half_life: runner.half_life,
+        }
+    }
+
+    fn runner(&self) -> BatchRunner {
+        let mut runner = BatchRunner::new();
+        runner.set_parameters(
+            self.start,
+            self.end,
+            self.rate,
+            self.slippage,
+            self.size,
+            self.pricetick,
+            self.capital,
+            self.mode,
+            self.risk_free,
+            self.annual_days,
+            self.half_life,
+        );
+        runner
+    }
+}
+
+///Shard of the settings of an optimization sent to a worker, its job naming the strategy in the
+///worker's strategy directory.
+#[derive(Debug, Serialize, Deserialize)]
+struct WorkerRequest {
+    token: String,
+    parameters: RunnerParameters,
+    job: BatchJob,
+    settings: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WorkerReply {
+    results: Result<Vec<OptimizationResult>, String>,
+}
+
+///Backtests the shards of DistributedOptimizers connecting to it with its token on all its
+///cores.
+pub struct OptimizationWorker {
+    listener: TcpListener,
+    max_workers: usize,
+    strategies: Arc<StrategyDirectory>,
+    token: Arc<str>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl OptimizationWorker {
+    ///Worker loading strategies from strategies for coordinators sending token, which mustn't
+    ///be empty.
+    pub fn bind(
+        addr: impl ToSocketAddrs,
+        strategies: StrategyDirectory,
+        token: &str,
+    ) -> io::Result<Self> {
+        if token.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "optimization worker needs a token",
+            ));
+        }
+        Ok(OptimizationWorker {
+            listener: TcpListener::bind(addr)?,
+            max_workers: thread::available_parallelism().map_or(1, |n| n.get()),
+            strategies: Arc::new(strategies),
+            token: token.into(),
+            connections: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    ///Serve coordinators until the listener fails, each connection on its own thread and at
+    ///most MAX_CONNECTIONS at once.
+    pub fn serve(&self) -> io::Result<()> {
+        output(&format!("优化节点启动：{}", self.local_addr()?));
+        for stream in self.listener.incoming() {
+            let mut stream = stream?;
+            let peer = stream
+                .peer_addr()
+                .map_or("-".to_string(), |addr| addr.to_string());
+            if self.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                self.connections.fetch_sub(1, Ordering::SeqCst);
+                output(&format!("优化连接{}拒绝：连接数已满", peer));
+                let _ = write_reply(&mut stream, Err("too many connections".to_string()));
+                continue;
+            }
+            let connection = Connection {
+                max_workers: self.max_workers,
+                strategies: self.strategies.clone(),
+                token: self.token.clone(),
+                connections: self.connections.clone(),
+            };
+            thread::spawn(move || {
+                if let Err(e) = connection.serve(stream) {
+                    output(&format!("优化连接{}中断：{}", peer, e));
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+///Connection of a coordinator to an OptimizationWorker, counted until it's dropped.
+struct Connection {
+    max_workers: usize,
+    strategies: Arc<StrategyDirectory>,
+    token: Arc<str>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Connection {
+    ///Reply to the requests of stream until it closes, or sends a line longer than
+    ///MAX_REQUEST_LEN or a wrong token.
+    fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            let len = reader
+                .by_ref()
+                .take(MAX_REQUEST_LEN + 1)
+                .read_line(&mut line)?;
+            if len == 0 {
+                return Ok(());
+            }
+            if len as u64 > MAX_REQUEST_LEN {
+                write_reply(&mut writer, Err("request too long".to_string()))?;
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "request too long",
+                ));
+            }
+            let request = match serde_json::from_str::<WorkerRequest>(&line) {
+                Ok(request) => request,
+                Err(e) => {
+                    write_reply(&mut writer, Err(format!("invalid request: {}", e)))?;
+                    continue;
+                }
+            };
+            if !tokens_equal(&request.token, &self.token) {
+                write_reply(&mut writer, Err("invalid token".to_string()))?;
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "invalid token",
+                ));
+            }
+            write_reply(&mut writer, self.run(request))?;
+        }
+    }
+
+    fn run(&self, mut request: WorkerRequest) -> Result<Vec<OptimizationResult>, String> {
+        output(&format!(
+            "收到优化任务，参数组合数：{}",
+            request.settings.len()
+        ));
+        let strategy = self
+            .strategies
+            .resolve(&request.job.strategy)
+            .map_err(|e| e.to_string())?;
+        request.job.strategy = strategy.to_string_lossy().into_owned();
+        let mut runner = request.parameters.runner();
+        runner.set_max_workers(self.max_workers);
+        let results = run_settings(&runner, &request.job, &request.settings);
+        Ok(results.into_iter().map(sendable).collect())
+    }
+}
+
+fn write_reply(
+    writer: &mut impl Write,
+    results: Result<Vec<OptimizationResult>, String>,
+) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, &WorkerReply { results })?;
+    writer.write_all(b"\n")
+}
+
+///Whether the tokens are equal, taking the same time wherever they differ.
+fn tokens_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+///Result as it reads back from JSON, which has no NaN or infinity: statistics that don't are
+///sent as failed instead of failing the whole reply.
+fn sendable(result: OptimizationResult) -> OptimizationResult {
+    let Ok(statistics) = &result.statistics else {
+        return result;
+    };
+    let round_trip =
+        serde_json::to_value(statistics).and_then(serde_json::from_value::<BacktestingStatistics>);
+    match round_trip {
+        Ok(_) => result,
+        Err(_) => OptimizationResult {
+            statistics: Err("statistics not finite".to_string()),
+            ..result
+        },
+    }
+}
+
+///Serve an OptimizationWorker on addr, blocking, e.g. for `vnrs optimize-worker --listen`,
+///with the strategy directory and token of the settings.
+pub fn serve_worker(addr: &str) -> io::Result<()> {
+    let token = get_settings()["optimize.token"].clone();
+    OptimizationWorker::bind(addr, StrategyDirectory::from_settings(), &token)?.serve()
+}
+
+///Coordinator sharding the settings of an optimization across OptimizationWorkers. Shards are
+///handed out as workers finish, so faster machines take more, and the shard of a worker that
+///drops is given to another one.
+pub struct DistributedOptimizer {
+    ///Addresses of the workers, e.g. "10.0.0.2:50052".
+    pub workers: Vec<String>,
+    pub parameters: RunnerParameters,
+    ///Settings sent to a worker at once.
+    pub shard_size: usize,
+    ///Token of the workers, the "optimize.token" setting by default.
+    pub token: String,
+}
+
+impl DistributedOptimizer {
+    pub fn new(workers: &[&str], runner: &BatchRunner) -> Self {
+        DistributedOptimizer {
+            workers: workers.iter().map(|worker| worker.to_string()).collect(),
+            parameters: RunnerParameters::from_runner(runner),
+            shard_size: 16,
+            token: get_settings()["optimize.token"].clone(),
+        }
+    }
+
+    ///Backtest job with every setting of optimization_setting on the workers, best first, each
+    ///worker loading the strategy of the same name as job's from its strategy directory.
+    ///Settings no worker could run are failed results.
+    pub fn run(
+        &self,
+        job: &BatchJob,
+        optimization_setting: &OptimizationSetting,
+    ) -> Vec<OptimizationResult> {
+        self.run_with(job, optimization_setting, output_progress)
+    }
+
+    ///run passing the progress to on_progress as the results of each shard come back, see
+    ///run_optimization_with.
+    pub fn run_with(
+        &self,
+        job: &BatchJob,
+        optimization_setting: &OptimizationSetting,
+        on_progress: impl Fn(&OptimizationProgress) + Send,
+    ) -> Vec<OptimizationResult> {
+        let mut settings = optimization_setting.valid_settings();
+        let mut tracker = Tracker::new(optimization_setting, settings.len(), on_progress);
+        let previous = tracker.resume();
+        skip_finished(&mut settings, &previous);
+        output(&format!(
+            "开始分布式优化，参数组合数：{}，节点数：{}",
+            settings.len(),
+            self.workers.len()
+        ));
+
+        let shards: VecDeque<(usize, Vec<String>)> = settings
+            .chunks(self.shard_size.max(1))
+            .enumerate()
+            .map(|(i, shard)| (i * self.shard_size.max(1), shard.to_vec()))
+            .collect();
+        let shards = Mutex::new(shards);
+        // Shards taken and not yet returned, workers wait on them in case they come back
+        let in_flight = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<OptimizationResult>>> =
+            Mutex::new(vec![None; settings.len()]);
+        let tracker = Mutex::new(tracker);
+        let stopped = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            for worker in &self.workers {
+                let (shards, in_flight, results) = (&shards, &in_flight, &results);
+                let (tracker, stopped) = (&tracker, &stopped);
+                scope.spawn(move || {
+                    let mut connection = None;
+                    loop {
+                        if stopped.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        let shard = {
+                            let mut shards = shards.lock().unwrap();
+                            let shard = shards.pop_front();
+                            if shard.is_some() {
+                                in_flight.fetch_add(1, Ordering::SeqCst);
+                            }
+                            shard
+                        };
+                        let Some((start, shard)) = shard else {
+                            if in_flight.load(Ordering::SeqCst) == 0 {
+                                break;
+                            }
+                            thread::sleep(Duration::from_millis(50));
+                            continue;
+                        };
+
+                        match self.run_shard(&mut connection, worker, job, &shard) {
+                            Ok(shard_results) => {
+                                let mut tracker = tracker.lock().unwrap();
+                                let mut results = results.lock().unwrap();
+                                for (i, result) in shard_results.into_iter().enumerate() {
+                                    if !stopped.load(Ordering::SeqCst) && !tracker.record(&result) {
+                                        stopped.store(true, Ordering::SeqCst);
+                                    }
+                                    results[start + i] = Some(result);
+                                }
+                                in_flight.fetch_sub(1, Ordering::SeqCst);
+                            }
+                            Err(e) => {
+                                output(&format!("优化节点{}失败：{}", worker, e));
+                                shards.lock().unwrap().push_back((start, shard));
+                                in_flight.fetch_sub(1, Ordering::SeqCst);
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        // Settings not run after an early stop are left out
+        let stopped = stopped.into_inner();
+        let mut results: Vec<OptimizationResult> = results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .zip(settings)
+            .filter_map(|(result, setting)| match result {
+                Some(result) => Some(result),
+                None if stopped => None,
+                None => Some(OptimizationResult {
+                    setting,
+                    statistics: Err("no worker available".to_string()),
+                    duration: 0.0,
+                }),
+            })
+            .collect();
+        let failed = results.iter().filter(|r| r.statistics.is_err()).count();
+        output(&format!(
+            "分布式优化完成，成功：{}，失败：{}",
+            results.len() - failed,
+            failed
+        ));
+        results.extend(previous);
+        optimization_setting.sort_results(&mut results);
+        results
+    }
+
+    ///Results of shard from worker, connecting on first use.
+    fn run_shard(
+        &self,
+        connection: &mut Option<(TcpStream, BufReader<TcpStream>)>,
+        worker: &str,
+        job: &BatchJob,
+        shard: &[String],
+    ) -> Result<Vec<OptimizationResult>, String> {
+        if connection.is_none() {
+            let stream = TcpStream::connect(worker).map_err(|e| e.to_string())?;
+            let reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+            *connection = Some((stream, reader));
+        }
+        let (stream, reader) = connection.as_mut().unwrap();
+
+        let request = WorkerRequest {
+            token: self.token.clone(),
+            parameters: self.parameters.clone(),
+            job: BatchJob {
+                strategy: strategy_name(&job.strategy),
+                ..job.clone()
+            },
+            settings: shard.to_vec(),
+        };
+        let mut line = serde_json::to_string(&request).map_err(|e| e.to_string())?;
+        line.push('\n');
+        stream
+            .write_all(line.as_bytes())
+            .map_err(|e| e.to_string())?;
+
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Err("connection closed".to_string());
+        }
+        let reply: WorkerReply = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+        let results = reply.results?;
+        if results.len() != shard.len() {
+            return Err(format!(
+                "{} results of {} settings",
+                results.len(),
+                shard.len()
+            ));
+        }
+        Ok(results)
+    }
+}
+
+fn output(msg: &str) {
+    println!("{datetime}\t{msg}", datetime = Local::now(), msg = msg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_of_every_combination() {
+        let mut setting = OptimizationSetting::new("sharpe_ratio");
+        setting
+            .add_parameter("fast_window", 5.0, 15.0, 5.0)
+            .unwrap();
+        setting.add_parameter("threshold", 0.1, 0.3, 0.1).unwrap();
+        setting.add_choices("mode", &["fast", "slow"]);
+        assert!(setting
+            .add_parameter("slow_window", 20.0, 10.0, 5.0)
+            .is_err());
+        assert!(setting
+            .add_parameter("slow_window", 10.0, 20.0, 0.0)
+            .is_err());
+
+        let settings = setting.generate_settings();
+        assert_eq!(settings.len(), setting.combination_count());
+        assert_eq!(settings.len(), 18);
+        assert_eq!(settings[0], "fast_window:5,threshold:0.1,mode:fast");
+        assert_eq!(settings[5], "fast_window:5,threshold:0.3,mode:slow");
+        assert_eq!(settings[17], "fast_window:15,threshold:0.3,mode:slow");
+        assert!(OptimizationSetting::new("sharpe_ratio")
+            .generate_settings()
+            .is_empty());
+
+        let statistics = BacktestingStatistics {
+            sharpe_ratio: 1.5,
+            ..Default::default()
+        };
+        assert_eq!(target_value(&statistics, "sharpe_ratio"), Some(1.5));
+        assert_eq!(target_value(&statistics, "start_date"), None);
+        let mut results = vec![
+            OptimizationResult {
+                setting: "a".to_string(),
+                statistics: Err("failed".to_string()),
+                duration: 0.0,
+            },
+            OptimizationResult {
+                setting: "b".to_string(),
+                statistics: Ok(BacktestingStatistics::default()),
+                duration: 0.0,
+            },
+            OptimizationResult {
+                setting: "c".to_string(),
+                statistics: Ok(statistics),
+                duration: 0.0,
+            },
+        ];
+        sort_results(&mut results, "sharpe_ratio");
+        let order: Vec<&str> = results.iter().map(|r| r.setting.as_str()).collect();
+        assert_eq!(order, vec!["c", "b", "a"]);
+    }
+
+    #[test]
+    fn constraints_skip_combinations() {
+        let mut setting = OptimizationSetting::new("sharpe_ratio");
+        setting.add_int_range("fast_window", 5, 20).unwrap();
+        setting
+            .add_parameter("slow_window", 10.0, 20.0, 5.0)
+            .unwrap();
+        setting.add_choices("mode", &["fast", "slow"]);
+        setting.add_constraint("fast_window < slow_window").unwrap();
+        setting.add_constraint("mode!=slow").unwrap();
+        assert!(setting.add_constraint("fast_window < window").is_err());
+        assert!(setting.add_constraint("fast_window slow_window").is_err());
+        assert_eq!(
+            setting.constraints[0].to_string(),
+            "fast_window < slow_window"
+        );
+
+        // 5..9 below all three slow windows, 10..14 below two, 15..19 below one
+        let settings = setting.generate_settings();
+        assert_eq!(settings.len(), 5 * 3 + 5 * 2 + 5);
+        assert_eq!(setting.combination_count(), settings.len());
+        assert!(settings.contains(&"fast_window:19,slow_window:20,mode:fast".to_string()));
+        assert!(!setting.satisfies("fast_window:10,slow_window:10,mode:fast"));
+        assert!(!setting.satisfies("fast_window:5,slow_window:10,mode:slow"));
+        assert!(setting.satisfies("fast_window:9.5,slow_window:10,mode:fast"));
+    }
+
+    #[test]
+    fn wide_ranges_are_not_listed() {
+        let mut setting = OptimizationSetting::new("sharpe_ratio");
+        setting.add_int_range("window", 0, 1_000_000_000).unwrap();
+        setting.add_float_range("threshold", 0.0, 1.0).unwrap();
+        assert_eq!(setting.grid_size(), Some(2_000_000_002));
+        assert_eq!(setting.combination_count(), 2_000_000_002);
+        setting.add_constraint("window < 500").unwrap();
+        setting.add_constraint("threshold <= 0.5").unwrap();
+        assert!(setting.satisfies("window:499,threshold:0.5"));
+
+        let mut choices = OptimizationSetting::new("sharpe_ratio");
+        choices.add_int_range("level", 1, 1_000_000_000).unwrap();
+        choices.add_choices("mode", &["fast", "slow"]);
+        // A choice of the other side, unknown names are still rejected
+        choices.add_constraint("mode != slow").unwrap();
+        assert!(choices.add_constraint("level > window").is_err());
+        assert!(Parameter::Int { low: 1, high: 10 }.contains("10"));
+        assert!(!Parameter::Int { low: 1, high: 10 }.contains("11"));
+
+        // Overflowing the count instead of panicking
+        choices.add_int_range("lots", i64::MIN, i64::MAX).unwrap();
+        assert_eq!(choices.parameters[2].1.value_count(), None);
+        assert_eq!(choices.grid_size(), None);
+        choices.constraints.clear();
+        assert_eq!(choices.combination_count(), usize::MAX);
+    }
+
+    #[test]
+    fn objectives_and_pareto_front() {
+        let result = |setting: &str, total_return: f64, max_ddpercent: f64| OptimizationResult {
+            setting: setting.to_string(),
+            statistics: Ok(BacktestingStatistics {
+                total_return,
+                max_ddpercent,
+                sharpe_ratio: 1.0,
+                ..Default::default()
+            }),
+            duration: 0.0,
+        };
+        let mut results = vec![
+            result("a", 30.0, -20.0),
+            result("b", 20.0, -5.0),
+            result("c", 10.0, -10.0),
+            result("d", 25.0, -20.0),
+            OptimizationResult {
+                setting: "e".to_string(),
+                statistics: Err("failed".to_string()),
+                duration: 0.0,
+            },
+            result("f", 5.0, -15.0),
+        ];
+
+        let mut setting = OptimizationSetting::new("sharpe_ratio");
+        setting.set_objective(Objective::new("calmar", |statistics| {
+            statistics.sharpe_ratio - 0.1 * statistics.max_ddpercent.abs()
+        }));
+        assert_eq!(setting.target_name, "calmar");
+        assert_eq!(setting.target(&results[1]), Some(0.5));
+        setting.sort_results(&mut results);
+        let order: Vec<&str> = results.iter().map(|r| r.setting.as_str()).collect();
+        assert_eq!(order, vec!["b", "c", "f", "a", "d", "e"]);
+
+        // a has the best return and b the smallest drawdown, d and c are beaten by them
+        let objectives = [
+            Objective::statistic("total_return"),
+            Objective::statistic("max_ddpercent"),
+        ];
+        let ranks = pareto_ranks(&results, &objectives);
+        assert_eq!(
+            ranks,
+            vec![Some(0), Some(1), Some(2), Some(0), Some(1), None]
+        );
+        let front: Vec<&str> = pareto_front(&results, &objectives)
+            .iter()
+            .map(|r| r.setting.as_str())
+            .collect();
+        assert_eq!(front, vec!["b", "a"]);
+    }
+
+    #[test]
+    fn stop_when_target_stops_improving() {
+        let result = |setting: &str, sharpe_ratio: f64| OptimizationResult {
+            setting: setting.to_string(),
+            statistics: Ok(BacktestingStatistics {
+                sharpe_ratio,
+                ..Default::default()
+            }),
+            duration: 0.0,
+        };
+        let mut setting = OptimizationSetting::new("sharpe_ratio");
+        setting.set_patience(2);
+        let best = Mutex::new(Vec::new());
+        let mut tracker = Tracker::new(&setting, 10, |progress: &OptimizationProgress| {
+            best.lock()
+                .unwrap()
+                .push(progress.best.unwrap().0.to_string());
+        });
+
+        assert!(tracker.record(&result("a", 1.0)));
+        assert!(tracker.record(&result("b", 2.0)));
+        assert!(tracker.record(&result("c", 1.5)));
+        assert!(!tracker.record(&result("d", 2.0)));
+        assert_eq!(*best.lock().unwrap(), vec!["a", "b", "b", "b"]);
+    }
+}
@@ -0,0 +1,361 @@
+/*!Exposure and risk report of a multi-symbol backtest, built from the daily results of the
+backtest of each symbol. Days a symbol has no result keep its last position and add no pnl. */
+use chrono::NaiveDate;
+use polars::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::backtesting::{BacktestingEngine, DailyResult};
+use super::batch::BatchResult;
+
+///Daily results of one symbol of a multi-symbol backtest.
+#[derive(Debug, Clone)]
+pub struct SymbolDaily {
+    pub vt_symbol: String,
+    pub size: f64,
+    pub daily_results: Vec<DailyResult>,
+}
+
+impl SymbolDaily {
+    ///Take the daily results of a finished backtest, after calculate_result or
+    ///calculate_result_statistics.
+    pub fn from_engine(vt_symbol: &str, engine: &BacktestingEngine) -> Self {
+        SymbolDaily {
+            vt_symbol: vt_symbol.to_string(),
+            size: engine.size,
+            daily_results: engine.get_daily_results().cloned().collect(),
+        }
+    }
+}
+
+///One row per date of all symbols, with each symbol's exposure and net pnl of that day.
+pub struct PortfolioReport {
+    symbols: Vec<String>,
+    dates: Vec<NaiveDate>,
+    ///exposure[symbol][day], signed value of the end of day position.
+    exposure: Vec<Vec<f64>>,
+    net_pnl: Vec<Vec<f64>>,
+    commission: Vec<f64>,
+    slippage: Vec<f64>,
+    trade_count: Vec<i64>,
+}
+
+impl PortfolioReport {
+    ///Legs of the same symbol, e.g. two strategies trading it, are added together.
+    pub fn new(legs: &[SymbolDaily]) -> Self {
+        let dates: Vec<NaiveDate> = legs
+            .iter()
+            .flat_map(|leg| leg.daily_results.iter().map(|result| result.date))
+            .collect::<BTreeSet<NaiveDate>>()
+            .into_iter()
+            .collect();
+
+        let mut report = PortfolioReport {
+            symbols: Vec::new(),
+            dates,
+            exposure: Vec::new(),
+            net_pnl: Vec::new(),
+            commission: Vec::new(),
+            slippage: Vec::new(),
+            trade_count: Vec::new(),
+        };
+        for leg in legs {
+            let ix = match report.symbols.iter().position(|s| *s == leg.vt_symbol) {
+                Some(ix) => ix,
+                None => {
+                    report.symbols.push(leg.vt_symbol.clone());
+                    report.exposure.push(vec![0.0; report.dates.len()]);
+                    report.net_pnl.push(vec![0.0; report.dates.len()]);
+                    report.commission.push(0.0);
+                    report.slippage.push(0.0);
+                    report.trade_count.push(0);
+                    report.symbols.len() - 1
+                }
+            };
+
+            let results: BTreeMap<NaiveDate, &DailyResult> = leg
+                .daily_results
+                .iter()
+                .map(|result| (result.date, result))
+                .collect();
+            let mut last_exposure = 0.0;
+            for (day, date) in report.dates.iter().enumerate() {
+                if let Some(result) = results.get(date) {
+                    last_exposure = result.end_pos * result.close_price * leg.size;
+                    report.net_pnl[ix][day] += result.net_pnl;
+                    report.commission[ix] += result.commission;
+                    report.slippage[ix] += result.slippage;
+                    report.trade_count[ix] += result.trade_count;
+                }
+                report.exposure[ix][day] += last_exposure;
+            }
+        }
+        report
+    }
+
+    ///Report of the jobs of a batch run with daily results kept, failed jobs are left out.
+    pub fn from_batch(results: &[BatchResult]) -> Self {
+        let legs: Vec<SymbolDaily> = results
+            .iter()
+            .filter_map(|result| result.daily.clone())
+            .collect();
+        Self::new(&legs)
+    }
+
+    ///Long, short, gross and net exposure and net pnl of the portfolio by date.
+    pub fn exposure_df(&self) -> DataFrame {
+        let by_day = |f: fn(f64) -> f64| -> Vec<f64> {
+            (0..self.dates.len())
+                .map(|day| self.exposure.iter().map(|leg| f(leg[day])).sum())
+                .collect()
+        };
+        let net_pnl: Vec<f64> = (0..self.dates.len())
+            .map(|day| self.net_pnl.iter().map(|leg| leg[day]).sum())
+            .collect();
+
+        DataFrame::new(vec![
+            self.date_series(),
+            Series::new("long_exposure", by_day(|value| value.max(0.0))),
+            Series::new("short_exposure", by_day(|value| value.min(0.0))),
+            Series::new("gross_exposure", by_day(f64::abs)),
+            Series::new("net_exposure", by_day(|value| value)),
+            Series::new("net_pnl", net_pnl),
+        ])
+        .unwrap()
+    }
+
+    ///Net pnl of each symbol and its share of the portfolio net pnl, with costs and the
+    ///average share of gross exposure.
+    pub fn contribution_df(&self) -> DataFrame {
+        let symbol_pnl: Vec<f64> = self.net_pnl.iter().map(|leg| leg.iter().sum()).collect();
+        let total_pnl: f64 = symbol_pnl.iter().sum();
+        let contribution: Vec<f64> = symbol_pnl
+            .iter()
+            .map(|pnl| {
+                if total_pnl != 0.0 {
+                    pnl / total_pnl
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+
+        let weights = self.weights();
+        let average_weight: Vec<f64> = (0..self.symbols.len())
+            .map(|ix| {
+                if self.dates.is_empty() {
+                    0.0
+                } else {
+                    weights.iter().map(|day| day[ix]).sum::<f64>() / self.dates.len() as f64
+                }
+            })
+            .collect();
+
+        DataFrame::new(vec![
+            Series::new("vt_symbol", self.symbols.clone()),
+            Series::new("net_pnl", symbol_pnl),
+            Series::new("contribution", contribution),
+            Series::new("commission", self.commission.clone()),
+            Series::new("slippage", self.slippage.clone()),
+            Series::new("trade_count", self.trade_count.clone()),
+            Series::new("average_weight", average_weight),
+        ])
+        .unwrap()
+    }
+
+    ///Correlation matrix of the daily net pnl of the symbols, one column per symbol. NaN for
+    ///a symbol whose pnl never changes.
+    pub fn correlation_df(&self) -> DataFrame {
+        let mut columns = vec![Series::new("vt_symbol", self.symbols.clone())];
+        for (symbol, pnl) in self.symbols.iter().zip(&self.net_pnl) {
+            columns.push(Series::new(
+                symbol,
+                self.net_pnl
+                    .iter()
+                    .map(|other| correlation(pnl, other))
+                    .collect::<Vec<f64>>(),
+            ));
+        }
+        DataFrame::new(columns).unwrap()
+    }
+
+    ///Concentration of gross exposure by date: weight and symbol of the largest position,
+    ///Herfindahl index of the weights and the effective number of positions, 1 / hhi.
+    ///Days without a position have zero weights and no top symbol.
+    pub fn concentration_df(&self) -> DataFrame {
+        let weights = self.weights();
+        let mut max_weight = Vec::with_capacity(self.dates.len());
+        let mut top_symbol = Vec::with_capacity(self.dates.len());
+        let mut hhi = Vec::with_capacity(self.dates.len());
+        let mut effective_count = Vec::with_capacity(self.dates.len());
+        for day in &weights {
+            let top = day
+                .iter()
+                .enumerate()
+                .filter(|(_, weight)| **weight > 0.0)
+                .max_by(|a, b| a.1.total_cmp(b.1));
+            let index: f64 = day.iter().map(|weight| weight * weight).sum();
+            max_weight.push(top.map_or(0.0, |(_, weight)| *weight));
+            top_symbol.push(top.map(|(ix, _)| self.symbols[ix].clone()));
+            hhi.push(index);
+            effective_count.push(if index > 0.0 { 1.0 / index } else { 0.0 });
+        }
+
+        DataFrame::new(vec![
+            self.date_series(),
+            Series::new("max_weight", max_weight),
+            Series::new("top_symbol", top_symbol),
+            Series::new("hhi", hhi),
+            Series::new("effective_count", effective_count),
+        ])
+        .unwrap()
+    }
+
+    ///weights[day][symbol], share of the gross exposure of the day.
+    fn weights(&self) -> Vec<Vec<f64>> {
+        (0..self.dates.len())
+            .map(|day| {
+                let gross: f64 = self.exposure.iter().map(|leg| leg[day].abs()).sum();
+                self.exposure
+                    .iter()
+                    .map(|leg| {
+                        if gross > 0.0 {
+                            leg[day].abs() / gross
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn date_series(&self) -> Series {
+        Int32Chunked::from_iter_values(
+            "date",
+            self.dates
+                .iter()
+                .map(|date| (*date - NaiveDate::default()).num_days() as i32),
+        )
+        .into_date()
+        .into_series()
+    }
+}
+
+///Pearson correlation, NaN if either series has no variance.
+pub(crate) fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    let mean_a = a.iter().sum::<f64>() / n;
+    let mean_b = b.iter().sum::<f64>() / n;
+    let mut covariance = 0.0;
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a).powi(2);
+        variance_b += (y - mean_b).powi(2);
+    }
+    if variance_a == 0.0 || variance_b == 0.0 {
+        return f64::NAN;
+    }
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leg(vt_symbol: &str, size: f64, days: &[(u32, f64, f64, f64)]) -> SymbolDaily {
+        SymbolDaily {
+            vt_symbol: vt_symbol.to_string(),
+            size,
+            daily_results: days
+                .iter()
+                .map(|(day, close_price, end_pos, net_pnl)| DailyResult {
+                    end_pos: *end_pos,
+                    net_pnl: *net_pnl,
+                    ..DailyResult::new(
+                        NaiveDate::from_ymd_opt(2024, 1, *day).unwrap(),
+                        *close_price,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn exposure_and_concentration() {
+        let report = PortfolioReport::new(&[
+            leg(
+                "IF888.CFFEX",
+                300.0,
+                &[
+                    (2, 4000.0, 1.0, 100.0),
+                    (3, 4010.0, 1.0, 3000.0),
+                    (4, 4000.0, 1.0, -3000.0),
+                ],
+            ),
+            //No bar on the 3rd, the short position is carried over
+            leg(
+                "rb888.SHFE",
+                10.0,
+                &[(2, 3600.0, -30.0, -50.0), (4, 3650.0, -30.0, -1500.0)],
+            ),
+        ]);
+
+        let exposure = report.exposure_df();
+        let column = |df: &DataFrame, name: &str| -> Vec<f64> {
+            df[name].f64().unwrap().into_no_null_iter().collect()
+        };
+        assert_eq!(exposure.height(), 3);
+        assert_eq!(
+            column(&exposure, "gross_exposure"),
+            vec![2_280_000.0, 2_283_000.0, 2_295_000.0]
+        );
+        assert_eq!(
+            column(&exposure, "net_exposure"),
+            vec![120_000.0, 123_000.0, 105_000.0]
+        );
+        assert_eq!(column(&exposure, "net_pnl"), vec![50.0, 3000.0, -4500.0]);
+
+        let contribution = report.contribution_df();
+        assert_eq!(column(&contribution, "net_pnl"), vec![100.0, -1550.0]);
+
+        let concentration = report.concentration_df();
+        let max_weight = column(&concentration, "max_weight");
+        assert!((max_weight[0] - 1_200_000.0 / 2_280_000.0).abs() < 1e-12);
+        let effective_count = column(&concentration, "effective_count");
+        assert!(effective_count
+            .iter()
+            .all(|count| *count > 1.9 && *count <= 2.0));
+    }
+
+    #[test]
+    fn pnl_correlation() {
+        let report = PortfolioReport::new(&[
+            leg(
+                "A.LOCAL",
+                1.0,
+                &[(2, 1.0, 0.0, 1.0), (3, 1.0, 0.0, 2.0), (4, 1.0, 0.0, 3.0)],
+            ),
+            leg(
+                "B.LOCAL",
+                1.0,
+                &[
+                    (2, 1.0, 0.0, -2.0),
+                    (3, 1.0, 0.0, -4.0),
+                    (4, 1.0, 0.0, -6.0),
+                ],
+            ),
+            leg("C.LOCAL", 1.0, &[(2, 1.0, 0.0, 5.0)]),
+        ]);
+        let correlation = report.correlation_df();
+        let a: Vec<f64> = correlation["A.LOCAL"]
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert!((a[0] - 1.0).abs() < 1e-12);
+        assert!((a[1] + 1.0).abs() < 1e-12);
+        assert!((a[2] + 3f64.sqrt() / 2.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,164 @@
+/*!Strategies that are a closure over the bars, to try a simple idea in a few lines inside the
+host binary, without a dylib or implementing CtaStrategy:
+
+```no_run
+use vnrs::prelude::*;
+
+let mut engine = BacktestingEngine::new();
+// set_parameters and load_data as for any backtest
+engine.add_strategy_fn(|context, bar| {
+    let (fast, slow) = (context.am.sma(10), context.am.sma(30));
+    if fast > slow && context.pos() <= 0.0 {
+        context.cancel_all();
+        context.buy(bar.close_price + 5.0, 1.0);
+    } else if fast < slow && context.pos() > 0.0 {
+        context.sell(bar.close_price - 5.0, 1.0);
+    }
+});
+engine.run_backtesting()?;
+# Ok::<(), VnrsError>(())
+```
+*/
+use super::sdk::{CtaStrategy, StrategyContext};
+use crate::vnrs::trader::object::BarData;
+use crate::vnrs::trader::utility::ArrayManager;
+
+///Bars in the ArrayManager of an FnStrategy unless given.
+pub const FN_STRATEGY_BARS: usize = 100;
+
+///What the closure of an FnStrategy gets besides the bar: the bars so far and limit orders.
+pub struct QuickContext<'a> {
+    context: &'a mut StrategyContext,
+    ///Bars up to the current one.
+    pub am: &'a mut ArrayManager,
+}
+
+impl QuickContext<'_> {
+    pub fn pos(&self) -> f64 {
+        self.context.pos()
+    }
+
+    pub fn buy(&self, price: f64, volume: f64) -> Vec<String> {
+        self.context.buy(price, volume, false)
+    }
+
+    pub fn sell(&self, price: f64, volume: f64) -> Vec<String> {
+        self.context.sell(price, volume, false)
+    }
+
+    pub fn short(&self, price: f64, volume: f64) -> Vec<String> {
+        self.context.short(price, volume, false)
+    }
+
+    pub fn cover(&self, price: f64, volume: f64) -> Vec<String> {
+        self.context.cover(price, volume, false)
+    }
+
+    pub fn cancel_all(&self) {
+        self.context.cancel_all()
+    }
+
+    ///Full context, e.g. for stop orders or the pricetick.
+    pub fn context(&mut self) -> &mut StrategyContext {
+        self.context
+    }
+}
+
+///Strategy calling on_bar with every bar once it is in the ArrayManager. It has no setting,
+///it is added from its value with BacktestingEngine::add_strategy_instance.
+pub struct FnStrategy<F> {
+    on_bar: F,
+    am: ArrayManager,
+}
+
+impl<F> FnStrategy<F>
+where
+    F: FnMut(&mut QuickContext, &BarData) + 'static,
+{
+    ///on_bar with an ArrayManager of the last bars bars.
+    pub fn from_fn(bars: usize, on_bar: F) -> Self {
+        FnStrategy {
+            on_bar,
+            am: ArrayManager::new(bars),
+        }
+    }
+}
+
+impl<F> CtaStrategy for FnStrategy<F>
+where
+    F: FnMut(&mut QuickContext, &BarData) + 'static,
+{
+    fn new(_context: &StrategyContext, _setting: &[(String, String)]) -> Self {
+        unreachable!("closure strategies are added with add_strategy_instance")
+    }
+
+    fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData) {
+        self.am.update_bar(bar);
+        let mut quick = QuickContext {
+            context,
+            am: &mut self.am,
+        };
+        (self.on_bar)(&mut quick, bar)
+    }
+}
+
+#[cfg(all(test, feature = "backtest-stats", feature = "dylib-strategies"))]
+mod tests {
+    use crate::prelude::*;
+    use crate::vnrs::trader::synthetic::SyntheticBars;
+
+    #[test]
+    fn moving_average_cross_as_closure() {
+        let bars = SyntheticBars {
+            symbol: "IF888".to_string(),
+            exchange: Exchange::CFFEX,
+            count: 2000,
+            start_price: 3500.0,
+            volatility: 0.002,
+            pricetick: 0.2,
+            ..Default::default()
+        }
+        .generate();
+        let mut engine = BacktestingEngine::new();
+        engine.set_output_callback(|_| {});
+        engine
+            .set_parameters(
+                "IF888.CFFEX",
+                Interval::MINUTE,
+                bars[0].datetime,
+                bars[bars.len() - 1].datetime,
+                0.0,
+                0.0,
+                300.0,
+                0.2,
+                1_000_000.0,
+                BacktestingMode::BAR,
+                0.0,
+                0,
+                120,
+            )
+            .unwrap();
+        engine.set_history_data(bars);
+        engine.add_strategy_fn(|context, bar| {
+            let (fast, slow) = (context.am.sma(10), context.am.sma(30));
+            if fast > slow && context.pos() == 0.0 {
+                context.buy(bar.close_price + 5.0, 1.0);
+            } else if fast < slow && context.pos() > 0.0 {
+                context.sell(bar.close_price - 5.0, 1.0);
+            }
+        });
+        engine.run_backtesting().unwrap();
+
+        let trades = engine.get_all_trades();
+        assert!(trades.len() > 2);
+        // Entries and exits alternate
+        for (i, trade) in trades.iter().enumerate() {
+            let direction = if i % 2 == 0 {
+                Direction::LONG
+            } else {
+                Direction::SHORT
+            };
+            assert_eq!(trade.direction, direction);
+        }
+    }
+}
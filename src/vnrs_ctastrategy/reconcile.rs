@@ -0,0 +1,220 @@
+/*!Reconciliation of the pos tracked by live strategies against the positions reported by the
+gateways.
+
+A standalone component, the crate has no live engine yet: the PositionReconciler is fed the
+strategy pos and the EVENT_POSITION events on an EventEngine, and only reports mismatches as
+EVENT_CTA_RECONCILE events, applying a correction is left to the engine running the
+strategies. */
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+use log::Level;
+
+use super::base::{EVENT_CTA_LOG, EVENT_CTA_RECONCILE};
+use crate::vnrs::event::{Event, EventEngine, HandlerType};
+use crate::vnrs::trader::clock::Clock;
+use crate::vnrs::trader::constant::Direction;
+use crate::vnrs::trader::event::{EVENT_POSITION, EVENT_TIMER};
+use crate::vnrs::trader::object::{LogData, PositionData};
+
+const POS_TOLERANCE: f64 = 1e-9;
+
+///Net pos of a vt_symbol differing between the strategies and the gateway. If corrected, the
+///pos of the only strategy trading the symbol was set to broker_pos.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionMismatch {
+    pub vt_symbol: String,
+    pub strategy_pos: f64,
+    pub broker_pos: f64,
+    pub strategies: Vec<String>,
+    pub corrected: bool,
+}
+
+///Compares the summed pos of the strategies of every vt_symbol with the net position of the
+///gateway. Only symbols traded by strategies are checked, positions opened by hand elsewhere
+///are left alone.
+#[derive(Debug, Default)]
+pub struct PositionReconciler {
+    ///Timer events between checks.
+    pub interval: u32,
+    ///Set the strategy pos to the broker pos when a single strategy trades the symbol, the
+    ///engine applies the correction when it receives the EVENT_CTA_RECONCILE event.
+    pub auto_correct: bool,
+    strategy_pos: HashMap<String, HashMap<String, f64>>,
+    broker_pos: HashMap<String, (String, f64)>,
+    count: u32,
+}
+
+impl PositionReconciler {
+    pub fn new(interval: u32, auto_correct: bool) -> Self {
+        PositionReconciler {
+            interval,
+            auto_correct,
+            ..Default::default()
+        }
+    }
+
+    ///Pos of a strategy after its trades, as kept by the engine.
+    pub fn update_strategy_pos(&mut self, strategy_name: &str, vt_symbol: &str, pos: f64) {
+        self.strategy_pos
+            .entry(vt_symbol.to_string())
+            .or_default()
+            .insert(strategy_name.to_string(), pos);
+    }
+
+    pub fn remove_strategy(&mut self, strategy_name: &str) {
+        for positions in self.strategy_pos.values_mut() {
+            positions.remove(strategy_name);
+        }
+        self.strategy_pos
+            .retain(|_, positions| !positions.is_empty());
+    }
+
+    ///Position pushed by a gateway, long and short positions are netted.
+    pub fn update_position(&mut self, position: &PositionData) {
+        let volume = match position.direction {
+            Direction::SHORT => -position.volume,
+            _ => position.volume,
+        };
+        self.broker_pos
+            .insert(position.vt_positionid(), (position.vt_symbol(), volume));
+    }
+
+    pub fn get_broker_pos(&self, vt_symbol: &str) -> f64 {
+        self.broker_pos
+            .values()
+            .filter(|(symbol, _)| symbol == vt_symbol)
+            .map(|(_, volume)| volume)
+            .sum()
+    }
+
+    ///Mismatches of all symbols traded by strategies, sorted by vt_symbol.
+    pub fn reconcile(&mut self) -> Vec<PositionMismatch> {
+        let mut mismatches = Vec::new();
+        let broker_pos: HashMap<String, f64> = self
+            .strategy_pos
+            .keys()
+            .map(|vt_symbol| (vt_symbol.clone(), self.get_broker_pos(vt_symbol)))
+            .collect();
+
+        for (vt_symbol, positions) in self.strategy_pos.iter_mut() {
+            let strategy_pos: f64 = positions.values().sum();
+            let broker_pos = broker_pos[vt_symbol];
+            if (strategy_pos - broker_pos).abs() < POS_TOLERANCE {
+                continue;
+            }
+
+            let corrected = self.auto_correct && positions.len() == 1;
+            if corrected {
+                positions.values_mut().for_each(|pos| *pos = broker_pos);
+            }
+            let mut strategies: Vec<String> = positions.keys().cloned().collect();
+            strategies.sort();
+            mismatches.push(PositionMismatch {
+                vt_symbol: vt_symbol.clone(),
+                strategy_pos,
+                broker_pos,
+                strategies,
+                corrected,
+            });
+        }
+        mismatches.sort_by(|a, b| a.vt_symbol.cmp(&b.vt_symbol));
+        mismatches
+    }
+
+    ///Keep positions from EVENT_POSITION events and reconcile every interval timer events,
+    ///putting a warning EVENT_CTA_LOG and an EVENT_CTA_RECONCILE event for each mismatch.
+    ///Logs are timed by clock. Returns the handler for unregistering.
+    pub fn register(
+        reconciler: Arc<Mutex<Self>>,
+        event_engine: &Arc<EventEngine>,
+        clock: Arc<dyn Clock>,
+    ) -> HandlerType {
+        let engine: Weak<EventEngine> = Arc::downgrade(event_engine);
+        let handler: HandlerType = Arc::new(move |event: &Event| {
+            let mut reconciler = reconciler.lock().unwrap();
+            if let Some(position) = event.data::<PositionData>() {
+                reconciler.update_position(position);
+                return;
+            }
+
+            reconciler.count += 1;
+            if reconciler.count < reconciler.interval {
+                return;
+            }
+            reconciler.count = 0;
+            let Some(event_engine) = engine.upgrade() else {
+                return;
+            };
+            for mismatch in reconciler.reconcile() {
+                let msg = format!(
+                    "{}持仓不一致，策略{}：{}，柜台：{}{}",
+                    mismatch.vt_symbol,
+                    mismatch.strategies.join(","),
+                    mismatch.strategy_pos,
+                    mismatch.broker_pos,
+                    if mismatch.corrected {
+                        "，已按柜台持仓修正"
+                    } else {
+                        ""
+                    }
+                );
+                let log = LogData::new(&msg, Level::Warn, "PositionReconciler", clock.now());
+                event_engine.put(Event::new(EVENT_CTA_LOG, log));
+                event_engine.put(Event::new(EVENT_CTA_RECONCILE, mismatch));
+            }
+        });
+        event_engine.register(EVENT_POSITION, handler.clone());
+        event_engine.register(EVENT_TIMER, handler.clone());
+        handler
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::constant::Exchange;
+
+    fn position(direction: Direction, volume: f64) -> PositionData {
+        PositionData {
+            gateway_name: "CTP".into(),
+            symbol: "rb2410".into(),
+            exchange: Exchange::SHFE,
+            direction,
+            volume,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mismatches_found_and_corrected() {
+        let mut reconciler = PositionReconciler::new(1, false);
+        reconciler.update_strategy_pos("atr", "rb2410.SHFE", 2.0);
+        reconciler.update_strategy_pos("boll", "rb2410.SHFE", -1.0);
+        reconciler.update_position(&position(Direction::LONG, 3.0));
+        reconciler.update_position(&position(Direction::SHORT, 2.0));
+        assert!(reconciler.reconcile().is_empty());
+
+        // A fill the strategies missed
+        reconciler.update_position(&position(Direction::LONG, 4.0));
+        let mismatches = reconciler.reconcile();
+        assert_eq!(
+            mismatches,
+            vec![PositionMismatch {
+                vt_symbol: "rb2410.SHFE".to_string(),
+                strategy_pos: 1.0,
+                broker_pos: 2.0,
+                strategies: vec!["atr".to_string(), "boll".to_string()],
+                corrected: false,
+            }]
+        );
+
+        // Corrected only when a single strategy trades the symbol
+        reconciler.auto_correct = true;
+        assert!(!reconciler.reconcile()[0].corrected);
+        reconciler.remove_strategy("boll");
+        reconciler.update_position(&position(Direction::LONG, 5.0));
+        assert!(reconciler.reconcile()[0].corrected);
+        assert!(reconciler.reconcile().is_empty());
+    }
+}
@@ -0,0 +1,458 @@
+/*!Rendering of the statistics printed at the end of a backtest. Labels are in Chinese or
+English, and the lines are tab separated as they always were, a table aligned by display width
+for terminals where tabs don't line up, or a single JSON object for scripts. */
+use chrono::NaiveDate;
+use serde_json::{Map, Value};
+
+use super::backtesting::BacktestingStatistics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    Zh,
+    En,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatisticsStyle {
+    ///Label, tab and value on each line, after a line of dashes.
+    #[default]
+    Tabs,
+    ///Labels padded to the widest one, CJK characters counting as two columns.
+    Table,
+    ///One line of JSON keyed by the BacktestingStatistics field names, values not rounded.
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatisticsValue {
+    Number(f64),
+    ///Already in percent, printed with a % sign.
+    Percent(f64),
+    Count(i64),
+    Date(NaiveDate),
+    Text(String),
+}
+
+///One printed statistic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatisticsRow {
+    ///Field name in BacktestingStatistics, the key in Json.
+    pub key: &'static str,
+    ///Chinese label with the separator printed before the value in Tabs.
+    pub zh: &'static str,
+    pub en: &'static str,
+    pub value: StatisticsValue,
+}
+
+impl StatisticsRow {
+    pub fn new(
+        key: &'static str,
+        zh: &'static str,
+        en: &'static str,
+        value: StatisticsValue,
+    ) -> Self {
+        StatisticsRow { key, zh, en, value }
+    }
+}
+
+///How BacktestingEngine prints statistics, see BacktestingEngine::set_statistics_formatter.
+///The default prints what it always has: Chinese, tab separated, 2 decimals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatisticsFormatter {
+    pub locale: Locale,
+    pub style: StatisticsStyle,
+    ///Decimals of numbers and percents, not applied to Json.
+    pub decimals: usize,
+}
+
+impl Default for StatisticsFormatter {
+    fn default() -> Self {
+        StatisticsFormatter {
+            locale: Locale::Zh,
+            style: StatisticsStyle::Tabs,
+            decimals: 2,
+        }
+    }
+}
+
+impl StatisticsFormatter {
+    pub fn new(locale: Locale, style: StatisticsStyle) -> Self {
+        StatisticsFormatter {
+            locale,
+            style,
+            ..Default::default()
+        }
+    }
+
+    ///Lines to print for rows.
+    pub fn render(&self, rows: &[StatisticsRow]) -> Vec<String> {
+        match self.style {
+            StatisticsStyle::Tabs => {
+                let mut lines = vec!["-".repeat(30)];
+                lines.extend(rows.iter().map(|row| match self.locale {
+                    Locale::Zh => format!("{}{}", row.zh, self.value(&row.value)),
+                    Locale::En => format!("{}:\t{}", row.en, self.value(&row.value)),
+                }));
+                lines
+            }
+            StatisticsStyle::Table => {
+                let cells: Vec<(&str, String)> = rows
+                    .iter()
+                    .map(|row| (self.label(row), self.value(&row.value)))
+                    .collect();
+                let label_width = cells
+                    .iter()
+                    .map(|(label, _)| display_width(label))
+                    .max()
+                    .unwrap_or(0);
+                let value_width = cells
+                    .iter()
+                    .map(|(_, value)| display_width(value))
+                    .max()
+                    .unwrap_or(0);
+                let mut lines = vec!["-".repeat(label_width + value_width + 2)];
+                lines.extend(cells.iter().map(|(label, value)| {
+                    format!(
+                        "{}{}  {}{}",
+                        label,
+                        " ".repeat(label_width - display_width(label)),
+                        " ".repeat(value_width - display_width(value)),
+                        value
+                    )
+                }));
+                lines
+            }
+            StatisticsStyle::Json => {
+                let object: Map<String, Value> = rows
+                    .iter()
+                    .map(|row| {
+                        let value = match &row.value {
+                            StatisticsValue::Number(x) | StatisticsValue::Percent(x) => {
+                                Value::from(*x)
+                            }
+                            StatisticsValue::Count(n) => Value::from(*n),
+                            StatisticsValue::Date(date) => Value::from(date.to_string()),
+                            StatisticsValue::Text(text) => Value::from(text.clone()),
+                        };
+                        (row.key.to_string(), value)
+                    })
+                    .collect();
+                vec![Value::Object(object).to_string()]
+            }
+        }
+    }
+
+    ///Label of row in the locale, without separator.
+    fn label<'a>(&self, row: &'a StatisticsRow) -> &'a str {
+        match self.locale {
+            Locale::Zh => row.zh.trim_end_matches([':', '：', ' ', '\t']),
+            Locale::En => row.en,
+        }
+    }
+
+    fn value(&self, value: &StatisticsValue) -> String {
+        match value {
+            StatisticsValue::Number(x) => format!("{:.*}", self.decimals, x),
+            StatisticsValue::Percent(x) => format!("{:.*}%", self.decimals, x),
+            StatisticsValue::Count(n) => n.to_string(),
+            StatisticsValue::Date(date) => date.to_string(),
+            StatisticsValue::Text(text) => text.clone(),
+        }
+    }
+}
+
+///Terminal columns of s, two for CJK and fullwidth characters.
+fn display_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| match c {
+            '\u{1100}'..='\u{115F}'
+            | '\u{2E80}'..='\u{A4CF}'
+            | '\u{AC00}'..='\u{D7A3}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{FE30}'..='\u{FE4F}'
+            | '\u{FF00}'..='\u{FF60}'
+            | '\u{FFE0}'..='\u{FFE6}' => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+///Rows printed for statistics, the intraday ones only with equity sampling on and financing
+///only when charged.
+pub fn statistics_rows(statistics: &BacktestingStatistics, intraday: bool) -> Vec<StatisticsRow> {
+    use StatisticsValue::{Count, Date, Number, Percent, Text};
+    let s = statistics;
+    let mut rows = vec![
+        StatisticsRow::new(
+            "start_date",
+            "首个交易日：\t",
+            "Start date",
+            Date(s.start_date),
+        ),
+        StatisticsRow::new("end_date", "最后交易日：\t", "End date", Date(s.end_date)),
+        StatisticsRow::new(
+            "total_days",
+            "总交易日：\t",
+            "Total days",
+            Count(s.total_days),
+        ),
+        StatisticsRow::new(
+            "profit_days",
+            "盈利交易日：\t",
+            "Profit days",
+            Count(s.profit_days),
+        ),
+        StatisticsRow::new(
+            "loss_days",
+            "亏损交易日：\t",
+            "Loss days",
+            Count(s.loss_days),
+        ),
+        StatisticsRow::new("capital", "起始资金：\t", "Capital", Number(s.capital)),
+        StatisticsRow::new(
+            "end_balance",
+            "结束资金：\t",
+            "End balance",
+            Number(s.end_balance),
+        ),
+        StatisticsRow::new(
+            "total_return",
+            "总收益率：\t",
+            "Total return",
+            Percent(s.total_return),
+        ),
+        StatisticsRow::new(
+            "annual_return",
+            "年化收益：\t",
+            "Annual return",
+            Percent(s.annual_return),
+        ),
+        StatisticsRow::new(
+            "max_drawdown",
+            "最大回撤: \t",
+            "Max drawdown",
+            Number(s.max_drawdown),
+        ),
+        StatisticsRow::new(
+            "max_ddpercent",
+            "百分比最大回撤: ",
+            "Max drawdown percent",
+            Percent(s.max_ddpercent),
+        ),
+        StatisticsRow::new(
+            "max_drawdown_duration",
+            "最长回撤天数: \t",
+            "Max drawdown days",
+            Count(s.max_drawdown_duration),
+        ),
+        StatisticsRow::new(
+            "total_net_pnl",
+            "总盈亏：\t",
+            "Total net pnl",
+            Number(s.total_net_pnl),
+        ),
+        StatisticsRow::new(
+            "total_commission",
+            "总手续费：\t",
+            "Total commission",
+            Number(s.total_commission),
+        ),
+        StatisticsRow::new(
+            "total_slippage",
+            "总滑点：\t",
+            "Total slippage",
+            Number(s.total_slippage),
+        ),
+    ];
+    if s.total_financing != 0.0 {
+        rows.push(StatisticsRow::new(
+            "total_financing",
+            "总融资成本：\t",
+            "Total financing",
+            Number(s.total_financing),
+        ));
+    }
+    rows.extend([
+        StatisticsRow::new(
+            "total_turnover",
+            "总成交金额：\t",
+            "Total turnover",
+            Number(s.total_turnover),
+        ),
+        StatisticsRow::new(
+            "total_trade_count",
+            "总成交笔数：\t",
+            "Total trades",
+            Count(s.total_trade_count),
+        ),
+        StatisticsRow::new(
+            "daily_net_pnl",
+            "日均盈亏：\t",
+            "Daily net pnl",
+            Number(s.daily_net_pnl),
+        ),
+        StatisticsRow::new(
+            "daily_commission",
+            "日均手续费：\t",
+            "Daily commission",
+            Number(s.daily_commission),
+        ),
+        StatisticsRow::new(
+            "daily_slippage",
+            "日均滑点：\t",
+            "Daily slippage",
+            Number(s.daily_slippage),
+        ),
+        StatisticsRow::new(
+            "daily_turnover",
+            "日均成交金额：\t",
+            "Daily turnover",
+            Number(s.daily_turnover),
+        ),
+        StatisticsRow::new(
+            "daily_trade_count",
+            "日均成交笔数：\t",
+            "Daily trades",
+            Number(s.daily_trade_count),
+        ),
+        StatisticsRow::new(
+            "daily_return",
+            "日均收益率：\t",
+            "Daily return",
+            Percent(s.daily_return),
+        ),
+        StatisticsRow::new(
+            "return_std",
+            "收益标准差：\t",
+            "Return std",
+            Percent(s.return_std),
+        ),
+        StatisticsRow::new(
+            "sharpe_ratio",
+            "Sharpe Ratio：\t",
+            "Sharpe ratio",
+            Number(s.sharpe_ratio),
+        ),
+        StatisticsRow::new(
+            "return_drawdown_ratio",
+            "收益回撤比：\t",
+            "Return drawdown ratio",
+            Number(s.return_drawdown_ratio),
+        ),
+        StatisticsRow::new(
+            "position_days_ratio",
+            "持仓天数占比：\t",
+            "Position days ratio",
+            Percent(s.position_days_ratio),
+        ),
+        StatisticsRow::new(
+            "position_bars_ratio",
+            "持仓K线占比：\t",
+            "Position bars ratio",
+            Percent(s.position_bars_ratio),
+        ),
+        StatisticsRow::new(
+            "average_holding_bars",
+            "平均持仓K线数：\t",
+            "Average holding bars",
+            Number(s.average_holding_bars),
+        ),
+        StatisticsRow::new(
+            "annual_turnover",
+            "年化换手率：\t",
+            "Annual turnover",
+            Number(s.annual_turnover),
+        ),
+        StatisticsRow::new(
+            "long_net_pnl",
+            "多头净盈亏：\t",
+            "Long net pnl",
+            Number(s.long_net_pnl),
+        ),
+        StatisticsRow::new(
+            "short_net_pnl",
+            "空头净盈亏：\t",
+            "Short net pnl",
+            Number(s.short_net_pnl),
+        ),
+    ]);
+    if intraday {
+        rows.extend([
+            StatisticsRow::new(
+                "intraday_max_drawdown",
+                "日内最大回撤: \t",
+                "Intraday max drawdown",
+                Number(s.intraday_max_drawdown),
+            ),
+            StatisticsRow::new(
+                "intraday_max_ddpercent",
+                "日内百分比最大回撤: ",
+                "Intraday max drawdown percent",
+                Percent(s.intraday_max_ddpercent),
+            ),
+            StatisticsRow::new(
+                "margin_call_count",
+                "保证金不足次数：\t",
+                "Margin calls",
+                Count(s.margin_call_count),
+            ),
+            StatisticsRow::new(
+                "intraday_sharpe_ratio",
+                "日内Sharpe Ratio：\t",
+                "Intraday sharpe ratio",
+                Number(s.intraday_sharpe_ratio),
+            ),
+        ]);
+    }
+    if let Some(risk_halt) = &s.risk_halt {
+        rows.push(StatisticsRow::new(
+            "risk_halt",
+            "风控停止交易：\t",
+            "Risk halt",
+            Text(format!("{} {}", risk_halt.datetime, risk_halt.reason)),
+        ));
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles_and_locales() {
+        let statistics = BacktestingStatistics {
+            total_days: 3,
+            total_return: 12.345,
+            sharpe_ratio: 1.5,
+            ..Default::default()
+        };
+        let rows = statistics_rows(&statistics, false);
+
+        let tabs = StatisticsFormatter::default().render(&rows);
+        assert_eq!(tabs[0], "-".repeat(30));
+        assert!(tabs.contains(&"总收益率：\t12.35%".to_string()));
+        assert!(tabs.contains(&"百分比最大回撤: 0.00%".to_string()));
+        assert!(!tabs.iter().any(|line| line.contains("融资")));
+
+        let en = StatisticsFormatter {
+            decimals: 1,
+            ..StatisticsFormatter::new(Locale::En, StatisticsStyle::Tabs)
+        };
+        assert!(en.render(&rows).contains(&"Total days:\t3".to_string()));
+
+        let table = StatisticsFormatter::new(Locale::Zh, StatisticsStyle::Table).render(&rows);
+        let widths: Vec<usize> = table[1..].iter().map(|line| display_width(line)).collect();
+        assert!(widths.iter().all(|width| *width == widths[0]));
+        assert_eq!(display_width(&table[0]), widths[0]);
+        assert!(table.iter().all(|line| !line.contains('\t')));
+
+        let json = StatisticsFormatter::new(Locale::Zh, StatisticsStyle::Json).render(&rows);
+        assert_eq!(json.len(), 1);
+        let value: Value = serde_json::from_str(&json[0]).unwrap();
+        assert_eq!(value["total_return"], 12.345);
+        assert_eq!(value["total_days"], 3);
+        assert_eq!(value["start_date"], NaiveDate::default().to_string());
+    }
+}
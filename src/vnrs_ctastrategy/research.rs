@@ -0,0 +1,254 @@
+/*!Factor research on the backtester: strategies emit the values of their signals or factors
+with StrategyContext::emit_factor while history is replayed, and the engine records them next to
+the close of each bar with the forward returns over a few horizons. The resulting DataFrame
+feeds the information coefficient and quantile analysis below. */
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use polars::prelude::*;
+
+///Factors emitted on each bar with its close, see BacktestingEngine::set_factor_research.
+#[derive(Debug, Clone, Default)]
+pub struct FactorRecorder {
+    ///Forward return horizons in bars.
+    horizons: Vec<usize>,
+    datetimes: Vec<NaiveDateTime>,
+    prices: Vec<f64>,
+    ///Values by factor name and bar, None on bars a factor wasn't emitted on.
+    factors: BTreeMap<String, Vec<Option<f64>>>,
+}
+
+impl FactorRecorder {
+    pub fn new(horizons: &[usize]) -> Self {
+        FactorRecorder {
+            horizons: horizons.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    ///Start the row of a bar, factors emitted until the next one belong to it.
+    pub fn record_bar(&mut self, datetime: NaiveDateTime, close_price: f64) {
+        self.datetimes.push(datetime);
+        self.prices.push(close_price);
+    }
+
+    ///Value of factor on the current bar, the last value counts if emitted twice. Ignored
+    ///before the first bar.
+    pub fn record_factor(&mut self, name: &str, value: f64) {
+        let rows = self.datetimes.len();
+        if rows == 0 {
+            return;
+        }
+        let values = self.factors.entry(name.to_string()).or_default();
+        values.resize(rows, None);
+        values[rows - 1] = Some(value);
+    }
+
+    ///Drop the rows recorded, keeping the horizons.
+    pub fn clear(&mut self) {
+        self.datetimes.clear();
+        self.prices.clear();
+        self.factors.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.datetimes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.datetimes.is_empty()
+    }
+
+    ///One row per bar: datetime, close, a column per factor in name order and a
+    ///fwd_return_{h} column per horizon, the return from the close to the close h bars
+    ///later, null for the last h bars.
+    pub fn df(&self) -> DataFrame {
+        let rows = self.len();
+        let mut columns = vec![
+            Series::new("datetime", self.datetimes.clone()),
+            Series::new("close", self.prices.clone()),
+        ];
+        for (name, values) in &self.factors {
+            let mut values = values.clone();
+            values.resize(rows, None);
+            columns.push(Series::new(name, values));
+        }
+        for &horizon in &self.horizons {
+            let returns: Vec<Option<f64>> = (0..rows)
+                .map(|i| {
+                    let later = *self.prices.get(i + horizon)?;
+                    let price = self.prices[i];
+                    (price != 0.0).then(|| later / price - 1.0)
+                })
+                .collect();
+            columns.push(Series::new(&forward_return_column(horizon), returns));
+        }
+        DataFrame::new(columns).unwrap()
+    }
+}
+
+///Name of the forward return column of horizon in FactorRecorder::df.
+pub fn forward_return_column(horizon: usize) -> String {
+    format!("fwd_return_{}", horizon)
+}
+
+///Pairs of the two columns where both are set.
+fn paired_values(df: &DataFrame, factor: &str, returns: &str) -> PolarsResult<Vec<(f64, f64)>> {
+    let factor = df.column(factor)?.f64()?;
+    let returns = df.column(returns)?.f64()?;
+    Ok(factor
+        .into_iter()
+        .zip(returns)
+        .filter_map(|(factor, returns)| Some((factor?, returns?)))
+        .filter(|(factor, returns)| factor.is_finite() && returns.is_finite())
+        .collect())
+}
+
+///Ranks starting at 1, ties get the average of their ranks.
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        for &index in &order[i..=j] {
+            ranks[index] = rank;
+        }
+        i = j + 1;
+    }
+    ranks
+}
+
+///Rank information coefficient, the Spearman correlation of factor with the returns column
+///over the bars where both are set. None with fewer than 3 such bars or a constant column.
+pub fn rank_ic(df: &DataFrame, factor: &str, returns: &str) -> PolarsResult<Option<f64>> {
+    let pairs = paired_values(df, factor, returns)?;
+    if pairs.len() < 3 {
+        return Ok(None);
+    }
+    let x = ranks(&pairs.iter().map(|pair| pair.0).collect::<Vec<f64>>());
+    let y = ranks(&pairs.iter().map(|pair| pair.1).collect::<Vec<f64>>());
+    let n = x.len() as f64;
+    let mean = (n + 1.0) / 2.0;
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in x.iter().zip(&y) {
+        cov += (x - mean) * (y - mean);
+        var_x += (x - mean).powi(2);
+        var_y += (y - mean).powi(2);
+    }
+    if var_x == 0.0 || var_y == 0.0 {
+        return Ok(None);
+    }
+    Ok(Some(cov / (var_x * var_y).sqrt()))
+}
+
+///Bars bucketed into quantiles quantiles of factor, 1 the lowest, with the count and mean of
+///the returns column in each. A factor worth trading shows returns rising or falling across
+///the quantiles.
+pub fn quantile_returns(
+    df: &DataFrame,
+    factor: &str,
+    returns: &str,
+    quantiles: usize,
+) -> PolarsResult<DataFrame> {
+    let pairs = paired_values(df, factor, returns)?;
+    let quantiles = quantiles.max(1);
+    let factor_ranks = ranks(&pairs.iter().map(|pair| pair.0).collect::<Vec<f64>>());
+    let mut sums = vec![0.0; quantiles];
+    let mut counts = vec![0u32; quantiles];
+    for (rank, (_, value)) in factor_ranks.iter().zip(&pairs) {
+        let bucket = (((rank - 1.0) / pairs.len() as f64) * quantiles as f64) as usize;
+        let bucket = bucket.min(quantiles - 1);
+        sums[bucket] += value;
+        counts[bucket] += 1;
+    }
+    DataFrame::new(vec![
+        Series::new("quantile", (1..=quantiles as u32).collect::<Vec<u32>>()),
+        Series::new("count", counts.clone()),
+        Series::new(
+            "mean_return",
+            sums.iter()
+                .zip(&counts)
+                .map(|(sum, &count)| (count > 0).then(|| sum / count as f64))
+                .collect::<Vec<Option<f64>>>(),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn factors_against_forward_returns() {
+        let start = NaiveDateTime::parse_from_str("2024-01-02 09:00", "%Y-%m-%d %H:%M").unwrap();
+        let prices = [100.0, 101.0, 103.0, 102.0, 106.0, 111.0];
+        let mut recorder = FactorRecorder::new(&[1, 2]);
+        recorder.record_factor("ignored", 1.0);
+        for (i, &price) in prices.iter().enumerate() {
+            recorder.record_bar(start + TimeDelta::minutes(i as i64), price);
+            // Perfect foresight of the next return, emitted on every other bar for momentum
+            if let Some(&next) = prices.get(i + 1) {
+                recorder.record_factor("foresight", next - price);
+            }
+            if i % 2 == 1 {
+                recorder.record_factor("momentum", 1.0);
+                recorder.record_factor("momentum", price - prices[i - 1]);
+            }
+        }
+
+        let df = recorder.df();
+        assert_eq!(
+            df.get_column_names(),
+            [
+                "datetime",
+                "close",
+                "foresight",
+                "momentum",
+                "fwd_return_1",
+                "fwd_return_2"
+            ]
+        );
+        assert_eq!(df.column("fwd_return_2").unwrap().null_count(), 2);
+        let momentum: Vec<Option<f64>> = df
+            .column("momentum")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            momentum,
+            vec![None, Some(1.0), None, Some(-1.0), None, Some(5.0)]
+        );
+
+        let ic = rank_ic(&df, "foresight", "fwd_return_1").unwrap().unwrap();
+        assert!((ic - 1.0).abs() < 1e-12);
+        // Only 2 bars have both
+        assert_eq!(rank_ic(&df, "momentum", "fwd_return_1").unwrap(), None);
+        assert!(rank_ic(&df, "unknown", "fwd_return_1").is_err());
+
+        let quantiles = quantile_returns(&df, "foresight", "fwd_return_1", 2).unwrap();
+        let counts: Vec<Option<u32>> = quantiles
+            .column("count")
+            .unwrap()
+            .u32()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(counts, vec![Some(3), Some(2)]);
+        let means = quantiles.column("mean_return").unwrap().f64().unwrap();
+        assert!(means.get(0).unwrap() < means.get(1).unwrap());
+    }
+
+    #[test]
+    fn ties_share_ranks() {
+        assert_eq!(ranks(&[3.0, 1.0, 3.0, 2.0]), vec![3.5, 1.0, 3.5, 2.0]);
+    }
+}
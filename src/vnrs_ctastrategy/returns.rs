@@ -0,0 +1,126 @@
+/*!Annualized statistics of returns sampled at any frequency, e.g. equity sampled on every
+minute bar, where annualizing by trading days alone would understate the volatility. */
+use std::collections::BTreeSet;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+///Statistics of a balance series, returns in percent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReturnsStatistics {
+    ///Returns a year, those of an average day times the trading days of a year.
+    pub periods_per_year: f64,
+    pub mean_return: f64,
+    pub return_std: f64,
+    pub annual_return: f64,
+    pub annual_volatility: f64,
+    pub sharpe_ratio: f64,
+}
+
+///Annualizes log returns between consecutive samples by the number of samples a year, taken
+///from how many samples the series has per calendar date it spans.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReturnsAnalyzer {
+    pub annual_days: i64,
+    ///Annual risk-free rate as a fraction, 0.02 for 2%.
+    pub risk_free: f64,
+}
+
+impl ReturnsAnalyzer {
+    pub fn new(annual_days: i64, risk_free: f64) -> Self {
+        ReturnsAnalyzer {
+            annual_days,
+            risk_free,
+        }
+    }
+
+    ///Returns a year of a series with returns ending at datetimes.
+    pub fn periods_per_year(&self, datetimes: &[NaiveDateTime]) -> f64 {
+        let dates: BTreeSet<_> = datetimes.iter().map(NaiveDateTime::date).collect();
+        if dates.is_empty() {
+            return 0.0;
+        }
+        datetimes.len() as f64 / dates.len() as f64 * self.annual_days as f64
+    }
+
+    ///Statistics of balances sampled at their datetimes, sorted by datetime. All zero with
+    ///fewer than two samples or a balance that isn't positive.
+    pub fn analyze(&self, samples: &[(NaiveDateTime, f64)]) -> ReturnsStatistics {
+        if samples.len() < 2 || samples.iter().any(|(_, balance)| *balance <= 0.0) {
+            return ReturnsStatistics::default();
+        }
+
+        let datetimes: Vec<NaiveDateTime> =
+            samples[1..].iter().map(|(datetime, _)| *datetime).collect();
+        let periods_per_year = self.periods_per_year(&datetimes);
+        let returns: Vec<f64> = samples
+            .windows(2)
+            .map(|pair| (pair[1].1 / pair[0].1).ln())
+            .collect();
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        let variance =
+            returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / returns.len() as f64;
+        let std = variance.sqrt();
+
+        let period_risk_free = (1.0 + self.risk_free).ln() / periods_per_year;
+        let sharpe_ratio = if std != 0.0 {
+            (mean - period_risk_free) / std * periods_per_year.sqrt()
+        } else {
+            0.0
+        };
+        ReturnsStatistics {
+            periods_per_year,
+            mean_return: mean * 100.0,
+            return_std: std * 100.0,
+            annual_return: mean * periods_per_year * 100.0,
+            annual_volatility: std * periods_per_year.sqrt() * 100.0,
+            sharpe_ratio,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeDelta};
+
+    #[test]
+    fn annualized_by_samples_per_day() {
+        let open = NaiveDate::from_ymd_opt(2024, 1, 2)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        // 2 days of 240 minute samples, returns alternating +0.1% and -0.05%
+        let mut samples = Vec::new();
+        let mut balance = 1_000_000.0;
+        for day in 0..2 {
+            for minute in 0..240 {
+                samples.push((
+                    open + TimeDelta::days(day) + TimeDelta::minutes(minute),
+                    balance,
+                ));
+                balance *= if minute % 2 == 0 { 1.001 } else { 0.9995 };
+            }
+        }
+
+        let analyzer = ReturnsAnalyzer::new(240, 0.0);
+        let statistics = analyzer.analyze(&samples);
+        assert_eq!(statistics.periods_per_year, 479.0 / 2.0 * 240.0);
+        let daily = ReturnsAnalyzer::new(240, 0.0).analyze(&[
+            (open, 1_000_000.0),
+            (open + TimeDelta::days(1), 1_010_000.0),
+        ]);
+        assert_eq!(daily.periods_per_year, 240.0);
+        assert!((daily.annual_return - 1.01f64.ln() * 240.0 * 100.0).abs() < 1e-9);
+
+        // A higher risk-free rate lowers the sharpe ratio
+        let with_risk_free = ReturnsAnalyzer::new(240, 0.05).analyze(&samples);
+        assert!(with_risk_free.sharpe_ratio < statistics.sharpe_ratio);
+        assert_eq!(
+            ReturnsAnalyzer::new(240, 0.0)
+                .analyze(&samples[..1])
+                .sharpe_ratio,
+            0.0
+        );
+    }
+}
@@ -0,0 +1,162 @@
+/*!Backtesting served over the vnpy compatible RPC, enabled by the `rpc` feature. */
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use std::panic::{self, AssertUnwindSafe};
+
+use super::backtesting::BacktestingEngine;
+use super::base::{BacktestingMode, StrategyDirectory};
+use super::batch::panic_message;
+use crate::vnrs::rpc::{Kwargs, RpcPublisher, RpcServer, Value};
+use crate::vnrs::trader::constant::Interval;
+
+///Keyword arguments of run_backtesting, datetimes as "%Y-%m-%d %H:%M:%S" strings.
+#[derive(Deserialize)]
+struct BacktestingRequest {
+    ///Name of a strategy dylib in the strategy directory of the server.
+    strategy: String,
+    setting: String,
+    vt_symbol: String,
+    interval: Interval,
+    start: String,
+    end: String,
+    rate: f64,
+    slippage: f64,
+    size: f64,
+    pricetick: f64,
+    capital: f64,
+    ///Net position carried into the backtest, flat if missing.
+    #[serde(default)]
+    initial_pos: f64,
+    risk_free: f64,
+    ///Derived from the market of the symbol if missing.
+    annual_days: Option<i64>,
+    half_life: i64,
+}
+
+///Register run_backtesting(**kwargs) on server, returning the statistics as a dict.
+///Logs and progress are published under the "log" and "progress" topics while it runs.
+///Strategies are named by clients and loaded from strategies.
+pub fn register_backtesting(server: &RpcServer, strategies: StrategyDirectory) {
+    let publisher = server.publisher();
+    server.register("run_backtesting", move |_, kwargs| {
+        run_backtesting(kwargs, &strategies, publisher.clone())
+    });
+}
+
+fn run_backtesting(
+    kwargs: Kwargs,
+    strategies: &StrategyDirectory,
+    publisher: RpcPublisher,
+) -> Result<Value, String> {
+    let request: BacktestingRequest =
+        serde_pickle::from_value(Value::Dict(kwargs)).map_err(|e| e.to_string())?;
+    let start = NaiveDateTime::parse_from_str(&request.start, "%Y-%m-%d %H:%M:%S")
+        .map_err(|e| e.to_string())?;
+    let end = NaiveDateTime::parse_from_str(&request.end, "%Y-%m-%d %H:%M:%S")
+        .map_err(|e| e.to_string())?;
+
+    let strategy_class = strategies
+        .load(&request.strategy)
+        .map_err(|e| e.to_string())?;
+
+    let statistics = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut engine = BacktestingEngine::new();
+
+        let log_publisher = publisher.clone();
+        engine.set_output_callback(move |msg| {
+            log_publisher.publish("log", Value::String(msg.to_string()));
+        });
+        let progress_publisher = publisher.clone();
+        engine.set_progress_callback(move |progress| {
+            if let Ok(data) = serde_pickle::to_value(progress) {
+                progress_publisher.publish("progress", data);
+            }
+        });
+
+        engine
+            .set_parameters(
+                &request.vt_symbol,
+                request.interval,
+                start,
+                end,
+                request.rate,
+                request.slippage,
+                request.size,
+                request.pricetick,
+                request.capital,
+                BacktestingMode::BAR,
+                request.risk_free,
+                0,
+                request.half_life,
+            )
+            .map_err(|e| e.to_string())?;
+        engine.set_initial_pos(request.initial_pos);
+        engine.set_annual_days(request.annual_days);
+        engine
+            .add_strategy(strategy_class, &request.setting)
+            .map_err(|e| e.to_string())?;
+        engine.load_data().map_err(|e| e.to_string())?;
+        engine.run_backtesting().map_err(|e| e.to_string())?;
+        engine.calculate_result();
+        Ok(engine.calculate_statistics(None, false))
+    }))
+    .map_err(panic_message)
+    .and_then(|statistics| statistics)?;
+
+    serde_pickle::to_value(&statistics).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::rpc::{HashableValue, RpcClient, RpcError};
+    use std::net::TcpListener;
+
+    #[test]
+    fn strategies_only_loaded_by_name() {
+        let listeners = [(); 2].map(|_| TcpListener::bind("127.0.0.1:0").unwrap());
+        let [rep_address, pub_address] = listeners
+            .each_ref()
+            .map(|listener| format!("tcp://{}", listener.local_addr().unwrap()));
+        drop(listeners);
+
+        let mut server = RpcServer::new();
+        register_backtesting(&server, StrategyDirectory::new(std::env::temp_dir()));
+        server.start(&rep_address, &pub_address).unwrap();
+        let mut client = RpcClient::new();
+        client.start(&rep_address, &pub_address, |_, _| {}).unwrap();
+
+        let kwargs = |strategy: &str| -> Kwargs {
+            let text = |s: &str| Value::String(s.to_string());
+            [
+                ("strategy", text(strategy)),
+                ("setting", text("")),
+                ("vt_symbol", text("IF888.CFFEX")),
+                ("interval", text("1m")),
+                ("start", text("2024-01-22 00:00:00")),
+                ("end", text("2024-02-01 00:00:00")),
+                ("rate", Value::F64(0.0)),
+                ("slippage", Value::F64(0.0)),
+                ("size", Value::F64(300.0)),
+                ("pricetick", Value::F64(0.2)),
+                ("capital", Value::F64(1_000_000.0)),
+                ("risk_free", Value::F64(0.0)),
+                ("half_life", Value::I64(120)),
+            ]
+            .into_iter()
+            .map(|(key, value)| (HashableValue::String(key.to_string()), value))
+            .collect()
+        };
+        for (strategy, error) in [
+            ("../../lib/evil", "invalid strategy name"),
+            ("missing_strategy", "not found"),
+        ] {
+            match client.call("run_backtesting", Vec::new(), kwargs(strategy)) {
+                Err(RpcError::Remote(msg)) => assert!(msg.contains(error), "{}", msg),
+                other => panic!("{} loaded: {:?}", strategy, other),
+            }
+        }
+        client.stop();
+        server.stop();
+    }
+}
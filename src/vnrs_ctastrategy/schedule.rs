@@ -0,0 +1,492 @@
+/*!Trading schedules of live strategies, started when a session opens and stopped when it
+closes, and of the symbols they trade, whose orders outside the sessions are rejected or held
+instead of being rejected by the exchange.
+
+The crate has no live engine yet, these are standalone building blocks for one: the
+StrategyScheduler puts EVENT_CTA_SCHEDULE events on an EventEngine for the engine running the
+strategies to act on, and the SessionGate is called by the code sending orders. */
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, Weak};
+
+use chrono::{Days, NaiveDateTime, NaiveTime};
+use log::Level;
+use serde::{Deserialize, Serialize};
+
+use super::base::{EVENT_CTA_LOG, EVENT_CTA_SCHEDULE};
+use crate::vnrs::event::{Event, EventEngine, HandlerType};
+use crate::vnrs::trader::calendar::TradingCalendar;
+use crate::vnrs::trader::clock::Clock;
+use crate::vnrs::trader::event::EVENT_TIMER;
+use crate::vnrs::trader::object::LogData;
+
+///Local times a session starts and stops at, a stop before the start crosses midnight like a
+///futures night session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub start: NaiveTime,
+    pub stop: NaiveTime,
+}
+
+impl Session {
+    fn crosses_midnight(&self) -> bool {
+        self.stop < self.start
+    }
+
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.crosses_midnight() {
+            time >= self.start || time < self.stop
+        } else {
+            time >= self.start && time < self.stop
+        }
+    }
+}
+
+///Sessions of a strategy. Outside them the strategy is stopped, after cancelling its orders
+///and, if flatten, closing its position.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradingSchedule {
+    pub sessions: Vec<Session>,
+    ///Sessions starting on a day the calendar doesn't trade are skipped.
+    #[serde(default)]
+    pub skip_holidays: bool,
+    #[serde(default)]
+    pub flatten: bool,
+}
+
+impl TradingSchedule {
+    pub fn is_active(&self, datetime: NaiveDateTime, calendar: &TradingCalendar) -> bool {
+        let time = datetime.time();
+        self.sessions.iter().any(|session| {
+            if !session.contains(time) {
+                return false;
+            }
+            if !self.skip_holidays {
+                return true;
+            }
+            // After midnight the session belongs to the day it started on
+            let start_date = if session.crosses_midnight() && time < session.stop {
+                datetime.date() - Days::new(1)
+            } else {
+                datetime.date()
+            };
+            calendar.is_trading_day(start_date)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleAction {
+    START,
+    ///Cancel all orders, close the position if flatten, then stop.
+    STOP {
+        flatten: bool,
+    },
+}
+
+///Data of an EVENT_CTA_SCHEDULE event, carried out by the engine running the strategy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleChange {
+    pub strategy_name: String,
+    pub action: ScheduleAction,
+}
+
+///Tracks whether each scheduled strategy should be running. It only reports the changes,
+///starting and stopping the strategies is left to the live engine handling them, which the
+///crate doesn't have yet.
+#[derive(Debug, Default)]
+pub struct StrategyScheduler {
+    pub calendar: TradingCalendar,
+    schedules: HashMap<String, TradingSchedule>,
+    active: HashMap<String, bool>,
+}
+
+impl StrategyScheduler {
+    pub fn new(calendar: TradingCalendar) -> Self {
+        StrategyScheduler {
+            calendar,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_schedule(&mut self, strategy_name: &str, schedule: TradingSchedule) {
+        self.schedules.insert(strategy_name.to_string(), schedule);
+        self.active.remove(strategy_name);
+    }
+
+    pub fn remove_schedule(&mut self, strategy_name: &str) {
+        self.schedules.remove(strategy_name);
+        self.active.remove(strategy_name);
+    }
+
+    ///Changes since the last check, sorted by strategy name. The first check of a strategy
+    ///always gives the action for its current state.
+    pub fn check(&mut self, datetime: NaiveDateTime) -> Vec<ScheduleChange> {
+        let mut changes = Vec::new();
+        for (strategy_name, schedule) in &self.schedules {
+            let active = schedule.is_active(datetime, &self.calendar);
+            if self.active.insert(strategy_name.clone(), active) == Some(active) {
+                continue;
+            }
+            changes.push(ScheduleChange {
+                strategy_name: strategy_name.clone(),
+                action: if active {
+                    ScheduleAction::START
+                } else {
+                    ScheduleAction::STOP {
+                        flatten: schedule.flatten,
+                    }
+                },
+            });
+        }
+        changes.sort_by(|a, b| a.strategy_name.cmp(&b.strategy_name));
+        changes
+    }
+
+    ///Check the time of clock on every timer event, putting an EVENT_CTA_LOG and an
+    ///EVENT_CTA_SCHEDULE event for each change. Returns the handler for unregistering.
+    pub fn register(
+        scheduler: Arc<Mutex<Self>>,
+        event_engine: &Arc<EventEngine>,
+        clock: Arc<dyn Clock>,
+    ) -> HandlerType {
+        let engine: Weak<EventEngine> = Arc::downgrade(event_engine);
+        let handler: HandlerType = Arc::new(move |_: &Event| {
+            let Some(event_engine) = engine.upgrade() else {
+                return;
+            };
+            let now = clock.now();
+            for change in scheduler.lock().unwrap().check(now) {
+                let msg = match change.action {
+                    ScheduleAction::START => {
+                        format!("{}交易时段开始，启动策略", change.strategy_name)
+                    }
+                    ScheduleAction::STOP { .. } => {
+                        format!("{}交易时段结束，停止策略", change.strategy_name)
+                    }
+                };
+                let log = LogData::new(&msg, Level::Info, "StrategyScheduler", now);
+                event_engine.put(Event::new(EVENT_CTA_LOG, log));
+                event_engine.put(Event::new(EVENT_CTA_SCHEDULE, change));
+            }
+        });
+        event_engine.register(EVENT_TIMER, handler.clone());
+        handler
+    }
+}
+
+///What the engine does with an order sent outside the sessions of its symbol.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClosedSessionAction {
+    #[default]
+    REJECT,
+    ///Hold the order and send it once the next session opens.
+    HOLD,
+}
+
+///Outcome of an order checked by a SessionGate, msg is the log written back to the strategy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionDecision<T> {
+    Send(T),
+    Held { msg: String },
+    Rejected { order: T, msg: String },
+}
+
+///Orders held by a SessionGate before new ones are rejected, see SessionGate::max_held.
+pub const MAX_HELD_ORDERS: usize = 1000;
+
+///Orders checked against the sessions of their vt_symbol before they reach a gateway. Symbols
+///without a schedule trade at any time.
+///
+///A standalone utility, not wired into an engine: the crate has no live engine, the code
+///sending orders to a gateway passes them through submit and sends the released ones.
+pub struct SessionGate<T> {
+    pub calendar: TradingCalendar,
+    pub action: ClosedSessionAction,
+    ///Orders held at most, further ones are rejected until held orders are released.
+    pub max_held: usize,
+    clock: Arc<dyn Clock>,
+    schedules: HashMap<String, TradingSchedule>,
+    held: VecDeque<(String, T)>,
+}
+
+impl<T> SessionGate<T> {
+    pub fn new(
+        calendar: TradingCalendar,
+        clock: Arc<dyn Clock>,
+        action: ClosedSessionAction,
+    ) -> Self {
+        SessionGate {
+            calendar,
+            action,
+            max_held: MAX_HELD_ORDERS,
+            clock,
+            schedules: HashMap::new(),
+            held: VecDeque::new(),
+        }
+    }
+
+    ///Sessions of vt_symbol, skip_holidays of the schedule to close it on the holidays of the
+    ///calendar.
+    pub fn set_schedule(&mut self, vt_symbol: &str, schedule: TradingSchedule) {
+        self.schedules.insert(vt_symbol.to_string(), schedule);
+    }
+
+    pub fn is_open(&self, vt_symbol: &str) -> bool {
+        self.schedules
+            .get(vt_symbol)
+            .is_none_or(|schedule| schedule.is_active(self.clock.now(), &self.calendar))
+    }
+
+    ///Check an order of vt_symbol at the time of the clock. While orders of vt_symbol are held,
+    ///newer ones are held behind them even in the session, so that release keeps their order.
+    pub fn submit(&mut self, vt_symbol: &str, order: T) -> SessionDecision<T> {
+        let now = self.clock.now();
+        if self.is_open(vt_symbol) {
+            if !self.held.iter().any(|(held, _)| held == vt_symbol) {
+                return SessionDecision::Send(order);
+            }
+            if let Some(rejected) = self.hold(vt_symbol, order) {
+                return rejected;
+            }
+            return SessionDecision::Held {
+                msg: format!("{}有暂缓的委托（{}），委托排在其后发送", vt_symbol, now),
+            };
+        }
+        match self.action {
+            ClosedSessionAction::REJECT => SessionDecision::Rejected {
+                order,
+                msg: format!("{}不在交易时段（{}），委托被拒绝", vt_symbol, now),
+            },
+            ClosedSessionAction::HOLD => {
+                if let Some(rejected) = self.hold(vt_symbol, order) {
+                    return rejected;
+                }
+                SessionDecision::Held {
+                    msg: format!(
+                        "{}不在交易时段（{}），委托暂缓至交易时段开始后发送",
+                        vt_symbol, now
+                    ),
+                }
+            }
+        }
+    }
+
+    ///Hold the order, None unless it is rejected for max_held orders being held already.
+    fn hold(&mut self, vt_symbol: &str, order: T) -> Option<SessionDecision<T>> {
+        if self.held.len() >= self.max_held {
+            return Some(SessionDecision::Rejected {
+                order,
+                msg: format!(
+                    "暂缓的委托已达上限{}，{}的委托被拒绝",
+                    self.max_held, vt_symbol
+                ),
+            });
+        }
+        self.held.push_back((vt_symbol.to_string(), order));
+        None
+    }
+
+    ///Held orders whose session is open now as (vt_symbol, order), in the order they were
+    ///sent. Call regularly, e.g. on every timer event, and send them.
+    pub fn release(&mut self) -> Vec<(String, T)> {
+        let held = std::mem::take(&mut self.held);
+        let (released, held): (VecDeque<_>, VecDeque<_>) = held
+            .into_iter()
+            .partition(|(vt_symbol, _)| self.is_open(vt_symbol));
+        self.held = held;
+        released.into()
+    }
+
+    ///Take the held orders matching f, e.g. the ones of a strategy being stopped or an order
+    ///cancelled before it was sent.
+    pub fn take_held<F: Fn(&T) -> bool>(&mut self, f: F) -> Vec<T> {
+        let held = std::mem::take(&mut self.held);
+        let (taken, held): (VecDeque<_>, VecDeque<_>) =
+            held.into_iter().partition(|(_, order)| f(order));
+        self.held = held;
+        taken.into_iter().map(|(_, order)| order).collect()
+    }
+
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn start_and_stop_by_session() {
+        let time = |s: &str| NaiveTime::parse_from_str(s, "%H:%M").unwrap();
+        let calendar = TradingCalendar::new([at("2024-10-01 00:00").date()]);
+        let mut scheduler = StrategyScheduler::new(calendar);
+        scheduler.add_schedule(
+            "day",
+            TradingSchedule {
+                sessions: vec![Session {
+                    start: time("09:25"),
+                    stop: time("14:55"),
+                }],
+                skip_holidays: true,
+                flatten: true,
+            },
+        );
+        scheduler.add_schedule(
+            "night",
+            TradingSchedule {
+                sessions: vec![Session {
+                    start: time("21:00"),
+                    stop: time("02:30"),
+                }],
+                skip_holidays: true,
+                flatten: false,
+            },
+        );
+
+        let stop = |name: &str, flatten| ScheduleChange {
+            strategy_name: name.to_string(),
+            action: ScheduleAction::STOP { flatten },
+        };
+        let start = |name: &str| ScheduleChange {
+            strategy_name: name.to_string(),
+            action: ScheduleAction::START,
+        };
+        assert_eq!(
+            scheduler.check(at("2024-09-30 09:00")),
+            vec![stop("day", true), stop("night", false)]
+        );
+        assert_eq!(scheduler.check(at("2024-09-30 09:25")), vec![start("day")]);
+        assert!(scheduler.check(at("2024-09-30 10:00")).is_empty());
+        assert_eq!(
+            scheduler.check(at("2024-09-30 14:55")),
+            vec![stop("day", true)]
+        );
+        assert_eq!(
+            scheduler.check(at("2024-09-30 21:00")),
+            vec![start("night")]
+        );
+        // The night session of the 30th runs past midnight into the holiday
+        assert!(scheduler.check(at("2024-10-01 01:00")).is_empty());
+        assert_eq!(
+            scheduler.check(at("2024-10-01 02:30")),
+            vec![stop("night", false)]
+        );
+        // Neither runs on the holiday
+        assert!(scheduler.check(at("2024-10-01 10:00")).is_empty());
+        assert!(scheduler.check(at("2024-10-01 22:00")).is_empty());
+    }
+
+    #[test]
+    fn orders_outside_sessions() {
+        use crate::vnrs::trader::clock::SimClock;
+
+        let time = |s: &str| NaiveTime::parse_from_str(s, "%H:%M").unwrap();
+        let clock = Arc::new(SimClock::new(at("2024-09-30 08:00")));
+        let calendar = TradingCalendar::new([at("2024-10-01 00:00").date()]);
+        let mut gate = SessionGate::new(calendar, clock.clone(), ClosedSessionAction::REJECT);
+        gate.set_schedule(
+            "rb2501.SHFE",
+            TradingSchedule {
+                sessions: vec![
+                    Session {
+                        start: time("09:00"),
+                        stop: time("15:00"),
+                    },
+                    Session {
+                        start: time("21:00"),
+                        stop: time("23:00"),
+                    },
+                ],
+                skip_holidays: true,
+                flatten: false,
+            },
+        );
+
+        assert_eq!(
+            gate.submit("rb2501.SHFE", 1),
+            SessionDecision::Rejected {
+                order: 1,
+                msg: "rb2501.SHFE不在交易时段（2024-09-30 08:00:00），委托被拒绝".to_string()
+            }
+        );
+        // Symbols without a schedule always trade
+        assert_eq!(gate.submit("BTC-USDT.OKX", 2), SessionDecision::Send(2));
+
+        gate.action = ClosedSessionAction::HOLD;
+        assert!(matches!(
+            gate.submit("rb2501.SHFE", 3),
+            SessionDecision::Held { .. }
+        ));
+        assert!(matches!(
+            gate.submit("rb2501.SHFE", 4),
+            SessionDecision::Held { .. }
+        ));
+        assert_eq!(gate.take_held(|order| *order == 4), vec![4]);
+        assert!(gate.release().is_empty());
+        clock.advance_to(at("2024-09-30 09:00"));
+        assert_eq!(gate.release(), vec![("rb2501.SHFE".to_string(), 3)]);
+        assert_eq!(gate.submit("rb2501.SHFE", 5), SessionDecision::Send(5));
+
+        // Closed all day on the holiday
+        clock.advance_to(at("2024-10-01 10:00"));
+        assert!(!gate.is_open("rb2501.SHFE"));
+        assert!(matches!(
+            gate.submit("rb2501.SHFE", 6),
+            SessionDecision::Held { .. }
+        ));
+        assert_eq!(gate.held(), 1);
+    }
+
+    #[test]
+    fn held_orders_go_first() {
+        use crate::vnrs::trader::clock::SimClock;
+
+        let time = |s: &str| NaiveTime::parse_from_str(s, "%H:%M").unwrap();
+        let clock = Arc::new(SimClock::new(at("2024-09-30 08:00")));
+        let mut gate = SessionGate::new(
+            TradingCalendar::default(),
+            clock.clone(),
+            ClosedSessionAction::HOLD,
+        );
+        gate.max_held = 3;
+        gate.set_schedule(
+            "rb2501.SHFE",
+            TradingSchedule {
+                sessions: vec![Session {
+                    start: time("09:00"),
+                    stop: time("15:00"),
+                }],
+                skip_holidays: false,
+                flatten: false,
+            },
+        );
+
+        for order in [1, 2] {
+            assert!(matches!(
+                gate.submit("rb2501.SHFE", order),
+                SessionDecision::Held { .. }
+            ));
+        }
+        // Orders sent once the session opened queue behind the ones held before
+        clock.advance_to(at("2024-09-30 09:00"));
+        assert!(matches!(
+            gate.submit("rb2501.SHFE", 3),
+            SessionDecision::Held { .. }
+        ));
+        assert!(matches!(
+            gate.submit("rb2501.SHFE", 4),
+            SessionDecision::Rejected { order: 4, .. }
+        ));
+        assert_eq!(gate.submit("BTC-USDT.OKX", 5), SessionDecision::Send(5));
+        assert_eq!(
+            gate.release(),
+            [1, 2, 3].map(|order| ("rb2501.SHFE".to_string(), order))
+        );
+        assert_eq!(gate.submit("rb2501.SHFE", 6), SessionDecision::Send(6));
+    }
+}
@@ -0,0 +1,1284 @@
+/*!Helpers for writing a strategy dylib without touching the raw ABI.
+
+A strategy implements CtaStrategy and exports it with export_strategy!, which generates the
+`abi_*` functions the engine looks up, or is built into the host and run without a dylib
+through strategy_class. Engine calls go through the StrategyContext passed to every callback.
+A strategy trading toward a target position can leave the orders to a
+target::TargetPosExecutor working them through the context. Tick mode strategies find order
+flow features of the ticks received in StrategyContext::tick_features.
+
+```no_run
+use vnrs::export_strategy;
+use vnrs::vnrs::trader::object::BarData;
+use vnrs::vnrs_ctastrategy::sdk::{setting_value, CtaStrategy, StrategyContext};
+
+struct BuyOnce {
+    volume: f64,
+}
+
+impl CtaStrategy for BuyOnce {
+    fn parameters() -> Vec<(String, String)> {
+        vec![("volume".to_string(), "1".to_string())]
+    }
+
+    fn new(_context: &StrategyContext, setting: &[(String, String)]) -> Self {
+        BuyOnce {
+            volume: setting_value(setting, "volume", 1.0),
+        }
+    }
+
+    fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData) {
+        if context.pos() == 0.0 {
+            context.buy(bar.close_price, self.volume, false);
+        }
+    }
+}
+
+export_strategy!(BuyOnce);
+```
+*/
+use std::ffi::{c_char, CString};
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
+
+#[cfg(feature = "dylib-strategies")]
+use super::base::ExternClass;
+use super::base::{
+    format_setting, from_c_str, parse_setting, to_c_string, EngineType, PositionDetail, StopOrder,
+    TimeInForce, VTable,
+};
+use super::target::TargetPosGateway;
+use super::template::CtaTemplate;
+use crate::vnrs::trader::constant::{Direction, Interval, Offset};
+use crate::vnrs::trader::features::TickFeatureEngine;
+use crate::vnrs::trader::object::{BarData, ContractData, OrderData, TickData, TradeData};
+use crate::vnrs::trader::utility::{atr_position_size, round_to};
+
+///Strategy logic behind a dylib, callbacks get the context to call back into the engine.
+pub trait CtaStrategy: Sized + 'static {
+    ///Default parameters, returned to the host by abi_get_parameters.
+    fn parameters() -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    ///Create the strategy from its "name:value" setting.
+    fn new(context: &StrategyContext, setting: &[(String, String)]) -> Self;
+
+    fn on_init(&mut self, _context: &mut StrategyContext) {}
+
+    fn on_start(&mut self, _context: &mut StrategyContext) {}
+
+    fn on_stop(&mut self, _context: &mut StrategyContext) {}
+
+    fn on_tick(&mut self, _context: &mut StrategyContext, _tick: &TickData) {}
+
+    fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData);
+
+    fn on_order(&mut self, _context: &mut StrategyContext, _order: &OrderData) {}
+
+    fn on_trade(&mut self, _context: &mut StrategyContext, _trade: &TradeData) {}
+
+    fn on_stop_order(&mut self, _context: &mut StrategyContext, _stop_order: &StopOrder) {}
+
+    ///Called on the engine timer, e.g. to time out pending orders.
+    fn on_timer(&mut self, _context: &mut StrategyContext) {}
+
+    ///Variables saved by the engine with the position, e.g. to resume a run.
+    fn variables(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    fn set_variables(&mut self, _variables: &[(String, String)]) {}
+
+    ///Apply parameters changed while running, already checked against parameters(). False
+    ///if the strategy doesn't take updates, the default.
+    fn update_setting(
+        &mut self,
+        _context: &mut StrategyContext,
+        _setting: &[(String, String)],
+    ) -> bool {
+        false
+    }
+
+    ///Start over as new would create the strategy from setting, for another run with a fresh
+    ///context, keeping what doesn't depend on the run, e.g. data prepared in an expensive
+    ///on_init that is skipped when it's there. False to be dropped and created with new
+    ///instead, the default.
+    fn reset(&mut self, _context: &StrategyContext, _setting: &[(String, String)]) -> bool {
+        false
+    }
+}
+
+///Ticks the rolling tick features are over until set_tick_feature_window.
+pub const TICK_FEATURE_WINDOW: usize = 100;
+
+///Value of a parameter in a strategy setting, default if it's missing or doesn't parse.
+pub fn setting_value<T: FromStr>(setting: &[(String, String)], name: &str, default: T) -> T {
+    setting
+        .iter()
+        .find(|(key, _)| key == name)
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(default)
+}
+
+///Strategy state owned by the SDK and safe wrappers over the engine VTable.
+pub struct StrategyContext {
+    v_table: *const VTable,
+    handle: usize,
+    strategy: *mut CtaTemplate,
+    strategy_name: String,
+    vt_symbol: String,
+    inited: bool,
+    trading: bool,
+    ///Position mirrored by the engine through abi_get_pos_mut.
+    pos: f64,
+    ///Reference attached to the orders sent, see set_reference.
+    reference: CString,
+    ///Updated with every tick before on_tick.
+    tick_features: TickFeatureEngine,
+}
+
+impl StrategyContext {
+    fn new(v_table: *const VTable, strategy_name: &str, vt_symbol: &str) -> Self {
+        StrategyContext {
+            v_table,
+            // The engine handle is the address of its VTable
+            handle: v_table as usize,
+            strategy: std::ptr::null_mut(),
+            strategy_name: strategy_name.to_string(),
+            vt_symbol: vt_symbol.to_string(),
+            inited: false,
+            trading: false,
+            pos: 0.0,
+            reference: CString::default(),
+            tick_features: TickFeatureEngine::new(TICK_FEATURE_WINDOW),
+        }
+    }
+
+    pub fn strategy_name(&self) -> &str {
+        &self.strategy_name
+    }
+
+    pub fn vt_symbol(&self) -> &str {
+        &self.vt_symbol
+    }
+
+    pub fn inited(&self) -> bool {
+        self.inited
+    }
+
+    pub fn trading(&self) -> bool {
+        self.trading
+    }
+
+    pub fn pos(&self) -> f64 {
+        self.pos
+    }
+
+    ///Tag the orders sent from now on, e.g. with the signal sending them. The reference is
+    ///copied to their OrderData and TradeData for attribution of the results.
+    pub fn set_reference(&mut self, reference: &str) {
+        self.reference = to_c_string(reference);
+    }
+
+    ///Order flow, VWAP and microprice of the ticks received so far, including the one passed
+    ///to on_tick.
+    pub fn tick_features(&self) -> &TickFeatureEngine {
+        &self.tick_features
+    }
+
+    ///Number of ticks the rolling tick features are over, starting them over.
+    pub fn set_tick_feature_window(&mut self, n: usize) {
+        self.tick_features = TickFeatureEngine::new(n);
+    }
+
+    fn v_table(&self) -> &VTable {
+        // The engine keeps its VTable alive as long as the strategy instance
+        unsafe { &*self.v_table }
+    }
+
+    ///Bars of the days before the backtest start, e.g. to warm up indicators in on_init. They
+    ///come from the datafeed if one is configured, else from the database.
+    pub fn load_bar(&self, days: i64, interval: Interval) -> Vec<BarData> {
+        self.load_bar_from(days, interval, false)
+    }
+
+    ///Like load_bar, but the bars are pushed through on_bar once on_init returns, the way
+    ///vn.py replays warm-up history into its callback.
+    pub fn load_bar_callback(&self, days: i64, interval: Interval, use_database: bool) {
+        let vt_symbol = to_c_string(&self.vt_symbol);
+        unsafe {
+            (self.v_table().abi_load_bar_callback)(
+                self.handle,
+                vt_symbol.as_ptr(),
+                days,
+                interval.kind(),
+                interval.size(),
+                use_database,
+            );
+        }
+    }
+
+    ///Like load_bar, with use_database skipping the datafeed.
+    pub fn load_bar_from(&self, days: i64, interval: Interval, use_database: bool) -> Vec<BarData> {
+        let v_table = self.v_table();
+        let vt_symbol = to_c_string(&self.vt_symbol);
+        unsafe {
+            let vec = (v_table.abi_load_bar_sized)(
+                self.handle,
+                vt_symbol.as_ptr(),
+                days,
+                interval.kind(),
+                interval.size(),
+                use_database,
+            );
+            let bars = (*vec).clone();
+            (v_table.abi_drop_vec_bar_data)(vec);
+            bars
+        }
+    }
+
+    ///Send an order, returns the vt_orderids. Nothing is sent until the strategy is trading,
+    ///price is rounded to the price tick so that the engine accepts it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn send_order(
+        &self,
+        direction: Direction,
+        offset: Offset,
+        price: f64,
+        volume: f64,
+        stop: bool,
+        lock: bool,
+        net: bool,
+    ) -> Vec<String> {
+        if !self.trading {
+            return Vec::new();
+        }
+
+        let pricetick = self.get_pricetick();
+        let price = if pricetick > 0.0 {
+            round_to(price, pricetick)
+        } else {
+            price
+        };
+
+        let v_table = self.v_table();
+        unsafe {
+            let vec = (v_table.abi_send_order_with_reference)(
+                self.handle,
+                self.strategy,
+                direction,
+                offset,
+                price,
+                volume,
+                stop,
+                lock,
+                net,
+                self.reference.as_ptr(),
+            );
+            let vt_orderids = (*vec).clone();
+            (v_table.abi_drop_vec_string)(vec);
+            vt_orderids
+        }
+    }
+
+    ///Send an entry limit order to open, each trade of it is closed by a take profit limit
+    ///order and a stop loss stop order sent by the engine, filling either one cancels the
+    ///other. Returns the vt_orderids of the entry.
+    #[allow(clippy::too_many_arguments)]
+    pub fn send_bracket_order(
+        &self,
+        direction: Direction,
+        price: f64,
+        volume: f64,
+        take_profit: f64,
+        stop_loss: f64,
+        lock: bool,
+        net: bool,
+    ) -> Vec<String> {
+        if !self.trading {
+            return Vec::new();
+        }
+
+        let pricetick = self.get_pricetick();
+        let round = |price: f64| {
+            if pricetick > 0.0 {
+                round_to(price, pricetick)
+            } else {
+                price
+            }
+        };
+
+        let v_table = self.v_table();
+        unsafe {
+            let vec = (v_table.abi_send_bracket_order)(
+                self.handle,
+                self.strategy,
+                direction,
+                round(price),
+                volume,
+                round(take_profit),
+                round(stop_loss),
+                lock,
+                net,
+                self.reference.as_ptr(),
+            );
+            let vt_orderids = (*vec).clone();
+            (v_table.abi_drop_vec_string)(vec);
+            vt_orderids
+        }
+    }
+
+    pub fn buy(&self, price: f64, volume: f64, stop: bool) -> Vec<String> {
+        self.send_order(
+            Direction::LONG,
+            Offset::OPEN,
+            price,
+            volume,
+            stop,
+            false,
+            false,
+        )
+    }
+
+    pub fn sell(&self, price: f64, volume: f64, stop: bool) -> Vec<String> {
+        self.send_order(
+            Direction::SHORT,
+            Offset::CLOSE,
+            price,
+            volume,
+            stop,
+            false,
+            false,
+        )
+    }
+
+    pub fn short(&self, price: f64, volume: f64, stop: bool) -> Vec<String> {
+        self.send_order(
+            Direction::SHORT,
+            Offset::OPEN,
+            price,
+            volume,
+            stop,
+            false,
+            false,
+        )
+    }
+
+    pub fn cover(&self, price: f64, volume: f64, stop: bool) -> Vec<String> {
+        self.send_order(
+            Direction::LONG,
+            Offset::CLOSE,
+            price,
+            volume,
+            stop,
+            false,
+            false,
+        )
+    }
+
+    ///Cancel a limit or stop order, e.g. to re-peg it to a new price.
+    pub fn cancel_order(&self, vt_orderid: &str) {
+        if self.trading {
+            let vt_orderid = to_c_string(vt_orderid);
+            unsafe {
+                (self.v_table().abi_cancel_order)(self.handle, self.strategy, vt_orderid.as_ptr());
+            }
+        }
+    }
+
+    pub fn cancel_all(&self) {
+        if self.trading {
+            (self.v_table().abi_cancel_all)(self.handle, self.strategy);
+        }
+    }
+
+    ///Cancel a resting order, limit or stop, once the trading day it was sent on is over.
+    pub fn set_day_order(&self, vt_orderid: &str) {
+        let vt_orderid = to_c_string(vt_orderid);
+        unsafe {
+            (self.v_table().abi_set_expiry)(
+                self.handle,
+                self.strategy,
+                vt_orderid.as_ptr(),
+                TimeInForce::DAY,
+                0,
+            );
+        }
+    }
+
+    ///Cancel a resting order, limit or stop, at the first bar at or after expiry.
+    pub fn set_expiry(&self, vt_orderid: &str, expiry: NaiveDateTime) {
+        let vt_orderid = to_c_string(vt_orderid);
+        unsafe {
+            (self.v_table().abi_set_expiry)(
+                self.handle,
+                self.strategy,
+                vt_orderid.as_ptr(),
+                TimeInForce::GTD,
+                expiry.and_utc().timestamp_millis(),
+            );
+        }
+    }
+
+    ///Position booked by the engine, same as pos once the engine has mirrored it.
+    pub fn get_pos(&self) -> f64 {
+        (self.v_table().abi_get_pos)(self.handle, self.strategy)
+    }
+
+    pub fn get_position_detail(&self) -> PositionDetail {
+        (self.v_table().abi_get_position_detail)(self.handle, self.strategy)
+    }
+
+    pub fn get_pricetick(&self) -> f64 {
+        (self.v_table().abi_get_pricetick)(self.handle, self.strategy)
+    }
+
+    ///Contract size, the value of one point of price per unit of volume.
+    pub fn get_size(&self) -> f64 {
+        (self.v_table().abi_get_size)(self.handle, self.strategy)
+    }
+
+    ///Whole volume losing at most risk when the price moves stop_distance, e.g. 2 ATR, against
+    ///it, sized with the contract size.
+    pub fn atr_position_size(&self, risk: f64, stop_distance: f64) -> f64 {
+        atr_position_size(risk, stop_distance, self.get_size())
+    }
+
+    ///Whether the strategy runs in a backtest or live, e.g. to skip waiting on real cancels.
+    pub fn get_engine_type(&self) -> EngineType {
+        (self.v_table().abi_get_engine_type)(self.handle)
+    }
+
+    ///Latest status of a stop order, its vt_orderids are the orders sent once triggered.
+    pub fn get_stop_order(&self, stop_orderid: &str) -> Option<StopOrder> {
+        let v_table = self.v_table();
+        let stop_orderid = to_c_string(stop_orderid);
+        unsafe {
+            let ptr =
+                (v_table.abi_get_stop_order)(self.handle, self.strategy, stop_orderid.as_ptr());
+            if ptr.is_null() {
+                return None;
+            }
+            let stop_order = (*ptr).clone();
+            (v_table.abi_drop_stop_order)(ptr);
+            Some(stop_order)
+        }
+    }
+
+    pub fn get_order(&self, vt_orderid: &str) -> Option<OrderData> {
+        let v_table = self.v_table();
+        let vt_orderid = to_c_string(vt_orderid);
+        unsafe {
+            let ptr = (v_table.abi_get_order)(self.handle, self.strategy, vt_orderid.as_ptr());
+            if ptr.is_null() {
+                return None;
+            }
+            let order = (*ptr).clone();
+            (v_table.abi_drop_order_data)(ptr);
+            Some(order)
+        }
+    }
+
+    ///Pricetick, size, min_volume and product of a contract, e.g. to size orders and round
+    ///prices of other symbols than the traded one, which an empty vt_symbol stands for. None
+    ///if the engine doesn't know the contract.
+    pub fn get_contract(&self, vt_symbol: &str) -> Option<ContractData> {
+        let v_table = self.v_table();
+        let vt_symbol = to_c_string(vt_symbol);
+        unsafe {
+            let ptr = (v_table.abi_get_contract)(self.handle, vt_symbol.as_ptr());
+            if ptr.is_null() {
+                return None;
+            }
+            let contract = (*ptr).clone();
+            (v_table.abi_drop_contract_data)(ptr);
+            Some(contract)
+        }
+    }
+
+    ///Trades of an order, in trade sequence.
+    pub fn get_trades_by_order(&self, vt_orderid: &str) -> Vec<TradeData> {
+        let v_table = self.v_table();
+        let vt_orderid = to_c_string(vt_orderid);
+        unsafe {
+            let vec =
+                (v_table.abi_get_trades_by_order)(self.handle, self.strategy, vt_orderid.as_ptr());
+            let trades = (*vec).clone();
+            (v_table.abi_drop_vec_trade_data)(vec);
+            trades
+        }
+    }
+
+    ///Value of a signal or factor on the current bar, recorded with the forward returns when
+    ///the backtest runs in research mode and ignored otherwise.
+    pub fn emit_factor(&self, name: &str, value: f64) {
+        let name = to_c_string(name);
+        unsafe {
+            (self.v_table().abi_emit_factor)(self.handle, self.strategy, name.as_ptr(), value);
+        }
+    }
+}
+
+impl TargetPosGateway for StrategyContext {
+    fn pos(&self) -> f64 {
+        self.pos
+    }
+
+    fn pricetick(&self) -> f64 {
+        self.get_pricetick()
+    }
+
+    fn send_order(
+        &mut self,
+        direction: Direction,
+        offset: Offset,
+        price: f64,
+        volume: f64,
+    ) -> Vec<String> {
+        StrategyContext::send_order(self, direction, offset, price, volume, false, false, false)
+    }
+
+    fn cancel_order(&mut self, vt_orderid: &str) {
+        StrategyContext::cancel_order(self, vt_orderid);
+    }
+
+    fn is_active(&self, vt_orderid: &str) -> bool {
+        self.get_order(vt_orderid)
+            .is_some_and(|order| order.is_active())
+    }
+}
+
+///Strategy instance behind the *mut CtaTemplate handed to the engine.
+pub struct StrategyInstance<S> {
+    context: StrategyContext,
+    strategy: S,
+    ///Last string returned by abi_get_variables, valid until the next call.
+    variables: CString,
+}
+
+///Functions called by the exports of export_strategy!, not meant to be called directly.
+///
+///# Safety
+///Pointers are the ones the engine passes to the `abi_*` exports of a strategy of type S.
+#[doc(hidden)]
+pub mod abi {
+    #![allow(clippy::missing_safety_doc)]
+    use super::*;
+
+    unsafe fn instance<'a, S>(strategy: *mut CtaTemplate) -> &'a mut StrategyInstance<S> {
+        &mut *(strategy as *mut StrategyInstance<S>)
+    }
+
+    pub unsafe fn new<S: CtaStrategy>(
+        cta_engine: *const VTable,
+        strategy_name: *const c_char,
+        vt_symbol: *const c_char,
+        setting: *const c_char,
+    ) -> *mut CtaTemplate {
+        let setting = parse_setting(&from_c_str(setting));
+        new_with(
+            cta_engine,
+            &from_c_str(strategy_name),
+            &from_c_str(vt_symbol),
+            |context| S::new(context, &setting),
+        )
+    }
+
+    ///Instance of a strategy made by build instead of S::new, e.g. around a value the host
+    ///already holds.
+    pub unsafe fn new_with<S: CtaStrategy>(
+        cta_engine: *const VTable,
+        strategy_name: &str,
+        vt_symbol: &str,
+        build: impl FnOnce(&StrategyContext) -> S,
+    ) -> *mut CtaTemplate {
+        let context = StrategyContext::new(cta_engine, strategy_name, vt_symbol);
+        let strategy = build(&context);
+        let instance = Box::into_raw(Box::new(StrategyInstance {
+            context,
+            strategy,
+            variables: CString::default(),
+        }));
+        (*instance).context.strategy = instance as *mut CtaTemplate;
+        instance as *mut CtaTemplate
+    }
+
+    ///Start the instance over with a new context, false if the strategy can't be reset.
+    pub unsafe fn reset<S: CtaStrategy>(
+        strategy: *mut CtaTemplate,
+        cta_engine: *const VTable,
+        strategy_name: *const c_char,
+        vt_symbol: *const c_char,
+        setting: *const c_char,
+    ) -> bool {
+        let setting = parse_setting(&from_c_str(setting));
+        let instance = instance::<S>(strategy);
+        instance.context = StrategyContext::new(
+            cta_engine,
+            &from_c_str(strategy_name),
+            &from_c_str(vt_symbol),
+        );
+        instance.context.strategy = strategy;
+        instance.variables = CString::default();
+        instance.strategy.reset(&instance.context, &setting)
+    }
+
+    pub unsafe fn drop<S: CtaStrategy>(strategy: *mut CtaTemplate) {
+        std::mem::drop(Box::from_raw(strategy as *mut StrategyInstance<S>));
+    }
+
+    pub unsafe fn on_init<S: CtaStrategy>(strategy: *mut CtaTemplate, handle: usize) {
+        let instance = instance::<S>(strategy);
+        instance.context.handle = handle;
+        instance.strategy.on_init(&mut instance.context);
+    }
+
+    pub unsafe fn on_start<S: CtaStrategy>(strategy: *mut CtaTemplate) {
+        let instance = instance::<S>(strategy);
+        instance.strategy.on_start(&mut instance.context);
+    }
+
+    pub unsafe fn on_stop<S: CtaStrategy>(strategy: *mut CtaTemplate) {
+        let instance = instance::<S>(strategy);
+        instance.strategy.on_stop(&mut instance.context);
+    }
+
+    pub unsafe fn on_tick<S: CtaStrategy>(strategy: *mut CtaTemplate, tick: *const TickData) {
+        let instance = instance::<S>(strategy);
+        instance.context.tick_features.update_tick(&*tick);
+        instance.strategy.on_tick(&mut instance.context, &*tick);
+    }
+
+    pub unsafe fn on_bar<S: CtaStrategy>(strategy: *mut CtaTemplate, bar: *const BarData) {
+        let instance = instance::<S>(strategy);
+        instance.strategy.on_bar(&mut instance.context, &*bar);
+    }
+
+    pub unsafe fn on_order<S: CtaStrategy>(strategy: *mut CtaTemplate, order: *const OrderData) {
+        let instance = instance::<S>(strategy);
+        instance.strategy.on_order(&mut instance.context, &*order);
+    }
+
+    pub unsafe fn on_trade<S: CtaStrategy>(strategy: *mut CtaTemplate, trade: *const TradeData) {
+        let instance = instance::<S>(strategy);
+        instance.strategy.on_trade(&mut instance.context, &*trade);
+    }
+
+    pub unsafe fn on_stop_order<S: CtaStrategy>(
+        strategy: *mut CtaTemplate,
+        stop_order: *const StopOrder,
+    ) {
+        let instance = instance::<S>(strategy);
+        instance
+            .strategy
+            .on_stop_order(&mut instance.context, &*stop_order);
+    }
+
+    pub unsafe fn on_timer<S: CtaStrategy>(strategy: *mut CtaTemplate) {
+        let instance = instance::<S>(strategy);
+        instance.strategy.on_timer(&mut instance.context);
+    }
+
+    pub unsafe fn get_inited_mut<S: CtaStrategy>(strategy: *mut CtaTemplate) -> *mut bool {
+        &mut instance::<S>(strategy).context.inited
+    }
+
+    pub unsafe fn get_trading_mut<S: CtaStrategy>(strategy: *mut CtaTemplate) -> *mut bool {
+        &mut instance::<S>(strategy).context.trading
+    }
+
+    pub unsafe fn get_pos_mut<S: CtaStrategy>(strategy: *mut CtaTemplate) -> *mut f64 {
+        &mut instance::<S>(strategy).context.pos
+    }
+
+    pub fn get_parameters<S: CtaStrategy>() -> CString {
+        to_c_string(&format_setting(&S::parameters()))
+    }
+
+    pub unsafe fn get_variables<S: CtaStrategy>(strategy: *mut CtaTemplate) -> *const c_char {
+        let instance = instance::<S>(strategy);
+        instance.variables = to_c_string(&format_setting(&instance.strategy.variables()));
+        instance.variables.as_ptr()
+    }
+
+    pub unsafe fn set_variables<S: CtaStrategy>(
+        strategy: *mut CtaTemplate,
+        variables: *const c_char,
+    ) {
+        let variables = parse_setting(&from_c_str(variables));
+        instance::<S>(strategy).strategy.set_variables(&variables);
+    }
+
+    pub unsafe fn update_setting<S: CtaStrategy>(
+        strategy: *mut CtaTemplate,
+        setting: *const c_char,
+    ) -> bool {
+        let setting = parse_setting(&from_c_str(setting));
+        let instance = instance::<S>(strategy);
+        instance
+            .strategy
+            .update_setting(&mut instance.context, &setting)
+    }
+}
+
+///Class of a strategy type built into the host, run by the engine through the same callbacks
+///export_strategy! exports from a dylib.
+#[cfg(feature = "dylib-strategies")]
+pub fn strategy_class<S: CtaStrategy>(name: &str) -> ExternClass {
+    use std::any::TypeId;
+    use std::collections::HashMap;
+    use std::sync::{Mutex, OnceLock};
+
+    extern "C" fn new<S: CtaStrategy>(
+        cta_engine: *const VTable,
+        strategy_name: *const c_char,
+        vt_symbol: *const c_char,
+        setting: *const c_char,
+    ) -> *mut CtaTemplate {
+        unsafe { abi::new::<S>(cta_engine, strategy_name, vt_symbol, setting) }
+    }
+    extern "C" fn drop<S: CtaStrategy>(strategy: *mut CtaTemplate) {
+        unsafe { abi::drop::<S>(strategy) }
+    }
+    extern "C" fn on_init<S: CtaStrategy>(strategy: *mut CtaTemplate, handle: usize) {
+        unsafe { abi::on_init::<S>(strategy, handle) }
+    }
+    extern "C" fn on_start<S: CtaStrategy>(strategy: *mut CtaTemplate) {
+        unsafe { abi::on_start::<S>(strategy) }
+    }
+    extern "C" fn on_stop<S: CtaStrategy>(strategy: *mut CtaTemplate) {
+        unsafe { abi::on_stop::<S>(strategy) }
+    }
+    extern "C" fn on_tick<S: CtaStrategy>(strategy: *mut CtaTemplate, tick: *const TickData) {
+        unsafe { abi::on_tick::<S>(strategy, tick) }
+    }
+    extern "C" fn on_bar<S: CtaStrategy>(strategy: *mut CtaTemplate, bar: *const BarData) {
+        unsafe { abi::on_bar::<S>(strategy, bar) }
+    }
+    extern "C" fn on_order<S: CtaStrategy>(strategy: *mut CtaTemplate, order: *const OrderData) {
+        unsafe { abi::on_order::<S>(strategy, order) }
+    }
+    extern "C" fn on_trade<S: CtaStrategy>(strategy: *mut CtaTemplate, trade: *const TradeData) {
+        unsafe { abi::on_trade::<S>(strategy, trade) }
+    }
+    extern "C" fn on_stop_order<S: CtaStrategy>(
+        strategy: *mut CtaTemplate,
+        stop_order: *const StopOrder,
+    ) {
+        unsafe { abi::on_stop_order::<S>(strategy, stop_order) }
+    }
+    extern "C" fn on_timer<S: CtaStrategy>(strategy: *mut CtaTemplate) {
+        unsafe { abi::on_timer::<S>(strategy) }
+    }
+    extern "C" fn get_inited_mut<S: CtaStrategy>(strategy: *mut CtaTemplate) -> *mut bool {
+        unsafe { abi::get_inited_mut::<S>(strategy) }
+    }
+    extern "C" fn get_trading_mut<S: CtaStrategy>(strategy: *mut CtaTemplate) -> *mut bool {
+        unsafe { abi::get_trading_mut::<S>(strategy) }
+    }
+    extern "C" fn get_pos_mut<S: CtaStrategy>(strategy: *mut CtaTemplate) -> *mut f64 {
+        unsafe { abi::get_pos_mut::<S>(strategy) }
+    }
+    extern "C" fn get_variables<S: CtaStrategy>(strategy: *mut CtaTemplate) -> *const c_char {
+        unsafe { abi::get_variables::<S>(strategy) }
+    }
+    extern "C" fn set_variables<S: CtaStrategy>(
+        strategy: *mut CtaTemplate,
+        variables: *const c_char,
+    ) {
+        unsafe { abi::set_variables::<S>(strategy, variables) }
+    }
+    extern "C" fn update_setting<S: CtaStrategy>(
+        strategy: *mut CtaTemplate,
+        setting: *const c_char,
+    ) -> bool {
+        unsafe { abi::update_setting::<S>(strategy, setting) }
+    }
+    extern "C" fn reset<S: CtaStrategy>(
+        strategy: *mut CtaTemplate,
+        cta_engine: *const VTable,
+        strategy_name: *const c_char,
+        vt_symbol: *const c_char,
+        setting: *const c_char,
+    ) -> bool {
+        unsafe { abi::reset::<S>(strategy, cta_engine, strategy_name, vt_symbol, setting) }
+    }
+    extern "C" fn get_parameters<S: CtaStrategy>() -> *const c_char {
+        // Statics are shared by all S, so the strings are kept by type
+        static PARAMETERS: OnceLock<Mutex<HashMap<TypeId, CString>>> = OnceLock::new();
+        let mut parameters = PARAMETERS.get_or_init(Default::default).lock().unwrap();
+        parameters
+            .entry(TypeId::of::<S>())
+            .or_insert_with(abi::get_parameters::<S>)
+            .as_ptr()
+    }
+
+    let mut class = ExternClass::default();
+    class.filename = name.into();
+    class.func_new = Some(new::<S>);
+    class.func_drop = Some(drop::<S>);
+    class.func_on_init = Some(on_init::<S>);
+    class.func_on_start = Some(on_start::<S>);
+    class.func_on_stop = Some(on_stop::<S>);
+    class.func_on_tick = Some(on_tick::<S>);
+    class.func_on_bar = Some(on_bar::<S>);
+    class.func_on_order = Some(on_order::<S>);
+    class.func_on_trade = Some(on_trade::<S>);
+    class.func_on_stop_order = Some(on_stop_order::<S>);
+    class.func_on_timer = Some(on_timer::<S>);
+    class.func_get_inited_mut = Some(get_inited_mut::<S>);
+    class.func_get_trading_mut = Some(get_trading_mut::<S>);
+    class.func_get_pos_mut = Some(get_pos_mut::<S>);
+    class.func_get_variables = Some(get_variables::<S>);
+    class.func_set_variables = Some(set_variables::<S>);
+    class.func_update_setting = Some(update_setting::<S>);
+    class.func_reset = Some(reset::<S>);
+    class.func_get_parameters = Some(get_parameters::<S>);
+    class
+}
+
+///Export a CtaStrategy type as the `abi_*` functions of a strategy dylib, once per crate.
+#[macro_export]
+macro_rules! export_strategy {
+    ($strategy:ty) => {
+        const _: () = {
+            use std::ffi::{c_char, CString};
+            use std::sync::OnceLock;
+            use $crate::vnrs::trader::object::{BarData, OrderData, TickData, TradeData};
+            use $crate::vnrs_ctastrategy::base::{StopOrder, VTable};
+            use $crate::vnrs_ctastrategy::sdk::abi;
+            use $crate::vnrs_ctastrategy::template::CtaTemplate;
+
+            #[no_mangle]
+            pub extern "C" fn abi_new(
+                cta_engine: *const VTable,
+                strategy_name: *const c_char,
+                vt_symbol: *const c_char,
+                setting: *const c_char,
+            ) -> *mut CtaTemplate {
+                unsafe { abi::new::<$strategy>(cta_engine, strategy_name, vt_symbol, setting) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_drop(strategy: *mut CtaTemplate) {
+                unsafe { abi::drop::<$strategy>(strategy) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_on_init(strategy: *mut CtaTemplate, handle: usize) {
+                unsafe { abi::on_init::<$strategy>(strategy, handle) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_on_start(strategy: *mut CtaTemplate) {
+                unsafe { abi::on_start::<$strategy>(strategy) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_on_stop(strategy: *mut CtaTemplate) {
+                unsafe { abi::on_stop::<$strategy>(strategy) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_on_tick(strategy: *mut CtaTemplate, tick: *const TickData) {
+                unsafe { abi::on_tick::<$strategy>(strategy, tick) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_on_bar(strategy: *mut CtaTemplate, bar: *const BarData) {
+                unsafe { abi::on_bar::<$strategy>(strategy, bar) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_on_order(strategy: *mut CtaTemplate, order: *const OrderData) {
+                unsafe { abi::on_order::<$strategy>(strategy, order) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_on_trade(strategy: *mut CtaTemplate, trade: *const TradeData) {
+                unsafe { abi::on_trade::<$strategy>(strategy, trade) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_on_stop_order(
+                strategy: *mut CtaTemplate,
+                stop_order: *const StopOrder,
+            ) {
+                unsafe { abi::on_stop_order::<$strategy>(strategy, stop_order) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_on_timer(strategy: *mut CtaTemplate) {
+                unsafe { abi::on_timer::<$strategy>(strategy) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_get_inited_mut(strategy: *mut CtaTemplate) -> *mut bool {
+                unsafe { abi::get_inited_mut::<$strategy>(strategy) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_get_trading_mut(strategy: *mut CtaTemplate) -> *mut bool {
+                unsafe { abi::get_trading_mut::<$strategy>(strategy) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_get_pos_mut(strategy: *mut CtaTemplate) -> *mut f64 {
+                unsafe { abi::get_pos_mut::<$strategy>(strategy) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_get_parameters() -> *const c_char {
+                static PARAMETERS: OnceLock<CString> = OnceLock::new();
+                PARAMETERS
+                    .get_or_init(abi::get_parameters::<$strategy>)
+                    .as_ptr()
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_get_variables(strategy: *mut CtaTemplate) -> *const c_char {
+                unsafe { abi::get_variables::<$strategy>(strategy) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_set_variables(
+                strategy: *mut CtaTemplate,
+                variables: *const c_char,
+            ) {
+                unsafe { abi::set_variables::<$strategy>(strategy, variables) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_update_setting(
+                strategy: *mut CtaTemplate,
+                setting: *const c_char,
+            ) -> bool {
+                unsafe { abi::update_setting::<$strategy>(strategy, setting) }
+            }
+
+            #[no_mangle]
+            pub extern "C" fn abi_reset(
+                strategy: *mut CtaTemplate,
+                cta_engine: *const VTable,
+                strategy_name: *const c_char,
+                vt_symbol: *const c_char,
+                setting: *const c_char,
+            ) -> bool {
+                unsafe {
+                    abi::reset::<$strategy>(strategy, cta_engine, strategy_name, vt_symbol, setting)
+                }
+            }
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnrs::trader::constant::IntervalKind;
+    use std::cell::RefCell;
+
+    thread_local! {
+        ///Engine calls received by the mock VTable, in order.
+        static CALLS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record(call: String) {
+        CALLS.with(|calls| calls.borrow_mut().push(call));
+    }
+
+    fn calls() -> Vec<String> {
+        CALLS.with(|calls| calls.borrow_mut().drain(..).collect())
+    }
+
+    extern "C" fn load_bar(
+        _: usize,
+        _: *const c_char,
+        _: i64,
+        _: IntervalKind,
+        _: bool,
+    ) -> *mut Vec<BarData> {
+        Box::into_raw(Box::default())
+    }
+    extern "C" fn drop_vec_bar_data(vec: *mut Vec<BarData>) {
+        record("drop bars".into());
+        unsafe { std::mem::drop(Box::from_raw(vec)) }
+    }
+    #[allow(clippy::too_many_arguments)]
+    extern "C" fn send_order(
+        _: usize,
+        _: *mut CtaTemplate,
+        _: Direction,
+        _: Offset,
+        _: f64,
+        _: f64,
+        _: bool,
+        _: bool,
+        _: bool,
+    ) -> *mut Vec<String> {
+        Box::into_raw(Box::default())
+    }
+    extern "C" fn drop_vec_string(vec: *mut Vec<String>) {
+        record("drop orderids".into());
+        unsafe { std::mem::drop(Box::from_raw(vec)) }
+    }
+    extern "C" fn cancel_all(_: usize, _: *mut CtaTemplate) {
+        record("cancel_all".into());
+    }
+    extern "C" fn get_pos(_: usize, _: *mut CtaTemplate) -> f64 {
+        2.0
+    }
+    extern "C" fn get_position_detail(_: usize, _: *mut CtaTemplate) -> PositionDetail {
+        PositionDetail::default()
+    }
+    extern "C" fn get_pricetick(_: usize, _: *mut CtaTemplate) -> f64 {
+        0.5
+    }
+    extern "C" fn get_size(_: usize, _: *mut CtaTemplate) -> f64 {
+        10.0
+    }
+    extern "C" fn get_engine_type(_: usize) -> EngineType {
+        EngineType::BACKTESTING
+    }
+    extern "C" fn get_stop_order(
+        _: usize,
+        _: *mut CtaTemplate,
+        _: *const c_char,
+    ) -> *mut StopOrder {
+        std::ptr::null_mut()
+    }
+    extern "C" fn drop_stop_order(_: *mut StopOrder) {}
+    extern "C" fn get_order(
+        _: usize,
+        _: *mut CtaTemplate,
+        vt_orderid: *const c_char,
+    ) -> *mut OrderData {
+        let vt_orderid = unsafe { from_c_str(vt_orderid) };
+        match vt_orderid.split_once('.') {
+            Some(("MOCK", orderid)) => Box::into_raw(Box::new(OrderData {
+                orderid: orderid.into(),
+                ..Default::default()
+            })),
+            _ => std::ptr::null_mut(),
+        }
+    }
+    extern "C" fn drop_order_data(order: *mut OrderData) {
+        record("drop order".into());
+        unsafe { std::mem::drop(Box::from_raw(order)) }
+    }
+    extern "C" fn get_trades_by_order(
+        _: usize,
+        _: *mut CtaTemplate,
+        _: *const c_char,
+    ) -> *mut Vec<TradeData> {
+        Box::into_raw(Box::default())
+    }
+    extern "C" fn drop_vec_trade_data(vec: *mut Vec<TradeData>) {
+        unsafe { std::mem::drop(Box::from_raw(vec)) }
+    }
+    extern "C" fn emit_factor(_: usize, _: *mut CtaTemplate, name: *const c_char, value: f64) {
+        record(format!("factor {}={}", unsafe { from_c_str(name) }, value));
+    }
+    extern "C" fn get_contract(_: usize, _: *const c_char) -> *mut ContractData {
+        std::ptr::null_mut()
+    }
+    extern "C" fn drop_contract_data(_: *mut ContractData) {}
+    #[allow(clippy::too_many_arguments)]
+    extern "C" fn send_bracket_order(
+        _: usize,
+        _: *mut CtaTemplate,
+        _: Direction,
+        _: f64,
+        _: f64,
+        _: f64,
+        _: f64,
+        _: bool,
+        _: bool,
+        _: *const c_char,
+    ) -> *mut Vec<String> {
+        Box::into_raw(Box::default())
+    }
+    extern "C" fn cancel_order(_: usize, _: *mut CtaTemplate, vt_orderid: *const c_char) {
+        record(format!("cancel {}", unsafe { from_c_str(vt_orderid) }));
+    }
+    extern "C" fn set_expiry(
+        _: usize,
+        _: *mut CtaTemplate,
+        vt_orderid: *const c_char,
+        time_in_force: TimeInForce,
+        expiry: i64,
+    ) {
+        let vt_orderid = unsafe { from_c_str(vt_orderid) };
+        record(format!(
+            "expiry {} {:?} {}",
+            vt_orderid, time_in_force, expiry
+        ));
+    }
+    extern "C" fn load_bar_callback(
+        _: usize,
+        _: *const c_char,
+        _: i64,
+        _: IntervalKind,
+        _: u32,
+        _: bool,
+    ) {
+    }
+    #[allow(clippy::too_many_arguments)]
+    extern "C" fn send_order_with_reference(
+        handle: usize,
+        _: *mut CtaTemplate,
+        direction: Direction,
+        offset: Offset,
+        price: f64,
+        volume: f64,
+        _: bool,
+        _: bool,
+        _: bool,
+        reference: *const c_char,
+    ) -> *mut Vec<String> {
+        assert_eq!(handle, &V_TABLE as *const VTable as usize);
+        let reference = unsafe { from_c_str(reference) };
+        record(format!(
+            "send {:?} {:?} {} {} {}",
+            direction, offset, price, volume, reference
+        ));
+        Box::into_raw(Box::new(vec!["MOCK.1".to_string()]))
+    }
+    extern "C" fn load_bar_sized(
+        _: usize,
+        vt_symbol: *const c_char,
+        days: i64,
+        kind: IntervalKind,
+        size: u32,
+        use_database: bool,
+    ) -> *mut Vec<BarData> {
+        let vt_symbol = unsafe { from_c_str(vt_symbol) };
+        record(format!(
+            "load {} {} {:?} {} {}",
+            vt_symbol, days, kind, size, use_database
+        ));
+        let interval = Interval::from_kind(kind, size).unwrap_or_default();
+        Box::into_raw(Box::new(vec![
+            BarData {
+                interval,
+                close_price: 3500.0,
+                ..Default::default()
+            };
+            2
+        ]))
+    }
+
+    static V_TABLE: VTable = VTable {
+        abi_load_bar: load_bar,
+        abi_drop_vec_bar_data: drop_vec_bar_data,
+        abi_send_order: send_order,
+        abi_drop_vec_string: drop_vec_string,
+        abi_cancel_all: cancel_all,
+        abi_get_pos: get_pos,
+        abi_get_position_detail: get_position_detail,
+        abi_get_pricetick: get_pricetick,
+        abi_get_size: get_size,
+        abi_get_engine_type: get_engine_type,
+        abi_get_stop_order: get_stop_order,
+        abi_drop_stop_order: drop_stop_order,
+        abi_get_order: get_order,
+        abi_drop_order_data: drop_order_data,
+        abi_get_trades_by_order: get_trades_by_order,
+        abi_drop_vec_trade_data: drop_vec_trade_data,
+        abi_emit_factor: emit_factor,
+        abi_get_contract: get_contract,
+        abi_drop_contract_data: drop_contract_data,
+        abi_send_bracket_order: send_bracket_order,
+        abi_cancel_order: cancel_order,
+        abi_set_expiry: set_expiry,
+        abi_load_bar_callback: load_bar_callback,
+        abi_send_order_with_reference: send_order_with_reference,
+        abi_load_bar_sized: load_bar_sized,
+    };
+
+    #[test]
+    fn c_strings() {
+        assert_eq!(to_c_string("rb2410.SHFE").to_str().unwrap(), "rb2410.SHFE");
+        // Interior NUL is dropped rather than truncating the string
+        assert_eq!(
+            to_c_string("rb\x002410\x00.SHFE").to_str().unwrap(),
+            "rb2410.SHFE"
+        );
+
+        let c_string = to_c_string("fast_window:10");
+        assert_eq!(unsafe { from_c_str(c_string.as_ptr()) }, "fast_window:10");
+        assert_eq!(unsafe { from_c_str(std::ptr::null()) }, "");
+    }
+
+    #[test]
+    fn settings() {
+        let setting = parse_setting(" fast_window: 10,slow_window:20,,fixed_size,name:a:b");
+        let expected = [
+            ("fast_window", "10"),
+            ("slow_window", "20"),
+            ("name", "a:b"),
+        ];
+        assert_eq!(
+            setting,
+            expected.map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+        assert_eq!(parse_setting(&format_setting(&setting)), setting);
+        assert!(parse_setting("").is_empty());
+
+        assert_eq!(setting_value(&setting, "fast_window", 5), 10);
+        assert_eq!(setting_value(&setting, "name", 5), 5);
+        assert_eq!(setting_value(&setting, "missing", 1.5), 1.5);
+    }
+
+    #[test]
+    fn context_through_v_table() {
+        let mut context = StrategyContext::new(&V_TABLE, "probe", "rb2410.SHFE");
+        assert_eq!(context.handle, &V_TABLE as *const VTable as usize);
+
+        // Nothing reaches the engine until the strategy is trading
+        assert!(context.buy(3500.2, 1.0, false).is_empty());
+        context.cancel_order("MOCK.1");
+        context.cancel_all();
+        assert!(calls().is_empty());
+
+        context.trading = true;
+        context.set_reference("break\0out");
+        assert_eq!(context.short(3500.2, 2.0, false), ["MOCK.1"]);
+        assert!(context.is_active("MOCK.1"));
+        assert!(context.get_order("OTHER.1").is_none());
+        context.cancel_order("MOCK.1");
+        assert_eq!(
+            calls(),
+            [
+                "send SHORT OPEN 3500 2 breakout",
+                "drop orderids",
+                "drop order",
+                "cancel MOCK.1",
+            ]
+        );
+
+        let bars = context.load_bar_from(10, Interval::SECONDS(5), true);
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].interval, Interval::SECONDS(5));
+        assert_eq!(calls(), ["load rb2410.SHFE 10 SECONDS 5 true", "drop bars"]);
+
+        let expiry = NaiveDateTime::parse_from_str("2024-09-30 15:00", "%Y-%m-%d %H:%M").unwrap();
+        context.set_expiry("MOCK.1", expiry);
+        context.emit_factor("momentum", 0.25);
+        assert_eq!(
+            calls(),
+            [
+                format!("expiry MOCK.1 GTD {}", expiry.and_utc().timestamp_millis()),
+                "factor momentum=0.25".to_string(),
+            ]
+        );
+
+        assert_eq!(context.get_pos(), 2.0);
+        assert_eq!(context.atr_position_size(1000.0, 20.0), 5.0);
+        assert_eq!(context.get_engine_type(), EngineType::BACKTESTING);
+        assert!(context.get_stop_order("STOP.1").is_none());
+        assert!(context.get_trades_by_order("MOCK.1").is_empty());
+    }
+}
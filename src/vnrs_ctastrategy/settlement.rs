@@ -0,0 +1,82 @@
+/*!Daily settlement as on the statements of a futures broker: at the settlement time of each
+trading day the pnl of the day is locked, marked to the settlement price, and the margin of the
+position held overnight is taken from the balance. Settlements are turned on with
+BacktestingEngine::set_settlement and read back with get_settlements to reconcile day by day. */
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+use crate::vnrs::trader::object::BarData;
+
+///Price positions are marked to at settlement.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SettlementPrice {
+    ///Close of the last bar before the settlement.
+    Close,
+    ///Volume weighted average price of the day, as futures exchanges settle. Close of the
+    ///last bar if the bars have no turnover.
+    #[default]
+    Vwap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SettlementConfig {
+    ///Local time of the trading day the day is settled at, in the days of the
+    ///TradingDayBoundary of the engine.
+    pub time: NaiveTime,
+    pub price: SettlementPrice,
+    ///Margin required per unit of contract value, 0 without margin.
+    pub margin_ratio: f64,
+}
+
+impl Default for SettlementConfig {
+    fn default() -> Self {
+        SettlementConfig {
+            time: NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
+            price: SettlementPrice::Vwap,
+            margin_ratio: 0.0,
+        }
+    }
+}
+
+///Locked result of one trading day, a line of the daily statement.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settlement {
+    pub date: NaiveDate,
+    ///Time of the bar the day was settled on.
+    pub datetime: NaiveDateTime,
+    pub price: f64,
+    pub pos: f64,
+    ///Net pnl of the day marked to the settlement price.
+    pub net_pnl: f64,
+    ///Balance after the pnl of the day.
+    pub balance: f64,
+    ///Margin of the position held at the settlement price.
+    pub margin: f64,
+    ///Balance left after the margin, below 0 when the margin can't be met.
+    pub available: f64,
+}
+
+///Turnover and volume of the bars of a day so far, for its volume weighted price.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DayVwap {
+    turnover: f64,
+    volume: f64,
+}
+
+impl DayVwap {
+    pub fn update_bar(&mut self, bar: &BarData) {
+        if bar.volume > 0.0 && bar.turnover > 0.0 {
+            self.turnover += bar.turnover;
+            self.volume += bar.volume;
+        }
+    }
+
+    ///Average price of the day, turnover counting size units of the contract per volume.
+    pub fn price(&self, size: f64) -> Option<f64> {
+        (self.volume > 0.0 && size > 0.0).then(|| self.turnover / (self.volume * size))
+    }
+
+    pub fn clear(&mut self) {
+        *self = DayVwap::default();
+    }
+}
@@ -0,0 +1,242 @@
+/*!Dead man's switch of the live engine: when market data stops coming in or a strategy stops
+beating its heartbeat for longer than a threshold, the working orders are cancelled and the
+positions optionally closed before anything worse happens unattended.
+
+A standalone component, the crate has no live engine or gateway yet: the Supervisor watches
+the EVENT_TICK events and heartbeats it is given and raises EVENT_CTA_SUPERVISOR events, the
+cancelling and closing is left to the engine running the strategies. */
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+use chrono::{NaiveDateTime, TimeDelta};
+use log::Level;
+
+use super::base::{EVENT_CTA_LOG, EVENT_CTA_SUPERVISOR};
+use super::schedule::TradingSchedule;
+use crate::vnrs::event::{Event, EventEngine, HandlerType};
+use crate::vnrs::trader::calendar::TradingCalendar;
+use crate::vnrs::trader::clock::Clock;
+use crate::vnrs::trader::event::{EVENT_TICK, EVENT_TIMER};
+use crate::vnrs::trader::object::{LogData, TickData};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlarmReason {
+    ///No tick since last.
+    MarketDataLost { last: NaiveDateTime },
+    ///No heartbeat of the strategy since last.
+    StrategyStalled {
+        strategy_name: String,
+        last: NaiveDateTime,
+    },
+}
+
+///Data of an EVENT_CTA_SUPERVISOR event. The engine cancels all working orders through the
+///gateways and, if flatten, closes the positions of the strategies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SupervisorAlarm {
+    pub reason: AlarmReason,
+    pub flatten: bool,
+}
+
+///Watches market data and strategy heartbeats. Each alarm is raised once, and again only
+///after ticks or the heartbeat came back in the meantime.
+#[derive(Debug)]
+pub struct Supervisor {
+    pub data_timeout: TimeDelta,
+    pub heartbeat_timeout: TimeDelta,
+    pub flatten: bool,
+    ///Market data is only expected within the sessions, always without a schedule.
+    pub schedule: Option<TradingSchedule>,
+    pub calendar: TradingCalendar,
+    last_tick: Option<NaiveDateTime>,
+    heartbeats: HashMap<String, NaiveDateTime>,
+    ///Reasons of the alarms raised and not yet recovered from.
+    raised: Vec<AlarmReason>,
+}
+
+impl Supervisor {
+    pub fn new(data_timeout: TimeDelta, heartbeat_timeout: TimeDelta, flatten: bool) -> Self {
+        Supervisor {
+            data_timeout,
+            heartbeat_timeout,
+            flatten,
+            schedule: None,
+            calendar: TradingCalendar::default(),
+            last_tick: None,
+            heartbeats: HashMap::new(),
+            raised: Vec::new(),
+        }
+    }
+
+    ///Market data arrived at now, checked from the first tick on.
+    pub fn update_tick(&mut self, now: NaiveDateTime) {
+        self.last_tick = Some(now);
+        self.raised
+            .retain(|reason| !matches!(reason, AlarmReason::MarketDataLost { .. }));
+    }
+
+    ///The strategy thread is alive at now, called e.g. after every callback it processed.
+    ///Strategies are checked from their first heartbeat on.
+    pub fn heartbeat(&mut self, strategy_name: &str, now: NaiveDateTime) {
+        self.heartbeats.insert(strategy_name.to_string(), now);
+        self.raised.retain(|reason| {
+            !matches!(reason, AlarmReason::StrategyStalled { strategy_name: name, .. }
+                if name == strategy_name)
+        });
+    }
+
+    ///Stop checking a strategy, e.g. when it is stopped on purpose.
+    pub fn remove_strategy(&mut self, strategy_name: &str) {
+        self.heartbeats.remove(strategy_name);
+    }
+
+    ///Alarms newly raised at now, market data first and then strategies by name.
+    pub fn check(&mut self, now: NaiveDateTime) -> Vec<SupervisorAlarm> {
+        let mut reasons = Vec::new();
+        let in_session = self
+            .schedule
+            .as_ref()
+            .is_none_or(|schedule| schedule.is_active(now, &self.calendar));
+        if let Some(last) = self.last_tick {
+            if in_session && now - last > self.data_timeout {
+                reasons.push(AlarmReason::MarketDataLost { last });
+            }
+        }
+        let mut stalled: Vec<(&String, &NaiveDateTime)> = self
+            .heartbeats
+            .iter()
+            .filter(|(_, last)| now - **last > self.heartbeat_timeout)
+            .collect();
+        stalled.sort();
+        for (strategy_name, last) in stalled {
+            reasons.push(AlarmReason::StrategyStalled {
+                strategy_name: strategy_name.clone(),
+                last: *last,
+            });
+        }
+
+        let mut alarms = Vec::new();
+        for reason in reasons {
+            let raised = self.raised.iter().any(|raised| match (raised, &reason) {
+                (AlarmReason::MarketDataLost { .. }, AlarmReason::MarketDataLost { .. }) => true,
+                (
+                    AlarmReason::StrategyStalled { strategy_name, .. },
+                    AlarmReason::StrategyStalled {
+                        strategy_name: name,
+                        ..
+                    },
+                ) => strategy_name == name,
+                _ => false,
+            });
+            if !raised {
+                self.raised.push(reason.clone());
+                alarms.push(SupervisorAlarm {
+                    reason,
+                    flatten: self.flatten,
+                });
+            }
+        }
+        alarms
+    }
+
+    ///Take ticks from EVENT_TICK events and check on every timer event, putting an error
+    ///EVENT_CTA_LOG, which reaches the notification channels listening to logs, and an
+    ///EVENT_CTA_SUPERVISOR event for each alarm. Returns the handler for unregistering.
+    pub fn register(
+        supervisor: Arc<Mutex<Self>>,
+        event_engine: &Arc<EventEngine>,
+        clock: Arc<dyn Clock>,
+    ) -> HandlerType {
+        let engine: Weak<EventEngine> = Arc::downgrade(event_engine);
+        let handler: HandlerType = Arc::new(move |event: &Event| {
+            let now = clock.now();
+            let mut supervisor = supervisor.lock().unwrap();
+            if event.data::<TickData>().is_some() {
+                supervisor.update_tick(now);
+                return;
+            }
+            let Some(event_engine) = engine.upgrade() else {
+                return;
+            };
+            for alarm in supervisor.check(now) {
+                let action = if alarm.flatten {
+                    "撤销全部委托并平仓"
+                } else {
+                    "撤销全部委托"
+                };
+                let msg = match &alarm.reason {
+                    AlarmReason::MarketDataLost { last } => {
+                        format!("行情中断，最后行情时间{}，{}", last, action)
+                    }
+                    AlarmReason::StrategyStalled {
+                        strategy_name,
+                        last,
+                    } => format!(
+                        "策略{}无响应，最后心跳时间{}，{}",
+                        strategy_name, last, action
+                    ),
+                };
+                let log = LogData::new(&msg, Level::Error, "Supervisor", now);
+                event_engine.put(Event::new(EVENT_CTA_LOG, log));
+                event_engine.put(Event::new(EVENT_CTA_SUPERVISOR, alarm));
+            }
+        });
+        event_engine.register(EVENT_TICK, handler.clone());
+        event_engine.register(EVENT_TIMER, handler.clone());
+        handler
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn alarms_on_silence() {
+        let mut supervisor = Supervisor::new(TimeDelta::seconds(30), TimeDelta::seconds(10), true);
+        // Nothing is checked before the first tick or heartbeat
+        assert!(supervisor.check(at("2024-10-08 09:30:00")).is_empty());
+
+        supervisor.update_tick(at("2024-10-08 09:30:00"));
+        supervisor.heartbeat("atr", at("2024-10-08 09:30:00"));
+        supervisor.heartbeat("boll", at("2024-10-08 09:30:05"));
+        assert!(supervisor.check(at("2024-10-08 09:30:10")).is_empty());
+
+        let alarms = supervisor.check(at("2024-10-08 09:30:12"));
+        assert_eq!(
+            alarms,
+            vec![SupervisorAlarm {
+                reason: AlarmReason::StrategyStalled {
+                    strategy_name: "atr".to_string(),
+                    last: at("2024-10-08 09:30:00"),
+                },
+                flatten: true,
+            }]
+        );
+        let alarms = supervisor.check(at("2024-10-08 09:30:31"));
+        let reasons: Vec<&AlarmReason> = alarms.iter().map(|alarm| &alarm.reason).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                &AlarmReason::MarketDataLost {
+                    last: at("2024-10-08 09:30:00")
+                },
+                &AlarmReason::StrategyStalled {
+                    strategy_name: "boll".to_string(),
+                    last: at("2024-10-08 09:30:05"),
+                }
+            ]
+        );
+        // Raised once until recovered
+        assert!(supervisor.check(at("2024-10-08 09:31:00")).is_empty());
+        supervisor.update_tick(at("2024-10-08 09:31:00"));
+        supervisor.heartbeat("atr", at("2024-10-08 09:31:00"));
+        supervisor.remove_strategy("boll");
+        assert!(supervisor.check(at("2024-10-08 09:31:05")).is_empty());
+        assert_eq!(supervisor.check(at("2024-10-08 09:31:31")).len(), 2);
+    }
+}
@@ -0,0 +1,154 @@
+/*!Execution of a target position, the way vn.py's TargetPosTemplate works: the strategy only
+sets the position it wants and a TargetPosExecutor works orders on every bar or tick until the
+position gets there. Orders left resting are cancelled and sent again at the new price, and an
+opposite position is closed before the new one is opened.
+
+The executor works against a TargetPosGateway, implemented by the StrategyContext of the SDK
+for strategy dylibs and by the BacktestingEngine for driving it from the host. */
+use crate::vnrs::trader::constant::{Direction, Offset};
+
+///Volume below which the position counts as at the target.
+const VOLUME_TOLERANCE: f64 = 1e-9;
+
+///Orders and position the executor works with.
+pub trait TargetPosGateway {
+    fn pos(&self) -> f64;
+
+    fn pricetick(&self) -> f64;
+
+    ///Send a limit order, returns the vt_orderids.
+    fn send_order(
+        &mut self,
+        direction: Direction,
+        offset: Offset,
+        price: f64,
+        volume: f64,
+    ) -> Vec<String>;
+
+    fn cancel_order(&mut self, vt_orderid: &str);
+
+    ///Whether the order may still trade.
+    fn is_active(&self, vt_orderid: &str) -> bool;
+}
+
+///Next order moving pos to target as (direction, offset, volume), closing an opposite
+///position first and opening the rest once it's flat. None at the target.
+pub fn next_target_order(pos: f64, target: f64) -> Option<(Direction, Offset, f64)> {
+    let change = target - pos;
+    if change.abs() <= VOLUME_TOLERANCE {
+        return None;
+    }
+    let direction = if change > 0.0 {
+        Direction::LONG
+    } else {
+        Direction::SHORT
+    };
+    // Closing reduces a position of the other side
+    if pos.abs() > VOLUME_TOLERANCE && (pos > 0.0) != (change > 0.0) {
+        Some((direction, Offset::CLOSE, change.abs().min(pos.abs())))
+    } else {
+        Some((direction, Offset::OPEN, change.abs()))
+    }
+}
+
+///Works orders toward a target position, see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct TargetPosExecutor {
+    target: f64,
+    ///Price ticks paid over the bar close or the other side of the tick to get filled.
+    payup: f64,
+    active_orderids: Vec<String>,
+}
+
+impl TargetPosExecutor {
+    pub fn new(payup: f64) -> Self {
+        TargetPosExecutor {
+            payup,
+            ..Default::default()
+        }
+    }
+
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    ///Position to work toward from the next bar or tick on.
+    pub fn set_target(&mut self, target: f64) {
+        self.target = target;
+    }
+
+    ///Orders sent and not finished when last checked.
+    pub fn active_orderids(&self) -> &[String] {
+        &self.active_orderids
+    }
+
+    ///Work orders at the close of a bar, call at the end of on_bar.
+    pub fn on_bar(&mut self, gateway: &mut impl TargetPosGateway, close_price: f64) {
+        self.trade(gateway, close_price, close_price);
+    }
+
+    ///Work orders at the best prices of a tick, call at the end of on_tick.
+    pub fn on_tick(&mut self, gateway: &mut impl TargetPosGateway, bid_price: f64, ask_price: f64) {
+        self.trade(gateway, bid_price, ask_price);
+    }
+
+    ///Cancel the orders still resting, then send the next order once none is left. A cancel
+    ///confirmed right away, as in a backtest, lets the order be sent again in the same call.
+    fn trade(&mut self, gateway: &mut impl TargetPosGateway, bid_price: f64, ask_price: f64) {
+        self.active_orderids
+            .retain(|vt_orderid| gateway.is_active(vt_orderid));
+        if !self.active_orderids.is_empty() {
+            for vt_orderid in &self.active_orderids {
+                gateway.cancel_order(vt_orderid);
+            }
+            self.active_orderids
+                .retain(|vt_orderid| gateway.is_active(vt_orderid));
+            if !self.active_orderids.is_empty() {
+                return;
+            }
+        }
+
+        let Some((direction, offset, volume)) = next_target_order(gateway.pos(), self.target)
+        else {
+            return;
+        };
+        let payup = self.payup * gateway.pricetick();
+        let price = match direction {
+            Direction::LONG => ask_price + payup,
+            _ => bid_price - payup,
+        };
+        let vt_orderids = gateway.send_order(direction, offset, price, volume);
+        self.active_orderids.extend(vt_orderids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_toward_target() {
+        assert_eq!(next_target_order(0.0, 0.0), None);
+        assert_eq!(
+            next_target_order(0.0, 3.0),
+            Some((Direction::LONG, Offset::OPEN, 3.0))
+        );
+        assert_eq!(
+            next_target_order(2.0, 5.0),
+            Some((Direction::LONG, Offset::OPEN, 3.0))
+        );
+        assert_eq!(
+            next_target_order(5.0, 2.0),
+            Some((Direction::SHORT, Offset::CLOSE, 3.0))
+        );
+        // A flip closes the position first
+        assert_eq!(
+            next_target_order(2.0, -3.0),
+            Some((Direction::SHORT, Offset::CLOSE, 2.0))
+        );
+        assert_eq!(
+            next_target_order(-2.0, 1.0),
+            Some((Direction::LONG, Offset::CLOSE, 2.0))
+        );
+    }
+}
@@ -0,0 +1,388 @@
+/*!Bayesian optimization by a tree-structured Parzen estimator (TPE), as in Optuna, for
+backtests too expensive to try every combination of a grid, e.g. in tick mode. After a few
+random trials, the trials are split into the best gamma of them and the rest, each parameter
+gets a density over the good ones l(x) and one over the rest g(x), and the next setting is the
+candidate drawn from l(x) with the highest l(x) / g(x). Int and Float ranges of the
+OptimizationSetting are searched continuously, Values are sampled as categories, and settings
+breaking its constraints are never tried. */
+use std::collections::HashSet;
+use std::f64::consts::PI;
+
+use super::batch::{BatchJob, BatchRunner};
+use super::optimize::{
+    format_value, output_progress, run_settings, OptimizationProgress, OptimizationResult,
+    OptimizationSetting, Parameter, Tracker,
+};
+use crate::vnrs::trader::synthetic::SplitMix64;
+
+///TPE search over the parameters of an OptimizationSetting, trials run in batches of the
+///workers of the BatchRunner.
+#[derive(Debug, Clone)]
+pub struct TpeOptimizer {
+    ///Backtests to run at most, fewer if the setting stops early or has fewer combinations.
+    pub n_trials: usize,
+    ///Random trials before the estimator takes over.
+    pub n_startup_trials: usize,
+    ///Candidates drawn from l(x) for each parameter of a trial.
+    pub n_candidates: usize,
+    ///Share of the trials counted as good.
+    pub gamma: f64,
+    ///Same seed, same trials for the same results.
+    pub seed: u64,
+}
+
+impl TpeOptimizer {
+    pub fn new(n_trials: usize) -> Self {
+        TpeOptimizer {
+            n_trials,
+            n_startup_trials: 10,
+            n_candidates: 24,
+            gamma: 0.25,
+            seed: 0,
+        }
+    }
+
+    ///Backtest job with the settings suggested by the estimator, best first.
+    pub fn run(
+        &self,
+        runner: &BatchRunner,
+        job: &BatchJob,
+        optimization_setting: &OptimizationSetting,
+    ) -> Vec<OptimizationResult> {
+        self.run_with(runner, job, optimization_setting, output_progress)
+    }
+
+    ///run passing the progress to on_progress after every backtest, see
+    ///optimize::run_optimization_with.
+    pub fn run_with(
+        &self,
+        runner: &BatchRunner,
+        job: &BatchJob,
+        optimization_setting: &OptimizationSetting,
+        on_progress: impl Fn(&OptimizationProgress) + Send,
+    ) -> Vec<OptimizationResult> {
+        let parameters = &optimization_setting.parameters;
+        // Ranges of floats never run out, other spaces may be smaller than n_trials
+        let finite = parameters
+            .iter()
+            .all(|(_, parameter)| !matches!(parameter, Parameter::Float { .. }));
+        // Only grids smaller than n_trials are generated to count the ones meeting the
+        // constraints, wide int ranges are never listed
+        let total = match optimization_setting.grid_size() {
+            Some(size) if finite && size <= self.n_trials => {
+                self.n_trials.min(optimization_setting.combination_count())
+            }
+            _ => self.n_trials,
+        };
+        let mut tracker = Tracker::new(optimization_setting, total, on_progress);
+        let mut rng = SplitMix64(self.seed);
+        let mut trials: Vec<(Vec<f64>, Option<f64>)> = Vec::new();
+        let mut results = Vec::new();
+        let mut tried = HashSet::new();
+        // Trials of an earlier run of the study guide the estimator as if just run
+        for result in tracker.resume() {
+            if let Some(point) = from_setting(parameters, &result.setting) {
+                trials.push((point, optimization_setting.target(&result)));
+            }
+            tried.insert(result.setting.clone());
+            results.push(result);
+        }
+
+        'trials: while results.len() < total {
+            let batch_size = runner.max_workers().min(total - results.len());
+            let mut batch = Vec::new();
+            while batch.len() < batch_size {
+                // Suggestions already tried or breaking a constraint are drawn again, at random
+                // after a few times
+                let point = (0..100)
+                    .map(|attempt| {
+                        if attempt < 5 {
+                            self.suggest(parameters, &trials, &mut rng)
+                        } else {
+                            random_point(parameters, &mut rng)
+                        }
+                    })
+                    .find(|point| {
+                        let setting = to_setting(parameters, point);
+                        !tried.contains(&setting) && optimization_setting.satisfies(&setting)
+                    });
+                let Some(point) = point else {
+                    break;
+                };
+                tried.insert(to_setting(parameters, &point));
+                batch.push(point);
+            }
+            if batch.is_empty() {
+                break;
+            }
+
+            let settings: Vec<String> = batch
+                .iter()
+                .map(|point| to_setting(parameters, point))
+                .collect();
+            let batch_results = run_settings(runner, job, &settings);
+            for (point, result) in batch.into_iter().zip(batch_results) {
+                let target = optimization_setting.target(&result);
+                trials.push((point, target));
+                let go_on = tracker.record(&result);
+                results.push(result);
+                if !go_on {
+                    break 'trials;
+                }
+            }
+        }
+
+        optimization_setting.sort_results(&mut results);
+        results
+    }
+
+    ///Next point, random until there are n_startup_trials.
+    fn suggest(
+        &self,
+        parameters: &[(String, Parameter)],
+        trials: &[(Vec<f64>, Option<f64>)],
+        rng: &mut SplitMix64,
+    ) -> Vec<f64> {
+        if trials.len() < self.n_startup_trials.max(1) {
+            return random_point(parameters, rng);
+        }
+
+        // Failed trials are the worst
+        let mut ranked: Vec<&(Vec<f64>, Option<f64>)> = trials.iter().collect();
+        ranked.sort_by(|a, b| {
+            b.1.unwrap_or(f64::NEG_INFINITY)
+                .total_cmp(&a.1.unwrap_or(f64::NEG_INFINITY))
+        });
+        let n_good = ((trials.len() as f64 * self.gamma).ceil() as usize).clamp(1, trials.len());
+        let (good, bad) = ranked.split_at(n_good);
+
+        parameters
+            .iter()
+            .enumerate()
+            .map(|(i, (_, parameter))| {
+                let good: Vec<f64> = good.iter().map(|(point, _)| point[i]).collect();
+                let bad: Vec<f64> = bad.iter().map(|(point, _)| point[i]).collect();
+                let l = Parzen::new(parameter, &good);
+                let g = Parzen::new(parameter, &bad);
+                (0..self.n_candidates.max(1))
+                    .map(|_| l.sample(rng))
+                    .map(|x| (x, l.density(x).ln() - g.density(x).ln()))
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(x, _)| x)
+                    .unwrap()
+            })
+            .collect()
+    }
+}
+
+///Point uniformly over the space, the index of a value for Values parameters.
+fn random_point(parameters: &[(String, Parameter)], rng: &mut SplitMix64) -> Vec<f64> {
+    parameters
+        .iter()
+        .map(|(_, parameter)| match parameter {
+            Parameter::Values(values) => (rng.next_f64() * values.len() as f64).floor(),
+            Parameter::Int { low, high } => {
+                *low as f64 + (rng.next_f64() * (high - low + 1) as f64).floor()
+            }
+            Parameter::Float { low, high } => low + rng.next_f64() * (high - low),
+        })
+        .collect()
+}
+
+fn to_setting(parameters: &[(String, Parameter)], point: &[f64]) -> String {
+    parameters
+        .iter()
+        .zip(point)
+        .map(|((name, parameter), x)| {
+            let value = match parameter {
+                Parameter::Values(values) => values[*x as usize].clone(),
+                Parameter::Int { .. } => (x.round() as i64).to_string(),
+                Parameter::Float { .. } => format_value(*x),
+            };
+            format!("{}:{}", name, value)
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+///Point of a setting made by to_setting, None if it doesn't match the parameters.
+fn from_setting(parameters: &[(String, Parameter)], setting: &str) -> Option<Vec<f64>> {
+    let values: Vec<(&str, &str)> = setting
+        .split(',')
+        .map(|pair| pair.split_once(':'))
+        .collect::<Option<_>>()?;
+    if values.len() != parameters.len() {
+        return None;
+    }
+    parameters
+        .iter()
+        .zip(values)
+        .map(|((name, parameter), (value_name, value))| {
+            if name != value_name {
+                return None;
+            }
+            match parameter {
+                Parameter::Values(values) => values
+                    .iter()
+                    .position(|choice| choice == value)
+                    .map(|i| i as f64),
+                Parameter::Int { .. } | Parameter::Float { .. } => value.parse().ok(),
+            }
+        })
+        .collect()
+}
+
+///Density of the trials of one parameter: a Gaussian at every observation, or a weight per
+///category, mixed with a uniform prior so that unexplored values keep a chance.
+enum Parzen {
+    Numeric {
+        low: f64,
+        high: f64,
+        observations: Vec<f64>,
+        bandwidth: f64,
+        integer: bool,
+    },
+    Categorical {
+        weights: Vec<f64>,
+    },
+}
+
+impl Parzen {
+    fn new(parameter: &Parameter, observations: &[f64]) -> Self {
+        let (low, high, integer) = match parameter {
+            Parameter::Values(values) => {
+                let mut weights = vec![1.0; values.len()];
+                for x in observations {
+                    weights[*x as usize] += 1.0;
+                }
+                let sum: f64 = weights.iter().sum();
+                weights.iter_mut().for_each(|weight| *weight /= sum);
+                return Parzen::Categorical { weights };
+            }
+            // Integers are searched as the numbers rounding to them
+            Parameter::Int { low, high } => (*low as f64 - 0.5, *high as f64 + 0.5, true),
+            Parameter::Float { low, high } => (*low, *high, false),
+        };
+        // Narrower as observations accumulate, like Scott's rule
+        let width = (high - low).max(f64::EPSILON);
+        let bandwidth = width / (1.0 + observations.len() as f64).powf(0.2) / 2.0;
+        Parzen::Numeric {
+            low,
+            high,
+            observations: observations.to_vec(),
+            bandwidth: bandwidth.max(width / 100.0),
+            integer,
+        }
+    }
+
+    fn sample(&self, rng: &mut SplitMix64) -> f64 {
+        match self {
+            Parzen::Numeric {
+                low,
+                high,
+                observations,
+                bandwidth,
+                integer,
+            } => {
+                // The prior is one component more besides the observations
+                let component = (rng.next_f64() * (observations.len() + 1) as f64) as usize;
+                let x = match observations.get(component) {
+                    Some(center) => center + bandwidth * rng.next_normal(),
+                    None => low + rng.next_f64() * (high - low),
+                };
+                let x = x.clamp(*low, *high);
+                if *integer {
+                    x.round().clamp(low + 0.5, high - 0.5)
+                } else {
+                    x
+                }
+            }
+            Parzen::Categorical { weights } => {
+                let mut u = rng.next_f64();
+                for (i, weight) in weights.iter().enumerate() {
+                    if u < *weight {
+                        return i as f64;
+                    }
+                    u -= weight;
+                }
+                (weights.len() - 1) as f64
+            }
+        }
+    }
+
+    fn density(&self, x: f64) -> f64 {
+        match self {
+            Parzen::Numeric {
+                low,
+                high,
+                observations,
+                bandwidth,
+                ..
+            } => {
+                let n = (observations.len() + 1) as f64;
+                let prior = 1.0 / (high - low).max(f64::EPSILON);
+                let kernels: f64 = observations
+                    .iter()
+                    .map(|center| {
+                        let z = (x - center) / bandwidth;
+                        (-0.5 * z * z).exp() / (bandwidth * (2.0 * PI).sqrt())
+                    })
+                    .sum();
+                (prior + kernels) / n
+            }
+            Parzen::Categorical { weights } => weights[x as usize],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimator_concentrates_on_the_best_region() {
+        let mut setting = OptimizationSetting::new("sharpe_ratio");
+        setting.add_float_range("x", -10.0, 10.0).unwrap();
+        setting.add_int_range("n", 1, 50).unwrap();
+        setting.add_choices("mode", &["a", "b", "c"]);
+        let parameters = &setting.parameters;
+        // Best at x = 3, n = 20 and mode b
+        let objective = |point: &[f64]| {
+            -(point[0] - 3.0).powi(2) - ((point[1] - 20.0) / 5.0).powi(2)
+                + if point[2] == 1.0 { 0.0 } else { -5.0 }
+        };
+
+        let optimizer = TpeOptimizer::new(80);
+        let mut rng = SplitMix64(optimizer.seed);
+        let mut trials: Vec<(Vec<f64>, Option<f64>)> = Vec::new();
+        for _ in 0..optimizer.n_trials {
+            let point = optimizer.suggest(parameters, &trials, &mut rng);
+            trials.push((point.clone(), Some(objective(&point))));
+        }
+        let random_best = trials[..10]
+            .iter()
+            .map(|(_, value)| value.unwrap())
+            .fold(f64::NEG_INFINITY, f64::max);
+        let (best, value) = trials
+            .iter()
+            .max_by(|a, b| a.1.unwrap().total_cmp(&b.1.unwrap()))
+            .unwrap();
+        assert!(value.unwrap() > random_best);
+        assert!((best[0] - 3.0).abs() < 1.5, "{:?}", best);
+        assert!((best[1] - 20.0).abs() <= 5.0, "{:?}", best);
+        assert_eq!(best[2], 1.0);
+        assert_eq!(best[1].fract(), 0.0);
+        assert!(to_setting(parameters, best).ends_with(",mode:b"));
+        let setting = to_setting(parameters, best);
+        let point = from_setting(parameters, &setting).unwrap();
+        assert_eq!(to_setting(parameters, &point), setting);
+        assert_eq!(from_setting(parameters, "x:1,n:2"), None);
+
+        // Later trials are mostly around the best
+        let late_near = trials[60..]
+            .iter()
+            .filter(|(point, _)| (point[0] - 3.0).abs() < 3.0)
+            .count();
+        assert!(late_near >= 10, "{}", late_near);
+    }
+}
@@ -0,0 +1,189 @@
+/*!Bar by bar state dump of a backtest for debugging strategies: the variables of the strategy,
+the position and the open orders after every bar, written as gzip compressed JSON lines. Two
+traces of the same backtest are compared with diff_traces to find where runs diverge. */
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::error::VnrsResult;
+use crate::vnrs::trader::constant::{Direction, Offset};
+
+///Limit or stop order open after a bar.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceOrder {
+    pub vt_orderid: String,
+    pub direction: Direction,
+    pub offset: Offset,
+    pub price: f64,
+    pub volume: f64,
+    pub traded: f64,
+}
+
+///State after one bar.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    pub datetime: NaiveDateTime,
+    pub pos: f64,
+    pub variables: Vec<(String, String)>,
+    pub orders: Vec<TraceOrder>,
+}
+
+pub struct TraceWriter {
+    encoder: GzEncoder<BufWriter<File>>,
+}
+
+impl TraceWriter {
+    ///Trace file at path, created or truncated.
+    pub fn create(path: impl AsRef<Path>) -> VnrsResult<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(TraceWriter {
+            encoder: GzEncoder::new(file, Compression::default()),
+        })
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> VnrsResult<()> {
+        serde_json::to_writer(&mut self.encoder, record)?;
+        self.encoder.write_all(b"\n")?;
+        Ok(())
+    }
+
+    ///Complete the gzip stream, the file isn't readable until then.
+    pub fn finish(self) -> VnrsResult<()> {
+        self.encoder.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+///Records of the trace file at path, in bar sequence.
+pub fn load_trace(path: impl AsRef<Path>) -> VnrsResult<Vec<TraceRecord>> {
+    let reader = BufReader::new(GzDecoder::new(File::open(path)?));
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        records.push(serde_json::from_str(&line?)?);
+    }
+    Ok(records)
+}
+
+///Difference of two traces at the bar of index, field being "datetime", "pos", "orders",
+///"bars" for traces of different length or the name of a variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceDiff {
+    pub index: usize,
+    pub datetime: NaiveDateTime,
+    pub field: String,
+    pub left: String,
+    pub right: String,
+}
+
+///Differences of two traces bar by bar, in bar sequence.
+pub fn diff_traces(left: &[TraceRecord], right: &[TraceRecord]) -> Vec<TraceDiff> {
+    let mut diffs = Vec::new();
+    for (index, (l, r)) in left.iter().zip(right).enumerate() {
+        let mut push = |field: &str, left: String, right: String| {
+            if left != right {
+                diffs.push(TraceDiff {
+                    index,
+                    datetime: l.datetime,
+                    field: field.to_string(),
+                    left,
+                    right,
+                });
+            }
+        };
+        push("datetime", l.datetime.to_string(), r.datetime.to_string());
+        push("pos", l.pos.to_string(), r.pos.to_string());
+        let find = |variables: &[(String, String)], name: &str| {
+            variables
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default()
+        };
+        for (name, value) in &l.variables {
+            push(name, value.clone(), find(&r.variables, name));
+        }
+        for (name, value) in &r.variables {
+            if !l.variables.iter().any(|(n, _)| n == name) {
+                push(name, String::new(), value.clone());
+            }
+        }
+        if l.orders != r.orders {
+            let describe =
+                |orders: &[TraceOrder]| serde_json::to_string(orders).unwrap_or_default();
+            push("orders", describe(&l.orders), describe(&r.orders));
+        }
+    }
+    if left.len() != right.len() {
+        let index = left.len().min(right.len());
+        let longer = if left.len() > right.len() {
+            left
+        } else {
+            right
+        };
+        diffs.push(TraceDiff {
+            index,
+            datetime: longer[index].datetime,
+            field: "bars".to_string(),
+            left: left.len().to_string(),
+            right: right.len().to_string(),
+        });
+    }
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn write_load_and_diff() {
+        let record = |minute: u32, pos: f64, fast: &str| TraceRecord {
+            datetime: NaiveDate::from_ymd_opt(2024, 1, 22)
+                .unwrap()
+                .and_hms_opt(9, minute, 0)
+                .unwrap(),
+            pos,
+            variables: vec![("fast_ma".to_string(), fast.to_string())],
+            orders: vec![TraceOrder {
+                vt_orderid: "BACKTESTING.1".to_string(),
+                direction: Direction::LONG,
+                offset: Offset::OPEN,
+                price: 100.0,
+                volume: 1.0,
+                traded: 0.0,
+            }],
+        };
+        let left = vec![record(0, 0.0, "1.5"), record(1, 1.0, "1.6")];
+        let path = std::env::temp_dir().join(format!("vnrs_trace_{}.gz", std::process::id()));
+        let mut writer = TraceWriter::create(&path).unwrap();
+        for r in &left {
+            writer.write(r).unwrap();
+        }
+        writer.finish().unwrap();
+        let loaded = load_trace(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, left);
+        assert!(diff_traces(&left, &loaded).is_empty());
+
+        let right = vec![
+            record(0, 0.0, "1.5"),
+            record(1, 2.0, "1.7"),
+            record(2, 2.0, "1.8"),
+        ];
+        let diffs = diff_traces(&left, &right);
+        let fields: Vec<(usize, &str)> =
+            diffs.iter().map(|d| (d.index, d.field.as_str())).collect();
+        assert_eq!(fields, vec![(1, "pos"), (1, "fast_ma"), (2, "bars")]);
+        assert_eq!(
+            (diffs[1].left.as_str(), diffs[1].right.as_str()),
+            ("1.6", "1.7")
+        );
+    }
+}
@@ -0,0 +1,281 @@
+/*!Terminal UI showing replay progress and final statistics, enabled by the `tui` feature. */
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind},
+        execute,
+        terminal::{enable_raw_mode, EnterAlternateScreen},
+    },
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Style},
+    widgets::{Block, Borders, Gauge, List, ListItem, Paragraph, Row, Sparkline, Table},
+    Frame, Terminal,
+};
+use std::io;
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::backtesting::{BacktestingEngine, BacktestingProgress, BacktestingStatistics};
+use super::batch::panic_message;
+use crate::error::VnrsResult;
+
+///Name of the thread running the backtest, its panics are shown in the UI.
+const WORKER_THREAD: &str = "tui-backtest";
+
+type PanicHook = dyn Fn(&PanicHookInfo<'_>) + Send + Sync;
+
+enum TuiEvent {
+    Log(String),
+    Progress(BacktestingProgress),
+    Finished(BacktestingStatistics),
+    Failed(String),
+}
+
+struct App {
+    title: String,
+    logs: Vec<String>,
+    progress: BacktestingProgress,
+    balances: Vec<f64>,
+    statistics: Option<BacktestingStatistics>,
+    error: Option<String>,
+}
+
+///Run a backtest on a worker thread and display it in the terminal until the user presses q.
+///setup configures the engine (set_parameters, add_strategy) before data is loaded, its error
+///is shown as the error of the run.
+pub fn run_with_tui<F>(title: &str, setup: F) -> io::Result<Option<BacktestingStatistics>>
+where
+    F: FnOnce(&mut BacktestingEngine) -> VnrsResult<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+
+    let guard = TerminalGuard::install()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+
+    thread::Builder::new()
+        .name(WORKER_THREAD.into())
+        .spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| -> VnrsResult<_> {
+                let mut engine = BacktestingEngine::new();
+
+                let log_sender = sender.clone();
+                engine.set_output_callback(move |msg| {
+                    let _ = log_sender.send(TuiEvent::Log(msg.to_string()));
+                });
+                let progress_sender = sender.clone();
+                engine.set_progress_callback(move |progress| {
+                    let _ = progress_sender.send(TuiEvent::Progress(progress.clone()));
+                });
+
+                setup(&mut engine)?;
+                engine.load_data()?;
+                engine.run_backtesting()?;
+                engine.calculate_result();
+                Ok(engine.calculate_statistics(None, false))
+            }));
+
+            let _ = sender.send(match result {
+                Ok(Ok(statistics)) => TuiEvent::Finished(statistics),
+                Ok(Err(e)) => TuiEvent::Failed(e.to_string()),
+                Err(payload) => TuiEvent::Failed(panic_message(payload)),
+            });
+        })?;
+
+    let mut app = App {
+        title: title.to_string(),
+        logs: Vec::new(),
+        progress: BacktestingProgress::default(),
+        balances: Vec::new(),
+        statistics: None,
+        error: None,
+    };
+
+    let result = app.run(&mut terminal, &receiver);
+    drop(guard);
+
+    result.map(|_| app.statistics)
+}
+
+///Raw mode and the alternate screen, restored on drop. A panic outside the backtest thread
+///restores the terminal before the previous panic hook reports it.
+struct TerminalGuard {
+    previous_hook: Arc<PanicHook>,
+}
+
+impl TerminalGuard {
+    fn install() -> io::Result<Self> {
+        let previous_hook: Arc<PanicHook> = panic::take_hook().into();
+        let hook = previous_hook.clone();
+        panic::set_hook(Box::new(move |info| {
+            // Panic messages of the backtest would be printed over the alternate screen
+            if thread::current().name() == Some(WORKER_THREAD) {
+                return;
+            }
+            ratatui::restore();
+            hook(info);
+        }));
+
+        let guard = TerminalGuard { previous_hook };
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        ratatui::restore();
+        // The hook can't be replaced while panicking, it has restored the terminal already
+        if !thread::panicking() {
+            let previous_hook = self.previous_hook.clone();
+            panic::set_hook(Box::new(move |info| previous_hook(info)));
+        }
+    }
+}
+
+impl App {
+    fn run(
+        &mut self,
+        terminal: &mut ratatui::DefaultTerminal,
+        receiver: &Receiver<TuiEvent>,
+    ) -> io::Result<()> {
+        loop {
+            while let Ok(event) = receiver.try_recv() {
+                self.process_event(event);
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if event::poll(Duration::from_millis(100))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press
+                        && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                    {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn process_event(&mut self, event: TuiEvent) {
+        match event {
+            TuiEvent::Log(msg) => self.logs.push(msg),
+            TuiEvent::Progress(progress) => {
+                self.balances.push(progress.balance);
+                self.progress = progress;
+            }
+            TuiEvent::Finished(statistics) => {
+                self.progress.progress = 1.0;
+                self.statistics = Some(statistics);
+            }
+            TuiEvent::Failed(msg) => self.error = Some(msg),
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Min(10),
+                Constraint::Length(10),
+            ])
+            .split(frame.area());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .split(rows[1]);
+
+        let status = if self.error.is_some() {
+            "失败"
+        } else if self.statistics.is_some() {
+            "完成，按q退出"
+        } else {
+            "回放中"
+        };
+        let gauge = Gauge::default()
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("{} - {}", self.title, status)),
+            )
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio(self.progress.progress.clamp(0.0, 1.0))
+            .label(self.progress.to_string());
+        frame.render_widget(gauge, rows[0]);
+
+        let mut lines = vec![
+            format!("时间：{}", self.progress.datetime),
+            format!("持仓：{}", self.progress.pos),
+            format!("资金：{:.2}", self.progress.balance),
+            format!("回撤：{:.2}", self.progress.drawdown),
+            format!("成交笔数：{}", self.progress.trade_count),
+        ];
+        if let Some(error) = &self.error {
+            lines.push(format!("错误：{}", error));
+        }
+        let account = Paragraph::new(lines.join("\n"))
+            .block(Block::default().borders(Borders::ALL).title("账户"));
+        frame.render_widget(account, columns[0]);
+
+        match &self.statistics {
+            Some(statistics) => frame.render_widget(statistics_table(statistics), columns[1]),
+            None => {
+                let min = self.balances.iter().cloned().fold(f64::INFINITY, f64::min);
+                let data: Vec<u64> = self.balances.iter().map(|b| (b - min) as u64).collect();
+                let sparkline = Sparkline::default()
+                    .block(Block::default().borders(Borders::ALL).title("资金曲线"))
+                    .data(&data)
+                    .style(Style::default().fg(Color::Cyan));
+                frame.render_widget(sparkline, columns[1]);
+            }
+        }
+
+        let height = rows[2].height.saturating_sub(2) as usize;
+        let logs: Vec<ListItem> = self
+            .logs
+            .iter()
+            .skip(self.logs.len().saturating_sub(height))
+            .map(|msg| ListItem::new(msg.as_str()))
+            .collect();
+        frame.render_widget(
+            List::new(logs).block(Block::default().borders(Borders::ALL).title("日志")),
+            rows[2],
+        );
+    }
+}
+
+fn statistics_table(s: &BacktestingStatistics) -> Table<'static> {
+    let rows = [
+        ("首个交易日", s.start_date.to_string()),
+        ("最后交易日", s.end_date.to_string()),
+        ("总交易日", s.total_days.to_string()),
+        ("盈利交易日", s.profit_days.to_string()),
+        ("亏损交易日", s.loss_days.to_string()),
+        ("起始资金", format!("{:.2}", s.capital)),
+        ("结束资金", format!("{:.2}", s.end_balance)),
+        ("总收益率", format!("{:.2}%", s.total_return)),
+        ("年化收益", format!("{:.2}%", s.annual_return)),
+        ("最大回撤", format!("{:.2}", s.max_drawdown)),
+        ("百分比最大回撤", format!("{:.2}%", s.max_ddpercent)),
+        ("最长回撤天数", s.max_drawdown_duration.to_string()),
+        ("总盈亏", format!("{:.2}", s.total_net_pnl)),
+        ("总手续费", format!("{:.2}", s.total_commission)),
+        ("总滑点", format!("{:.2}", s.total_slippage)),
+        ("总成交笔数", s.total_trade_count.to_string()),
+        ("日均收益率", format!("{:.2}%", s.daily_return)),
+        ("收益标准差", format!("{:.2}%", s.return_std)),
+        ("Sharpe Ratio", format!("{:.2}", s.sharpe_ratio)),
+        ("收益回撤比", format!("{:.2}", s.return_drawdown_ratio)),
+    ];
+
+    Table::new(
+        rows.into_iter()
+            .map(|(k, v)| Row::new(vec![k.to_string(), v])),
+        [Constraint::Length(16), Constraint::Min(10)],
+    )
+    .block(Block::default().borders(Borders::ALL).title("统计指标"))
+}
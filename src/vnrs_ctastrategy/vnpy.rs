@@ -0,0 +1,201 @@
+/*!Import of the strategies of a vn.py deployment from its cta_strategy_setting.json, with the
+positions and variables saved in its cta_strategy_data.json, e.g. when migrating to vnrs. Each
+strategy class maps to the dylib of the snake case name, AtrRsiStrategy to atr_rsi_strategy,
+which can be changed before adding the strategy to an engine. */
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde_json::{Map, Value};
+
+use super::backtesting::BacktestingEngine;
+use super::base::{format_setting, ExternClass};
+use crate::error::{VnrsError, VnrsResult};
+
+///Setting file of vn.py's CtaEngine.
+pub const VNPY_SETTING_FILENAME: &str = "cta_strategy_setting.json";
+
+///Strategy of a vn.py setting file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VnpyStrategy {
+    pub strategy_name: String,
+    pub class_name: String,
+    ///Name of the strategy dylib without prefix and extension, see dylib_name.
+    pub dylib: String,
+    pub vt_symbol: String,
+    pub setting: Vec<(String, String)>,
+    ///Saved position, 0 without data.
+    pub pos: f64,
+    ///Saved variables other than pos.
+    pub variables: Vec<(String, String)>,
+}
+
+impl VnpyStrategy {
+    ///Setting as a "name:value,name:value" string for add_strategy.
+    pub fn setting_string(&self) -> String {
+        format_setting(&self.setting)
+    }
+
+    ///Add the strategy from its dylib in dir to engine, set up for its vt_symbol, starting from
+    ///the saved position with the saved variables restored after on_init.
+    pub fn add_to(&self, engine: &mut BacktestingEngine, dir: impl AsRef<Path>) -> VnrsResult<()> {
+        if engine.get_vt_symbol() != self.vt_symbol {
+            return Err(VnrsError::Setting(format!(
+                "strategy {} trades {}, not {}",
+                self.strategy_name,
+                self.vt_symbol,
+                engine.get_vt_symbol()
+            )));
+        }
+        let filename = dir.as_ref().join(libloading::library_filename(&self.dylib));
+        let class = Arc::new(ExternClass::new(filename)?);
+        engine.add_strategy(class, &self.setting_string())?;
+        engine.initial_pos = self.pos;
+        if !self.variables.is_empty() {
+            engine.set_strategy_variables(self.variables.clone());
+        }
+        Ok(())
+    }
+}
+
+///Snake case dylib name of a strategy class, e.g. atr_rsi_strategy for AtrRsiStrategy.
+pub fn dylib_name(class_name: &str) -> String {
+    let mut name = String::with_capacity(class_name.len() + 4);
+    let mut previous_lower = false;
+    for c in class_name.chars() {
+        if c.is_uppercase() {
+            if previous_lower {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+            previous_lower = false;
+        } else {
+            name.push(c);
+            previous_lower = c.is_lowercase() || c.is_ascii_digit();
+        }
+    }
+    name
+}
+
+///Strategies of the content of a vn.py setting file in name order, with the positions and
+///variables of the content of its data file if given.
+pub fn parse_vnpy_strategies(setting: &str, data: Option<&str>) -> VnrsResult<Vec<VnpyStrategy>> {
+    let invalid = |what: &str| VnrsError::Setting(format!("invalid vn.py {}", what));
+    let Value::Object(strategies) = serde_json::from_str(setting)? else {
+        return Err(invalid("strategy setting"));
+    };
+    let data = match data {
+        Some(data) => match serde_json::from_str(data)? {
+            Value::Object(data) => data,
+            _ => return Err(invalid("strategy data")),
+        },
+        None => Map::new(),
+    };
+
+    let mut result = Vec::with_capacity(strategies.len());
+    for (strategy_name, strategy) in strategies {
+        let field = |name: &str| strategy[name].as_str().map(str::to_string);
+        let (Some(class_name), Some(vt_symbol)) = (field("class_name"), field("vt_symbol")) else {
+            return Err(invalid(&format!("strategy {}", strategy_name)));
+        };
+        // vn.py repeats the class name inside the setting
+        let setting = strategy["setting"]
+            .as_object()
+            .map(|setting| {
+                values(setting)
+                    .filter(|(name, _)| name != "class_name")
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut pos = 0.0;
+        let mut variables = Vec::new();
+        if let Some(saved) = data.get(&strategy_name).and_then(Value::as_object) {
+            pos = saved["pos"].as_f64().unwrap_or_default();
+            variables = values(saved).filter(|(name, _)| name != "pos").collect();
+        }
+
+        result.push(VnpyStrategy {
+            dylib: dylib_name(&class_name),
+            strategy_name,
+            class_name,
+            vt_symbol,
+            setting,
+            pos,
+            variables,
+        });
+    }
+    Ok(result)
+}
+
+///Strategies of the vn.py setting file at setting_path, with the data file at data_path, e.g.
+///the two files in the .vntrader dir of the deployment.
+pub fn load_vnpy_strategies(
+    setting_path: impl AsRef<Path>,
+    data_path: Option<&Path>,
+) -> VnrsResult<Vec<VnpyStrategy>> {
+    let setting = fs::read_to_string(setting_path)?;
+    let data = data_path.map(fs::read_to_string).transpose()?;
+    parse_vnpy_strategies(&setting, data.as_deref())
+}
+
+///(name, value) of a JSON object as strategy settings and variables take them.
+fn values(object: &Map<String, Value>) -> impl Iterator<Item = (String, String)> + '_ {
+    object.iter().map(|(name, value)| {
+        let value = match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        (name.clone(), value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_vnpy_files() {
+        assert_eq!(dylib_name("AtrRsiStrategy"), "atr_rsi_strategy");
+        assert_eq!(dylib_name("DoubleMa2Strategy"), "double_ma2_strategy");
+        assert_eq!(dylib_name("RBreakerStrategy"), "rbreaker_strategy");
+
+        let setting = r#"{
+            "atr_rsi_IF": {
+                "class_name": "AtrRsiStrategy",
+                "vt_symbol": "IF888.CFFEX",
+                "setting": {"class_name": "AtrRsiStrategy", "atr_length": 22, "rsi_entry": 16.5}
+            },
+            "boll_rb": {
+                "class_name": "BollChannelStrategy",
+                "vt_symbol": "rb2405.SHFE",
+                "setting": {"boll_window": 18}
+            }
+        }"#;
+        let data = r#"{"atr_rsi_IF": {"pos": -2, "intra_trade_high": 4012.2, "inited": true}}"#;
+        let strategies = parse_vnpy_strategies(setting, Some(data)).unwrap();
+        assert_eq!(strategies.len(), 2);
+
+        let atr_rsi = &strategies[0];
+        assert_eq!(atr_rsi.strategy_name, "atr_rsi_IF");
+        assert_eq!(atr_rsi.dylib, "atr_rsi_strategy");
+        assert_eq!(atr_rsi.vt_symbol, "IF888.CFFEX");
+        assert_eq!(atr_rsi.setting_string(), "atr_length:22,rsi_entry:16.5");
+        assert_eq!(atr_rsi.pos, -2.0);
+        assert_eq!(
+            atr_rsi.variables,
+            vec![
+                ("inited".to_string(), "true".to_string()),
+                ("intra_trade_high".to_string(), "4012.2".to_string())
+            ]
+        );
+
+        let boll = &strategies[1];
+        assert_eq!(boll.dylib, "boll_channel_strategy");
+        assert_eq!(boll.pos, 0.0);
+        assert!(boll.variables.is_empty());
+
+        assert!(parse_vnpy_strategies(r#"{"a": {"vt_symbol": "x"}}"#, None).is_err());
+        assert!(parse_vnpy_strategies("[]", None).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+/*!Enter on RSI strength while ATR is above its average, exit on a trailing stop. */
+use vnrs::prelude::*;
+
+struct AtrRsiStrategy {
+    atr_length: usize,
+    atr_ma_length: usize,
+    rsi_length: usize,
+    rsi_entry: f64,
+    trailing_percent: f64,
+    fixed_size: f64,
+
+    atr_value: f64,
+    atr_ma: f64,
+    rsi_value: f64,
+    intra_trade_high: f64,
+    intra_trade_low: f64,
+
+    am: ArrayManager,
+}
+
+impl CtaStrategy for AtrRsiStrategy {
+    fn parameters() -> Vec<(String, String)> {
+        vec![
+            ("atr_length".to_string(), "22".to_string()),
+            ("atr_ma_length".to_string(), "10".to_string()),
+            ("rsi_length".to_string(), "5".to_string()),
+            ("rsi_entry".to_string(), "16".to_string()),
+            ("trailing_percent".to_string(), "0.8".to_string()),
+            ("fixed_size".to_string(), "1".to_string()),
+        ]
+    }
+
+    fn new(_context: &StrategyContext, setting: &[(String, String)]) -> Self {
+        AtrRsiStrategy {
+            atr_length: setting_value(setting, "atr_length", 22),
+            atr_ma_length: setting_value(setting, "atr_ma_length", 10),
+            rsi_length: setting_value(setting, "rsi_length", 5),
+            rsi_entry: setting_value(setting, "rsi_entry", 16.0),
+            trailing_percent: setting_value(setting, "trailing_percent", 0.8),
+            fixed_size: setting_value(setting, "fixed_size", 1.0),
+            atr_value: 0.0,
+            atr_ma: 0.0,
+            rsi_value: 0.0,
+            intra_trade_high: 0.0,
+            intra_trade_low: 0.0,
+            am: ArrayManager::new(100),
+        }
+    }
+
+    fn on_init(&mut self, context: &mut StrategyContext) {
+        for bar in context.load_bar(10, Interval::MINUTE) {
+            self.on_bar(context, &bar);
+        }
+    }
+
+    fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData) {
+        context.cancel_all();
+
+        self.am.update_bar(bar);
+        if !self.am.inited {
+            return;
+        }
+
+        let atr_array = self.am.atr_array(self.atr_length);
+        let recent = &atr_array[atr_array.len() - self.atr_ma_length..];
+        self.atr_value = atr_array[atr_array.len() - 1];
+        self.atr_ma = recent.iter().sum::<f64>() / recent.len() as f64;
+        self.rsi_value = self.am.rsi(self.rsi_length);
+
+        let pos = context.pos();
+        if pos == 0.0 {
+            self.intra_trade_high = bar.high_price;
+            self.intra_trade_low = bar.low_price;
+
+            if self.atr_value > self.atr_ma {
+                if self.rsi_value > 50.0 + self.rsi_entry {
+                    context.buy(bar.close_price + 5.0, self.fixed_size, false);
+                } else if self.rsi_value < 50.0 - self.rsi_entry {
+                    context.short(bar.close_price - 5.0, self.fixed_size, false);
+                }
+            }
+        } else if pos > 0.0 {
+            self.intra_trade_high = self.intra_trade_high.max(bar.high_price);
+            self.intra_trade_low = bar.low_price;
+
+            let long_stop = self.intra_trade_high * (1.0 - self.trailing_percent / 100.0);
+            context.sell(long_stop, pos.abs(), true);
+        } else {
+            self.intra_trade_high = bar.high_price;
+            self.intra_trade_low = self.intra_trade_low.min(bar.low_price);
+
+            let short_stop = self.intra_trade_low * (1.0 + self.trailing_percent / 100.0);
+            context.cover(short_stop, pos.abs(), true);
+        }
+    }
+
+    fn variables(&self) -> Vec<(String, String)> {
+        vec![
+            ("atr_value".to_string(), self.atr_value.to_string()),
+            ("atr_ma".to_string(), self.atr_ma.to_string()),
+            ("rsi_value".to_string(), self.rsi_value.to_string()),
+            (
+                "intra_trade_high".to_string(),
+                self.intra_trade_high.to_string(),
+            ),
+            (
+                "intra_trade_low".to_string(),
+                self.intra_trade_low.to_string(),
+            ),
+        ]
+    }
+
+    fn set_variables(&mut self, variables: &[(String, String)]) {
+        self.intra_trade_high = setting_value(variables, "intra_trade_high", self.intra_trade_high);
+        self.intra_trade_low = setting_value(variables, "intra_trade_low", self.intra_trade_low);
+    }
+}
+
+export_strategy!(AtrRsiStrategy);
@@ -0,0 +1,126 @@
+/*!Break out of the Bollinger band in the direction of CCI, exit on an ATR trailing stop. */
+use vnrs::prelude::*;
+
+struct BollChannelStrategy {
+    boll_window: usize,
+    boll_dev: f64,
+    cci_window: usize,
+    atr_window: usize,
+    sl_multiplier: f64,
+    fixed_size: f64,
+
+    boll_up: f64,
+    boll_down: f64,
+    cci_value: f64,
+    atr_value: f64,
+    intra_trade_high: f64,
+    intra_trade_low: f64,
+    long_stop: f64,
+    short_stop: f64,
+
+    am: ArrayManager,
+}
+
+impl CtaStrategy for BollChannelStrategy {
+    fn parameters() -> Vec<(String, String)> {
+        vec![
+            ("boll_window".to_string(), "18".to_string()),
+            ("boll_dev".to_string(), "3.4".to_string()),
+            ("cci_window".to_string(), "10".to_string()),
+            ("atr_window".to_string(), "30".to_string()),
+            ("sl_multiplier".to_string(), "5.2".to_string()),
+            ("fixed_size".to_string(), "1".to_string()),
+        ]
+    }
+
+    fn new(_context: &StrategyContext, setting: &[(String, String)]) -> Self {
+        BollChannelStrategy {
+            boll_window: setting_value(setting, "boll_window", 18),
+            boll_dev: setting_value(setting, "boll_dev", 3.4),
+            cci_window: setting_value(setting, "cci_window", 10),
+            atr_window: setting_value(setting, "atr_window", 30),
+            sl_multiplier: setting_value(setting, "sl_multiplier", 5.2),
+            fixed_size: setting_value(setting, "fixed_size", 1.0),
+            boll_up: 0.0,
+            boll_down: 0.0,
+            cci_value: 0.0,
+            atr_value: 0.0,
+            intra_trade_high: 0.0,
+            intra_trade_low: 0.0,
+            long_stop: 0.0,
+            short_stop: 0.0,
+            am: ArrayManager::new(100),
+        }
+    }
+
+    fn on_init(&mut self, context: &mut StrategyContext) {
+        for bar in context.load_bar(10, Interval::MINUTE) {
+            self.on_bar(context, &bar);
+        }
+    }
+
+    fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData) {
+        context.cancel_all();
+
+        self.am.update_bar(bar);
+        if !self.am.inited {
+            return;
+        }
+
+        (self.boll_up, self.boll_down) = self.am.boll(self.boll_window, self.boll_dev);
+        self.cci_value = self.am.cci(self.cci_window);
+        self.atr_value = self.am.atr(self.atr_window);
+
+        let pos = context.pos();
+        if pos == 0.0 {
+            self.intra_trade_high = bar.high_price;
+            self.intra_trade_low = bar.low_price;
+
+            if self.cci_value > 0.0 {
+                context.buy(self.boll_up, self.fixed_size, true);
+            } else if self.cci_value < 0.0 {
+                context.short(self.boll_down, self.fixed_size, true);
+            }
+        } else if pos > 0.0 {
+            self.intra_trade_high = self.intra_trade_high.max(bar.high_price);
+            self.intra_trade_low = bar.low_price;
+
+            self.long_stop = self.intra_trade_high - self.atr_value * self.sl_multiplier;
+            context.sell(self.long_stop, pos.abs(), true);
+        } else {
+            self.intra_trade_high = bar.high_price;
+            self.intra_trade_low = self.intra_trade_low.min(bar.low_price);
+
+            self.short_stop = self.intra_trade_low + self.atr_value * self.sl_multiplier;
+            context.cover(self.short_stop, pos.abs(), true);
+        }
+    }
+
+    fn variables(&self) -> Vec<(String, String)> {
+        vec![
+            ("boll_up".to_string(), self.boll_up.to_string()),
+            ("boll_down".to_string(), self.boll_down.to_string()),
+            ("cci_value".to_string(), self.cci_value.to_string()),
+            ("atr_value".to_string(), self.atr_value.to_string()),
+            (
+                "intra_trade_high".to_string(),
+                self.intra_trade_high.to_string(),
+            ),
+            (
+                "intra_trade_low".to_string(),
+                self.intra_trade_low.to_string(),
+            ),
+            ("long_stop".to_string(), self.long_stop.to_string()),
+            ("short_stop".to_string(), self.short_stop.to_string()),
+        ]
+    }
+
+    fn set_variables(&mut self, variables: &[(String, String)]) {
+        self.intra_trade_high = setting_value(variables, "intra_trade_high", self.intra_trade_high);
+        self.intra_trade_low = setting_value(variables, "intra_trade_low", self.intra_trade_low);
+        self.long_stop = setting_value(variables, "long_stop", self.long_stop);
+        self.short_stop = setting_value(variables, "short_stop", self.short_stop);
+    }
+}
+
+export_strategy!(BollChannelStrategy);
@@ -0,0 +1,97 @@
+/*!Double moving average crossover, reversing the position on each cross. */
+use vnrs::prelude::*;
+
+struct DoubleMaStrategy {
+    fast_window: usize,
+    slow_window: usize,
+
+    fast_ma0: f64,
+    fast_ma1: f64,
+    slow_ma0: f64,
+    slow_ma1: f64,
+
+    am: ArrayManager,
+}
+
+impl CtaStrategy for DoubleMaStrategy {
+    fn parameters() -> Vec<(String, String)> {
+        vec![
+            ("fast_window".to_string(), "10".to_string()),
+            ("slow_window".to_string(), "20".to_string()),
+        ]
+    }
+
+    fn new(_context: &StrategyContext, setting: &[(String, String)]) -> Self {
+        DoubleMaStrategy {
+            fast_window: setting_value(setting, "fast_window", 10),
+            slow_window: setting_value(setting, "slow_window", 20),
+            fast_ma0: 0.0,
+            fast_ma1: 0.0,
+            slow_ma0: 0.0,
+            slow_ma1: 0.0,
+            am: ArrayManager::new(100),
+        }
+    }
+
+    fn reset(&mut self, context: &StrategyContext, setting: &[(String, String)]) -> bool {
+        *self = DoubleMaStrategy::new(context, setting);
+        true
+    }
+
+    fn on_init(&mut self, context: &mut StrategyContext) {
+        context.load_bar_callback(10, Interval::MINUTE, false);
+    }
+
+    fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData) {
+        self.am.update_bar(bar);
+        if !self.am.inited {
+            return;
+        }
+
+        self.fast_ma1 = self.fast_ma0;
+        self.slow_ma1 = self.slow_ma0;
+        self.fast_ma0 = self.am.sma(self.fast_window);
+        self.slow_ma0 = self.am.sma(self.slow_window);
+        context.emit_factor("ma_spread", self.fast_ma0 - self.slow_ma0);
+
+        let cross_over = self.fast_ma0 > self.slow_ma0 && self.fast_ma1 < self.slow_ma1;
+        let cross_below = self.fast_ma0 < self.slow_ma0 && self.fast_ma1 > self.slow_ma1;
+        let pos = context.pos();
+
+        if cross_over {
+            context.set_reference("cross_over");
+            if pos == 0.0 {
+                context.buy(bar.close_price, 1.0, false);
+            } else if pos < 0.0 {
+                context.cover(bar.close_price, 1.0, false);
+                context.buy(bar.close_price, 1.0, false);
+            }
+        } else if cross_below {
+            context.set_reference("cross_below");
+            if pos == 0.0 {
+                context.short(bar.close_price, 1.0, false);
+            } else if pos > 0.0 {
+                context.sell(bar.close_price, 1.0, false);
+                context.short(bar.close_price, 1.0, false);
+            }
+        }
+    }
+
+    fn variables(&self) -> Vec<(String, String)> {
+        vec![
+            ("fast_ma0".to_string(), self.fast_ma0.to_string()),
+            ("fast_ma1".to_string(), self.fast_ma1.to_string()),
+            ("slow_ma0".to_string(), self.slow_ma0.to_string()),
+            ("slow_ma1".to_string(), self.slow_ma1.to_string()),
+        ]
+    }
+
+    fn set_variables(&mut self, variables: &[(String, String)]) {
+        self.fast_ma0 = setting_value(variables, "fast_ma0", self.fast_ma0);
+        self.fast_ma1 = setting_value(variables, "fast_ma1", self.fast_ma1);
+        self.slow_ma0 = setting_value(variables, "slow_ma0", self.slow_ma0);
+        self.slow_ma1 = setting_value(variables, "slow_ma1", self.slow_ma1);
+    }
+}
+
+export_strategy!(DoubleMaStrategy);
@@ -0,0 +1,174 @@
+/*!R-Breaker, intraday trend breakout and reversal levels from the previous day's range. */
+use chrono::{NaiveDateTime, NaiveTime};
+use vnrs::prelude::*;
+
+struct RBreakerStrategy {
+    setup_coef: f64,
+    break_coef: f64,
+    enter_coef_1: f64,
+    enter_coef_2: f64,
+    fixed_size: f64,
+    donchian_window: usize,
+    trailing_long: f64,
+    trailing_short: f64,
+    multiplier: f64,
+    exit_time: NaiveTime,
+
+    buy_break: f64,
+    sell_setup: f64,
+    sell_enter: f64,
+    buy_enter: f64,
+    buy_setup: f64,
+    sell_break: f64,
+
+    intra_trade_high: f64,
+    intra_trade_low: f64,
+
+    day_open: f64,
+    day_high: f64,
+    day_close: f64,
+    day_low: f64,
+    tend_high: f64,
+    tend_low: f64,
+
+    last_datetime: Option<NaiveDateTime>,
+    am: ArrayManager,
+}
+
+impl CtaStrategy for RBreakerStrategy {
+    fn parameters() -> Vec<(String, String)> {
+        vec![
+            ("setup_coef".to_string(), "0.25".to_string()),
+            ("break_coef".to_string(), "0.2".to_string()),
+            ("enter_coef_1".to_string(), "1.07".to_string()),
+            ("enter_coef_2".to_string(), "0.07".to_string()),
+            ("fixed_size".to_string(), "1".to_string()),
+            ("donchian_window".to_string(), "30".to_string()),
+            ("trailing_long".to_string(), "0.4".to_string()),
+            ("trailing_short".to_string(), "0.4".to_string()),
+            ("multiplier".to_string(), "3".to_string()),
+        ]
+    }
+
+    fn new(_context: &StrategyContext, setting: &[(String, String)]) -> Self {
+        RBreakerStrategy {
+            setup_coef: setting_value(setting, "setup_coef", 0.25),
+            break_coef: setting_value(setting, "break_coef", 0.2),
+            enter_coef_1: setting_value(setting, "enter_coef_1", 1.07),
+            enter_coef_2: setting_value(setting, "enter_coef_2", 0.07),
+            fixed_size: setting_value(setting, "fixed_size", 1.0),
+            donchian_window: setting_value(setting, "donchian_window", 30),
+            trailing_long: setting_value(setting, "trailing_long", 0.4),
+            trailing_short: setting_value(setting, "trailing_short", 0.4),
+            multiplier: setting_value(setting, "multiplier", 3.0),
+            exit_time: NaiveTime::from_hms_opt(14, 55, 0).unwrap(),
+            buy_break: 0.0,
+            sell_setup: 0.0,
+            sell_enter: 0.0,
+            buy_enter: 0.0,
+            buy_setup: 0.0,
+            sell_break: 0.0,
+            intra_trade_high: 0.0,
+            intra_trade_low: 0.0,
+            day_open: 0.0,
+            day_high: 0.0,
+            day_close: 0.0,
+            day_low: 0.0,
+            tend_high: 0.0,
+            tend_low: 0.0,
+            last_datetime: None,
+            am: ArrayManager::new(100),
+        }
+    }
+
+    fn on_init(&mut self, context: &mut StrategyContext) {
+        for bar in context.load_bar(10, Interval::MINUTE) {
+            self.on_bar(context, &bar);
+        }
+    }
+
+    fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData) {
+        context.cancel_all();
+
+        self.am.update_bar(bar);
+        let Some(last_datetime) = self.last_datetime.replace(bar.datetime) else {
+            return;
+        };
+
+        if last_datetime.date() != bar.datetime.date() {
+            // Levels of the new day from the range of the day before
+            if self.day_open != 0.0 {
+                self.buy_setup = self.day_low - self.setup_coef * (self.day_high - self.day_close);
+                self.sell_setup = self.day_high + self.setup_coef * (self.day_close - self.day_low);
+                self.buy_enter = (self.enter_coef_1 / 2.0) * (self.day_high + self.day_low)
+                    - self.enter_coef_2 * self.day_high;
+                self.sell_enter = (self.enter_coef_1 / 2.0) * (self.day_high + self.day_low)
+                    - self.enter_coef_2 * self.day_low;
+                self.buy_break =
+                    self.buy_setup + self.break_coef * (self.sell_setup - self.buy_setup);
+                self.sell_break =
+                    self.sell_setup - self.break_coef * (self.sell_setup - self.buy_setup);
+            }
+
+            self.day_open = bar.open_price;
+            self.day_high = bar.high_price;
+            self.day_close = bar.close_price;
+            self.day_low = bar.low_price;
+        } else {
+            self.day_high = self.day_high.max(bar.high_price);
+            self.day_low = self.day_low.min(bar.low_price);
+            self.day_close = bar.close_price;
+        }
+
+        if self.sell_setup == 0.0 || !self.am.inited {
+            return;
+        }
+
+        (self.tend_high, self.tend_low) = self.am.donchian(self.donchian_window);
+
+        let pos = context.pos();
+        if bar.datetime.time() < self.exit_time {
+            if pos == 0.0 {
+                self.intra_trade_low = bar.low_price;
+                self.intra_trade_high = bar.high_price;
+
+                if self.tend_high > self.sell_setup {
+                    let long_entry = self.buy_break.max(self.day_high);
+                    context.buy(long_entry, self.fixed_size, true);
+                    context.short(self.sell_enter, self.multiplier * self.fixed_size, true);
+                } else if self.tend_low < self.buy_setup {
+                    let short_entry = self.sell_break.min(self.day_low);
+                    context.short(short_entry, self.fixed_size, true);
+                    context.buy(self.buy_enter, self.multiplier * self.fixed_size, true);
+                }
+            } else if pos > 0.0 {
+                self.intra_trade_high = self.intra_trade_high.max(bar.high_price);
+                let long_stop = self.intra_trade_high * (1.0 - self.trailing_long / 100.0);
+                context.sell(long_stop, pos.abs(), true);
+            } else {
+                self.intra_trade_low = self.intra_trade_low.min(bar.low_price);
+                let short_stop = self.intra_trade_low * (1.0 + self.trailing_short / 100.0);
+                context.cover(short_stop, pos.abs(), true);
+            }
+        }
+        // Close existing position before the end of the day
+        else if pos > 0.0 {
+            context.sell(bar.close_price * 0.99, pos.abs(), false);
+        } else if pos < 0.0 {
+            context.cover(bar.close_price * 1.01, pos.abs(), false);
+        }
+    }
+
+    fn variables(&self) -> Vec<(String, String)> {
+        vec![
+            ("buy_break".to_string(), self.buy_break.to_string()),
+            ("sell_setup".to_string(), self.sell_setup.to_string()),
+            ("sell_enter".to_string(), self.sell_enter.to_string()),
+            ("buy_enter".to_string(), self.buy_enter.to_string()),
+            ("buy_setup".to_string(), self.buy_setup.to_string()),
+            ("sell_break".to_string(), self.sell_break.to_string()),
+        ]
+    }
+}
+
+export_strategy!(RBreakerStrategy);
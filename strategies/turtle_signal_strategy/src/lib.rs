@@ -0,0 +1,152 @@
+/*!Turtle trading signals, Donchian breakout entries pyramided by half an ATR and a 2 ATR stop. */
+use vnrs::prelude::*;
+
+struct TurtleSignalStrategy {
+    entry_window: usize,
+    exit_window: usize,
+    atr_window: usize,
+    fixed_size: f64,
+
+    entry_up: f64,
+    entry_down: f64,
+    exit_up: f64,
+    exit_down: f64,
+    atr_value: f64,
+
+    long_entry: f64,
+    short_entry: f64,
+    long_stop: f64,
+    short_stop: f64,
+
+    am: ArrayManager,
+}
+
+impl TurtleSignalStrategy {
+    ///Stop orders for each unit not yet entered, spaced half an ATR apart.
+    fn send_buy_orders(&self, context: &StrategyContext, price: f64) {
+        let units = context.pos() / self.fixed_size;
+        for (unit, offset) in [0.0, 0.5, 1.0, 1.5].into_iter().enumerate() {
+            if units < (unit + 1) as f64 {
+                context.buy(price + self.atr_value * offset, self.fixed_size, true);
+            }
+        }
+    }
+
+    fn send_short_orders(&self, context: &StrategyContext, price: f64) {
+        let units = context.pos() / self.fixed_size;
+        for (unit, offset) in [0.0, 0.5, 1.0, 1.5].into_iter().enumerate() {
+            if units > -((unit + 1) as f64) {
+                context.short(price - self.atr_value * offset, self.fixed_size, true);
+            }
+        }
+    }
+}
+
+impl CtaStrategy for TurtleSignalStrategy {
+    fn parameters() -> Vec<(String, String)> {
+        vec![
+            ("entry_window".to_string(), "20".to_string()),
+            ("exit_window".to_string(), "10".to_string()),
+            ("atr_window".to_string(), "20".to_string()),
+            ("fixed_size".to_string(), "1".to_string()),
+        ]
+    }
+
+    fn new(_context: &StrategyContext, setting: &[(String, String)]) -> Self {
+        TurtleSignalStrategy {
+            entry_window: setting_value(setting, "entry_window", 20),
+            exit_window: setting_value(setting, "exit_window", 10),
+            atr_window: setting_value(setting, "atr_window", 20),
+            fixed_size: setting_value(setting, "fixed_size", 1.0),
+            entry_up: 0.0,
+            entry_down: 0.0,
+            exit_up: 0.0,
+            exit_down: 0.0,
+            atr_value: 0.0,
+            long_entry: 0.0,
+            short_entry: 0.0,
+            long_stop: 0.0,
+            short_stop: 0.0,
+            am: ArrayManager::new(100),
+        }
+    }
+
+    fn on_init(&mut self, context: &mut StrategyContext) {
+        for bar in context.load_bar(20, Interval::MINUTE) {
+            self.on_bar(context, &bar);
+        }
+    }
+
+    fn on_bar(&mut self, context: &mut StrategyContext, bar: &BarData) {
+        context.cancel_all();
+
+        self.am.update_bar(bar);
+        if !self.am.inited {
+            return;
+        }
+
+        let pos = context.pos();
+        // Entry channel is only updated while flat, so adds use the price of the first entry
+        if pos == 0.0 {
+            (self.entry_up, self.entry_down) = self.am.donchian(self.entry_window);
+        }
+        (self.exit_up, self.exit_down) = self.am.donchian(self.exit_window);
+
+        if pos == 0.0 {
+            self.atr_value = self.am.atr(self.atr_window);
+            self.long_entry = 0.0;
+            self.short_entry = 0.0;
+            self.long_stop = 0.0;
+            self.short_stop = 0.0;
+
+            self.send_buy_orders(context, self.entry_up);
+            self.send_short_orders(context, self.entry_down);
+        } else if pos > 0.0 {
+            self.send_buy_orders(context, self.entry_up);
+
+            let sell_price = self.long_stop.max(self.exit_down);
+            context.sell(sell_price, pos.abs(), true);
+        } else {
+            self.send_short_orders(context, self.entry_down);
+
+            let cover_price = self.short_stop.min(self.exit_up);
+            context.cover(cover_price, pos.abs(), true);
+        }
+    }
+
+    fn on_trade(&mut self, _context: &mut StrategyContext, trade: &TradeData) {
+        if trade.direction == Direction::LONG {
+            self.long_entry = trade.price;
+            self.long_stop = self.long_entry - 2.0 * self.atr_value;
+        } else {
+            self.short_entry = trade.price;
+            self.short_stop = self.short_entry + 2.0 * self.atr_value;
+        }
+    }
+
+    fn variables(&self) -> Vec<(String, String)> {
+        vec![
+            ("entry_up".to_string(), self.entry_up.to_string()),
+            ("entry_down".to_string(), self.entry_down.to_string()),
+            ("exit_up".to_string(), self.exit_up.to_string()),
+            ("exit_down".to_string(), self.exit_down.to_string()),
+            ("atr_value".to_string(), self.atr_value.to_string()),
+            ("long_entry".to_string(), self.long_entry.to_string()),
+            ("short_entry".to_string(), self.short_entry.to_string()),
+            ("long_stop".to_string(), self.long_stop.to_string()),
+            ("short_stop".to_string(), self.short_stop.to_string()),
+        ]
+    }
+
+    fn set_variables(&mut self, variables: &[(String, String)]) {
+        self.entry_up = setting_value(variables, "entry_up", self.entry_up);
+        self.entry_down = setting_value(variables, "entry_down", self.entry_down);
+        self.atr_value = setting_value(variables, "atr_value", self.atr_value);
+        self.long_entry = setting_value(variables, "long_entry", self.long_entry);
+        self.short_entry = setting_value(variables, "short_entry", self.short_entry);
+        self.long_stop = setting_value(variables, "long_stop", self.long_stop);
+        self.short_stop = setting_value(variables, "short_stop", self.short_stop);
+    }
+}
+
+export_strategy!(TurtleSignalStrategy);
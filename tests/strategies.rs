@@ -0,0 +1,651 @@
+/*!Backtests of the example strategies in strategies/, built as dylibs and loaded through the
+strategy ABI, so that ABI changes breaking them fail here. Reference runs are checked against
+the golden files in tests/golden, run with UPDATE_GOLDEN=1 to rewrite them after an intended
+change of results. */
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_json::{json, Value};
+
+use vnrs::vnrs::trader::constant::{Direction, Exchange, Interval};
+use vnrs::vnrs::trader::database::{get_database, set_database, MemoryDatabase};
+use vnrs::vnrs::trader::object::{BarData, OrderData, TradeData};
+use vnrs::vnrs::trader::synthetic::SyntheticBars;
+use vnrs::vnrs::trader::utility::IdGenerator;
+use vnrs::vnrs_ctastrategy::backtesting::{BacktestingEngine, DailyResult};
+use vnrs::vnrs_ctastrategy::base::{BacktestingMode, ExternClass, StrategyDirectory};
+use vnrs::vnrs_ctastrategy::batch::{BatchJob, BatchRunner};
+use vnrs::vnrs_ctastrategy::determinism::audit_determinism;
+use vnrs::vnrs_ctastrategy::observer::BacktestObserver;
+use vnrs::vnrs_ctastrategy::optimize::{
+    load_study, run_optimization_with, DistributedOptimizer, OptimizationSetting,
+    OptimizationWorker,
+};
+use vnrs::vnrs_ctastrategy::research::{forward_return_column, rank_ic};
+use vnrs::vnrs_ctastrategy::tpe::TpeOptimizer;
+
+const STRATEGIES: [&str; 5] = [
+    "double_ma_strategy",
+    "atr_rsi_strategy",
+    "boll_channel_strategy",
+    "turtle_signal_strategy",
+    "r_breaker_strategy",
+];
+
+///Directory of the strategy dylibs, built once together with the synthetic data shared by
+///all tests.
+static LIBRARY_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+fn library_dir() -> &'static Path {
+    LIBRARY_DIR.get_or_init(|| {
+        // Test binaries are in target/<profile>/deps
+        let exe = std::env::current_exe().unwrap();
+        let profile_dir = exe.parent().unwrap().parent().unwrap().to_path_buf();
+
+        let mut command = Command::new(env!("CARGO"));
+        command
+            .arg("build")
+            .arg("--manifest-path")
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+            .arg("--target-dir")
+            .arg(profile_dir.parent().unwrap());
+        for name in STRATEGIES {
+            command.args(["-p", name]);
+        }
+        if profile_dir.ends_with("release") {
+            command.arg("--release");
+        }
+        assert!(
+            command.status().unwrap().success(),
+            "strategies failed to build"
+        );
+
+        let database = MemoryDatabase::new();
+        database.save_bar_data(
+            SyntheticBars {
+                symbol: "IF888".to_string(),
+                exchange: Exchange::CFFEX,
+                interval: Interval::MINUTE,
+                start: datetime(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()),
+                count: 45 * 24 * 60,
+                start_price: 4000.0,
+                volatility: 0.0005,
+                seed: 2643,
+                pricetick: 0.2,
+                ..Default::default()
+            }
+            .generate(),
+        );
+        set_database(Arc::new(database));
+
+        profile_dir
+    })
+}
+
+fn datetime(date: NaiveDate) -> NaiveDateTime {
+    date.and_hms_opt(0, 0, 0).unwrap()
+}
+
+///Engine with the strategy added and the data loaded, ready to replay.
+fn load_backtesting(name: &str, setting: &str) -> (Arc<ExternClass>, BacktestingEngine) {
+    load_backtesting_period(
+        name,
+        setting,
+        NaiveDate::from_ymd_opt(2024, 1, 22).unwrap(),
+        NaiveDate::from_ymd_opt(2024, 2, 15).unwrap(),
+    )
+}
+
+fn load_backtesting_period(
+    name: &str,
+    setting: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> (Arc<ExternClass>, BacktestingEngine) {
+    let dir = library_dir();
+
+    let class = Arc::new(
+        ExternClass::new(dir.join(libloading::library_filename(name)))
+            .unwrap_or_else(|e| panic!("{}", e)),
+    );
+    let mut engine = BacktestingEngine::new();
+    engine.set_output_callback(|_| {});
+    engine
+        .set_parameters(
+            "IF888.CFFEX",
+            Interval::MINUTE,
+            datetime(start),
+            datetime(end),
+            2.5e-5,
+            0.2,
+            300.0,
+            0.2,
+            10_000_000.0,
+            BacktestingMode::BAR,
+            0.0,
+            240,
+            120,
+        )
+        .unwrap();
+    engine.add_strategy(class.clone(), setting).unwrap();
+    engine.load_data().unwrap();
+    (class, engine)
+}
+
+fn run_backtesting(name: &str, setting: &str) -> (Arc<ExternClass>, BacktestingEngine) {
+    let (class, mut engine) = load_backtesting(name, setting);
+    engine.run_backtesting().unwrap();
+    (class, engine)
+}
+
+///The strategy trades, and the position it was told about matches its trades.
+fn check_strategy(name: &str, setting: &str, parameters: &[&str]) -> BacktestingEngine {
+    let (class, mut engine) = run_backtesting(name, setting);
+
+    let names: Vec<String> = class
+        .get_parameters()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(names, parameters);
+
+    let trades = engine.get_all_trades();
+    assert!(!trades.is_empty(), "{} didn't trade", name);
+
+    let pos: f64 = trades
+        .iter()
+        .map(|trade| match trade.direction {
+            Direction::LONG => trade.volume,
+            _ => -trade.volume,
+        })
+        .sum();
+    assert_eq!(engine.get_pos(), pos);
+
+    let statistics = engine.calculate_result_statistics(false);
+    assert_eq!(statistics.total_trade_count, trades.len() as i64);
+    assert!(statistics.end_balance.is_finite());
+    engine
+}
+
+#[test]
+fn double_ma_strategy() {
+    let mut engine = check_strategy(
+        "double_ma_strategy",
+        "fast_window:10,slow_window:20",
+        &["fast_window", "slow_window"],
+    );
+
+    // Trades carry the reference of the signal sending the order
+    for trade in engine.get_all_trades() {
+        let expected = match trade.direction {
+            Direction::LONG => "cross_over",
+            _ => "cross_below",
+        };
+        assert_eq!(trade.reference, expected);
+    }
+
+    // The run is kept in the database with its setting, statistics and trades
+    let statistics = engine.calculate_result_statistics(false);
+    let id = engine.save_backtest_record(&statistics, true).unwrap();
+    let record = get_database()
+        .unwrap()
+        .load_backtest_record(id)
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.setting, "fast_window:10,slow_window:20");
+    assert_eq!(record.strategy_hash.len(), 16);
+    assert_eq!(
+        record.statistics["total_trade_count"],
+        statistics.total_trade_count
+    );
+    let trades = record.trades.unwrap();
+    assert_eq!(
+        trades.as_array().unwrap().len(),
+        engine.get_all_trades().len()
+    );
+}
+
+#[test]
+fn atr_rsi_strategy() {
+    check_strategy(
+        "atr_rsi_strategy",
+        "",
+        &[
+            "atr_length",
+            "atr_ma_length",
+            "rsi_length",
+            "rsi_entry",
+            "trailing_percent",
+            "fixed_size",
+        ],
+    );
+}
+
+#[test]
+fn boll_channel_strategy() {
+    check_strategy(
+        "boll_channel_strategy",
+        "boll_dev:2",
+        &[
+            "boll_window",
+            "boll_dev",
+            "cci_window",
+            "atr_window",
+            "sl_multiplier",
+            "fixed_size",
+        ],
+    );
+}
+
+#[test]
+fn turtle_signal_strategy() {
+    let engine = check_strategy(
+        "turtle_signal_strategy",
+        "",
+        &["entry_window", "exit_window", "atr_window", "fixed_size"],
+    );
+
+    // Trades of the triggered stop orders map back to their orders
+    for trade in engine.get_all_trades() {
+        let vt_orderid = trade.vt_orderid();
+        let order = engine.get_order(&vt_orderid).unwrap();
+        let traded: f64 = engine
+            .get_trades_by_order(&vt_orderid)
+            .iter()
+            .map(|t| t.volume)
+            .sum();
+        assert_eq!(order.traded, traded);
+    }
+    assert!(engine.get_stop_order("STOP.unknown").is_none());
+}
+
+#[test]
+fn r_breaker_strategy() {
+    check_strategy(
+        "r_breaker_strategy",
+        "",
+        &[
+            "setup_coef",
+            "break_coef",
+            "enter_coef_1",
+            "enter_coef_2",
+            "fixed_size",
+            "donchian_window",
+            "trailing_long",
+            "trailing_short",
+            "multiplier",
+        ],
+    );
+}
+
+#[test]
+fn optimization_sharded_across_workers() {
+    let dir = library_dir();
+    let mut runner = BatchRunner::new();
+    runner.set_parameters(
+        datetime(NaiveDate::from_ymd_opt(2024, 1, 22).unwrap()),
+        datetime(NaiveDate::from_ymd_opt(2024, 2, 1).unwrap()),
+        2.5e-5,
+        0.2,
+        300.0,
+        0.2,
+        10_000_000.0,
+        BacktestingMode::BAR,
+        0.0,
+        Some(240),
+        120,
+    );
+    runner.set_max_workers(2);
+    let strategy = dir.join(libloading::library_filename("double_ma_strategy"));
+    let job = BatchJob::new(
+        "IF888.CFFEX",
+        Interval::MINUTE,
+        &strategy.to_string_lossy(),
+        "",
+    );
+    let mut setting = OptimizationSetting::new("total_net_pnl");
+    setting
+        .add_parameter("fast_window", 5.0, 10.0, 5.0)
+        .unwrap();
+    setting
+        .add_parameter("slow_window", 20.0, 30.0, 10.0)
+        .unwrap();
+    let completed = Mutex::new(Vec::new());
+    let local = run_optimization_with(&runner, &job, &setting, |progress| {
+        completed.lock().unwrap().push(progress.completed);
+    });
+    assert_eq!(local.len(), 4);
+    // Each result is reported as it finishes
+    assert_eq!(completed.into_inner().unwrap(), vec![1, 2, 3, 4]);
+    // The dylib is loaded once and its instances are reset between the backtests
+    let class = ExternClass::load(&strategy).unwrap();
+    assert!(Arc::ptr_eq(&class, &ExternClass::load(&strategy).unwrap()));
+    assert!((1..=2).contains(&class.pooled()));
+
+    let mut workers = Vec::new();
+    for _ in 0..2 {
+        let worker = OptimizationWorker::bind(
+            "127.0.0.1:0",
+            StrategyDirectory::new(dir.clone()),
+            "optimize-token",
+        )
+        .unwrap();
+        workers.push(worker.local_addr().unwrap().to_string());
+        std::thread::spawn(move || worker.serve());
+    }
+    // A worker that is down, its shards go to the others
+    let down = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    workers.push(down.local_addr().unwrap().to_string());
+    drop(down);
+
+    let workers: Vec<&str> = workers.iter().map(String::as_str).collect();
+    let mut optimizer = DistributedOptimizer::new(&workers, &runner);
+    optimizer.shard_size = 1;
+    // Without the token of the workers no setting is run
+    optimizer.token = "guess".to_string();
+    let rejected = optimizer.run(&job, &setting);
+    assert!(rejected.iter().all(|result| result.statistics.is_err()));
+    optimizer.token = "optimize-token".to_string();
+    let distributed = optimizer.run(&job, &setting);
+    assert_eq!(distributed.len(), local.len());
+    for (distributed, local) in distributed.iter().zip(&local) {
+        assert_eq!(distributed.setting, local.setting);
+        assert_eq!(
+            distributed.target("total_net_pnl"),
+            local.target("total_net_pnl")
+        );
+    }
+
+    // A study stopped halfway runs only the settings it is missing when run again
+    let mut half = OptimizationSetting::new("total_net_pnl");
+    half.add_parameter("fast_window", 5.0, 5.0, 5.0).unwrap();
+    half.add_parameter("slow_window", 20.0, 30.0, 10.0).unwrap();
+    half.set_study("double_ma_study");
+    run_optimization_with(&runner, &job, &half, |_| {});
+    let mut study = setting.clone();
+    study.set_study("double_ma_study");
+    let completed = Mutex::new(Vec::new());
+    let resumed = run_optimization_with(&runner, &job, &study, |progress| {
+        completed.lock().unwrap().push(progress.completed);
+    });
+    assert_eq!(completed.into_inner().unwrap(), vec![3, 4]);
+    for (resumed, local) in resumed.iter().zip(&local) {
+        assert_eq!(resumed.setting, local.setting);
+        assert_eq!(
+            resumed.target("total_net_pnl"),
+            local.target("total_net_pnl")
+        );
+    }
+    let saved = load_study("double_ma_study").unwrap();
+    assert_eq!(saved.len(), 4);
+    assert!(saved.iter().all(|result| result.duration > 0.0));
+
+    // Sampled from integer ranges by the estimator, each setting backtested once
+    let mut setting = OptimizationSetting::new("total_net_pnl");
+    setting.add_int_range("fast_window", 3, 12).unwrap();
+    setting.add_int_range("slow_window", 15, 40).unwrap();
+    let mut optimizer = TpeOptimizer::new(6);
+    optimizer.n_startup_trials = 3;
+    let results = optimizer.run_with(&runner, &job, &setting, |_| {});
+    assert_eq!(results.len(), 6);
+    assert!(results.iter().all(|result| result.statistics.is_ok()));
+    let mut settings: Vec<&str> = results.iter().map(|r| r.setting.as_str()).collect();
+    settings.sort();
+    settings.dedup();
+    assert_eq!(settings.len(), 6);
+
+    // Concurrent runs and the run of a reset instance give the same results
+    let report = audit_determinism(&runner, &job, 2).unwrap();
+    assert_eq!(report.runs, 3);
+    assert!(report.is_deterministic());
+}
+
+#[test]
+fn step_and_run_until() {
+    let setting = "fast_window:10,slow_window:20";
+    let (_, complete) = run_backtesting("double_ma_strategy", setting);
+    let (_, mut engine) = load_backtesting("double_ma_strategy", setting);
+    let (_, total) = engine.replay_position();
+
+    assert!(engine.step());
+    assert_eq!(engine.replay_position(), (1, total));
+
+    // Replays up to and including the bar at the datetime, and stops there
+    let moment = NaiveDate::from_ymd_opt(2024, 2, 1)
+        .unwrap()
+        .and_hms_opt(10, 0, 0)
+        .unwrap();
+    let replayed = engine.run_until(moment);
+    let (position, _) = engine.replay_position();
+    assert_eq!(replayed, position - 1);
+    let trades = engine.get_all_trades();
+    assert!(trades.iter().all(|trade| trade.datetime <= moment));
+    assert!(trades.len() < complete.get_all_trades().len());
+
+    engine.pause();
+    engine.run_backtesting().unwrap();
+    assert_eq!(engine.replay_position(), (position + 1, total));
+
+    // Continuing replays the rest as a single run would
+    engine.run_backtesting().unwrap();
+    assert_eq!(engine.replay_position(), (total, total));
+    assert!(!engine.step());
+    let fills = |engine: &BacktestingEngine| -> Vec<(NaiveDateTime, Direction, f64, f64)> {
+        engine
+            .get_all_trades()
+            .into_iter()
+            .map(|trade| (trade.datetime, trade.direction, trade.price, trade.volume))
+            .collect()
+    };
+    assert_eq!(fills(&engine), fills(&complete));
+    assert_eq!(engine.get_pos(), complete.get_pos());
+}
+
+#[test]
+fn ids_go_on_after_restore() {
+    let setting = "fast_window:10,slow_window:20";
+    let (_, mut engine) = load_backtesting("double_ma_strategy", setting);
+    let checkpoints = Arc::new(Mutex::new(Vec::new()));
+    let saved = checkpoints.clone();
+    engine.set_checkpoint_callback(3000, move |state| {
+        saved.lock().unwrap().push(state.to_string());
+    });
+    let moment = NaiveDate::from_ymd_opt(2024, 2, 1)
+        .unwrap()
+        .and_hms_opt(10, 0, 0)
+        .unwrap();
+    engine.run_until(moment);
+    let state = checkpoints.lock().unwrap().last().cloned().unwrap();
+
+    // Resumed by another engine, e.g. after a restart
+    let (_, mut resumed) = load_backtesting("double_ma_strategy", setting);
+    assert_ne!(resumed.get_run_id(), engine.get_run_id());
+    resumed.restore_state(&state).unwrap();
+    assert_eq!(resumed.get_run_id(), engine.get_run_id());
+    let before = resumed.get_all_trades().len();
+    resumed.run_backtesting().unwrap();
+
+    let trades = resumed.get_all_trades();
+    assert!(before > 0 && trades.len() > before);
+    // Scoped by the strategy name, not the path of its dylib
+    let prefix = format!("{}_double_ma_strategy_", engine.get_run_id());
+    assert!(trades
+        .iter()
+        .all(|trade| trade.tradeid.starts_with(&prefix) && trade.orderid.starts_with(&prefix)));
+    let sequences: Vec<i64> = trades
+        .iter()
+        .map(|trade| IdGenerator::sequence(&trade.tradeid))
+        .collect();
+    assert!(sequences.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn history_streamed_over_memory_limit() {
+    let setting = "fast_window:10,slow_window:20";
+    let (_, complete) = run_backtesting("double_ma_strategy", setting);
+    let (_, mut engine) = load_backtesting("double_ma_strategy", setting);
+    engine.set_memory_limit(Some(100_000));
+    engine.load_data().unwrap();
+    assert!(engine.history_memory() < complete.history_memory() / 2);
+
+    let moment = NaiveDate::from_ymd_opt(2024, 2, 1)
+        .unwrap()
+        .and_hms_opt(10, 0, 0)
+        .unwrap();
+    engine.run_until(moment);
+    engine.run_backtesting().unwrap();
+    assert_eq!(engine.replay_position(), complete.replay_position());
+    assert!(engine.history_memory() < complete.history_memory() / 2);
+    let fills = |engine: &BacktestingEngine| -> Vec<(NaiveDateTime, f64, f64)> {
+        engine
+            .get_all_trades()
+            .into_iter()
+            .map(|trade| (trade.datetime, trade.price, trade.volume))
+            .collect()
+    };
+    assert_eq!(fills(&engine), fills(&complete));
+}
+
+#[derive(Default)]
+struct Counts {
+    bars: usize,
+    orders: usize,
+    trades: usize,
+    days: Vec<(String, i64)>,
+    pos: f64,
+}
+
+impl BacktestObserver for Counts {
+    fn on_bar_processed(&mut self, _bar: &BarData, pos: f64) {
+        self.bars += 1;
+        self.pos = pos;
+    }
+
+    fn on_order(&mut self, _order: &OrderData) {
+        self.orders += 1;
+    }
+
+    fn on_trade(&mut self, _trade: &TradeData) {
+        self.trades += 1;
+    }
+
+    fn on_day_closed(&mut self, result: &DailyResult) {
+        self.days
+            .push((result.date.to_string(), result.trade_count));
+    }
+}
+
+#[test]
+fn observers_follow_the_replay() {
+    let (_, mut engine) = load_backtesting("double_ma_strategy", "fast_window:10,slow_window:20");
+    let counts = Arc::new(Mutex::new(Counts::default()));
+    engine.add_observer(counts.clone());
+    engine.run_backtesting().unwrap();
+
+    let counts = counts.lock().unwrap();
+    assert_eq!(counts.bars, engine.replay_position().1);
+    assert_eq!(counts.trades, engine.get_all_trades().len());
+    assert!(counts.orders > counts.trades);
+    assert_eq!(counts.pos, engine.get_pos());
+    // Every day is closed once with all of its trades
+    let days: Vec<(String, i64)> = engine
+        .get_daily_results()
+        .map(|result| (result.date.to_string(), result.trade_count))
+        .collect();
+    assert_eq!(counts.days, days);
+}
+
+#[test]
+fn factors_recorded_in_research_mode() {
+    let setting = "fast_window:10,slow_window:20";
+    let (_, mut engine) = load_backtesting("double_ma_strategy", setting);
+    engine.set_factor_research(&[1, 5]);
+    engine.run_backtesting().unwrap();
+
+    let df = engine.factor_df().unwrap();
+    assert_eq!(df.height(), engine.replay_position().1);
+    // The indicators are warmed up before the replay, so it's emitted on every bar
+    assert_eq!(df.column("ma_spread").unwrap().null_count(), 0);
+    let ic = rank_ic(&df, "ma_spread", &forward_return_column(5)).unwrap();
+    assert!(ic.is_some_and(|ic| ic.abs() <= 1.0));
+
+    // Ignored outside research mode
+    let (_, engine) = run_backtesting("double_ma_strategy", setting);
+    assert!(engine.factor_df().is_none());
+}
+
+///Reference runs with golden files, a limit order and a stop order strategy.
+const GOLDEN_RUNS: [(&str, &str); 2] = [
+    ("double_ma_strategy", "fast_window:10,slow_window:20"),
+    ("turtle_signal_strategy", ""),
+];
+
+#[test]
+fn golden_results() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for (name, setting) in GOLDEN_RUNS {
+        let (_, mut engine) = load_backtesting_period(
+            name,
+            setting,
+            NaiveDate::from_ymd_opt(2024, 1, 22).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 1, 24).unwrap(),
+        );
+        engine.run_backtesting().unwrap();
+        let statistics = engine.calculate_result_statistics(false);
+        // One line per trade, floats printed so that they read back exactly. Order ids depend
+        // on the path of the library and the engine, so they are left out.
+        let trades: Vec<String> = engine
+            .get_all_trades()
+            .iter()
+            .map(|trade| {
+                format!(
+                    "{} {:?} {:?} {} {}",
+                    trade.datetime, trade.direction, trade.offset, trade.price, trade.volume
+                )
+            })
+            .collect();
+        let actual = json!({
+            "trades": trades,
+            "statistics": statistics,
+        });
+
+        let path = dir.join(format!("{}.json", name));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(&path, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+            continue;
+        }
+        let text = fs::read_to_string(&path).unwrap_or_else(|e| {
+            panic!(
+                "{}: {}, run with UPDATE_GOLDEN=1 to create it",
+                path.display(),
+                e
+            )
+        });
+        let expected: Value = serde_json::from_str(&text).unwrap();
+        // Parsed from text like the golden file, parsing may be off by an ulp
+        let actual: Value = serde_json::from_str(&actual.to_string()).unwrap();
+
+        let trades = |value: &Value| value["trades"].as_array().cloned().unwrap_or_default();
+        let (actual_trades, expected_trades) = (trades(&actual), trades(&expected));
+        if let Some(index) = (0..actual_trades.len().max(expected_trades.len()))
+            .find(|&i| actual_trades.get(i) != expected_trades.get(i))
+        {
+            panic!(
+                "{} trade {} differs from the golden file:\n  golden: {}\n  actual: {}",
+                name,
+                index,
+                expected_trades.get(index).unwrap_or(&Value::Null),
+                actual_trades.get(index).unwrap_or(&Value::Null)
+            );
+        }
+        assert_eq!(
+            actual["statistics"], expected["statistics"],
+            "{} statistics differ from the golden file",
+            name
+        );
+    }
+}